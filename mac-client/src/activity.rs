@@ -0,0 +1,436 @@
+//! Remote-activity tracking for the tray.
+//!
+//! Whenever browser input is injected into a shell the tray icon is tinted
+//! for [`FLASH_DURATION`], and the Sessions submenu shows how long ago each
+//! session last received remote input, so it is obvious when someone is
+//! typing on the machine from a browser. Each session's submenu also shows
+//! how many bytes it has mirrored to the relay and had injected, with live
+//! rates, which makes a forgotten session streaming huge output stand out.
+//! Sessions are listed grouped by project, optionally only the recently
+//! active ones and most recently active first (see [`SessionView`]), so the
+//! menu stays usable with dozens of sessions.
+
+use crate::history::format_duration;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+/// How long the tray icon stays tinted after the last remote input.
+pub const FLASH_DURATION: Duration = Duration::from_millis(1500);
+
+/// Sessions with input or output within this long count as active.
+pub const ACTIVE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Tint applied to the tray icon during remote activity (macOS system orange).
+pub const ACTIVE_TINT: [u8; 3] = [255, 149, 0];
+
+/// Recolor every pixel of an RGBA image to `tint`, keeping its alpha.
+///
+/// The tray icon is a template image (shape in alpha only), so this turns
+/// it into a solid colored version of the same shape.
+pub fn tint_rgba(rgba: &mut [u8], tint: [u8; 3]) {
+    for px in rgba.chunks_exact_mut(4) {
+        px[..3].copy_from_slice(&tint);
+    }
+}
+
+/// Compact human-readable byte count ("512 B", "3.4 MB", "12 KB").
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{:.1} {}", value, UNITS[unit])
+    } else {
+        format!("{:.0} {}", value, UNITS[unit])
+    }
+}
+
+/// Bytes a session has mirrored to the relay (out) and had injected from
+/// browsers (in), with rates over the last sample interval.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    /// Bytes per second
+    pub out_rate: u64,
+    pub in_rate: u64,
+    sampled_at: Option<Instant>,
+}
+
+impl Traffic {
+    /// Take new counter readings; rates are measured from the previous one.
+    fn sample(&mut self, now: Instant, bytes_out: u64, bytes_in: u64) {
+        if let Some(at) = self.sampled_at {
+            let millis = now.saturating_duration_since(at).as_millis() as u64;
+            if millis == 0 {
+                return;
+            }
+            self.out_rate = bytes_out.saturating_sub(self.bytes_out) * 1000 / millis;
+            self.in_rate = bytes_in.saturating_sub(self.bytes_in) * 1000 / millis;
+        }
+        self.bytes_out = bytes_out;
+        self.bytes_in = bytes_in;
+        self.sampled_at = Some(now);
+    }
+}
+
+/// A connected session, when it last received browser input, and its
+/// traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionActivity {
+    pub session_id: String,
+    pub name: String,
+    pub project: Option<String>,
+    pub last_input: Option<Instant>,
+    /// When the session last produced output (seen at a traffic sample)
+    pub last_output: Option<Instant>,
+    pub traffic: Traffic,
+}
+
+impl SessionActivity {
+    /// Latest browser input or shell output.
+    pub fn last_active(&self) -> Option<Instant> {
+        self.last_input.max(self.last_output)
+    }
+
+    /// Whether the session had input or output within [`ACTIVE_WINDOW`].
+    pub fn is_active(&self, now: Instant) -> bool {
+        self.last_active()
+            .is_some_and(|at| now.saturating_duration_since(at) < ACTIVE_WINDOW)
+    }
+
+    /// Submenu title, e.g. "zsh - ~/src — 12 KB/s out" while output is
+    /// streaming.
+    pub fn title(&self) -> String {
+        match self.traffic.out_rate {
+            0 => self.name.clone(),
+            rate => format!("{} — {}/s out", self.name, format_bytes(rate)),
+        }
+    }
+
+    /// Remote input line, e.g. "Last remote activity 12s ago".
+    pub fn activity_label(&self, now: Instant) -> String {
+        match self.last_input {
+            Some(at) => format!(
+                "Last remote activity {} ago",
+                format_duration(now.saturating_duration_since(at).as_secs())
+            ),
+            None => "No remote activity".to_string(),
+        }
+    }
+
+    /// Traffic lines, e.g. "Output: 3.4 MB (12 KB/s)" and "Input: 120 B (0 B/s)".
+    pub fn traffic_labels(&self) -> [String; 2] {
+        let t = &self.traffic;
+        [
+            format!("Output: {} ({}/s)", format_bytes(t.bytes_out), format_bytes(t.out_rate)),
+            format!("Input: {} ({}/s)", format_bytes(t.bytes_in), format_bytes(t.in_rate)),
+        ]
+    }
+}
+
+/// How the Sessions submenu lists sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionView {
+    /// Leave out sessions without activity within [`ACTIVE_WINDOW`]
+    pub only_active: bool,
+    /// Most recently active projects and sessions first, not attach order
+    pub by_activity: bool,
+}
+
+/// One project's submenu in the Sessions submenu.
+#[derive(Debug)]
+pub struct SessionGroup<'a> {
+    pub project: Option<&'a str>,
+    /// Sessions listed under the current [`SessionView`]
+    pub sessions: Vec<&'a SessionActivity>,
+    /// All sessions of the project
+    pub total: usize,
+    /// Sessions active within [`ACTIVE_WINDOW`]
+    pub active: usize,
+}
+
+impl SessionGroup<'_> {
+    /// Submenu title, e.g. "ignis (12, 3 active)" or "Other (2, paused)".
+    pub fn title(&self, paused: bool) -> String {
+        let mut title = format!("{} ({}", self.project.unwrap_or("Other"), self.total);
+        if self.active > 0 {
+            title.push_str(&format!(", {} active", self.active));
+        }
+        if paused {
+            title.push_str(", paused");
+        }
+        title.push(')');
+        title
+    }
+}
+
+/// Connected sessions in attach order, with their remote activity.
+#[derive(Debug, Default)]
+pub struct ActivityTracker {
+    sessions: Vec<SessionActivity>,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sessions(&self) -> &[SessionActivity] {
+        &self.sessions
+    }
+
+    pub fn attach(&mut self, session_id: String, name: String) {
+        self.sessions.retain(|s| s.session_id != session_id);
+        self.sessions.push(SessionActivity {
+            session_id,
+            name,
+            project: None,
+            last_input: None,
+            last_output: None,
+            traffic: Traffic::default(),
+        });
+    }
+
+    pub fn session(&self, session_id: &str) -> Option<&SessionActivity> {
+        self.sessions.iter().find(|s| s.session_id == session_id)
+    }
+
+    pub fn set_project(&mut self, session_id: &str, project: String) {
+        if let Some(s) = self.sessions.iter_mut().find(|s| s.session_id == session_id) {
+            s.project = Some(project);
+        }
+    }
+
+    /// Sessions grouped by project, groups in order of their first session.
+    /// Sessions without a known project form a group of their own.
+    pub fn groups(&self) -> Vec<(Option<&str>, Vec<&SessionActivity>)> {
+        let mut groups: Vec<(Option<&str>, Vec<&SessionActivity>)> = Vec::new();
+        for session in &self.sessions {
+            let project = session.project.as_deref();
+            match groups.iter_mut().find(|(p, _)| *p == project) {
+                Some((_, members)) => members.push(session),
+                None => groups.push((project, vec![session])),
+            }
+        }
+        groups
+    }
+
+    /// Groups as listed in the menu under `view`. Projects with no listed
+    /// session are left out.
+    pub fn view(&self, view: SessionView, now: Instant) -> Vec<SessionGroup<'_>> {
+        let mut groups: Vec<SessionGroup> = self
+            .groups()
+            .into_iter()
+            .map(|(project, members)| {
+                let total = members.len();
+                let active = members.iter().filter(|s| s.is_active(now)).count();
+                let mut sessions: Vec<&SessionActivity> = members
+                    .into_iter()
+                    .filter(|s| !view.only_active || s.is_active(now))
+                    .collect();
+                if view.by_activity {
+                    sessions.sort_by_key(|s| Reverse(s.last_active()));
+                }
+                SessionGroup {
+                    project,
+                    sessions,
+                    total,
+                    active,
+                }
+            })
+            .filter(|group| !group.sessions.is_empty())
+            .collect();
+        if view.by_activity {
+            groups.sort_by_key(|group| Reverse(group.sessions.iter().filter_map(|s| s.last_active()).max()));
+        }
+        groups
+    }
+
+    pub fn rename(&mut self, session_id: &str, name: String) {
+        if let Some(s) = self.sessions.iter_mut().find(|s| s.session_id == session_id) {
+            s.name = name;
+        }
+    }
+
+    pub fn detach(&mut self, session_id: &str) {
+        self.sessions.retain(|s| s.session_id != session_id);
+    }
+
+    /// Note browser input to a session.
+    pub fn record_input(&mut self, session_id: &str, now: Instant) {
+        if let Some(s) = self.sessions.iter_mut().find(|s| s.session_id == session_id) {
+            s.last_input = Some(now);
+        }
+    }
+
+    /// Note a reading of a session's byte counters.
+    pub fn record_traffic(&mut self, session_id: &str, now: Instant, bytes_out: u64, bytes_in: u64) {
+        if let Some(s) = self.sessions.iter_mut().find(|s| s.session_id == session_id) {
+            if bytes_out > s.traffic.bytes_out {
+                s.last_output = Some(now);
+            }
+            s.traffic.sample(now, bytes_out, bytes_in);
+        }
+    }
+
+    /// Whether any session received remote input within [`FLASH_DURATION`].
+    pub fn is_active(&self, now: Instant) -> bool {
+        self.sessions.iter().any(|s| {
+            s.last_input
+                .is_some_and(|at| now.saturating_duration_since(at) < FLASH_DURATION)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity_flash_window() {
+        let mut tracker = ActivityTracker::new();
+        let t0 = Instant::now();
+        tracker.attach("a".into(), "zsh".into());
+        assert!(!tracker.is_active(t0));
+
+        tracker.record_input("a", t0);
+        assert!(tracker.is_active(t0 + Duration::from_millis(500)));
+        assert!(!tracker.is_active(t0 + FLASH_DURATION));
+
+        // Input for unknown sessions is ignored
+        tracker.record_input("b", t0 + FLASH_DURATION);
+        assert!(!tracker.is_active(t0 + FLASH_DURATION));
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut tracker = ActivityTracker::new();
+        let t0 = Instant::now();
+        tracker.attach("a".into(), "zsh".into());
+        assert_eq!(tracker.sessions()[0].activity_label(t0), "No remote activity");
+
+        tracker.record_input("a", t0);
+        tracker.rename("a", "zsh - ~/src".into());
+        assert_eq!(
+            tracker.sessions()[0].activity_label(t0 + Duration::from_secs(12)),
+            "Last remote activity 12s ago"
+        );
+        assert_eq!(tracker.sessions()[0].title(), "zsh - ~/src");
+
+        tracker.detach("a");
+        assert!(tracker.sessions().is_empty());
+    }
+
+    #[test]
+    fn test_traffic_rates() {
+        let mut tracker = ActivityTracker::new();
+        let t0 = Instant::now();
+        tracker.attach("a".into(), "zsh".into());
+
+        // The first reading only sets the baseline
+        tracker.record_traffic("a", t0, 4096, 10);
+        assert_eq!(tracker.sessions()[0].traffic.out_rate, 0);
+
+        tracker.record_traffic("a", t0 + Duration::from_secs(2), 4096 + 24 * 1024, 10);
+        let session = &tracker.sessions()[0];
+        assert_eq!(session.traffic.out_rate, 12 * 1024);
+        assert_eq!(session.traffic.in_rate, 0);
+        assert_eq!(session.title(), "zsh — 12 KB/s out");
+        assert_eq!(
+            session.traffic_labels(),
+            ["Output: 28 KB (12 KB/s)".to_string(), "Input: 10 B (0 B/s)".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(3 * 1024 * 1024 + 400 * 1024), "3.4 MB");
+        assert_eq!(format_bytes(12 * 1024), "12 KB");
+        assert_eq!(format_bytes(5 << 30), "5.0 GB");
+    }
+
+    #[test]
+    fn test_groups_by_project() {
+        let mut tracker = ActivityTracker::new();
+        for id in ["a", "b", "c", "d"] {
+            tracker.attach(id.into(), "zsh".into());
+        }
+        tracker.set_project("a", "ignis".into());
+        tracker.set_project("b", "Downloads".into());
+        tracker.set_project("c", "ignis".into());
+
+        let groups: Vec<(Option<&str>, Vec<&str>)> = tracker
+            .groups()
+            .into_iter()
+            .map(|(p, members)| (p, members.iter().map(|s| s.session_id.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (Some("ignis"), vec!["a", "c"]),
+                (Some("Downloads"), vec!["b"]),
+                (None, vec!["d"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_view_filters_and_sorts_by_activity() {
+        let mut tracker = ActivityTracker::new();
+        let t0 = Instant::now();
+        for id in ["a", "b", "c", "d"] {
+            tracker.attach(id.into(), "zsh".into());
+        }
+        tracker.set_project("a", "ignis".into());
+        tracker.set_project("b", "ignis".into());
+        tracker.set_project("c", "Downloads".into());
+        let t1 = t0 + ACTIVE_WINDOW;
+        tracker.record_input("d", t0);
+        tracker.record_traffic("b", t1, 100, 0);
+        tracker.record_input("c", t1 + Duration::from_secs(1));
+
+        let listed = |view| -> Vec<(String, Vec<String>)> {
+            tracker
+                .view(view, t1 + Duration::from_secs(2))
+                .iter()
+                .map(|g| (g.title(false), g.sessions.iter().map(|s| s.session_id.clone()).collect()))
+                .collect()
+        };
+        let all = SessionView::default();
+        assert_eq!(
+            listed(all),
+            vec![
+                ("ignis (2, 1 active)".into(), vec!["a".into(), "b".into()]),
+                ("Downloads (1, 1 active)".into(), vec!["c".into()]),
+                ("Other (1)".into(), vec!["d".into()]),
+            ]
+        );
+        let recent = SessionView {
+            only_active: true,
+            by_activity: true,
+        };
+        assert_eq!(
+            listed(recent),
+            vec![
+                ("Downloads (1, 1 active)".into(), vec!["c".into()]),
+                ("ignis (2, 1 active)".into(), vec!["b".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tint_keeps_alpha() {
+        let mut rgba = vec![0, 0, 0, 255, 10, 20, 30, 0];
+        tint_rgba(&mut rgba, ACTIVE_TINT);
+        assert_eq!(rgba, vec![255, 149, 0, 255, 255, 149, 0, 0]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Recent errors shown in the tray's "Errors" submenu.
+//!
+//! Failures from background tasks (PTY listener, control socket, relay
+//! connection) are collected here instead of only being logged. Critical
+//! ones also raise a macOS notification, but a failure that keeps repeating
+//! is folded into one entry and notified at most once per
+//! [`RENOTIFY_INTERVAL`].
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of entries kept in the log.
+pub const MAX_ALERTS: usize = 20;
+
+/// Minimum time between notifications for the same critical message.
+pub const RENOTIFY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How serious an alert is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Shown in the menu only
+    Warning,
+    /// Shown in the menu and raised as a notification
+    Critical,
+}
+
+/// A failure reported by a background task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    pub fn critical(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Critical,
+            message: message.into(),
+        }
+    }
+}
+
+/// An alert as recorded in the log, with repeats folded in.
+#[derive(Debug, Clone)]
+pub struct AlertEntry {
+    pub alert: Alert,
+    pub last_seen: SystemTime,
+    pub count: u32,
+    last_notified: Option<SystemTime>,
+}
+
+impl AlertEntry {
+    /// Menu label, e.g. "14:05  Relay unreachable (x3)".
+    pub fn label(&self) -> String {
+        let marker = match self.alert.severity {
+            Severity::Critical => "⚠ ",
+            Severity::Warning => "",
+        };
+        let repeats = if self.count > 1 {
+            format!(" (x{})", self.count)
+        } else {
+            String::new()
+        };
+        format!(
+            "{}  {}{}{}",
+            clock_time(self.last_seen),
+            marker,
+            self.alert.message,
+            repeats
+        )
+    }
+}
+
+/// Local wall-clock time as "HH:MM".
+fn clock_time(at: SystemTime) -> String {
+    let secs = at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as libc::time_t)
+        .unwrap_or(0);
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return "--:--".into();
+    }
+    format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+}
+
+/// Most recent first, at most [`MAX_ALERTS`] entries.
+#[derive(Debug, Default)]
+pub struct AlertLog {
+    entries: VecDeque<AlertEntry>,
+}
+
+impl AlertLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &AlertEntry> {
+        self.entries.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Record an alert. Returns true if it should raise a notification.
+    pub fn push(&mut self, alert: Alert, now: SystemTime) -> bool {
+        let existing = self.entries.iter().position(|e| e.alert == alert);
+        let mut entry = match existing.and_then(|i| self.entries.remove(i)) {
+            Some(mut entry) => {
+                entry.count += 1;
+                entry.last_seen = now;
+                entry
+            }
+            None => AlertEntry {
+                alert,
+                last_seen: now,
+                count: 1,
+                last_notified: None,
+            },
+        };
+
+        let notify = entry.alert.severity == Severity::Critical
+            && entry.last_notified.is_none_or(|at| {
+                now.duration_since(at).unwrap_or_default() >= RENOTIFY_INTERVAL
+            });
+        if notify {
+            entry.last_notified = Some(now);
+        }
+
+        self.entries.push_front(entry);
+        self.entries.truncate(MAX_ALERTS);
+        notify
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_fold_and_renotify_after_interval() {
+        let mut log = AlertLog::new();
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert!(log.push(Alert::critical("Relay unreachable"), t0));
+        assert!(!log.push(Alert::critical("Relay unreachable"), t0 + Duration::from_secs(10)));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.entries().next().unwrap().count, 2);
+
+        assert!(log.push(Alert::critical("Relay unreachable"), t0 + RENOTIFY_INTERVAL));
+    }
+
+    #[test]
+    fn test_warnings_never_notify() {
+        let mut log = AlertLog::new();
+        assert!(!log.push(Alert::warning("cloudflared not found"), SystemTime::now()));
+        assert!(!log.is_empty());
+    }
+
+    #[test]
+    fn test_newest_first_and_capped() {
+        let mut log = AlertLog::new();
+        let now = SystemTime::now();
+        for i in 0..MAX_ALERTS + 5 {
+            log.push(Alert::warning(format!("error {}", i)), now);
+        }
+        assert_eq!(log.len(), MAX_ALERTS);
+        let newest = format!("error {}", MAX_ALERTS + 4);
+        assert_eq!(log.entries().next().unwrap().alert.message, newest);
+
+        // A repeat moves back to the top
+        log.push(Alert::warning("error 10"), now);
+        assert_eq!(log.entries().next().unwrap().alert.message, "error 10");
+        assert_eq!(log.len(), MAX_ALERTS);
+    }
+}
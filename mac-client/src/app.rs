@@ -3,7 +3,18 @@
 //! This module defines the unified event types and app state for integrating
 //! the tray icon, relay client, and IPC server.
 
-use muda::MenuItem;
+use crate::alerts::Alert;
+use crate::approval::PendingApproval;
+use crate::config::{Config, SshHost};
+use crate::history::RecentSession;
+use crate::orphans::OrphanProxy;
+use crate::paste_guard::HeldPaste;
+use crate::protocol::{Role, Viewer};
+use crate::pty::SessionStats;
+use crate::transcript::Transcript;
+use crate::relay::latency::LatencyStats;
+use muda::{MenuItem, Submenu};
+use std::sync::Arc;
 
 /// Events sent from background tasks to the main UI thread.
 ///
@@ -22,24 +33,80 @@ pub enum UiEvent {
     BrowserConnected(String),
     /// A browser disconnected from this session
     BrowserDisconnected(String),
+    /// A browser is waiting to be approved from the menu
+    ApprovalRequest(PendingApproval),
+    /// A browser stopped waiting for approval (left or timed out)
+    ApprovalCancelled(String),
+    /// The browsers connected now, oldest first
+    Presence(Vec<Viewer>),
     /// Error from relay
     RelayError(String),
+    /// A one-time join link token arrived, valid for `expires_in_secs`
+    JoinToken { token: String, expires_in_secs: u64, role: Role },
+    /// A chat line from someone watching
+    Chat { name: Option<String>, text: String },
+    /// Round-trip time to the relay
+    RelayLatency(LatencyStats),
 
     // From cloudflared tunnel
     /// Tunnel URL is available
     TunnelUrl(String),
 
     // From IPC
-    /// A shell session connected via IPC
-    ShellConnected { session_id: String, name: String },
+    /// A shell session connected via IPC, with its byte counters
+    ShellConnected {
+        session_id: String,
+        name: String,
+        stats: Arc<SessionStats>,
+        transcript: Arc<Transcript>,
+    },
     /// A shell session disconnected
     ShellDisconnected { session_id: String },
+    /// A shell session ended; recorded in the recent-sessions history
+    SessionEnded(RecentSession),
     /// A shell session was renamed (directory change)
     ShellRenamed { session_id: String, name: String },
+    /// A shell session's project (git repo or top-level directory) changed
+    SessionProject { session_id: String, project: String },
     /// Shell session count changed
     ShellCountChanged(usize),
     /// Error from PTY manager
     PtyError(String),
+    /// Failure from a background task, shown in the "Errors" submenu
+    Alert(Alert),
+    /// Browser input was injected into a session (throttled per session)
+    RemoteInput { session_id: String },
+    /// Browser input to a session exceeded the rate limit and is being dropped
+    InputRateLimited { session_id: String, name: String },
+    /// A session (`name`), or with `None` every session, ran over its
+    /// bandwidth budget and is sent screen snapshots instead of its output
+    BandwidthExceeded { name: Option<String> },
+    /// Large browser input is held until it is confirmed from the menu
+    PasteHeld(HeldPaste),
+    /// A program in a session copied text with OSC 52
+    ClipboardCopy {
+        session_id: String,
+        name: String,
+        text: String,
+    },
+    /// A session rang the terminal bell
+    Bell {
+        session_id: String,
+        name: String,
+        tty: Option<String>,
+    },
+    /// pty-proxy processes that are running but not connected (the full list)
+    OrphanedProxies(Vec<OrphanProxy>),
+    /// The user answered the clipboard permission prompt for a session
+    ClipboardPermission { session_id: String, allowed: bool },
+
+    // From the preferences dialog
+    /// Preferences were edited and saved
+    ConfigChanged(Box<Config>),
+
+    // From the URL scheme handler
+    /// An `ignis://` link was opened
+    OpenUrl(String),
 
     // Terminal data forwarding
     /// Terminal data from IPC (shell -> relay)
@@ -59,6 +126,22 @@ pub enum BackgroundCommand {
     SendToShell { session_id: String, data: Vec<u8> },
     /// Reconnect to relay to get a new session code
     ReconnectRelay,
+    /// Leave the relay for the one at this URL
+    SwitchRelay(String),
+    /// Pause or share all sessions of a project with browsers
+    SetProjectPaused { project: String, paused: bool },
+    /// Do Not Disturb: pause or resume sharing every session
+    SetSharingPaused(bool),
+    /// Drop or accept browser input to one session
+    SetSessionReadOnly { session_id: String, read_only: bool },
+    /// Let a browser waiting for approval in, or turn it away
+    AnswerApproval { browser_id: String, approved: bool },
+    /// Ask the relay for a one-time join link, with at most `role`
+    CreateJoinLink { role: Role },
+    /// Write a held paste to its session (`send`) or discard it
+    ReleasePaste { id: u64, send: bool },
+    /// Open an ssh session to a configured host
+    ConnectSsh(SshHost),
 }
 
 /// Application state holding current values and menu item references.
@@ -76,14 +159,18 @@ pub struct AppState {
     pub browser_count: usize,
     /// Current tunnel URL (None if not yet available)
     pub tunnel_url: Option<String>,
+    /// Do Not Disturb is on
+    pub sharing_paused: bool,
+    /// Recent round trips to the relay (None until measured)
+    pub latency: Option<LatencyStats>,
 
     // Menu items that need dynamic updates
     /// Display item showing session code
     pub code_item: MenuItem,
     /// Display item showing connection status
     pub status_item: MenuItem,
-    /// Display item showing session count
-    pub count_item: MenuItem,
+    /// Submenu titled with the session count, listing each session's remote activity
+    pub count_item: Submenu,
     /// Display item showing tunnel URL
     pub url_item: MenuItem,
     /// Action item for copying URL (text changes for confirmation)
@@ -95,7 +182,7 @@ impl AppState {
     pub fn new(
         code_item: MenuItem,
         status_item: MenuItem,
-        count_item: MenuItem,
+        count_item: Submenu,
         url_item: MenuItem,
         copy_item: MenuItem,
     ) -> Self {
@@ -105,6 +192,8 @@ impl AppState {
             shell_count: 0,
             browser_count: 0,
             tunnel_url: None,
+            sharing_paused: false,
+            latency: None,
             code_item,
             status_item,
             count_item,
@@ -124,12 +213,18 @@ impl AppState {
 
     /// Update the status display menu item.
     pub fn update_status_display(&self) {
-        let status = if self.relay_connected {
-            "Connected"
+        let mut status = if self.relay_connected {
+            "Status: Connected".to_string()
         } else {
-            "Disconnected"
+            "Status: Disconnected".to_string()
         };
-        self.status_item.set_text(format!("Status: {}", status));
+        if let Some(latency) = self.latency.filter(|_| self.relay_connected) {
+            status.push_str(&format!(" · {}", latency.label()));
+        }
+        if self.sharing_paused {
+            status.push_str(" (Do Not Disturb)");
+        }
+        self.status_item.set_text(status);
     }
 
     /// Update the session count display menu item.
@@ -165,12 +260,20 @@ mod tests {
         let _shell_conn = UiEvent::ShellConnected {
             session_id: "sess-1".into(),
             name: "zsh".into(),
+            stats: Arc::new(SessionStats::default()),
+            transcript: Arc::new(Transcript::default()),
         };
         let _shell_disc = UiEvent::ShellDisconnected {
             session_id: "sess-1".into(),
         };
         let _shell_count = UiEvent::ShellCountChanged(5);
         let _pty_error = UiEvent::PtyError("pty error".into());
+        let _alert = UiEvent::Alert(Alert::critical("socket bind failed"));
+        let _rate_limited = UiEvent::InputRateLimited {
+            session_id: "sess-1".into(),
+            name: "zsh".into(),
+        };
+        let _bandwidth = UiEvent::BandwidthExceeded { name: Some("zsh".into()) };
         let _terminal_from_shell = UiEvent::TerminalDataFromShell {
             session_id: "sess-1".into(),
             data: vec![0x1b, 0x5b, 0x41],
@@ -192,5 +295,6 @@ mod tests {
             session_id: "sess-1".into(),
             data: vec![0x04, 0x05, 0x06],
         };
+        let _switch_relay = BackgroundCommand::SwitchRelay("wss://relay.example.com/ws".into());
     }
 }
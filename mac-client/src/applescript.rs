@@ -0,0 +1,167 @@
+//! Serialized AppleScript worker.
+//!
+//! Terminal automation (closing and focusing windows, opening new ones,
+//! notifications) goes through `osascript`, which can hang for a long time
+//! when the scripted app is busy or waiting on an Automation permission
+//! prompt. Scripts are therefore run one at a time on a single worker
+//! thread, each killed after a timeout, so they neither pile up as parallel
+//! `osascript` processes nor block the task that asked for them. Once the
+//! app starts quitting ([`shut_down`]) queued scripts are dropped.
+//!
+//! Dialogs that wait for the user (preferences, clipboard permission) do not
+//! go through the worker.
+
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Longest a script may run before `osascript` is killed.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set once the app is quitting.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+struct Job {
+    /// What the script does, for log messages
+    what: &'static str,
+    script: String,
+    reply: Option<mpsc::Sender<Result<String, String>>>,
+}
+
+fn worker() -> &'static Mutex<mpsc::Sender<Job>> {
+    static WORKER: OnceLock<Mutex<mpsc::Sender<Job>>> = OnceLock::new();
+    WORKER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("applescript".into())
+            .spawn(move || {
+                for job in rx {
+                    let result = if SHUTTING_DOWN.load(Ordering::Relaxed) {
+                        debug!("Skipping AppleScript ({}): quitting", job.what);
+                        Err("quitting".to_string())
+                    } else {
+                        run_with_timeout(&job.script, TIMEOUT)
+                    };
+                    match job.reply {
+                        Some(reply) => {
+                            let _ = reply.send(result);
+                        }
+                        None => {
+                            if let Err(e) = result {
+                                warn!("AppleScript ({}) failed: {}", job.what, e);
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("Failed to start AppleScript worker");
+        Mutex::new(tx)
+    })
+}
+
+fn submit(job: Job) -> bool {
+    worker().lock().unwrap().send(job).is_ok()
+}
+
+/// Queue `script` and return at once; failures are logged.
+pub fn queue(what: &'static str, script: String) {
+    submit(Job {
+        what,
+        script,
+        reply: None,
+    });
+}
+
+/// Run `script` after any queued ones and return its trimmed output.
+/// Blocks the caller; call from a thread that may wait.
+pub fn run(what: &'static str, script: String) -> Result<String, String> {
+    let (reply, result) = mpsc::channel();
+    if !submit(Job {
+        what,
+        script,
+        reply: Some(reply),
+    }) {
+        return Err("AppleScript worker is not running".into());
+    }
+    result
+        .recv()
+        .map_err(|_| "AppleScript worker stopped".to_string())?
+}
+
+/// Drop every script queued from now on; called when the app quits, so
+/// session teardown does not script Terminal windows.
+pub fn shut_down() {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Run `osascript -e script`, killing it after `timeout`.
+fn run_with_timeout(script: &str, timeout: Duration) -> Result<String, String> {
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(script);
+    run_command(command, timeout)
+}
+
+/// Run `command` and return its trimmed stdout, killing it after `timeout`.
+fn run_command(mut command: Command, timeout: Duration) -> Result<String, String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run {:?}: {}", command.get_program(), e))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {:?}", timeout));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+            Err(e) => return Err(format!("failed to wait for {:?}: {}", command.get_program(), e)),
+        }
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to read {:?} output: {}", command.get_program(), e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn test_run_command_output_and_timeout() {
+        assert_eq!(run_command(sh("echo ' done '"), TIMEOUT), Ok("done".into()));
+
+        let err = run_command(sh("echo 'no such window' >&2; exit 1"), TIMEOUT).unwrap_err();
+        assert!(err.ends_with(": no such window"), "{}", err);
+
+        let started = Instant::now();
+        let err = run_command(sh("sleep 5"), Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err, "timed out after 100ms");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
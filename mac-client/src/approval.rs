@@ -0,0 +1,145 @@
+//! Browser approval (`require_approval` in `[security]`).
+//!
+//! With approval on, the relay holds every new browser back (no session
+//! list, scrollback or output) until the Mac answers. Waiting browsers are
+//! listed under "Browser Requests" in the menu bar. Browsers let in with
+//! "Always Allow" are remembered by the random key each browser keeps in its
+//! local storage, one per line in `allowed_browsers` next to the config
+//! file, and are approved without asking from then on.
+
+use crate::paths;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A browser waiting for approval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingApproval {
+    pub browser_id: String,
+    /// Key the browser keeps across visits; needed for "Always Allow"
+    pub browser_key: Option<String>,
+    /// Browser and platform, e.g. "Safari on iPhone"
+    pub label: String,
+}
+
+impl PendingApproval {
+    pub fn new(browser_id: String, browser_key: Option<String>, user_agent: Option<&str>) -> Self {
+        Self {
+            browser_id,
+            browser_key: browser_key.filter(|key| !key.trim().is_empty()),
+            label: browser_label(user_agent),
+        }
+    }
+}
+
+/// Location of the always-allowed browser keys.
+pub fn allowed_path() -> Option<PathBuf> {
+    Some(paths::config_dir()?.join("allowed_browsers"))
+}
+
+/// Whether the browser with `key` was allowed with "Always Allow".
+pub fn is_always_allowed(key: &str) -> bool {
+    allowed_path().is_some_and(|path| read_keys(&path).contains(key))
+}
+
+/// Remember `key` so the browser is let in without asking.
+pub fn always_allow(key: &str) -> io::Result<()> {
+    let path = allowed_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    add_key(&path, key)
+}
+
+fn read_keys(path: &Path) -> HashSet<String> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn add_key(path: &Path, key: &str) -> io::Result<()> {
+    if read_keys(path).contains(key) {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", key)
+}
+
+/// Short description of a browser from its User-Agent header.
+pub fn browser_label(user_agent: Option<&str>) -> String {
+    let Some(ua) = user_agent else {
+        return "Unknown browser".into();
+    };
+    let browser = if ua.contains("Edg/") || ua.contains("EdgiOS/") {
+        "Edge"
+    } else if ua.contains("OPR/") {
+        "Opera"
+    } else if ua.contains("Firefox/") || ua.contains("FxiOS/") {
+        "Firefox"
+    } else if ua.contains("Chrome/") || ua.contains("CriOS/") {
+        "Chrome"
+    } else if ua.contains("Safari/") {
+        "Safari"
+    } else {
+        "Browser"
+    };
+    let platform = if ua.contains("iPhone") {
+        "iPhone"
+    } else if ua.contains("iPad") {
+        "iPad"
+    } else if ua.contains("Android") {
+        "Android"
+    } else if ua.contains("CrOS") {
+        "ChromeOS"
+    } else if ua.contains("Macintosh") || ua.contains("Mac OS X") {
+        "Mac"
+    } else if ua.contains("Windows") {
+        "Windows"
+    } else if ua.contains("Linux") {
+        "Linux"
+    } else {
+        return browser.into();
+    };
+    format!("{} on {}", browser, platform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_browser_labels() {
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+        assert_eq!(browser_label(Some(iphone)), "Safari on iPhone");
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+        assert_eq!(browser_label(Some(chrome)), "Chrome on Windows");
+        let edge = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 Edg/124.0.0.0";
+        assert_eq!(browser_label(Some(edge)), "Edge on Mac");
+        assert_eq!(browser_label(Some("curl/8.4.0")), "Browser");
+        assert_eq!(browser_label(None), "Unknown browser");
+    }
+
+    #[test]
+    fn test_allowed_keys_are_appended_once() {
+        let dir = std::env::temp_dir().join(format!("allowed-browsers-test-{}", std::process::id()));
+        let path = dir.join("allowed_browsers");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(read_keys(&path).is_empty());
+        add_key(&path, "k1").unwrap();
+        add_key(&path, "k2").unwrap();
+        add_key(&path, "k1").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "k1\nk2\n");
+        assert!(read_keys(&path).contains("k2"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! Pluggable sources of terminal sessions.
+//!
+//! A [`SessionBackend`] reports its sessions as [`PtyEvent`]s (attach,
+//! output, resize, detach) and carries out [`PtyCommand`]s for them (write,
+//! resize, kill). pty-proxy sessions ([`PtyManager`](crate::pty::PtyManager))
+//! and mirrored tmux panes ([`TmuxManager`](crate::tmux::TmuxManager)) are
+//! backends; others (ssh, `docker exec`) plug in the same way.
+//!
+//! The router never sees a backend: [`Backends`] merges their events into one
+//! stream and serves one command channel, sending each command to the backend
+//! owning the session. Session IDs must not overlap between backends.
+
+use crate::pty::{PtyCommand, PtyEvent, PtySessionInfo};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info};
+
+/// A source of terminal sessions.
+pub trait SessionBackend: Send + Sync {
+    /// Name for logs, e.g. "pty-proxy".
+    fn name(&self) -> &'static str;
+
+    /// Whether a session belongs to this backend.
+    fn owns(&self, session_id: &str) -> bool;
+
+    /// Events of the backend's sessions; `None` once taken.
+    fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<PtyEvent>>;
+
+    /// Carry out a command for one of the backend's sessions, or for all of
+    /// them (`ListSessions`, `Shutdown`).
+    fn send(&self, cmd: PtyCommand);
+
+    /// Type browser input into a session.
+    fn write(&self, session_id: String, data: Vec<u8>) {
+        self.send(PtyCommand::Write { session_id, data });
+    }
+
+    fn resize(&self, session_id: String, cols: u16, rows: u16) {
+        self.send(PtyCommand::Resize {
+            session_id,
+            cols,
+            rows,
+        });
+    }
+
+    /// End a session (closes its terminal window or pane).
+    fn kill(&self, session_id: String) {
+        self.send(PtyCommand::KillSession { session_id });
+    }
+
+    fn rename(&self, session_id: String, name: String) {
+        self.send(PtyCommand::Rename { session_id, name });
+    }
+
+    /// The backend's connected sessions.
+    fn list_sessions(&self) -> oneshot::Receiver<Vec<(String, PtySessionInfo)>> {
+        let (reply, list) = oneshot::channel();
+        self.send(PtyCommand::ListSessions { reply });
+        list
+    }
+
+    fn shutdown(&self) {
+        self.send(PtyCommand::Shutdown);
+    }
+}
+
+/// All session backends, asked in order which one owns a session.
+pub struct Backends {
+    backends: Vec<Box<dyn SessionBackend>>,
+}
+
+impl Backends {
+    pub fn new(backends: Vec<Box<dyn SessionBackend>>) -> Self {
+        for backend in &backends {
+            info!("Session backend: {}", backend.name());
+        }
+        Self { backends }
+    }
+
+    /// Events of every backend, merged. Call once, inside the runtime.
+    pub fn take_events(&mut self) -> mpsc::UnboundedReceiver<PtyEvent> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        for backend in &mut self.backends {
+            let Some(mut events) = backend.take_events() else {
+                continue;
+            };
+            let event_tx = event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if event_tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        event_rx
+    }
+
+    fn owner(&self, session_id: &str) -> Option<&dyn SessionBackend> {
+        self.backends
+            .iter()
+            .find(|b| b.owns(session_id))
+            .map(|b| b.as_ref())
+    }
+
+    /// Carry out a command with the backend it concerns.
+    pub async fn dispatch(&self, cmd: PtyCommand) {
+        let session_id = match cmd {
+            PtyCommand::Write { ref session_id, .. }
+            | PtyCommand::KillSession { ref session_id }
+            | PtyCommand::Rename { ref session_id, .. }
+            | PtyCommand::Resize { ref session_id, .. } => session_id.clone(),
+            PtyCommand::ListSessions { reply } => {
+                let _ = reply.send(self.list_sessions().await);
+                return;
+            }
+            PtyCommand::Shutdown => {
+                for backend in &self.backends {
+                    backend.shutdown();
+                }
+                return;
+            }
+        };
+        match self.owner(&session_id) {
+            Some(backend) => backend.send(cmd),
+            None => debug!("No backend owns session {}", session_id),
+        }
+    }
+
+    /// Connected sessions of every backend.
+    pub async fn list_sessions(&self) -> Vec<(String, PtySessionInfo)> {
+        let lists: Vec<_> = self.backends.iter().map(|b| b.list_sessions()).collect();
+        let mut sessions = Vec::new();
+        for list in lists {
+            sessions.extend(list.await.unwrap_or_default());
+        }
+        sessions
+    }
+
+    /// Serve the command channel until it closes.
+    pub async fn run(&self, mut cmd_rx: mpsc::UnboundedReceiver<PtyCommand>) {
+        while let Some(cmd) = cmd_rx.recv().await {
+            self.dispatch(cmd).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Owns IDs with its prefix, records writes and lists one session.
+    struct FakeBackend {
+        prefix: &'static str,
+        written: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SessionBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            self.prefix
+        }
+
+        fn owns(&self, session_id: &str) -> bool {
+            session_id.starts_with(self.prefix)
+        }
+
+        fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<PtyEvent>> {
+            None
+        }
+
+        fn send(&self, cmd: PtyCommand) {
+            match cmd {
+                PtyCommand::Write { session_id, .. } => self.written.lock().unwrap().push(session_id),
+                PtyCommand::ListSessions { reply } => {
+                    let info = PtySessionInfo {
+                        name: self.prefix.into(),
+                        shell: "/bin/zsh".into(),
+                        pid: 1,
+                        tty: "/dev/ttys001".into(),
+                        cwd: None,
+                        stats: Default::default(),
+                    };
+                    let _ = reply.send(vec![(format!("{}1", self.prefix), info)]);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_go_to_the_owning_backend() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let backends = Backends::new(vec![
+            Box::new(FakeBackend {
+                prefix: "tmux-",
+                written: written.clone(),
+            }),
+            Box::new(FakeBackend {
+                prefix: "",
+                written: written.clone(),
+            }),
+        ]);
+        for session_id in ["tmux-1-3", "5f0c"] {
+            backends
+                .dispatch(PtyCommand::Write {
+                    session_id: session_id.into(),
+                    data: b"ls\r".to_vec(),
+                })
+                .await;
+        }
+        assert_eq!(*written.lock().unwrap(), vec!["tmux-1-3", "5f0c"]);
+
+        let (reply, list) = oneshot::channel();
+        backends.dispatch(PtyCommand::ListSessions { reply }).await;
+        let ids: Vec<String> = list.await.unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["tmux-1", "1"]);
+    }
+}
@@ -0,0 +1,200 @@
+//! Hourly output budgets for relays on metered hosts.
+//!
+//! Output bytes sent through the relay are counted per session and in total
+//! over a fixed one-hour window. A session over its own budget is held, and
+//! once the total budget is used up every session is: its output is no
+//! longer streamed, and while it changes the router sends a plain-text
+//! snapshot of its screen every `snapshot_secs` instead (see
+//! [`crate::transcript::screen`]). Snapshots count against the budgets too.
+//! Every session streams again when the window ends.
+
+use crate::config::BandwidthConfig;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Length of a budget window.
+pub const WINDOW: Duration = Duration::from_secs(3600);
+
+/// Rows of a snapshot when the session's size is not known.
+const DEFAULT_ROWS: u16 = 24;
+
+/// Which budget a session ran over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The session's own budget
+    Session,
+    /// The budget shared by every session
+    Total,
+}
+
+/// What to do with one chunk of output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Within budget, stream it
+    Stream,
+    /// The session is held, leave it to the next snapshot
+    Held,
+    /// The chunk would exceed a budget: from now on the session (or, for
+    /// [`Limit::Total`], every session) is held
+    Exceeded(Limit),
+}
+
+/// Output counts of the current window.
+#[derive(Debug)]
+pub struct Budget {
+    config: BandwidthConfig,
+    window_start: Instant,
+    total: u64,
+    sessions: HashMap<String, u64>,
+    /// Sessions over their own budget
+    held: HashSet<String>,
+    /// The total budget is used up
+    all_held: bool,
+    /// Held sessions with output since their last snapshot
+    dirty: HashSet<String>,
+    rows: HashMap<String, u16>,
+}
+
+impl Budget {
+    pub fn new(config: BandwidthConfig, now: Instant) -> Self {
+        Self {
+            config,
+            window_start: now,
+            total: 0,
+            sessions: HashMap::new(),
+            held: HashSet::new(),
+            all_held: false,
+            dirty: HashSet::new(),
+            rows: HashMap::new(),
+        }
+    }
+
+    /// Start a new window once the current one is over.
+    fn roll(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.window_start) < WINDOW {
+            return;
+        }
+        if self.all_held || !self.held.is_empty() {
+            info!("New bandwidth window, streaming output again");
+        }
+        self.window_start = now;
+        self.total = 0;
+        self.sessions.clear();
+        self.held.clear();
+        self.all_held = false;
+        self.dirty.clear();
+    }
+
+    /// Check `bytes` of output from a session, counting them if they are
+    /// streamed.
+    pub fn record(&mut self, session_id: &str, bytes: usize, now: Instant) -> Decision {
+        self.roll(now);
+        if self.all_held || self.held.contains(session_id) {
+            self.dirty.insert(session_id.to_string());
+            return Decision::Held;
+        }
+        let bytes = bytes as u64;
+        let used = self.sessions.get(session_id).copied().unwrap_or(0);
+        let over = |limit: u64, used: u64| limit > 0 && used + bytes > limit;
+        let decision = if over(self.config.session_bytes_per_hour, used) {
+            self.held.insert(session_id.to_string());
+            Decision::Exceeded(Limit::Session)
+        } else if over(self.config.total_bytes_per_hour, self.total) {
+            self.all_held = true;
+            Decision::Exceeded(Limit::Total)
+        } else {
+            self.count(session_id, bytes);
+            return Decision::Stream;
+        };
+        self.dirty.insert(session_id.to_string());
+        decision
+    }
+
+    fn count(&mut self, session_id: &str, bytes: u64) {
+        self.total += bytes;
+        *self.sessions.entry(session_id.to_string()).or_default() += bytes;
+    }
+
+    /// Count a snapshot sent for a held session.
+    pub fn count_snapshot(&mut self, session_id: &str, bytes: usize) {
+        self.count(session_id, bytes as u64);
+    }
+
+    /// Held sessions due for a snapshot, i.e. with output since their last
+    /// one.
+    pub fn due_snapshots(&mut self, now: Instant) -> Vec<String> {
+        self.roll(now);
+        let mut due: Vec<String> = self.dirty.drain().collect();
+        due.sort();
+        due
+    }
+
+    pub fn set_rows(&mut self, session_id: &str, rows: u16) {
+        self.rows.insert(session_id.to_string(), rows);
+    }
+
+    /// Rows of the session's screen.
+    pub fn rows(&self, session_id: &str) -> u16 {
+        self.rows.get(session_id).copied().unwrap_or(DEFAULT_ROWS)
+    }
+
+    /// Forget an ended session. Its output still counts towards the total.
+    pub fn remove(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+        self.held.remove(session_id);
+        self.dirty.remove(session_id);
+        self.rows.remove(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(session_bytes_per_hour: u64, total_bytes_per_hour: u64, now: Instant) -> Budget {
+        Budget::new(
+            BandwidthConfig {
+                enabled: true,
+                session_bytes_per_hour,
+                total_bytes_per_hour,
+                snapshot_secs: 10,
+            },
+            now,
+        )
+    }
+
+    #[test]
+    fn test_session_over_budget_is_held_until_the_window_ends() {
+        let start = Instant::now();
+        let mut budget = budget(100, 0, start);
+        assert_eq!(budget.record("a", 60, start), Decision::Stream);
+        assert_eq!(budget.record("a", 60, start), Decision::Exceeded(Limit::Session));
+        assert_eq!(budget.record("a", 1, start), Decision::Held);
+        // Other sessions have their own budget
+        assert_eq!(budget.record("b", 100, start), Decision::Stream);
+        assert_eq!(budget.due_snapshots(start), vec!["a"]);
+        assert!(budget.due_snapshots(start).is_empty());
+
+        let later = start + WINDOW;
+        assert_eq!(budget.record("a", 60, later), Decision::Stream);
+        assert!(budget.due_snapshots(later).is_empty());
+    }
+
+    #[test]
+    fn test_total_budget_holds_every_session() {
+        let start = Instant::now();
+        let mut budget = budget(0, 100, start);
+        assert_eq!(budget.record("a", 50, start), Decision::Stream);
+        assert_eq!(budget.record("b", 50, start), Decision::Stream);
+        assert_eq!(budget.record("b", 1, start), Decision::Exceeded(Limit::Total));
+        assert_eq!(budget.record("a", 1, start), Decision::Held);
+        assert_eq!(budget.due_snapshots(start), vec!["a", "b"]);
+
+        budget.remove("a");
+        assert_eq!(budget.record("c", 1, start), Decision::Held);
+        assert_eq!(budget.rows("c"), DEFAULT_ROWS);
+        budget.set_rows("c", 50);
+        assert_eq!(budget.rows("c"), 50);
+    }
+}
@@ -0,0 +1,144 @@
+//! Bell notifications.
+//!
+//! A BEL (0x07) in a session's output, or a `bell` message from its
+//! pty-proxy, posts "Bell in: <session>" unless that session's tab is the
+//! focused window in Terminal or iTerm2, so long-running jobs (`make; tput
+//! bel`) can ping through the menu bar app. BEL also terminates OSC
+//! sequences (window titles, clipboard writes); those are not bells, so the
+//! output is scanned with the escape state kept between reads.
+
+use crate::applescript;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Shortest gap between two bell notifications of one session, so a shell
+/// beeping on every failed tab completion does not flood Notification Center.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Outside any escape sequence
+    Ground,
+    /// Saw ESC
+    Escape,
+    /// Inside a string sequence (OSC, DCS, APC, PM or SOS)
+    String,
+    /// Saw ESC inside a string sequence, waiting for `\`
+    StringEscape,
+}
+
+/// Incremental BEL detector for one session's output.
+#[derive(Debug)]
+pub struct BellScanner {
+    state: State,
+    last_reported: Option<Instant>,
+}
+
+impl Default for BellScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BellScanner {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            last_reported: None,
+        }
+    }
+
+    /// Scan a chunk of output; true if it rang a bell that should be
+    /// reported (see [`BellScanner::report`]).
+    pub fn feed(&mut self, data: &[u8], now: Instant) -> bool {
+        let mut rang = false;
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Ground, BEL) => {
+                    rang = true;
+                    State::Ground
+                }
+                (State::Ground, ESC) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, b']' | b'P' | b'_' | b'^' | b'X') => State::String,
+                (State::Escape, ESC) => State::Escape,
+                (State::Escape, _) => State::Ground,
+                (State::String, BEL) => State::Ground,
+                (State::String, ESC) => State::StringEscape,
+                (State::String, _) => State::String,
+                (State::StringEscape, b'\\') => State::Ground,
+                (State::StringEscape, ESC) => State::StringEscape,
+                // ESC followed by anything else aborts the string
+                (State::StringEscape, _) => State::Ground,
+            };
+        }
+        rang && self.report(now)
+    }
+
+    /// Whether a bell at `now` should be reported: at most one per
+    /// [`MIN_INTERVAL`].
+    pub fn report(&mut self, now: Instant) -> bool {
+        if self
+            .last_reported
+            .is_some_and(|last| now.duration_since(last) < MIN_INTERVAL)
+        {
+            return false;
+        }
+        self.last_reported = Some(now);
+        true
+    }
+}
+
+/// The tty of the focused tab when Terminal or iTerm2 is the frontmost app.
+/// Blocks until the AppleScript worker has run the script.
+pub fn focused_tty() -> Option<String> {
+    let script = r#"if application "Terminal" is running then
+    tell application "Terminal"
+        if frontmost then return tty of selected tab of front window
+    end tell
+end if
+if application "iTerm2" is running then
+    tell application "iTerm2"
+        if frontmost then return tty of current session of current window
+    end tell
+end if
+return """#;
+    match applescript::run("bell focus check", script.to_string()) {
+        Ok(tty) if !tty.is_empty() => Some(tty),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("Cannot tell the focused terminal tab: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bel_outside_sequences_rings() {
+        let now = Instant::now();
+        let mut scanner = BellScanner::new();
+        assert!(!scanner.feed(b"\x1b]0;make - ~/build\x07\x1b[1mdone\x1b[0m", now));
+        // Title split across reads, terminated by BEL
+        assert!(!scanner.feed(b"\x1b]2;vim", now));
+        assert!(!scanner.feed(b" notes.md\x07", now));
+        assert!(!scanner.feed(b"\x1bPq#0\x1b\\", now));
+        assert!(scanner.feed(b"build finished\x07\r\n", now));
+    }
+
+    #[test]
+    fn test_bells_are_throttled() {
+        let start = Instant::now();
+        let mut scanner = BellScanner::new();
+        assert!(scanner.feed(b"\x07", start));
+        assert!(!scanner.feed(b"\x07", start + Duration::from_secs(1)));
+        assert!(!scanner.report(start + Duration::from_secs(2)));
+        assert!(scanner.feed(b"\x07", start + MIN_INTERVAL));
+    }
+}
@@ -0,0 +1,142 @@
+//! OSC 52 clipboard bridging to the macOS pasteboard.
+//!
+//! pty-proxy reports OSC 52 copies (tmux, neovim, ...) as base64 payloads.
+//! Writing to the pasteboard on behalf of a shell is gated by a per-session
+//! permission prompt: the first copy from a session asks the user, and the
+//! answer applies to the rest of that session's lifetime.
+
+use crate::notify::applescript_quote;
+use base64::Engine;
+use std::collections::HashMap;
+use tracing::{error, info, warn};
+
+/// Characters of the copied text shown in the permission prompt.
+const PREVIEW_CHARS: usize = 80;
+
+/// Decode an OSC 52 payload into text. Invalid UTF-8 is replaced.
+pub fn decode_osc52(data: &str) -> Option<String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// What to do with a copy offered by a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Offer {
+    /// The session is allowed; write this text now
+    Write(String),
+    /// First copy from this session; ask the user, then call [`ClipboardBridge::decide`]
+    Prompt,
+    /// A prompt is already open; the text replaces the pending copy
+    Queued,
+    /// The user denied this session
+    Denied,
+}
+
+/// Per-session pasteboard permissions and copies waiting for a decision.
+#[derive(Debug, Default)]
+pub struct ClipboardBridge {
+    decisions: HashMap<String, bool>,
+    pending: HashMap<String, String>,
+}
+
+impl ClipboardBridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer a copy from a session.
+    pub fn offer(&mut self, session_id: &str, text: String) -> Offer {
+        match self.decisions.get(session_id) {
+            Some(true) => Offer::Write(text),
+            Some(false) => Offer::Denied,
+            None => {
+                if self.pending.insert(session_id.to_string(), text).is_some() {
+                    Offer::Queued
+                } else {
+                    Offer::Prompt
+                }
+            }
+        }
+    }
+
+    /// Record the user's answer. Returns the latest pending copy to write if allowed.
+    pub fn decide(&mut self, session_id: &str, allowed: bool) -> Option<String> {
+        self.decisions.insert(session_id.to_string(), allowed);
+        let text = self.pending.remove(session_id)?;
+        allowed.then_some(text)
+    }
+
+    /// Drop all state for a session that ended.
+    pub fn forget(&mut self, session_id: &str) {
+        self.decisions.remove(session_id);
+        self.pending.remove(session_id);
+    }
+}
+
+/// Ask whether `name` may write to the pasteboard (blocking). Anything but
+/// an explicit "Allow" counts as a denial.
+pub fn prompt(name: &str, text: &str) -> bool {
+    let mut preview: String = text.chars().take(PREVIEW_CHARS).collect();
+    if text.chars().count() > PREVIEW_CHARS {
+        preview.push('…');
+    }
+    let message = format!(
+        "\"{}\" wants to copy {} characters to the Mac clipboard:\n\n{}\n\nAllow copies from this session?",
+        name,
+        text.chars().count(),
+        preview
+    );
+    let script = format!(
+        "display dialog {} with title \"Terminal Remote\" buttons {{\"Deny\", \"Allow\"}} default button \"Deny\"",
+        applescript_quote(&message)
+    );
+    match std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains("button returned:Allow"),
+        Err(e) => {
+            warn!("Failed to run osascript for clipboard prompt: {}", e);
+            false
+        }
+    }
+}
+
+/// Write text to the macOS pasteboard.
+pub fn write_pasteboard(text: &str) {
+    match arboard::Clipboard::new().and_then(|mut c| c.set_text(text)) {
+        Ok(()) => info!("Copied {} bytes from a session to the clipboard", text.len()),
+        Err(e) => error!("Failed to write clipboard: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_osc52() {
+        assert_eq!(decode_osc52("aGVsbG8gd29ybGQ=").as_deref(), Some("hello world"));
+        assert_eq!(decode_osc52("not base64!"), None);
+    }
+
+    #[test]
+    fn test_prompt_once_per_session() {
+        let mut bridge = ClipboardBridge::new();
+        assert_eq!(bridge.offer("s1", "a".into()), Offer::Prompt);
+        assert_eq!(bridge.offer("s1", "b".into()), Offer::Queued);
+        // The latest copy wins once allowed
+        assert_eq!(bridge.decide("s1", true).as_deref(), Some("b"));
+        assert_eq!(bridge.offer("s1", "c".into()), Offer::Write("c".into()));
+
+        assert_eq!(bridge.offer("s2", "x".into()), Offer::Prompt);
+        assert_eq!(bridge.decide("s2", false), None);
+        assert_eq!(bridge.offer("s2", "y".into()), Offer::Denied);
+
+        bridge.forget("s2");
+        assert_eq!(bridge.offer("s2", "z".into()), Offer::Prompt);
+    }
+}
@@ -0,0 +1,543 @@
+//! User configuration loaded from `config.toml` in [`paths::config_dir`]
+//! (`~/Library/Application Support/Terminal Remote/`, or its
+//! `instances/<name>/` for a named instance).
+//!
+//! Every field has a default, so a missing file or a file containing only
+//! some sections is fine:
+//!
+//! ```toml
+//! relay_url = "wss://relay.example.com/ws"
+//!
+//! [rate_limit]
+//! bytes_per_sec = 65536
+//! messages_per_sec = 200
+//! ```
+//!
+//! The file is also written back by the Preferences dialog.
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// Relay URL used when neither `RELAY_URL` nor the config file sets one.
+pub const DEFAULT_RELAY_URL: &str = "ws://localhost:3000/ws";
+
+/// Scrollback kept by the bundled relay-server for each mac-client (1 MB).
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+/// Top-level mac-client configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Relay WebSocket URL (the `RELAY_URL` environment variable wins)
+    pub relay_url: Option<String>,
+    /// Relays to switch between from the menu bar (`[[relays]]`)
+    pub relays: Vec<RelayEndpoint>,
+    /// Key for relays that only host sessions for Macs they gave one (the
+    /// `RELAY_API_KEY` environment variable wins)
+    pub relay_api_key: Option<String>,
+    /// Web UI address put in join and pairing links (default: the tunnel URL)
+    pub web_url: Option<String>,
+    /// Name browsers see for this Mac (default: the computer name)
+    pub display_name: Option<String>,
+    /// Show macOS notifications
+    pub notifications: bool,
+    /// Terminal app whose window is closed when a browser closes a session
+    pub close_window: CloseWindow,
+    /// Size of a session when browsers and the Mac's terminal differ
+    pub size_policy: SizePolicy,
+    /// Scrollback replayed to newly joined browsers, in bytes per terminal;
+    /// the relay keeps no more than this (0: nothing) nor than its own cap
+    pub scrollback_bytes: usize,
+    /// Directory for session recordings and exported transcripts
+    pub recording_dir: Option<PathBuf>,
+    /// What browsers are allowed to do
+    pub security: SecurityConfig,
+    /// Limits on browser input injected into shells
+    pub rate_limit: RateLimitConfig,
+    /// Hourly budgets for output sent to the relay
+    pub bandwidth: BandwidthConfig,
+    /// Where OSC 52 copies from shells are delivered
+    pub clipboard: ClipboardConfig,
+    /// Peer-to-peer WebRTC connections to browsers
+    pub direct: DirectConfig,
+    /// Global keyboard shortcuts for menu actions
+    pub hotkeys: HotkeyConfig,
+    /// Mirror tmux panes as sessions
+    pub tmux: TmuxConfig,
+    /// Remote hosts opened over ssh from the menu bar
+    pub ssh: SshConfig,
+    /// Encrypt terminal output so only paired browsers can read it
+    pub end_to_end_encryption: bool,
+    /// Have the relay record session output, so browsers can play back
+    /// what happened while they were away
+    pub record_on_relay: bool,
+    /// Let the relay compress output to browsers that can inflate it; off
+    /// saves relay CPU on fast networks
+    pub compress_output: bool,
+    /// Anonymous usage counters sent to a maintainer's endpoint
+    pub telemetry: TelemetryConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            relay_url: None,
+            relays: Vec::new(),
+            relay_api_key: None,
+            web_url: None,
+            display_name: None,
+            notifications: true,
+            close_window: CloseWindow::default(),
+            size_policy: SizePolicy::default(),
+            scrollback_bytes: DEFAULT_SCROLLBACK_BYTES,
+            recording_dir: None,
+            security: SecurityConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            clipboard: ClipboardConfig::default(),
+            direct: DirectConfig::default(),
+            hotkeys: HotkeyConfig::default(),
+            tmux: TmuxConfig::default(),
+            ssh: SshConfig::default(),
+            end_to_end_encryption: false,
+            record_on_relay: false,
+            compress_output: true,
+            telemetry: TelemetryConfig::default(),
+        }
+    }
+}
+
+/// One `[[relays]]` entry, listed in the Relay submenu.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayEndpoint {
+    /// Menu title; the URL if empty
+    pub name: String,
+    /// Relay WebSocket URL
+    pub url: String,
+    /// Web UI address of this relay, for join and pairing links
+    pub web_url: Option<String>,
+}
+
+impl RelayEndpoint {
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.url
+        } else {
+            &self.name
+        }
+    }
+}
+
+/// Which terminal app's window to close when a browser closes a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloseWindow {
+    /// Leave windows open; the shell is sent a close instead
+    Off,
+    /// Close the Terminal.app window on the session's tty
+    #[default]
+    Terminal,
+    /// Close the iTerm2 session on the session's tty
+    Iterm,
+}
+
+impl CloseWindow {
+    /// Name shown in Preferences.
+    pub fn label(self) -> &'static str {
+        match self {
+            CloseWindow::Off => "Off",
+            CloseWindow::Terminal => "Terminal",
+            CloseWindow::Iterm => "iTerm",
+        }
+    }
+
+    /// The option after this one, for cycling through them in Preferences.
+    pub fn next(self) -> Self {
+        match self {
+            CloseWindow::Off => CloseWindow::Terminal,
+            CloseWindow::Terminal => CloseWindow::Iterm,
+            CloseWindow::Iterm => CloseWindow::Off,
+        }
+    }
+}
+
+/// How one size is chosen for a session viewed at several sizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizePolicy {
+    /// The smallest columns and rows of the Mac's terminal and every browser,
+    /// so everything fits everywhere
+    #[default]
+    Smallest,
+    /// The Mac's terminal keeps its size (ssh sessions: the size they were
+    /// opened at); browsers are told to match it
+    Local,
+}
+
+/// Toggles for browser-initiated actions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// Browsers may open new Terminal windows
+    pub allow_remote_create: bool,
+    /// Browsers may close sessions (and their Terminal windows)
+    pub allow_remote_close: bool,
+    /// Each new browser waits until it is approved from the menu bar
+    pub require_approval: bool,
+    /// Browsers must enter this password (or send it as a bearer token) to
+    /// join; checked by the relay
+    pub password: Option<String>,
+    /// Browsers allowed in a session at once; the relay may allow fewer
+    pub max_browsers: Option<usize>,
+    /// Hold large browser input until it is confirmed from the menu bar
+    pub confirm_paste: bool,
+    /// Input larger than this many bytes needs confirmation
+    pub confirm_paste_bytes: usize,
+    /// Input with more line breaks than this needs confirmation
+    pub confirm_paste_lines: usize,
+    /// Have the relay keep none of the session's output: no scrollback,
+    /// recording or chat history. Browsers see output from when they join.
+    /// A relay auditing input still logs it.
+    pub zero_retention: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            allow_remote_create: true,
+            allow_remote_close: true,
+            require_approval: false,
+            password: None,
+            max_browsers: None,
+            confirm_paste: true,
+            confirm_paste_bytes: 2048,
+            confirm_paste_lines: 5,
+            zero_retention: false,
+        }
+    }
+}
+
+/// Delivery of OSC 52 copies made by programs running in a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Write copies to the macOS pasteboard (asks once per session)
+    pub to_pasteboard: bool,
+    /// Forward copies to connected browsers
+    pub to_browsers: bool,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            to_pasteboard: true,
+            to_browsers: true,
+        }
+    }
+}
+
+/// Direct mode: terminal frames over a WebRTC data channel when a browser
+/// can reach the Mac, with the relay used only for signaling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectConfig {
+    /// Answer browsers' offers for a direct connection
+    pub enabled: bool,
+    /// STUN/TURN URLs for reaching browsers on other networks
+    pub ice_servers: Vec<String>,
+}
+
+impl Default for DirectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ice_servers: vec!["stun:stun.l.google.com:19302".into()],
+        }
+    }
+}
+
+/// Global shortcuts, e.g. "Option+Shift+C" or "⌥⇧C"; empty turns one off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub copy_join_url: String,
+    pub copy_code: String,
+    pub show_qr_code: String,
+    pub do_not_disturb: String,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        Self {
+            copy_join_url: "Option+Shift+C".into(),
+            copy_code: String::new(),
+            show_qr_code: String::new(),
+            do_not_disturb: "Option+Shift+P".into(),
+        }
+    }
+}
+
+/// tmux sessions whose panes are mirrored alongside pty-proxy sessions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TmuxConfig {
+    pub enabled: bool,
+    /// Names of the tmux sessions to mirror; empty mirrors all of them
+    pub sessions: Vec<String>,
+    /// tmux binary (default: the first of Homebrew's and the system's)
+    pub path: Option<PathBuf>,
+}
+
+/// Remote hosts whose shells mac-client runs itself, as `ssh` in a local
+/// PTY, so they can be shared without installing pty-proxy on them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshConfig {
+    /// Listed under "SSH Hosts" in the menu bar (`[[ssh.hosts]]`)
+    pub hosts: Vec<SshHost>,
+    /// ssh binary (default: /usr/bin/ssh)
+    pub path: Option<PathBuf>,
+}
+
+/// One `[[ssh.hosts]]` entry.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SshHost {
+    /// Menu title and session name; the destination if empty
+    pub name: String,
+    /// `user@host`, or a `Host` alias from ~/.ssh/config
+    pub destination: String,
+    pub port: Option<u16>,
+    /// Extra ssh options, e.g. `["-i", "~/.ssh/deploy"]`
+    pub args: Vec<String>,
+}
+
+impl SshHost {
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.destination
+        } else {
+            &self.name
+        }
+    }
+}
+
+/// Opt-in anonymous usage metrics (see [`crate::telemetry`]); nothing is
+/// sent unless `enabled` is set and an `endpoint` is given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// URL the daily report is posted to
+    pub endpoint: Option<String>,
+}
+
+impl TelemetryConfig {
+    /// Endpoint to report to, if reporting is on.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref().filter(|e| self.enabled && !e.is_empty())
+    }
+}
+
+/// Per-session token-bucket limits on browser input.
+///
+/// Each session gets one bucket for bytes and one for messages. A bucket
+/// refills at its per-second rate and holds at most its burst size, so short
+/// bursts (pastes) pass while sustained floods are dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub bytes_per_sec: u32,
+    pub burst_bytes: u32,
+    pub messages_per_sec: u32,
+    pub burst_messages: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bytes_per_sec: 64 * 1024,
+            burst_bytes: 256 * 1024,
+            messages_per_sec: 200,
+            burst_messages: 1000,
+        }
+    }
+}
+
+/// Hourly budgets for terminal output sent through the relay, for relays on
+/// metered hosts.
+///
+/// A session over its own budget, or every session once the total budget is
+/// used up, stops streaming output and instead sends a snapshot of its screen
+/// every `snapshot_secs` while it changes. Streaming resumes when the hour
+/// ends. 0 means no limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    pub enabled: bool,
+    pub session_bytes_per_hour: u64,
+    pub total_bytes_per_hour: u64,
+    pub snapshot_secs: u64,
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            session_bytes_per_hour: 50 * 1024 * 1024,
+            total_bytes_per_hour: 200 * 1024 * 1024,
+            snapshot_secs: 10,
+        }
+    }
+}
+
+impl Config {
+    /// Location of the config file (`config.toml` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("config.toml"))
+    }
+
+    /// Effective relay URL: `RELAY_URL`, then the config file, then the default.
+    pub fn relay_url(&self) -> String {
+        std::env::var("RELAY_URL")
+            .ok()
+            .or_else(|| self.relay_url.clone())
+            .unwrap_or_else(|| DEFAULT_RELAY_URL.to_string())
+    }
+
+    /// Effective relay API key: `RELAY_API_KEY`, then the config file.
+    pub fn relay_api_key(&self) -> Option<String> {
+        std::env::var("RELAY_API_KEY")
+            .ok()
+            .or_else(|| self.relay_api_key.clone())
+            .filter(|key| !key.is_empty())
+    }
+
+    /// Parse a config from TOML text.
+    pub fn from_toml_str(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Serialize the config as TOML text.
+    pub fn to_toml_string(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+
+    /// Write the config file, creating its directory if needed.
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = Self::path().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set")
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = self
+            .to_toml_string()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(&path, text)?;
+        info!("Saved config to {}", path.display());
+        Ok(())
+    }
+
+    /// Load the config file, falling back to defaults if it is missing or invalid.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => match Self::from_toml_str(&text) {
+                Ok(config) => {
+                    info!("Loaded config from {}", path.display());
+                    config
+                }
+                Err(e) => {
+                    warn!("Invalid config at {}, using defaults: {}", path.display(), e);
+                    Self::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                warn!("Failed to read config at {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        assert_eq!(Config::from_toml_str("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_partial_rate_limit_section() {
+        let config = Config::from_toml_str("[rate_limit]\nbytes_per_sec = 1024\n").unwrap();
+        assert_eq!(config.rate_limit.bytes_per_sec, 1024);
+        assert_eq!(
+            config.rate_limit.messages_per_sec,
+            RateLimitConfig::default().messages_per_sec
+        );
+        assert!(config.rate_limit.enabled);
+    }
+
+    #[test]
+    fn test_roundtrip_through_toml() {
+        let config = Config {
+            relay_url: Some("wss://relay.example.com/ws".into()),
+            notifications: false,
+            recording_dir: Some(PathBuf::from("/tmp/recordings")),
+            ..Config::default()
+        };
+        let text = config.to_toml_string().unwrap();
+        assert_eq!(Config::from_toml_str(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_close_window_names() {
+        let config = Config::from_toml_str("close_window = \"iterm\"\n").unwrap();
+        assert_eq!(config.close_window, CloseWindow::Iterm);
+        assert!(Config::from_toml_str("close_window = \"iTerm2\"\n").is_err());
+    }
+
+    #[test]
+    fn test_ssh_hosts() {
+        let config = Config::from_toml_str(
+            "[[ssh.hosts]]\ndestination = \"deploy@web1\"\nport = 2222\n\n\
+             [[ssh.hosts]]\nname = \"db\"\ndestination = \"db.internal\"\n",
+        )
+        .unwrap();
+        let labels: Vec<&str> = config.ssh.hosts.iter().map(|h| h.label()).collect();
+        assert_eq!(labels, vec!["deploy@web1", "db"]);
+        assert_eq!(config.ssh.hosts[0].port, Some(2222));
+        let text = config.to_toml_string().unwrap();
+        assert_eq!(Config::from_toml_str(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_relays() {
+        let config = Config::from_toml_str(
+            "relay_url = \"wss://a.example.com/ws\"\n\n\
+             [[relays]]\nname = \"Home\"\nurl = \"ws://localhost:3000/ws\"\n\n\
+             [[relays]]\nurl = \"wss://a.example.com/ws\"\nweb_url = \"https://a.example.com\"\n",
+        )
+        .unwrap();
+        let labels: Vec<&str> = config.relays.iter().map(|r| r.label()).collect();
+        assert_eq!(labels, vec!["Home", "wss://a.example.com/ws"]);
+        let text = config.to_toml_string().unwrap();
+        assert_eq!(Config::from_toml_str(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_invalid_type_rejected() {
+        assert!(Config::from_toml_str("[rate_limit]\nenabled = \"yes\"\n").is_err());
+    }
+}
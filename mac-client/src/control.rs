@@ -0,0 +1,241 @@
+//! Local control socket for querying mac-client state.
+//!
+//! Scripts, launcher plugins (Raycast, Alfred) and health checks connect to
+//! the control socket, write one JSON request per line and read one JSON
+//! response line back:
+//!
+//! ```text
+//! $ echo '{"type":"status"}' | nc -U ~/Library/Group\ Containers/group.com.terminal-remote/control.sock
+//! {"type":"status","relay":{...},"sessions":[...]}
+//! ```
+
+use crate::pty::PtyCommand;
+use crate::watchdog::{self, BoundSocket};
+use crate::{paths, socket};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+
+/// Requests accepted on the control socket (one JSON object per line).
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Report relay state and all attached sessions.
+    Status,
+}
+
+/// Responses written back on the control socket (one JSON object per line).
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status(StatusReport),
+    Error { message: String },
+}
+
+/// Snapshot of the relay connection, kept up to date by the background tasks.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RelayStatus {
+    /// Whether the WebSocket to the relay is currently open
+    pub connected: bool,
+    /// Session code assigned by the relay (None until registered)
+    pub session_code: Option<String>,
+    /// Public tunnel URL (None until cloudflared reports one)
+    pub tunnel_url: Option<String>,
+    /// Number of browsers currently joined to this mac-client
+    pub browsers: usize,
+}
+
+/// Relay status shared between the relay forwarder and the control server.
+pub type SharedRelayStatus = Arc<Mutex<RelayStatus>>;
+
+/// Per-session entry in a status report.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStatus {
+    pub id: String,
+    pub name: String,
+    pub shell: String,
+    pub pid: u32,
+    pub tty: String,
+    /// Browsers receiving this session's output. Every joined browser
+    /// receives every session, so this equals the relay-level count.
+    pub browsers: usize,
+    /// Shell output bytes mirrored to the relay
+    pub bytes_out: u64,
+    /// Browser input bytes injected into the shell
+    pub bytes_in: u64,
+}
+
+/// Full response to a `status` request.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub relay: RelayStatus,
+    pub sessions: Vec<SessionStatus>,
+}
+
+/// Listen for control requests on the Unix socket.
+pub async fn run_control_server(
+    relay_status: SharedRelayStatus,
+    pty_cmd_tx: mpsc::UnboundedSender<PtyCommand>,
+) -> std::io::Result<()> {
+    let socket_path = paths::control_socket();
+    let listener = socket::bind_exclusive(&socket_path)?;
+    let bound = BoundSocket::new(&socket_path)?;
+    info!("Control server listening on {}", socket_path.display());
+    if let Some(legacy) = paths::legacy_socket(&socket_path) {
+        if let Err(e) = socket::link_legacy(&socket_path, &legacy) {
+            warn!("Cannot link {} for older clients: {}", legacy.display(), e);
+        }
+    }
+
+    let mut checks = watchdog::checks();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = checks.tick() => {
+                bound.check().await.map_err(|e| {
+                    std::io::Error::other(format!("health check failed: {}", e))
+                })?;
+                continue;
+            }
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                let relay_status = relay_status.clone();
+                let pty_cmd_tx = pty_cmd_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_control_connection(stream, relay_status, pty_cmd_tx).await {
+                        debug!("Control connection ended: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Control accept failed: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Serve newline-delimited requests on a single control connection.
+async fn handle_control_connection(
+    stream: UnixStream,
+    relay_status: SharedRelayStatus,
+    pty_cmd_tx: mpsc::UnboundedSender<PtyCommand>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => handle_request(request, &relay_status, &pty_cmd_tx).await,
+            Err(e) => ControlResponse::Error {
+                message: format!("Invalid request: {}", e),
+            },
+        };
+        let mut json = serde_json::to_vec(&response)?;
+        json.push(b'\n');
+        writer.write_all(&json).await?;
+    }
+
+    Ok(())
+}
+
+/// Build the response for a single request.
+async fn handle_request(
+    request: ControlRequest,
+    relay_status: &SharedRelayStatus,
+    pty_cmd_tx: &mpsc::UnboundedSender<PtyCommand>,
+) -> ControlResponse {
+    match request {
+        ControlRequest::Status => {
+            let relay = relay_status.lock().unwrap().clone();
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if pty_cmd_tx.send(PtyCommand::ListSessions { reply: reply_tx }).is_err() {
+                return ControlResponse::Error {
+                    message: "PTY manager is not running".into(),
+                };
+            }
+            let Ok(sessions) = reply_rx.await else {
+                return ControlResponse::Error {
+                    message: "PTY manager did not answer".into(),
+                };
+            };
+
+            let sessions = sessions
+                .into_iter()
+                .map(|(id, info)| SessionStatus {
+                    id,
+                    name: info.name,
+                    shell: info.shell,
+                    pid: info.pid,
+                    tty: info.tty,
+                    browsers: relay.browsers,
+                    bytes_out: info.stats.bytes_out.load(Ordering::Relaxed),
+                    bytes_in: info.stats.bytes_in.load(Ordering::Relaxed),
+                })
+                .collect();
+
+            ControlResponse::Status(StatusReport { relay, sessions })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_request_deserialization() {
+        let req: ControlRequest = serde_json::from_str(r#"{"type":"status"}"#).unwrap();
+        assert!(matches!(req, ControlRequest::Status));
+    }
+
+    #[test]
+    fn test_unknown_request_rejected() {
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"type":"reboot"}"#).is_err());
+    }
+
+    #[test]
+    fn test_status_response_serialization() {
+        let report = StatusReport {
+            relay: RelayStatus {
+                connected: true,
+                session_code: Some("ABC123".into()),
+                tunnel_url: None,
+                browsers: 2,
+            },
+            sessions: vec![SessionStatus {
+                id: "sess-1".into(),
+                name: "zsh - ~".into(),
+                shell: "/bin/zsh".into(),
+                pid: 42,
+                tty: "/dev/ttys001".into(),
+                browsers: 2,
+                bytes_out: 1024,
+                bytes_in: 16,
+            }],
+        };
+        let json = serde_json::to_string(&ControlResponse::Status(report)).unwrap();
+        assert!(json.starts_with("{\"type\":\"status\""));
+        assert!(json.contains("\"session_code\":\"ABC123\""));
+        assert!(json.contains("\"bytes_out\":1024"));
+        assert!(json.contains("\"tty\":\"/dev/ttys001\""));
+    }
+
+    #[test]
+    fn test_error_response_serialization() {
+        let resp = ControlResponse::Error {
+            message: "nope".into(),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, "{\"type\":\"error\",\"message\":\"nope\"}");
+    }
+}
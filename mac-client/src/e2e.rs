@@ -0,0 +1,337 @@
+//! End-to-end encryption of terminal output.
+//!
+//! With `end_to_end_encryption = true` the relay only ever sees ciphertext
+//! for terminal output, so whoever operates the relay cannot read sessions.
+//!
+//! Keys:
+//!
+//! - The **device key** is a P-256 keypair plus a random 16-byte pairing
+//!   secret, created once and kept in `e2e_key` next to the config file.
+//! - The **pairing string** carries the device public key and the pairing
+//!   secret. It reaches the browser out of band, in the fragment of the
+//!   pairing link (shown as a QR code), which browsers never send to the
+//!   server.
+//! - The **content key** is a random AES-256-GCM key for this run. Every
+//!   output frame payload is sealed with it as `nonce || ciphertext`, with
+//!   the session ID as associated data.
+//!
+//! A browser holding the pairing string sends `e2e_hello` with an ephemeral
+//! P-256 public key. Both sides derive a wrapping key with HKDF-SHA256 over
+//! the ECDH secret, salted with the pairing secret, and the content key is
+//! returned wrapped in `e2e_key`. The relay can neither derive the wrapping
+//! key (it lacks the private keys) nor fake a hello of its own (it lacks the
+//! pairing secret). All of this is available in browsers through WebCrypto.
+//!
+//! Session names, sizes and clipboard messages are not encrypted.
+//!
+//! The mac-client registers as a passthrough session: the relay keeps and
+//! forwards frames as they are, routing them by the session ID in front of
+//! the payload (which stays in the clear), and leaves message contents out
+//! of its logs.
+
+use crate::paths;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hkdf::Hkdf;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Version byte at the start of a pairing string.
+const PAIRING_VERSION: u8 = 1;
+
+/// HKDF info string for the key-wrapping key.
+const WRAP_INFO: &[u8] = b"terminal-remote e2e v1";
+
+const SECRET_KEY_LEN: usize = 32;
+const PAIRING_SECRET_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Fragment parameter carrying the pairing string in a pairing link.
+pub const PAIRING_PARAM: &str = "pair";
+
+/// Long-lived device identity shared with browsers by pairing.
+#[derive(Clone)]
+pub struct DeviceKey {
+    secret: SecretKey,
+    pairing_secret: [u8; PAIRING_SECRET_LEN],
+}
+
+impl DeviceKey {
+    /// Create a new random device key.
+    pub fn generate() -> Self {
+        let mut pairing_secret = [0u8; PAIRING_SECRET_LEN];
+        OsRng.fill_bytes(&mut pairing_secret);
+        Self {
+            secret: SecretKey::random(&mut OsRng),
+            pairing_secret,
+        }
+    }
+
+    /// Location of the key file (`e2e_key` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("e2e_key"))
+    }
+
+    /// Load the device key, creating and saving a new one on first use.
+    pub fn load_or_create() -> io::Result<Self> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::decode(text.trim()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a valid device key", path.display()),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = Self::generate();
+                key.save(&path)?;
+                Ok(key)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the key readable by the owner only.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        writeln!(file, "{}", self.encode())
+    }
+
+    fn encode(&self) -> String {
+        let mut bytes = self.secret.to_bytes().to_vec();
+        bytes.extend_from_slice(&self.pairing_secret);
+        STANDARD.encode(bytes)
+    }
+
+    fn decode(text: &str) -> Option<Self> {
+        let bytes = STANDARD.decode(text).ok()?;
+        if bytes.len() != SECRET_KEY_LEN + PAIRING_SECRET_LEN {
+            return None;
+        }
+        let secret = SecretKey::from_slice(&bytes[..SECRET_KEY_LEN]).ok()?;
+        let mut pairing_secret = [0u8; PAIRING_SECRET_LEN];
+        pairing_secret.copy_from_slice(&bytes[SECRET_KEY_LEN..]);
+        Some(Self {
+            secret,
+            pairing_secret,
+        })
+    }
+
+    /// Uncompressed SEC1 encoding of the public key (65 bytes).
+    fn public_key_bytes(&self) -> Vec<u8> {
+        self.secret
+            .public_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    /// Pairing string: base64url of version, public key and pairing secret.
+    pub fn pairing_string(&self) -> String {
+        let mut bytes = vec![PAIRING_VERSION];
+        bytes.extend_from_slice(&self.public_key_bytes());
+        bytes.extend_from_slice(&self.pairing_secret);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Short fingerprint of the public key, e.g. "3f2a-91c0-7be4", shown on
+    /// both ends so users can check they paired with the right Mac.
+    pub fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(self.public_key_bytes());
+        digest[..6]
+            .chunks(2)
+            .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    /// Key-wrapping key shared with the holder of `peer`'s private key.
+    fn wrap_key(&self, peer: &PublicKey) -> Key<Aes256Gcm> {
+        let shared = p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), peer.as_affine());
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.pairing_secret), shared.raw_secret_bytes());
+        let mut key = Key::<Aes256Gcm>::default();
+        hkdf.expand(WRAP_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        key
+    }
+}
+
+/// Pairing link: the browser URL with the pairing string in its fragment.
+pub fn pairing_link(base_url: &str, pairing: &str) -> String {
+    format!("{}/#{}={}", base_url.trim_end_matches('/'), PAIRING_PARAM, pairing)
+}
+
+/// Render `text` as a QR code PNG at `path`.
+pub fn write_qr_png(text: &str, path: &Path) -> Result<(), String> {
+    let code = qrcode::QrCode::new(text.as_bytes()).map_err(|e| e.to_string())?;
+    let image = code
+        .render::<image::Luma<u8>>()
+        .min_dimensions(320, 320)
+        .build();
+    image.save(path).map_err(|e| e.to_string())
+}
+
+/// Encrypts output frames for paired browsers.
+pub struct Encryptor {
+    device: DeviceKey,
+    content_key: Key<Aes256Gcm>,
+    cipher: Aes256Gcm,
+}
+
+impl Encryptor {
+    /// Start a run with a fresh content key.
+    pub fn new(device: DeviceKey) -> Self {
+        let content_key = Aes256Gcm::generate_key(&mut OsRng);
+        Self {
+            device,
+            cipher: Aes256Gcm::new(&content_key),
+            content_key,
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.device.fingerprint()
+    }
+
+    /// Seal an output payload for `session_id`: `nonce || ciphertext`.
+    pub fn seal(&self, session_id: &str, data: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data,
+                    aad: session_id.as_bytes(),
+                },
+            )
+            .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+        let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    /// Wrap the content key for the browser that sent `e2e_hello` with
+    /// `public_key` (base64 SEC1). Returns the base64 `nonce || ciphertext`.
+    pub fn wrap_content_key(&self, public_key: &str) -> Result<String, String> {
+        let bytes = STANDARD
+            .decode(public_key)
+            .map_err(|_| "public key is not valid base64".to_string())?;
+        let peer = PublicKey::from_sec1_bytes(&bytes)
+            .map_err(|_| "public key is not a P-256 point".to_string())?;
+        let cipher = Aes256Gcm::new(&self.device.wrap_key(&peer));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.content_key,
+                    aad: &bytes,
+                },
+            )
+            .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&sealed);
+        Ok(STANDARD.encode(out))
+    }
+}
+
+/// Split `nonce || ciphertext` and decrypt it.
+#[cfg(test)]
+fn open(cipher: &Aes256Gcm, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, ciphertext) = sealed.split_at_checked(NONCE_LEN)?;
+    cipher
+        .decrypt(aes_gcm::Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a browser does with the pairing string and the `e2e_key` reply.
+    fn browser_unwrap(pairing: &str, browser: &SecretKey, wrapped: &str) -> Option<Aes256Gcm> {
+        let bytes = URL_SAFE_NO_PAD.decode(pairing).ok()?;
+        assert_eq!(bytes[0], PAIRING_VERSION);
+        let mac_public = PublicKey::from_sec1_bytes(&bytes[1..66]).ok()?;
+        let pairing_secret = &bytes[66..];
+
+        let shared = p256::ecdh::diffie_hellman(browser.to_nonzero_scalar(), mac_public.as_affine());
+        let hkdf = Hkdf::<Sha256>::new(Some(pairing_secret), shared.raw_secret_bytes());
+        let mut wrap_key = Key::<Aes256Gcm>::default();
+        hkdf.expand(WRAP_INFO, &mut wrap_key).ok()?;
+
+        let browser_public = browser.public_key().to_encoded_point(false);
+        let content_key = open(
+            &Aes256Gcm::new(&wrap_key),
+            &STANDARD.decode(wrapped).ok()?,
+            browser_public.as_bytes(),
+        )?;
+        Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&content_key)))
+    }
+
+    #[test]
+    fn test_paired_browser_decrypts_output() {
+        let device = DeviceKey::generate();
+        let pairing = device.pairing_string();
+        let encryptor = Encryptor::new(device);
+
+        let browser = SecretKey::random(&mut OsRng);
+        let hello = STANDARD.encode(browser.public_key().to_encoded_point(false).as_bytes());
+        let wrapped = encryptor.wrap_content_key(&hello).unwrap();
+        let content = browser_unwrap(&pairing, &browser, &wrapped).unwrap();
+
+        let sealed = encryptor.seal("sess-1", b"ls -la\r\n");
+        assert_ne!(&sealed[NONCE_LEN..], b"ls -la\r\n");
+        assert_eq!(open(&content, &sealed, b"sess-1").unwrap(), b"ls -la\r\n");
+        // Frames cannot be moved to another session
+        assert!(open(&content, &sealed, b"sess-2").is_none());
+    }
+
+    #[test]
+    fn test_wrong_pairing_secret_cannot_unwrap() {
+        let device = DeviceKey::generate();
+        let encryptor = Encryptor::new(device.clone());
+        let browser = SecretKey::random(&mut OsRng);
+        let hello = STANDARD.encode(browser.public_key().to_encoded_point(false).as_bytes());
+        let wrapped = encryptor.wrap_content_key(&hello).unwrap();
+
+        // Same public key, different pairing secret (e.g. the relay guessing)
+        let mut forged = URL_SAFE_NO_PAD.decode(device.pairing_string()).unwrap();
+        *forged.last_mut().unwrap() ^= 1;
+        let forged = URL_SAFE_NO_PAD.encode(forged);
+        assert!(browser_unwrap(&forged, &browser, &wrapped).is_none());
+
+        assert!(encryptor.wrap_content_key("not base64!").is_err());
+        assert!(encryptor.wrap_content_key(&STANDARD.encode([4u8; 65])).is_err());
+    }
+
+    #[test]
+    fn test_device_key_roundtrip() {
+        let device = DeviceKey::generate();
+        let decoded = DeviceKey::decode(&device.encode()).unwrap();
+        assert_eq!(decoded.pairing_string(), device.pairing_string());
+        assert_eq!(decoded.fingerprint().len(), 14);
+        assert!(DeviceKey::decode("AAAA").is_none());
+        assert_eq!(
+            pairing_link("https://x.trycloudflare.com/", "abc"),
+            "https://x.trycloudflare.com/#pair=abc"
+        );
+    }
+}
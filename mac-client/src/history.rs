@@ -0,0 +1,161 @@
+//! Recently ended sessions, persisted for the "Recent" tray submenu.
+//!
+//! When a session detaches its name, shell, working directory and duration
+//! are recorded in `recent.json` next to the config file. Choosing an entry from
+//! the menu opens a new Terminal window in the same directory with the same
+//! shell.
+
+use crate::applescript;
+use crate::notify::applescript_quote;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// Number of entries kept in the history.
+pub const MAX_RECENT: usize = 10;
+
+/// Metadata of a session that has ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentSession {
+    pub name: String,
+    pub shell: String,
+    pub cwd: Option<String>,
+    /// Unix timestamp (seconds) when the session attached
+    pub started_at: u64,
+    pub duration_secs: u64,
+}
+
+impl RecentSession {
+    /// Menu label, e.g. "zsh - ~/src (1h 5m)".
+    pub fn label(&self) -> String {
+        format!("{} ({})", self.name, format_duration(self.duration_secs))
+    }
+
+    /// Shell command that reopens this session's directory and shell.
+    pub fn reopen_command(&self) -> String {
+        let exec = format!("exec {}", shell_quote(&self.shell));
+        match &self.cwd {
+            Some(cwd) => format!("cd {} && {}", shell_quote(cwd), exec),
+            None => exec,
+        }
+    }
+}
+
+/// Compact human-readable duration ("45s", "12m", "1h 5m").
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+/// Quote a string for POSIX sh.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Most recent first, at most [`MAX_RECENT`] entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentSessions {
+    pub entries: Vec<RecentSession>,
+}
+
+impl RecentSessions {
+    /// Location of the history file (`recent.json` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("recent.json"))
+    }
+
+    /// Load the history, starting empty if it is missing or unreadable.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Ignoring invalid session history at {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write the history file.
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = Self::path().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set")
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Record an ended session. An older entry for the same directory and
+    /// shell is replaced so the menu does not fill up with duplicates.
+    pub fn push(&mut self, session: RecentSession) {
+        self.entries
+            .retain(|e| !(e.cwd == session.cwd && e.shell == session.shell));
+        self.entries.insert(0, session);
+        self.entries.truncate(MAX_RECENT);
+    }
+}
+
+/// Open a new Terminal window in the session's directory with its shell.
+pub fn reopen(session: &RecentSession) {
+    let script = format!(
+        "tell application \"Terminal\"\nactivate\ndo script {}\nend tell",
+        applescript_quote(&session.reopen_command())
+    );
+    info!("Reopening recent session: {}", session.name);
+    applescript::queue("reopen", script);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(cwd: &str, shell: &str) -> RecentSession {
+        RecentSession {
+            name: format!("{} - {}", shell, cwd),
+            shell: shell.into(),
+            cwd: Some(cwd.into()),
+            started_at: 0,
+            duration_secs: 90,
+        }
+    }
+
+    #[test]
+    fn test_push_dedupes_and_caps() {
+        let mut recent = RecentSessions::default();
+        recent.push(session("/a", "/bin/zsh"));
+        recent.push(session("/b", "/bin/zsh"));
+        recent.push(session("/a", "/bin/zsh"));
+        assert_eq!(recent.entries.len(), 2);
+        assert_eq!(recent.entries[0].cwd.as_deref(), Some("/a"));
+
+        for i in 0..20 {
+            recent.push(session(&format!("/d{}", i), "/bin/bash"));
+        }
+        assert_eq!(recent.entries.len(), MAX_RECENT);
+        assert_eq!(recent.entries[0].cwd.as_deref(), Some("/d19"));
+    }
+
+    #[test]
+    fn test_reopen_command_quotes_paths() {
+        let s = session("/Users/me/it's here", "/bin/zsh");
+        assert_eq!(
+            s.reopen_command(),
+            r"cd '/Users/me/it'\''s here' && exec '/bin/zsh'"
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(12 * 60 + 5), "12m");
+        assert_eq!(format_duration(3900), "1h 5m");
+    }
+}
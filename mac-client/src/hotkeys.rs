@@ -0,0 +1,174 @@
+//! Global keyboard shortcuts (`[hotkeys]` in the config).
+//!
+//! Frequent menu actions can be bound to system-wide shortcuts so they work
+//! without reaching for the menu bar. A shortcut is written as modifiers and
+//! a key joined by `+` ("Option+Shift+C") or with the macOS symbols
+//! ("⌥⇧C"); an empty string turns it off. Shortcuts are registered at
+//! startup; invalid ones and ones another app already holds are listed under
+//! "Errors" and skipped.
+
+use crate::config::HotkeyConfig;
+use global_hotkey::hotkey::{HotKey, Modifiers};
+use global_hotkey::GlobalHotKeyManager;
+use std::collections::HashMap;
+use tracing::info;
+
+/// What a shortcut does; each matches a menu item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    CopyJoinUrl,
+    CopyCode,
+    ShowJoinQr,
+    ToggleDoNotDisturb,
+}
+
+impl HotkeyAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            HotkeyAction::CopyJoinUrl => "Copy Join URL",
+            HotkeyAction::CopyCode => "Copy Session Code",
+            HotkeyAction::ShowJoinQr => "Show QR Code",
+            HotkeyAction::ToggleDoNotDisturb => "Do Not Disturb",
+        }
+    }
+}
+
+/// Parse a shortcut; `None` if it is empty (turned off).
+pub fn parse(shortcut: &str) -> Result<Option<HotKey>, String> {
+    let shortcut = shortcut.trim();
+    if shortcut.is_empty() {
+        return Ok(None);
+    }
+    let mut text = String::new();
+    for c in shortcut.chars() {
+        match c {
+            '⌘' => text.push_str("Cmd+"),
+            '⌥' => text.push_str("Option+"),
+            '⌃' => text.push_str("Ctrl+"),
+            '⇧' => text.push_str("Shift+"),
+            c => text.push(c),
+        }
+    }
+    let hotkey: HotKey = text
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a valid shortcut", shortcut))?;
+    // A key with only Shift (or nothing) would swallow ordinary typing
+    if !hotkey
+        .mods
+        .intersects(Modifiers::SUPER | Modifiers::ALT | Modifiers::CONTROL)
+    {
+        return Err(format!(
+            "\"{}\" needs Command, Option or Control to be a global shortcut",
+            shortcut
+        ));
+    }
+    Ok(Some(hotkey))
+}
+
+/// The configured shortcuts with their actions, and a message for each one
+/// that is invalid or bound twice.
+pub fn bindings(config: &HotkeyConfig) -> (Vec<(HotKey, HotkeyAction)>, Vec<String>) {
+    let configured = [
+        (&config.copy_join_url, HotkeyAction::CopyJoinUrl),
+        (&config.copy_code, HotkeyAction::CopyCode),
+        (&config.show_qr_code, HotkeyAction::ShowJoinQr),
+        (&config.do_not_disturb, HotkeyAction::ToggleDoNotDisturb),
+    ];
+    let mut bound: Vec<(HotKey, HotkeyAction)> = Vec::new();
+    let mut problems = Vec::new();
+    for (shortcut, action) in configured {
+        match parse(shortcut) {
+            Ok(None) => {}
+            Ok(Some(hotkey)) => match bound.iter().find(|(other, _)| other.id() == hotkey.id()) {
+                Some((_, other)) => problems.push(format!(
+                    "Shortcut \"{}\" for {} is already used for {}",
+                    shortcut,
+                    action.label(),
+                    other.label()
+                )),
+                None => bound.push((hotkey, action)),
+            },
+            Err(e) => problems.push(format!("{} ({})", e, action.label())),
+        }
+    }
+    (bound, problems)
+}
+
+/// Registered shortcuts. Dropping this unregisters them.
+pub struct Hotkeys {
+    _manager: GlobalHotKeyManager,
+    actions: HashMap<u32, HotkeyAction>,
+}
+
+impl Hotkeys {
+    /// Register the configured shortcuts, returning a message for each one
+    /// that could not be. Call on the main thread.
+    pub fn register(config: &HotkeyConfig) -> (Option<Self>, Vec<String>) {
+        let (bound, mut problems) = bindings(config);
+        if bound.is_empty() {
+            return (None, problems);
+        }
+        let manager = match GlobalHotKeyManager::new() {
+            Ok(manager) => manager,
+            Err(e) => {
+                problems.push(format!("Cannot register global shortcuts: {}", e));
+                return (None, problems);
+            }
+        };
+        let mut actions = HashMap::new();
+        for (hotkey, action) in bound {
+            match manager.register(hotkey) {
+                Ok(()) => {
+                    info!("Global shortcut {} for {}", hotkey.into_string(), action.label());
+                    actions.insert(hotkey.id(), action);
+                }
+                Err(e) => problems.push(format!(
+                    "Cannot register shortcut {} for {}: {}",
+                    hotkey.into_string(),
+                    action.label(),
+                    e
+                )),
+            }
+        }
+        let hotkeys = Self {
+            _manager: manager,
+            actions,
+        };
+        (Some(hotkeys), problems)
+    }
+
+    /// The action bound to a pressed shortcut.
+    pub fn action(&self, id: u32) -> Option<HotkeyAction> {
+        self.actions.get(&id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use global_hotkey::hotkey::Code;
+
+    #[test]
+    fn test_parse_names_and_symbols() {
+        let expected = HotKey::new(Some(Modifiers::ALT | Modifiers::SHIFT), Code::KeyC);
+        assert_eq!(parse("Option+Shift+C").unwrap(), Some(expected));
+        assert_eq!(parse("⌥⇧C").unwrap(), Some(expected));
+        assert_eq!(parse("  ").unwrap(), None);
+        assert!(parse("Shift+C").is_err());
+        assert!(parse("Option+Shift+Nope").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_shortcuts_are_reported() {
+        let config = HotkeyConfig {
+            copy_code: "⌥⇧P".into(),
+            show_qr_code: "Cmd".into(),
+            ..HotkeyConfig::default()
+        };
+        let (bound, problems) = bindings(&config);
+        let actions: Vec<HotkeyAction> = bound.iter().map(|(_, action)| *action).collect();
+        assert_eq!(actions, vec![HotkeyAction::CopyJoinUrl, HotkeyAction::CopyCode]);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[1].contains("already used for Copy Session Code"), "{:?}", problems);
+    }
+}
@@ -0,0 +1,123 @@
+//! Identity of this mac-client towards the relay.
+//!
+//! The client ID is a UUID created on first start and kept in `client_id`
+//! next to the config file, so the relay sees the same client across
+//! restarts and reconnects and can account usage to it. The display name
+//! (`display_name` in the config, else the Mac's computer name) is shown to
+//! browsers, so someone running Terminal Remote on several Macs can tell
+//! them apart.
+
+use crate::paths;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// How this mac-client registers with the relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub id: String,
+    pub name: String,
+}
+
+impl ClientIdentity {
+    /// Location of the client ID file (`client_id` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("client_id"))
+    }
+
+    /// Load the stored client ID (creating it on first use) and pick the
+    /// display name. Falls back to an ID for this run only if the file
+    /// cannot be read or written.
+    pub fn load(display_name: Option<&str>) -> Self {
+        let id = match Self::path() {
+            Some(path) => load_or_create_id(&path).unwrap_or_else(|e| {
+                warn!("Cannot use client ID at {}: {}", path.display(), e);
+                uuid::Uuid::new_v4().to_string()
+            }),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        let name = display_name_or(display_name, computer_name);
+        info!("Client ID {} ({})", id, name);
+        Self { id, name }
+    }
+}
+
+/// `configured` unless it is missing or blank, else `fallback()`.
+fn display_name_or(configured: Option<&str>, fallback: impl FnOnce() -> String) -> String {
+    configured
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(fallback)
+}
+
+/// Read the client ID from `path`, writing a new one if the file is missing
+/// or does not hold a UUID.
+fn load_or_create_id(path: &Path) -> io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) if uuid::Uuid::parse_str(text.trim()).is_ok() => {
+            return Ok(text.trim().to_string());
+        }
+        Ok(_) => warn!("{} does not hold a client ID, replacing it", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    std::fs::write(path, format!("{}\n", id))?;
+    Ok(id)
+}
+
+/// The name set in System Settings > General > Sharing, else the host name.
+fn computer_name() -> String {
+    let from_scutil = Command::new("scutil")
+        .args(["--get", "ComputerName"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|name| !name.is_empty());
+    from_scutil.unwrap_or_else(|| {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+            return "Mac".to_string();
+        }
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        let host = String::from_utf8_lossy(&buf[..len]);
+        host.trim_end_matches(".local").to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_id_persists_and_replaces_garbage() {
+        let dir = std::env::temp_dir().join(format!("client-id-test-{}", std::process::id()));
+        let path = dir.join("client_id");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let id = load_or_create_id(&path).unwrap();
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+        assert_eq!(load_or_create_id(&path).unwrap(), id);
+
+        std::fs::write(&path, "not a uuid").unwrap();
+        let replaced = load_or_create_id(&path).unwrap();
+        assert_ne!(replaced, id);
+        assert_eq!(load_or_create_id(&path).unwrap(), replaced);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_display_name_from_config_wins() {
+        let fallback = || "MacBook".to_string();
+        assert_eq!(display_name_or(Some("  Studio Mac "), fallback), "Studio Mac");
+        assert_eq!(display_name_or(Some(" "), fallback), "MacBook");
+        assert_eq!(display_name_or(None, fallback), "MacBook");
+    }
+}
@@ -0,0 +1,143 @@
+//! Join URL and its QR code.
+//!
+//! The join URL opens the web UI's login page with the session code in the
+//! fragment (`/login#code=ABC123`), so a phone or another laptop can connect
+//! by scanning instead of typing the code. With end-to-end encryption on it
+//! also carries the pairing string. The tray's "Show QR Code…" popover draws
+//! it with [`QrImage`].
+//!
+//! A one-time link also carries a token from the relay (`&token=…`): it lets
+//! one browser in without the password or approval, once, and stops working
+//! after [`ONE_TIME_LINK_TTL_SECS`]. A view-only link's token lets it in as
+//! a viewer only, which the relay enforces; the link also says `role=viewer`
+//! so the web UI asks for no more.
+
+use crate::e2e::PAIRING_PARAM;
+use crate::protocol::Role;
+
+/// Fragment parameter carrying the session code in a join URL.
+pub const CODE_PARAM: &str = "code";
+
+/// Fragment parameter carrying the one-time token in a join URL.
+pub const TOKEN_PARAM: &str = "token";
+
+/// Fragment parameter asking the web UI to join as a viewer.
+pub const ROLE_PARAM: &str = "role";
+
+/// How long one-time links work.
+pub const ONE_TIME_LINK_TTL_SECS: u64 = 600;
+
+/// Light modules of the quiet zone around the code, per side.
+const QUIET_ZONE: usize = 4;
+
+const DARK: u32 = 0x0000_0000;
+const LIGHT: u32 = 0x00ff_ffff;
+
+/// Join URL for `code` on the web UI at `base`, with the pairing string
+/// when end-to-end encryption is on.
+pub fn join_url(base: &str, code: &str, pairing: Option<&str>) -> String {
+    let mut url = format!("{}/login#{}={}", base.trim_end_matches('/'), CODE_PARAM, code);
+    if let Some(pairing) = pairing {
+        url.push_str(&format!("&{}={}", PAIRING_PARAM, pairing));
+    }
+    url
+}
+
+/// Join URL that works once, with the `token` minted by the relay for
+/// `role`.
+pub fn one_time_url(base: &str, code: &str, token: &str, role: Role, pairing: Option<&str>) -> String {
+    let mut url = format!("{}&{}={}", join_url(base, code, pairing), TOKEN_PARAM, token);
+    if role == Role::Viewer {
+        url.push_str(&format!("&{}=viewer", ROLE_PARAM));
+    }
+    url
+}
+
+/// QR code modules, ready to draw at any size.
+pub struct QrImage {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl QrImage {
+    pub fn new(text: &str) -> Result<Self, String> {
+        let code = qrcode::QrCode::new(text.as_bytes()).map_err(|e| e.to_string())?;
+        Ok(Self {
+            width: code.width(),
+            dark: code
+                .to_colors()
+                .into_iter()
+                .map(|c| c == qrcode::Color::Dark)
+                .collect(),
+        })
+    }
+
+    /// Modules per side, including the quiet zone.
+    pub fn modules(&self) -> usize {
+        self.width + 2 * QUIET_ZONE
+    }
+
+    /// Draw the code centered on a white `width` x `height` canvas, scaled
+    /// by the largest whole number of pixels per module that fits. Pixels
+    /// are `0x00RRGGBB`, row by row.
+    pub fn render(&self, width: u32, height: u32) -> Vec<u32> {
+        let (width, height) = (width as usize, height as usize);
+        let mut pixels = vec![LIGHT; width * height];
+        let scale = (width.min(height) / self.modules()).max(1);
+        let left = width.saturating_sub(self.width * scale) / 2;
+        let top = height.saturating_sub(self.width * scale) / 2;
+        for y in 0..(self.width * scale).min(height) {
+            for x in 0..(self.width * scale).min(width) {
+                if self.dark[(y / scale) * self.width + x / scale] {
+                    let (px, py) = (left + x, top + y);
+                    if px < width && py < height {
+                        pixels[py * width + px] = DARK;
+                    }
+                }
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_url_carries_code_and_pairing() {
+        assert_eq!(
+            join_url("https://t.example.com/", "ABC123", None),
+            "https://t.example.com/login#code=ABC123"
+        );
+        assert_eq!(
+            join_url("http://localhost:3000", "ABC123", Some("AQID")),
+            "http://localhost:3000/login#code=ABC123&pair=AQID"
+        );
+        assert_eq!(
+            one_time_url("http://localhost:3000", "ABC123", "t0k3n", Role::Controller, None),
+            "http://localhost:3000/login#code=ABC123&token=t0k3n"
+        );
+        assert_eq!(
+            one_time_url("http://localhost:3000", "ABC123", "t0k3n", Role::Viewer, None),
+            "http://localhost:3000/login#code=ABC123&token=t0k3n&role=viewer"
+        );
+    }
+
+    #[test]
+    fn test_render_scales_and_centers_code() {
+        let qr = QrImage::new("https://t.example.com/login#code=ABC123").unwrap();
+        let side = (qr.modules() * 3) as u32;
+        let pixels = qr.render(side, side + 10);
+        assert_eq!(pixels.len(), (side * (side + 10)) as usize);
+
+        // The top-left finder pattern (a dark ring around a light ring)
+        // starts after the quiet zone, 5 rows down for vertical centering.
+        let at = |x: usize, y: usize| pixels[y * side as usize + x];
+        let (left, top) = (QUIET_ZONE * 3, QUIET_ZONE * 3 + 5);
+        assert_eq!(at(left - 1, top), LIGHT);
+        assert_eq!(at(left, top - 1), LIGHT);
+        assert_eq!(at(left, top), DARK);
+        assert_eq!(at(left + 3, top + 3), LIGHT);
+    }
+}
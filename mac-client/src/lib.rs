@@ -1,6 +1,40 @@
 // mac-client library root
 
+pub mod activity;
+pub mod alerts;
 pub mod app;
+pub mod backend;
+pub mod bandwidth;
+pub mod applescript;
+pub mod approval;
+pub mod bell;
+pub mod clipboard;
+pub mod config;
+pub mod control;
+pub mod diagnostics;
+pub mod e2e;
+pub mod history;
+pub mod hotkeys;
+pub mod identity;
+pub mod join;
+pub mod notify;
+pub mod orphans;
+pub mod paste_guard;
+pub mod paths;
+pub mod preferences;
+pub mod project;
 pub mod protocol;
 pub mod pty;
+pub mod ratelimit;
 pub mod relay;
+pub mod router;
+pub mod shell_integration;
+pub mod sizing;
+pub mod socket;
+pub mod ssh;
+pub mod supervisor;
+pub mod telemetry;
+pub mod tmux;
+pub mod transcript;
+pub mod url_scheme;
+pub mod watchdog;
@@ -7,37 +7,240 @@
 //! We use winit's EventLoop to drive the main thread.
 
 use image::ImageReader;
+use mac_client::activity::{self, ActivityTracker, SessionView};
+use mac_client::alerts::{Alert, AlertLog};
 use mac_client::app::{AppState, BackgroundCommand, UiEvent};
-use mac_client::pty::{PtyCommand, PtyEvent, PtyManager};
+use mac_client::backend::{Backends, SessionBackend};
+use mac_client::applescript;
+use mac_client::approval::{self, PendingApproval};
+use mac_client::bell;
+use mac_client::clipboard::{self, ClipboardBridge, Offer};
+use mac_client::config::Config;
+use mac_client::control::{self, RelayStatus, SessionStatus, SharedRelayStatus};
+use mac_client::diagnostics;
+use mac_client::e2e::{self, DeviceKey, Encryptor};
+use mac_client::history::{self, RecentSessions};
+use mac_client::hotkeys::{HotkeyAction, Hotkeys};
+use mac_client::identity::ClientIdentity;
+use mac_client::join::{self, QrImage};
+use mac_client::notify;
+use mac_client::orphans::{self, OrphanProxy, OrphanTracker};
+use mac_client::paste_guard::{HeldPaste, MAX_HELD_PASTES};
+use mac_client::paths;
+use mac_client::preferences;
+use mac_client::protocol::{Role, Viewer};
+use mac_client::pty::registry::{SessionRegistry, SharedRegistry};
+use mac_client::pty::{PtyCommand, PtyManager, SessionStats};
 use mac_client::relay::{RelayClient, RelayCommand, RelayEvent};
-use muda::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use mac_client::router::{InboundFrame, Router};
+use mac_client::shell_integration::{self, Shell};
+use mac_client::socket::{self, SocketState};
+use mac_client::ssh::SshManager;
+use mac_client::supervisor::supervise;
+use mac_client::telemetry::{self, Usage};
+use mac_client::tmux::TmuxManager;
+use mac_client::transcript::{self, Transcript};
+use mac_client::url_scheme::{self, UrlAction};
+use mac_client::watchdog::{self, Heartbeat};
+use muda::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
 use smappservice_rs::{AppService, ServiceStatus, ServiceType};
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader, Cursor};
+use std::num::NonZeroU32;
 use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::{mpsc, Arc};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::runtime::Runtime;
-use tray_icon::{TrayIcon, TrayIconBuilder};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
 use tracing::{debug, error, info, warn};
 use winit::application::ApplicationHandler;
-use winit::event::WindowEvent;
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::event::{ElementState, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
-use winit::window::WindowId;
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{Window, WindowId, WindowLevel};
+
+/// A log file larger than this is set aside at startup.
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
 
 // Menu item IDs
 const ID_REGEN_CODE: &str = "regen_code";
 const ID_COPY_URL: &str = "copy_url";
 const ID_COPY_CODE: &str = "copy_code";
+const ID_COPY_JOIN_URL: &str = "copy_join_url";
+const ID_COPY_ONE_TIME_LINK: &str = "copy_one_time_link";
+const ID_COPY_VIEW_ONLY_LINK: &str = "copy_view_only_link";
+const ID_PREFERENCES: &str = "preferences";
+const ID_SAVE_DIAGNOSTICS: &str = "save_diagnostics";
 const ID_LOGIN_ITEM: &str = "login_item";
+const ID_SHELL_INTEGRATION: &str = "shell_integration";
+const ID_DO_NOT_DISTURB: &str = "do_not_disturb";
 const ID_QUIT: &str = "quit";
+/// Prefix of "Recent" submenu item IDs, followed by the entry index
+const ID_RECENT_PREFIX: &str = "recent:";
+const ID_CLEAR_ERRORS: &str = "clear_errors";
+const ID_COPY_PAIRING_LINK: &str = "copy_pairing_link";
+const ID_SHOW_PAIRING_QR: &str = "show_pairing_qr";
+const ID_SHOW_JOIN_QR: &str = "show_join_qr";
+/// Prefix of the per-project "Share with Browsers" item IDs, followed by the project
+const ID_PROJECT_SHARE_PREFIX: &str = "project_share:";
+/// Sessions submenu view toggles
+const ID_SESSIONS_ONLY_ACTIVE: &str = "sessions_only_active";
+const ID_SESSIONS_BY_ACTIVITY: &str = "sessions_by_activity";
+/// Answers to browsers waiting for approval: prefix + browser ID
+const ID_APPROVE_PREFIX: &str = "approve:";
+const ID_ALWAYS_ALLOW_PREFIX: &str = "always_allow:";
+const ID_DENY_PREFIX: &str = "deny:";
+/// Transcript exports from the session submenus: prefix + session ID
+const ID_EXPORT_TEXT_PREFIX: &str = "export_text:";
+const ID_EXPORT_RAW_PREFIX: &str = "export_raw:";
+/// "Read Only" toggles in the session submenus: prefix + session ID
+const ID_READ_ONLY_PREFIX: &str = "read_only:";
+/// Actions on disconnected pty-proxies: prefix + proxy pid
+const ID_ORPHAN_RECONNECT_PREFIX: &str = "orphan_reconnect:";
+const ID_ORPHAN_WAIT_PREFIX: &str = "orphan_wait:";
+const ID_ORPHAN_KILL_PREFIX: &str = "orphan_kill:";
+/// Answers to held pastes: prefix + paste ID
+const ID_PASTE_SEND_PREFIX: &str = "paste_send:";
+const ID_PASTE_DISCARD_PREFIX: &str = "paste_discard:";
+/// "SSH Hosts" submenu item IDs: prefix + index in the config's hosts
+const ID_SSH_PREFIX: &str = "ssh:";
+/// "Relay" submenu item IDs: prefix + index in the config's relays
+const ID_RELAY_PREFIX: &str = "relay:";
 
 /// Custom events for our application
 #[derive(Debug)]
 enum AppEvent {
     TrayIconEvent(tray_icon::TrayIconEvent),
     MenuEvent(muda::MenuEvent),
+    HotKey(global_hotkey::GlobalHotKeyEvent),
+}
+
+/// A session's submenu in its project submenu
+struct SessionMenu {
+    session_id: String,
+    menu: Submenu,
+    output: MenuItem,
+    input: MenuItem,
+    activity: MenuItem,
+}
+
+impl SessionMenu {
+    fn new(session: &activity::SessionActivity, read_only: bool, now: Instant) -> Self {
+        let [output, input] = session.traffic_labels();
+        let entry = Self {
+            session_id: session.session_id.clone(),
+            menu: Submenu::new(session.title(), true),
+            output: MenuItem::new(output, false, None),
+            input: MenuItem::new(input, false, None),
+            activity: MenuItem::new(session.activity_label(now), false, None),
+        };
+        let id = &session.session_id;
+        let _ = entry.menu.append_items(&[
+            &entry.output,
+            &entry.input,
+            &entry.activity,
+            &PredefinedMenuItem::separator(),
+            &CheckMenuItem::with_id(format!("{}{}", ID_READ_ONLY_PREFIX, id), "Read Only", true, read_only, None),
+            &MenuItem::with_id(format!("{}{}", ID_EXPORT_TEXT_PREFIX, id), "Export Transcript…", true, None),
+            &MenuItem::with_id(format!("{}{}", ID_EXPORT_RAW_PREFIX, id), "Export Raw Transcript…", true, None),
+        ]);
+        entry
+    }
+
+    fn refresh(&self, session: &activity::SessionActivity, now: Instant) {
+        let [output, input] = session.traffic_labels();
+        self.menu.set_text(session.title());
+        self.output.set_text(output);
+        self.input.set_text(input);
+        self.activity.set_text(session.activity_label(now));
+    }
+}
+
+/// Side of the join QR code popover, in points
+const QR_POPOVER_SIZE: f64 = 280.0;
+
+/// Borderless window under the tray icon showing the join URL as a QR
+/// code. It closes when it loses focus or on Escape.
+struct QrPopover {
+    window: Rc<Window>,
+    surface: softbuffer::Surface<Rc<Window>, Rc<Window>>,
+    url: String,
+    qr: QrImage,
+}
+
+impl QrPopover {
+    fn open(
+        event_loop: &ActiveEventLoop,
+        url: String,
+        anchor: Option<tray_icon::Rect>,
+    ) -> Result<Self, String> {
+        let qr = QrImage::new(&url)?;
+        let attributes = Window::default_attributes()
+            .with_title("Scan to Connect")
+            .with_inner_size(LogicalSize::new(QR_POPOVER_SIZE, QR_POPOVER_SIZE))
+            .with_decorations(false)
+            .with_resizable(false)
+            .with_window_level(WindowLevel::AlwaysOnTop)
+            .with_visible(false);
+        let window = Rc::new(event_loop.create_window(attributes).map_err(|e| e.to_string())?);
+        let context = softbuffer::Context::new(window.clone()).map_err(|e| e.to_string())?;
+        let surface =
+            softbuffer::Surface::new(&context, window.clone()).map_err(|e| e.to_string())?;
+
+        // Center under the tray icon, like a popover
+        if let Some(rect) = anchor {
+            let width = window.outer_size().width as f64;
+            window.set_outer_position(PhysicalPosition::new(
+                rect.position.x + rect.size.width as f64 / 2.0 - width / 2.0,
+                rect.position.y + rect.size.height as f64,
+            ));
+        }
+        window.set_visible(true);
+        window.focus_window();
+        Ok(Self { window, surface, url, qr })
+    }
+
+    /// Show a new join URL (the code was regenerated or the tunnel came up).
+    fn set_url(&mut self, url: String) {
+        if url == self.url {
+            return;
+        }
+        match QrImage::new(&url) {
+            Ok(qr) => {
+                self.qr = qr;
+                self.url = url;
+                self.window.request_redraw();
+            }
+            Err(e) => error!("Failed to render join QR code: {}", e),
+        }
+    }
+
+    fn draw(&mut self) {
+        let size = self.window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            return;
+        };
+        if let Err(e) = self.surface.resize(width, height) {
+            error!("Failed to resize QR code popover: {}", e);
+            return;
+        }
+        let pixels = self.qr.render(size.width, size.height);
+        match self.surface.buffer_mut() {
+            Ok(mut buffer) => {
+                buffer.copy_from_slice(&pixels);
+                if let Err(e) = buffer.present() {
+                    error!("Failed to draw QR code popover: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to draw QR code popover: {}", e),
+        }
+    }
 }
 
 /// Main application state
@@ -45,6 +248,8 @@ struct App {
     tray_icon: Option<TrayIcon>,
     app_state: Option<AppState>,
     login_item: Option<CheckMenuItem>,
+    shell_integration_item: Option<CheckMenuItem>,
+    do_not_disturb_item: Option<CheckMenuItem>,
     bg_tx: Option<mpsc::Sender<BackgroundCommand>>,
     ui_rx: Option<mpsc::Receiver<UiEvent>>,
     bg_handle: Option<thread::JoinHandle<()>>,
@@ -52,6 +257,78 @@ struct App {
     pty_cmd_tx: Option<tokio::sync::mpsc::UnboundedSender<PtyCommand>>,
     cloudflared_pid: Arc<AtomicU32>,
     relay_server_pid: Arc<AtomicU32>,
+    config: Config,
+    ui_tx: Option<mpsc::Sender<UiEvent>>,
+    preferences_open: Arc<AtomicBool>,
+    recent: RecentSessions,
+    recent_menu: Option<Submenu>,
+    recent_items: Vec<MenuItem>,
+    clipboard: ClipboardBridge,
+    alerts: AlertLog,
+    alerts_menu: Option<Submenu>,
+    alert_items: Vec<MenuItem>,
+    /// Browsers waiting for approval, oldest first
+    approvals: Vec<PendingApproval>,
+    /// "Browser Requests" submenu, present when approval is required
+    approvals_menu: Option<Submenu>,
+    approval_menus: Vec<Submenu>,
+    /// Browsers connected to the session, oldest first
+    viewers: Vec<Viewer>,
+    /// "Watching" submenu listing them
+    viewers_menu: Option<Submenu>,
+    viewer_items: Vec<MenuItem>,
+    /// Large browser input waiting for confirmation, oldest first
+    held_pastes: Vec<HeldPaste>,
+    /// "Held Pastes" submenu, present when the paste guard is on
+    pastes_menu: Option<Submenu>,
+    paste_menus: Vec<Submenu>,
+    /// pty-proxies running without a connection, as last scanned
+    orphans: Vec<OrphanProxy>,
+    /// Proxies the user chose to wait for; not listed while they stay orphaned
+    waiting_orphans: HashSet<u32>,
+    /// "Disconnected Sessions" submenu
+    orphans_menu: Option<Submenu>,
+    orphan_menus: Vec<Submenu>,
+    activity: ActivityTracker,
+    /// One submenu per project in the Sessions submenu
+    project_menus: Vec<Submenu>,
+    /// Session submenus in the project submenus
+    session_menus: Vec<SessionMenu>,
+    /// Shown instead when "Show Only Active" leaves nothing to list
+    sessions_placeholder: Option<MenuItem>,
+    /// Filter and order of the Sessions submenu, with its toggles
+    session_view: SessionView,
+    only_active_item: Option<CheckMenuItem>,
+    by_activity_item: Option<CheckMenuItem>,
+    /// Group titles and session IDs as last listed, to notice when the
+    /// listing must be rebuilt
+    session_listing: Vec<String>,
+    /// Byte counters of connected sessions, sampled for the throughput display
+    session_stats: HashMap<String, Arc<SessionStats>>,
+    /// Latest output of connected sessions, for transcript exports
+    transcripts: HashMap<String, Arc<Transcript>>,
+    traffic_sampled: Instant,
+    /// Projects whose sessions are hidden from browsers
+    paused_projects: HashSet<String>,
+    /// Normal (template) and tinted tray icons
+    tray_icons: Option<(Icon, Icon)>,
+    icon_active: bool,
+    /// Device key when end-to-end encryption is on
+    device_key: Option<DeviceKey>,
+    /// Open join QR code popover
+    qr_popover: Option<QrPopover>,
+    /// Persisted session IDs and per-session settings
+    registry: SharedRegistry,
+    /// Registered global shortcuts
+    hotkeys: Option<Hotkeys>,
+    /// URL of the relay in use
+    relay_url: String,
+    /// "Relay" submenu entries, one per configured relay
+    relay_items: Vec<CheckMenuItem>,
+    /// Relay switched to from the menu, until its session code arrives
+    switching_relay: Option<String>,
+    /// Usage counters, when telemetry is on
+    usage: Option<Usage>,
 }
 
 impl App {
@@ -60,6 +337,8 @@ impl App {
             tray_icon: None,
             app_state: None,
             login_item: None,
+            shell_integration_item: None,
+            do_not_disturb_item: None,
             bg_tx: None,
             ui_rx: None,
             bg_handle: None,
@@ -67,10 +346,938 @@ impl App {
             pty_cmd_tx: None,
             cloudflared_pid: Arc::new(AtomicU32::new(0)),
             relay_server_pid: Arc::new(AtomicU32::new(0)),
+            config: Config::default(),
+            ui_tx: None,
+            preferences_open: Arc::new(AtomicBool::new(false)),
+            recent: RecentSessions::default(),
+            recent_menu: None,
+            recent_items: Vec::new(),
+            clipboard: ClipboardBridge::new(),
+            alerts: AlertLog::new(),
+            alerts_menu: None,
+            alert_items: Vec::new(),
+            approvals: Vec::new(),
+            approvals_menu: None,
+            approval_menus: Vec::new(),
+            viewers: Vec::new(),
+            viewers_menu: None,
+            viewer_items: Vec::new(),
+            held_pastes: Vec::new(),
+            pastes_menu: None,
+            paste_menus: Vec::new(),
+            orphans: Vec::new(),
+            waiting_orphans: HashSet::new(),
+            orphans_menu: None,
+            orphan_menus: Vec::new(),
+            activity: ActivityTracker::new(),
+            project_menus: Vec::new(),
+            session_menus: Vec::new(),
+            sessions_placeholder: None,
+            session_view: SessionView::default(),
+            only_active_item: None,
+            by_activity_item: None,
+            session_listing: Vec::new(),
+            session_stats: HashMap::new(),
+            transcripts: HashMap::new(),
+            traffic_sampled: Instant::now(),
+            paused_projects: HashSet::new(),
+            tray_icons: None,
+            icon_active: false,
+            device_key: None,
+            qr_popover: None,
+            registry: Arc::new(Mutex::new(SessionRegistry::default())),
+            hotkeys: None,
+            relay_url: String::new(),
+            relay_items: Vec::new(),
+            switching_relay: None,
+            usage: None,
+        }
+    }
+
+    /// Replace the "Recent" submenu entries with the current history.
+    fn rebuild_recent_menu(&mut self) {
+        let Some(submenu) = &self.recent_menu else {
+            return;
+        };
+        for item in self.recent_items.drain(..) {
+            let _ = submenu.remove(&item);
+        }
+        if self.recent.entries.is_empty() {
+            let item = MenuItem::new("No recent sessions", false, None);
+            let _ = submenu.append(&item);
+            self.recent_items.push(item);
+            return;
+        }
+        for (i, entry) in self.recent.entries.iter().enumerate() {
+            let item = MenuItem::with_id(
+                format!("{}{}", ID_RECENT_PREFIX, i),
+                entry.label(),
+                true,
+                None,
+            );
+            let _ = submenu.append(&item);
+            self.recent_items.push(item);
+        }
+    }
+
+    /// Record a background failure, notify if it is critical, and refresh
+    /// the "Errors" submenu.
+    fn raise_alert(&mut self, alert: Alert) {
+        let message = alert.message.clone();
+        if let Some(usage) = &mut self.usage {
+            usage.record_error(&message);
+        }
+        if self.alerts.push(alert, SystemTime::now()) && self.config.notifications {
+            notify::notify("Terminal Remote", &message);
+        }
+        self.rebuild_alerts_menu();
+    }
+
+    /// Send the usage counters once a day when telemetry is on.
+    fn report_usage(&mut self) {
+        let now = SystemTime::now();
+        let (Some(usage), Some(endpoint)) = (&mut self.usage, self.config.telemetry.endpoint()) else {
+            return;
+        };
+        if !usage.due(now) {
+            return;
+        }
+        telemetry::send_in_background(endpoint.to_string(), usage.take_report(now));
+        if let Err(e) = usage.save() {
+            warn!("Cannot save usage counters: {}", e);
+        }
+    }
+
+    /// Replace the "Errors" submenu entries with the current alert log.
+    fn rebuild_alerts_menu(&mut self) {
+        let Some(submenu) = &self.alerts_menu else {
+            return;
+        };
+        for item in self.alert_items.drain(..) {
+            let _ = submenu.remove(&item);
+        }
+        if self.alerts.is_empty() {
+            submenu.set_text("Errors");
+            let item = MenuItem::new("No errors", false, None);
+            let _ = submenu.append(&item);
+            self.alert_items.push(item);
+            return;
+        }
+        submenu.set_text(format!("Errors ({})", self.alerts.len()));
+        for entry in self.alerts.entries() {
+            let item = MenuItem::new(entry.label(), false, None);
+            let _ = submenu.append(&item);
+            self.alert_items.push(item);
+        }
+        let clear = MenuItem::with_id(ID_CLEAR_ERRORS, "Clear Errors", true, None);
+        let _ = submenu.append(&clear);
+        self.alert_items.push(clear);
+    }
+
+    /// Replace the "Browser Requests" submenu entries with the browsers
+    /// waiting for approval.
+    fn rebuild_approvals_menu(&mut self) {
+        let Some(submenu) = &self.approvals_menu else {
+            return;
+        };
+        for menu in self.approval_menus.drain(..) {
+            let _ = submenu.remove(&menu);
+        }
+        submenu.set_enabled(!self.approvals.is_empty());
+        if self.approvals.is_empty() {
+            submenu.set_text("Browser Requests");
+            return;
+        }
+        submenu.set_text(format!("Browser Requests ({})", self.approvals.len()));
+        for pending in &self.approvals {
+            let menu = Submenu::new(format!("{} ({})", pending.label, pending.browser_id), true);
+            let id = &pending.browser_id;
+            let _ = menu.append_items(&[
+                &MenuItem::with_id(format!("{}{}", ID_APPROVE_PREFIX, id), "Allow", true, None),
+                &MenuItem::with_id(
+                    format!("{}{}", ID_ALWAYS_ALLOW_PREFIX, id),
+                    "Always Allow This Browser",
+                    pending.browser_key.is_some(),
+                    None,
+                ),
+                &MenuItem::with_id(format!("{}{}", ID_DENY_PREFIX, id), "Deny", true, None),
+            ]);
+            let _ = submenu.append(&menu);
+            self.approval_menus.push(menu);
+        }
+    }
+
+    /// Replace the "Watching" submenu entries with the browsers connected
+    /// now: name or ID, role, how long ago they joined and from where.
+    fn rebuild_viewers_menu(&mut self) {
+        let Some(submenu) = &self.viewers_menu else {
+            return;
+        };
+        for item in self.viewer_items.drain(..) {
+            let _ = submenu.remove(&item);
+        }
+        submenu.set_enabled(!self.viewers.is_empty());
+        if self.viewers.is_empty() {
+            submenu.set_text("Watching");
+            return;
+        }
+        submenu.set_text(format!("Watching ({})", self.viewers.len()));
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        for viewer in &self.viewers {
+            let name = viewer
+                .nickname
+                .clone()
+                .unwrap_or_else(|| format!("Browser {}", viewer.browser_id));
+            let role = match viewer.role {
+                Role::Controller => "can type",
+                Role::Viewer => "view only",
+            };
+            let mut label = format!(
+                "{} ({}, {})",
+                name,
+                role,
+                history::format_duration(now.saturating_sub(viewer.joined_at))
+            );
+            if let Some(ip) = &viewer.ip {
+                label.push_str(&format!(" from {}", ip));
+            }
+            let item = MenuItem::new(label, false, None);
+            let _ = submenu.append(&item);
+            self.viewer_items.push(item);
+        }
+    }
+
+    /// Let a waiting browser in or turn it away. With `always` its key is
+    /// remembered and it is let in without asking from then on.
+    fn answer_approval(&mut self, browser_id: &str, approved: bool, always: bool) {
+        let Some(index) = self.approvals.iter().position(|p| p.browser_id == browser_id) else {
+            return;
+        };
+        let pending = self.approvals.remove(index);
+        if always {
+            if let Some(key) = &pending.browser_key {
+                if let Err(e) = approval::always_allow(key) {
+                    warn!("Cannot remember browser {}: {}", pending.label, e);
+                }
+            }
+        }
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::AnswerApproval {
+                browser_id: pending.browser_id,
+                approved,
+            });
+        }
+        self.rebuild_approvals_menu();
+    }
+
+    /// Replace the "Held Pastes" submenu entries with the pastes waiting for
+    /// confirmation.
+    fn rebuild_pastes_menu(&mut self) {
+        let Some(submenu) = &self.pastes_menu else {
+            return;
+        };
+        for menu in self.paste_menus.drain(..) {
+            let _ = submenu.remove(&menu);
+        }
+        submenu.set_enabled(!self.held_pastes.is_empty());
+        if self.held_pastes.is_empty() {
+            submenu.set_text("Held Pastes");
+            return;
+        }
+        submenu.set_text(format!("Held Pastes ({})", self.held_pastes.len()));
+        for held in &self.held_pastes {
+            let menu = Submenu::new(format!("{}: {}", held.session_name, held.summary), true);
+            let _ = menu.append_items(&[
+                &MenuItem::new(held.preview.clone(), false, None),
+                &PredefinedMenuItem::separator(),
+                &MenuItem::with_id(format!("{}{}", ID_PASTE_SEND_PREFIX, held.id), "Paste", true, None),
+                &MenuItem::with_id(format!("{}{}", ID_PASTE_DISCARD_PREFIX, held.id), "Discard", true, None),
+            ]);
+            let _ = submenu.append(&menu);
+            self.paste_menus.push(menu);
+        }
+    }
+
+    /// Write a held paste to its session or discard it.
+    fn release_paste(&mut self, id: &str, send: bool) {
+        let Ok(id) = id.parse::<u64>() else {
+            return;
+        };
+        self.held_pastes.retain(|held| held.id != id);
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::ReleasePaste { id, send });
+        }
+        self.rebuild_pastes_menu();
+    }
+
+    /// Replace the "Disconnected Sessions" submenu entries with the proxies
+    /// found running without a connection.
+    fn rebuild_orphans_menu(&mut self) {
+        let Some(submenu) = &self.orphans_menu else {
+            return;
+        };
+        for menu in self.orphan_menus.drain(..) {
+            let _ = submenu.remove(&menu);
+        }
+        let listed: Vec<&OrphanProxy> = self
+            .orphans
+            .iter()
+            .filter(|o| !self.waiting_orphans.contains(&o.pid))
+            .collect();
+        submenu.set_enabled(!listed.is_empty());
+        if listed.is_empty() {
+            submenu.set_text("Disconnected Sessions");
+            return;
+        }
+        submenu.set_text(format!("Disconnected Sessions ({})", listed.len()));
+        for orphan in listed {
+            let pid = orphan.pid;
+            let menu = Submenu::new(orphan.label(), true);
+            let _ = menu.append_items(&[
+                &MenuItem::with_id(format!("{}{}", ID_ORPHAN_RECONNECT_PREFIX, pid), "Reconnect Now", true, None),
+                &MenuItem::with_id(format!("{}{}", ID_ORPHAN_WAIT_PREFIX, pid), "Wait", true, None),
+                &PredefinedMenuItem::separator(),
+                &MenuItem::with_id(format!("{}{}", ID_ORPHAN_KILL_PREFIX, pid), "Force Quit (Ends the Shell)", true, None),
+            ]);
+            let _ = submenu.append(&menu);
+            self.orphan_menus.push(menu);
+        }
+    }
+
+    /// Take the newest scan for disconnected proxies, notifying about ones
+    /// not listed before.
+    fn update_orphans(&mut self, orphans: Vec<OrphanProxy>) {
+        self.waiting_orphans
+            .retain(|pid| orphans.iter().any(|o| o.pid == *pid));
+        let new = orphans
+            .iter()
+            .filter(|o| !self.orphans.iter().any(|known| known.pid == o.pid))
+            .count();
+        if new > 0 {
+            warn!("{} pty-proxy process(es) running but not connected", new);
+            if self.config.notifications {
+                notify::notify(
+                    "Terminal Remote",
+                    &format!(
+                        "{} running session(s) did not reconnect. See Disconnected Sessions in the menu.",
+                        new
+                    ),
+                );
+            }
+        }
+        self.orphans = orphans;
+        self.rebuild_orphans_menu();
+    }
+
+    /// Act on a disconnected proxy from its submenu: nudge it to reconnect,
+    /// wait for it, or kill it.
+    fn handle_orphan(&mut self, pid: &str, action: &str) {
+        let Ok(pid) = pid.parse::<u32>() else {
+            return;
+        };
+        if !self.orphans.iter().any(|o| o.pid == pid) {
+            return;
+        }
+        let result = match action {
+            // Listed again by the next scan if it still has not connected
+            ID_ORPHAN_RECONNECT_PREFIX => orphans::nudge(pid),
+            ID_ORPHAN_KILL_PREFIX => {
+                info!("Force quitting disconnected pty-proxy {}", pid);
+                orphans::force_quit(pid).map(|()| self.orphans.retain(|o| o.pid != pid))
+            }
+            _ => {
+                self.waiting_orphans.insert(pid);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.raise_alert(Alert::warning(format!("Cannot signal pty-proxy {}: {}", pid, e)));
+        }
+        self.rebuild_orphans_menu();
+    }
+
+    /// Open an ssh session to a host from the "SSH Hosts" submenu.
+    fn connect_ssh(&mut self, index: &str) {
+        let Some(host) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.config.ssh.hosts.get(i).cloned())
+        else {
+            return;
+        };
+        info!("Opening ssh session to {}", host.destination);
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::ConnectSsh(host));
+        }
+    }
+
+    /// Leave the current relay for one from the "Relay" submenu; it hands out
+    /// a new session code. The choice is saved to the config file.
+    fn switch_relay(&mut self, index: &str) {
+        let Some(relay) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.config.relays.get(i).cloned())
+        else {
+            return;
+        };
+        if relay.url != self.relay_url {
+            info!("Switching to relay {} ({})", relay.label(), relay.url);
+            self.relay_url = relay.url.clone();
+            self.switching_relay = Some(relay.label().to_string());
+            self.config.relay_url = Some(relay.url.clone());
+            if let Err(e) = self.config.save() {
+                warn!("Cannot save the relay choice: {}", e);
+            }
+            if let Some(bg_tx) = &self.bg_tx {
+                let _ = bg_tx.send(BackgroundCommand::SwitchRelay(relay.url));
+            }
+        }
+        // Clicking toggles the item; keep exactly the active relay checked
+        for (item, relay) in self.relay_items.iter().zip(&self.config.relays) {
+            item.set_checked(relay.url == self.relay_url);
+        }
+    }
+
+    /// Group titles and session IDs the Sessions submenu lists right now.
+    fn session_listing(&self, now: Instant) -> Vec<String> {
+        let mut listing = Vec::new();
+        for group in self.activity.view(self.session_view, now) {
+            let paused = group.project.is_some_and(|p| self.paused_projects.contains(p));
+            listing.push(group.title(paused));
+            listing.extend(group.sessions.iter().map(|s| s.session_id.clone()));
+        }
+        listing
+    }
+
+    /// Replace the Sessions submenu entries with the tracked sessions, one
+    /// submenu per project with a toggle to share it with browsers.
+    fn rebuild_sessions_menu(&mut self) {
+        let Some(app_state) = &self.app_state else {
+            return;
+        };
+        for menu in self.project_menus.drain(..) {
+            let _ = app_state.count_item.remove(&menu);
+        }
+        if let Some(item) = self.sessions_placeholder.take() {
+            let _ = app_state.count_item.remove(&item);
+        }
+        self.session_menus.clear();
+        let now = Instant::now();
+        self.session_listing = self.session_listing(now);
+        let groups = self.activity.view(self.session_view, now);
+        if groups.is_empty() && !self.activity.sessions().is_empty() {
+            let item = MenuItem::new("No active sessions", false, None);
+            let _ = app_state.count_item.append(&item);
+            self.sessions_placeholder = Some(item);
+        }
+        for group in groups {
+            let project = group.project;
+            let paused = project.is_some_and(|p| self.paused_projects.contains(p));
+            let menu = Submenu::new(group.title(paused), true);
+            for session in group.sessions {
+                let read_only = self.registry.lock().unwrap().read_only(&session.session_id);
+                let entry = SessionMenu::new(session, read_only, now);
+                let _ = menu.append(&entry.menu);
+                self.session_menus.push(entry);
+            }
+            if let Some(project) = project {
+                let share = CheckMenuItem::with_id(
+                    format!("{}{}", ID_PROJECT_SHARE_PREFIX, project),
+                    "Share with Browsers",
+                    true,
+                    !paused,
+                    None,
+                );
+                let _ = menu.append_items(&[&PredefinedMenuItem::separator(), &share]);
+            }
+            let _ = app_state.count_item.append(&menu);
+            self.project_menus.push(menu);
+        }
+    }
+
+    /// Ask where to save a session's buffered output and write it there,
+    /// as plain text or `raw` with escape sequences.
+    fn export_transcript(&self, session_id: &str, raw: bool) {
+        let Some(transcript) = self.transcripts.get(session_id) else {
+            return;
+        };
+        let output = transcript.snapshot();
+        let name = self
+            .activity
+            .session(session_id)
+            .map(|s| s.name.clone())
+            .unwrap_or_else(|| session_id.to_string());
+        let dir = self.config.recording_dir.clone().or_else(|| {
+            let dir = paths::default_recording_dir()?;
+            std::fs::create_dir_all(&dir).ok()?;
+            Some(dir)
+        });
+        let ui_tx = self.ui_tx.clone();
+        thread::spawn(move || {
+            let default_name = transcript::file_name(&name, raw, SystemTime::now());
+            let Some(path) = transcript::choose_path(&name, &default_name, dir.as_deref()) else {
+                return;
+            };
+            match transcript::export(&output, raw, &path) {
+                Ok(()) => info!("Exported transcript of {} to {}", name, path.display()),
+                Err(e) => {
+                    let message = format!("Cannot export transcript to {}: {}", path.display(), e);
+                    if let Some(ui_tx) = ui_tx {
+                        let _ = ui_tx.send(UiEvent::Alert(Alert::warning(message)));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Ask where to save a diagnostics bundle, write it there and show it
+    /// in the Finder.
+    fn save_diagnostics(&self) {
+        let config = self.config.clone();
+        let ui_tx = self.ui_tx.clone();
+        thread::spawn(move || {
+            let default_name = diagnostics::file_name(SystemTime::now());
+            let dir = diagnostics::default_dir();
+            let Some(path) = transcript::choose_save_path(
+                "Save diagnostics for a bug report to:",
+                &default_name,
+                dir.as_deref(),
+            ) else {
+                return;
+            };
+            let files = diagnostics::collect(&config);
+            match diagnostics::save(&files, &path) {
+                Ok(()) => {
+                    info!("Saved diagnostics to {}", path.display());
+                    let _ = Command::new("open").arg("-R").arg(&path).spawn();
+                }
+                Err(e) => {
+                    let message = format!("Cannot save diagnostics to {}: {}", path.display(), e);
+                    if let Some(ui_tx) = ui_tx {
+                        let _ = ui_tx.send(UiEvent::Alert(Alert::warning(message)));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Drop browser input to a session, or accept it again. The setting is
+    /// remembered if the session's proxy reconnects.
+    fn toggle_read_only(&mut self, session_id: &str) {
+        let read_only = {
+            let mut registry = self.registry.lock().unwrap();
+            let read_only = !registry.read_only(session_id);
+            registry.set_read_only(session_id, read_only);
+            read_only
+        };
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::SetSessionReadOnly {
+                session_id: session_id.to_string(),
+                read_only,
+            });
+        }
+    }
+
+    /// Pause or share every session of a project with browsers.
+    fn toggle_project_shared(&mut self, project: &str) {
+        let paused = !self.paused_projects.remove(project);
+        if paused {
+            self.paused_projects.insert(project.to_string());
+        }
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::SetProjectPaused {
+                project: project.to_string(),
+                paused,
+            });
+        }
+        self.rebuild_sessions_menu();
+    }
+
+    /// Flip "Show Only Active" (`only_active`) or "Sort by Recent Activity".
+    fn toggle_session_view(&mut self, only_active: bool) {
+        let (enabled, item) = if only_active {
+            self.session_view.only_active = !self.session_view.only_active;
+            (self.session_view.only_active, &self.only_active_item)
+        } else {
+            self.session_view.by_activity = !self.session_view.by_activity;
+            (self.session_view.by_activity, &self.by_activity_item)
+        };
+        if let Some(item) = item {
+            item.set_checked(enabled);
+        }
+        self.rebuild_sessions_menu();
+    }
+
+    /// Refresh the session submenus (once a second, or immediately after
+    /// new input) and tint the tray icon while input is arriving. Byte
+    /// counters are sampled once a second for the throughput rates.
+    fn update_activity_display(&mut self, input_seen: bool) {
+        let now = Instant::now();
+        let sample = now.duration_since(self.traffic_sampled) >= Duration::from_secs(1);
+        if sample {
+            for (session_id, stats) in &self.session_stats {
+                self.activity.record_traffic(
+                    session_id,
+                    now,
+                    stats.bytes_out.load(Ordering::Relaxed),
+                    stats.bytes_in.load(Ordering::Relaxed),
+                );
+            }
+            self.traffic_sampled = now;
+        }
+        if sample && self.session_listing(now) != self.session_listing {
+            // Sessions became (in)active or changed order
+            self.rebuild_sessions_menu();
+        } else if input_seen || sample {
+            for entry in &self.session_menus {
+                if let Some(session) = self.activity.session(&entry.session_id) {
+                    entry.refresh(session, now);
+                }
+            }
+        }
+
+        let active = self.activity.is_active(now);
+        if active == self.icon_active {
+            return;
+        }
+        self.icon_active = active;
+        if let (Some(tray_icon), Some((normal, tinted))) = (&self.tray_icon, &self.tray_icons) {
+            let icon = if active { tinted } else { normal };
+            if let Err(e) = tray_icon.set_icon(Some(icon.clone())) {
+                warn!("Failed to update tray icon: {}", e);
+            }
+            tray_icon.set_icon_as_template(!active);
+        }
+    }
+
+    /// Address of the web UI: the active relay's `web_url` from `[[relays]]`,
+    /// else `web_url` from the config, else the tunnel URL, or the relay's
+    /// own address until the tunnel is up.
+    fn web_base(&self) -> String {
+        self.config
+            .relays
+            .iter()
+            .find(|r| r.url == self.relay_url)
+            .and_then(|r| r.web_url.clone())
+            .or_else(|| self.config.web_url.clone())
+            .or_else(|| self.app_state.as_ref()?.tunnel_url.clone())
+            .unwrap_or_else(|| {
+                let http = self
+                    .relay_url
+                    .replacen("wss://", "https://", 1)
+                    .replacen("ws://", "http://", 1);
+                http.trim_end_matches("/ws").to_string()
+            })
+    }
+
+    /// Link that opens the web UI paired with this Mac.
+    fn pairing_link(&self) -> Option<String> {
+        let device_key = self.device_key.as_ref()?;
+        Some(e2e::pairing_link(&self.web_base(), &device_key.pairing_string()))
+    }
+
+    /// Link that opens the web UI and connects with the current session
+    /// code (and pairs, with end-to-end encryption on).
+    fn join_url(&self) -> Option<String> {
+        let code = self.app_state.as_ref()?.session_code.as_ref()?;
+        let pairing = self.device_key.as_ref().map(|key| key.pairing_string());
+        Some(join::join_url(&self.web_base(), code, pairing.as_deref()))
+    }
+
+    /// Open the join QR code popover, or close it if it is already open.
+    fn toggle_join_qr(&mut self, event_loop: &ActiveEventLoop) {
+        if self.qr_popover.take().is_some() {
+            return;
+        }
+        let Some(url) = self.join_url() else {
+            info!("No session code yet, not showing QR code");
+            return;
+        };
+        let anchor = self.tray_icon.as_ref().and_then(|tray_icon| tray_icon.rect());
+        match QrPopover::open(event_loop, url, anchor) {
+            Ok(popover) => self.qr_popover = Some(popover),
+            Err(e) => error!("Failed to open QR code popover: {}", e),
+        }
+    }
+
+    /// Render the pairing link as a QR code and open it in Preview.
+    fn show_pairing_qr(&self) {
+        let Some(link) = self.pairing_link() else {
+            return;
+        };
+        let path = paths::runtime_dir().join("pairing.png");
+        if let Err(e) = e2e::write_qr_png(&link, &path) {
+            error!("Failed to render pairing QR code: {}", e);
+            return;
+        }
+        if let Err(e) = Command::new("open").arg(&path).spawn() {
+            error!("Failed to open pairing QR code: {}", e);
+        }
+    }
+
+    /// Copy the session code, returning it if there is one.
+    fn copy_session_code(&self) -> Option<String> {
+        let code = self.app_state.as_ref()?.session_code.clone()?;
+        if let Ok(mut clipboard) = arboard::Clipboard::new() {
+            if clipboard.set_text(code.clone()).is_ok() {
+                info!("Session code copied to clipboard: {}", code);
+            }
+        }
+        Some(code)
+    }
+
+    /// Copy a join URL that works once, with a token just minted by the relay.
+    fn copy_one_time_link(&self, token: &str, expires_in_secs: u64, role: Role) {
+        let Some(code) = self.app_state.as_ref().and_then(|s| s.session_code.as_ref()) else {
+            return;
+        };
+        let pairing = self.device_key.as_ref().map(|key| key.pairing_string());
+        let url = join::one_time_url(&self.web_base(), code, token, role, pairing.as_deref());
+        if let Ok(mut clipboard) = arboard::Clipboard::new() {
+            if clipboard.set_text(url).is_ok() {
+                info!("One-time join link copied to clipboard, valid for {}s, role {:?}", expires_in_secs, role);
+                if self.config.notifications {
+                    let kind = match role {
+                        Role::Controller => "One-time link",
+                        Role::Viewer => "View-only link",
+                    };
+                    notify::notify(
+                        "Terminal Remote",
+                        &format!("{} copied. It works once, for {} minutes.", kind, expires_in_secs / 60),
+                    );
+                }
+            }
+        }
+    }
+
+    fn copy_join_url(&self) {
+        if let Some(url) = self.join_url() {
+            if let Ok(mut clipboard) = arboard::Clipboard::new() {
+                if clipboard.set_text(url.clone()).is_ok() {
+                    info!("Join URL copied to clipboard: {}", url);
+                }
+            }
+        }
+    }
+
+    /// Run the menu action bound to a pressed global shortcut.
+    fn handle_hotkey(&mut self, event_loop: &ActiveEventLoop, id: u32) {
+        let Some(action) = self.hotkeys.as_ref().and_then(|hotkeys| hotkeys.action(id)) else {
+            return;
+        };
+        debug!("Global shortcut: {}", action.label());
+        match action {
+            HotkeyAction::CopyJoinUrl => self.copy_join_url(),
+            HotkeyAction::CopyCode => {
+                self.copy_session_code();
+            }
+            HotkeyAction::ShowJoinQr => self.toggle_join_qr(event_loop),
+            HotkeyAction::ToggleDoNotDisturb => self.toggle_do_not_disturb(),
+        }
+    }
+
+    fn toggle_do_not_disturb(&mut self) {
+        if let Some(app_state) = &self.app_state {
+            self.set_do_not_disturb(!app_state.sharing_paused);
+        }
+    }
+
+    /// Do Not Disturb: stop mirroring every session, refuse new browsers
+    /// and hold back notifications, or undo all of that.
+    fn set_do_not_disturb(&mut self, paused: bool) {
+        let Some(app_state) = &mut self.app_state else {
+            return;
+        };
+        if app_state.sharing_paused == paused {
+            return;
+        }
+        info!("Do Not Disturb {}", if paused { "on" } else { "off" });
+        app_state.sharing_paused = paused;
+        app_state.update_status_display();
+        notify::set_muted(paused);
+        if let Some(item) = &self.do_not_disturb_item {
+            item.set_checked(paused);
+        }
+        if let Some(bg_tx) = &self.bg_tx {
+            let _ = bg_tx.send(BackgroundCommand::SetSharingPaused(paused));
+        }
+    }
+
+    /// Carry out an `ignis://` link and answer its x-callback-url, if any.
+    fn open_url(&mut self, url: &str) {
+        info!("Opening {}", url);
+        let callbacks = url_scheme::Callbacks::of(url);
+        let result = match url_scheme::parse(url) {
+            Ok(UrlAction::ListSessions) => {
+                self.list_sessions(callbacks);
+                return;
+            }
+            Ok(action) => self.run_url_action(action),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(result) => callbacks.succeed(result.as_deref()),
+            Err(e) => {
+                warn!("{}", e);
+                callbacks.fail(&e);
+            }
         }
     }
 
-    fn handle_menu_event(&mut self, event: muda::MenuEvent) {
+    /// Run a link's action, returning its result for `x-success`.
+    fn run_url_action(&mut self, action: UrlAction) -> Result<Option<String>, String> {
+        match action {
+            UrlAction::FocusSession(session_id) => {
+                let tty = self
+                    .registry
+                    .lock()
+                    .unwrap()
+                    .entries()
+                    .iter()
+                    .find(|e| e.session_id == session_id)
+                    .map(|e| e.tty.clone())
+                    .ok_or_else(|| format!("No session {}", session_id))?;
+                thread::spawn(move || url_scheme::focus_terminal_tab(&tty));
+                Ok(None)
+            }
+            UrlAction::CloseSession(session_id) => {
+                if !self.session_stats.contains_key(&session_id) {
+                    return Err(format!("No session {}", session_id));
+                }
+                let pty_cmd_tx = self.pty_cmd_tx.as_ref().ok_or("PTY manager is not running")?;
+                pty_cmd_tx
+                    .send(PtyCommand::KillSession { session_id })
+                    .map_err(|_| "PTY manager is not running".to_string())?;
+                Ok(None)
+            }
+            UrlAction::Pause | UrlAction::Resume | UrlAction::ToggleSharing => {
+                let paused = self.app_state.as_ref().is_some_and(|s| s.sharing_paused);
+                self.set_do_not_disturb(match action {
+                    UrlAction::Pause => true,
+                    UrlAction::Resume => false,
+                    _ => !paused,
+                });
+                let paused = self.app_state.as_ref().is_some_and(|s| s.sharing_paused);
+                Ok(Some(if paused { "paused" } else { "sharing" }.to_string()))
+            }
+            UrlAction::CopyCode => match self.copy_session_code() {
+                Some(code) => Ok(Some(code)),
+                None => Err("Not connected to the relay".to_string()),
+            },
+            UrlAction::ListSessions => unreachable!("answered asynchronously"),
+        }
+    }
+
+    /// Answer `list-sessions` with the same session entries as the control
+    /// socket's `status`, as JSON.
+    fn list_sessions(&self, callbacks: url_scheme::Callbacks) {
+        let Some(pty_cmd_tx) = self.pty_cmd_tx.clone() else {
+            callbacks.fail("PTY manager is not running");
+            return;
+        };
+        let browsers = self.app_state.as_ref().map_or(0, |s| s.browser_count);
+        thread::spawn(move || {
+            let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+            let sessions = pty_cmd_tx
+                .send(PtyCommand::ListSessions { reply: reply_tx })
+                .ok()
+                .and_then(|_| reply_rx.blocking_recv().ok());
+            let Some(sessions) = sessions else {
+                callbacks.fail("PTY manager did not answer");
+                return;
+            };
+            let sessions: Vec<SessionStatus> = sessions
+                .into_iter()
+                .map(|(id, info)| SessionStatus {
+                    id,
+                    name: info.name,
+                    shell: info.shell,
+                    pid: info.pid,
+                    tty: info.tty,
+                    browsers,
+                    bytes_out: info.stats.bytes_out.load(Ordering::Relaxed),
+                    bytes_in: info.stats.bytes_in.load(Ordering::Relaxed),
+                })
+                .collect();
+            info!("Listing {} session(s) for an x-callback-url", sessions.len());
+            match serde_json::to_string(&sessions) {
+                Ok(json) => callbacks.succeed(Some(&json)),
+                Err(e) => callbacks.fail(&e.to_string()),
+            }
+        });
+    }
+
+    /// Install the shell integration, or remove it if it is installed, and
+    /// say what changed in a notification.
+    fn toggle_shell_integration(&mut self) {
+        let Some(home) = std::env::var_os("HOME").map(std::path::PathBuf::from) else {
+            return;
+        };
+        let installed = shell_integration::is_installed(&home);
+        let result = if installed {
+            shell_integration::uninstall(&home)
+        } else {
+            let login_shell = std::env::var("SHELL").ok();
+            shell_integration::install(&home, login_shell.as_deref().and_then(Shell::from_path))
+        };
+        match result {
+            Ok(changes) => {
+                for change in &changes {
+                    info!("Shell integration: {}", change);
+                }
+                notify::notify(
+                    "Terminal Remote",
+                    if installed {
+                        "Shell integration removed. It stays active in open terminals until they exit."
+                    } else {
+                        "Shell integration installed. New terminal windows can be shared."
+                    },
+                );
+            }
+            Err(e) => {
+                error!("Failed to update shell integration: {}", e);
+                self.raise_alert(Alert::warning(format!("Shell integration: {}", e)));
+            }
+        }
+        if let Some(item) = &self.shell_integration_item {
+            item.set_checked(shell_integration::is_installed(&home));
+        }
+    }
+
+    /// Open the preferences dialog on a helper thread (osascript blocks).
+    fn open_preferences(&self) {
+        if self.preferences_open.swap(true, Ordering::SeqCst) {
+            debug!("Preferences already open");
+            return;
+        }
+        let Some(ui_tx) = self.ui_tx.clone() else {
+            return;
+        };
+        let config = self.config.clone();
+        let preferences_open = self.preferences_open.clone();
+        thread::spawn(move || {
+            if let Some(edited) = preferences::run(&config) {
+                match edited.save() {
+                    Ok(()) => {
+                        if preferences::needs_restart(&config, &edited) {
+                            notify::notify(
+                                "Terminal Remote",
+                                "Preferences saved. Some changes take effect after restart.",
+                            );
+                        }
+                        let _ = ui_tx.send(UiEvent::ConfigChanged(Box::new(edited)));
+                    }
+                    Err(e) => error!("Failed to save preferences: {}", e),
+                }
+            }
+            preferences_open.store(false, Ordering::SeqCst);
+        });
+    }
+
+    fn handle_menu_event(&mut self, event_loop: &ActiveEventLoop, event: muda::MenuEvent) {
         debug!("Menu event: {:?}", event);
 
         match event.id().0.as_str() {
@@ -92,16 +1299,27 @@ impl App {
                 }
             }
             ID_COPY_CODE => {
-                if let Some(app_state) = &self.app_state {
-                    if let Some(code) = &app_state.session_code {
-                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                            if clipboard.set_text(code.clone()).is_ok() {
-                                info!("Session code copied to clipboard: {}", code);
-                            }
-                        }
-                    }
+                self.copy_session_code();
+            }
+            ID_COPY_JOIN_URL => {
+                self.copy_join_url();
+            }
+            ID_COPY_ONE_TIME_LINK => {
+                if let Some(bg_tx) = &self.bg_tx {
+                    let _ = bg_tx.send(BackgroundCommand::CreateJoinLink { role: Role::Controller });
+                }
+            }
+            ID_COPY_VIEW_ONLY_LINK => {
+                if let Some(bg_tx) = &self.bg_tx {
+                    let _ = bg_tx.send(BackgroundCommand::CreateJoinLink { role: Role::Viewer });
                 }
             }
+            ID_PREFERENCES => {
+                self.open_preferences();
+            }
+            ID_SAVE_DIAGNOSTICS => {
+                self.save_diagnostics();
+            }
             ID_LOGIN_ITEM => {
                 if let Some(login_item) = &self.login_item {
                     let current = login_item.is_checked();
@@ -125,8 +1343,15 @@ impl App {
                     }
                 }
             }
+            ID_SHELL_INTEGRATION => {
+                self.toggle_shell_integration();
+            }
+            ID_DO_NOT_DISTURB => {
+                self.toggle_do_not_disturb();
+            }
             ID_QUIT => {
                 info!("Quit requested, exiting");
+                applescript::shut_down();
                 let pid = self.cloudflared_pid.load(Ordering::Relaxed);
                 if pid != 0 {
                     info!("Killing cloudflared (pid {})", pid);
@@ -139,6 +1364,83 @@ impl App {
                 }
                 std::process::exit(0);
             }
+            ID_COPY_PAIRING_LINK => {
+                if let Some(link) = self.pairing_link() {
+                    if let Ok(mut clipboard) = arboard::Clipboard::new() {
+                        if clipboard.set_text(link).is_ok() {
+                            info!("Pairing link copied to clipboard");
+                        }
+                    }
+                }
+            }
+            ID_SHOW_PAIRING_QR => {
+                self.show_pairing_qr();
+            }
+            ID_SHOW_JOIN_QR => {
+                self.toggle_join_qr(event_loop);
+            }
+            ID_CLEAR_ERRORS => {
+                self.alerts.clear();
+                self.rebuild_alerts_menu();
+            }
+            id if id.starts_with(ID_RECENT_PREFIX) => {
+                let entry = id[ID_RECENT_PREFIX.len()..]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| self.recent.entries.get(i).cloned());
+                if let Some(entry) = entry {
+                    history::reopen(&entry);
+                }
+            }
+            id if id.starts_with(ID_APPROVE_PREFIX) => {
+                self.answer_approval(&id[ID_APPROVE_PREFIX.len()..], true, false);
+            }
+            id if id.starts_with(ID_ALWAYS_ALLOW_PREFIX) => {
+                self.answer_approval(&id[ID_ALWAYS_ALLOW_PREFIX.len()..], true, true);
+            }
+            id if id.starts_with(ID_DENY_PREFIX) => {
+                self.answer_approval(&id[ID_DENY_PREFIX.len()..], false, false);
+            }
+            id if id.starts_with(ID_ORPHAN_RECONNECT_PREFIX) => {
+                self.handle_orphan(&id[ID_ORPHAN_RECONNECT_PREFIX.len()..], ID_ORPHAN_RECONNECT_PREFIX);
+            }
+            id if id.starts_with(ID_ORPHAN_WAIT_PREFIX) => {
+                self.handle_orphan(&id[ID_ORPHAN_WAIT_PREFIX.len()..], ID_ORPHAN_WAIT_PREFIX);
+            }
+            id if id.starts_with(ID_ORPHAN_KILL_PREFIX) => {
+                self.handle_orphan(&id[ID_ORPHAN_KILL_PREFIX.len()..], ID_ORPHAN_KILL_PREFIX);
+            }
+            id if id.starts_with(ID_READ_ONLY_PREFIX) => {
+                self.toggle_read_only(&id[ID_READ_ONLY_PREFIX.len()..]);
+            }
+            id if id.starts_with(ID_EXPORT_TEXT_PREFIX) => {
+                self.export_transcript(&id[ID_EXPORT_TEXT_PREFIX.len()..], false);
+            }
+            id if id.starts_with(ID_EXPORT_RAW_PREFIX) => {
+                self.export_transcript(&id[ID_EXPORT_RAW_PREFIX.len()..], true);
+            }
+            id if id.starts_with(ID_RELAY_PREFIX) => {
+                self.switch_relay(&id[ID_RELAY_PREFIX.len()..]);
+            }
+            id if id.starts_with(ID_SSH_PREFIX) => {
+                self.connect_ssh(&id[ID_SSH_PREFIX.len()..]);
+            }
+            id if id.starts_with(ID_PASTE_SEND_PREFIX) => {
+                self.release_paste(&id[ID_PASTE_SEND_PREFIX.len()..], true);
+            }
+            id if id.starts_with(ID_PASTE_DISCARD_PREFIX) => {
+                self.release_paste(&id[ID_PASTE_DISCARD_PREFIX.len()..], false);
+            }
+            ID_SESSIONS_ONLY_ACTIVE => {
+                self.toggle_session_view(true);
+            }
+            ID_SESSIONS_BY_ACTIVITY => {
+                self.toggle_session_view(false);
+            }
+            id if id.starts_with(ID_PROJECT_SHARE_PREFIX) => {
+                let project = id[ID_PROJECT_SHARE_PREFIX.len()..].to_string();
+                self.toggle_project_shared(&project);
+            }
             _ => {
                 debug!("Unknown menu item clicked: {:?}", event.id());
             }
@@ -146,6 +1448,15 @@ impl App {
     }
 
     fn handle_ui_events(&mut self) {
+        let mut recent_changed = false;
+        let mut raised = Vec::new();
+        let mut sessions_changed = false;
+        let mut input_seen = false;
+        let mut join_changed = false;
+        let mut approvals_changed = false;
+        let mut pastes_changed = false;
+        let mut scanned_orphans = None;
+        let mut opened_urls = Vec::new();
         if let Some(ui_rx) = &self.ui_rx {
             while let Ok(event) = ui_rx.try_recv() {
                 debug!("UI event: {:?}", event);
@@ -159,15 +1470,36 @@ impl App {
                         }
                         UiEvent::RelayDisconnected => {
                             info!("Relay disconnected");
+                            if app_state.relay_connected {
+                                if let Some(usage) = &mut self.usage {
+                                    usage.record_reconnect();
+                                }
+                            }
                             app_state.relay_connected = false;
                             app_state.session_code = None;
+                            app_state.latency = None;
                             app_state.update_status_display();
                             app_state.update_code_display();
+                            join_changed = true;
+                            // The relay turned away every browser still waiting
+                            self.approvals.clear();
+                            approvals_changed = true;
+                            self.viewers.clear();
+                            self.rebuild_viewers_menu();
                         }
                         UiEvent::SessionCode(code) => {
                             info!("Received session code: {}", code);
+                            if let Some(relay) = self.switching_relay.take() {
+                                if self.config.notifications {
+                                    notify::notify(
+                                        "Terminal Remote",
+                                        &format!("Switched to relay \"{}\". New code: {}", relay, code),
+                                    );
+                                }
+                            }
                             app_state.session_code = Some(code);
                             app_state.update_code_display();
+                            join_changed = true;
                         }
                         UiEvent::BrowserConnected(browser_id) => {
                             info!("Browser connected: {}", browser_id);
@@ -177,26 +1509,99 @@ impl App {
                             info!("Browser disconnected: {}", browser_id);
                             app_state.browser_count = app_state.browser_count.saturating_sub(1);
                         }
+                        UiEvent::Presence(viewers) => {
+                            app_state.browser_count = viewers.len();
+                            self.viewers = viewers;
+                            self.rebuild_viewers_menu();
+                        }
+                        UiEvent::ApprovalRequest(pending) => {
+                            info!("Browser {} ({}) waiting for approval", pending.browser_id, pending.label);
+                            if self.config.notifications {
+                                notify::notify(
+                                    "Terminal Remote",
+                                    &format!(
+                                        "{} wants to connect. Allow or deny it under Browser Requests in the menu bar.",
+                                        pending.label
+                                    ),
+                                );
+                            }
+                            self.approvals.push(pending);
+                            approvals_changed = true;
+                        }
+                        UiEvent::ApprovalCancelled(browser_id) => {
+                            self.approvals.retain(|p| p.browser_id != browser_id);
+                            approvals_changed = true;
+                        }
+                        UiEvent::RelayLatency(stats) => {
+                            app_state.latency = Some(stats);
+                            app_state.update_status_display();
+                        }
                         UiEvent::TunnelUrl(url) => {
                             info!("Tunnel URL: {}", url);
                             app_state.tunnel_url = Some(url);
                             app_state.update_url_display();
+                            join_changed = true;
+                        }
+                        UiEvent::JoinToken { token, expires_in_secs, role } => {
+                            self.copy_one_time_link(&token, expires_in_secs, role);
+                        }
+                        UiEvent::Chat { name, text } => {
+                            if self.config.notifications {
+                                let from = name.as_deref().unwrap_or("Someone watching");
+                                notify::notify("Terminal Remote", &format!("{}: {}", from, text));
+                            }
                         }
                         UiEvent::RelayError(msg) => {
                             error!("Relay error: {}", msg);
+                            raised.push(Alert::warning(msg));
                         }
-                        UiEvent::ShellConnected { session_id, name } => {
+                        UiEvent::ShellConnected { session_id, name, stats, transcript } => {
                             info!("Shell connected: {} ({})", name, session_id);
+                            if let Some(usage) = &mut self.usage {
+                                usage.record_session();
+                            }
+                            self.session_stats.insert(session_id.clone(), stats);
+                            self.transcripts.insert(session_id.clone(), transcript);
+                            // A session that reconnected after a restart keeps its answer
+                            if let Some(allowed) =
+                                self.registry.lock().unwrap().clipboard_allowed(&session_id)
+                            {
+                                self.clipboard.decide(&session_id, allowed);
+                            }
+                            self.activity.attach(session_id, name);
+                            sessions_changed = true;
                             app_state.shell_count += 1;
                             app_state.update_count_display();
                         }
                         UiEvent::ShellDisconnected { session_id } => {
                             info!("Shell disconnected: {}", session_id);
+                            self.clipboard.forget(&session_id);
+                            self.session_stats.remove(&session_id);
+                            self.transcripts.remove(&session_id);
+                            self.activity.detach(&session_id);
+                            sessions_changed = true;
+                            // The router discarded the session's held pastes
+                            self.held_pastes.retain(|held| held.session_id != session_id);
+                            pastes_changed = true;
                             app_state.shell_count = app_state.shell_count.saturating_sub(1);
                             app_state.update_count_display();
                         }
+                        UiEvent::SessionEnded(session) => {
+                            self.recent.push(session);
+                            if let Err(e) = self.recent.save() {
+                                warn!("Failed to save session history: {}", e);
+                            }
+                            recent_changed = true;
+                        }
                         UiEvent::ShellRenamed { session_id, name } => {
                             info!("Shell renamed: {} -> {}", session_id, name);
+                            self.activity.rename(&session_id, name);
+                            input_seen = true;
+                        }
+                        UiEvent::SessionProject { session_id, project } => {
+                            debug!("Shell {} is in project {}", session_id, project);
+                            self.activity.set_project(&session_id, project);
+                            sessions_changed = true;
                         }
                         UiEvent::ShellCountChanged(count) => {
                             debug!("Shell count changed: {}", count);
@@ -205,6 +1610,117 @@ impl App {
                         }
                         UiEvent::PtyError(msg) => {
                             error!("PTY error: {}", msg);
+                            raised.push(Alert::critical(msg));
+                        }
+                        UiEvent::Alert(alert) => {
+                            error!("{}", alert.message);
+                            raised.push(alert);
+                        }
+                        UiEvent::RemoteInput { session_id } => {
+                            self.activity.record_input(&session_id, Instant::now());
+                            input_seen = true;
+                        }
+                        UiEvent::InputRateLimited { session_id, name } => {
+                            warn!("Input rate limited: {} ({})", name, session_id);
+                            if self.config.notifications {
+                                notify::notify(
+                                    "Terminal Remote",
+                                    &format!("Browser input to \"{}\" exceeded the rate limit and is being dropped", name),
+                                );
+                            }
+                        }
+                        UiEvent::BandwidthExceeded { name } => {
+                            let message = match name {
+                                Some(name) => format!(
+                                    "\"{}\" is over its hourly bandwidth budget; browsers see snapshots of it",
+                                    name
+                                ),
+                                None => "The hourly bandwidth budget is used up; browsers see snapshots of sessions"
+                                    .to_string(),
+                            };
+                            warn!("{}", message);
+                            if self.config.notifications {
+                                notify::notify("Terminal Remote", &message);
+                            }
+                        }
+                        UiEvent::PasteHeld(held) => {
+                            warn!("Holding {} from a browser for \"{}\"", held.summary, held.session_name);
+                            if self.config.notifications {
+                                notify::notify(
+                                    "Terminal Remote",
+                                    &format!(
+                                        "A browser pasted {} into \"{}\". Paste or discard it under Held Pastes in the menu bar.",
+                                        held.summary, held.session_name
+                                    ),
+                                );
+                            }
+                            // The router keeps the same number and drops the oldest
+                            if self.held_pastes.len() >= MAX_HELD_PASTES {
+                                self.held_pastes.remove(0);
+                            }
+                            self.held_pastes.push(held);
+                            pastes_changed = true;
+                        }
+                        UiEvent::OrphanedProxies(orphans) => {
+                            scanned_orphans = Some(orphans);
+                        }
+                        UiEvent::Bell { session_id, name, tty } => {
+                            debug!("Bell in {} ({})", name, session_id);
+                            if self.config.notifications {
+                                // The focus check waits on the AppleScript worker
+                                thread::spawn(move || {
+                                    if tty.is_some() && bell::focused_tty() == tty {
+                                        return;
+                                    }
+                                    notify::notify("Terminal Remote", &format!("Bell in: {}", name));
+                                });
+                            }
+                        }
+                        UiEvent::ClipboardCopy {
+                            session_id,
+                            name,
+                            text,
+                        } => {
+                            if !self.config.clipboard.to_pasteboard {
+                                continue;
+                            }
+                            match self.clipboard.offer(&session_id, text.clone()) {
+                                Offer::Write(text) => clipboard::write_pasteboard(&text),
+                                Offer::Prompt => {
+                                    let ui_tx = self.ui_tx.clone();
+                                    thread::spawn(move || {
+                                        let allowed = clipboard::prompt(&name, &text);
+                                        info!(
+                                            "Clipboard access for {} {}",
+                                            name,
+                                            if allowed { "allowed" } else { "denied" }
+                                        );
+                                        if let Some(ui_tx) = ui_tx {
+                                            let _ = ui_tx.send(UiEvent::ClipboardPermission {
+                                                session_id,
+                                                allowed,
+                                            });
+                                        }
+                                    });
+                                }
+                                Offer::Queued => {}
+                                Offer::Denied => {
+                                    debug!("Dropping clipboard copy from denied session {}", session_id);
+                                }
+                            }
+                        }
+                        UiEvent::ClipboardPermission { session_id, allowed } => {
+                            self.registry.lock().unwrap().set_clipboard_allowed(&session_id, allowed);
+                            if let Some(text) = self.clipboard.decide(&session_id, allowed) {
+                                clipboard::write_pasteboard(&text);
+                            }
+                        }
+                        UiEvent::ConfigChanged(config) => {
+                            info!("Preferences updated");
+                            self.config = *config;
+                        }
+                        UiEvent::OpenUrl(url) => {
+                            opened_urls.push(url);
                         }
                         UiEvent::TerminalDataFromShell { session_id, data } => {
                             debug!(
@@ -224,6 +1740,40 @@ impl App {
                 }
             }
         }
+        if recent_changed {
+            self.rebuild_recent_menu();
+        }
+        for url in opened_urls {
+            self.open_url(&url);
+        }
+        for alert in raised {
+            self.raise_alert(alert);
+        }
+        if sessions_changed {
+            self.rebuild_sessions_menu();
+        }
+        if approvals_changed {
+            self.rebuild_approvals_menu();
+        }
+        if pastes_changed {
+            self.rebuild_pastes_menu();
+        }
+        if let Some(orphans) = scanned_orphans {
+            self.update_orphans(orphans);
+        }
+        if join_changed && self.qr_popover.is_some() {
+            match self.join_url() {
+                Some(url) => {
+                    if let Some(popover) = &mut self.qr_popover {
+                        popover.set_url(url);
+                    }
+                }
+                // Disconnected: the code on screen no longer works
+                None => self.qr_popover = None,
+            }
+        }
+        self.update_activity_display(input_seen);
+        self.report_usage();
 
         // Reset copy button text after 2 seconds
         if let Some(reset_time) = self.copy_reset_time {
@@ -261,13 +1811,18 @@ impl ApplicationHandler<AppEvent> for App {
         }
     }
 
-    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: AppEvent) {
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: AppEvent) {
         match event {
             AppEvent::TrayIconEvent(e) => {
                 debug!("Tray event: {:?}", e);
             }
             AppEvent::MenuEvent(e) => {
-                self.handle_menu_event(e);
+                self.handle_menu_event(event_loop, e);
+            }
+            AppEvent::HotKey(e) => {
+                if e.state == global_hotkey::HotKeyState::Pressed {
+                    self.handle_hotkey(event_loop, e.id);
+                }
             }
         }
     }
@@ -275,10 +1830,29 @@ impl ApplicationHandler<AppEvent> for App {
     fn window_event(
         &mut self,
         _event_loop: &ActiveEventLoop,
-        _window_id: WindowId,
-        _event: WindowEvent,
+        window_id: WindowId,
+        event: WindowEvent,
     ) {
-        // We don't have any windows, but winit requires this
+        // The join QR code popover is our only window
+        let Some(popover) = &mut self.qr_popover else {
+            return;
+        };
+        if popover.window.id() != window_id {
+            return;
+        }
+        match event {
+            WindowEvent::RedrawRequested => popover.draw(),
+            WindowEvent::CloseRequested | WindowEvent::Focused(false) => {
+                self.qr_popover = None;
+            }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed
+                    && event.logical_key == Key::Named(NamedKey::Escape) =>
+            {
+                self.qr_popover = None;
+            }
+            _ => {}
+        }
     }
 
     fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
@@ -288,14 +1862,130 @@ impl ApplicationHandler<AppEvent> for App {
     }
 }
 
-fn main() {
-    // Initialize tracing subscriber for logging
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
+/// Scan for pty-proxies that are running but not connected every
+/// [`orphans::SCAN_INTERVAL`], until the UI goes away.
+fn spawn_orphan_scanner(registry: SharedRegistry, ui_tx: mpsc::Sender<UiEvent>) {
+    let instance = paths::instance();
+    thread::spawn(move || {
+        let mut tracker = OrphanTracker::default();
+        loop {
+            thread::sleep(orphans::SCAN_INTERVAL);
+            let processes = match orphans::list_processes() {
+                Ok(processes) => processes,
+                Err(e) => {
+                    debug!("Cannot list processes: {}", e);
+                    continue;
+                }
+            };
+            let connected = registry.lock().unwrap().connected_pids();
+            let found = orphans::find_orphans(&processes, instance.as_deref(), &connected);
+            if let Some(orphans) = tracker.update(found) {
+                if ui_tx.send(UiEvent::OrphanedProxies(orphans)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Log and show a startup error, then exit.
+fn exit_with_error(message: &str) -> ! {
+    error!("{}", message);
+    notify::notify("Terminal Remote", message);
+    // Give osascript a moment before the process exits
+    thread::sleep(Duration::from_millis(500));
+    std::process::exit(1);
+}
+
+/// Log to stderr and, for the menu bar app, to [`paths::log_file`]. A log
+/// over [`MAX_LOG_BYTES`] is moved to `<name>.old` first.
+fn init_logging(to_file: bool) {
+    use tracing_subscriber::prelude::*;
+
+    let file = to_file.then(paths::log_file).flatten().and_then(|path| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok()?;
+        }
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.len() > MAX_LOG_BYTES) {
+            let _ = std::fs::rename(&path, path.with_extension("log.old"));
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok()
+    });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::DEBUG)
+        .with(tracing_subscriber::fmt::layer())
+        .with(file.map(|file| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+        }))
         .init();
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // `--instance <name>` is equivalent to TERMINAL_REMOTE_INSTANCE; the
+    // variable is what every path lookup reads, the log file's included
+    let instance_arg = args.iter().position(|a| a == "--instance").map(|pos| args.get(pos + 1));
+    if let Some(Some(name)) = instance_arg {
+        std::env::set_var(paths::INSTANCE_ENV, name);
+    }
+
+    let command = args
+        .get(1)
+        .map(String::as_str)
+        .filter(|c| matches!(*c, "install-shell-integration" | "uninstall-shell-integration"));
+    init_logging(command.is_none());
+    if let Some(command) = command {
+        run_shell_integration_command(command == "install-shell-integration");
+    }
 
     info!("Starting mac-client menu bar application");
 
+    if let Some(None) = instance_arg {
+        exit_with_error("--instance requires a name");
+    }
+    let instance = paths::instance();
+    if let Some(name) = &instance {
+        if let Err(e) = paths::validate_instance(name) {
+            exit_with_error(&e);
+        }
+        info!("Running as instance \"{}\"", name);
+    }
+    if let Err(e) = paths::ensure_runtime_dir() {
+        exit_with_error(&format!("Cannot use runtime directory: {}", e));
+    }
+    if let Err(e) = paths::migrate_legacy_config() {
+        warn!("Cannot move the config from ~/.terminal-remote: {}", e);
+    }
+
+    // Refuse to start next to a running instance: binding would steal its socket
+    let pty_socket = paths::pty_socket();
+    if let Ok(SocketState::Live) = socket::probe(&pty_socket) {
+        error!("Another mac-client is already listening on {}, exiting", pty_socket.display());
+        notify::notify("Terminal Remote", "Terminal Remote is already running");
+        // Give osascript a moment before the process exits
+        thread::sleep(Duration::from_millis(500));
+        std::process::exit(1);
+    }
+
+    let config = Config::load();
+    let device_key = if config.end_to_end_encryption {
+        match DeviceKey::load_or_create() {
+            Ok(key) => {
+                info!("End-to-end encryption on, key fingerprint {}", key.fingerprint());
+                Some(key)
+            }
+            Err(e) => exit_with_error(&format!("Cannot load end-to-end encryption key: {}", e)),
+        }
+    } else {
+        None
+    };
+
     // Create the event loop FIRST (required on macOS)
     let event_loop = EventLoop::<AppEvent>::with_user_event()
         .build()
@@ -312,10 +2002,19 @@ fn main() {
         let _ = proxy.send_event(AppEvent::MenuEvent(event));
     }));
 
+    let proxy = event_loop.create_proxy();
+    global_hotkey::GlobalHotKeyEvent::set_event_handler(Some(move |event| {
+        let _ = proxy.send_event(AppEvent::HotKey(event));
+    }));
+
     // Create channels for UI <-> background communication
     let (ui_tx, ui_rx) = mpsc::channel::<UiEvent>();
     let (bg_tx, bg_rx) = mpsc::channel::<BackgroundCommand>();
 
+    // Before the event loop starts, so a link that launched the app arrives
+    #[cfg(target_os = "macos")]
+    url_scheme::register(ui_tx.clone());
+
     // Create pty command channel (sender stays in main thread)
     let (pty_cmd_tx, pty_cmd_rx) = tokio::sync::mpsc::unbounded_channel::<PtyCommand>();
 
@@ -336,7 +2035,10 @@ fn main() {
         match relay_bin {
             Some(bin) => {
                 info!("Starting relay-server from: {}", bin.display());
-                match Command::new(&bin).spawn() {
+                match Command::new(&bin)
+                    .env("SCROLLBACK_BYTES", config.scrollback_bytes.to_string())
+                    .spawn()
+                {
                     Ok(child) => {
                         let pid = child.id();
                         info!("relay-server started (pid {})", pid);
@@ -383,16 +2085,30 @@ fn main() {
                     }
                 }
             }
-            libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
-            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, handler as *const () as libc::sighandler_t);
+            libc::signal(libc::SIGINT, handler as *const () as libc::sighandler_t);
         }
     }
 
     // Spawn background thread with Tokio runtime
     let ui_tx_bg = ui_tx.clone();
+    let ui_tx_app = ui_tx.clone();
     let cloudflared_pid_bg = cloudflared_pid.clone();
+    let config_bg = config.clone();
+    let encryptor = device_key.clone().map(|key| Arc::new(Encryptor::new(key)));
+    let registry: SharedRegistry = Arc::new(Mutex::new(SessionRegistry::load()));
+    let registry_bg = registry.clone();
+    spawn_orphan_scanner(registry.clone(), ui_tx.clone());
     let bg_handle = thread::spawn(move || {
-        run_background_tasks(ui_tx_bg, bg_rx, pty_cmd_rx, cloudflared_pid_bg);
+        run_background_tasks(
+            config_bg,
+            encryptor,
+            registry_bg,
+            ui_tx_bg,
+            bg_rx,
+            pty_cmd_rx,
+            cloudflared_pid_bg,
+        );
     });
 
     // Load icon from embedded bytes
@@ -404,8 +2120,12 @@ fn main() {
         .expect("Failed to decode icon");
     let icon_rgba = icon_image.to_rgba8();
     let (width, height) = icon_rgba.dimensions();
+    let mut tinted_rgba = icon_rgba.clone().into_raw();
+    activity::tint_rgba(&mut tinted_rgba, activity::ACTIVE_TINT);
     let icon = tray_icon::Icon::from_rgba(icon_rgba.into_raw(), width, height)
         .expect("Failed to create icon");
+    let tinted_icon = tray_icon::Icon::from_rgba(tinted_rgba, width, height)
+        .expect("Failed to create activity icon");
 
     debug!("Icon loaded: {}x{}", width, height);
 
@@ -416,19 +2136,91 @@ fn main() {
     let url_item = MenuItem::new("URL: starting tunnel...", false, None);
     let code_item = MenuItem::new("Code: ------", false, None);
     let status_item = MenuItem::new("Status: Connecting...", false, None);
-    let sessions_item = MenuItem::new("Sessions: 0", false, None);
+    let sessions_item = Submenu::new("Sessions: 0", true);
+    let only_active_item =
+        CheckMenuItem::with_id(ID_SESSIONS_ONLY_ACTIVE, "Show Only Active", true, false, None);
+    let by_activity_item =
+        CheckMenuItem::with_id(ID_SESSIONS_BY_ACTIVITY, "Sort by Recent Activity", true, false, None);
+    let _ = sessions_item.append_items(&[
+        &only_active_item,
+        &by_activity_item,
+        &PredefinedMenuItem::separator(),
+    ]);
 
     // Action items
     let regen_code_item = MenuItem::with_id(ID_REGEN_CODE, "Regenerate Code", true, None);
     let copy_url_item = MenuItem::with_id(ID_COPY_URL, "Copy URL", true, None);
     let copy_code_item = MenuItem::with_id(ID_COPY_CODE, "Copy Session Code", true, None);
+    let copy_join_url_item = MenuItem::with_id(ID_COPY_JOIN_URL, "Copy Join URL", true, None);
+    let copy_one_time_link_item =
+        MenuItem::with_id(ID_COPY_ONE_TIME_LINK, "Copy One-Time Link (10 min)", true, None);
+    let copy_view_only_link_item =
+        MenuItem::with_id(ID_COPY_VIEW_ONLY_LINK, "Copy View-Only Link (10 min)", true, None);
+    let show_qr_item = MenuItem::with_id(ID_SHOW_JOIN_QR, "Show QR Code…", true, None);
+    let active_relay = config.relay_url();
+    let relay_items: Vec<CheckMenuItem> = config
+        .relays
+        .iter()
+        .enumerate()
+        .map(|(i, relay)| {
+            let id = format!("{}{}", ID_RELAY_PREFIX, i);
+            CheckMenuItem::with_id(id, relay.label(), true, relay.url == active_relay, None)
+        })
+        .collect();
+    let relay_menu = (!relay_items.is_empty()).then(|| {
+        let menu = Submenu::new("Relay", true);
+        for item in &relay_items {
+            let _ = menu.append(item);
+        }
+        menu
+    });
+    let ssh_menu = (!config.ssh.hosts.is_empty()).then(|| {
+        let menu = Submenu::new("SSH Hosts", true);
+        for (i, host) in config.ssh.hosts.iter().enumerate() {
+            let _ = menu.append(&MenuItem::with_id(format!("{}{}", ID_SSH_PREFIX, i), host.label(), true, None));
+        }
+        menu
+    });
+    let recent_menu = Submenu::new("Recent Sessions", true);
+    let orphans_menu = Submenu::new("Disconnected Sessions", false);
+    let alerts_menu = Submenu::new("Errors", true);
+    let pastes_menu = config
+        .security
+        .confirm_paste
+        .then(|| Submenu::new("Held Pastes", false));
+    let approvals_menu = config
+        .security
+        .require_approval
+        .then(|| Submenu::new("Browser Requests", false));
+    let viewers_menu = Submenu::new("Watching", false);
+    let e2e_menu = device_key.as_ref().map(|key| {
+        let menu = Submenu::new("End-to-End Encryption", true);
+        let _ = menu.append_items(&[
+            &MenuItem::new(format!("Key: {}", key.fingerprint()), false, None),
+            &MenuItem::with_id(ID_COPY_PAIRING_LINK, "Copy Pairing Link", true, None),
+            &MenuItem::with_id(ID_SHOW_PAIRING_QR, "Show Pairing QR Code…", true, None),
+        ]);
+        menu
+    });
 
     // Check current login item status and set initial checkbox state
     let is_login_enabled = is_login_item_enabled();
     let login_item =
         CheckMenuItem::with_id(ID_LOGIN_ITEM, "Start at Login", true, is_login_enabled, None);
     debug!("Login item initial state: {}", is_login_enabled);
+    let do_not_disturb_item =
+        CheckMenuItem::with_id(ID_DO_NOT_DISTURB, "Do Not Disturb", true, false, None);
+    let shell_integration_item = CheckMenuItem::with_id(
+        ID_SHELL_INTEGRATION,
+        "Shell Integration",
+        true,
+        std::env::var_os("HOME")
+            .is_some_and(|home| shell_integration::is_installed(std::path::Path::new(&home))),
+        None,
+    );
 
+    let preferences_item = MenuItem::with_id(ID_PREFERENCES, "Preferences…", true, None);
+    let diagnostics_item = MenuItem::with_id(ID_SAVE_DIAGNOSTICS, "Save Diagnostics…", true, None);
     let quit_item = MenuItem::with_id(ID_QUIT, "Quit", true, None);
 
     // Assemble menu
@@ -444,17 +2236,61 @@ fn main() {
         .expect("Failed to add copy url item");
     menu.append(&copy_code_item)
         .expect("Failed to add copy code item");
+    menu.append(&copy_join_url_item)
+        .expect("Failed to add copy join url item");
+    menu.append(&copy_one_time_link_item)
+        .expect("Failed to add copy one-time link item");
+    menu.append(&copy_view_only_link_item)
+        .expect("Failed to add copy view-only link item");
+    menu.append(&show_qr_item)
+        .expect("Failed to add show qr item");
     menu.append(&regen_code_item)
         .expect("Failed to add regen code item");
+    if let Some(relay_menu) = &relay_menu {
+        menu.append(relay_menu)
+            .expect("Failed to add relay menu");
+    }
+    menu.append(&do_not_disturb_item)
+        .expect("Failed to add do not disturb item");
+    menu.append(&viewers_menu)
+        .expect("Failed to add watching menu");
+    if let Some(approvals_menu) = &approvals_menu {
+        menu.append(approvals_menu)
+            .expect("Failed to add browser requests menu");
+    }
+    if let Some(pastes_menu) = &pastes_menu {
+        menu.append(pastes_menu)
+            .expect("Failed to add held pastes menu");
+    }
+    if let Some(ssh_menu) = &ssh_menu {
+        menu.append(ssh_menu)
+            .expect("Failed to add ssh hosts menu");
+    }
+    menu.append(&recent_menu)
+        .expect("Failed to add recent sessions menu");
+    menu.append(&orphans_menu)
+        .expect("Failed to add disconnected sessions menu");
+    menu.append(&alerts_menu)
+        .expect("Failed to add errors menu");
+    if let Some(e2e_menu) = &e2e_menu {
+        menu.append(e2e_menu)
+            .expect("Failed to add encryption menu");
+    }
     menu.append(&PredefinedMenuItem::separator())
         .expect("Failed to add separator");
+    menu.append(&preferences_item)
+        .expect("Failed to add preferences item");
+    menu.append(&diagnostics_item)
+        .expect("Failed to add diagnostics item");
     menu.append(&login_item)
         .expect("Failed to add login item");
+    menu.append(&shell_integration_item)
+        .expect("Failed to add shell integration item");
     menu.append(&PredefinedMenuItem::separator())
         .expect("Failed to add separator");
     menu.append(&quit_item).expect("Failed to add quit item");
 
-    debug!("Menu constructed with {} items", 9);
+    debug!("Menu constructed with {} items", 12);
 
     // Create app state with menu item references
     let app_state = AppState::new(
@@ -468,9 +2304,12 @@ fn main() {
     // Create tray icon
     let tray_icon = TrayIconBuilder::new()
         .with_menu(Box::new(menu))
-        .with_icon(icon)
+        .with_icon(icon.clone())
         .with_icon_as_template(true)
-        .with_tooltip("Terminal Remote")
+        .with_tooltip(match &instance {
+            Some(name) => format!("Terminal Remote ({})", name),
+            None => "Terminal Remote".to_string(),
+        })
         .build()
         .expect("Failed to create tray icon");
 
@@ -479,14 +2318,44 @@ fn main() {
     // Create our application handler
     let mut app = App::new();
     app.tray_icon = Some(tray_icon);
+    app.tray_icons = Some((icon, tinted_icon));
     app.app_state = Some(app_state);
     app.login_item = Some(login_item);
+    app.shell_integration_item = Some(shell_integration_item);
+    app.do_not_disturb_item = Some(do_not_disturb_item);
+    app.only_active_item = Some(only_active_item);
+    app.by_activity_item = Some(by_activity_item);
     app.bg_tx = Some(bg_tx);
     app.ui_rx = Some(ui_rx);
     app.bg_handle = Some(bg_handle);
     app.pty_cmd_tx = Some(pty_cmd_tx);
     app.cloudflared_pid = cloudflared_pid;
     app.relay_server_pid = relay_server_pid;
+    if config.telemetry.endpoint().is_some() {
+        info!("Telemetry is on: anonymous usage counters are reported daily");
+        app.usage = Some(Usage::load(SystemTime::now()));
+    }
+    app.relay_url = active_relay;
+    app.relay_items = relay_items;
+    app.config = config;
+    app.device_key = device_key;
+    app.registry = registry;
+    app.ui_tx = Some(ui_tx_app);
+    app.recent = RecentSessions::load();
+    app.recent_menu = Some(recent_menu);
+    app.rebuild_recent_menu();
+    app.alerts_menu = Some(alerts_menu);
+    app.rebuild_alerts_menu();
+    app.approvals_menu = approvals_menu;
+    app.viewers_menu = Some(viewers_menu);
+    app.pastes_menu = pastes_menu;
+    app.orphans_menu = Some(orphans_menu);
+    let (hotkeys, problems) = Hotkeys::register(&app.config.hotkeys);
+    app.hotkeys = hotkeys;
+    for problem in problems {
+        warn!("{}", problem);
+        app.raise_alert(Alert::warning(problem));
+    }
 
     info!("Entering main event loop");
 
@@ -494,6 +2363,11 @@ fn main() {
     event_loop.run_app(&mut app).expect("Event loop failed");
 
     // Clean up
+    if let Some(usage) = &app.usage {
+        if let Err(e) = usage.save() {
+            warn!("Cannot save usage counters: {}", e);
+        }
+    }
     info!("Waiting for background thread to finish...");
     if let Some(handle) = app.bg_handle.take() {
         if let Err(e) = handle.join() {
@@ -504,6 +2378,47 @@ fn main() {
     info!("Application exiting");
 }
 
+/// `mac-client install-shell-integration` / `uninstall-shell-integration`:
+/// update the rc files, print what changed and exit.
+fn run_shell_integration_command(install: bool) -> ! {
+    let Some(home) = std::env::var_os("HOME").map(std::path::PathBuf::from) else {
+        eprintln!("HOME is not set");
+        std::process::exit(1);
+    };
+    let result = if install {
+        let login_shell = std::env::var("SHELL").ok();
+        shell_integration::install(&home, login_shell.as_deref().and_then(Shell::from_path))
+    } else {
+        shell_integration::uninstall(&home)
+    };
+    match result {
+        Ok(changes) if changes.is_empty() => {
+            println!(
+                "{}",
+                if install {
+                    "No zsh, bash or fish rc file found; set SHELL to create one"
+                } else {
+                    "Shell integration is not installed"
+                }
+            );
+            std::process::exit(0);
+        }
+        Ok(changes) => {
+            for change in changes {
+                println!("{}", change);
+            }
+            if install {
+                println!("Open a new terminal window to start sharing it.");
+            }
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Failed to update shell integration: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 /// Check if the app is currently registered as a login item.
 ///
 /// Returns true if enabled, false otherwise (not registered, requires approval, or not found).
@@ -541,6 +2456,9 @@ fn configure_login_item(enable: bool) -> Result<(), Box<dyn std::error::Error>>
 
 /// Run background tasks (relay client and PTY manager) on a Tokio runtime.
 fn run_background_tasks(
+    config: Config,
+    encryptor: Option<Arc<Encryptor>>,
+    registry: SharedRegistry,
     ui_tx: mpsc::Sender<UiEvent>,
     bg_rx: mpsc::Receiver<BackgroundCommand>,
     pty_cmd_rx: tokio::sync::mpsc::UnboundedReceiver<PtyCommand>,
@@ -551,9 +2469,8 @@ fn run_background_tasks(
     let rt = Runtime::new().expect("Failed to create Tokio runtime");
 
     rt.block_on(async {
-        // Get relay URL from env or default
-        let relay_url = std::env::var("RELAY_URL")
-            .unwrap_or_else(|_| "ws://localhost:3000/ws".to_string());
+        // Get relay URL from env, config file, or default
+        let relay_url = config.relay_url();
         info!("Using relay URL: {}", relay_url);
 
         // Create channels for relay events
@@ -563,117 +2480,196 @@ fn run_background_tasks(
         let (relay_cmd_tx, relay_cmd_rx) = tokio::sync::mpsc::unbounded_channel::<RelayCommand>();
 
         // Create relay client
-        let mut relay = RelayClient::new(relay_url, relay_event_tx, relay_cmd_rx);
-
-        // Store command senders for data forwarding
-        let relay_cmd_tx_for_pty = relay_cmd_tx.clone();
-        let relay_cmd_tx_for_relay = relay_cmd_tx.clone();
-
-        // Shared session list for browser sync
-        let session_list: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>> =
-            std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
-        let session_list_for_pty = session_list.clone();
-        let session_list_for_relay = session_list.clone();
+        let identity = ClientIdentity::load(config.display_name.as_deref());
+        let (relay_switch_tx, relay_switch_rx) = tokio::sync::mpsc::unbounded_channel();
+        let relay_heartbeat = Heartbeat::default();
+        let mut relay = RelayClient::new(relay_url, relay_event_tx, relay_cmd_rx)
+            .with_identity(identity)
+            .with_relay_switch(relay_switch_rx)
+            .with_heartbeat(relay_heartbeat.clone());
+        if config.direct.enabled {
+            relay = relay.with_direct_mode(config.direct.ice_servers.clone());
+        }
+        if let Some(password) = config.security.password.clone().filter(|p| !p.is_empty()) {
+            relay = relay.with_password(password);
+        }
+        if let Some(key) = config.relay_api_key() {
+            relay = relay.with_api_key(key);
+        }
+        if encryptor.is_some() {
+            relay = relay.with_passthrough();
+        }
+        if config.security.zero_retention {
+            if config.record_on_relay {
+                warn!("record_on_relay is ignored: the relay keeps nothing with zero_retention on");
+            }
+            relay = relay.with_zero_retention();
+        } else if config.record_on_relay {
+            relay = relay.with_recording();
+        }
+        if let Some(max) = config.security.max_browsers {
+            relay = relay.with_browser_limit(max);
+        }
+        relay = relay.with_scrollback_limit(config.scrollback_bytes);
+        if !config.compress_output {
+            relay = relay.without_compression();
+        }
 
-        // Create PTY manager (replaces both TmuxManager and IpcServer)
-        let (_pty_manager, mut pty_event_rx, pty_internal_cmd_tx) = PtyManager::new();
+        // Session backends: tmux panes when mirrored, ssh sessions when
+        // hosts are configured, then pty-proxy sessions, which own every
+        // other session ID
+        let mut session_backends: Vec<Box<dyn SessionBackend>> = Vec::new();
+        if config.tmux.enabled {
+            session_backends.push(Box::new(TmuxManager::new(config.tmux.clone())));
+        }
+        let mut ssh_launcher = None;
+        if !config.ssh.hosts.is_empty() {
+            let ssh = SshManager::new(config.ssh.clone());
+            ssh_launcher = Some(ssh.launcher());
+            session_backends.push(Box::new(ssh));
+        }
+        session_backends.push(Box::new(PtyManager::new(registry, config.close_window)));
+        let mut backends = Backends::new(session_backends);
+        let pty_event_rx = backends.take_events();
+
+        // Commands go to whichever backend owns the session
+        let backends = Arc::new(backends);
+        let (pty_internal_cmd_tx, backend_cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let backends_for_commands = backends.clone();
+        let backend_handle = tokio::spawn(async move {
+            backends_for_commands.run(backend_cmd_rx).await;
+        });
 
         // No AttachAll needed — sessions auto-register when pty-proxy connects
 
-        // Clone for relay forwarding (before move)
-        let pty_cmd_tx_for_relay = pty_internal_cmd_tx.clone();
+        // Router owns the session list and the relay frame format
+        let router = Router::new(
+            relay_cmd_tx.clone(),
+            pty_internal_cmd_tx.clone(),
+            ui_tx.clone(),
+            config.rate_limit.clone(),
+            config.security.clone(),
+            config.clipboard.clone(),
+            encryptor,
+        );
+        let router = router.with_size_policy(config.size_policy);
+        let router = if config.bandwidth.enabled {
+            router.with_bandwidth(config.bandwidth.clone())
+        } else {
+            router
+        };
+        let router_for_relay = router.clone();
+        let router_for_commands = router.clone();
+
+        // Relay state shared with the control socket
+        let relay_status: SharedRelayStatus = Arc::new(Mutex::new(RelayStatus::default()));
+
+        // Long-running tasks are restarted if they stop; failures are
+        // listed in the Errors submenu
+        let report = |ui_tx: &mpsc::Sender<UiEvent>| {
+            let ui_tx = ui_tx.clone();
+            move |alert| {
+                let _ = ui_tx.send(UiEvent::Alert(alert));
+            }
+        };
+
+        // Serve status queries on the control socket
+        let relay_status_control = relay_status.clone();
+        let pty_cmd_tx_for_control = pty_internal_cmd_tx.clone();
+        let control_handle = supervise("Control socket", report(&ui_tx), move || {
+            let relay_status = relay_status_control.clone();
+            let pty_cmd_tx = pty_cmd_tx_for_control.clone();
+            async move {
+                control::run_control_server(relay_status, pty_cmd_tx)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
 
         // Forward pty commands from main thread to pty manager
-        let mut pty_cmd_rx = pty_cmd_rx;
-        let pty_forward_handle = tokio::spawn(async move {
-            while let Some(cmd) = pty_cmd_rx.recv().await {
-                if pty_internal_cmd_tx.send(cmd).is_err() {
-                    break;
+        let pty_cmd_rx = Arc::new(tokio::sync::Mutex::new(pty_cmd_rx));
+        let pty_forward_handle = supervise("PTY command forwarder", report(&ui_tx), move || {
+            let pty_cmd_rx = pty_cmd_rx.clone();
+            let pty_internal_cmd_tx = pty_internal_cmd_tx.clone();
+            async move {
+                let mut pty_cmd_rx = pty_cmd_rx.lock().await;
+                while let Some(cmd) = pty_cmd_rx.recv().await {
+                    if pty_internal_cmd_tx.send(cmd).is_err() {
+                        break;
+                    }
                 }
+                Ok(())
             }
         });
 
         // Spawn cloudflared tunnel
         let ui_tx_tunnel = ui_tx.clone();
         let cloudflared_pid = cloudflared_pid.clone();
+        let relay_status_tunnel = relay_status.clone();
         let tunnel_handle = tokio::task::spawn_blocking(move || {
-            run_cloudflared_tunnel(ui_tx_tunnel, cloudflared_pid);
+            run_cloudflared_tunnel(ui_tx_tunnel, cloudflared_pid, relay_status_tunnel);
+        });
+
+        // Screen snapshots of sessions over their bandwidth budget
+        let router_for_snapshots = router.clone();
+        let snapshot_secs = config.bandwidth.snapshot_secs.max(1);
+        let snapshot_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(snapshot_secs));
+            loop {
+                interval.tick().await;
+                router_for_snapshots.send_snapshots();
+            }
         });
 
         // Forward PTY events to relay (output -> browser)
-        let ui_tx_pty = ui_tx.clone();
-        let pty_event_handle = tokio::spawn(async move {
-            while let Some(event) = pty_event_rx.recv().await {
-                match event {
-                    PtyEvent::Attached { session_id, session_name } => {
-                        info!("pty-proxy session connected: {} ({})", session_name, session_id);
-                        // Update session list
-                        {
-                            let mut list = session_list_for_pty.lock().unwrap();
-                            list.push((session_id.clone(), session_name.clone()));
-                        }
-                        // Notify relay to send to browser
-                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionConnected {
-                            session_id: session_id.clone(),
-                            name: session_name.clone(),
-                        });
-                        // Notify UI
-                        let _ = ui_tx_pty.send(UiEvent::ShellConnected {
-                            session_id,
-                            name: session_name,
-                        });
-                    }
-                    PtyEvent::Detached { session_id } => {
-                        info!("pty-proxy session disconnected: {}", session_id);
-                        // Update session list
-                        {
-                            let mut list = session_list_for_pty.lock().unwrap();
-                            list.retain(|(id, _)| id != &session_id);
-                        }
-                        // Notify relay to send to browser
-                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionDisconnected {
-                            session_id: session_id.clone(),
-                        });
-                        // Notify UI
-                        let _ = ui_tx_pty.send(UiEvent::ShellDisconnected { session_id });
-                    }
-                    PtyEvent::Output { session_id, data } => {
-                        // Forward pty output to relay for browser
-                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendTerminalData {
-                            session_id,
-                            data,
-                        });
-                    }
-                    PtyEvent::SessionResize { session_id, cols, rows } => {
-                        // Forward mac terminal resize to browser (one-way: mac -> UI)
-                        let _ = relay_cmd_tx_for_pty.send(RelayCommand::SendSessionResize {
-                            session_id,
-                            cols,
-                            rows,
-                        });
-                    }
-                    PtyEvent::Error(msg) => {
-                        error!("PTY error: {}", msg);
-                    }
+        let pty_event_rx = Arc::new(tokio::sync::Mutex::new(pty_event_rx));
+        let pty_event_handle = supervise("PTY event router", report(&ui_tx), move || {
+            let pty_event_rx = pty_event_rx.clone();
+            let router = router.clone();
+            async move {
+                let mut pty_event_rx = pty_event_rx.lock().await;
+                while let Some(event) = pty_event_rx.recv().await {
+                    router.route_pty_event(event);
                 }
+                Ok(())
             }
         });
 
         // Spawn relay client task
-        let relay_handle = tokio::spawn(async move {
-            relay.run().await;
+        // The watchdog restarts it if it stops showing signs of life
+        let relay = Arc::new(tokio::sync::Mutex::new(relay));
+        let relay_handle = supervise("Relay client", report(&ui_tx), move || {
+            let relay = relay.clone();
+            let heartbeat = relay_heartbeat.clone();
+            async move {
+                heartbeat.beat();
+                let mut relay = relay.lock().await;
+                tokio::select! {
+                    _ = relay.run() => Err("relay client exited".to_string()),
+                    silent = heartbeat.stalled(watchdog::RELAY_STALL_AFTER) => {
+                        Err(format!("stalled, no sign of life for {}s", silent.as_secs()))
+                    }
+                }
+            }
         });
 
-        // Spawn event forwarding task
+        // Spawn event forwarding task (blocking: std::sync::mpsc)
+        let relay_event_rx = Arc::new(Mutex::new(relay_event_rx));
         let ui_tx_relay = ui_tx.clone();
-        let relay_forward_handle = tokio::task::spawn_blocking(move || {
-            forward_relay_events(
-                relay_event_rx,
-                ui_tx_relay,
-                pty_cmd_tx_for_relay,
-                relay_cmd_tx_for_relay,
-                session_list_for_relay,
-            );
+        let allow_remote_create = config.security.allow_remote_create;
+        let relay_forward_handle = supervise("Relay event forwarder", report(&ui_tx), move || {
+            let relay_event_rx = relay_event_rx.clone();
+            let ui_tx = ui_tx_relay.clone();
+            let router = router_for_relay.clone();
+            let relay_status = relay_status.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let rx = relay_event_rx.lock().unwrap_or_else(|e| e.into_inner());
+                    forward_relay_events(&rx, ui_tx, router, relay_status, allow_remote_create);
+                })
+                .await
+                .map_err(|e| e.to_string())
+            }
         });
 
         // Wait for shutdown signal
@@ -695,6 +2691,35 @@ fn run_background_tasks(
                     info!("Reconnecting relay to regenerate session code");
                     let _ = relay_cmd_tx.send(RelayCommand::Reconnect);
                 }
+                Ok(BackgroundCommand::SwitchRelay(url)) => {
+                    let _ = relay_switch_tx.send(url);
+                }
+                Ok(BackgroundCommand::SetProjectPaused { project, paused }) => {
+                    router_for_commands.set_project_paused(&project, paused);
+                }
+                Ok(BackgroundCommand::SetSharingPaused(paused)) => {
+                    router_for_commands.set_sharing_paused(paused);
+                }
+                Ok(BackgroundCommand::SetSessionReadOnly { session_id, read_only }) => {
+                    router_for_commands.set_read_only(&session_id, read_only);
+                }
+                Ok(BackgroundCommand::CreateJoinLink { role }) => {
+                    let _ = relay_cmd_tx.send(RelayCommand::CreateJoinToken {
+                        ttl_secs: join::ONE_TIME_LINK_TTL_SECS,
+                        role,
+                    });
+                }
+                Ok(BackgroundCommand::AnswerApproval { browser_id, approved }) => {
+                    router_for_commands.answer_approval(browser_id, approved);
+                }
+                Ok(BackgroundCommand::ReleasePaste { id, send }) => {
+                    router_for_commands.release_paste(id, send);
+                }
+                Ok(BackgroundCommand::ConnectSsh(host)) => {
+                    if let Some(ssh_launcher) = &ssh_launcher {
+                        ssh_launcher.connect(host);
+                    }
+                }
                 Err(mpsc::TryRecvError::Empty) => {
                     // No command, continue
                 }
@@ -711,7 +2736,10 @@ fn run_background_tasks(
         relay_forward_handle.abort();
         pty_forward_handle.abort();
         pty_event_handle.abort();
+        snapshot_handle.abort();
         tunnel_handle.abort();
+        control_handle.abort();
+        backend_handle.abort();
 
         info!("Background tasks shut down");
     });
@@ -724,71 +2752,104 @@ fn run_background_tasks(
 ///
 /// This runs in a spawn_blocking task because std::sync::mpsc::recv() is blocking.
 /// Converts RelayEvent from the relay module into UiEvent for the main thread.
-/// Also forwards terminal data from relay to PTY manager (browser -> shell).
+/// Terminal data from the relay is handed to the router (browser -> shell).
 fn forward_relay_events(
-    rx: mpsc::Receiver<RelayEvent>,
+    rx: &mpsc::Receiver<RelayEvent>,
     ui_tx: mpsc::Sender<UiEvent>,
-    pty_cmd_tx: tokio::sync::mpsc::UnboundedSender<PtyCommand>,
-    relay_cmd_tx: tokio::sync::mpsc::UnboundedSender<RelayCommand>,
-    session_list: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    router: Router,
+    relay_status: SharedRelayStatus,
+    allow_remote_create: bool,
 ) {
     debug!("Relay event forwarder starting");
     loop {
         match rx.recv() {
             Ok(event) => {
                 let ui_event = match event {
-                    RelayEvent::Connected => UiEvent::RelayConnected,
-                    RelayEvent::Disconnected => UiEvent::RelayDisconnected,
-                    RelayEvent::SessionCode(code) => UiEvent::SessionCode(code),
+                    RelayEvent::Connected => {
+                        relay_status.lock().unwrap().connected = true;
+                        UiEvent::RelayConnected
+                    }
+                    RelayEvent::Disconnected => {
+                        {
+                            let mut status = relay_status.lock().unwrap();
+                            status.connected = false;
+                            status.session_code = None;
+                            status.browsers = 0;
+                        }
+                        UiEvent::RelayDisconnected
+                    }
+                    RelayEvent::SessionCode(code) => {
+                        relay_status.lock().unwrap().session_code = Some(code.clone());
+                        router.announce_sharing();
+                        UiEvent::SessionCode(code)
+                    }
                     RelayEvent::BrowserConnected(id) => {
+                        relay_status.lock().unwrap().browsers += 1;
                         // Send session list to newly connected browser
-                        let sessions = session_list.lock().unwrap().clone();
-                        info!("Browser connected, sending {} sessions", sessions.len());
-                        let _ = relay_cmd_tx.send(RelayCommand::SendSessionList { sessions });
+                        router.announce_e2e();
+                        router.send_session_list();
                         UiEvent::BrowserConnected(id)
                     }
-                    RelayEvent::BrowserDisconnected(id) => UiEvent::BrowserDisconnected(id),
+                    RelayEvent::BrowserDisconnected(id) => {
+                        router.browser_left(&id);
+                        {
+                            let mut status = relay_status.lock().unwrap();
+                            status.browsers = status.browsers.saturating_sub(1);
+                        }
+                        UiEvent::BrowserDisconnected(id)
+                    }
+                    RelayEvent::ApprovalRequest { browser_id, browser_key, user_agent } => {
+                        let pending = PendingApproval::new(browser_id, browser_key, user_agent.as_deref());
+                        if pending.browser_key.as_deref().is_some_and(approval::is_always_allowed) {
+                            info!("Letting in always-allowed browser {} ({})", pending.browser_id, pending.label);
+                            router.answer_approval(pending.browser_id, true);
+                            continue;
+                        }
+                        UiEvent::ApprovalRequest(pending)
+                    }
+                    RelayEvent::ApprovalCancelled(id) => UiEvent::ApprovalCancelled(id),
+                    RelayEvent::Presence(viewers) => {
+                        relay_status.lock().unwrap().browsers = viewers.len();
+                        UiEvent::Presence(viewers)
+                    }
+                    // Older relays grant what the web UI asks for, which
+                    // the link's URL keeps to what was asked
+                    RelayEvent::JoinToken { token, expires_in_secs, role } => UiEvent::JoinToken {
+                        token,
+                        expires_in_secs,
+                        role: role.unwrap_or_default(),
+                    },
+                    RelayEvent::Chat { name, text } => UiEvent::Chat { name, text },
                     RelayEvent::Error(msg) => UiEvent::RelayError(msg),
+                    RelayEvent::Unreachable(msg) => UiEvent::Alert(Alert::critical(msg)),
+                    RelayEvent::Latency(stats) => UiEvent::RelayLatency(stats),
                     RelayEvent::TerminalData { session_id, data } => {
                         // Forward to PTY manager (browser -> shell)
-                        let _ = pty_cmd_tx.send(PtyCommand::Write {
+                        router.route_inbound(InboundFrame::Input {
                             session_id: session_id.clone(),
                             data: data.clone(),
                         });
                         UiEvent::TerminalDataFromRelay { session_id, data }
                     }
-                    RelayEvent::CloseSession { session_id } => {
-                        // Kill the pty-proxy session
-                        info!("Closing session: {}", session_id);
-                        let _ = pty_cmd_tx.send(PtyCommand::KillSession {
-                            session_id: session_id.clone(),
-                        });
-                        // No UI event - session will emit Detached event
+                    RelayEvent::E2eHello { public_key } => {
+                        router.handle_e2e_hello(public_key);
+                        continue;
+                    }
+                    RelayEvent::SessionCommand(cmd) => {
+                        // Replies and resulting session events go back via the router
+                        router.dispatch(cmd);
+                        continue;
+                    }
+                    RelayEvent::CreateSession if !allow_remote_create => {
+                        warn!("Ignoring create_session from browser: disabled in preferences");
                         continue;
                     }
                     RelayEvent::CreateSession => {
                         info!("Creating new terminal session");
-                        match std::process::Command::new("osascript")
-                            .arg("-e")
-                            .arg(r#"tell application "Terminal" to do script ""
-"#)
-                            .output()
-                        {
-                            Ok(output) => {
-                                if output.status.success() {
-                                    info!("New terminal window created");
-                                } else {
-                                    error!(
-                                        "osascript create failed ({}): {}",
-                                        output.status,
-                                        String::from_utf8_lossy(&output.stderr)
-                                    );
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to run osascript for create: {}", e);
-                            }
-                        }
+                        applescript::queue(
+                            "create",
+                            r#"tell application "Terminal" to do script """#.to_string(),
+                        );
                         continue;
                     }
                 };
@@ -824,7 +2885,11 @@ fn find_cloudflared() -> String {
     "cloudflared".to_string()
 }
 
-fn run_cloudflared_tunnel(ui_tx: mpsc::Sender<UiEvent>, pid_store: Arc<AtomicU32>) -> Option<Child> {
+fn run_cloudflared_tunnel(
+    ui_tx: mpsc::Sender<UiEvent>,
+    pid_store: Arc<AtomicU32>,
+    relay_status: SharedRelayStatus,
+) -> Option<Child> {
     let cloudflared = find_cloudflared();
     info!("Using cloudflared at: {}", cloudflared);
 
@@ -855,6 +2920,7 @@ fn run_cloudflared_tunnel(ui_tx: mpsc::Sender<UiEvent>, pid_store: Arc<AtomicU32
                 // Look for the tunnel URL in cloudflared output
                 if let Some(url) = extract_tunnel_url(&line) {
                     info!("Tunnel URL found: {}", url);
+                    relay_status.lock().unwrap().tunnel_url = Some(url.clone());
                     let _ = ui_tx.send(UiEvent::TunnelUrl(url));
                 } else {
                     debug!("cloudflared: {}", line);
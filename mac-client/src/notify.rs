@@ -0,0 +1,43 @@
+//! macOS user notifications via `osascript`.
+
+use crate::applescript;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::info;
+
+/// Set while Do Not Disturb is on.
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// Hold back notifications (they are only logged) until unmuted.
+pub fn set_muted(muted: bool) {
+    MUTED.store(muted, Ordering::Relaxed);
+}
+
+/// Quote a string as an AppleScript string literal.
+pub fn applescript_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Post a notification to Notification Center without blocking the caller.
+pub fn notify(title: &str, message: &str) {
+    if MUTED.load(Ordering::Relaxed) {
+        info!("Notification suppressed (Do Not Disturb): {}", message);
+        return;
+    }
+    let script = format!(
+        "display notification {} with title {}",
+        applescript_quote(message),
+        applescript_quote(title)
+    );
+    applescript::queue("notification", script);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applescript_quote_escapes() {
+        assert_eq!(applescript_quote("plain"), "\"plain\"");
+        assert_eq!(applescript_quote(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+    }
+}
@@ -0,0 +1,232 @@
+//! pty-proxy processes that are running but not connected.
+//!
+//! After a mac-client restart, a proxy that cannot reach the socket (an old
+//! build looking in the wrong place, a mismatched instance, a socket file
+//! that was removed) keeps its shell running but never shows up in the
+//! Sessions submenu. The process list is scanned every [`SCAN_INTERVAL`]
+//! for pty-proxy processes of this instance whose shell is not connected;
+//! ones missed by two scans in a row are listed under "Disconnected
+//! Sessions", where they can be nudged to reconnect right away, left to
+//! retry on their own, or force quit.
+//!
+//! A proxy belongs to this instance if its `TERMINAL_REMOTE_INSTANCE`
+//! matches (read from `ps -E`). Proxies pointed elsewhere with
+//! `TERMINAL_REMOTE_SOCKET` are left alone.
+
+use std::collections::HashSet;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+/// Time between scans. Longer than pty-proxy's reconnect interval (5s), so
+/// a proxy reconnecting after a restart is never reported.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(15);
+
+const PROXY_NAME: &str = "pty-proxy";
+const INSTANCE_ENV: &str = "TERMINAL_REMOTE_INSTANCE=";
+const SOCKET_ENV: &str = "TERMINAL_REMOTE_SOCKET=";
+
+/// One line of `ps` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: u32,
+    pub tty: String,
+    /// Command line followed by the environment
+    pub command: String,
+}
+
+impl Process {
+    /// File name of the executable.
+    fn name(&self) -> &str {
+        let program = self.command.split_whitespace().next().unwrap_or("");
+        program.rsplit('/').next().unwrap_or(program).trim_start_matches('-')
+    }
+
+    fn env<'a>(&'a self, prefix: &str) -> Option<&'a str> {
+        self.command
+            .split_whitespace()
+            .find_map(|word| word.strip_prefix(prefix))
+    }
+}
+
+/// A pty-proxy whose shell is not connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanProxy {
+    pub pid: u32,
+    pub tty: String,
+    /// Name and pid of the shell it runs
+    pub shell: Option<(String, u32)>,
+}
+
+impl OrphanProxy {
+    /// Menu title, e.g. "ttys003: zsh (pty-proxy 812)".
+    pub fn label(&self) -> String {
+        let tty = if self.tty.starts_with("tty") || self.tty == "??" {
+            self.tty.clone()
+        } else {
+            format!("tty{}", self.tty)
+        };
+        match &self.shell {
+            Some((name, _)) => format!("{}: {} (pty-proxy {})", tty, name, self.pid),
+            None => format!("{}: pty-proxy {}", tty, self.pid),
+        }
+    }
+}
+
+/// Parse `ps -o pid=,ppid=,tty=,command=` output.
+pub fn parse_ps(output: &str) -> Vec<Process> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid = fields.next()?.parse().ok()?;
+            let tty = fields.next()?.to_string();
+            let command = fields.collect::<Vec<_>>().join(" ");
+            Some(Process {
+                pid,
+                ppid,
+                tty,
+                command,
+            })
+        })
+        .collect()
+}
+
+/// Proxies of `instance` whose shell pid is not in `connected_shells`.
+pub fn find_orphans(
+    processes: &[Process],
+    instance: Option<&str>,
+    connected_shells: &HashSet<u32>,
+) -> Vec<OrphanProxy> {
+    processes
+        .iter()
+        .filter(|p| p.name() == PROXY_NAME)
+        .filter(|p| p.env(SOCKET_ENV).is_none())
+        .filter(|p| p.env(INSTANCE_ENV).filter(|s| !s.is_empty()) == instance)
+        .filter_map(|proxy| {
+            let shell = processes.iter().find(|c| c.ppid == proxy.pid);
+            if shell.is_some_and(|s| connected_shells.contains(&s.pid)) {
+                return None;
+            }
+            Some(OrphanProxy {
+                pid: proxy.pid,
+                tty: proxy.tty.clone(),
+                shell: shell.map(|s| (s.name().to_string(), s.pid)),
+            })
+        })
+        .collect()
+}
+
+/// List this user's processes, with their environment.
+pub fn list_processes() -> io::Result<Vec<Process>> {
+    let output = Command::new("ps")
+        .args(["-xwwE", "-o", "pid=,ppid=,tty=,command="])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ps failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_ps(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Reports a proxy only once two scans in a row found it disconnected.
+#[derive(Debug, Default)]
+pub struct OrphanTracker {
+    seen: HashSet<u32>,
+    reported: Vec<OrphanProxy>,
+}
+
+impl OrphanTracker {
+    /// Feed one scan; returns the new list if it changed.
+    pub fn update(&mut self, found: Vec<OrphanProxy>) -> Option<Vec<OrphanProxy>> {
+        let seen = std::mem::replace(&mut self.seen, found.iter().map(|o| o.pid).collect());
+        let confirmed: Vec<OrphanProxy> = found.into_iter().filter(|o| seen.contains(&o.pid)).collect();
+        if confirmed == self.reported {
+            return None;
+        }
+        self.reported = confirmed.clone();
+        Some(confirmed)
+    }
+}
+
+/// Make a proxy retry its connection now instead of at its next attempt.
+///
+/// pty-proxy re-reads the terminal size on SIGWINCH and, since it learned
+/// to, reconnects if it is disconnected. Older builds only re-read the
+/// size, so the signal is harmless to them (unlike SIGUSR1, which would
+/// end them).
+pub fn nudge(pid: u32) -> io::Result<()> {
+    signal(pid, libc::SIGWINCH)
+}
+
+/// Kill a proxy, which also ends its shell.
+pub fn force_quit(pid: u32) -> io::Result<()> {
+    signal(pid, libc::SIGKILL)
+}
+
+fn signal(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    if pid == 0 || pid > i32::MAX as u32 {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    if unsafe { libc::kill(pid as i32, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PS: &str = "\
+  1     0 ??       /sbin/launchd
+ 810   805 s001     /usr/local/bin/pty-proxy HOME=/Users/me TERM=xterm-256color
+ 811   810 s001     -zsh HOME=/Users/me
+ 812   805 s003     /usr/local/bin/pty-proxy HOME=/Users/me TERMINAL_REMOTE_INSTANCE=
+ 813   812 s003     -zsh HOME=/Users/me
+ 820   805 s004     pty-proxy HOME=/Users/me TERMINAL_REMOTE_INSTANCE=work
+ 821   820 s004     /bin/bash HOME=/Users/me
+ 830   805 s005     pty-proxy TERMINAL_REMOTE_SOCKET=/tmp/other.sock
+ 840   805 s006     vim pty-proxy.rs
+";
+
+    #[test]
+    fn test_finds_disconnected_proxies_of_this_instance() {
+        let processes = parse_ps(PS);
+        assert_eq!(processes.len(), 9);
+        let connected: HashSet<u32> = [811].into();
+
+        let orphans = find_orphans(&processes, None, &connected);
+        assert_eq!(
+            orphans,
+            vec![OrphanProxy {
+                pid: 812,
+                tty: "s003".into(),
+                shell: Some(("zsh".into(), 813)),
+            }]
+        );
+        assert_eq!(orphans[0].label(), "ttys003: zsh (pty-proxy 812)");
+
+        let work = find_orphans(&processes, Some("work"), &connected);
+        assert_eq!(work.iter().map(|o| o.pid).collect::<Vec<_>>(), vec![820]);
+    }
+
+    #[test]
+    fn test_tracker_waits_for_a_second_scan() {
+        let orphan = |pid| OrphanProxy {
+            pid,
+            tty: "s001".into(),
+            shell: None,
+        };
+        let mut tracker = OrphanTracker::default();
+        assert_eq!(tracker.update(vec![orphan(1), orphan(2)]), None);
+        // 1 reconnected in between
+        assert_eq!(tracker.update(vec![orphan(2)]), Some(vec![orphan(2)]));
+        assert_eq!(tracker.update(vec![orphan(2)]), None);
+        assert_eq!(tracker.update(Vec::new()), Some(Vec::new()));
+    }
+}
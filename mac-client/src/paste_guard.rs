@@ -0,0 +1,111 @@
+//! Large-paste guard (`confirm_paste` in `[security]`).
+//!
+//! Browser input that is larger than `confirm_paste_bytes` or has more than
+//! `confirm_paste_lines` line breaks is not written to the shell right away.
+//! The router holds it and the user is asked under "Held Pastes" in the menu
+//! bar whether to paste or discard it, so a mistaken paste or a flood of
+//! commands from a compromised browser is not executed unseen. Typing sends
+//! a few bytes per message and never trips the guard.
+
+/// Pastes held at once; the oldest is discarded beyond this.
+pub const MAX_HELD_PASTES: usize = 8;
+
+/// Longest preview shown in the menu, in characters.
+const PREVIEW_CHARS: usize = 60;
+
+/// A paste waiting for confirmation, as listed in the menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldPaste {
+    pub id: u64,
+    pub session_id: String,
+    pub session_name: String,
+    /// Size and line count, e.g. "3.1 KB, 12 lines"
+    pub summary: String,
+    /// First non-empty line, shortened
+    pub preview: String,
+}
+
+impl HeldPaste {
+    pub fn new(id: u64, session_id: String, session_name: String, data: &[u8]) -> Self {
+        Self {
+            id,
+            session_id,
+            session_name,
+            summary: summary(data),
+            preview: preview(data),
+        }
+    }
+}
+
+/// Number of line breaks (CR, LF or CRLF) in `data`.
+pub fn line_breaks(data: &[u8]) -> usize {
+    let mut count = 0;
+    let mut prev = 0u8;
+    for &b in data {
+        if b == b'\r' || (b == b'\n' && prev != b'\r') {
+            count += 1;
+        }
+        prev = b;
+    }
+    count
+}
+
+/// Whether input must be confirmed before it reaches the shell.
+pub fn needs_confirmation(data: &[u8], max_bytes: usize, max_lines: usize) -> bool {
+    data.len() > max_bytes || line_breaks(data) > max_lines
+}
+
+fn summary(data: &[u8]) -> String {
+    let size = if data.len() < 1024 {
+        format!("{} bytes", data.len())
+    } else {
+        format!("{:.1} KB", data.len() as f64 / 1024.0)
+    };
+    match line_breaks(data) {
+        0 => size,
+        1 => format!("{}, 1 line break", size),
+        n => format!("{}, {} line breaks", size, n),
+    }
+}
+
+fn preview(data: &[u8]) -> String {
+    let text = String::from_utf8_lossy(data);
+    let line = text
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+    let printable: String = line.chars().filter(|c| !c.is_control()).collect();
+    if printable.chars().count() > PREVIEW_CHARS {
+        let short: String = printable.chars().take(PREVIEW_CHARS).collect();
+        format!("{}…", short)
+    } else {
+        printable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_confirmation() {
+        assert!(!needs_confirmation(b"ls -la\r", 2048, 5));
+        assert!(needs_confirmation(&[b'a'; 2049], 2048, 5));
+        assert!(!needs_confirmation(b"a\r\nb\r\nc\r\nd\r\ne\r\n", 2048, 5));
+        assert!(needs_confirmation(b"a\nb\nc\nd\ne\nf\n", 2048, 5));
+        assert_eq!(line_breaks(b"a\r\nb\rc\n\n"), 4);
+    }
+
+    #[test]
+    fn test_summary_and_preview() {
+        let held = HeldPaste::new(1, "s1".into(), "zsh".into(), b"\n  rm -rf build\x1b\nmake\n");
+        assert_eq!(held.summary, "22 bytes, 3 line breaks");
+        assert_eq!(held.preview, "rm -rf build");
+
+        let long = "x".repeat(3000);
+        let held = HeldPaste::new(2, "s1".into(), "zsh".into(), long.as_bytes());
+        assert_eq!(held.summary, "2.9 KB");
+        assert_eq!(held.preview.chars().count(), PREVIEW_CHARS + 1);
+    }
+}
@@ -0,0 +1,349 @@
+//! Per-user, per-instance locations of sockets, config, logs and recordings.
+//!
+//! Everything lives where a sandboxed, notarized app may write:
+//!
+//! | | location |
+//! |---|---|
+//! | sockets | `~/Library/Group Containers/group.com.terminal-remote/` (mode 0700) |
+//! | config, history, keys | `~/Library/Application Support/Terminal Remote/` |
+//! | log file | `~/Library/Logs/Terminal Remote/` |
+//! | recordings, transcripts | `~/Library/Application Support/Terminal Remote/Recordings/` |
+//!
+//! The sockets are in the app group container so pty-proxy, which runs in
+//! the user's shells outside the sandbox, can reach them. Their directory is
+//! found from the user's real home directory (a sandboxed app's `HOME`
+//! points into its own container); everything else follows `HOME`, so it
+//! lands in the container when sandboxed.
+//!
+//! A user can run more than one instance by giving each a name with
+//! `--instance <name>` or `TERMINAL_REMOTE_INSTANCE`:
+//!
+//! | | default instance | instance `work` |
+//! |---|---|---|
+//! | pty-proxy socket | `pty.sock` | `pty-work.sock` |
+//! | control socket | `control.sock` | `control-work.sock` |
+//! | config and history | `Terminal Remote/` | `Terminal Remote/instances/work/` |
+//!
+//! Older releases used `/tmp/terminal-remote-<uid>/` for sockets and
+//! `~/.terminal-remote/` for config. Config files are moved over on first
+//! start ([`migrate_legacy_config`]), and the sockets are also linked from
+//! the old directory when it can be written, so pty-proxy builds and shell
+//! integration scripts from before the move keep connecting. pty-proxy
+//! tries the group container first, then the old directory.
+
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Environment variable naming the instance.
+pub const INSTANCE_ENV: &str = "TERMINAL_REMOTE_INSTANCE";
+
+/// App group shared with pty-proxy (`com.apple.security.application-groups`).
+pub const APP_GROUP: &str = "group.com.terminal-remote";
+
+/// Folder name under Application Support and Logs.
+const APP_FOLDER: &str = "Terminal Remote";
+
+/// Longest accepted instance name (socket paths are limited to 104 bytes).
+const MAX_INSTANCE_LEN: usize = 32;
+
+/// Longest Unix socket path macOS accepts (`sun_path` minus the NUL).
+const MAX_SOCKET_PATH: usize = 103;
+
+/// Files moved from the legacy config directory.
+const MIGRATED_FILES: &[&str] = &[
+    "config.toml",
+    "recent.json",
+    "sessions.json",
+    "e2e_key",
+    "client_id",
+    "allowed_browsers",
+];
+
+/// Check that an instance name is safe to use in file names.
+pub fn validate_instance(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_INSTANCE_LEN {
+        return Err(format!(
+            "instance name must be 1-{} characters",
+            MAX_INSTANCE_LEN
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "instance name \"{}\" may only contain letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The instance name from the environment, if set and non-empty.
+///
+/// `main` validates the name at startup, so later callers can rely on it.
+pub fn instance() -> Option<String> {
+    std::env::var(INSTANCE_ENV).ok().filter(|s| !s.is_empty())
+}
+
+/// The current user's home directory from the user database, which is not
+/// redirected into the container when the app is sandboxed.
+pub fn user_home() -> Option<PathBuf> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let rc = unsafe {
+        libc::getpwuid_r(libc::getuid(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 || result.is_null() || pwd.pw_dir.is_null() {
+        return std::env::var_os("HOME").map(PathBuf::from);
+    }
+    let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+    Some(PathBuf::from(dir.to_string_lossy().into_owned()))
+}
+
+/// `HOME`: the real home directory, or the app container when sandboxed.
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+/// The app group container under `home`, holding the sockets.
+pub fn group_container_in(home: &Path) -> PathBuf {
+    home.join("Library/Group Containers").join(APP_GROUP)
+}
+
+/// Socket directory of releases before the group container.
+pub fn legacy_runtime_dir_for(uid: u32) -> PathBuf {
+    PathBuf::from(format!("/tmp/terminal-remote-{}", uid))
+}
+
+fn legacy_runtime_dir() -> PathBuf {
+    legacy_runtime_dir_for(unsafe { libc::getuid() })
+}
+
+/// Socket directory for a user with `home`: the group container, unless
+/// the longest socket path would not fit there (very long user or instance
+/// names), in which case the legacy directory is used.
+fn runtime_dir_in(home: Option<&Path>, uid: u32, instance: Option<&str>) -> PathBuf {
+    match home.map(group_container_in) {
+        Some(dir) if socket_fits(&dir, instance) => dir,
+        _ => legacy_runtime_dir_for(uid),
+    }
+}
+
+fn socket_fits(dir: &Path, instance: Option<&str>) -> bool {
+    let longest = dir.join(socket_file_name("control", instance));
+    longest.as_os_str().len() <= MAX_SOCKET_PATH
+}
+
+/// Private runtime directory for the current user's sockets.
+pub fn runtime_dir() -> PathBuf {
+    runtime_dir_in(
+        user_home().as_deref(),
+        unsafe { libc::getuid() },
+        instance().as_deref(),
+    )
+}
+
+/// File name of a socket, suffixed with the instance name if there is one.
+pub fn socket_file_name(kind: &str, instance: Option<&str>) -> String {
+    match instance {
+        Some(name) => format!("{}-{}.sock", kind, name),
+        None => format!("{}.sock", kind),
+    }
+}
+
+/// Socket pty-proxy instances connect to.
+pub fn pty_socket() -> PathBuf {
+    runtime_dir().join(socket_file_name("pty", instance().as_deref()))
+}
+
+/// Socket serving control requests.
+pub fn control_socket() -> PathBuf {
+    runtime_dir().join(socket_file_name("control", instance().as_deref()))
+}
+
+/// Where older pty-proxy builds look for the socket `socket` (a path from
+/// [`pty_socket`] or [`control_socket`]); None if that is already it.
+pub fn legacy_socket(socket: &Path) -> Option<PathBuf> {
+    let legacy = legacy_runtime_dir().join(socket.file_name()?);
+    (legacy != socket).then_some(legacy)
+}
+
+/// Application Support folder of the app (all instances).
+fn app_support_dir() -> Option<PathBuf> {
+    Some(home()?.join("Library/Application Support").join(APP_FOLDER))
+}
+
+/// Directory holding the config file, session history and keys.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = app_support_dir()?;
+    Some(match instance() {
+        Some(name) => dir.join("instances").join(name),
+        None => dir,
+    })
+}
+
+/// Config directory of releases before Application Support.
+fn legacy_config_dir() -> Option<PathBuf> {
+    let dir = home()?.join(".terminal-remote");
+    Some(match instance() {
+        Some(name) => dir.join("instances").join(name),
+        None => dir,
+    })
+}
+
+/// Directory for the log file.
+pub fn logs_dir() -> Option<PathBuf> {
+    Some(home()?.join("Library/Logs").join(APP_FOLDER))
+}
+
+/// Log file of this instance, e.g. `mac-client-work.log`.
+pub fn log_file() -> Option<PathBuf> {
+    let name = match instance() {
+        Some(name) => format!("mac-client-{}.log", name),
+        None => "mac-client.log".to_string(),
+    };
+    Some(logs_dir()?.join(name))
+}
+
+/// Recordings and exported transcripts when `recording_dir` is not set.
+pub fn default_recording_dir() -> Option<PathBuf> {
+    Some(app_support_dir()?.join("Recordings"))
+}
+
+/// Move config files from `~/.terminal-remote/` to [`config_dir`] if the
+/// new directory has none yet. Returns the names of the files moved.
+pub fn migrate_legacy_config() -> io::Result<Vec<&'static str>> {
+    let (Some(legacy), Some(dir)) = (legacy_config_dir(), config_dir()) else {
+        return Ok(Vec::new());
+    };
+    let moved = migrate_files(&legacy, &dir)?;
+    if !moved.is_empty() {
+        info!(
+            "Moved {} from {} to {}",
+            moved.join(", "),
+            legacy.display(),
+            dir.display()
+        );
+    }
+    Ok(moved)
+}
+
+fn migrate_files(from: &Path, to: &Path) -> io::Result<Vec<&'static str>> {
+    if MIGRATED_FILES.iter().any(|name| to.join(name).exists()) {
+        return Ok(Vec::new());
+    }
+    let present: Vec<&'static str> = MIGRATED_FILES
+        .iter()
+        .copied()
+        .filter(|name| from.join(name).is_file())
+        .collect();
+    if present.is_empty() {
+        return Ok(Vec::new());
+    }
+    std::fs::create_dir_all(to)?;
+    for name in &present {
+        let (src, dst) = (from.join(name), to.join(name));
+        if std::fs::rename(&src, &dst).is_err() {
+            // Different volume: copy, keeping permissions (e2e_key is 0600)
+            std::fs::copy(&src, &dst)?;
+            std::fs::remove_file(&src)?;
+        }
+    }
+    Ok(present)
+}
+
+/// Create `dir` (mode 0700) if needed and make sure it belongs to the
+/// current user, so nobody else can plant or read sockets.
+pub fn ensure_private_dir(dir: &Path) -> io::Result<()> {
+    match std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+
+    let meta = std::fs::symlink_metadata(dir)?;
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a directory owned by uid {}", dir.display(), uid),
+        ));
+    }
+    if meta.permissions().mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Create the runtime directory (see [`ensure_private_dir`]).
+pub fn ensure_runtime_dir() -> io::Result<PathBuf> {
+    let dir = runtime_dir();
+    ensure_private_dir(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_names() {
+        assert_eq!(socket_file_name("pty", None), "pty.sock");
+        assert_eq!(socket_file_name("control", Some("work")), "control-work.sock");
+        let home = Path::new("/Users/me");
+        assert_eq!(
+            runtime_dir_in(Some(home), 501, None).join(socket_file_name("pty", None)),
+            PathBuf::from("/Users/me/Library/Group Containers/group.com.terminal-remote/pty.sock")
+        );
+    }
+
+    #[test]
+    fn test_long_socket_paths_fall_back_to_tmp() {
+        let home = PathBuf::from(format!("/Users/{}", "a".repeat(40)));
+        assert_eq!(
+            runtime_dir_in(Some(&home), 501, Some(&"w".repeat(MAX_INSTANCE_LEN))),
+            PathBuf::from("/tmp/terminal-remote-501")
+        );
+        assert_eq!(runtime_dir_in(None, 501, None), legacy_runtime_dir_for(501));
+    }
+
+    #[test]
+    fn test_validate_instance() {
+        assert!(validate_instance("work").is_ok());
+        assert!(validate_instance("team_2-b").is_ok());
+        assert!(validate_instance("").is_err());
+        assert!(validate_instance("../etc").is_err());
+        assert!(validate_instance(&"x".repeat(MAX_INSTANCE_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_config_is_moved_once() {
+        let root = std::env::temp_dir().join(format!("paths-migrate-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (legacy, dir) = (root.join(".terminal-remote"), root.join("Terminal Remote"));
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("config.toml"), "notifications = false\n").unwrap();
+        std::fs::write(legacy.join("client_id"), "id\n").unwrap();
+        std::fs::write(legacy.join("init.zsh"), "# sourced by shells\n").unwrap();
+
+        assert_eq!(migrate_files(&legacy, &dir).unwrap(), vec!["config.toml", "client_id"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.toml")).unwrap(),
+            "notifications = false\n"
+        );
+        assert!(!legacy.join("config.toml").exists());
+        assert!(legacy.join("init.zsh").exists());
+
+        // Never overwrites a config that is already in the new place
+        std::fs::write(legacy.join("recent.json"), "[]").unwrap();
+        assert!(migrate_files(&legacy, &dir).unwrap().is_empty());
+        assert!(legacy.join("recent.json").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! Preferences dialog opened from the tray menu.
+//!
+//! A winit + tray-icon app has no settings window of its own, so preferences
+//! are edited through standard AppleScript dialogs driven by `osascript`: a
+//! list of settings showing their current values, then a prompt for the one
+//! chosen (toggles flip in place). Closing the list with "Done" writes the
+//! config file.
+
+use crate::config::Config;
+use crate::notify::applescript_quote;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// An editable setting shown in the preferences list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    RelayUrl,
+    WebUrl,
+    DisplayName,
+    Notifications,
+    CloseWindow,
+    ScrollbackKb,
+    RecordingDir,
+    AllowRemoteCreate,
+    AllowRemoteClose,
+    RequireApproval,
+    ConfirmPaste,
+    InputRateLimit,
+    ClipboardToPasteboard,
+    ClipboardToBrowsers,
+    DirectConnections,
+    EndToEndEncryption,
+}
+
+/// Settings in display order.
+pub const SETTINGS: [Setting; 16] = [
+    Setting::RelayUrl,
+    Setting::WebUrl,
+    Setting::DisplayName,
+    Setting::Notifications,
+    Setting::CloseWindow,
+    Setting::ScrollbackKb,
+    Setting::RecordingDir,
+    Setting::AllowRemoteCreate,
+    Setting::AllowRemoteClose,
+    Setting::RequireApproval,
+    Setting::ConfirmPaste,
+    Setting::InputRateLimit,
+    Setting::ClipboardToPasteboard,
+    Setting::ClipboardToBrowsers,
+    Setting::DirectConnections,
+    Setting::EndToEndEncryption,
+];
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "On"
+    } else {
+        "Off"
+    }
+}
+
+impl Setting {
+    fn title(self) -> &'static str {
+        match self {
+            Setting::RelayUrl => "Relay URL",
+            Setting::WebUrl => "Web UI address",
+            Setting::DisplayName => "Name shown to browsers",
+            Setting::Notifications => "Notifications",
+            Setting::CloseWindow => "Close window when a browser closes a session",
+            Setting::ScrollbackKb => "Scrollback (KB)",
+            Setting::RecordingDir => "Recording directory",
+            Setting::AllowRemoteCreate => "Browsers can open new windows",
+            Setting::AllowRemoteClose => "Browsers can close sessions",
+            Setting::RequireApproval => "Approve each new browser",
+            Setting::ConfirmPaste => "Confirm large pastes from browsers",
+            Setting::InputRateLimit => "Browser input rate limit",
+            Setting::ClipboardToPasteboard => "Terminal copy to Mac clipboard",
+            Setting::ClipboardToBrowsers => "Terminal copy to browsers",
+            Setting::DirectConnections => "Direct browser connections",
+            Setting::EndToEndEncryption => "End-to-end encryption",
+        }
+    }
+
+    /// Current value as shown in the list and prefilled in text prompts.
+    pub fn value(self, config: &Config) -> String {
+        match self {
+            Setting::RelayUrl => config.relay_url(),
+            Setting::WebUrl => config
+                .web_url
+                .clone()
+                .unwrap_or_else(|| "(tunnel URL)".into()),
+            Setting::DisplayName => config
+                .display_name
+                .clone()
+                .unwrap_or_else(|| "(computer name)".into()),
+            Setting::Notifications => on_off(config.notifications).into(),
+            Setting::CloseWindow => config.close_window.label().into(),
+            Setting::ScrollbackKb => (config.scrollback_bytes / 1024).to_string(),
+            Setting::RecordingDir => config
+                .recording_dir
+                .as_ref()
+                .map(|d| d.display().to_string())
+                .unwrap_or_else(|| "(not set)".into()),
+            Setting::AllowRemoteCreate => on_off(config.security.allow_remote_create).into(),
+            Setting::AllowRemoteClose => on_off(config.security.allow_remote_close).into(),
+            Setting::RequireApproval => on_off(config.security.require_approval).into(),
+            Setting::ConfirmPaste => on_off(config.security.confirm_paste).into(),
+            Setting::InputRateLimit => on_off(config.rate_limit.enabled).into(),
+            Setting::ClipboardToPasteboard => on_off(config.clipboard.to_pasteboard).into(),
+            Setting::ClipboardToBrowsers => on_off(config.clipboard.to_browsers).into(),
+            Setting::DirectConnections => on_off(config.direct.enabled).into(),
+            Setting::EndToEndEncryption => on_off(config.end_to_end_encryption).into(),
+        }
+    }
+
+    /// List entry: "Title: value".
+    pub fn label(self, config: &Config) -> String {
+        format!("{}: {}", self.title(), self.value(config))
+    }
+
+    /// Find the setting a list entry belongs to.
+    pub fn from_label(label: &str) -> Option<Setting> {
+        SETTINGS
+            .into_iter()
+            .find(|s| label.starts_with(&format!("{}: ", s.title())))
+    }
+
+    /// Flip a boolean setting, or move a choice to its next option. Returns
+    /// false if the setting is not a toggle.
+    pub fn toggle(self, config: &mut Config) -> bool {
+        let flag = match self {
+            Setting::CloseWindow => {
+                config.close_window = config.close_window.next();
+                return true;
+            }
+            Setting::Notifications => &mut config.notifications,
+            Setting::AllowRemoteCreate => &mut config.security.allow_remote_create,
+            Setting::AllowRemoteClose => &mut config.security.allow_remote_close,
+            Setting::RequireApproval => &mut config.security.require_approval,
+            Setting::ConfirmPaste => &mut config.security.confirm_paste,
+            Setting::InputRateLimit => &mut config.rate_limit.enabled,
+            Setting::ClipboardToPasteboard => &mut config.clipboard.to_pasteboard,
+            Setting::ClipboardToBrowsers => &mut config.clipboard.to_browsers,
+            Setting::DirectConnections => &mut config.direct.enabled,
+            Setting::EndToEndEncryption => &mut config.end_to_end_encryption,
+            _ => return false,
+        };
+        *flag = !*flag;
+        true
+    }
+
+    /// Apply a value typed by the user.
+    pub fn apply_text(self, config: &mut Config, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        match self {
+            Setting::RelayUrl => {
+                if text.is_empty() {
+                    config.relay_url = None;
+                } else if text.starts_with("ws://") || text.starts_with("wss://") {
+                    config.relay_url = Some(text.to_string());
+                } else {
+                    return Err("Relay URL must start with ws:// or wss://".into());
+                }
+            }
+            Setting::WebUrl => {
+                if text.is_empty() {
+                    config.web_url = None;
+                } else if text.starts_with("http://") || text.starts_with("https://") {
+                    config.web_url = Some(text.trim_end_matches('/').to_string());
+                } else {
+                    return Err("Web UI address must start with http:// or https://".into());
+                }
+            }
+            Setting::DisplayName => {
+                config.display_name = (!text.is_empty()).then(|| text.to_string());
+            }
+            Setting::ScrollbackKb => {
+                let kb: usize = text
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a whole number of kilobytes", text))?;
+                if kb == 0 {
+                    return Err("Scrollback must be at least 1 KB".into());
+                }
+                config.scrollback_bytes = kb * 1024;
+            }
+            Setting::RecordingDir => {
+                config.recording_dir = (!text.is_empty()).then(|| PathBuf::from(text));
+            }
+            _ => return Err(format!("{} is not a text setting", self.title())),
+        }
+        Ok(())
+    }
+}
+
+/// Whether going from `old` to `new` changes anything that is only read at startup.
+pub fn needs_restart(old: &Config, new: &Config) -> bool {
+    let mut old = old.clone();
+    old.notifications = new.notifications;
+    old.web_url = new.web_url.clone();
+    old.clipboard.to_pasteboard = new.clipboard.to_pasteboard;
+    old != *new
+}
+
+/// Run an AppleScript and return its trimmed output, or None if the user
+/// cancelled or osascript failed.
+fn osascript(script: &str) -> Option<String> {
+    match std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(script)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to run osascript for preferences: {}", e);
+            None
+        }
+    }
+}
+
+fn show_error(message: &str) {
+    let _ = osascript(&format!(
+        "display alert \"Terminal Remote\" message {} as warning",
+        applescript_quote(message)
+    ));
+}
+
+/// Prompt for a new value of a text or folder setting.
+fn prompt(setting: Setting, config: &Config) -> Option<String> {
+    if setting == Setting::RecordingDir {
+        return osascript(&format!(
+            "POSIX path of (choose folder with prompt {})",
+            applescript_quote("Choose a directory for session recordings:")
+        ));
+    }
+    let current = match setting {
+        Setting::RelayUrl => config.relay_url.clone().unwrap_or_default(),
+        Setting::WebUrl => config.web_url.clone().unwrap_or_default(),
+        Setting::DisplayName => config.display_name.clone().unwrap_or_default(),
+        _ => setting.value(config),
+    };
+    osascript(&format!(
+        "text returned of (display dialog {} default answer {} with title \"Terminal Remote Preferences\")",
+        applescript_quote(&format!("{}:", setting.title())),
+        applescript_quote(&current)
+    ))
+}
+
+/// Show the preferences dialogs (blocking). Returns the edited config if the
+/// user changed anything; the caller is responsible for saving it.
+pub fn run(config: &Config) -> Option<Config> {
+    let mut edited = config.clone();
+
+    loop {
+        let items = SETTINGS
+            .iter()
+            .map(|s| applescript_quote(&s.label(&edited)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let script = format!(
+            "choose from list {{{}}} with title \"Terminal Remote Preferences\" \
+             with prompt \"Select a setting to change:\" \
+             OK button name \"Edit\" cancel button name \"Done\"",
+            items
+        );
+        let Some(choice) = osascript(&script) else {
+            break;
+        };
+        // "false" means the list was dismissed with Done
+        let Some(setting) = Setting::from_label(&choice) else {
+            break;
+        };
+
+        if setting.toggle(&mut edited) {
+            continue;
+        }
+        if let Some(text) = prompt(setting, &edited) {
+            if let Err(message) = setting.apply_text(&mut edited, &text) {
+                show_error(&message);
+            }
+        }
+    }
+
+    if edited == *config {
+        info!("Preferences closed without changes");
+        None
+    } else {
+        Some(edited)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_roundtrip_to_settings() {
+        let config = Config::default();
+        for setting in SETTINGS {
+            assert_eq!(Setting::from_label(&setting.label(&config)), Some(setting));
+        }
+        assert_eq!(Setting::from_label("false"), None);
+    }
+
+    #[test]
+    fn test_toggle_only_boolean_settings() {
+        let mut config = Config::default();
+        assert!(Setting::AllowRemoteClose.toggle(&mut config));
+        assert!(!config.security.allow_remote_close);
+        assert!(!Setting::RelayUrl.toggle(&mut config));
+
+        assert!(Setting::CloseWindow.toggle(&mut config));
+        assert_eq!(Setting::CloseWindow.value(&config), "iTerm");
+        Setting::CloseWindow.toggle(&mut config);
+        assert_eq!(Setting::CloseWindow.value(&config), "Off");
+    }
+
+    #[test]
+    fn test_apply_text_validates() {
+        let mut config = Config::default();
+        assert!(Setting::RelayUrl.apply_text(&mut config, "http://nope").is_err());
+        Setting::RelayUrl
+            .apply_text(&mut config, " wss://relay.example.com/ws ")
+            .unwrap();
+        assert_eq!(config.relay_url.as_deref(), Some("wss://relay.example.com/ws"));
+
+        assert!(Setting::ScrollbackKb.apply_text(&mut config, "lots").is_err());
+        Setting::ScrollbackKb.apply_text(&mut config, "2048").unwrap();
+        assert_eq!(config.scrollback_bytes, 2 * 1024 * 1024);
+
+        assert!(Setting::WebUrl.apply_text(&mut config, "terminal.example.com").is_err());
+        Setting::WebUrl
+            .apply_text(&mut config, "https://terminal.example.com/")
+            .unwrap();
+        assert_eq!(config.web_url.as_deref(), Some("https://terminal.example.com"));
+        Setting::WebUrl.apply_text(&mut config, "").unwrap();
+        assert_eq!(config.web_url, None);
+    }
+
+    #[test]
+    fn test_notifications_apply_without_restart() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.notifications = false;
+        new.web_url = Some("https://terminal.example.com".into());
+        assert!(!needs_restart(&old, &new));
+        new.scrollback_bytes *= 2;
+        assert!(needs_restart(&old, &new));
+    }
+}
@@ -0,0 +1,66 @@
+//! Project detection for grouping sessions.
+//!
+//! A session's project is derived from its working directory: the name of
+//! the enclosing git repository if there is one, otherwise the top-level
+//! directory it is in (the first directory below `$HOME`, or below `/`
+//! outside the home directory). Sessions with the same project are grouped
+//! in the tray menu and the browser's tab list, and can be paused or shared
+//! with browsers as a group.
+
+use std::path::{Component, Path};
+
+/// Project of a shell in `cwd`, checking the filesystem for `.git`.
+pub fn detect(cwd: &str) -> String {
+    let home = std::env::var("HOME").ok();
+    detect_with(Path::new(cwd), home.as_deref().map(Path::new), |dir| {
+        dir.join(".git").exists()
+    })
+}
+
+/// Project of a shell in `cwd`, with `is_repo` telling whether a directory
+/// is the root of a git repository (a `.git` directory or worktree file).
+pub fn detect_with(cwd: &Path, home: Option<&Path>, is_repo: impl Fn(&Path) -> bool) -> String {
+    if let Some(root) = cwd.ancestors().find(|dir| is_repo(dir)) {
+        if let Some(name) = root.file_name() {
+            return name.to_string_lossy().into_owned();
+        }
+    }
+
+    let (base, rest) = match home.and_then(|home| cwd.strip_prefix(home).ok()) {
+        Some(rest) => ("~", rest),
+        None => ("/", cwd.strip_prefix("/").unwrap_or(cwd)),
+    };
+    match rest.components().find_map(|c| match c {
+        Component::Normal(name) => Some(name),
+        _ => None,
+    }) {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => base.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_in(cwd: &str, repos: &[&str]) -> String {
+        detect_with(Path::new(cwd), Some(Path::new("/Users/me")), |dir| {
+            repos.iter().any(|r| Path::new(r) == dir)
+        })
+    }
+
+    #[test]
+    fn test_git_repo_root_names_project() {
+        let repos = ["/Users/me/src/ignis"];
+        assert_eq!(detect_in("/Users/me/src/ignis", &repos), "ignis");
+        assert_eq!(detect_in("/Users/me/src/ignis/mac-client/src", &repos), "ignis");
+    }
+
+    #[test]
+    fn test_top_level_directory_outside_repos() {
+        assert_eq!(detect_in("/Users/me/Downloads/tmp", &[]), "Downloads");
+        assert_eq!(detect_in("/Users/me", &[]), "~");
+        assert_eq!(detect_in("/var/log", &[]), "var");
+        assert_eq!(detect_in("/", &[]), "/");
+    }
+}
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
 
+/// Newest protocol version this client speaks; sent in `register`, the
+/// relay answers with the version to use
+pub const PROTOCOL_VERSION: u32 = 1;
+
 /// Control messages sent as JSON over WebSocket Text frames.
 /// Terminal I/O is sent as Binary frames (not wrapped in ControlMessage).
 ///
@@ -8,38 +12,422 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ControlMessage {
     // Mac-client -> Relay
-    Register { client_id: String },
+    /// `client_id` is stable across restarts; `name` is shown to browsers.
+    /// With a `password`, browsers have to send it in their `auth`
+    Register {
+        client_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        /// Output is end-to-end encrypted; the relay keeps frames opaque
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        passthrough: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        /// From the last `registered`, to get the same code back
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        /// Scrollback the relay keeps per terminal, at most its own cap
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scrollback_bytes: Option<u64>,
+        /// For relays that only host sessions for Macs they gave a key
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key: Option<String>,
+        /// The relay keeps nothing of the session, in memory or on disk
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        zero_retention: bool,
+    },
+    /// Do Not Disturb: while paused the relay refuses new browsers
+    SharingPaused { paused: bool },
+    /// While required, new browsers wait for an `approve_browser` before
+    /// they receive anything
+    ApprovalRequired { required: bool },
+    /// Answer to an `approval_request`; `role` can let the browser in as
+    /// a viewer only
+    ApproveBrowser {
+        browser_id: String,
+        approved: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+    },
+    /// The most browsers joining from now on get (default: controller)
+    BrowserRole { role: Role },
+    /// Grant or revoke input for one connected browser
+    SetRole { browser_id: String, role: Role },
+    /// Mint a join link token that works for `ttl_secs` (default: ten
+    /// minutes) and lets in `uses` browsers (default: one), with at most
+    /// `role` (default: controller)
+    CreateJoinToken {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_secs: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uses: Option<u32>,
+    },
+    /// Start or stop recording the session's output on the relay
+    SetRecording { enabled: bool },
+    /// Allow or refuse compressed output for browsers joining from now on
+    SetCompression { enabled: bool },
+    /// Browsers allowed in this session at once (capped by the relay)
+    SetBrowserLimit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<usize>,
+    },
 
     // Relay -> Mac-client
-    Registered { code: String },
-    BrowserConnected { browser_id: String },
+    /// `version` is the protocol version to speak; relays from before
+    /// versioning leave it out and speak 1
+    Registered {
+        code: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        /// Send in the next `register` to reclaim this code and its browsers
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    /// Answer to `create_join_token`; relays from before scoped links
+    /// leave out `role` and `uses`
+    JoinToken {
+        token: String,
+        expires_in_secs: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uses: Option<u32>,
+    },
+    /// The relay records the session as `recording_id`, or not
+    RecordingState {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recording_id: Option<String>,
+    },
+    /// `role` is what the browser may do
+    BrowserConnected {
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+    },
     BrowserDisconnected { browser_id: String },
+    /// Everyone connected to the session, oldest first, whenever that changes
+    Presence { viewers: Vec<Viewer> },
+    /// A browser is waiting for approval; `browser_key` is the random key the
+    /// browser keeps across visits, `user_agent` its User-Agent header
+    ApprovalRequest {
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user_agent: Option<String>,
+    },
+    /// A browser waiting for approval left or timed out
+    ApprovalCancelled { browser_id: String },
 
     // Browser -> Relay (not used by mac-client but included for completeness)
-    Auth { session_code: String },
+    Auth {
+        session_code: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<FrameCompression>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nickname: Option<String>,
+    },
 
     // Relay -> Browser (not used by mac-client)
-    AuthSuccess,
-    AuthFailed { reason: String },
+    /// `client_name` is the display name of the Mac the code belongs to
+    AuthSuccess {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<FrameCompression>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        resumed: bool,
+    },
+    AuthFailed {
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<AuthFailure>,
+    },
+    /// The Mac has to approve this browser before it is let in
+    AwaitingApproval,
+    /// The Mac changed what this browser may do
+    RoleChanged { role: Role },
+    /// The relay is holding the session for the Mac to reconnect (false),
+    /// or it did (true)
+    MacStatus { connected: bool },
+    /// The browser missed output; the scrollback follows again
+    Resync,
+    /// The session keeps no output; the browser sees it from now on
+    LiveFromNow,
 
     // Browser -> Relay -> Mac-client
     CloseSession { session_id: String },
     CreateSession,
+    ListSessions,
+    RenameSession { session_id: String, name: String },
+    /// The size a browser would like; the relay fills in `browser_id` so
+    /// the Mac can weigh every browser's size
+    ResizeSession {
+        #[serde(default)]
+        browser_id: String,
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    /// Paired browser asks for the output key, with an ephemeral P-256 public key (base64)
+    E2eHello { public_key: String },
+    /// Search the scrollback of one session, or of every shared session when
+    /// `session_id` is left out. Browsers leave `browser_id` out; the relay
+    /// fills it in so the `search_results` come back to that browser only
+    Search {
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        q: String,
+    },
+
+    // Browser <-> Relay (not used by mac-client)
+    ListRecordings,
+    SetNickname {
+        #[serde(default)]
+        nickname: Option<String>,
+    },
+    RecordingList { recordings: Vec<RecordingInfo> },
+    PlaybackSpeed { speed: f64 },
+    PlaybackEnded,
 
     // Mac-client -> Relay -> Browser (session list on connect)
     SessionList { sessions: Vec<SessionInfo> },
     SessionConnected { session_id: String, name: String },
     SessionDisconnected { session_id: String },
+    SessionRenamed { session_id: String, name: String },
+    /// What changed about a session; fields left out stay as they were
+    SessionMetadata {
+        session_id: String,
+        #[serde(flatten)]
+        metadata: SessionMetadata,
+    },
+    SessionError { session_id: String, message: String },
     SessionResize { session_id: String, cols: u16, rows: u16 },
+    /// OSC 52 copy from a shell; `data` is the base64 payload as sent by the program
+    Clipboard { session_id: String, data: String },
+    /// Terminal output is end-to-end encrypted; `fingerprint` identifies the Mac's key
+    E2eRequired { fingerprint: String },
+    /// Output key wrapped for the browser whose `e2e_hello` carried `public_key`
+    E2eKey { public_key: String, key: String },
+    /// Matches of a `search`, newest first; `truncated` when there were more
+    SearchResults {
+        browser_id: String,
+        q: String,
+        matches: Vec<SearchMatch>,
+        truncated: bool,
+    },
+
+    // WebRTC signaling for direct mode: Browser <-> Relay <-> Mac-client.
+    // Browsers leave `browser_id` out; the relay fills it in on the way to
+    // the mac-client and uses it to deliver the reply to that browser only.
+    /// SDP offer for a data channel from the browser
+    RtcOffer {
+        #[serde(default)]
+        browser_id: String,
+        sdp: String,
+    },
+    /// SDP answer from the mac-client
+    RtcAnswer { browser_id: String, sdp: String },
+    /// Trickled ICE candidate, in either direction
+    RtcCandidate {
+        #[serde(default)]
+        browser_id: String,
+        candidate: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sdp_mid: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sdp_mline_index: Option<u16>,
+    },
+    /// Mac-client -> Relay: the browser's data channel opened (or closed);
+    /// while active the relay stops forwarding terminal output to it
+    RtcDirect { browser_id: String, active: bool },
+
+    // Round-trip latency: Browser -> Relay -> Mac-client -> Relay -> Browser.
+    // The mac-client answers every probe at once with an echo carrying the
+    // same `id`, `sent_at`, `browser_id` and `relay_at`.
+    LatencyProbe {
+        id: u64,
+        sent_at: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rtt_ms: Option<f64>,
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        relay_at: Option<u64>,
+    },
+    LatencyEcho {
+        id: u64,
+        sent_at: f64,
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        relay_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mac_rtt_ms: Option<f64>,
+    },
 
     // Bidirectional
     Error { message: String },
+    /// A chat line of the session. Browsers send `text` and the `name` to
+    /// show, if any; the relay fills in `browser_id` and `at` (Unix
+    /// milliseconds) and passes it to every browser and the mac-client
+    Chat {
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        text: String,
+        #[serde(default)]
+        at: u64,
+    },
+
+    /// A message of a type this client does not know, from a newer relay;
+    /// never sent
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// How the relay compresses binary frames to a browser.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameCompression {
+    Deflate,
+}
+
+/// Why a browser was not let in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailure {
+    InvalidCode,
+    PasswordRequired,
+    WrongPassword,
+    SharingPaused,
+    TooManyBrowsers,
+    SessionFull,
+    NotApproved,
+    RateLimited,
+    IpNotAllowed,
+    InvalidToken,
+    UnsupportedVersion,
+}
+
+/// What a browser may do in a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Types into shells and manages sessions
+    #[default]
+    Controller,
+    /// Only watches; the relay drops its input
+    Viewer,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct SessionInfo {
     pub id: String,
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Git repository or top-level directory the shell is in, for grouping
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Window title the program in the session set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Changes to a session's [`SessionInfo`], as `session_metadata` carries them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl From<&SessionInfo> for SessionMetadata {
+    /// Everything about a session, for browsers that may have missed it
+    fn from(info: &SessionInfo) -> Self {
+        Self {
+            name: Some(info.name.clone()),
+            shell: info.shell.clone(),
+            cwd: info.cwd.clone(),
+            project: info.project.clone(),
+            title: info.title.clone(),
+        }
+    }
+}
+
+/// A recording kept on the relay.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RecordingInfo {
+    pub id: String,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub bytes: u64,
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// A browser connected to the session, as `presence` lists it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Viewer {
+    pub browser_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    pub role: Role,
+    /// Unix time the browser joined, in seconds
+    pub joined_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+}
+
+/// A scrollback line containing a search term.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SearchMatch {
+    pub session_id: String,
+    /// Lines between this one and the end of the scrollback (0: the last line)
+    pub line: usize,
+    /// Character offset of the match in the line
+    pub column: usize,
+    /// The line as plain text, shortened around the match
+    pub snippet: String,
 }
 
 #[cfg(test)]
@@ -50,9 +438,18 @@ mod tests {
     fn test_register_serialization() {
         let msg = ControlMessage::Register {
             client_id: "test".into(),
+            name: None,
+            password: None,
+            passthrough: false,
+            version: Some(PROTOCOL_VERSION),
+            resume_token: None,
+            scrollback_bytes: None,
+            api_key: None,
+            zero_retention: false,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
+        assert!(json.contains("\"version\":1"));
         assert!(json.contains("\"client_id\":\"test\""));
     }
 
@@ -61,8 +458,10 @@ mod tests {
         let json = r#"{"type":"registered","code":"ABC123"}"#;
         let msg: ControlMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ControlMessage::Registered { code } => {
+            ControlMessage::Registered { code, version, resume_token } => {
                 assert_eq!(code, "ABC123");
+                assert_eq!(version, None);
+                assert_eq!(resume_token, None);
             }
             _ => panic!("Expected Registered message"),
         }
@@ -73,13 +472,66 @@ mod tests {
         let json = r#"{"type":"browser_connected","browser_id":"browser-uuid"}"#;
         let msg: ControlMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ControlMessage::BrowserConnected { browser_id } => {
+            ControlMessage::BrowserConnected { browser_id, role } => {
                 assert_eq!(browser_id, "browser-uuid");
+                assert_eq!(role, None);
             }
             _ => panic!("Expected BrowserConnected message"),
         }
     }
 
+    #[test]
+    fn test_approval_request_deserialization() {
+        let json = r#"{"type":"approval_request","browser_id":"b1","browser_key":"k1"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ControlMessage::ApprovalRequest { browser_id, browser_key, user_agent } => {
+                assert_eq!(browser_id, "b1");
+                assert_eq!(browser_key.as_deref(), Some("k1"));
+                assert_eq!(user_agent, None);
+            }
+            _ => panic!("Expected ApprovalRequest message"),
+        }
+
+        let msg = ControlMessage::ApproveBrowser { browser_id: "b1".into(), approved: true, role: None };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"approve_browser","browser_id":"b1","approved":true}"#);
+
+        let msg = ControlMessage::BrowserRole { role: Role::Viewer };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"browser_role","role":"viewer"}"#);
+    }
+
+    #[test]
+    fn test_resize_session_deserialization() {
+        let json = r#"{"type":"resize_session","session_id":"s1","cols":120,"rows":40}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ControlMessage::ResizeSession { session_id, cols, rows, .. } => {
+                assert_eq!(session_id, "s1");
+                assert_eq!((cols, rows), (120, 40));
+            }
+            _ => panic!("Expected ResizeSession message"),
+        }
+    }
+
+    #[test]
+    fn test_session_info_metadata_serialization() {
+        let info = SessionInfo {
+            id: "s1".into(),
+            name: "zsh".into(),
+            shell: Some("/bin/zsh".into()),
+            pid: Some(42),
+            tty: Some("/dev/ttys003".into()),
+            cwd: None,
+            project: None,
+            title: None,
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(json.contains("\"pid\":42"));
+        assert!(json.contains("\"tty\":\"/dev/ttys003\""));
+    }
+
     #[test]
     fn test_error_deserialization() {
         let json = r#"{"type":"error","message":"Something went wrong"}"#;
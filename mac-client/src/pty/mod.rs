@@ -1,26 +1,39 @@
 //! PTY proxy integration module for managing terminal sessions.
 //!
-//! Replaces the tmux module. Instead of tmux, terminal sessions are captured
-//! by pty-proxy instances that connect to us via Unix socket.
+//! Terminal sessions are captured by pty-proxy instances that connect to us
+//! via Unix socket. tmux panes can be mirrored as well (see [`crate::tmux`]).
 //!
 //! Each pty-proxy sends:
-//!   - Registration (JSON): shell info, pid, tty
+//!   - Registration (JSON): shell info, pid, tty, proxy ID
 //!   - Framed I/O: length-prefixed messages tagged 'I' (input) or 'O' (output)
-//!   - Resize notifications
+//!   - Resize, working directory, OSC 52 clipboard and bell notifications
 //!
 //! We forward output to relay (-> browser) and inject browser input back.
-
+//! Session IDs come from the [`registry`], so proxies reconnecting after a
+//! dropped connection or a mac-client restart keep theirs, along with their
+//! per-session settings.
+
+pub mod registry;
+
+use crate::alerts::{Alert, Severity};
+use crate::backend::SessionBackend;
+use crate::bell::BellScanner;
+use crate::config::CloseWindow;
+use crate::notify::applescript_quote;
+use crate::transcript::Transcript;
+use crate::watchdog::{self, BoundSocket};
+use crate::{applescript, paths, socket, supervisor};
+use registry::SharedRegistry;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::{mpsc, Mutex};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot, Mutex};
 use tracing::{debug, error, info, warn};
 
-/// Socket path for pty-proxy connections.
-pub const SOCKET_PATH: &str = "/tmp/terminal-remote.sock";
-
 /// Information about a connected pty-proxy session.
 #[derive(Debug, Clone)]
 pub struct PtySessionInfo {
@@ -28,6 +41,19 @@ pub struct PtySessionInfo {
     pub shell: String,
     pub pid: u32,
     pub tty: String,
+    /// Working directory reported at registration
+    pub cwd: Option<String>,
+    /// Live byte counters, shared with the frame reader.
+    pub stats: Arc<SessionStats>,
+}
+
+/// Byte counters for a single session.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    /// Shell output bytes mirrored to the relay.
+    pub bytes_out: AtomicU64,
+    /// Browser input bytes injected into the shell.
+    pub bytes_in: AtomicU64,
 }
 
 /// Events emitted by the PTY manager.
@@ -37,6 +63,16 @@ pub enum PtyEvent {
     Attached {
         session_id: String,
         session_name: String,
+        shell: String,
+        pid: u32,
+        tty: String,
+        cwd: Option<String>,
+        /// Byte counters, updated as the session runs
+        stats: Arc<SessionStats>,
+        /// Latest output, for exporting a transcript
+        transcript: Arc<Transcript>,
+        /// Browser input is dropped (remembered from an earlier connection)
+        read_only: bool,
     },
     /// A pty-proxy session disconnected.
     Detached {
@@ -47,6 +83,19 @@ pub enum PtyEvent {
         session_id: String,
         data: Vec<u8>,
     },
+    /// The shell changed its working directory.
+    CwdChanged { session_id: String, cwd: String },
+    /// A program in the session copied text with OSC 52 (base64 payload).
+    Clipboard { session_id: String, data: String },
+    /// A program in the session set the window title (OSC 0 or 2).
+    TitleChanged { session_id: String, title: String },
+    /// The session rang the terminal bell (throttled per session).
+    Bell { session_id: String },
+    /// Session name changed (e.g. the shell changed directory).
+    Renamed {
+        session_id: String,
+        name: String,
+    },
     /// Terminal resized on mac (pty-proxy SIGWINCH → browser).
     SessionResize {
         session_id: String,
@@ -55,6 +104,8 @@ pub enum PtyEvent {
     },
     /// Error occurred.
     Error(String),
+    /// A problem that was recovered from, listed without a notification.
+    Warning(String),
 }
 
 /// Commands that can be sent to the PTY manager.
@@ -69,6 +120,21 @@ pub enum PtyCommand {
     KillSession {
         session_id: String,
     },
+    /// Rename a session (browser-initiated).
+    Rename {
+        session_id: String,
+        name: String,
+    },
+    /// Resize a session's PTY to the size chosen for browsers and the Mac.
+    Resize {
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    /// Reply with a snapshot of all connected sessions.
+    ListSessions {
+        reply: oneshot::Sender<Vec<(String, PtySessionInfo)>>,
+    },
     /// Shutdown the PTY manager.
     Shutdown,
 }
@@ -80,11 +146,23 @@ struct Registration {
     shell: String,
     pid: u32,
     tty: String,
+    /// Sent by pty-proxy builds that track the shell's working directory
+    #[serde(default)]
+    cwd: Option<String>,
+    /// Stable for the proxy's lifetime; sent by newer pty-proxy builds
+    #[serde(default)]
+    proxy_id: Option<String>,
 }
 
-/// Manages pty-proxy connections.
-/// Exists to own the Drop impl that cleans up the socket file.
-pub struct PtyManager;
+/// Manages pty-proxy connections; the [`SessionBackend`] for sessions
+/// started by pty-proxy. Its Drop impl cleans up the socket file.
+pub struct PtyManager {
+    events: Option<mpsc::UnboundedReceiver<PtyEvent>>,
+    commands: mpsc::UnboundedSender<PtyCommand>,
+    /// Set once the listener is bound, so Drop never removes a socket that
+    /// belongs to another running instance.
+    owns_socket: Arc<AtomicBool>,
+}
 
 /// Handle for writing to a connected pty-proxy.
 struct SessionHandle {
@@ -98,15 +176,8 @@ struct SessionHandle {
 type TtyMap = Arc<Mutex<HashMap<String, String>>>;
 
 impl PtyManager {
-    /// Create a new PtyManager.
-    /// Returns the manager, event receiver, and command sender.
-    ///
-    /// This has the same signature pattern as TmuxManager::new() for easy swap.
-    pub fn new() -> (
-        Self,
-        mpsc::UnboundedReceiver<PtyEvent>,
-        mpsc::UnboundedSender<PtyCommand>,
-    ) {
+    /// Create a new PtyManager that assigns session IDs from `registry`.
+    pub fn new(registry: SharedRegistry, close_window: CloseWindow) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let (command_tx, command_rx) = mpsc::unbounded_channel();
 
@@ -116,22 +187,78 @@ impl PtyManager {
         // TTY map persists across session lifecycle for late close handling
         let tty_map: TtyMap = Arc::new(Mutex::new(HashMap::new()));
 
-        // Start command processor
+        // Repeated failures of either task reach the UI as PTY errors
+        let report = |event_tx: &mpsc::UnboundedSender<PtyEvent>| {
+            let event_tx = event_tx.clone();
+            move |alert: Alert| {
+                if alert.severity == Severity::Critical {
+                    let _ = event_tx.send(PtyEvent::Error(alert.message));
+                }
+            }
+        };
+
+        // Start command processor; the receiver outlives restarts
+        let command_rx = Arc::new(Mutex::new(command_rx));
         let sessions_cmd = sessions.clone();
         let tty_map_cmd = tty_map.clone();
-        tokio::spawn(async move {
-            process_commands(command_rx, sessions_cmd, tty_map_cmd).await;
+        let event_tx_cmd = event_tx.clone();
+        supervisor::supervise("PTY command processor", report(&event_tx), move || {
+            let command_rx = command_rx.clone();
+            let sessions = sessions_cmd.clone();
+            let tty_map = tty_map_cmd.clone();
+            let event_tx = event_tx_cmd.clone();
+            async move {
+                process_commands(
+                    &mut *command_rx.lock().await,
+                    sessions,
+                    tty_map,
+                    event_tx,
+                    close_window,
+                )
+                .await;
+                Ok(())
+            }
         });
 
         // Start Unix socket listener
         let event_tx_listen = event_tx.clone();
-        tokio::spawn(async move {
-            if let Err(e) = run_listener(sessions, event_tx_listen, tty_map).await {
-                error!("PTY listener failed: {}", e);
-            }
+        let owns_socket = Arc::new(AtomicBool::new(false));
+        let owns_socket_listen = owns_socket.clone();
+        supervisor::supervise("PTY listener", report(&event_tx), move || {
+            run_listener(
+                sessions.clone(),
+                event_tx_listen.clone(),
+                tty_map.clone(),
+                registry.clone(),
+                owns_socket_listen.clone(),
+            )
         });
 
-        (Self, event_rx, command_tx)
+        Self {
+            events: Some(event_rx),
+            commands: command_tx,
+            owns_socket,
+        }
+    }
+}
+
+impl SessionBackend for PtyManager {
+    fn name(&self) -> &'static str {
+        "pty-proxy"
+    }
+
+    /// Session IDs come from the registry, so every ID not claimed by
+    /// another backend is a pty-proxy session; list this backend last.
+    fn owns(&self, _session_id: &str) -> bool {
+        true
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<PtyEvent>> {
+        self.events.take()
+    }
+
+    fn send(&self, cmd: PtyCommand) {
+        let _ = self.commands.send(cmd);
     }
 }
 
@@ -140,24 +267,45 @@ async fn run_listener(
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     event_tx: mpsc::UnboundedSender<PtyEvent>,
     tty_map: TtyMap,
-) -> std::io::Result<()> {
-    // Remove stale socket
-    if std::path::Path::new(SOCKET_PATH).exists() {
-        warn!("Removing stale socket at {}", SOCKET_PATH);
-        std::fs::remove_file(SOCKET_PATH)?;
+    registry: SharedRegistry,
+    owns_socket: Arc<AtomicBool>,
+) -> Result<(), String> {
+    let socket_path = paths::pty_socket();
+    let listener = socket::bind_exclusive(&socket_path)
+        .map_err(|e| format!("cannot listen on {}: {}", socket_path.display(), e))?;
+    owns_socket.store(true, Ordering::Relaxed);
+    let bound = BoundSocket::new(&socket_path)
+        .map_err(|e| format!("cannot inspect {}: {}", socket_path.display(), e))?;
+    info!("PTY manager listening on {}", socket_path.display());
+    if let Some(legacy) = paths::legacy_socket(&socket_path) {
+        if let Err(e) = socket::link_legacy(&socket_path, &legacy) {
+            warn!("Cannot link {} for older pty-proxy builds: {}", legacy.display(), e);
+        }
     }
 
-    let listener = UnixListener::bind(SOCKET_PATH)?;
-    info!("PTY manager listening on {}", SOCKET_PATH);
-
+    let mut checks = watchdog::checks();
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = checks.tick() => {
+                if let Err(e) = bound.check().await {
+                    let _ = event_tx.send(PtyEvent::Warning(format!(
+                        "PTY listener failed its health check and is listening again: {}",
+                        e
+                    )));
+                    return Err(format!("health check failed: {}", e));
+                }
+                continue;
+            }
+        };
+        match accepted {
             Ok((stream, _)) => {
                 let sessions = sessions.clone();
                 let event_tx = event_tx.clone();
                 let tty_map = tty_map.clone();
+                let registry = registry.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = handle_proxy_connection(stream, sessions, event_tx, tty_map).await {
+                    if let Err(e) = handle_proxy_connection(stream, sessions, event_tx, tty_map, registry).await {
                         debug!("Proxy connection ended: {}", e);
                     }
                 });
@@ -176,8 +324,8 @@ async fn handle_proxy_connection(
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     event_tx: mpsc::UnboundedSender<PtyEvent>,
     tty_map: TtyMap,
+    registry: SharedRegistry,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let session_id = uuid::Uuid::new_v4().to_string();
     let (mut reader, writer) = stream.into_split();
 
     // Read registration frame: 4 bytes length + JSON
@@ -191,6 +339,12 @@ async fn handle_proxy_connection(
         serde_json::from_slice(&buf)?
     };
 
+    let (session_id, settings) = {
+        let mut registry = registry.lock().unwrap();
+        let session_id = registry.claim(reg.proxy_id.as_deref(), reg.pid, &reg.tty, &reg.name);
+        let settings = registry.settings(&session_id);
+        (session_id, settings)
+    };
     let session_name = reg.name.clone();
     let tty = reg.tty.clone();
     info!(
@@ -202,11 +356,18 @@ async fn handle_proxy_connection(
         "pty-proxy connected"
     );
 
+    let info_shell = reg.shell.clone();
+    let info_pid = reg.pid;
+    let info_cwd = reg.cwd.clone();
+    let stats = Arc::new(SessionStats::default());
+    let transcript = Arc::new(Transcript::default());
     let info = PtySessionInfo {
         name: reg.name,
         shell: reg.shell,
         pid: reg.pid,
         tty: reg.tty,
+        cwd: reg.cwd,
+        stats: stats.clone(),
     };
 
     // Store session and TTY mapping
@@ -226,16 +387,24 @@ async fn handle_proxy_connection(
     let _ = event_tx.send(PtyEvent::Attached {
         session_id: session_id.clone(),
         session_name,
+        shell: info_shell,
+        pid: info_pid,
+        tty: tty.clone(),
+        cwd: info_cwd,
+        stats: stats.clone(),
+        transcript: transcript.clone(),
+        read_only: settings.read_only,
     });
 
     // Read frames from pty-proxy
-    let result = read_proxy_frames(&mut reader, &session_id, &event_tx).await;
+    let result = read_proxy_frames(&mut reader, &session_id, &event_tx, &stats, &transcript).await;
 
     // Cleanup on disconnect
     {
         let mut sessions_guard = sessions.lock().await;
         sessions_guard.remove(&session_id);
     }
+    registry.lock().unwrap().detach(&session_id);
     let _ = event_tx.send(PtyEvent::Detached {
         session_id: session_id.clone(),
     });
@@ -256,7 +425,10 @@ async fn read_proxy_frames(
     reader: &mut tokio::net::unix::OwnedReadHalf,
     session_id: &str,
     event_tx: &mpsc::UnboundedSender<PtyEvent>,
+    stats: &SessionStats,
+    transcript: &Transcript,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut bell = BellScanner::new();
     loop {
         // Read frame length
         let len = match reader.read_u32().await {
@@ -281,6 +453,13 @@ async fn read_proxy_frames(
         match payload[0] {
             b'O' => {
                 // Output from shell -> forward to browser
+                stats.bytes_out.fetch_add(len as u64 - 1, Ordering::Relaxed);
+                transcript.push(&payload[1..]);
+                if bell.feed(&payload[1..], Instant::now()) {
+                    let _ = event_tx.send(PtyEvent::Bell {
+                        session_id: session_id.to_string(),
+                    });
+                }
                 let _ = event_tx.send(PtyEvent::Output {
                     session_id: session_id.to_string(),
                     data: payload[1..].to_vec(),
@@ -295,19 +474,59 @@ async fn read_proxy_frames(
                 let text = String::from_utf8_lossy(&payload);
                 debug!(session_id = %session_id, "Control message from proxy: {}", text);
 
-                // Parse resize and forward to browser
+                // Parse resize/rename and forward to browser
                 if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&payload) {
-                    if json.get("type").and_then(|t| t.as_str()) == Some("resize") {
-                        if let (Some(cols), Some(rows)) = (
-                            json.get("cols").and_then(|c| c.as_u64()),
-                            json.get("rows").and_then(|r| r.as_u64()),
-                        ) {
-                            let _ = event_tx.send(PtyEvent::SessionResize {
+                    match json.get("type").and_then(|t| t.as_str()) {
+                        Some("resize") => {
+                            if let (Some(cols), Some(rows)) = (
+                                json.get("cols").and_then(|c| c.as_u64()),
+                                json.get("rows").and_then(|r| r.as_u64()),
+                            ) {
+                                let _ = event_tx.send(PtyEvent::SessionResize {
+                                    session_id: session_id.to_string(),
+                                    cols: cols as u16,
+                                    rows: rows as u16,
+                                });
+                            }
+                        }
+                        Some("cwd") => {
+                            if let Some(path) = json.get("path").and_then(|p| p.as_str()) {
+                                let _ = event_tx.send(PtyEvent::CwdChanged {
+                                    session_id: session_id.to_string(),
+                                    cwd: path.to_string(),
+                                });
+                            }
+                        }
+                        Some("clipboard") => {
+                            if let Some(data) = json.get("data").and_then(|d| d.as_str()) {
+                                let _ = event_tx.send(PtyEvent::Clipboard {
+                                    session_id: session_id.to_string(),
+                                    data: data.to_string(),
+                                });
+                            }
+                        }
+                        Some("title") => {
+                            if let Some(title) = json.get("title").and_then(|t| t.as_str()) {
+                                let _ = event_tx.send(PtyEvent::TitleChanged {
+                                    session_id: session_id.to_string(),
+                                    title: title.to_string(),
+                                });
+                            }
+                        }
+                        Some("bell") if bell.report(Instant::now()) => {
+                            let _ = event_tx.send(PtyEvent::Bell {
                                 session_id: session_id.to_string(),
-                                cols: cols as u16,
-                                rows: rows as u16,
                             });
                         }
+                        Some("rename") => {
+                            if let Some(name) = json.get("name").and_then(|n| n.as_str()) {
+                                let _ = event_tx.send(PtyEvent::Renamed {
+                                    session_id: session_id.to_string(),
+                                    name: name.to_string(),
+                                });
+                            }
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -333,9 +552,11 @@ async fn send_frame(
 
 /// Process commands sent to the PTY manager.
 async fn process_commands(
-    mut command_rx: mpsc::UnboundedReceiver<PtyCommand>,
+    command_rx: &mut mpsc::UnboundedReceiver<PtyCommand>,
     sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
     tty_map: TtyMap,
+    event_tx: mpsc::UnboundedSender<PtyEvent>,
+    close_window: CloseWindow,
 ) {
     while let Some(cmd) = command_rx.recv().await {
         match cmd {
@@ -350,23 +571,28 @@ async fn process_commands(
                     let json = serde_json::to_vec(&msg).unwrap();
                     if let Err(e) = send_frame(&mut session.writer, &json).await {
                         warn!(session_id = %session_id, error = %e, "Write failed");
+                    } else {
+                        session.info.stats.bytes_in.fetch_add(data.len() as u64, Ordering::Relaxed);
                     }
                 }
             }
             PtyCommand::KillSession { session_id } => {
-                // Close the Terminal.app window FIRST — this kills the shell
+                // Close the terminal window FIRST — this kills the shell
                 // naturally and prevents Terminal.app from reopening a new shell
-                // (which happens when pty-proxy exits with code 0).
-                let tty = {
+                // (which happens when pty-proxy exits with code 0). Skipped
+                // while quitting: the sessions are going away with the app.
+                let script = if applescript::is_shutting_down() {
+                    None
+                } else {
                     let tty_guard = tty_map.lock().await;
-                    tty_guard.get(&session_id).cloned()
+                    tty_guard
+                        .get(&session_id)
+                        .and_then(|tty| close_window_script(close_window, tty))
                 };
 
-                if let Some(tty) = tty {
-                    info!(session_id = %session_id, tty = %tty, "Closing terminal window first");
-                    tokio::task::spawn_blocking(move || {
-                        close_terminal_window_force(&tty);
-                    }).await.ok();
+                if let Some(script) = script {
+                    info!(session_id = %session_id, app = close_window.label(), "Closing terminal window first");
+                    applescript::queue("close window", script);
                 } else {
                     // Fallback: send close message to pty-proxy directly
                     let mut sessions_guard = sessions.lock().await;
@@ -384,6 +610,36 @@ async fn process_commands(
                     }
                 }
             }
+            PtyCommand::Rename { session_id, name } => {
+                let mut sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get_mut(&session_id) {
+                    session.info.name = name.clone();
+                    let _ = event_tx.send(PtyEvent::Renamed { session_id, name });
+                }
+            }
+            PtyCommand::Resize { session_id, cols, rows } => {
+                let mut sessions_guard = sessions.lock().await;
+                if let Some(session) = sessions_guard.get_mut(&session_id) {
+                    let msg = serde_json::json!({
+                        "type": "resize",
+                        "cols": cols,
+                        "rows": rows,
+                    });
+                    let json = serde_json::to_vec(&msg).unwrap();
+                    if let Err(e) = send_frame(&mut session.writer, &json).await {
+                        warn!(session_id = %session_id, error = %e, "Resize failed");
+                    }
+                }
+            }
+            PtyCommand::ListSessions { reply } => {
+                let sessions_guard = sessions.lock().await;
+                let mut list: Vec<(String, PtySessionInfo)> = sessions_guard
+                    .iter()
+                    .map(|(id, session)| (id.clone(), session.info.clone()))
+                    .collect();
+                list.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+                let _ = reply.send(list);
+            }
             PtyCommand::Shutdown => {
                 info!("PTY manager shutting down");
                 let mut sessions_guard = sessions.lock().await;
@@ -399,46 +655,55 @@ async fn process_commands(
     }
 }
 
-/// Force-close a Terminal.app window by TTY — no `busy` check.
-/// Used when the browser explicitly requests closing a session.
-/// Closes the window first so Terminal.app kills the shell naturally,
-/// preventing the "reopen shell on exit" cycle.
-fn close_terminal_window_force(tty: &str) {
+/// Script force-closing the window (Terminal) or session (iTerm2) on
+/// `tty` — no `busy` check. Used when the browser explicitly requests
+/// closing a session. `None` when closing is off or the tty is unknown.
+fn close_window_script(close_window: CloseWindow, tty: &str) -> Option<String> {
     if tty == "unknown" || tty.is_empty() {
-        return;
+        return None;
     }
-    let script = format!(
-        r#"tell application "Terminal"
+    let tty = applescript_quote(tty);
+    match close_window {
+        CloseWindow::Off => None,
+        CloseWindow::Terminal => Some(format!(
+            r#"tell application "Terminal"
     repeat with w in windows
         try
-            if tty of first tab of w is "{tty}" then
+            if tty of first tab of w is {tty} then
                 close w saving no
             end if
         end try
     end repeat
-end tell"#,
-        tty = tty
-    );
-    match std::process::Command::new("osascript")
-        .arg("-e")
-        .arg(&script)
-        .output()
-    {
-        Ok(output) if !output.status.success() => {
-            warn!(
-                "osascript force-close failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
-        Err(e) => warn!("Failed to run osascript for force-close: {}", e),
-        _ => info!(tty = %tty, "Terminal window force-closed"),
+end tell"#
+        )),
+        CloseWindow::Iterm => Some(format!(
+            r#"tell application "iTerm2"
+    repeat with w in windows
+        repeat with t in tabs of w
+            repeat with s in sessions of t
+                if tty of s is {tty} then
+                    close s
+                    return
+                end if
+            end repeat
+        end repeat
+    end repeat
+end tell"#
+        )),
     }
 }
 
 impl Drop for PtyManager {
     fn drop(&mut self) {
+        if !self.owns_socket.load(Ordering::Relaxed) {
+            return;
+        }
         info!("PTY manager dropped, cleaning up socket");
-        if let Err(e) = std::fs::remove_file(SOCKET_PATH) {
+        let socket_path = paths::pty_socket();
+        if let Some(legacy) = paths::legacy_socket(&socket_path) {
+            socket::unlink_legacy(&socket_path, &legacy);
+        }
+        if let Err(e) = std::fs::remove_file(&socket_path) {
             if e.kind() != std::io::ErrorKind::NotFound {
                 warn!("Failed to remove socket: {}", e);
             }
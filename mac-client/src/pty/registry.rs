@@ -0,0 +1,317 @@
+//! Session registry persisted across mac-client restarts.
+//!
+//! pty-proxy reconnects on its own when mac-client goes away or the
+//! connection drops, so the same shells register again. The registry
+//! (`sessions.json` in [`paths::config_dir`]) remembers the ID and the
+//! per-session settings ([`SessionSettings`]) of every session by a stable
+//! identity: the proxy's own ID when it sends one, else the shell pid and
+//! tty. A reconnecting proxy gets its old ID back, so browsers keep their
+//! tabs and the settings are applied again without asking. An entry is
+//! kept while its shell runs and dropped once the shell is gone, when its
+//! proxy disconnects or at load.
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Per-session toggles that outlive the proxy connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    /// Answer to the clipboard permission prompt, once asked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clipboard_allowed: Option<bool>,
+    /// Browser input is dropped ("Read Only" in the session's submenu)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+/// A session as remembered across reconnects and restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub session_id: String,
+    /// ID pty-proxy keeps for its lifetime (older builds send none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_id: Option<String>,
+    /// Shell pid reported by pty-proxy
+    pub pid: u32,
+    pub tty: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: SessionSettings,
+}
+
+/// Registry shared by the PTY manager and the UI thread.
+pub type SharedRegistry = Arc<Mutex<SessionRegistry>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRegistry {
+    entries: Vec<RegistryEntry>,
+    /// File to save to; `None` keeps the registry in memory only
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Sessions with a live proxy connection in this run
+    #[serde(skip)]
+    connected: HashSet<String>,
+    /// Whether a shell pid is still running
+    #[serde(skip, default = "default_is_alive")]
+    is_alive: fn(u32) -> bool,
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            path: None,
+            connected: HashSet::new(),
+            is_alive: process_alive,
+        }
+    }
+}
+
+fn default_is_alive() -> fn(u32) -> bool {
+    process_alive
+}
+
+impl SessionRegistry {
+    /// Location of the registry file (`sessions.json` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("sessions.json"))
+    }
+
+    /// Load the registry left by a previous run, keeping only sessions whose
+    /// shell is still running.
+    pub fn load() -> Self {
+        Self::load_from(Self::path(), process_alive)
+    }
+
+    /// Load the registry at `path`, keeping entries for which `is_alive`
+    /// holds for the shell pid (also used when a proxy disconnects).
+    pub fn load_from(path: Option<PathBuf>, is_alive: fn(u32) -> bool) -> Self {
+        let mut registry = match path.as_ref().map(std::fs::read) {
+            Some(Ok(bytes)) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Ignoring invalid session registry: {}", e);
+                Self::default()
+            }),
+            _ => Self::default(),
+        };
+        registry.entries.retain(|e| is_alive(e.pid));
+        if !registry.entries.is_empty() {
+            info!(
+                "Session registry: {} session(s) from a previous run may reconnect",
+                registry.entries.len()
+            );
+        }
+        registry.path = path;
+        registry.is_alive = is_alive;
+        registry
+    }
+
+    pub fn entries(&self) -> &[RegistryEntry] {
+        &self.entries
+    }
+
+    /// ID for a proxy registering with this proxy ID (if it sent one), shell
+    /// pid and tty: the ID the session had before the proxy reconnected or
+    /// mac-client restarted, or a fresh one.
+    pub fn claim(&mut self, proxy_id: Option<&str>, pid: u32, tty: &str, name: &str) -> String {
+        let connected = &self.connected;
+        let free = |e: &RegistryEntry| !connected.contains(&e.session_id);
+        let by_proxy = proxy_id.and_then(|id| {
+            self.entries
+                .iter()
+                .position(|e| e.proxy_id.as_deref() == Some(id) && free(e))
+        });
+        let known = by_proxy.or_else(|| {
+            self.entries
+                .iter()
+                .position(|e| e.pid == pid && e.tty == tty && free(e))
+        });
+        let session_id = match known {
+            Some(i) => {
+                let entry = &mut self.entries[i];
+                info!(session_id = %entry.session_id, "Session reattached");
+                entry.name = name.to_string();
+                entry.proxy_id = proxy_id.map(str::to_string);
+                entry.session_id.clone()
+            }
+            None => {
+                let session_id = uuid::Uuid::new_v4().to_string();
+                self.entries.push(RegistryEntry {
+                    session_id: session_id.clone(),
+                    proxy_id: proxy_id.map(str::to_string),
+                    pid,
+                    tty: tty.to_string(),
+                    name: name.to_string(),
+                    settings: SessionSettings::default(),
+                });
+                session_id
+            }
+        };
+        self.connected.insert(session_id.clone());
+        self.save();
+        session_id
+    }
+
+    /// Note that a session's proxy disconnected. The entry is kept for the
+    /// proxy to reattach while the shell runs, and forgotten once it exited.
+    pub fn detach(&mut self, session_id: &str) {
+        self.connected.remove(session_id);
+        let is_alive = self.is_alive;
+        self.entries
+            .retain(|e| e.session_id != session_id || is_alive(e.pid));
+        self.save();
+    }
+
+    /// Shell pids of the sessions with a live proxy connection.
+    pub fn connected_pids(&self) -> HashSet<u32> {
+        self.entries
+            .iter()
+            .filter(|e| self.connected.contains(&e.session_id))
+            .map(|e| e.pid)
+            .collect()
+    }
+
+    /// Settings of a session (defaults if it is unknown).
+    pub fn settings(&self, session_id: &str) -> SessionSettings {
+        self.entries
+            .iter()
+            .find(|e| e.session_id == session_id)
+            .map(|e| e.settings.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn clipboard_allowed(&self, session_id: &str) -> Option<bool> {
+        self.settings(session_id).clipboard_allowed
+    }
+
+    pub fn set_clipboard_allowed(&mut self, session_id: &str, allowed: bool) {
+        self.update(session_id, |s| s.clipboard_allowed = Some(allowed));
+    }
+
+    pub fn read_only(&self, session_id: &str) -> bool {
+        self.settings(session_id).read_only
+    }
+
+    pub fn set_read_only(&mut self, session_id: &str, read_only: bool) {
+        self.update(session_id, |s| s.read_only = read_only);
+    }
+
+    fn update(&mut self, session_id: &str, change: impl FnOnce(&mut SessionSettings)) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.session_id == session_id) {
+            change(&mut entry.settings);
+            self.save();
+        }
+    }
+
+    /// Write the registry file, replacing it atomically so a crash mid-write
+    /// never leaves it truncated.
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            warn!("Failed to save session registry to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Whether a process with this pid exists.
+fn process_alive(pid: u32) -> bool {
+    if pid == 0 || pid > i32::MAX as u32 {
+        return false;
+    }
+    let found = unsafe { libc::kill(pid as i32, 0) } == 0;
+    found || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_after_restart_reuses_id() {
+        let path = std::env::temp_dir().join(format!("session-registry-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut before = SessionRegistry::load_from(Some(path.clone()), |_| true);
+        let a = before.claim(None, 100, "/dev/ttys001", "zsh - ~");
+        let b = before.claim(None, 200, "/dev/ttys002", "zsh - ~/src");
+        let ended = before.claim(None, 300, "/dev/ttys003", "bash - /tmp");
+        before.set_clipboard_allowed(&a, true);
+        drop(before);
+
+        // pid 200's shell exited while mac-client was down
+        let mut after = SessionRegistry::load_from(Some(path.clone()), |pid| pid != 200);
+        assert_eq!(after.entries().len(), 2);
+        assert_eq!(after.claim(None, 100, "/dev/ttys001", "zsh - ~"), a);
+        assert_eq!(after.clipboard_allowed(&a), Some(true));
+        assert_ne!(after.claim(None, 200, "/dev/ttys002", "zsh - ~/src"), b);
+        // Same pid on another tty is a different session
+        assert_ne!(after.claim(None, 300, "/dev/ttys009", "bash - /tmp"), ended);
+        drop(after);
+
+        // The shell of `a` exited: its proxy's disconnect forgets it
+        let mut after = SessionRegistry::load_from(Some(path.clone()), |_| true);
+        assert_eq!(after.claim(None, 100, "/dev/ttys001", "zsh - ~"), a);
+        after.is_alive = |pid| pid != 100;
+        after.detach(&a);
+        let reloaded = SessionRegistry::load_from(Some(path.clone()), |_| true);
+        assert!(reloaded.entries().iter().all(|e| e.session_id != a));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_connected_session_is_not_claimed_twice() {
+        let mut registry = SessionRegistry::load_from(None, |_| false);
+        let first = registry.claim(None, 100, "/dev/ttys001", "zsh");
+        let second = registry.claim(None, 100, "/dev/ttys001", "zsh");
+        assert_ne!(first, second);
+
+        registry.detach(&second);
+        assert_eq!(registry.entries().len(), 1);
+        assert_eq!(registry.clipboard_allowed(&first), None);
+    }
+
+    #[test]
+    fn test_settings_survive_proxy_reconnect() {
+        let mut registry = SessionRegistry::load_from(None, |_| true);
+        let id = registry.claim(Some("4242-a"), 100, "/dev/ttys001", "zsh");
+        registry.set_read_only(&id, true);
+        registry.set_clipboard_allowed(&id, false);
+        registry.detach(&id);
+
+        // The proxy reconnects while the shell keeps running
+        assert_eq!(registry.claim(Some("4242-a"), 100, "/dev/ttys001", "zsh - ~/src"), id);
+        let expected = SessionSettings {
+            clipboard_allowed: Some(false),
+            read_only: true,
+        };
+        assert_eq!(registry.settings(&id), expected);
+
+        // A proxy restarted in the same shell is found by pid and tty
+        registry.detach(&id);
+        assert_eq!(registry.claim(Some("4243-b"), 100, "/dev/ttys001", "zsh"), id);
+        assert!(registry.read_only(&id));
+        assert_ne!(registry.claim(Some("4243-b"), 100, "/dev/ttys001", "zsh"), id);
+    }
+
+    #[test]
+    fn test_entries_without_settings_load() {
+        let json = r#"{"entries":[{"session_id":"s","pid":1,"tty":"/dev/ttys001","name":"zsh","clipboard_allowed":true}]}"#;
+        let registry: SessionRegistry = serde_json::from_str(json).unwrap();
+        assert_eq!(registry.clipboard_allowed("s"), Some(true));
+        assert!(!registry.read_only("s"));
+        assert_eq!(registry.entries()[0].proxy_id, None);
+    }
+}
@@ -0,0 +1,170 @@
+//! Token-bucket rate limiting for browser input.
+//!
+//! Protects shells from a compromised or runaway browser flooding them with
+//! input. Limits are applied per session on both bytes and messages.
+
+use crate::config::RateLimitConfig;
+use std::time::Instant;
+
+/// A token bucket that refills continuously up to its capacity.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    pub fn new(refill_per_sec: u32, capacity: u32, now: Instant) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: f64::from(refill_per_sec),
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Cost of `n` units, capped at capacity so a single oversized message
+    /// can still pass once the bucket is full.
+    fn cost(&self, n: usize) -> f64 {
+        (n as f64).min(self.capacity)
+    }
+
+    fn has(&self, n: usize) -> bool {
+        self.tokens >= self.cost(n)
+    }
+
+    fn take(&mut self, n: usize) {
+        self.tokens -= self.cost(n);
+    }
+
+    /// Take `n` tokens if available.
+    pub fn try_take(&mut self, n: usize, now: Instant) -> bool {
+        self.refill(now);
+        if self.has(n) {
+            self.take(n);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Outcome of checking one input message against the limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Within limits, forward to the shell
+    Allow,
+    /// Over the limit, drop the message
+    Drop,
+    /// Over the limit for the first time since input was last allowed
+    Tripped,
+}
+
+/// Per-session limiter combining a byte bucket and a message bucket.
+#[derive(Debug, Clone)]
+pub struct InputLimiter {
+    bytes: TokenBucket,
+    messages: TokenBucket,
+    limited: bool,
+}
+
+impl InputLimiter {
+    pub fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            bytes: TokenBucket::new(config.bytes_per_sec, config.burst_bytes, now),
+            messages: TokenBucket::new(config.messages_per_sec, config.burst_messages, now),
+            limited: false,
+        }
+    }
+
+    /// Check a message of `len` bytes. Tokens are only consumed when both
+    /// buckets have room.
+    pub fn check(&mut self, len: usize, now: Instant) -> Verdict {
+        self.bytes.refill(now);
+        self.messages.refill(now);
+
+        if self.bytes.has(len) && self.messages.has(1) {
+            self.bytes.take(len);
+            self.messages.take(1);
+            self.limited = false;
+            Verdict::Allow
+        } else if self.limited {
+            Verdict::Drop
+        } else {
+            self.limited = true;
+            Verdict::Tripped
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(bytes_per_sec: u32, burst_bytes: u32, messages_per_sec: u32, burst_messages: u32) -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            bytes_per_sec,
+            burst_bytes,
+            messages_per_sec,
+            burst_messages,
+        }
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 10, start);
+        assert!(bucket.try_take(10, start));
+        assert!(!bucket.try_take(1, start));
+        assert!(bucket.try_take(5, start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(1, start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_oversized_message_passes_when_full() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 10, start);
+        assert!(bucket.try_take(1000, start));
+        assert!(!bucket.try_take(1, start));
+    }
+
+    #[test]
+    fn test_limiter_trips_once_then_drops() {
+        let start = Instant::now();
+        let mut limiter = InputLimiter::new(&config(1000, 1000, 2, 2), start);
+        assert_eq!(limiter.check(1, start), Verdict::Allow);
+        assert_eq!(limiter.check(1, start), Verdict::Allow);
+        assert_eq!(limiter.check(1, start), Verdict::Tripped);
+        assert_eq!(limiter.check(1, start), Verdict::Drop);
+
+        // Recovers after refill, and can trip again afterwards
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(1, later), Verdict::Allow);
+        assert_eq!(limiter.check(1, later), Verdict::Allow);
+        assert_eq!(limiter.check(1, later), Verdict::Tripped);
+    }
+
+    #[test]
+    fn test_byte_limit_does_not_consume_message_tokens() {
+        let start = Instant::now();
+        let mut limiter = InputLimiter::new(&config(10, 10, 0, 2), start);
+        assert_eq!(limiter.check(10, start), Verdict::Allow);
+        assert_eq!(limiter.check(10, start), Verdict::Tripped);
+        // The dropped message did not use up the last message token
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(10, later), Verdict::Allow);
+        assert_eq!(limiter.check(0, later), Verdict::Tripped);
+    }
+}
@@ -1,9 +1,31 @@
-use crate::protocol::ControlMessage;
+use super::direct::{DirectEvent, DirectLinks};
+use super::latency::{LatencyProbe, LatencyStats, PING_INTERVAL};
+use crate::identity::ClientIdentity;
+use crate::protocol::{ControlMessage, Role, SearchMatch, SessionInfo, SessionMetadata, Viewer, PROTOCOL_VERSION};
+use crate::router::{self, InboundFrame, SessionCommand};
+use crate::watchdog::Heartbeat;
 use futures_util::{SinkExt, StreamExt};
 use std::error::Error;
 use std::sync::mpsc::Sender;
-use std::time::Duration;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+};
+
+/// Consecutive failed connection attempts before the relay is reported unreachable.
+pub const UNREACHABLE_AFTER_ATTEMPTS: u32 = 3;
+
+/// Longest wait for the relay to accept a WebSocket connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Next relay URL to switch to; never resolves without a switch channel.
+async fn next_switch(switch_rx: Option<&mut tokio::sync::mpsc::UnboundedReceiver<String>>) -> Option<String> {
+    match switch_rx {
+        Some(switch_rx) => switch_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
 /// Events emitted by the RelayClient to the main thread.
 /// These are sent via std::sync::mpsc (not tokio::sync) for AppKit compatibility.
@@ -19,14 +41,35 @@ pub enum RelayEvent {
     BrowserConnected(String),
     /// A browser disconnected from this session
     BrowserDisconnected(String),
+    /// A browser is waiting to be approved
+    ApprovalRequest {
+        browser_id: String,
+        browser_key: Option<String>,
+        user_agent: Option<String>,
+    },
+    /// A browser stopped waiting for approval
+    ApprovalCancelled(String),
+    /// The browsers connected now, oldest first
+    Presence(Vec<Viewer>),
     /// Error message from relay
     Error(String),
+    /// Repeated connection attempts failed (sent on every failure past the threshold)
+    Unreachable(String),
+    /// Round-trip time and ping loss, updated after every ping
+    Latency(LatencyStats),
     /// Terminal data received from relay (browser input -> shell)
     TerminalData { session_id: String, data: Vec<u8> },
-    /// Close session request from browser
-    CloseSession { session_id: String },
     /// Create new session request from browser
     CreateSession,
+    /// Session management request from browser (list/close/rename/resize/search)
+    SessionCommand(SessionCommand),
+    /// A paired browser asked for the output key
+    E2eHello { public_key: String },
+    /// A one-time join link token, valid for `expires_in_secs`, letting
+    /// its browser in with at most `role` (None from older relays)
+    JoinToken { token: String, expires_in_secs: u64, role: Option<Role> },
+    /// Someone watching wrote in the session chat
+    Chat { name: Option<String>, text: String },
 }
 
 /// Commands sent to RelayClient for sending data to relay.
@@ -35,13 +78,43 @@ pub enum RelayCommand {
     /// Send terminal data to relay (shell output -> browser)
     SendTerminalData { session_id: String, data: Vec<u8> },
     /// Send session list to relay (for browser)
-    SendSessionList { sessions: Vec<(String, String)> }, // (id, name)
+    SendSessionList { sessions: Vec<SessionInfo> },
     /// Notify relay that a session connected
     SendSessionConnected { session_id: String, name: String },
     /// Notify relay that a session disconnected
     SendSessionDisconnected { session_id: String },
+    /// Notify relay that a session was renamed
+    SendSessionRenamed { session_id: String, name: String },
+    /// Tell the relay what changed about a session
+    SendSessionMetadata { session_id: String, metadata: SessionMetadata },
     /// Notify relay that a session resized (mac -> browser)
     SendSessionResize { session_id: String, cols: u16, rows: u16 },
+    /// Report that a browser session command failed
+    SendSessionError { session_id: String, message: String },
+    /// Answer a browser's scrollback search
+    SendSearchResults {
+        browser_id: String,
+        q: String,
+        matches: Vec<SearchMatch>,
+        truncated: bool,
+    },
+    /// Forward a shell's OSC 52 copy to browsers (base64 payload)
+    SendClipboard { session_id: String, data: String },
+    /// Tell browsers that output is end-to-end encrypted
+    SendE2eRequired { fingerprint: String },
+    /// Send the wrapped output key to the browser that sent `public_key`
+    SendE2eKey { public_key: String, key: String },
+    /// Tell the relay to refuse (or accept again) new browsers
+    SendSharingPaused { paused: bool },
+    /// Tell the relay to hold new browsers until they are approved
+    SendApprovalRequired { required: bool },
+    /// Let a waiting browser in, or turn it away
+    SendBrowserApproval { browser_id: String, approved: bool },
+    /// Grant or revoke a connected browser's input
+    SendBrowserRole { browser_id: String, role: Role },
+    /// Ask for a join link token that works once within `ttl_secs`, with
+    /// at most `role`
+    CreateJoinToken { ttl_secs: u64, role: Role },
     /// Disconnect and reconnect to get a new session code
     Reconnect,
 }
@@ -51,9 +124,39 @@ pub enum RelayCommand {
 pub struct RelayClient {
     relay_url: String,
     client_id: String,
+    /// Display name sent at registration
+    client_name: Option<String>,
     event_tx: Sender<RelayEvent>,
     command_rx: tokio::sync::mpsc::UnboundedReceiver<RelayCommand>,
     reconnect_attempts: u32,
+    /// ICE servers for direct mode, `None` when browsers stay on the relay
+    direct: Option<Vec<String>>,
+    /// URLs of relays to switch to, chosen from the menu bar
+    switch_rx: Option<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    /// Bumped on every connection attempt and ping, for the watchdog
+    heartbeat: Option<Heartbeat>,
+    /// Password browsers must send to join, registered with the relay
+    password: Option<String>,
+    /// Output is end-to-end encrypted, registered as a passthrough session
+    passthrough: bool,
+    /// Ask the relay to record the session for playback
+    record: bool,
+    /// Let the relay compress output to browsers that support it
+    compress: bool,
+    /// Browsers the relay lets into the session at once
+    max_browsers: Option<usize>,
+    /// Scrollback the relay keeps per terminal, if not its default
+    scrollback_bytes: Option<u64>,
+    /// Key the relay wants before it hosts the session, if it does
+    api_key: Option<String>,
+    /// The relay keeps nothing of the session
+    zero_retention: bool,
+    /// From the last registration: gets the same session code back when
+    /// reconnecting to the same relay
+    resume_token: std::sync::Mutex<Option<String>>,
+    /// Code of the last registration, named when resuming so a load
+    /// balancer routing on codes sends us back to the relay holding it
+    session_code: std::sync::Mutex<Option<String>>,
 }
 
 impl RelayClient {
@@ -74,12 +177,120 @@ impl RelayClient {
         Self {
             relay_url,
             client_id,
+            client_name: None,
             event_tx,
             command_rx,
             reconnect_attempts: 0,
+            direct: None,
+            switch_rx: None,
+            heartbeat: None,
+            password: None,
+            passthrough: false,
+            record: false,
+            compress: true,
+            max_browsers: None,
+            scrollback_bytes: None,
+            api_key: None,
+            zero_retention: false,
+            resume_token: std::sync::Mutex::new(None),
+            session_code: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Register with a stable client ID and display name instead of an ID
+    /// for this run only.
+    pub fn with_identity(mut self, identity: ClientIdentity) -> Self {
+        self.client_id = identity.id;
+        self.client_name = Some(identity.name);
+        self
+    }
+
+    /// Answer browsers' WebRTC offers so their terminal frames can bypass
+    /// the relay, using `ice_servers` (STUN/TURN URLs) to find a path.
+    pub fn with_direct_mode(mut self, ice_servers: Vec<String>) -> Self {
+        self.direct = Some(ice_servers);
+        self
+    }
+
+    /// Leave the current relay for the one whose URL arrives on `switch_rx`,
+    /// right away, also while waiting to reconnect.
+    pub fn with_relay_switch(mut self, switch_rx: tokio::sync::mpsc::UnboundedReceiver<String>) -> Self {
+        self.switch_rx = Some(switch_rx);
+        self
+    }
+
+    /// Have the relay turn away browsers that do not send `password`.
+    pub fn with_password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+
+    /// Tell the relay output is end-to-end encrypted, so it keeps frames
+    /// opaque and leaves session contents out of its logs.
+    pub fn with_passthrough(mut self) -> Self {
+        self.passthrough = true;
+        self
+    }
+
+    /// Have the relay record session output after every registration.
+    pub fn with_recording(mut self) -> Self {
+        self.record = true;
+        self
+    }
+
+    /// Have the relay turn away browsers once `max` are in the session.
+    pub fn with_browser_limit(mut self, max: usize) -> Self {
+        self.max_browsers = Some(max);
+        self
+    }
+
+    /// Have the relay keep no more than `bytes` of scrollback per terminal
+    /// for replay to browsers; 0 keeps none.
+    pub fn with_scrollback_limit(mut self, bytes: usize) -> Self {
+        self.scrollback_bytes = Some(bytes as u64);
+        self
+    }
+
+    /// Have the relay keep nothing of the session, in memory or on disk:
+    /// browsers only see output from when they join, and recording is off.
+    pub fn with_zero_retention(mut self) -> Self {
+        self.zero_retention = true;
+        self
+    }
+
+    /// Register with `key`, for relays that only host sessions for Macs
+    /// their operator gave a key.
+    pub fn with_api_key(mut self, key: String) -> Self {
+        self.api_key = Some(key);
+        self
+    }
+
+    /// Have the relay send output to browsers uncompressed.
+    pub fn without_compression(mut self) -> Self {
+        self.compress = false;
+        self
+    }
+
+    /// Show signs of life on `heartbeat` (see [`crate::watchdog`]).
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    fn beat(&self) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.beat();
         }
     }
 
+    /// Use `url` from the next connection on.
+    fn switch_to(&mut self, url: String) {
+        tracing::info!("Switching relay: {} -> {}", self.relay_url, url);
+        self.relay_url = url;
+        self.reconnect_attempts = 0;
+        *self.resume_token.lock().unwrap() = None;
+    }
+
     /// Main run loop. Connects to relay and auto-reconnects on disconnect.
     /// This method runs forever (until the task is cancelled).
     pub async fn run(&mut self) {
@@ -91,6 +302,12 @@ impl RelayClient {
                 }
                 Err(e) => {
                     tracing::error!("Connection error: {}", e);
+                    if self.reconnect_attempts + 1 >= UNREACHABLE_AFTER_ATTEMPTS {
+                        let _ = self.event_tx.send(RelayEvent::Unreachable(format!(
+                            "Relay unreachable at {}",
+                            self.relay_url
+                        )));
+                    }
                 }
             }
 
@@ -100,17 +317,30 @@ impl RelayClient {
             // Exponential backoff: 1s, 2s, 4s, 8s, 16s, 32s max
             let delay_secs = (2u64).pow(self.reconnect_attempts.min(5));
             tracing::info!("Reconnecting in {}s...", delay_secs);
-            tokio::time::sleep(Duration::from_secs(delay_secs)).await;
-            self.reconnect_attempts += 1;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(delay_secs)) => {
+                    self.reconnect_attempts += 1;
+                }
+                Some(url) = next_switch(self.switch_rx.as_mut()) => self.switch_to(url),
+            }
         }
     }
 
     /// Connect to relay, register, and handle messages until disconnected.
     async fn connect_and_run(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         tracing::info!("Connecting to relay: {}", self.relay_url);
+        self.beat();
 
         // Connect to WebSocket
-        let (ws_stream, _response) = connect_async(&self.relay_url).await?;
+        let mut request = self.relay_url.as_str().into_client_request()?;
+        if self.resume_token.lock().unwrap().is_some() {
+            if let Some(code) = self.session_code.lock().unwrap().as_deref() {
+                request.headers_mut().insert("x-relay-session", code.parse()?);
+            }
+        }
+        let (ws_stream, _response) = tokio::time::timeout(CONNECT_TIMEOUT, connect_async(request))
+            .await
+            .map_err(|_| format!("no answer from {} within {:?}", self.relay_url, CONNECT_TIMEOUT))??;
         tracing::info!("Connected to relay");
 
         // Notify main thread
@@ -124,19 +354,68 @@ impl RelayClient {
         // Send Register message
         let register_msg = ControlMessage::Register {
             client_id: self.client_id.clone(),
+            name: self.client_name.clone(),
+            password: self.password.clone(),
+            passthrough: self.passthrough,
+            version: Some(PROTOCOL_VERSION),
+            resume_token: self.resume_token.lock().unwrap().clone(),
+            scrollback_bytes: self.scrollback_bytes,
+            api_key: self.api_key.clone(),
+            zero_retention: self.zero_retention,
         };
         let json = serde_json::to_string(&register_msg)?;
-        tracing::debug!("Sending Register: {}", json);
+        tracing::debug!("Sending Register (password: {})", self.password.is_some());
         write.send(Message::Text(json.into())).await?;
+        if self.record {
+            let json = serde_json::to_string(&ControlMessage::SetRecording { enabled: true })?;
+            write.send(Message::Text(json.into())).await?;
+        }
+        if !self.compress {
+            let json = serde_json::to_string(&ControlMessage::SetCompression { enabled: false })?;
+            write.send(Message::Text(json.into())).await?;
+        }
+        if let Some(max) = self.max_browsers {
+            let json = serde_json::to_string(&ControlMessage::SetBrowserLimit { max: Some(max) })?;
+            write.send(Message::Text(json.into())).await?;
+        }
+
+        // Peer connections live only as long as this relay connection
+        let (direct_tx, mut direct_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut direct = self
+            .direct
+            .clone()
+            .map(|ice_servers| DirectLinks::new(ice_servers, direct_tx));
+
+        let mut probe = LatencyProbe::default();
+        let mut ping_timer = tokio::time::interval(PING_INTERVAL);
+        ping_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
         // Message handling loop - select on both WebSocket and commands
         loop {
             tokio::select! {
+                // Measure the round trip to the relay
+                _ = ping_timer.tick() => {
+                    self.beat();
+                    let payload = probe.ping(Instant::now());
+                    write.send(Message::Ping(payload.into())).await?;
+                    if let Some(stats) = probe.stats().filter(|s| s.loss_percent > 0) {
+                        let _ = self.event_tx.send(RelayEvent::Latency(stats));
+                    }
+                }
+
                 // Handle incoming WebSocket messages
                 msg_result = read.next() => {
                     match msg_result {
                         Some(Ok(Message::Text(text))) => {
-                            self.handle_text_message(&text)?;
+                            if let Some(msg) = self.handle_text_message(&text)? {
+                                let reply = match msg {
+                                    ControlMessage::LatencyEcho { .. } => Some(msg),
+                                    signal => Self::handle_signal(direct.as_mut(), signal).await,
+                                };
+                                if let Some(reply) = reply {
+                                    send_control(&mut write, &reply).await?;
+                                }
+                            }
                         }
                         Some(Ok(Message::Binary(data))) => {
                             // Binary messages are terminal I/O from browser
@@ -151,8 +430,13 @@ impl RelayClient {
                             tracing::trace!("Received ping, sending pong");
                             write.send(Message::Pong(data)).await?;
                         }
-                        Some(Ok(Message::Pong(_))) => {
-                            tracing::trace!("Received pong");
+                        Some(Ok(Message::Pong(data))) => {
+                            if let Some(rtt) = probe.pong(&data, Instant::now()) {
+                                tracing::trace!("Relay round trip: {:?}", rtt);
+                                if let Some(stats) = probe.stats() {
+                                    let _ = self.event_tx.send(RelayEvent::Latency(stats));
+                                }
+                            }
                         }
                         Some(Ok(Message::Frame(_))) => {
                             // Raw frame, typically not used directly
@@ -168,20 +452,48 @@ impl RelayClient {
                     }
                 }
 
+                // Signaling, input and state changes of direct links
+                Some(event) = direct_rx.recv() => {
+                    match event {
+                        DirectEvent::Signal(msg) => send_control(&mut write, &msg).await?,
+                        DirectEvent::Opened(browser_id) => {
+                            tracing::info!("Direct link to browser {} open", browser_id);
+                            let msg = ControlMessage::RtcDirect { browser_id, active: true };
+                            send_control(&mut write, &msg).await?;
+                        }
+                        // Input over a link counts only from controllers
+                        DirectEvent::Frame { browser_id, data } => {
+                            if direct.as_ref().is_some_and(|direct| direct.accepts_input(&browser_id)) {
+                                self.handle_binary_message(&data);
+                            } else {
+                                tracing::debug!("Dropping direct input from browser {}, not a controller", browser_id);
+                            }
+                        }
+                        DirectEvent::Closed(browser_id) => {
+                            let Some(direct) = direct.as_mut() else { continue };
+                            if direct.close(&browser_id).await {
+                                tracing::info!("Direct link to browser {} closed, back on the relay", browser_id);
+                                let msg = ControlMessage::RtcDirect { browser_id, active: false };
+                                send_control(&mut write, &msg).await?;
+                            }
+                        }
+                    }
+                }
+
                 // Handle commands from IPC (send terminal data to relay)
                 cmd = self.command_rx.recv() => {
                     match cmd {
                         Some(RelayCommand::SendTerminalData { session_id, data }) => {
-                            if let Err(e) = Self::send_terminal_data(&mut write, &session_id, &data).await {
+                            let frame = router::encode_frame(&session_id, &data);
+                            if let Some(direct) = &direct {
+                                direct.send(&frame).await;
+                            }
+                            if let Err(e) = Self::send_terminal_data(&mut write, &session_id, frame).await {
                                 tracing::warn!("Failed to send terminal data: {}", e);
                             }
                         }
                         Some(RelayCommand::SendSessionList { sessions }) => {
-                            let msg = ControlMessage::SessionList {
-                                sessions: sessions.into_iter().map(|(id, name)| {
-                                    crate::protocol::SessionInfo { id, name }
-                                }).collect(),
-                            };
+                            let msg = ControlMessage::SessionList { sessions };
                             let json = serde_json::to_string(&msg).unwrap();
                             tracing::debug!("Sending SessionList: {}", json);
                             if let Err(e) = write.send(Message::Text(json.into())).await {
@@ -204,6 +516,22 @@ impl RelayClient {
                                 tracing::warn!("Failed to send session disconnected: {}", e);
                             }
                         }
+                        Some(RelayCommand::SendSessionRenamed { session_id, name }) => {
+                            let msg = ControlMessage::SessionRenamed { session_id, name };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending SessionRenamed: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send session renamed: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendSessionMetadata { session_id, metadata }) => {
+                            let msg = ControlMessage::SessionMetadata { session_id, metadata };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending SessionMetadata: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send session metadata: {}", e);
+                            }
+                        }
                         Some(RelayCommand::SendSessionResize { session_id, cols, rows }) => {
                             let msg = ControlMessage::SessionResize { session_id, cols, rows };
                             let json = serde_json::to_string(&msg).unwrap();
@@ -212,8 +540,95 @@ impl RelayClient {
                                 tracing::warn!("Failed to send session resize: {}", e);
                             }
                         }
+                        Some(RelayCommand::SendSessionError { session_id, message }) => {
+                            let msg = ControlMessage::SessionError { session_id, message };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending SessionError: {}", json);
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send session error: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendSearchResults { browser_id, q, matches, truncated }) => {
+                            let msg = ControlMessage::SearchResults { browser_id, q, matches, truncated };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending SearchResults ({} bytes)", json.len());
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send search results: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendClipboard { session_id, data }) => {
+                            let msg = ControlMessage::Clipboard { session_id, data };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending Clipboard ({} bytes)", json.len());
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send clipboard: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendSharingPaused { paused }) => {
+                            let msg = ControlMessage::SharingPaused { paused };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send sharing_paused: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendApprovalRequired { required }) => {
+                            let msg = ControlMessage::ApprovalRequired { required };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send approval_required: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendBrowserApproval { browser_id, approved }) => {
+                            let msg = ControlMessage::ApproveBrowser { browser_id, approved, role: None };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send approve_browser: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendBrowserRole { browser_id, role }) => {
+                            let msg = ControlMessage::SetRole { browser_id: browser_id.clone(), role };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send set_role: {}", e);
+                            }
+                            // A viewer's input must not reach the shells past the relay
+                            let Some(direct) = direct.as_mut() else { continue };
+                            if direct.set_role(&browser_id, role).await {
+                                tracing::info!("Closed the direct link to browser {}, now a viewer", browser_id);
+                                let msg = ControlMessage::RtcDirect { browser_id, active: false };
+                                send_control(&mut write, &msg).await?;
+                            }
+                        }
+                        Some(RelayCommand::CreateJoinToken { ttl_secs, role }) => {
+                            let msg = ControlMessage::CreateJoinToken {
+                                ttl_secs: Some(ttl_secs),
+                                role: Some(role),
+                                uses: None,
+                            };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send create_join_token: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendE2eRequired { fingerprint }) => {
+                            let msg = ControlMessage::E2eRequired { fingerprint };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send e2e_required: {}", e);
+                            }
+                        }
+                        Some(RelayCommand::SendE2eKey { public_key, key }) => {
+                            let msg = ControlMessage::E2eKey { public_key, key };
+                            let json = serde_json::to_string(&msg).unwrap();
+                            tracing::debug!("Sending wrapped output key");
+                            if let Err(e) = write.send(Message::Text(json.into())).await {
+                                tracing::warn!("Failed to send e2e_key: {}", e);
+                            }
+                        }
                         Some(RelayCommand::Reconnect) => {
                             tracing::info!("Reconnect requested, closing connection");
+                            // A new session code, not the one browsers know
+                            *self.resume_token.lock().unwrap() = None;
                             let _ = write.send(Message::Close(None)).await;
                             break;
                         }
@@ -223,33 +638,34 @@ impl RelayClient {
                         }
                     }
                 }
+
+                // Leave for another relay
+                Some(url) = next_switch(self.switch_rx.as_mut()) => {
+                    self.switch_to(url);
+                    let _ = write.send(Message::Close(None)).await;
+                    break;
+                }
             }
         }
 
         Ok(())
     }
 
-    /// Send terminal data to relay for a specific session.
+    /// Send a terminal data frame to relay for a specific session.
     ///
-    /// Frame format: 1 byte session_id length + session_id bytes + terminal data
+    /// The frame is tagged with the session ID by the router.
     async fn send_terminal_data<S>(
         write: &mut S,
         session_id: &str,
-        data: &[u8],
+        frame: Vec<u8>,
     ) -> Result<(), Box<dyn Error + Send + Sync>>
     where
         S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
     {
-        // Frame format: 1 byte session_id length + session_id + data
-        let mut frame = Vec::with_capacity(1 + session_id.len() + data.len());
-        frame.push(session_id.len() as u8);
-        frame.extend_from_slice(session_id.as_bytes());
-        frame.extend_from_slice(data);
-
         tracing::trace!(
-            "Sending terminal data: session={}, {} bytes",
+            "Sending terminal data: session={}, {} byte frame",
             session_id,
-            data.len()
+            frame.len()
         );
         write.send(Message::Binary(frame.into())).await?;
         Ok(())
@@ -257,77 +673,126 @@ impl RelayClient {
 
     /// Handle a binary message from the relay server (browser input -> shell).
     ///
-    /// Frame format: 1 byte session_id length + session_id bytes + payload
-    /// Payload can be either:
-    /// - Raw terminal input (keystrokes)
-    /// - JSON control message (e.g., {"type":"resize","cols":80,"rows":24})
+    /// Frames are decoded by the router; see `router::decode_inbound`.
     fn handle_binary_message(&self, data: &[u8]) {
-        if data.len() < 2 {
-            tracing::warn!("Binary message too short: {} bytes", data.len());
-            return;
-        }
-
-        let id_len = data[0] as usize;
-        if data.len() < 1 + id_len {
-            tracing::warn!(
-                "Binary message malformed: id_len={} but only {} bytes total",
-                id_len,
-                data.len()
-            );
-            return;
+        match router::decode_inbound(data) {
+            Ok(InboundFrame::Input { session_id, data }) => {
+                tracing::trace!(
+                    "Received terminal data: session={}, {} bytes",
+                    session_id,
+                    data.len()
+                );
+                let _ = self.event_tx.send(RelayEvent::TerminalData { session_id, data });
+            }
+            Err(e) => {
+                tracing::warn!("Binary message rejected: {}", e);
+            }
         }
+    }
 
-        let session_id = String::from_utf8_lossy(&data[1..1 + id_len]).to_string();
-        let payload = &data[1 + id_len..];
-
-        // Check if payload is a JSON control message (starts with '{')
-        if payload.first() == Some(&b'{') {
-            if let Ok(text) = std::str::from_utf8(payload) {
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
-                    let msg_type = json.get("type").and_then(|t| t.as_str());
-
-                    if msg_type == Some("close_session") {
-                        tracing::info!("Received close_session: session={}", session_id);
-                        let _ = self.event_tx.send(RelayEvent::CloseSession {
-                            session_id,
-                        });
-                        return;
+    /// Answer a browser's WebRTC signaling, returning the reply for the relay.
+    async fn handle_signal(
+        direct: Option<&mut DirectLinks>,
+        msg: ControlMessage,
+    ) -> Option<ControlMessage> {
+        let Some(direct) = direct else {
+            tracing::debug!("Direct mode off, ignoring {:?}", msg);
+            return None;
+        };
+        match msg {
+            ControlMessage::BrowserConnected { browser_id, role } => {
+                // Relays from before roles let every browser control
+                direct.browser_connected(browser_id, role.unwrap_or(Role::Controller));
+                None
+            }
+            ControlMessage::BrowserDisconnected { browser_id } => {
+                direct.browser_disconnected(&browser_id).await;
+                None
+            }
+            ControlMessage::RtcOffer { browser_id, .. } if !direct.is_connected(&browser_id) => {
+                tracing::warn!("Ignoring direct link offer from unknown browser {}", browser_id);
+                None
+            }
+            ControlMessage::RtcOffer { browser_id, sdp } => {
+                match direct.offer(&browser_id, sdp).await {
+                    Ok(sdp) => Some(ControlMessage::RtcAnswer { browser_id, sdp }),
+                    Err(e) => {
+                        tracing::warn!("Cannot answer browser {}: {}", browser_id, e);
+                        None
                     }
                 }
             }
+            ControlMessage::RtcCandidate { browser_id, candidate, sdp_mid, sdp_mline_index } => {
+                if let Err(e) = direct
+                    .candidate(&browser_id, candidate, sdp_mid, sdp_mline_index)
+                    .await
+                {
+                    tracing::debug!("Ignoring ICE candidate from browser {}: {}", browser_id, e);
+                }
+                None
+            }
+            _ => None,
         }
-
-        // Regular terminal input
-        tracing::trace!(
-            "Received terminal data: session={}, {} bytes",
-            session_id,
-            payload.len()
-        );
-
-        let _ = self.event_tx.send(RelayEvent::TerminalData {
-            session_id,
-            data: payload.to_vec(),
-        });
     }
 
-    /// Handle a text message from the relay server.
-    fn handle_text_message(&self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    /// Handle a text message from the relay server. WebRTC signaling and
+    /// browsers joining or leaving are returned for the caller to answer or
+    /// hand to the direct links, and the echo of a latency probe for it to
+    /// send.
+    fn handle_text_message(
+        &self,
+        text: &str,
+    ) -> Result<Option<ControlMessage>, Box<dyn Error + Send + Sync>> {
         tracing::debug!("Received text message: {}", text);
 
         let msg: ControlMessage = serde_json::from_str(text)?;
 
         match msg {
-            ControlMessage::Registered { code } => {
-                tracing::info!("Registered with session code: {}", code);
+            ControlMessage::Registered { code, version, resume_token } => {
+                tracing::info!(
+                    "Registered with session code: {} (protocol version {})",
+                    code,
+                    version.unwrap_or(1)
+                );
+                *self.resume_token.lock().unwrap() = resume_token;
+                *self.session_code.lock().unwrap() = Some(code.clone());
                 let _ = self.event_tx.send(RelayEvent::SessionCode(code));
             }
-            ControlMessage::BrowserConnected { browser_id } => {
+            ControlMessage::BrowserConnected { ref browser_id, .. } => {
                 tracing::info!("Browser connected: {}", browser_id);
-                let _ = self.event_tx.send(RelayEvent::BrowserConnected(browser_id));
+                let _ = self.event_tx.send(RelayEvent::BrowserConnected(browser_id.clone()));
+                return Ok(Some(msg));
             }
-            ControlMessage::BrowserDisconnected { browser_id } => {
+            ControlMessage::BrowserDisconnected { ref browser_id } => {
                 tracing::info!("Browser disconnected: {}", browser_id);
-                let _ = self.event_tx.send(RelayEvent::BrowserDisconnected(browser_id));
+                let _ = self.event_tx.send(RelayEvent::BrowserDisconnected(browser_id.clone()));
+                return Ok(Some(msg));
+            }
+            ControlMessage::ApprovalRequest { browser_id, browser_key, user_agent } => {
+                tracing::info!("Browser {} asks to be approved", browser_id);
+                let _ = self.event_tx.send(RelayEvent::ApprovalRequest {
+                    browser_id,
+                    browser_key,
+                    user_agent,
+                });
+            }
+            ControlMessage::ApprovalCancelled { browser_id } => {
+                tracing::info!("Browser {} stopped waiting for approval", browser_id);
+                let _ = self.event_tx.send(RelayEvent::ApprovalCancelled(browser_id));
+            }
+            ControlMessage::RecordingState { recording_id } => match recording_id {
+                Some(id) => tracing::info!("Relay is recording this session as {}", id),
+                None => tracing::info!("Relay stopped recording this session"),
+            },
+            ControlMessage::JoinToken { token, expires_in_secs, role, .. } => {
+                tracing::info!("Got a join link token, valid for {}s, role {:?}", expires_in_secs, role);
+                let _ = self.event_tx.send(RelayEvent::JoinToken { token, expires_in_secs, role });
+            }
+            ControlMessage::Presence { viewers } => {
+                let _ = self.event_tx.send(RelayEvent::Presence(viewers));
+            }
+            ControlMessage::Chat { name, text, .. } => {
+                let _ = self.event_tx.send(RelayEvent::Chat { name, text });
             }
             ControlMessage::Error { message } => {
                 tracing::error!("Relay error: {}", message);
@@ -337,16 +802,77 @@ impl RelayClient {
                 tracing::info!("Received create_session request from browser");
                 let _ = self.event_tx.send(RelayEvent::CreateSession);
             }
+            ControlMessage::ListSessions => {
+                let _ = self
+                    .event_tx
+                    .send(RelayEvent::SessionCommand(SessionCommand::List));
+            }
+            ControlMessage::CloseSession { session_id } => {
+                let _ = self
+                    .event_tx
+                    .send(RelayEvent::SessionCommand(SessionCommand::Close { session_id }));
+            }
+            ControlMessage::RenameSession { session_id, name } => {
+                let _ = self
+                    .event_tx
+                    .send(RelayEvent::SessionCommand(SessionCommand::Rename { session_id, name }));
+            }
+            ControlMessage::ResizeSession {
+                browser_id,
+                session_id,
+                cols,
+                rows,
+            } => {
+                let _ = self.event_tx.send(RelayEvent::SessionCommand(SessionCommand::Resize {
+                    browser_id,
+                    session_id,
+                    cols,
+                    rows,
+                }));
+            }
+            ControlMessage::Search { browser_id, session_id, q } => {
+                let _ = self.event_tx.send(RelayEvent::SessionCommand(SessionCommand::Search {
+                    browser_id,
+                    session_id,
+                    q,
+                }));
+            }
+            ControlMessage::E2eHello { public_key } => {
+                let _ = self.event_tx.send(RelayEvent::E2eHello { public_key });
+            }
+            ControlMessage::RtcOffer { .. } | ControlMessage::RtcCandidate { .. } => {
+                return Ok(Some(msg));
+            }
+            // Answered right away, so the round trip shows the network only
+            ControlMessage::LatencyProbe { id, sent_at, browser_id, relay_at, .. } => {
+                return Ok(Some(ControlMessage::LatencyEcho {
+                    id,
+                    sent_at,
+                    browser_id,
+                    relay_at,
+                    mac_rtt_ms: None,
+                }));
+            }
             // Other message types are for browser<->relay communication
             _ => {
                 tracing::warn!("Received unexpected message type: {:?}", msg);
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 }
 
+/// Send a control message to the relay as JSON text.
+async fn send_control<S>(write: &mut S, msg: &ControlMessage) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+{
+    let json = serde_json::to_string(msg)?;
+    write.send(Message::Text(json.into())).await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -360,6 +886,7 @@ mod tests {
         let _browser_conn = RelayEvent::BrowserConnected("browser-id".into());
         let _browser_disc = RelayEvent::BrowserDisconnected("browser-id".into());
         let _error = RelayEvent::Error("test error".into());
+        let _unreachable = RelayEvent::Unreachable("relay unreachable".into());
         let _terminal_data = RelayEvent::TerminalData {
             session_id: "sess-1".into(),
             data: vec![0x68, 0x65, 0x6c, 0x6c, 0x6f],
@@ -384,4 +911,59 @@ mod tests {
         assert!(uuid::Uuid::parse_str(&client.client_id).is_ok());
         assert_eq!(client.reconnect_attempts, 0);
     }
+
+    #[test]
+    fn test_text_session_commands_dispatched() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let client = RelayClient::new("ws://localhost:3000/ws".into(), tx, cmd_rx);
+
+        client
+            .handle_text_message(r#"{"type":"rename_session","session_id":"s1","name":"logs"}"#)
+            .unwrap();
+        client.handle_text_message(r#"{"type":"list_sessions"}"#).unwrap();
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(RelayEvent::SessionCommand(SessionCommand::Rename { ref name, .. })) if name == "logs"
+        ));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(RelayEvent::SessionCommand(SessionCommand::List))
+        ));
+    }
+
+    #[test]
+    fn test_rtc_signaling_returned_to_caller() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let client = RelayClient::new("ws://localhost:3000/ws".into(), tx, cmd_rx);
+
+        let signal = client
+            .handle_text_message(r#"{"type":"rtc_offer","browser_id":"b1","sdp":"v=0"}"#)
+            .unwrap();
+        assert!(matches!(
+            signal,
+            Some(ControlMessage::RtcOffer { ref browser_id, .. }) if browser_id == "b1"
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_latency_probe_echoed() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (_cmd_tx, cmd_rx) = tokio::sync::mpsc::unbounded_channel();
+        let client = RelayClient::new("ws://localhost:3000/ws".into(), tx, cmd_rx);
+
+        let echo = client
+            .handle_text_message(
+                r#"{"type":"latency_probe","id":7,"sent_at":99.5,"browser_id":"b1","relay_at":1760000000000}"#,
+            )
+            .unwrap();
+        assert!(matches!(
+            echo,
+            Some(ControlMessage::LatencyEcho { id: 7, ref browser_id, relay_at: Some(1760000000000), .. }) if browser_id == "b1"
+        ));
+        assert!(rx.try_recv().is_err());
+    }
 }
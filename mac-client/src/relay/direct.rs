@@ -0,0 +1,369 @@
+//! Direct mode: terminal frames over peer-to-peer WebRTC data channels.
+//!
+//! The relay is only used for signaling. A browser sends an `rtc_offer` and
+//! its ICE candidates over its WebSocket; the relay tags them with the
+//! browser's id and [`DirectLinks`] answers through the relay the same way.
+//! Once the browser's data channel opens, terminal frames (the same bytes as
+//! the relay's binary frames) travel over it in both directions and the relay
+//! is told to stop forwarding output to that browser. If ICE never connects
+//! the browser simply stays on the relay; if the channel closes the relay is
+//! told to resume.
+//!
+//! The relay never sees what travels over a link, so the Mac checks roles
+//! itself: only browsers the relay reported as connected get a link, input
+//! over a link counts only while its browser is a controller, and a link is
+//! closed when its browser leaves or is made a viewer.
+
+use crate::protocol::{ControlMessage, Role};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use webrtc::api::{APIBuilder, API};
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// Something a direct link needs the relay connection to do.
+#[derive(Debug)]
+pub enum DirectEvent {
+    /// Send a signaling message to the relay
+    Signal(ControlMessage),
+    /// The browser's data channel opened
+    Opened(String),
+    /// A frame arrived on a browser's data channel (its input)
+    Frame { browser_id: String, data: Vec<u8> },
+    /// The link to the browser failed or closed
+    Closed(String),
+}
+
+/// A peer connection to one browser.
+struct Link {
+    peer: Arc<RTCPeerConnection>,
+    /// Set once the browser's data channel is open
+    channel: Arc<Mutex<Option<Arc<RTCDataChannel>>>>,
+}
+
+/// Peer connections to the browsers of one relay connection.
+pub struct DirectLinks {
+    api: API,
+    ice_servers: Vec<String>,
+    links: HashMap<String, Link>,
+    /// Browsers in the session and what they may do, as the relay reported
+    roles: HashMap<String, Role>,
+    event_tx: mpsc::UnboundedSender<DirectEvent>,
+}
+
+impl DirectLinks {
+    /// `ice_servers` are STUN/TURN URLs used to find a path to browsers on
+    /// other networks; browsers on the same network connect without them.
+    pub fn new(ice_servers: Vec<String>, event_tx: mpsc::UnboundedSender<DirectEvent>) -> Self {
+        Self {
+            api: APIBuilder::new().build(),
+            ice_servers,
+            links: HashMap::new(),
+            roles: HashMap::new(),
+            event_tx,
+        }
+    }
+
+    /// A browser joined the session with `role`.
+    pub fn browser_connected(&mut self, browser_id: String, role: Role) {
+        self.roles.insert(browser_id, role);
+    }
+
+    /// A browser left the session; its link goes with it.
+    pub async fn browser_disconnected(&mut self, browser_id: &str) {
+        self.roles.remove(browser_id);
+        self.close(browser_id).await;
+    }
+
+    /// The Mac changed what a browser may do. Returns true if that closed
+    /// its open link, for the relay to forward its output again.
+    pub async fn set_role(&mut self, browser_id: &str, role: Role) -> bool {
+        let Some(current) = self.roles.get_mut(browser_id) else {
+            return false;
+        };
+        *current = role;
+        role == Role::Viewer && self.close(browser_id).await
+    }
+
+    /// Whether the relay reported the browser as in the session, so it may
+    /// have a link.
+    pub fn is_connected(&self, browser_id: &str) -> bool {
+        self.roles.contains_key(browser_id)
+    }
+
+    /// Whether input arriving on the browser's link counts.
+    pub fn accepts_input(&self, browser_id: &str) -> bool {
+        self.links.contains_key(browser_id) && self.roles.get(browser_id) == Some(&Role::Controller)
+    }
+
+    /// Answer a browser's offer, replacing any earlier link to it. Returns
+    /// the SDP answer; local ICE candidates follow as [`DirectEvent::Signal`].
+    pub async fn offer(&mut self, browser_id: &str, sdp: String) -> Result<String, webrtc::Error> {
+        self.close(browser_id).await;
+
+        let config = RTCConfiguration {
+            ice_servers: vec![RTCIceServer {
+                urls: self.ice_servers.clone(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let peer = Arc::new(self.api.new_peer_connection(config).await?);
+        let channel = Arc::new(Mutex::new(None));
+        self.watch(browser_id, &peer, &channel);
+
+        let result = async {
+            peer.set_remote_description(RTCSessionDescription::offer(sdp)?)
+                .await?;
+            let answer = peer.create_answer(None).await?;
+            let sdp = answer.sdp.clone();
+            peer.set_local_description(answer).await?;
+            Ok(sdp)
+        }
+        .await;
+        if result.is_err() {
+            let _ = peer.close().await;
+        } else {
+            self.links.insert(browser_id.to_string(), Link { peer, channel });
+        }
+        result
+    }
+
+    /// Hook up the callbacks that report candidates, the data channel and
+    /// failures of `peer` as [`DirectEvent`]s.
+    fn watch(
+        &self,
+        browser_id: &str,
+        peer: &RTCPeerConnection,
+        slot: &Arc<Mutex<Option<Arc<RTCDataChannel>>>>,
+    ) {
+        let (id, tx) = (browser_id.to_string(), self.event_tx.clone());
+        peer.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let (id, tx) = (id.clone(), tx.clone());
+            Box::pin(async move {
+                let Some(init) = candidate.and_then(|c| c.to_json().ok()) else {
+                    return;
+                };
+                let _ = tx.send(DirectEvent::Signal(ControlMessage::RtcCandidate {
+                    browser_id: id,
+                    candidate: init.candidate,
+                    sdp_mid: init.sdp_mid,
+                    sdp_mline_index: init.sdp_mline_index,
+                }));
+            })
+        }));
+
+        let (id, tx) = (browser_id.to_string(), self.event_tx.clone());
+        peer.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+            if matches!(
+                state,
+                RTCPeerConnectionState::Failed
+                    | RTCPeerConnectionState::Disconnected
+                    | RTCPeerConnectionState::Closed
+            ) {
+                tracing::info!("Direct link to browser {} {}", id, state);
+                let _ = tx.send(DirectEvent::Closed(id.clone()));
+            }
+            Box::pin(async {})
+        }));
+
+        let (id, tx, slot) = (browser_id.to_string(), self.event_tx.clone(), slot.clone());
+        peer.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+            let (id, tx, slot) = (id.clone(), tx.clone(), slot.clone());
+            Box::pin(async move {
+                let open = channel.clone();
+                let (open_id, open_tx) = (id.clone(), tx.clone());
+                channel.on_open(Box::new(move || {
+                    *slot.lock().unwrap() = Some(open);
+                    let _ = open_tx.send(DirectEvent::Opened(open_id));
+                    Box::pin(async {})
+                }));
+                let (frame_id, frame_tx) = (id.clone(), tx.clone());
+                channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                    if !msg.is_string {
+                        let _ = frame_tx.send(DirectEvent::Frame {
+                            browser_id: frame_id.clone(),
+                            data: msg.data.to_vec(),
+                        });
+                    }
+                    Box::pin(async {})
+                }));
+                channel.on_close(Box::new(move || {
+                    let _ = tx.send(DirectEvent::Closed(id.clone()));
+                    Box::pin(async {})
+                }));
+            })
+        }));
+    }
+
+    /// Add a browser's trickled ICE candidate.
+    pub async fn candidate(
+        &self,
+        browser_id: &str,
+        candidate: String,
+        sdp_mid: Option<String>,
+        sdp_mline_index: Option<u16>,
+    ) -> Result<(), webrtc::Error> {
+        let Some(link) = self.links.get(browser_id) else {
+            return Ok(());
+        };
+        link.peer
+            .add_ice_candidate(RTCIceCandidateInit {
+                candidate,
+                sdp_mid,
+                sdp_mline_index,
+                username_fragment: None,
+            })
+            .await
+    }
+
+    /// Send a terminal frame to every browser in the session with an open
+    /// data channel.
+    pub async fn send(&self, frame: &[u8]) {
+        if self.links.is_empty() {
+            return;
+        }
+        let data = Bytes::copy_from_slice(frame);
+        let links = self.links.iter().filter(|(browser_id, _)| self.roles.contains_key(*browser_id));
+        for (browser_id, link) in links {
+            let channel = link.channel.lock().unwrap().clone();
+            if let Some(channel) = channel {
+                if let Err(e) = channel.send(&data).await {
+                    tracing::warn!("Direct send to browser {} failed: {}", browser_id, e);
+                }
+            }
+        }
+    }
+
+    /// Close the link to a browser. Returns true if its data channel was open,
+    /// i.e. the relay had stopped forwarding output to it.
+    pub async fn close(&mut self, browser_id: &str) -> bool {
+        let Some(link) = self.links.remove(browser_id) else {
+            return false;
+        };
+        let was_open = link.channel.lock().unwrap().take().is_some();
+        if let Err(e) = link.peer.close().await {
+            tracing::debug!("Closing direct link to browser {}: {}", browser_id, e);
+        }
+        was_open
+    }
+
+}
+
+impl Drop for DirectLinks {
+    /// The relay connection the links were signaled over is gone; close them.
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        for (_, link) in self.links.drain() {
+            runtime.spawn(async move {
+                let _ = link.peer.close().await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A webrtc-rs peer playing the browser: offers, trickles candidates and
+    /// exchanges frames with [`DirectLinks`] on this machine.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_frames_flow_over_data_channel() {
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        let mut links = DirectLinks::new(Vec::new(), event_tx);
+        links.browser_connected("b1".into(), Role::Controller);
+
+        let browser = APIBuilder::new()
+            .build()
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap();
+        let channel = browser.create_data_channel("terminal", None).await.unwrap();
+        let (candidate_tx, mut candidates) = mpsc::unbounded_channel();
+        browser.on_ice_candidate(Box::new(move |c: Option<RTCIceCandidate>| {
+            if let Some(init) = c.and_then(|c| c.to_json().ok()) {
+                let _ = candidate_tx.send(init);
+            }
+            Box::pin(async {})
+        }));
+        let (output_tx, mut output) = mpsc::unbounded_channel();
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let _ = output_tx.send(msg.data.to_vec());
+            Box::pin(async {})
+        }));
+
+        let offer = browser.create_offer(None).await.unwrap();
+        browser.set_local_description(offer.clone()).await.unwrap();
+        let answer = links.offer("b1", offer.sdp).await.unwrap();
+        browser
+            .set_remote_description(RTCSessionDescription::answer(answer).unwrap())
+            .await
+            .unwrap();
+
+        let connect = async {
+            loop {
+                tokio::select! {
+                    Some(init) = candidates.recv() => {
+                        links
+                            .candidate("b1", init.candidate, init.sdp_mid, init.sdp_mline_index)
+                            .await
+                            .unwrap();
+                    }
+                    Some(event) = events.recv() => match event {
+                        DirectEvent::Signal(ControlMessage::RtcCandidate {
+                            candidate, sdp_mid, sdp_mline_index, ..
+                        }) => {
+                            let init = RTCIceCandidateInit {
+                                candidate,
+                                sdp_mid,
+                                sdp_mline_index,
+                                username_fragment: None,
+                            };
+                            browser.add_ice_candidate(init).await.unwrap();
+                        }
+                        DirectEvent::Opened(browser_id) => return browser_id,
+                        other => panic!("unexpected {:?}", other),
+                    },
+                }
+            }
+        };
+        let opened = tokio::time::timeout(Duration::from_secs(20), connect).await;
+        assert_eq!(opened.unwrap(), "b1");
+
+        links.send(b"\x02s1output").await;
+        let received = tokio::time::timeout(Duration::from_secs(5), output.recv()).await;
+        assert_eq!(received.unwrap().unwrap(), b"\x02s1output");
+
+        channel.send(&Bytes::from_static(b"\x02s1input")).await.unwrap();
+        let input = async {
+            loop {
+                if let Some(DirectEvent::Frame { browser_id, data }) = events.recv().await {
+                    return (browser_id, data);
+                }
+            }
+        };
+        let input = tokio::time::timeout(Duration::from_secs(5), input).await;
+        assert_eq!(input.unwrap(), ("b1".to_string(), b"\x02s1input".to_vec()));
+        assert!(links.accepts_input("b1"));
+
+        // Made a viewer, the browser loses its link
+        assert!(links.set_role("b1", Role::Viewer).await);
+        assert!(!links.accepts_input("b1"));
+        assert!(!links.close("b1").await);
+        links.browser_disconnected("b1").await;
+        assert!(!links.is_connected("b1"));
+        let _ = browser.close().await;
+    }
+}
@@ -0,0 +1,173 @@
+//! Round-trip time to the relay, measured with WebSocket pings.
+//!
+//! Every [`PING_INTERVAL`] the relay client sends a ping carrying a sequence
+//! number; the relay's pong echoes it back. A ping still unanswered when the
+//! next one goes out counts as lost. [`LatencyProbe::stats`] summarizes the
+//! last [`WINDOW`] pings for the status menu item.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Time between pings.
+pub const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Pings the statistics are computed over.
+const WINDOW: usize = 6;
+
+/// Round trips above this make typing feel laggy.
+const SLOW_RTT: Duration = Duration::from_millis(250);
+
+/// Share of lost pings (percent) at which the connection is flagged.
+const POOR_LOSS_PERCENT: u8 = 20;
+
+/// Summary of recent pings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Mean round trip of the answered pings, `None` if none were answered
+    pub rtt: Option<Duration>,
+    /// Share of pings without a pong, in percent
+    pub loss_percent: u8,
+}
+
+impl LatencyStats {
+    /// Whether latency or loss make interactive use painful.
+    pub fn is_poor(&self) -> bool {
+        self.rtt.is_none_or(|rtt| rtt >= SLOW_RTT) || self.loss_percent >= POOR_LOSS_PERCENT
+    }
+
+    /// Short form for the status line: "43ms", "310ms, 33% loss ⚠".
+    pub fn label(&self) -> String {
+        let mut label = match self.rtt {
+            Some(rtt) => format!("{}ms", rtt.as_millis()),
+            None => "no response".to_string(),
+        };
+        if self.loss_percent > 0 {
+            label.push_str(&format!(", {}% loss", self.loss_percent));
+        }
+        if self.is_poor() {
+            label.push_str(" ⚠");
+        }
+        label
+    }
+}
+
+/// Outcome of one ping.
+#[derive(Debug, Clone, Copy)]
+enum Ping {
+    Pending { seq: u64, sent_at: Instant },
+    Answered(Duration),
+    Lost,
+}
+
+/// Pings sent on one connection and their outcomes.
+#[derive(Debug, Default)]
+pub struct LatencyProbe {
+    next_seq: u64,
+    pings: VecDeque<Ping>,
+}
+
+impl LatencyProbe {
+    /// Start a ping, returning its payload. A previous ping still waiting
+    /// for its pong is counted as lost.
+    pub fn ping(&mut self, now: Instant) -> Vec<u8> {
+        for ping in &mut self.pings {
+            if matches!(ping, Ping::Pending { .. }) {
+                *ping = Ping::Lost;
+            }
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pings.push_back(Ping::Pending { seq, sent_at: now });
+        while self.pings.len() > WINDOW {
+            self.pings.pop_front();
+        }
+        seq.to_be_bytes().to_vec()
+    }
+
+    /// Record a pong, returning the round trip if it answers a pending ping.
+    pub fn pong(&mut self, payload: &[u8], now: Instant) -> Option<Duration> {
+        let seq = u64::from_be_bytes(payload.try_into().ok()?);
+        let ping = self
+            .pings
+            .iter_mut()
+            .find(|p| matches!(p, Ping::Pending { seq: s, .. } if *s == seq))?;
+        let Ping::Pending { sent_at, .. } = *ping else {
+            return None;
+        };
+        let rtt = now.saturating_duration_since(sent_at);
+        *ping = Ping::Answered(rtt);
+        Some(rtt)
+    }
+
+    /// Statistics over finished pings, or `None` before the first one.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let rtts: Vec<Duration> = self
+            .pings
+            .iter()
+            .filter_map(|p| match p {
+                Ping::Answered(rtt) => Some(*rtt),
+                _ => None,
+            })
+            .collect();
+        let lost = self.pings.iter().filter(|p| matches!(p, Ping::Lost)).count();
+        let finished = rtts.len() + lost;
+        if finished == 0 {
+            return None;
+        }
+        Some(LatencyStats {
+            rtt: (!rtts.is_empty()).then(|| rtts.iter().sum::<Duration>() / rtts.len() as u32),
+            loss_percent: (lost * 100 / finished) as u8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_and_loss() {
+        let start = Instant::now();
+        let mut probe = LatencyProbe::default();
+        assert_eq!(probe.stats(), None);
+
+        let first = probe.ping(start);
+        assert_eq!(
+            probe.pong(&first, start + Duration::from_millis(40)),
+            Some(Duration::from_millis(40))
+        );
+        // A duplicate or unknown pong is ignored
+        assert_eq!(probe.pong(&first, start + Duration::from_millis(50)), None);
+        assert_eq!(probe.pong(b"junk", start), None);
+
+        let second = probe.ping(start + PING_INTERVAL);
+        probe.pong(&second, start + PING_INTERVAL + Duration::from_millis(60));
+        let stats = probe.stats().unwrap();
+        assert_eq!(stats.rtt, Some(Duration::from_millis(50)));
+        assert_eq!(stats.loss_percent, 0);
+        assert_eq!(stats.label(), "50ms");
+
+        // Unanswered until the next ping goes out
+        probe.ping(start + PING_INTERVAL * 2);
+        probe.ping(start + PING_INTERVAL * 3);
+        let stats = probe.stats().unwrap();
+        assert_eq!(stats.loss_percent, 33);
+        assert!(stats.is_poor());
+        assert_eq!(stats.label(), "50ms, 33% loss ⚠");
+    }
+
+    #[test]
+    fn test_window_forgets_old_pings() {
+        let start = Instant::now();
+        let mut probe = LatencyProbe::default();
+        probe.ping(start);
+        for i in 1..=WINDOW as u32 {
+            let at = start + PING_INTERVAL * i;
+            let payload = probe.ping(at);
+            probe.pong(&payload, at + Duration::from_millis(300));
+        }
+        let stats = probe.stats().unwrap();
+        assert_eq!(stats.loss_percent, 0);
+        assert_eq!(stats.label(), "300ms ⚠");
+    }
+}
@@ -1,2 +1,4 @@
 mod connection;
+mod direct;
+pub mod latency;
 pub use connection::{RelayClient, RelayCommand, RelayEvent};
@@ -0,0 +1,1839 @@
+//! Frame routing between PTY sessions and the relay.
+//!
+//! All terminal traffic on the relay link is multiplexed over one WebSocket
+//! using binary frames tagged with the terminal session ID:
+//!
+//! ```text
+//! [1 byte session_id length][session_id bytes][payload]
+//! ```
+//!
+//! This module owns that wire format. Output from every session is wrapped
+//! with its ID on the way out, browser input is demultiplexed by ID to the
+//! right pty-proxy on the way in, and session lifecycle changes
+//! (attach/detach/rename/resize) are turned into typed relay commands.
+//!
+//! Session management requests from the browser arrive as JSON text messages
+//! and are dispatched here as [`SessionCommand`]s.
+//!
+//! Each session is tagged with a project (see [`crate::project`]). Sessions
+//! of a paused project are hidden from browsers: they are left out of the
+//! session list and their output and input are dropped until the project is
+//! shared again.
+//!
+//! With end-to-end encryption on, output payloads are sealed here before
+//! they are framed for the relay (see [`crate::e2e`]).
+//!
+//! Large browser input is held here until it is confirmed from the menu
+//! (see [`crate::paste_guard`]). Input to a session set to read only is
+//! dropped; the setting is kept in the session registry and comes back with
+//! the session when its proxy reconnects.
+//!
+//! Browser searches run here over each session's transcript (see
+//! [`crate::transcript::search`]); the matching lines go back to the
+//! browser that asked. With end-to-end encryption on they would pass the
+//! relay in plain text, so searches are refused.
+//!
+//! With bandwidth budgets on, output is counted here (see
+//! [`crate::bandwidth`]); a session over budget stops streaming and is sent
+//! screen snapshots by [`Router::send_snapshots`] instead.
+//!
+//! A session viewed at several sizes gets one, chosen from the Mac's
+//! terminal and the browsers' requests (see [`crate::sizing`]); it is pushed
+//! to the session and broadcast to browsers.
+
+use crate::alerts::Alert;
+use crate::app::UiEvent;
+use crate::bandwidth::{Budget, Decision, Limit};
+use crate::clipboard;
+use crate::config::{BandwidthConfig, ClipboardConfig, RateLimitConfig, SecurityConfig, SizePolicy};
+use crate::e2e::Encryptor;
+use crate::history::RecentSession;
+use crate::paste_guard::{self, HeldPaste, MAX_HELD_PASTES};
+use crate::project;
+use crate::protocol::{SearchMatch, SessionInfo, SessionMetadata};
+use crate::pty::{PtyCommand, PtyEvent};
+use crate::ratelimit::{InputLimiter, Verdict};
+use crate::relay::RelayCommand;
+use crate::sizing::{Size, Sizes};
+use crate::tmux;
+use crate::transcript::{self, Transcript};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, trace, warn};
+
+/// Minimum time between remote-activity UI events for one session, so
+/// fast typing does not flood the UI channel.
+const ACTIVITY_EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Matches sent back for one search; more are reported as truncated.
+const MAX_SEARCH_MATCHES: usize = 200;
+
+/// Browser input held by the paste guard.
+struct PendingPaste {
+    id: u64,
+    session_id: String,
+    data: Vec<u8>,
+}
+
+/// Errors produced when decoding a relay frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// Frame is too short to carry a session ID and payload
+    TooShort(usize),
+    /// Declared session ID length runs past the end of the frame
+    Truncated { id_len: usize, total: usize },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::TooShort(len) => write!(f, "frame too short: {} bytes", len),
+            FrameError::Truncated { id_len, total } => write!(
+                f,
+                "frame malformed: id_len={} but only {} bytes total",
+                id_len, total
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// A decoded frame received from the relay (browser -> shell).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InboundFrame {
+    /// Raw terminal input (keystrokes, paste) for a session
+    Input { session_id: String, data: Vec<u8> },
+}
+
+/// A session management request from the browser (relay text message).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionCommand {
+    /// Reply with the full session list
+    List,
+    /// Close a session
+    Close { session_id: String },
+    /// Rename a session
+    Rename { session_id: String, name: String },
+    /// A browser's size for a session; the relay fills in `browser_id`
+    Resize {
+        browser_id: String,
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    /// Search the scrollback of one session, or of every shared session
+    Search {
+        browser_id: String,
+        session_id: Option<String>,
+        q: String,
+    },
+}
+
+/// Wrap a payload with its session ID prefix.
+pub fn encode_frame(session_id: &str, payload: &[u8]) -> Vec<u8> {
+    debug_assert!(session_id.len() <= u8::MAX as usize, "session ID too long");
+    let mut frame = Vec::with_capacity(1 + session_id.len() + payload.len());
+    frame.push(session_id.len() as u8);
+    frame.extend_from_slice(session_id.as_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Split a frame into its session ID and payload.
+pub fn split_frame(frame: &[u8]) -> Result<(String, &[u8]), FrameError> {
+    if frame.len() < 2 {
+        return Err(FrameError::TooShort(frame.len()));
+    }
+    let id_len = frame[0] as usize;
+    if frame.len() < 1 + id_len {
+        return Err(FrameError::Truncated {
+            id_len,
+            total: frame.len(),
+        });
+    }
+    let session_id = String::from_utf8_lossy(&frame[1..1 + id_len]).to_string();
+    Ok((session_id, &frame[1 + id_len..]))
+}
+
+/// Decode a frame received from the relay.
+///
+/// The payload is always terminal input, even if it reads like a control
+/// message: closing a session is a text `close_session` message.
+pub fn decode_inbound(frame: &[u8]) -> Result<InboundFrame, FrameError> {
+    let (session_id, payload) = split_frame(frame)?;
+    Ok(InboundFrame::Input {
+        session_id,
+        data: payload.to_vec(),
+    })
+}
+
+/// Ordered list of attached sessions with their metadata.
+pub type SessionList = Arc<Mutex<Vec<SessionInfo>>>;
+
+/// Routes session events to the relay and relay input to sessions.
+///
+/// Owns the list of attached sessions so that every component sees the same
+/// view when announcing sessions to newly joined browsers, and the per-session
+/// input rate limiters.
+#[derive(Clone)]
+pub struct Router {
+    relay_cmd_tx: mpsc::UnboundedSender<RelayCommand>,
+    pty_cmd_tx: mpsc::UnboundedSender<PtyCommand>,
+    ui_tx: std_mpsc::Sender<UiEvent>,
+    sessions: SessionList,
+    rate_limit: RateLimitConfig,
+    security: SecurityConfig,
+    clipboard: ClipboardConfig,
+    limiters: Arc<Mutex<HashMap<String, InputLimiter>>>,
+    /// When each attached session started, for the recent-sessions history
+    started: Arc<Mutex<HashMap<String, SystemTime>>>,
+    /// When a remote-activity event was last sent for each session
+    activity_sent: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Projects whose sessions are hidden from browsers
+    paused_projects: Arc<Mutex<HashSet<String>>>,
+    /// Do Not Disturb: every session is hidden and the relay refuses new browsers
+    sharing_paused: Arc<AtomicBool>,
+    /// Sessions whose browser input is dropped
+    read_only: Arc<Mutex<HashSet<String>>>,
+    /// Latest output of each attached session, for searches
+    transcripts: Arc<Mutex<HashMap<String, Arc<Transcript>>>>,
+    /// Seals output for paired browsers when end-to-end encryption is on
+    e2e: Option<Arc<Encryptor>>,
+    /// Output counts when bandwidth budgets are on
+    bandwidth: Option<Arc<Mutex<Budget>>>,
+    /// Sizes of the Mac's terminal and the browsers, per session
+    sizes: Arc<Mutex<Sizes>>,
+    /// Large input waiting for confirmation, oldest first
+    held_pastes: Arc<Mutex<Vec<PendingPaste>>>,
+    next_paste_id: Arc<AtomicU64>,
+}
+
+impl Router {
+    pub fn new(
+        relay_cmd_tx: mpsc::UnboundedSender<RelayCommand>,
+        pty_cmd_tx: mpsc::UnboundedSender<PtyCommand>,
+        ui_tx: std_mpsc::Sender<UiEvent>,
+        rate_limit: RateLimitConfig,
+        security: SecurityConfig,
+        clipboard: ClipboardConfig,
+        e2e: Option<Arc<Encryptor>>,
+    ) -> Self {
+        Self {
+            relay_cmd_tx,
+            pty_cmd_tx,
+            ui_tx,
+            sessions: Arc::new(Mutex::new(Vec::new())),
+            rate_limit,
+            security,
+            clipboard,
+            limiters: Arc::new(Mutex::new(HashMap::new())),
+            started: Arc::new(Mutex::new(HashMap::new())),
+            activity_sent: Arc::new(Mutex::new(HashMap::new())),
+            paused_projects: Arc::new(Mutex::new(HashSet::new())),
+            sharing_paused: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(Mutex::new(HashSet::new())),
+            transcripts: Arc::new(Mutex::new(HashMap::new())),
+            e2e,
+            bandwidth: None,
+            sizes: Arc::new(Mutex::new(Sizes::default())),
+            held_pastes: Arc::new(Mutex::new(Vec::new())),
+            next_paste_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Count output against hourly budgets, holding sessions that run over.
+    pub fn with_bandwidth(mut self, config: BandwidthConfig) -> Self {
+        info!(
+            "Bandwidth budgets: {} bytes per session, {} in total per hour",
+            config.session_bytes_per_hour, config.total_bytes_per_hour
+        );
+        self.bandwidth = Some(Arc::new(Mutex::new(Budget::new(config, Instant::now()))));
+        self
+    }
+
+    /// Choose session sizes with `policy` instead of the smallest one.
+    pub fn with_size_policy(mut self, policy: SizePolicy) -> Self {
+        self.sizes = Arc::new(Mutex::new(Sizes::new(policy)));
+        self
+    }
+
+    /// Snapshot of the attached sessions.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.sessions.lock().unwrap().clone()
+    }
+
+    /// Display name of a session, falling back to its ID.
+    fn session_name(&self, session_id: &str) -> String {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|s| s.id == session_id)
+            .map(|s| s.name.clone())
+            .unwrap_or_else(|| session_id.to_string())
+    }
+
+    /// Whether a session is attached and shared with browsers.
+    fn has_session(&self, session_id: &str) -> bool {
+        if self.sharing_paused.load(Ordering::Relaxed) {
+            return false;
+        }
+        let paused = self.paused_projects.lock().unwrap();
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| s.id == session_id && !is_paused(s, &paused))
+    }
+
+    /// Whether a session is hidden from browsers: it belongs to a paused
+    /// project, or sharing is paused altogether.
+    fn is_hidden(&self, session_id: &str) -> bool {
+        if self.sharing_paused.load(Ordering::Relaxed) {
+            return true;
+        }
+        let paused = self.paused_projects.lock().unwrap();
+        if paused.is_empty() {
+            return false;
+        }
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| s.id == session_id && is_paused(s, &paused))
+    }
+
+    /// Pause or share every session of a project at once.
+    ///
+    /// Pausing tells browsers the project's sessions disconnected; sharing
+    /// announces them again and re-sends the session list.
+    pub fn set_project_paused(&self, project: &str, paused: bool) {
+        let changed = {
+            let mut set = self.paused_projects.lock().unwrap();
+            if paused {
+                set.insert(project.to_string())
+            } else {
+                set.remove(project)
+            }
+        };
+        if !changed {
+            return;
+        }
+        info!(
+            "{} project {}",
+            if paused { "Pausing" } else { "Sharing" },
+            project
+        );
+        // Under Do Not Disturb browsers see nothing either way
+        if self.sharing_paused.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let members: Vec<SessionInfo> = self
+            .sessions()
+            .into_iter()
+            .filter(|s| s.project.as_deref() == Some(project))
+            .collect();
+        for session in members {
+            let cmd = if paused {
+                RelayCommand::SendSessionDisconnected {
+                    session_id: session.id,
+                }
+            } else {
+                RelayCommand::SendSessionConnected {
+                    session_id: session.id,
+                    name: session.name,
+                }
+            };
+            let _ = self.relay_cmd_tx.send(cmd);
+        }
+        if !paused {
+            self.send_session_list();
+        }
+    }
+
+    /// Drop browser input to one session, or accept it again.
+    pub fn set_read_only(&self, session_id: &str, read_only: bool) {
+        let mut set = self.read_only.lock().unwrap();
+        if read_only {
+            set.insert(session_id.to_string());
+        } else {
+            set.remove(session_id);
+        }
+        info!(
+            "Session {} {}",
+            session_id,
+            if read_only { "is read only" } else { "accepts input again" }
+        );
+    }
+
+    /// Do Not Disturb: hide every session from browsers and have the relay
+    /// refuse new ones, or share again.
+    pub fn set_sharing_paused(&self, paused: bool) {
+        if self.sharing_paused.swap(paused, Ordering::Relaxed) == paused {
+            return;
+        }
+        info!("{} sharing with browsers", if paused { "Pausing" } else { "Resuming" });
+
+        let hidden_projects = self.paused_projects.lock().unwrap().clone();
+        for session in self.sessions() {
+            if is_paused(&session, &hidden_projects) {
+                continue;
+            }
+            let cmd = if paused {
+                RelayCommand::SendSessionDisconnected {
+                    session_id: session.id,
+                }
+            } else {
+                RelayCommand::SendSessionConnected {
+                    session_id: session.id,
+                    name: session.name,
+                }
+            };
+            let _ = self.relay_cmd_tx.send(cmd);
+        }
+        let _ = self.relay_cmd_tx.send(RelayCommand::SendSharingPaused { paused });
+        if !paused {
+            self.send_session_list();
+        }
+    }
+
+    /// Repeat Do Not Disturb and the browser approval requirement to a
+    /// relay we (re)registered with.
+    pub fn announce_sharing(&self) {
+        if self.security.require_approval {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendApprovalRequired { required: true });
+        }
+        if self.sharing_paused.load(Ordering::Relaxed) {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendSharingPaused { paused: true });
+        }
+    }
+
+    /// Let a browser waiting for approval in, or turn it away.
+    pub fn answer_approval(&self, browser_id: String, approved: bool) {
+        info!("{} browser {}", if approved { "Approving" } else { "Denying" }, browser_id);
+        let _ = self
+            .relay_cmd_tx
+            .send(RelayCommand::SendBrowserApproval { browser_id, approved });
+    }
+
+    /// Route an event from the PTY manager (shell -> relay/UI).
+    pub fn route_pty_event(&self, event: PtyEvent) {
+        match event {
+            PtyEvent::Attached {
+                session_id,
+                session_name,
+                shell,
+                pid,
+                tty,
+                cwd,
+                stats,
+                transcript,
+                read_only,
+            } => {
+                info!("pty-proxy session connected: {} ({})", session_name, session_id);
+                if read_only {
+                    info!("Session {} is read only", session_id);
+                    self.read_only.lock().unwrap().insert(session_id.clone());
+                }
+                let project = cwd.as_deref().map(project::detect);
+                self.sessions.lock().unwrap().push(SessionInfo {
+                    id: session_id.clone(),
+                    name: session_name.clone(),
+                    shell: Some(shell),
+                    pid: Some(pid),
+                    tty: Some(tty),
+                    cwd,
+                    project: project.clone(),
+                    title: None,
+                });
+                self.started
+                    .lock()
+                    .unwrap()
+                    .insert(session_id.clone(), SystemTime::now());
+                self.transcripts
+                    .lock()
+                    .unwrap()
+                    .insert(session_id.clone(), transcript.clone());
+                if !self.is_hidden(&session_id) {
+                    let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionConnected {
+                        session_id: session_id.clone(),
+                        name: session_name.clone(),
+                    });
+                }
+                let _ = self.ui_tx.send(UiEvent::ShellConnected {
+                    session_id: session_id.clone(),
+                    name: session_name,
+                    stats,
+                    transcript,
+                });
+                if let Some(project) = project {
+                    let _ = self.ui_tx.send(UiEvent::SessionProject { session_id, project });
+                }
+            }
+            PtyEvent::Detached { session_id } => {
+                info!("pty-proxy session disconnected: {}", session_id);
+                let hidden = self.is_hidden(&session_id);
+                let ended = {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    let pos = sessions.iter().position(|s| s.id == session_id);
+                    pos.map(|i| sessions.remove(i))
+                };
+                self.limiters.lock().unwrap().remove(&session_id);
+                self.read_only.lock().unwrap().remove(&session_id);
+                self.transcripts.lock().unwrap().remove(&session_id);
+                if let Some(budget) = &self.bandwidth {
+                    budget.lock().unwrap().remove(&session_id);
+                }
+                self.sizes.lock().unwrap().remove_session(&session_id);
+                self.activity_sent.lock().unwrap().remove(&session_id);
+                self.held_pastes.lock().unwrap().retain(|p| p.session_id != session_id);
+                let started = self.started.lock().unwrap().remove(&session_id);
+                if let (Some(info), Some(started)) = (ended, started) {
+                    let _ = self.ui_tx.send(UiEvent::SessionEnded(recent_session(info, started)));
+                }
+                if !hidden {
+                    let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionDisconnected {
+                        session_id: session_id.clone(),
+                    });
+                }
+                let _ = self.ui_tx.send(UiEvent::ShellDisconnected { session_id });
+            }
+            PtyEvent::Renamed { session_id, name } => {
+                info!("pty-proxy session renamed: {} -> {}", session_id, name);
+                {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    if let Some(entry) = sessions.iter_mut().find(|s| s.id == session_id) {
+                        entry.name = name.clone();
+                    }
+                }
+                if !self.is_hidden(&session_id) {
+                    let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionRenamed {
+                        session_id: session_id.clone(),
+                        name: name.clone(),
+                    });
+                }
+                let _ = self.ui_tx.send(UiEvent::ShellRenamed { session_id, name });
+            }
+            PtyEvent::CwdChanged { session_id, cwd } => {
+                let project = project::detect(&cwd);
+                let was_hidden = self.is_hidden(&session_id);
+                let previous = {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    let Some(entry) = sessions.iter_mut().find(|s| s.id == session_id) else {
+                        return;
+                    };
+                    entry.cwd = Some(cwd.clone());
+                    entry.project.replace(project.clone())
+                };
+                if previous.as_deref() == Some(project.as_str()) {
+                    if !was_hidden {
+                        let metadata = SessionMetadata {
+                            cwd: Some(cwd),
+                            ..SessionMetadata::default()
+                        };
+                        let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionMetadata { session_id, metadata });
+                    }
+                    return;
+                }
+                self.project_changed(session_id, project, was_hidden);
+            }
+            PtyEvent::TitleChanged { session_id, title } => {
+                {
+                    let mut sessions = self.sessions.lock().unwrap();
+                    let Some(entry) = sessions.iter_mut().find(|s| s.id == session_id) else {
+                        return;
+                    };
+                    // Prompts set the title every time they are drawn
+                    if entry.title.as_deref() == Some(title.as_str()) {
+                        return;
+                    }
+                    entry.title = Some(title.clone());
+                }
+                if !self.is_hidden(&session_id) {
+                    let metadata = SessionMetadata {
+                        title: Some(title),
+                        ..SessionMetadata::default()
+                    };
+                    let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionMetadata { session_id, metadata });
+                }
+            }
+            PtyEvent::Clipboard { session_id, data } => {
+                if self.clipboard.to_browsers && !self.is_hidden(&session_id) {
+                    let _ = self.relay_cmd_tx.send(RelayCommand::SendClipboard {
+                        session_id: session_id.clone(),
+                        data: data.clone(),
+                    });
+                }
+                let Some(text) = clipboard::decode_osc52(&data) else {
+                    warn!("Ignoring undecodable clipboard payload from session {}", session_id);
+                    return;
+                };
+                let _ = self.ui_tx.send(UiEvent::ClipboardCopy {
+                    name: self.session_name(&session_id),
+                    session_id,
+                    text,
+                });
+            }
+            PtyEvent::Bell { session_id } => {
+                let (name, tty) = {
+                    let sessions = self.sessions.lock().unwrap();
+                    match sessions.iter().find(|s| s.id == session_id) {
+                        Some(s) => (s.name.clone(), s.tty.clone()),
+                        None => return,
+                    }
+                };
+                let _ = self.ui_tx.send(UiEvent::Bell { session_id, name, tty });
+            }
+            PtyEvent::Output { session_id, data } => {
+                if self.is_hidden(&session_id) {
+                    return;
+                }
+                if !self.admit_output(&session_id, data.len()) {
+                    return;
+                }
+                self.send_output(session_id, data);
+            }
+            PtyEvent::SessionResize { session_id, cols, rows } => {
+                // The Mac's terminal resized; the PTY already has its size
+                let changed = self.sizes.lock().unwrap().set_local(&session_id, (cols, rows));
+                if let Some(size) = changed {
+                    self.apply_size(session_id, size, size != (cols, rows));
+                }
+            }
+            PtyEvent::Error(msg) => {
+                let _ = self.ui_tx.send(UiEvent::PtyError(msg));
+            }
+            PtyEvent::Warning(msg) => {
+                let _ = self.ui_tx.send(UiEvent::Alert(Alert::warning(msg)));
+            }
+        }
+    }
+
+    /// A session's effective size changed: resize its PTY if it does not
+    /// have the size yet (`push`) and tell browsers.
+    fn apply_size(&self, session_id: String, (cols, rows): Size, push: bool) {
+        if let Some(budget) = &self.bandwidth {
+            budget.lock().unwrap().set_rows(&session_id, rows);
+        }
+        if push {
+            info!("Resizing session {} to {}x{}", session_id, cols, rows);
+            let _ = self.pty_cmd_tx.send(PtyCommand::Resize {
+                session_id: session_id.clone(),
+                cols,
+                rows,
+            });
+        }
+        if !self.is_hidden(&session_id) {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionResize { session_id, cols, rows });
+        }
+    }
+
+    /// A browser disconnected: sessions it kept small may grow again.
+    pub fn browser_left(&self, browser_id: &str) {
+        let changed = self.sizes.lock().unwrap().remove_browser(browser_id);
+        for (session_id, size) in changed {
+            self.apply_size(session_id, size, true);
+        }
+    }
+
+    /// Frame output for the relay, sealed if end-to-end encryption is on.
+    fn send_output(&self, session_id: String, data: Vec<u8>) {
+        let data = match &self.e2e {
+            Some(e2e) => e2e.seal(&session_id, &data),
+            None => data,
+        };
+        let _ = self.relay_cmd_tx.send(RelayCommand::SendTerminalData { session_id, data });
+    }
+
+    /// Apply the bandwidth budgets. Returns false if the output must not be
+    /// streamed; when a budget runs out the user and the browsers are told.
+    fn admit_output(&self, session_id: &str, len: usize) -> bool {
+        let Some(budget) = &self.bandwidth else {
+            return true;
+        };
+        let decision = budget.lock().unwrap().record(session_id, len, Instant::now());
+        match decision {
+            Decision::Stream => true,
+            Decision::Held => false,
+            Decision::Exceeded(Limit::Session) => {
+                let name = self.session_name(session_id);
+                warn!("Session {} ({}) is over its bandwidth budget", name, session_id);
+                self.reject(
+                    session_id.to_string(),
+                    "Over the hourly bandwidth budget, showing snapshots of the screen".into(),
+                );
+                let _ = self.ui_tx.send(UiEvent::BandwidthExceeded { name: Some(name) });
+                false
+            }
+            Decision::Exceeded(Limit::Total) => {
+                warn!("Total bandwidth budget used up");
+                for session in self.sessions() {
+                    if !self.is_hidden(&session.id) {
+                        self.reject(
+                            session.id,
+                            "Relay bandwidth budget used up, showing snapshots of the screen".into(),
+                        );
+                    }
+                }
+                let _ = self.ui_tx.send(UiEvent::BandwidthExceeded { name: None });
+                false
+            }
+        }
+    }
+
+    /// Send a screen snapshot of each held session whose output changed
+    /// since its last one. Called every `snapshot_secs`.
+    pub fn send_snapshots(&self) {
+        let Some(budget) = &self.bandwidth else {
+            return;
+        };
+        let due = budget.lock().unwrap().due_snapshots(Instant::now());
+        for session_id in due {
+            if self.is_hidden(&session_id) {
+                continue;
+            }
+            let Some(transcript) = self.transcripts.lock().unwrap().get(&session_id).cloned() else {
+                continue;
+            };
+            let rows = budget.lock().unwrap().rows(&session_id);
+            let screen = transcript::screen(&transcript.snapshot(), rows);
+            budget.lock().unwrap().count_snapshot(&session_id, screen.len());
+            trace!("Sending a {} byte snapshot of session {}", screen.len(), session_id);
+            self.send_output(session_id, screen);
+        }
+    }
+
+    /// Route a decoded frame from the relay (browser -> shell).
+    pub fn route_inbound(&self, frame: InboundFrame) {
+        match frame {
+            InboundFrame::Input { session_id, data } => {
+                // Before the rate limit, which keeps state per session
+                if !self.has_session(&session_id) {
+                    trace!("Dropping input for unknown or paused session {}", session_id);
+                    return;
+                }
+                if self.read_only.lock().unwrap().contains(&session_id) {
+                    trace!("Dropping input for read-only session {}", session_id);
+                    return;
+                }
+                if !self.admit_input(&session_id, data.len()) {
+                    return;
+                }
+                if self.security.confirm_paste
+                    && paste_guard::needs_confirmation(
+                        &data,
+                        self.security.confirm_paste_bytes,
+                        self.security.confirm_paste_lines,
+                    )
+                {
+                    self.hold_paste(session_id, data);
+                    return;
+                }
+                trace!("Routing {} input bytes to session {}", data.len(), session_id);
+                self.note_activity(&session_id);
+                let _ = self.pty_cmd_tx.send(PtyCommand::Write { session_id, data });
+            }
+        }
+    }
+
+    /// Apply the input rate limit of an attached session. Returns false if
+    /// the input must be dropped; the first drop after a period of allowed
+    /// input notifies the user and the browser.
+    fn admit_input(&self, session_id: &str, len: usize) -> bool {
+        if !self.rate_limit.enabled {
+            return true;
+        }
+        let verdict = self
+            .limiters
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_insert_with(|| InputLimiter::new(&self.rate_limit, Instant::now()))
+            .check(len, Instant::now());
+
+        match verdict {
+            Verdict::Allow => true,
+            Verdict::Drop => false,
+            Verdict::Tripped => {
+                warn!("Input rate limit exceeded for session {}, dropping input", session_id);
+                let _ = self.ui_tx.send(UiEvent::InputRateLimited {
+                    session_id: session_id.to_string(),
+                    name: self.session_name(session_id),
+                });
+                self.reject(
+                    session_id.to_string(),
+                    "Input rate limit exceeded, dropping input".into(),
+                );
+                false
+            }
+        }
+    }
+
+    /// Keep large input back until it is confirmed, and tell the UI and the
+    /// browser.
+    fn hold_paste(&self, session_id: String, data: Vec<u8>) {
+        let id = self.next_paste_id.fetch_add(1, Ordering::Relaxed);
+        let held = HeldPaste::new(id, session_id.clone(), self.session_name(&session_id), &data);
+        info!("Holding paste {} for session {} ({})", id, session_id, held.summary);
+        {
+            let mut pastes = self.held_pastes.lock().unwrap();
+            if pastes.len() >= MAX_HELD_PASTES {
+                let dropped = pastes.remove(0);
+                warn!("Too many held pastes, discarding paste {}", dropped.id);
+            }
+            pastes.push(PendingPaste {
+                id,
+                session_id: session_id.clone(),
+                data,
+            });
+        }
+        let _ = self.ui_tx.send(UiEvent::PasteHeld(held));
+        self.reject(session_id, "Large paste held until it is confirmed on the Mac".into());
+    }
+
+    /// Write a held paste to its session (`send`) or discard it. Pastes for
+    /// sessions that ended or were hidden meanwhile are discarded.
+    pub fn release_paste(&self, id: u64, send: bool) {
+        let held = {
+            let mut pastes = self.held_pastes.lock().unwrap();
+            let pos = pastes.iter().position(|p| p.id == id);
+            pos.map(|i| pastes.remove(i))
+        };
+        let Some(PendingPaste { session_id, data, .. }) = held else {
+            return;
+        };
+        if !send {
+            info!("Discarding held paste {}", id);
+            return;
+        }
+        if !self.has_session(&session_id) {
+            warn!("Session {} is gone or paused, discarding held paste {}", session_id, id);
+            return;
+        }
+        info!("Writing held paste {} ({} bytes) to session {}", id, data.len(), session_id);
+        self.note_activity(&session_id);
+        let _ = self.pty_cmd_tx.send(PtyCommand::Write { session_id, data });
+    }
+
+    /// Tell the UI that browser input reached a session, at most once per
+    /// [`ACTIVITY_EVENT_INTERVAL`].
+    fn note_activity(&self, session_id: &str) {
+        let now = Instant::now();
+        let mut sent = self.activity_sent.lock().unwrap();
+        if sent
+            .get(session_id)
+            .is_some_and(|at| now.duration_since(*at) < ACTIVITY_EVENT_INTERVAL)
+        {
+            return;
+        }
+        sent.insert(session_id.to_string(), now);
+        let _ = self.ui_tx.send(UiEvent::RemoteInput {
+            session_id: session_id.to_string(),
+        });
+    }
+
+    /// Tell browsers that output is end-to-end encrypted, so ones opened
+    /// without the pairing link can say so instead of showing ciphertext.
+    pub fn announce_e2e(&self) {
+        if let Some(e2e) = &self.e2e {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendE2eRequired {
+                fingerprint: e2e.fingerprint(),
+            });
+        }
+    }
+
+    /// Answer a paired browser's `e2e_hello` with the wrapped output key.
+    pub fn handle_e2e_hello(&self, public_key: String) {
+        let Some(e2e) = &self.e2e else {
+            warn!("Ignoring e2e_hello: end-to-end encryption is off");
+            return;
+        };
+        match e2e.wrap_content_key(&public_key) {
+            Ok(key) => {
+                info!("Sending output key to a paired browser");
+                let _ = self
+                    .relay_cmd_tx
+                    .send(RelayCommand::SendE2eKey { public_key, key });
+            }
+            Err(e) => warn!("Rejecting e2e_hello: {}", e),
+        }
+    }
+
+    /// A session moved to another project: update the tray and, if that
+    /// pauses or shares it, tell browsers it left or joined.
+    fn project_changed(&self, session_id: String, project: String, was_hidden: bool) {
+        let hidden = self.is_hidden(&session_id);
+        if hidden && !was_hidden {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionDisconnected {
+                session_id: session_id.clone(),
+            });
+        } else if was_hidden && !hidden {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionConnected {
+                name: self.session_name(&session_id),
+                session_id: session_id.clone(),
+            });
+        }
+        if !hidden {
+            // Browsers group tabs by project; one that just reappeared
+            // needs the rest of its metadata too
+            let metadata = self
+                .sessions
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|s| s.id == session_id)
+                .map(SessionMetadata::from);
+            if let Some(metadata) = metadata {
+                let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionMetadata {
+                    session_id: session_id.clone(),
+                    metadata,
+                });
+            }
+        }
+        let _ = self.ui_tx.send(UiEvent::SessionProject { session_id, project });
+    }
+
+    /// Announce the sessions shared with browsers to the relay.
+    pub fn send_session_list(&self) {
+        let paused = self.paused_projects.lock().unwrap().clone();
+        let sharing_paused = self.sharing_paused.load(Ordering::Relaxed);
+        let sessions: Vec<SessionInfo> = self
+            .sessions()
+            .into_iter()
+            .filter(|s| !sharing_paused && !is_paused(s, &paused))
+            .collect();
+        info!("Sending {} sessions to relay", sessions.len());
+        let sizes: Vec<(String, Size)> = {
+            let all = self.sizes.lock().unwrap();
+            sessions
+                .iter()
+                .filter_map(|s| Some((s.id.clone(), all.effective(&s.id)?)))
+                .collect()
+        };
+        let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionList { sessions });
+        // So browsers that just joined render at the sessions' sizes
+        for (session_id, (cols, rows)) in sizes {
+            let _ = self.relay_cmd_tx.send(RelayCommand::SendSessionResize { session_id, cols, rows });
+        }
+    }
+
+    /// Apply a session management request from the browser.
+    ///
+    /// Rename and resize requests for unknown sessions are answered with a
+    /// non-fatal `session_error` instead of being forwarded to the PTY manager.
+    pub fn dispatch(&self, cmd: SessionCommand) {
+        let (session_id, pty_cmd) = match cmd {
+            SessionCommand::List => {
+                self.send_session_list();
+                return;
+            }
+            SessionCommand::Close { session_id } if !self.security.allow_remote_close => {
+                return self.reject(session_id, "Closing sessions from the browser is disabled".into());
+            }
+            SessionCommand::Close { session_id } => {
+                // Not checked against the session list: the PTY manager still
+                // knows the TTY of a session that already detached and can
+                // close its Terminal window.
+                info!("Closing session: {}", session_id);
+                let _ = self.pty_cmd_tx.send(PtyCommand::KillSession { session_id });
+                return;
+            }
+            SessionCommand::Rename { session_id, name } => {
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    return self.reject(session_id, "Session name must not be empty".into());
+                }
+                (session_id.clone(), PtyCommand::Rename { session_id, name })
+            }
+            SessionCommand::Resize {
+                browser_id,
+                session_id,
+                cols,
+                rows,
+            } => {
+                if cols == 0 || rows == 0 {
+                    return self.reject(session_id, format!("Invalid size {}x{}", cols, rows));
+                }
+                if !self.has_session(&session_id) {
+                    warn!("Session command for unknown session: {}", session_id);
+                    return self.reject(session_id, "Unknown session".into());
+                }
+                if tmux::is_tmux_session(&session_id) {
+                    // Panes keep the size the user gave them; repeat it
+                    let size = self.sizes.lock().unwrap().effective(&session_id);
+                    if let Some(size) = size {
+                        self.apply_size(session_id, size, false);
+                    }
+                    return;
+                }
+                let changed = self.sizes.lock().unwrap().set_browser(&session_id, &browser_id, (cols, rows));
+                if let Some(size) = changed {
+                    self.apply_size(session_id, size, true);
+                }
+                return;
+            }
+            SessionCommand::Search { browser_id, session_id, q } => {
+                return self.search(browser_id, session_id, q);
+            }
+        };
+
+        if !self.has_session(&session_id) {
+            warn!("Session command for unknown session: {}", session_id);
+            return self.reject(session_id, "Unknown session".into());
+        }
+        let _ = self.pty_cmd_tx.send(pty_cmd);
+    }
+
+    /// Answer a browser's search with the matching lines of the session's
+    /// transcript, or of every session shared with browsers.
+    fn search(&self, browser_id: String, session_id: Option<String>, q: String) {
+        if self.e2e.is_some() {
+            let message = "Search is not available with end-to-end encryption".to_string();
+            return self.reject(session_id.unwrap_or_default(), message);
+        }
+        let session_ids: Vec<String> = match session_id {
+            Some(session_id) if !self.has_session(&session_id) => {
+                return self.reject(session_id, "Unknown session".into());
+            }
+            Some(session_id) => vec![session_id],
+            None => self
+                .sessions()
+                .into_iter()
+                .map(|s| s.id)
+                .filter(|id| self.has_session(id))
+                .collect(),
+        };
+        let mut matches = Vec::new();
+        for session_id in session_ids {
+            let Some(transcript) = self.transcripts.lock().unwrap().get(&session_id).cloned() else {
+                continue;
+            };
+            let limit = MAX_SEARCH_MATCHES + 1 - matches.len();
+            for found in transcript::search(&transcript.snapshot(), &q, limit) {
+                matches.push(SearchMatch {
+                    session_id: session_id.clone(),
+                    line: found.line,
+                    column: found.column,
+                    snippet: found.snippet,
+                });
+            }
+            if matches.len() > MAX_SEARCH_MATCHES {
+                break;
+            }
+        }
+        let truncated = matches.len() > MAX_SEARCH_MATCHES;
+        matches.truncate(MAX_SEARCH_MATCHES);
+        info!("Search for {:?}: {} match(es)", q, matches.len());
+        let _ = self.relay_cmd_tx.send(RelayCommand::SendSearchResults {
+            browser_id,
+            q,
+            matches,
+            truncated,
+        });
+    }
+
+    fn reject(&self, session_id: String, message: String) {
+        let _ = self
+            .relay_cmd_tx
+            .send(RelayCommand::SendSessionError { session_id, message });
+    }
+}
+
+fn is_paused(session: &SessionInfo, paused: &HashSet<String>) -> bool {
+    session.project.as_ref().is_some_and(|p| paused.contains(p))
+}
+
+/// History entry for a session that just ended.
+fn recent_session(info: SessionInfo, started: SystemTime) -> RecentSession {
+    RecentSession {
+        name: info.name,
+        shell: info.shell.unwrap_or_default(),
+        cwd: info.cwd,
+        started_at: started
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        duration_secs: started.elapsed().map(|d| d.as_secs()).unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_router() -> (
+        Router,
+        mpsc::UnboundedReceiver<RelayCommand>,
+        mpsc::UnboundedReceiver<PtyCommand>,
+        std_mpsc::Receiver<UiEvent>,
+    ) {
+        let (relay_tx, relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, ui_rx) = std_mpsc::channel();
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            RateLimitConfig::default(),
+            SecurityConfig::default(),
+            ClipboardConfig::default(),
+            None,
+        );
+        (router, relay_rx, pty_rx, ui_rx)
+    }
+
+    fn attach(router: &Router, session_id: &str) {
+        router.route_pty_event(PtyEvent::Attached {
+            session_id: session_id.into(),
+            session_name: "zsh".into(),
+            shell: "/bin/zsh".into(),
+            pid: 42,
+            tty: "/dev/ttys001".into(),
+            cwd: Some("/Users/me".into()),
+            stats: Default::default(),
+            transcript: Default::default(),
+            read_only: false,
+        });
+    }
+
+    #[test]
+    fn test_encode_split_roundtrip() {
+        let frame = encode_frame("sess-1", b"hello");
+        assert_eq!(frame[0], 6);
+        let (id, payload) = split_frame(&frame).unwrap();
+        assert_eq!(id, "sess-1");
+        assert_eq!(payload, b"hello");
+    }
+
+    #[test]
+    fn test_split_rejects_short_and_truncated() {
+        assert_eq!(split_frame(&[3]), Err(FrameError::TooShort(1)));
+        assert_eq!(
+            split_frame(&[10, b'a', b'b']),
+            Err(FrameError::Truncated { id_len: 10, total: 3 })
+        );
+    }
+
+    #[test]
+    fn test_decode_input_that_looks_like_json() {
+        // A user typing '{' must still reach the shell
+        let frame = encode_frame("sess-1", b"{ls");
+        assert_eq!(
+            decode_inbound(&frame).unwrap(),
+            InboundFrame::Input {
+                session_id: "sess-1".into(),
+                data: b"{ls".to_vec()
+            }
+        );
+        // and so must text that reads like a control message
+        let frame = encode_frame("sess-1", b"{\"type\":\"close_session\"}");
+        assert!(matches!(decode_inbound(&frame).unwrap(), InboundFrame::Input { .. }));
+    }
+
+    #[test]
+    fn test_attach_rename_detach_updates_session_list() {
+        let (router, mut relay_rx, _pty_rx, _ui_rx) = test_router();
+
+        attach(&router, "a");
+        router.route_pty_event(PtyEvent::Renamed {
+            session_id: "a".into(),
+            name: "zsh - ~/src".into(),
+        });
+        let sessions = router.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].name, "zsh - ~/src");
+        assert_eq!(sessions[0].tty.as_deref(), Some("/dev/ttys001"));
+
+        router.route_pty_event(PtyEvent::Detached {
+            session_id: "a".into(),
+        });
+        assert!(router.sessions().is_empty());
+
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionConnected { .. })
+        ));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionRenamed { .. })
+        ));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionDisconnected { .. })
+        ));
+    }
+
+    #[test]
+    fn test_detach_records_recent_session_with_latest_cwd() {
+        let (router, _relay_rx, _pty_rx, ui_rx) = test_router();
+        attach(&router, "a");
+        router.route_pty_event(PtyEvent::CwdChanged {
+            session_id: "a".into(),
+            cwd: "/Users/me/src".into(),
+        });
+        router.route_pty_event(PtyEvent::Detached {
+            session_id: "a".into(),
+        });
+
+        let ended = ui_rx
+            .try_iter()
+            .find_map(|e| match e {
+                UiEvent::SessionEnded(s) => Some(s),
+                _ => None,
+            })
+            .expect("SessionEnded event");
+        assert_eq!(ended.cwd.as_deref(), Some("/Users/me/src"));
+        assert_eq!(ended.shell, "/bin/zsh");
+    }
+
+    #[test]
+    fn test_paused_project_is_hidden_from_browsers() {
+        let (router, mut relay_rx, mut pty_rx, _ui_rx) = test_router();
+        for (id, cwd) in [("a", "/opt/tools"), ("b", "/srv/www")] {
+            router.route_pty_event(PtyEvent::Attached {
+                session_id: id.into(),
+                session_name: "zsh".into(),
+                shell: "/bin/zsh".into(),
+                pid: 42,
+                tty: "/dev/ttys001".into(),
+                cwd: Some(cwd.into()),
+                stats: Default::default(),
+                transcript: Default::default(),
+                read_only: false,
+            });
+        }
+        assert_eq!(router.sessions()[0].project.as_deref(), Some("opt"));
+        while relay_rx.try_recv().is_ok() {}
+
+        router.set_project_paused("opt", true);
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionDisconnected { session_id }) if session_id == "a"
+        ));
+
+        // Output, input and the session list skip the paused session
+        router.route_pty_event(PtyEvent::Output {
+            session_id: "a".into(),
+            data: b"secret".to_vec(),
+        });
+        router.route_inbound(InboundFrame::Input {
+            session_id: "a".into(),
+            data: b"ls\r".to_vec(),
+        });
+        assert!(pty_rx.try_recv().is_err());
+        router.send_session_list();
+        match relay_rx.try_recv() {
+            Ok(RelayCommand::SendSessionList { sessions }) => {
+                let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+                assert_eq!(ids, vec!["b"]);
+            }
+            other => panic!("Expected session list, got {:?}", other),
+        }
+
+        // Moving into a shared project makes the session visible again
+        router.route_pty_event(PtyEvent::CwdChanged {
+            session_id: "a".into(),
+            cwd: "/srv/www/site".into(),
+        });
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionConnected { session_id, .. }) if session_id == "a"
+        ));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionMetadata { metadata, .. }) if metadata.project.as_deref() == Some("srv")
+        ));
+
+        router.set_project_paused("srv", true);
+        router.set_project_paused("srv", false);
+        let connected = std::iter::from_fn(|| relay_rx.try_recv().ok())
+            .filter(|c| matches!(c, RelayCommand::SendSessionConnected { .. }))
+            .count();
+        assert_eq!(connected, 2);
+    }
+
+    #[test]
+    fn test_read_only_session_drops_input() {
+        let (router, _relay_rx, mut pty_rx, _ui_rx) = test_router();
+        router.route_pty_event(PtyEvent::Attached {
+            session_id: "a".into(),
+            session_name: "zsh".into(),
+            shell: "/bin/zsh".into(),
+            pid: 42,
+            tty: "/dev/ttys001".into(),
+            cwd: None,
+            stats: Default::default(),
+            transcript: Default::default(),
+            read_only: true,
+        });
+        let input = || InboundFrame::Input {
+            session_id: "a".into(),
+            data: b"rm -rf build\r".to_vec(),
+        };
+        router.route_inbound(input());
+        assert!(pty_rx.try_recv().is_err());
+
+        router.set_read_only("a", false);
+        router.route_inbound(input());
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Write { .. })));
+    }
+
+    #[test]
+    fn test_search_answers_the_browser_that_asked() {
+        let (router, mut relay_rx, _pty_rx, _ui_rx) = test_router();
+        let transcript = Arc::new(Transcript::default());
+        transcript.push(b"$ cargo test\r\ntest result: FAILED\r\n$ ");
+        router.route_pty_event(PtyEvent::Attached {
+            session_id: "a".into(),
+            session_name: "zsh".into(),
+            shell: "/bin/zsh".into(),
+            pid: 42,
+            tty: "/dev/ttys001".into(),
+            cwd: None,
+            stats: Default::default(),
+            transcript,
+            read_only: false,
+        });
+        while relay_rx.try_recv().is_ok() {}
+
+        router.dispatch(SessionCommand::Search {
+            browser_id: "b1".into(),
+            session_id: None,
+            q: "failed".into(),
+        });
+        match relay_rx.try_recv() {
+            Ok(RelayCommand::SendSearchResults { browser_id, matches, truncated, .. }) => {
+                assert_eq!(browser_id, "b1");
+                assert!(!truncated);
+                assert_eq!(
+                    matches,
+                    vec![SearchMatch {
+                        session_id: "a".into(),
+                        line: 1,
+                        column: 13,
+                        snippet: "test result: FAILED".into(),
+                    }]
+                );
+            }
+            other => panic!("expected search results, got {:?}", other),
+        }
+
+        router.dispatch(SessionCommand::Search {
+            browser_id: "b1".into(),
+            session_id: Some("gone".into()),
+            q: "failed".into(),
+        });
+        assert!(matches!(relay_rx.try_recv(), Ok(RelayCommand::SendSessionError { .. })));
+    }
+
+    #[test]
+    fn test_sharing_paused_hides_everything() {
+        let (router, mut relay_rx, mut pty_rx, _ui_rx) = test_router();
+        for (id, cwd) in [("a", "/opt/tools"), ("b", "/srv/www")] {
+            router.route_pty_event(PtyEvent::Attached {
+                session_id: id.into(),
+                session_name: "zsh".into(),
+                shell: "/bin/zsh".into(),
+                pid: 42,
+                tty: "/dev/ttys001".into(),
+                cwd: Some(cwd.into()),
+                stats: Default::default(),
+                transcript: Default::default(),
+                read_only: false,
+            });
+        }
+        router.set_project_paused("opt", true);
+        while relay_rx.try_recv().is_ok() {}
+
+        router.set_sharing_paused(true);
+        let sent: Vec<RelayCommand> = std::iter::from_fn(|| relay_rx.try_recv().ok()).collect();
+        assert!(matches!(
+            sent.as_slice(),
+            [
+                RelayCommand::SendSessionDisconnected { session_id },
+                RelayCommand::SendSharingPaused { paused: true },
+            ] if session_id == "b"
+        ));
+
+        router.route_pty_event(PtyEvent::Output {
+            session_id: "b".into(),
+            data: b"secret".to_vec(),
+        });
+        router.route_inbound(InboundFrame::Input {
+            session_id: "b".into(),
+            data: b"ls\r".to_vec(),
+        });
+        router.set_project_paused("opt", false);
+        assert!(relay_rx.try_recv().is_err());
+        assert!(pty_rx.try_recv().is_err());
+        router.send_session_list();
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionList { sessions }) if sessions.is_empty()
+        ));
+
+        // Resuming shows both sessions, "opt" having been shared meanwhile
+        router.announce_sharing();
+        assert!(matches!(relay_rx.try_recv(), Ok(RelayCommand::SendSharingPaused { paused: true })));
+        router.set_sharing_paused(false);
+        let connected = std::iter::from_fn(|| relay_rx.try_recv().ok())
+            .filter(|c| matches!(c, RelayCommand::SendSessionConnected { .. }))
+            .count();
+        assert_eq!(connected, 2);
+    }
+
+    #[test]
+    fn test_large_paste_held_until_confirmed() {
+        let (router, mut relay_rx, mut pty_rx, ui_rx) = test_router();
+        attach(&router, "a");
+        while relay_rx.try_recv().is_ok() {}
+
+        router.route_inbound(InboundFrame::Input {
+            session_id: "a".into(),
+            data: b"ls\r".to_vec(),
+        });
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Write { .. })));
+
+        let paste = b"echo hi\n".repeat(10);
+        router.route_inbound(InboundFrame::Input {
+            session_id: "a".into(),
+            data: paste.clone(),
+        });
+        assert!(pty_rx.try_recv().is_err());
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionError { ref session_id, .. }) if session_id == "a"
+        ));
+        let held = std::iter::from_fn(|| ui_rx.try_recv().ok())
+            .find_map(|event| match event {
+                UiEvent::PasteHeld(held) => Some(held),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(held.preview, "echo hi");
+
+        router.release_paste(held.id, true);
+        match pty_rx.try_recv() {
+            Ok(PtyCommand::Write { session_id, data }) => {
+                assert_eq!(session_id, "a");
+                assert_eq!(data, paste);
+            }
+            other => panic!("expected the held paste, got {:?}", other),
+        }
+        router.release_paste(held.id, true);
+        assert!(pty_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_approval_requirement_announced() {
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, _pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, _ui_rx) = std_mpsc::channel();
+        let security = SecurityConfig {
+            require_approval: true,
+            ..Default::default()
+        };
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            RateLimitConfig::default(),
+            security,
+            ClipboardConfig::default(),
+            None,
+        );
+
+        router.announce_sharing();
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendApprovalRequired { required: true })
+        ));
+        assert!(relay_rx.try_recv().is_err());
+
+        router.answer_approval("b1".into(), false);
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendBrowserApproval { ref browser_id, approved: false }) if browser_id == "b1"
+        ));
+    }
+
+    #[test]
+    fn test_output_sealed_when_e2e_enabled() {
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, _pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, _ui_rx) = std_mpsc::channel();
+        let e2e = Encryptor::new(crate::e2e::DeviceKey::generate());
+        let fingerprint = e2e.fingerprint();
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            RateLimitConfig::default(),
+            SecurityConfig::default(),
+            ClipboardConfig::default(),
+            Some(Arc::new(e2e)),
+        );
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+
+        router.route_pty_event(PtyEvent::Output {
+            session_id: "a".into(),
+            data: b"secret".to_vec(),
+        });
+        match relay_rx.try_recv() {
+            Ok(RelayCommand::SendTerminalData { data, .. }) => {
+                assert!(!data.windows(6).any(|w| w == b"secret"));
+            }
+            other => panic!("Expected terminal data, got {:?}", other),
+        }
+
+        router.announce_e2e();
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendE2eRequired { fingerprint: ref f }) if *f == fingerprint
+        ));
+        router.handle_e2e_hello("not a key".into());
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_clipboard_forwarded_and_decoded() {
+        let (router, mut relay_rx, _pty_rx, ui_rx) = test_router();
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+
+        router.route_pty_event(PtyEvent::Clipboard {
+            session_id: "a".into(),
+            data: "aGVsbG8=".into(),
+        });
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendClipboard { session_id, data }) if session_id == "a" && data == "aGVsbG8="
+        ));
+        let copy = ui_rx
+            .try_iter()
+            .find_map(|e| match e {
+                UiEvent::ClipboardCopy { name, text, .. } => Some((name, text)),
+                _ => None,
+            })
+            .expect("ClipboardCopy event");
+        assert_eq!(copy, ("zsh".to_string(), "hello".to_string()));
+    }
+
+    #[test]
+    fn test_inbound_input_routed_to_session() {
+        let (router, _relay_rx, mut pty_rx, _ui_rx) = test_router();
+        attach(&router, "a");
+        router.route_inbound(InboundFrame::Input {
+            session_id: "a".into(),
+            data: b"ls\r".to_vec(),
+        });
+        match pty_rx.try_recv() {
+            Ok(PtyCommand::Write { session_id, data }) => {
+                assert_eq!(session_id, "a");
+                assert_eq!(data, b"ls\r");
+            }
+            other => panic!("Expected Write, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remote_input_activity_is_throttled() {
+        let (router, _relay_rx, _pty_rx, ui_rx) = test_router();
+        attach(&router, "a");
+        for _ in 0..5 {
+            router.route_inbound(InboundFrame::Input {
+                session_id: "a".into(),
+                data: b"x".to_vec(),
+            });
+        }
+        let events = ui_rx
+            .try_iter()
+            .filter(|e| matches!(e, UiEvent::RemoteInput { session_id } if session_id == "a"))
+            .count();
+        assert_eq!(events, 1);
+    }
+
+    #[test]
+    fn test_dispatch_list_replies_with_metadata() {
+        let (router, mut relay_rx, _pty_rx, _ui_rx) = test_router();
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+
+        router.dispatch(SessionCommand::List);
+        match relay_rx.try_recv() {
+            Ok(RelayCommand::SendSessionList { sessions }) => {
+                assert_eq!(sessions.len(), 1);
+                assert_eq!(sessions[0].pid, Some(42));
+                assert_eq!(sessions[0].shell.as_deref(), Some("/bin/zsh"));
+            }
+            other => panic!("Expected SendSessionList, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_maps_to_pty_commands() {
+        let (router, _relay_rx, mut pty_rx, _ui_rx) = test_router();
+        attach(&router, "a");
+
+        router.dispatch(SessionCommand::Rename {
+            session_id: "a".into(),
+            name: " build ".into(),
+        });
+        router.dispatch(SessionCommand::Resize {
+            browser_id: "b1".into(),
+            session_id: "a".into(),
+            cols: 100,
+            rows: 30,
+        });
+        router.dispatch(SessionCommand::Close {
+            session_id: "a".into(),
+        });
+
+        assert!(matches!(
+            pty_rx.try_recv(),
+            Ok(PtyCommand::Rename { ref name, .. }) if name == "build"
+        ));
+        assert!(matches!(
+            pty_rx.try_recv(),
+            Ok(PtyCommand::Resize { cols: 100, rows: 30, .. })
+        ));
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::KillSession { .. })));
+    }
+
+    #[test]
+    fn test_smallest_size_is_pushed_and_broadcast() {
+        let (router, mut relay_rx, mut pty_rx, _ui_rx) = test_router();
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+        let resize = |browser_id: &str, cols, rows| SessionCommand::Resize {
+            browser_id: browser_id.into(),
+            session_id: "a".into(),
+            cols,
+            rows,
+        };
+
+        // The Mac's terminal already has its size
+        router.route_pty_event(PtyEvent::SessionResize {
+            session_id: "a".into(),
+            cols: 120,
+            rows: 40,
+        });
+        assert!(pty_rx.try_recv().is_err());
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionResize { cols: 120, rows: 40, .. })
+        ));
+
+        router.dispatch(resize("phone", 60, 50));
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Resize { cols: 60, rows: 40, .. })));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionResize { cols: 60, rows: 40, .. })
+        ));
+        router.dispatch(resize("laptop", 200, 60));
+        assert!(pty_rx.try_recv().is_err());
+        assert!(relay_rx.try_recv().is_err());
+
+        router.browser_left("phone");
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Resize { cols: 120, rows: 40, .. })));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionResize { cols: 120, rows: 40, .. })
+        ));
+
+        router.send_session_list();
+        assert!(matches!(relay_rx.try_recv(), Ok(RelayCommand::SendSessionList { .. })));
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionResize { cols: 120, rows: 40, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dispatch_unknown_session_reports_error() {
+        let (router, mut relay_rx, mut pty_rx, _ui_rx) = test_router();
+        router.dispatch(SessionCommand::Resize {
+            browser_id: "b1".into(),
+            session_id: "missing".into(),
+            cols: 80,
+            rows: 24,
+        });
+        assert!(pty_rx.try_recv().is_err());
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionError { ref session_id, .. }) if session_id == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_input_flood_is_dropped_and_reported() {
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, mut pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, ui_rx) = std_mpsc::channel();
+        let rate_limit = RateLimitConfig {
+            messages_per_sec: 0,
+            burst_messages: 2,
+            ..RateLimitConfig::default()
+        };
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            rate_limit,
+            SecurityConfig::default(),
+            ClipboardConfig::default(),
+            None,
+        );
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+        let _ = ui_rx.try_recv(); // ShellConnected
+        let _ = ui_rx.try_recv(); // SessionProject
+
+        for _ in 0..4 {
+            router.route_inbound(InboundFrame::Input {
+                session_id: "a".into(),
+                data: b"x".to_vec(),
+            });
+        }
+
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Write { .. })));
+        assert!(matches!(pty_rx.try_recv(), Ok(PtyCommand::Write { .. })));
+        assert!(pty_rx.try_recv().is_err());
+        // Reported once, not per dropped message
+        let mut events = ui_rx
+            .try_iter()
+            .filter(|e| !matches!(e, UiEvent::RemoteInput { .. }));
+        assert!(matches!(
+            events.next(),
+            Some(UiEvent::InputRateLimited { ref name, .. }) if name == "zsh"
+        ));
+        assert!(events.next().is_none());
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionError { .. })
+        ));
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_input_for_unknown_sessions_is_not_rate_limited() {
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, mut pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, ui_rx) = std_mpsc::channel();
+        let rate_limit = RateLimitConfig {
+            messages_per_sec: 0,
+            burst_messages: 0,
+            ..RateLimitConfig::default()
+        };
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            rate_limit,
+            SecurityConfig::default(),
+            ClipboardConfig::default(),
+            None,
+        );
+
+        for i in 0..100 {
+            router.route_inbound(InboundFrame::Input {
+                session_id: format!("made-up-{}", i),
+                data: b"x".to_vec(),
+            });
+        }
+
+        // No limiters kept, nobody notified
+        assert!(router.limiters.lock().unwrap().is_empty());
+        assert!(pty_rx.try_recv().is_err());
+        assert!(ui_rx.try_recv().is_err());
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_session_over_bandwidth_budget_gets_snapshots() {
+        let (router, mut relay_rx, _pty_rx, ui_rx) = test_router();
+        let router = router.with_bandwidth(BandwidthConfig {
+            enabled: true,
+            session_bytes_per_hour: 10,
+            total_bytes_per_hour: 0,
+            snapshot_secs: 10,
+        });
+        let transcript = Arc::new(Transcript::default());
+        router.route_pty_event(PtyEvent::Attached {
+            session_id: "a".into(),
+            session_name: "zsh".into(),
+            shell: "/bin/zsh".into(),
+            pid: 42,
+            tty: "/dev/ttys001".into(),
+            cwd: None,
+            stats: Default::default(),
+            transcript: transcript.clone(),
+            read_only: false,
+        });
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+        let _ = ui_rx.try_recv(); // ShellConnected
+
+        for data in [&b"$ ls\r\n"[..], b"file.txt\r\n", b"$ "] {
+            transcript.push(data);
+            router.route_pty_event(PtyEvent::Output {
+                session_id: "a".into(),
+                data: data.to_vec(),
+            });
+        }
+        assert!(matches!(relay_rx.try_recv(), Ok(RelayCommand::SendTerminalData { .. })));
+        assert!(matches!(relay_rx.try_recv(), Ok(RelayCommand::SendSessionError { .. })));
+        assert!(relay_rx.try_recv().is_err());
+        assert!(matches!(
+            ui_rx.try_recv(),
+            Ok(UiEvent::BandwidthExceeded { name: Some(ref name) }) if name == "zsh"
+        ));
+
+        router.send_snapshots();
+        match relay_rx.try_recv() {
+            Ok(RelayCommand::SendTerminalData { data, .. }) => {
+                assert_eq!(data, b"\x1b[H\x1b[2J$ ls\r\nfile.txt\r\n$ ");
+            }
+            other => panic!("Expected a snapshot, got {:?}", other),
+        }
+        // Nothing new since the last snapshot
+        router.send_snapshots();
+        assert!(relay_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_remote_close_can_be_disabled() {
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel();
+        let (pty_tx, mut pty_rx) = mpsc::unbounded_channel();
+        let (ui_tx, _ui_rx) = std_mpsc::channel();
+        let security = SecurityConfig {
+            allow_remote_close: false,
+            ..SecurityConfig::default()
+        };
+        let router = Router::new(
+            relay_tx,
+            pty_tx,
+            ui_tx,
+            RateLimitConfig::default(),
+            security,
+            ClipboardConfig::default(),
+            None,
+        );
+        attach(&router, "a");
+        let _ = relay_rx.try_recv(); // SendSessionConnected
+
+        router.dispatch(SessionCommand::Close {
+            session_id: "a".into(),
+        });
+        assert!(pty_rx.try_recv().is_err());
+        assert!(matches!(
+            relay_rx.try_recv(),
+            Ok(RelayCommand::SendSessionError { .. })
+        ));
+    }
+}
@@ -0,0 +1,300 @@
+//! Installing and removing the shell integration.
+//!
+//! The shell integration (`shell-integration/init.{zsh,bash,fish}`) wraps
+//! every new interactive terminal shell in pty-proxy. Installing writes the
+//! init scripts to `~/.terminal-remote/` and appends a guarded snippet that
+//! sources them to the shell rc files, between marker lines so it can be
+//! found again and removed cleanly:
+//!
+//! ```text
+//! # >>> Terminal Remote shell integration >>>
+//! [ -f "$HOME/.terminal-remote/init.zsh" ] && source "$HOME/.terminal-remote/init.zsh"
+//! # <<< Terminal Remote shell integration <<<
+//! ```
+//!
+//! The source line written by `scripts/install.sh` counts as installed too,
+//! and is removed along with the snippet.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BEGIN_MARKER: &str = "# >>> Terminal Remote shell integration >>>";
+const END_MARKER: &str = "# <<< Terminal Remote shell integration <<<";
+
+/// Comment `scripts/install.sh` writes above its source line.
+const LEGACY_COMMENT: &str = "# Terminal Remote shell integration";
+
+/// Directory below `$HOME` holding the init scripts.
+const INIT_DIR: &str = ".terminal-remote";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Zsh,
+    Bash,
+    Fish,
+}
+
+impl Shell {
+    pub const ALL: [Shell; 3] = [Shell::Zsh, Shell::Bash, Shell::Fish];
+
+    /// Shell for a `$SHELL` value such as `/bin/zsh`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).file_name()?.to_str()? {
+            "zsh" => Some(Shell::Zsh),
+            "bash" => Some(Shell::Bash),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    fn init_file(self) -> &'static str {
+        match self {
+            Shell::Zsh => "init.zsh",
+            Shell::Bash => "init.bash",
+            Shell::Fish => "init.fish",
+        }
+    }
+
+    fn init_script(self) -> &'static str {
+        match self {
+            Shell::Zsh => include_str!("../../shell-integration/init.zsh"),
+            Shell::Bash => include_str!("../../shell-integration/init.bash"),
+            Shell::Fish => include_str!("../../shell-integration/init.fish"),
+        }
+    }
+
+    /// Rc file the snippet goes into. Bash uses `.bashrc`, or
+    /// `.bash_profile` if only that exists (as `scripts/install.sh` does).
+    pub fn rc_file(self, home: &Path) -> PathBuf {
+        match self {
+            Shell::Zsh => home.join(".zshrc"),
+            Shell::Bash => {
+                let bashrc = home.join(".bashrc");
+                let profile = home.join(".bash_profile");
+                if !bashrc.exists() && profile.exists() {
+                    profile
+                } else {
+                    bashrc
+                }
+            }
+            Shell::Fish => home.join(".config/fish/config.fish"),
+        }
+    }
+
+    /// Guarded snippet sourcing the init script, with its marker lines.
+    pub fn snippet(self) -> String {
+        let init = format!("$HOME/{}/{}", INIT_DIR, self.init_file());
+        let line = match self {
+            Shell::Fish => format!("test -f \"{0}\"; and source \"{0}\"", init),
+            _ => format!("[ -f \"{0}\" ] && source \"{0}\"", init),
+        };
+        format!("{}\n{}\n{}\n", BEGIN_MARKER, line, END_MARKER)
+    }
+}
+
+/// Whether an rc file's contents already load the shell integration.
+pub fn is_installed_in(rc: &str) -> bool {
+    rc.lines()
+        .any(|line| line.trim() == BEGIN_MARKER || is_legacy_source_line(line))
+}
+
+fn is_legacy_source_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("source") && line.contains("terminal-remote/init.")
+}
+
+/// `rc` with the snippet appended after a blank line, or `None` if the
+/// integration is already installed.
+pub fn add_snippet(rc: &str, shell: Shell) -> Option<String> {
+    if is_installed_in(rc) {
+        return None;
+    }
+    let mut out = rc.to_string();
+    if !out.is_empty() {
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out.push_str(&shell.snippet());
+    Some(out)
+}
+
+/// `rc` without the snippet (and the blank line before it) or the
+/// `scripts/install.sh` source line, or `None` if neither is present.
+pub fn remove_snippet(rc: &str) -> Option<String> {
+    if !is_installed_in(rc) {
+        return None;
+    }
+    let mut kept: Vec<&str> = Vec::new();
+    let mut in_block = false;
+    for line in rc.lines() {
+        let trimmed = line.trim();
+        if trimmed == BEGIN_MARKER {
+            in_block = true;
+            if kept.last().is_some_and(|l| l.trim().is_empty()) {
+                kept.pop();
+            }
+        } else if in_block {
+            in_block = trimmed != END_MARKER;
+        } else if trimmed == LEGACY_COMMENT || is_legacy_source_line(line) {
+            if kept.last().is_some_and(|l| l.trim().is_empty()) {
+                kept.pop();
+            }
+        } else {
+            kept.push(line);
+        }
+    }
+    let mut out = kept.join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Some(out)
+}
+
+/// What happened to one rc file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    AlreadyInstalled(PathBuf),
+    Removed(PathBuf),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "Added to {}", path.display()),
+            Change::AlreadyInstalled(path) => write!(f, "Already installed in {}", path.display()),
+            Change::Removed(path) => write!(f, "Removed from {}", path.display()),
+        }
+    }
+}
+
+/// Whether any shell's rc file loads the integration.
+pub fn is_installed(home: &Path) -> bool {
+    Shell::ALL.iter().any(|shell| {
+        std::fs::read_to_string(shell.rc_file(home)).is_ok_and(|rc| is_installed_in(&rc))
+    })
+}
+
+/// Write the init scripts and add the snippet to the rc files of every
+/// shell that has one, and of `login_shell` (creating its rc file).
+pub fn install(home: &Path, login_shell: Option<Shell>) -> io::Result<Vec<Change>> {
+    let init_dir = home.join(INIT_DIR);
+    std::fs::create_dir_all(&init_dir)?;
+    for shell in Shell::ALL {
+        std::fs::write(init_dir.join(shell.init_file()), shell.init_script())?;
+    }
+
+    let mut changes = Vec::new();
+    for shell in Shell::ALL {
+        let path = shell.rc_file(home);
+        let rc = match std::fs::read_to_string(&path) {
+            Ok(rc) => rc,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && login_shell == Some(shell) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                String::new()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        match add_snippet(&rc, shell) {
+            Some(updated) => {
+                std::fs::write(&path, updated)?;
+                changes.push(Change::Added(path));
+            }
+            None => changes.push(Change::AlreadyInstalled(path)),
+        }
+    }
+    Ok(changes)
+}
+
+/// Remove the snippet from every rc file that has it, and the init scripts.
+pub fn uninstall(home: &Path) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let rc_files = [
+        home.join(".zshrc"),
+        home.join(".bashrc"),
+        home.join(".bash_profile"),
+        home.join(".config/fish/config.fish"),
+    ];
+    for path in rc_files {
+        let rc = match std::fs::read_to_string(&path) {
+            Ok(rc) => rc,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(updated) = remove_snippet(&rc) {
+            std::fs::write(&path, updated)?;
+            changes.push(Change::Removed(path));
+        }
+    }
+    for shell in Shell::ALL {
+        match std::fs::remove_file(home.join(INIT_DIR).join(shell.init_file())) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove_round_trip() {
+        let rc = "export PATH=\"$HOME/bin:$PATH\"\neval \"$(starship init zsh)\"\n";
+        let added = add_snippet(rc, Shell::Zsh).unwrap();
+        assert!(added.starts_with(rc));
+        assert!(added.ends_with(&Shell::Zsh.snippet()));
+        assert!(added.contains("source \"$HOME/.terminal-remote/init.zsh\""));
+
+        // Installing twice is detected
+        assert_eq!(add_snippet(&added, Shell::Zsh), None);
+        assert_eq!(remove_snippet(&added).as_deref(), Some(rc));
+        assert_eq!(remove_snippet(rc), None);
+
+        // An empty or newly created rc file
+        let added = add_snippet("", Shell::Fish).unwrap();
+        assert!(added.contains("test -f \"$HOME/.terminal-remote/init.fish\"; and source"));
+        assert_eq!(remove_snippet(&added).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_install_script_line_counts_as_installed() {
+        let rc = "alias ll='ls -l'\n\n# Terminal Remote shell integration\nsource \"/Users/me/.terminal-remote/init.bash\"\n";
+        assert!(is_installed_in(rc));
+        assert_eq!(add_snippet(rc, Shell::Bash), None);
+        assert_eq!(remove_snippet(rc).as_deref(), Some("alias ll='ls -l'\n"));
+    }
+
+    #[test]
+    fn test_install_and_uninstall_files() {
+        let home = std::env::temp_dir().join(format!("shell-integration-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&home);
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(home.join(".bashrc"), "alias ll='ls -l'\n").unwrap();
+
+        let changes = install(&home, Shell::from_path("/bin/zsh")).unwrap();
+        assert_eq!(
+            changes,
+            vec![Change::Added(home.join(".zshrc")), Change::Added(home.join(".bashrc"))]
+        );
+        assert!(home.join(".terminal-remote/init.fish").exists());
+        assert!(is_installed(&home));
+        assert_eq!(
+            install(&home, Some(Shell::Zsh)).unwrap()[0],
+            Change::AlreadyInstalled(home.join(".zshrc"))
+        );
+
+        assert_eq!(uninstall(&home).unwrap().len(), 2);
+        assert!(!is_installed(&home));
+        assert!(!home.join(".terminal-remote/init.zsh").exists());
+        assert_eq!(std::fs::read_to_string(home.join(".bashrc")).unwrap(), "alias ll='ls -l'\n");
+        let _ = std::fs::remove_dir_all(&home);
+    }
+}
@@ -0,0 +1,138 @@
+//! One size per session when it is viewed at several.
+//!
+//! The Mac's terminal reports its size (pty-proxy on SIGWINCH, tmux for its
+//! panes, ssh sessions the size they were opened at) and each browser may
+//! ask for its own with `resize_session`. The [`SizePolicy`] turns them into the effective size, which the router pushes
+//! to the session's PTY and broadcasts to every browser as `session_resize`
+//! so their renderers match. A browser's request is forgotten when it
+//! disconnects.
+
+use crate::config::SizePolicy;
+use std::collections::HashMap;
+
+/// Columns and rows.
+pub type Size = (u16, u16);
+
+#[derive(Debug, Default)]
+struct Requests {
+    local: Option<Size>,
+    browsers: HashMap<String, Size>,
+    effective: Option<Size>,
+}
+
+impl Requests {
+    fn smallest(&self) -> Option<Size> {
+        self.local
+            .iter()
+            .chain(self.browsers.values())
+            .copied()
+            .reduce(|(c1, r1), (c2, r2)| (c1.min(c2), r1.min(r2)))
+    }
+
+    fn compute(&self, policy: SizePolicy) -> Option<Size> {
+        match policy {
+            SizePolicy::Smallest => self.smallest(),
+            SizePolicy::Local => self.local.or_else(|| self.smallest()),
+        }
+    }
+
+    /// Recompute the effective size; returns it if it changed.
+    fn update(&mut self, policy: SizePolicy) -> Option<Size> {
+        let effective = self.compute(policy);
+        if effective == self.effective {
+            return None;
+        }
+        self.effective = effective;
+        effective
+    }
+}
+
+/// Size requests of every session.
+#[derive(Debug, Default)]
+pub struct Sizes {
+    policy: SizePolicy,
+    sessions: HashMap<String, Requests>,
+}
+
+impl Sizes {
+    pub fn new(policy: SizePolicy) -> Self {
+        Self {
+            policy,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// The Mac's terminal has this size. Returns the new effective size if
+    /// it changed.
+    pub fn set_local(&mut self, session_id: &str, size: Size) -> Option<Size> {
+        let requests = self.sessions.entry(session_id.to_string()).or_default();
+        requests.local = Some(size);
+        requests.update(self.policy)
+    }
+
+    /// A browser asked for this size. Returns the new effective size if it
+    /// changed.
+    pub fn set_browser(&mut self, session_id: &str, browser_id: &str, size: Size) -> Option<Size> {
+        let requests = self.sessions.entry(session_id.to_string()).or_default();
+        requests.browsers.insert(browser_id.to_string(), size);
+        requests.update(self.policy)
+    }
+
+    /// Forget a browser's requests. Returns the sessions whose effective
+    /// size changed, with the new size.
+    pub fn remove_browser(&mut self, browser_id: &str) -> Vec<(String, Size)> {
+        let mut changed = Vec::new();
+        for (session_id, requests) in &mut self.sessions {
+            if requests.browsers.remove(browser_id).is_none() {
+                continue;
+            }
+            if let Some(size) = requests.update(self.policy) {
+                changed.push((session_id.clone(), size));
+            }
+        }
+        changed.sort();
+        changed
+    }
+
+    pub fn local(&self, session_id: &str) -> Option<Size> {
+        self.sessions.get(session_id).and_then(|r| r.local)
+    }
+
+    pub fn effective(&self, session_id: &str) -> Option<Size> {
+        self.sessions.get(session_id).and_then(|r| r.effective)
+    }
+
+    pub fn remove_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smallest_fits_every_viewer() {
+        let mut sizes = Sizes::new(SizePolicy::Smallest);
+        assert_eq!(sizes.set_local("a", (120, 40)), Some((120, 40)));
+        assert_eq!(sizes.set_browser("a", "phone", (60, 50)), Some((60, 40)));
+        // Larger than the effective size: nothing changes
+        assert_eq!(sizes.set_browser("a", "laptop", (200, 60)), None);
+        assert_eq!(sizes.remove_browser("laptop"), Vec::new());
+        assert_eq!(sizes.remove_browser("phone"), vec![("a".to_string(), (120, 40))]);
+        assert_eq!(sizes.effective("a"), Some((120, 40)));
+    }
+
+    #[test]
+    fn test_local_wins_when_known() {
+        let mut sizes = Sizes::new(SizePolicy::Local);
+        // Size of the Mac's terminal not known yet: the browsers decide
+        assert_eq!(sizes.set_browser("b", "phone", (60, 50)), Some((60, 50)));
+        assert_eq!(sizes.set_local("a", (120, 40)), Some((120, 40)));
+        assert_eq!(sizes.set_browser("a", "phone", (60, 50)), None);
+        assert_eq!(sizes.local("a"), Some((120, 40)));
+
+        sizes.remove_session("a");
+        assert_eq!(sizes.effective("a"), None);
+    }
+}
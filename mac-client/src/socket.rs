@@ -0,0 +1,139 @@
+//! Safe binding of the Unix sockets mac-client listens on.
+//!
+//! A socket file left behind by a crashed instance must be removed before
+//! binding, but a socket owned by a running instance must not be: unlinking
+//! it silently cuts that instance off from every new pty-proxy. Before
+//! removing anything we try to connect, and only a refused connection marks
+//! the file as stale.
+//!
+//! Sockets are also linked from their pre-group-container location
+//! ([`link_legacy`]) for pty-proxy builds that only know that one.
+
+use std::io;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use crate::paths;
+use std::path::Path;
+use tokio::net::UnixListener;
+use tracing::{debug, warn};
+
+/// What is currently at a socket path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketState {
+    /// Nothing at the path
+    Absent,
+    /// A socket file with no listener behind it
+    Stale,
+    /// Another process is accepting connections
+    Live,
+}
+
+/// Probe a socket path by attempting to connect to it.
+pub fn probe(path: &Path) -> io::Result<SocketState> {
+    if std::fs::symlink_metadata(path).is_err() {
+        return Ok(SocketState::Absent);
+    }
+    match StdUnixStream::connect(path) {
+        Ok(_) => Ok(SocketState::Live),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(SocketState::Stale),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(SocketState::Absent),
+        Err(e) => Err(e),
+    }
+}
+
+/// Bind a listener at `path`, removing a stale socket file first.
+///
+/// Fails with [`io::ErrorKind::AddrInUse`] if another process is listening.
+pub fn bind_exclusive(path: &Path) -> io::Result<UnixListener> {
+    match probe(path)? {
+        SocketState::Live => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance is already listening on {}", path.display()),
+            ));
+        }
+        SocketState::Stale => {
+            warn!("Removing stale socket at {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        SocketState::Absent => {}
+    }
+    UnixListener::bind(path)
+}
+
+/// Link `socket` from `legacy` so older pty-proxy builds find it. A live
+/// socket there (an older mac-client still running) is left alone.
+pub fn link_legacy(socket: &Path, legacy: &Path) -> io::Result<()> {
+    if let Some(dir) = legacy.parent() {
+        paths::ensure_private_dir(dir)?;
+    }
+    if std::fs::symlink_metadata(legacy).is_ok() {
+        if probe(legacy)? == SocketState::Live && std::fs::read_link(legacy).ok().as_deref() != Some(socket) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance is listening on {}", legacy.display()),
+            ));
+        }
+        std::fs::remove_file(legacy)?;
+    }
+    std::os::unix::fs::symlink(socket, legacy)?;
+    debug!("Linked {} to {}", legacy.display(), socket.display());
+    Ok(())
+}
+
+/// Remove the link made by [`link_legacy`], if it still points to `socket`.
+pub fn unlink_legacy(socket: &Path, legacy: &Path) {
+    if std::fs::read_link(legacy).ok().as_deref() == Some(socket) {
+        let _ = std::fs::remove_file(legacy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener as StdUnixListener;
+    use std::path::PathBuf;
+
+    fn temp_socket(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tr-socket-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_probe_absent() {
+        let path = temp_socket("absent");
+        assert_eq!(probe(&path).unwrap(), SocketState::Absent);
+    }
+
+    #[test]
+    fn test_probe_live_and_stale() {
+        let path = temp_socket("live");
+        let listener = StdUnixListener::bind(&path).unwrap();
+        assert_eq!(probe(&path).unwrap(), SocketState::Live);
+
+        // Dropping the listener leaves the socket file behind
+        drop(listener);
+        assert_eq!(probe(&path).unwrap(), SocketState::Stale);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_link_reaches_the_socket() {
+        let path = temp_socket("linked");
+        let dir = std::env::temp_dir().join(format!("tr-socket-test-{}-legacy", std::process::id()));
+        let legacy = dir.join("pty.sock");
+        let _listener = StdUnixListener::bind(&path).unwrap();
+
+        // A stale socket left at the old path is replaced
+        std::fs::create_dir_all(&dir).unwrap();
+        drop(StdUnixListener::bind(&legacy).unwrap());
+        link_legacy(&path, &legacy).unwrap();
+        assert_eq!(probe(&legacy).unwrap(), SocketState::Live);
+        link_legacy(&path, &legacy).unwrap();
+
+        unlink_legacy(&path, &legacy);
+        assert_eq!(probe(&legacy).unwrap(), SocketState::Absent);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,474 @@
+//! Remote shells over ssh, for servers without pty-proxy (`[[ssh.hosts]]`
+//! in the config).
+//!
+//! Choosing a host under "SSH Hosts" in the menu bar makes [`SshManager`]
+//! run `ssh <destination>` in a PTY it opens itself, and report it as a
+//! session like any pty-proxy session. There is no local terminal window:
+//! browsers set the PTY size, and closing the session from a browser hangs
+//! up ssh. Password and host key prompts appear in the browser, so key or
+//! agent authentication is the smoother setup.
+
+use crate::alerts::{Alert, Severity};
+use crate::backend::SessionBackend;
+use crate::bell::BellScanner;
+use crate::config::{SshConfig, SshHost};
+use crate::pty::{PtyCommand, PtyEvent, PtySessionInfo, SessionStats};
+use crate::supervisor;
+use crate::transcript::Transcript;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+
+/// Prefix of the IDs of ssh sessions.
+pub const SESSION_PREFIX: &str = "ssh-";
+
+const DEFAULT_SSH: &str = "/usr/bin/ssh";
+
+/// Size of a new session until a browser sets one.
+const INITIAL_SIZE: (u16, u16) = (80, 24);
+
+/// Whether a session ID belongs to an ssh session.
+pub fn is_ssh_session(session_id: &str) -> bool {
+    session_id.starts_with(SESSION_PREFIX)
+}
+
+/// ssh arguments connecting to `host`.
+pub fn ssh_args(host: &SshHost) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(port) = host.port {
+        args.push("-p".to_string());
+        args.push(port.to_string());
+    }
+    args.extend(host.args.iter().cloned());
+    // Nothing after the destination is taken as an option
+    args.push("--".to_string());
+    args.push(host.destination.clone());
+    args
+}
+
+/// A process running in a new PTY.
+pub struct PtyChild {
+    pub child: Child,
+    /// Master side of the PTY
+    pub master: OwnedFd,
+    /// Path of the slave side, e.g. /dev/ttys007
+    pub tty: String,
+}
+
+/// Run `program` as the session leader of a new PTY of the given size.
+pub fn spawn_in_pty(program: &Path, args: &[String], cols: u16, rows: u16) -> io::Result<PtyChild> {
+    let (mut master, mut slave): (RawFd, RawFd) = (-1, -1);
+    let mut size = winsize(cols, rows);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            // *mut on macOS, *const elsewhere
+            std::ptr::addr_of_mut!(size),
+        )
+    };
+    if opened != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    let tty = tty_name(&slave).unwrap_or_default();
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env("TERM", "xterm-256color")
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave))
+        .kill_on_drop(true);
+    unsafe {
+        command.pre_exec(|| {
+            // New session with the PTY (stdin) as controlling terminal
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // The command, and with it our copies of the slave, is dropped on
+    // return, so reads from the master end when the child exits
+    let child = command.spawn()?;
+    Ok(PtyChild { child, master, tty })
+}
+
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn set_size(master: &OwnedFd, cols: u16, rows: u16) {
+    let size = winsize(cols, rows);
+    unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+}
+
+fn tty_name(fd: &OwnedFd) -> Option<String> {
+    let mut buf = [0 as libc::c_char; 128];
+    if unsafe { libc::ttyname_r(fd.as_raw_fd(), buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// What [`SshManager`] is asked to do.
+enum SshCommand {
+    Connect(SshHost),
+    Session(PtyCommand),
+}
+
+/// Opens ssh sessions; handed to the menu bar's command loop.
+#[derive(Clone)]
+pub struct SshLauncher {
+    commands: mpsc::UnboundedSender<SshCommand>,
+}
+
+impl SshLauncher {
+    /// Start a session connected to `host`.
+    pub fn connect(&self, host: SshHost) {
+        let _ = self.commands.send(SshCommand::Connect(host));
+    }
+}
+
+/// Runs ssh sessions; the [`SessionBackend`] for sessions whose ID starts
+/// with [`SESSION_PREFIX`].
+pub struct SshManager {
+    events: Option<mpsc::UnboundedReceiver<PtyEvent>>,
+    commands: mpsc::UnboundedSender<SshCommand>,
+}
+
+impl SshManager {
+    pub fn new(config: SshConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let binary = config.path.unwrap_or_else(|| PathBuf::from(DEFAULT_SSH));
+
+        let report = {
+            let event_tx = event_tx.clone();
+            move |alert: Alert| {
+                if alert.severity == Severity::Critical {
+                    let _ = event_tx.send(PtyEvent::Error(alert.message));
+                }
+            }
+        };
+        let command_rx = Arc::new(Mutex::new(command_rx));
+        supervisor::supervise("ssh sessions", report, move || {
+            let command_rx = command_rx.clone();
+            let mut sessions = Sessions::new(binary.clone(), event_tx.clone());
+            async move {
+                sessions.run(&mut *command_rx.lock().await).await;
+                Ok(())
+            }
+        });
+        Self {
+            events: Some(event_rx),
+            commands: command_tx,
+        }
+    }
+
+    pub fn launcher(&self) -> SshLauncher {
+        SshLauncher {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl SessionBackend for SshManager {
+    fn name(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn owns(&self, session_id: &str) -> bool {
+        is_ssh_session(session_id)
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<PtyEvent>> {
+        self.events.take()
+    }
+
+    fn send(&self, cmd: PtyCommand) {
+        let _ = self.commands.send(SshCommand::Session(cmd));
+    }
+}
+
+/// A running ssh session.
+struct SshSession {
+    name: String,
+    pid: u32,
+    tty: String,
+    master: OwnedFd,
+    writer: File,
+    child: Child,
+    stats: Arc<SessionStats>,
+    transcript: Arc<Transcript>,
+    bell: BellScanner,
+}
+
+/// What the PTY readers report: output, or `None` once ssh exited.
+type ReaderEvent = (String, Option<Vec<u8>>);
+
+struct Sessions {
+    binary: PathBuf,
+    event_tx: mpsc::UnboundedSender<PtyEvent>,
+    sessions: HashMap<String, SshSession>,
+    reader_tx: mpsc::UnboundedSender<ReaderEvent>,
+    reader_rx: mpsc::UnboundedReceiver<ReaderEvent>,
+}
+
+impl Sessions {
+    fn new(binary: PathBuf, event_tx: mpsc::UnboundedSender<PtyEvent>) -> Self {
+        let (reader_tx, reader_rx) = mpsc::unbounded_channel();
+        Self {
+            binary,
+            event_tx,
+            sessions: HashMap::new(),
+            reader_tx,
+            reader_rx,
+        }
+    }
+
+    async fn run(&mut self, command_rx: &mut mpsc::UnboundedReceiver<SshCommand>) {
+        loop {
+            tokio::select! {
+                Some((session_id, data)) = self.reader_rx.recv() => match data {
+                    Some(data) => self.output(&session_id, data),
+                    None => self.ended(&session_id).await,
+                },
+                cmd = command_rx.recv() => match cmd {
+                    Some(SshCommand::Session(PtyCommand::Shutdown)) | None => break,
+                    Some(SshCommand::Connect(host)) => self.connect(host),
+                    Some(SshCommand::Session(cmd)) => self.command(cmd).await,
+                },
+            }
+        }
+        info!("ssh sessions shutting down");
+        // Dropping the children kills them
+        self.sessions.clear();
+    }
+
+    fn connect(&mut self, host: SshHost) {
+        let (cols, rows) = INITIAL_SIZE;
+        let spawned = spawn_in_pty(&self.binary, &ssh_args(&host), cols, rows)
+            .and_then(|pty| Ok((pty.master.try_clone()?, pty.master.try_clone()?, pty)));
+        let (reader, writer, pty) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                warn!("Cannot start ssh to {}: {}", host.destination, e);
+                let _ = self.event_tx.send(PtyEvent::Error(format!(
+                    "Cannot start ssh to {}: {}",
+                    host.label(),
+                    e
+                )));
+                return;
+            }
+        };
+        let session_id = format!("{}{}", SESSION_PREFIX, &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let name = format!("ssh {}", host.label());
+        let pid = pty.child.id().unwrap_or(0);
+        info!(session_id = %session_id, "ssh to {} started (pid {})", host.destination, pid);
+
+        let stats = Arc::new(SessionStats::default());
+        let transcript = Arc::new(Transcript::default());
+        let _ = self.event_tx.send(PtyEvent::Attached {
+            session_id: session_id.clone(),
+            session_name: name.clone(),
+            shell: "ssh".into(),
+            pid,
+            tty: pty.tty.clone(),
+            cwd: None,
+            stats: stats.clone(),
+            transcript: transcript.clone(),
+            read_only: false,
+        });
+        let _ = self.event_tx.send(PtyEvent::SessionResize {
+            session_id: session_id.clone(),
+            cols,
+            rows,
+        });
+
+        let reader_tx = self.reader_tx.clone();
+        let reader_id = session_id.clone();
+        tokio::spawn(async move {
+            let mut reader = File::from_std(std::fs::File::from(reader));
+            let mut buf = vec![0u8; 8192];
+            loop {
+                // EIO once ssh exited and the slave side closed
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if reader_tx.send((reader_id.clone(), Some(buf[..n].to_vec()))).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            let _ = reader_tx.send((reader_id, None));
+        });
+
+        self.sessions.insert(
+            session_id,
+            SshSession {
+                name,
+                pid,
+                tty: pty.tty,
+                master: pty.master,
+                writer: File::from_std(std::fs::File::from(writer)),
+                child: pty.child,
+                stats,
+                transcript,
+                bell: BellScanner::new(),
+            },
+        );
+    }
+
+    fn output(&mut self, session_id: &str, data: Vec<u8>) {
+        let Some(session) = self.sessions.get_mut(session_id) else {
+            return;
+        };
+        session.stats.bytes_out.fetch_add(data.len() as u64, Ordering::Relaxed);
+        session.transcript.push(&data);
+        if session.bell.feed(&data, Instant::now()) {
+            let _ = self.event_tx.send(PtyEvent::Bell {
+                session_id: session_id.to_string(),
+            });
+        }
+        let _ = self.event_tx.send(PtyEvent::Output {
+            session_id: session_id.to_string(),
+            data,
+        });
+    }
+
+    async fn ended(&mut self, session_id: &str) {
+        let Some(mut session) = self.sessions.remove(session_id) else {
+            return;
+        };
+        match session.child.wait().await {
+            Ok(status) => info!(session_id = %session_id, "{} exited ({})", session.name, status),
+            Err(e) => debug!(session_id = %session_id, "Cannot wait for ssh: {}", e),
+        }
+        let _ = self.event_tx.send(PtyEvent::Detached {
+            session_id: session_id.to_string(),
+        });
+    }
+
+    async fn command(&mut self, cmd: PtyCommand) {
+        match cmd {
+            PtyCommand::Write { session_id, data } => {
+                let Some(session) = self.sessions.get_mut(&session_id) else {
+                    return;
+                };
+                match session.writer.write_all(&data).await {
+                    Ok(()) => {
+                        session.stats.bytes_in.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    }
+                    Err(e) => warn!(session_id = %session_id, "Cannot write to ssh: {}", e),
+                }
+            }
+            PtyCommand::KillSession { session_id } => {
+                let Some(session) = self.sessions.get(&session_id) else {
+                    return;
+                };
+                info!(session_id = %session_id, "Hanging up {}", session.name);
+                // The reader sees the PTY close and reports the end
+                if session.pid != 0 {
+                    unsafe { libc::kill(session.pid as i32, libc::SIGHUP) };
+                }
+            }
+            PtyCommand::Rename { session_id, name } => {
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.name = name.clone();
+                    let _ = self.event_tx.send(PtyEvent::Renamed { session_id, name });
+                }
+            }
+            PtyCommand::Resize { session_id, cols, rows } => {
+                if let Some(session) = self.sessions.get(&session_id) {
+                    set_size(&session.master, cols, rows);
+                }
+            }
+            PtyCommand::ListSessions { reply } => {
+                let mut list: Vec<(String, PtySessionInfo)> = self
+                    .sessions
+                    .iter()
+                    .map(|(session_id, s)| {
+                        let info = PtySessionInfo {
+                            name: s.name.clone(),
+                            shell: "ssh".into(),
+                            pid: s.pid,
+                            tty: s.tty.clone(),
+                            cwd: None,
+                            stats: s.stats.clone(),
+                        };
+                        (session_id.clone(), info)
+                    })
+                    .collect();
+                list.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+                let _ = reply.send(list);
+            }
+            PtyCommand::Shutdown => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args() {
+        let host = SshHost {
+            name: "web".into(),
+            destination: "deploy@web1".into(),
+            port: Some(2222),
+            args: vec!["-i".into(), "~/.ssh/deploy".into()],
+        };
+        assert_eq!(
+            ssh_args(&host),
+            vec!["-p", "2222", "-i", "~/.ssh/deploy", "--", "deploy@web1"]
+        );
+        assert!(is_ssh_session("ssh-1f2e3d4c"));
+        assert!(!is_ssh_session("tmux-4711-3"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_in_pty() {
+        let pty = spawn_in_pty(
+            &PathBuf::from("/bin/sh"),
+            &["-c".into(), "stty size; test -t 0 && echo tty".into()],
+            100,
+            30,
+        )
+        .unwrap();
+        let mut reader = File::from_std(std::fs::File::from(pty.master));
+        let mut output = Vec::new();
+        let mut buf = [0u8; 256];
+        while let Ok(n) = reader.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(String::from_utf8_lossy(&output), "30 100\r\ntty\r\n");
+    }
+}
@@ -0,0 +1,194 @@
+//! Restarting background tasks that stop unexpectedly.
+//!
+//! The PTY listener, the relay client and the forwarding loops between them
+//! are meant to run for the life of the app. If one panics or gives up with
+//! an error, the app keeps running but stops working, so each runs under
+//! [`supervise`]: the failure is logged and reported as an [`Alert`], and
+//! the task is started again after a [`Backoff`] delay. A task that returns
+//! `Ok(())` has finished (its input channel closed at shutdown) and is not
+//! restarted.
+
+use crate::alerts::Alert;
+use std::any::Any;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Delay before the first restart; doubled on every further failure.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between restarts.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A task that ran this long before failing starts over at the initial delay.
+pub const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+/// Failures in a row after which the alert is critical (notified).
+pub const CRITICAL_AFTER: u32 = 3;
+
+/// Restart delays of one task.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    failures: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: INITIAL_BACKOFF,
+            failures: 0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Record a failure of a run that lasted `ran_for`, returning the delay
+    /// before the next run.
+    pub fn fail(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= HEALTHY_AFTER {
+            self.failures = 0;
+        }
+        self.failures += 1;
+        self.initial
+            .saturating_mul(1 << (self.failures - 1).min(6))
+            .min(MAX_BACKOFF)
+    }
+
+    /// Failures in a row so far.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+/// Aborts the task when dropped, so aborting the supervisor stops it too.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Run the task built by `make` on the Tokio runtime, building and running
+/// it again whenever it panics or returns an error, until it returns
+/// `Ok(())` or the returned handle is aborted. Every failure is passed to
+/// `report`: as a warning, or a critical alert once the task has failed
+/// [`CRITICAL_AFTER`] times in a row.
+pub fn supervise<F, Fut>(
+    name: &'static str,
+    report: impl Fn(Alert) + Send + 'static,
+    make: F,
+) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    supervise_with(name, Backoff::default(), report, make)
+}
+
+fn supervise_with<F, Fut>(
+    name: &'static str,
+    mut backoff: Backoff,
+    report: impl Fn(Alert) + Send + 'static,
+    mut make: F,
+) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let started = Instant::now();
+            let mut task = AbortOnDrop(tokio::spawn(make()));
+            let reason = match (&mut task.0).await {
+                Ok(Ok(())) => {
+                    info!("{} finished", name);
+                    return;
+                }
+                Ok(Err(e)) => e,
+                Err(e) if e.is_panic() => format!("panicked: {}", panic_message(&*e.into_panic())),
+                Err(_) => return,
+            };
+            let delay = backoff.fail(started.elapsed());
+            error!(
+                "{} stopped ({}), restarting in {:?} (failure {} in a row)",
+                name,
+                reason,
+                delay,
+                backoff.failures()
+            );
+            let message = format!("{} stopped and was restarted: {}", name, reason);
+            report(if backoff.failures() >= CRITICAL_AFTER {
+                Alert::critical(message)
+            } else {
+                Alert::warning(message)
+            });
+            tokio::time::sleep(delay).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Severity;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_backoff_doubles_and_resets_after_healthy_run() {
+        let mut backoff = Backoff::default();
+        let quick = Duration::from_millis(10);
+        let delays: Vec<_> = (0..8).map(|_| backoff.fail(quick).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff.failures(), 8);
+
+        assert_eq!(backoff.fail(HEALTHY_AFTER), INITIAL_BACKOFF);
+        assert_eq!(backoff.failures(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_until_ok() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let runs_task = runs.clone();
+        let alerts_report = alerts.clone();
+        let backoff = Backoff {
+            initial: Duration::from_millis(1),
+            failures: 0,
+        };
+        let handle = supervise_with(
+            "test task",
+            backoff,
+            move |alert| alerts_report.lock().unwrap().push(alert),
+            move || {
+                let run = runs_task.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match run {
+                        0 => panic!("boom"),
+                        1 | 2 => Err("gave up".to_string()),
+                        _ => Ok(()),
+                    }
+                }
+            },
+        );
+        handle.await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 3);
+        assert_eq!(alerts[0].message, "test task stopped and was restarted: panicked: boom");
+        assert_eq!(alerts[1].severity, Severity::Warning);
+        assert_eq!(alerts[2].severity, Severity::Critical);
+    }
+}
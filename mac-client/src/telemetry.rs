@@ -0,0 +1,217 @@
+//! Opt-in anonymous usage metrics (`[telemetry]` in the config, off by
+//! default).
+//!
+//! Only aggregate counters are kept: sessions connected, relay connections
+//! lost, and errors by category. Categories are a fixed list matched against
+//! the error text, which itself is never sent, nor are session names, paths,
+//! host names, codes or any identifier of the Mac. The counters are kept in
+//! `usage.json` next to the config file and posted as JSON to the configured
+//! endpoint once every [`REPORT_INTERVAL`], then reset:
+//!
+//! ```json
+//! {"version":"2.0.0","period_secs":86400,"sessions":14,"relay_reconnects":2,
+//!  "errors":{"relay":3,"tunnel":1}}
+//! ```
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Time covered by one report.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Seconds `curl` may take to post a report.
+const POST_TIMEOUT_SECS: &str = "10";
+
+/// Error categories, with the words (lowercase) that put an error in one.
+/// Errors matching none are counted as "other".
+const ERROR_CATEGORIES: &[(&str, &[&str])] = &[
+    ("tunnel", &["cloudflared", "tunnel"]),
+    ("relay", &["relay"]),
+    ("tmux", &["tmux"]),
+    ("ssh", &["ssh"]),
+    ("control_socket", &["control socket"]),
+    ("pty", &["pty", "socket"]),
+    ("hotkeys", &["shortcut", "hotkey"]),
+    ("shell_integration", &["shell integration"]),
+];
+
+/// Category an error is counted under.
+pub fn error_category(message: &str) -> &'static str {
+    let message = message.to_lowercase();
+    ERROR_CATEGORIES
+        .iter()
+        .find(|(_, words)| words.iter().any(|w| message.contains(w)))
+        .map_or("other", |(category, _)| category)
+}
+
+fn unix_secs(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Counters since the last report.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    /// Unix timestamp (seconds) the counters started at
+    pub since: u64,
+    pub sessions: u64,
+    pub relay_reconnects: u64,
+    pub errors: BTreeMap<String, u64>,
+}
+
+/// What is sent to the endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub period_secs: u64,
+    pub sessions: u64,
+    pub relay_reconnects: u64,
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl Usage {
+    /// Location of the counters file (`usage.json` in [`paths::config_dir`]).
+    pub fn path() -> Option<PathBuf> {
+        Some(paths::config_dir()?.join("usage.json"))
+    }
+
+    /// Load the counters, starting from zero now if they are missing or
+    /// unreadable.
+    pub fn load(now: SystemTime) -> Self {
+        let usage = Self::path()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice::<Usage>(&bytes).ok())
+            .unwrap_or_default();
+        if usage.since == 0 {
+            return Self {
+                since: unix_secs(now),
+                ..usage
+            };
+        }
+        usage
+    }
+
+    /// Write the counters file.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn record_session(&mut self) {
+        self.sessions += 1;
+    }
+
+    /// A connection to the relay was lost.
+    pub fn record_reconnect(&mut self) {
+        self.relay_reconnects += 1;
+    }
+
+    pub fn record_error(&mut self, message: &str) {
+        *self.errors.entry(error_category(message).to_string()).or_default() += 1;
+    }
+
+    /// Whether a report covering [`REPORT_INTERVAL`] is due.
+    pub fn due(&self, now: SystemTime) -> bool {
+        unix_secs(now).saturating_sub(self.since) >= REPORT_INTERVAL.as_secs()
+    }
+
+    /// The counters as a report; they start over from zero at `now`.
+    pub fn take_report(&mut self, now: SystemTime) -> Report {
+        let now = unix_secs(now);
+        let usage = std::mem::replace(
+            self,
+            Usage {
+                since: now,
+                ..Usage::default()
+            },
+        );
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            period_secs: now.saturating_sub(usage.since),
+            sessions: usage.sessions,
+            relay_reconnects: usage.relay_reconnects,
+            errors: usage.errors,
+        }
+    }
+}
+
+/// Post a report to `endpoint` (blocking, up to 10 s).
+pub fn send(endpoint: &str, report: &Report) -> io::Result<()> {
+    let body = serde_json::to_vec(report)?;
+    let mut child = Command::new("/usr/bin/curl")
+        .args(["-fsS", "-m", POST_TIMEOUT_SECS, "-X", "POST"])
+        .args(["-H", "Content-Type: application/json", "--data-binary", "@-"])
+        .arg(endpoint)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(&body)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    info!("Sent usage report ({} sessions)", report.sessions);
+    Ok(())
+}
+
+/// Post a report on a helper thread, logging failures. A report that
+/// cannot be sent is dropped.
+pub fn send_in_background(endpoint: String, report: Report) {
+    std::thread::spawn(move || {
+        if let Err(e) = send(&endpoint, &report) {
+            warn!("Cannot send usage report to {}: {}", endpoint, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_categories() {
+        assert_eq!(error_category("Relay unreachable at wss://relay.example.com/ws"), "relay");
+        assert_eq!(error_category("cloudflared not found: No such file"), "tunnel");
+        assert_eq!(error_category("PTY listener stopped: Address in use"), "pty");
+        assert_eq!(error_category("Control socket failed"), "control_socket");
+        assert_eq!(error_category("Cannot open /Users/me/secret.txt"), "other");
+    }
+
+    #[test]
+    fn test_report_resets_counters() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut usage = Usage {
+            since: unix_secs(start),
+            ..Usage::default()
+        };
+        usage.record_session();
+        usage.record_session();
+        usage.record_reconnect();
+        usage.record_error("Relay unreachable");
+        assert!(!usage.due(start + Duration::from_secs(60)));
+
+        let later = start + REPORT_INTERVAL;
+        assert!(usage.due(later));
+        let report = usage.take_report(later);
+        assert_eq!(report.period_secs, REPORT_INTERVAL.as_secs());
+        assert_eq!((report.sessions, report.relay_reconnects), (2, 1));
+        assert_eq!(report.errors, BTreeMap::from([("relay".to_string(), 1)]));
+        assert_eq!(usage.sessions, 0);
+        assert!(!usage.due(later));
+    }
+}
@@ -0,0 +1,682 @@
+//! tmux panes as sessions, for users who keep their shells in tmux instead
+//! of wrapping every terminal with pty-proxy (`[tmux]` in the config).
+//!
+//! [`TmuxManager`] emits the same [`PtyEvent`] stream and takes the same
+//! [`PtyCommand`]s as the PTY manager, so the router and the relay see every
+//! pane of a mirrored tmux session as one more session. It attaches one
+//! control-mode client (`tmux -C`) per tmux session:
+//!
+//! - `%output` notifications carry pane output, with bytes below 0x20 and
+//!   `\` escaped as octal (`\033`)
+//! - window and layout notifications trigger a new `list-panes -a`, which
+//!   is also run every [`POLL_INTERVAL`] to notice new tmux sessions
+//! - browser input is written back with `send-keys -H` (hex bytes)
+//!
+//! A newly found pane starts with its visible screen (`capture-pane -e`).
+//! Control clients attach with `ignore-size`, so they never shrink the
+//! user's windows; browsers are told the pane's size instead of resizing
+//! it. Closing a session from a browser kills the pane. Needs tmux 3.2 or
+//! later.
+
+use crate::alerts::{Alert, Severity};
+use crate::backend::SessionBackend;
+use crate::bell::BellScanner;
+use crate::config::TmuxConfig;
+use crate::pty::{PtyCommand, PtyEvent, PtySessionInfo, SessionStats};
+use crate::supervisor;
+use crate::transcript::Transcript;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+
+/// How often tmux is asked for its panes when nothing changed.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Session IDs of tmux panes start with this.
+pub const SESSION_PREFIX: &str = "tmux-";
+
+/// Where tmux is looked for when `path` is not set (apps started from the
+/// Finder do not get the shell's `PATH`).
+const TMUX_PATHS: &[&str] = &["/opt/homebrew/bin/tmux", "/usr/local/bin/tmux", "/usr/bin/tmux"];
+
+/// Bytes of input per `send-keys` command.
+const SEND_KEYS_CHUNK: usize = 256;
+
+/// `list-panes` format, tab-separated in the order of [`Pane`]'s fields.
+const PANE_FORMAT: &str = "#{pid}\t#{session_name}\t#{pane_id}\t#{pane_pid}\t#{pane_tty}\t\
+#{pane_current_command}\t#{pane_current_path}\t#{session_name}:#{window_index}.#{pane_index}\t\
+#{pane_width}\t#{pane_height}";
+
+/// Whether a session ID belongs to a tmux pane.
+pub fn is_tmux_session(session_id: &str) -> bool {
+    session_id.starts_with(SESSION_PREFIX)
+}
+
+/// A pane as listed by `list-panes -a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pane {
+    /// tmux server pid, so pane IDs of a restarted server are not mixed up
+    pub server_pid: u32,
+    pub session: String,
+    /// Pane ID, e.g. `%3`
+    pub pane_id: String,
+    /// Pid of the pane's shell
+    pub pid: u32,
+    pub tty: String,
+    /// Program in the foreground
+    pub command: String,
+    pub path: String,
+    /// `session:window.pane`
+    pub target: String,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Pane {
+    /// Session ID of the pane, e.g. `tmux-4711-3`.
+    pub fn session_id(&self) -> String {
+        format!(
+            "{}{}-{}",
+            SESSION_PREFIX,
+            self.server_pid,
+            self.pane_id.trim_start_matches('%')
+        )
+    }
+
+    /// Display name, e.g. `tmux work:1.0 - ~/src`.
+    pub fn name(&self) -> String {
+        format!("tmux {} - {}", self.target, self.path)
+    }
+}
+
+/// Parse `list-panes -F` output in [`PANE_FORMAT`].
+pub fn parse_panes(output: &str) -> Vec<Pane> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [server_pid, session, pane_id, pid, tty, command, path, target, cols, rows] =
+                fields.as_slice()
+            else {
+                return None;
+            };
+            Some(Pane {
+                server_pid: server_pid.parse().ok()?,
+                session: session.to_string(),
+                pane_id: pane_id.to_string(),
+                pid: pid.parse().ok()?,
+                tty: tty.to_string(),
+                command: command.to_string(),
+                path: path.to_string(),
+                target: target.to_string(),
+                cols: cols.parse().ok()?,
+                rows: rows.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// A line from a control-mode client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// Output of a pane
+    Output { pane_id: String, data: Vec<u8> },
+    /// Windows or panes were added, closed or rearranged
+    LayoutChanged,
+    /// The client is detaching (its session ended or the server exited)
+    Exit,
+    /// Command replies and notifications that need no action
+    Other,
+}
+
+/// Parse a control-mode line (without the newline).
+pub fn parse_line(line: &[u8]) -> Notification {
+    let (name, rest) = match line.iter().position(|&b| b == b' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, &[][..]),
+    };
+    match name {
+        b"%output" => {
+            let (pane, data) = match rest.iter().position(|&b| b == b' ') {
+                Some(i) => (&rest[..i], &rest[i + 1..]),
+                None => (rest, &[][..]),
+            };
+            Notification::Output {
+                pane_id: String::from_utf8_lossy(pane).into_owned(),
+                data: unescape(data),
+            }
+        }
+        b"%window-add" | b"%window-close" | b"%unlinked-window-add" | b"%unlinked-window-close"
+        | b"%layout-change" | b"%session-window-changed" | b"%window-pane-changed"
+        | b"%sessions-changed" | b"%session-renamed" | b"%window-renamed" => Notification::LayoutChanged,
+        b"%exit" => Notification::Exit,
+        _ => Notification::Other,
+    }
+}
+
+/// Undo the octal escaping of `%output` data (tmux writes `\` as `\134`).
+pub fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let octal = data.get(i + 1..i + 4).filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match (data[i], octal) {
+            (b'\\', Some(digits)) => {
+                let value = digits.iter().fold(0u32, |v, d| v * 8 + u32::from(d - b'0'));
+                out.push(value as u8);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Control-mode commands typing `data` into a pane, one line each.
+pub fn send_keys_commands(pane_id: &str, data: &[u8]) -> String {
+    let mut commands = String::new();
+    for chunk in data.chunks(SEND_KEYS_CHUNK) {
+        commands.push_str("send-keys -H -t ");
+        commands.push_str(pane_id);
+        for byte in chunk {
+            commands.push_str(&format!(" {:02x}", byte));
+        }
+        commands.push('\n');
+    }
+    commands
+}
+
+/// Screen contents from `capture-pane -p -e` as terminal output: clear the
+/// screen first and end lines with CRLF.
+fn screen_output(captured: &[u8]) -> Vec<u8> {
+    let mut out = b"\x1b[H\x1b[2J".to_vec();
+    let text = captured.strip_suffix(b"\n").unwrap_or(captured);
+    for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(line);
+    }
+    out
+}
+
+/// The tmux binary: the configured one or the first one found.
+fn tmux_binary(config: &TmuxConfig) -> PathBuf {
+    if let Some(path) = &config.path {
+        return path.clone();
+    }
+    TMUX_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .find(|p| p.exists())
+        .unwrap_or_else(|| PathBuf::from("tmux"))
+}
+
+/// Mirrors tmux panes as sessions; the [`SessionBackend`] for sessions
+/// whose ID starts with [`SESSION_PREFIX`].
+pub struct TmuxManager {
+    events: Option<mpsc::UnboundedReceiver<PtyEvent>>,
+    commands: mpsc::UnboundedSender<PtyCommand>,
+}
+
+impl TmuxManager {
+    /// Start mirroring the tmux sessions named in `config` (all if none
+    /// are).
+    pub fn new(config: TmuxConfig) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let binary = tmux_binary(&config);
+        info!("Mirroring tmux sessions with {}", binary.display());
+
+        let report = {
+            let event_tx = event_tx.clone();
+            move |alert: Alert| {
+                if alert.severity == Severity::Critical {
+                    let _ = event_tx.send(PtyEvent::Error(alert.message));
+                }
+            }
+        };
+        let command_rx = Arc::new(Mutex::new(command_rx));
+        supervisor::supervise("tmux mirror", report, move || {
+            let command_rx = command_rx.clone();
+            let mut mirror = Mirror::new(binary.clone(), config.sessions.clone(), event_tx.clone());
+            async move {
+                mirror.run(&mut *command_rx.lock().await).await;
+                Ok(())
+            }
+        });
+        Self {
+            events: Some(event_rx),
+            commands: command_tx,
+        }
+    }
+}
+
+impl SessionBackend for TmuxManager {
+    fn name(&self) -> &'static str {
+        "tmux"
+    }
+
+    fn owns(&self, session_id: &str) -> bool {
+        is_tmux_session(session_id)
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<PtyEvent>> {
+        self.events.take()
+    }
+
+    fn send(&self, cmd: PtyCommand) {
+        let _ = self.commands.send(cmd);
+    }
+}
+
+/// A pane being mirrored.
+struct MirroredPane {
+    pane: Pane,
+    session_id: String,
+    stats: Arc<SessionStats>,
+    transcript: Arc<Transcript>,
+    bell: BellScanner,
+}
+
+/// A control-mode client attached to one tmux session.
+struct ControlClient {
+    stdin: ChildStdin,
+    _child: Child,
+}
+
+/// What the client readers report.
+enum ClientEvent {
+    Line(Notification),
+    Closed(String),
+}
+
+struct Mirror {
+    binary: PathBuf,
+    /// tmux sessions to mirror; empty for all
+    sessions: Vec<String>,
+    event_tx: mpsc::UnboundedSender<PtyEvent>,
+    /// By pane ID
+    panes: HashMap<String, MirroredPane>,
+    /// By tmux session name
+    clients: HashMap<String, ControlClient>,
+    client_tx: mpsc::UnboundedSender<ClientEvent>,
+    client_rx: mpsc::UnboundedReceiver<ClientEvent>,
+}
+
+impl Mirror {
+    fn new(binary: PathBuf, sessions: Vec<String>, event_tx: mpsc::UnboundedSender<PtyEvent>) -> Self {
+        let (client_tx, client_rx) = mpsc::unbounded_channel();
+        Self {
+            binary,
+            sessions,
+            event_tx,
+            panes: HashMap::new(),
+            clients: HashMap::new(),
+            client_tx,
+            client_rx,
+        }
+    }
+
+    async fn run(&mut self, command_rx: &mut mpsc::UnboundedReceiver<PtyCommand>) {
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = poll.tick() => self.refresh().await,
+                Some(event) = self.client_rx.recv() => match event {
+                    ClientEvent::Line(Notification::Output { pane_id, data }) => {
+                        self.output(&pane_id, data);
+                    }
+                    ClientEvent::Line(Notification::LayoutChanged) => self.refresh().await,
+                    ClientEvent::Line(_) => {}
+                    ClientEvent::Closed(session) => {
+                        debug!("tmux control client for {} exited", session);
+                        self.clients.remove(&session);
+                        self.refresh().await;
+                    }
+                },
+                cmd = command_rx.recv() => match cmd {
+                    Some(PtyCommand::Shutdown) | None => break,
+                    Some(cmd) => self.command(cmd).await,
+                },
+            }
+        }
+        info!("tmux mirror shutting down");
+        self.clients.clear();
+    }
+
+    fn wanted(&self, session: &str) -> bool {
+        self.sessions.is_empty() || self.sessions.iter().any(|s| s == session)
+    }
+
+    /// Run a tmux command and return its output, None if it failed (for
+    /// list commands: no server running).
+    async fn tmux(&self, args: &[&str]) -> Option<Vec<u8>> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await;
+        match output {
+            Ok(output) if output.status.success() => Some(output.stdout),
+            Ok(_) => None,
+            Err(e) => {
+                debug!("Cannot run {}: {}", self.binary.display(), e);
+                None
+            }
+        }
+    }
+
+    /// List the panes again: attach clients to new tmux sessions and report
+    /// panes that appeared, went away or changed.
+    async fn refresh(&mut self) {
+        let listed = self
+            .tmux(&["list-panes", "-a", "-F", PANE_FORMAT])
+            .await
+            .map(|out| parse_panes(&String::from_utf8_lossy(&out)))
+            .unwrap_or_default();
+        let mut panes: Vec<Pane> = Vec::new();
+        for pane in listed {
+            // A window linked into two sessions lists its panes twice
+            if self.wanted(&pane.session) && !panes.iter().any(|p| p.pane_id == pane.pane_id) {
+                panes.push(pane);
+            }
+        }
+
+        let sessions: Vec<String> = panes.iter().map(|p| p.session.clone()).collect();
+        self.clients.retain(|name, _| sessions.contains(name));
+        for session in sessions {
+            if !self.clients.contains_key(&session) {
+                self.attach_client(&session);
+            }
+        }
+
+        let gone: Vec<String> = self
+            .panes
+            .keys()
+            .filter(|id| !panes.iter().any(|p| &p.pane_id == *id))
+            .cloned()
+            .collect();
+        for pane_id in gone {
+            if let Some(mirrored) = self.panes.remove(&pane_id) {
+                info!("tmux pane {} closed", mirrored.pane.target);
+                let _ = self.event_tx.send(PtyEvent::Detached {
+                    session_id: mirrored.session_id,
+                });
+            }
+        }
+
+        for pane in panes {
+            match self.panes.get_mut(&pane.pane_id) {
+                Some(mirrored) => {
+                    let session_id = mirrored.session_id.clone();
+                    if mirrored.pane.path != pane.path {
+                        let _ = self.event_tx.send(PtyEvent::CwdChanged {
+                            session_id: session_id.clone(),
+                            cwd: pane.path.clone(),
+                        });
+                    }
+                    if mirrored.pane.name() != pane.name() {
+                        let _ = self.event_tx.send(PtyEvent::Renamed {
+                            session_id: session_id.clone(),
+                            name: pane.name(),
+                        });
+                    }
+                    if (mirrored.pane.cols, mirrored.pane.rows) != (pane.cols, pane.rows) {
+                        let _ = self.event_tx.send(PtyEvent::SessionResize {
+                            session_id,
+                            cols: pane.cols,
+                            rows: pane.rows,
+                        });
+                    }
+                    mirrored.pane = pane;
+                }
+                None => self.attach_pane(pane).await,
+            }
+        }
+    }
+
+    /// Start a control client for a tmux session, reading its lines into
+    /// `client_rx`.
+    fn attach_client(&mut self, session: &str) {
+        let target = format!("={}", session);
+        let spawned = Command::new(&self.binary)
+            .args(["-C", "attach-session", "-f", "ignore-size", "-t", &target])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Cannot attach to tmux session {}: {}", session, e);
+                return;
+            }
+        };
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return;
+        };
+        info!("Attached to tmux session {}", session);
+        let client_tx = self.client_tx.clone();
+        let session_name = session.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout);
+            let mut line = Vec::new();
+            // Command replies come between %begin and %end (or %error)
+            let mut in_reply = false;
+            loop {
+                line.clear();
+                match lines.read_until(b'\n', &mut line).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let text = line.strip_suffix(b"\n").unwrap_or(&line);
+                if text.starts_with(b"%begin ") {
+                    in_reply = true;
+                } else if text.starts_with(b"%end ") || text.starts_with(b"%error ") {
+                    in_reply = false;
+                } else if !in_reply && client_tx.send(ClientEvent::Line(parse_line(text))).is_err() {
+                    return;
+                }
+            }
+            let _ = client_tx.send(ClientEvent::Closed(session_name));
+        });
+        self.clients.insert(
+            session.to_string(),
+            ControlClient {
+                stdin,
+                _child: child,
+            },
+        );
+    }
+
+    /// Report a new pane and send its current screen.
+    async fn attach_pane(&mut self, pane: Pane) {
+        let session_id = pane.session_id();
+        info!(session_id = %session_id, "tmux pane {} attached", pane.target);
+        let stats = Arc::new(SessionStats::default());
+        let transcript = Arc::new(Transcript::default());
+        let _ = self.event_tx.send(PtyEvent::Attached {
+            session_id: session_id.clone(),
+            session_name: pane.name(),
+            shell: pane.command.clone(),
+            pid: pane.pid,
+            tty: pane.tty.clone(),
+            cwd: Some(pane.path.clone()),
+            stats: stats.clone(),
+            transcript: transcript.clone(),
+            read_only: false,
+        });
+        let _ = self.event_tx.send(PtyEvent::SessionResize {
+            session_id: session_id.clone(),
+            cols: pane.cols,
+            rows: pane.rows,
+        });
+        let screen = self
+            .tmux(&["capture-pane", "-p", "-e", "-t", &pane.pane_id])
+            .await;
+        let pane_id = pane.pane_id.clone();
+        self.panes.insert(
+            pane_id.clone(),
+            MirroredPane {
+                pane,
+                session_id,
+                stats,
+                transcript,
+                bell: BellScanner::new(),
+            },
+        );
+        if let Some(screen) = screen {
+            self.output(&pane_id, screen_output(&screen));
+        }
+    }
+
+    fn output(&mut self, pane_id: &str, data: Vec<u8>) {
+        // Output of a pane not listed yet; its screen is captured when it is
+        let Some(mirrored) = self.panes.get_mut(pane_id) else {
+            return;
+        };
+        mirrored.stats.bytes_out.fetch_add(data.len() as u64, Ordering::Relaxed);
+        mirrored.transcript.push(&data);
+        if mirrored.bell.feed(&data, Instant::now()) {
+            let _ = self.event_tx.send(PtyEvent::Bell {
+                session_id: mirrored.session_id.clone(),
+            });
+        }
+        let _ = self.event_tx.send(PtyEvent::Output {
+            session_id: mirrored.session_id.clone(),
+            data,
+        });
+    }
+
+    fn pane_for(&mut self, session_id: &str) -> Option<&mut MirroredPane> {
+        self.panes.values_mut().find(|p| p.session_id == session_id)
+    }
+
+    /// Send control-mode commands through the client of a pane's session.
+    async fn send(&mut self, session: &str, commands: &str) -> bool {
+        let Some(client) = self.clients.get_mut(session) else {
+            return false;
+        };
+        match client.stdin.write_all(commands.as_bytes()).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Cannot send to tmux session {}: {}", session, e);
+                false
+            }
+        }
+    }
+
+    async fn command(&mut self, cmd: PtyCommand) {
+        match cmd {
+            PtyCommand::Write { session_id, data } => {
+                let Some(mirrored) = self.pane_for(&session_id) else {
+                    return;
+                };
+                let (session, commands) = (
+                    mirrored.pane.session.clone(),
+                    send_keys_commands(&mirrored.pane.pane_id, &data),
+                );
+                let stats = mirrored.stats.clone();
+                if self.send(&session, &commands).await {
+                    stats.bytes_in.fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+            }
+            PtyCommand::KillSession { session_id } => {
+                let Some(mirrored) = self.pane_for(&session_id) else {
+                    return;
+                };
+                info!(session_id = %session_id, "Killing tmux pane {}", mirrored.pane.target);
+                let (session, command) = (
+                    mirrored.pane.session.clone(),
+                    format!("kill-pane -t {}\n", mirrored.pane.pane_id),
+                );
+                self.send(&session, &command).await;
+            }
+            PtyCommand::Rename { session_id, name } => {
+                if self.pane_for(&session_id).is_some() {
+                    let _ = self.event_tx.send(PtyEvent::Renamed { session_id, name });
+                }
+            }
+            PtyCommand::Resize { session_id, .. } => {
+                // The pane keeps the size the user gave it; tell the browser
+                let Some(mirrored) = self.pane_for(&session_id) else {
+                    return;
+                };
+                let (cols, rows) = (mirrored.pane.cols, mirrored.pane.rows);
+                let _ = self.event_tx.send(PtyEvent::SessionResize { session_id, cols, rows });
+            }
+            PtyCommand::ListSessions { reply } => {
+                let mut list: Vec<(String, PtySessionInfo)> = self
+                    .panes
+                    .values()
+                    .map(|p| {
+                        let info = PtySessionInfo {
+                            name: p.pane.name(),
+                            shell: p.pane.command.clone(),
+                            pid: p.pane.pid,
+                            tty: p.pane.tty.clone(),
+                            cwd: Some(p.pane.path.clone()),
+                            stats: p.stats.clone(),
+                        };
+                        (p.session_id.clone(), info)
+                    })
+                    .collect();
+                list.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+                let _ = reply.send(list);
+            }
+            PtyCommand::Shutdown => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_panes() {
+        let output = "4711\twork\t%3\t812\t/dev/ttys004\tvim\t/Users/me/src\twork:1.0\t120\t40\n\
+                      4711\twork\t%4\tbad\t/dev/ttys005\tzsh\t/tmp\twork:1.1\t80\t24\n";
+        let panes = parse_panes(output);
+        assert_eq!(panes.len(), 1);
+        assert_eq!(panes[0].session_id(), "tmux-4711-3");
+        assert_eq!(panes[0].name(), "tmux work:1.0 - /Users/me/src");
+        assert_eq!((panes[0].cols, panes[0].rows), (120, 40));
+        assert!(is_tmux_session(&panes[0].session_id()));
+    }
+
+    #[test]
+    fn test_parse_control_lines() {
+        assert_eq!(
+            parse_line(b"%output %3 ls\\015\\012file \\134 \\033[0m"),
+            Notification::Output {
+                pane_id: "%3".into(),
+                data: b"ls\r\nfile \\ \x1b[0m".to_vec(),
+            }
+        );
+        assert_eq!(parse_line(b"%window-add @2"), Notification::LayoutChanged);
+        assert_eq!(parse_line(b"%exit"), Notification::Exit);
+        assert_eq!(parse_line(b"%client-session-changed /dev/ttys001 $1 work"), Notification::Other);
+    }
+
+    #[test]
+    fn test_send_keys_and_screen() {
+        assert_eq!(send_keys_commands("%3", b"ls\r"), "send-keys -H -t %3 6c 73 0d\n");
+        let long = vec![b'a'; SEND_KEYS_CHUNK + 1];
+        assert_eq!(send_keys_commands("%3", &long).lines().count(), 2);
+        assert_eq!(screen_output(b"$ ls\nfile\n"), b"\x1b[H\x1b[2J$ ls\r\nfile".to_vec());
+    }
+}
@@ -0,0 +1,287 @@
+//! Session transcripts for "Export Transcript…" in the Sessions submenu
+//! and for browser scrollback searches.
+//!
+//! Each session keeps its latest [`TRANSCRIPT_BYTES`] of shell output. An
+//! export writes them either raw, escape sequences included (replays with
+//! `cat` in a terminal), or as plain text with the escape sequences and
+//! control characters stripped, for attaching to bug reports. Searches run
+//! over the same plain text, so browsers get matching lines without
+//! downloading the whole history. Sessions over their bandwidth budget
+//! send a plain-text [`screen`] built from it instead of their output.
+
+use crate::notify::applescript_quote;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Output kept per session (the relay's default scrollback, 1 MB).
+pub const TRANSCRIPT_BYTES: usize = 1024 * 1024;
+
+/// Characters of a search match's line sent back, and how many of them
+/// come before the match.
+const SNIPPET_CHARS: usize = 160;
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Output looked at for a screen snapshot, enough for a few full screens.
+const SCREEN_TAIL_BYTES: usize = 64 * 1024;
+
+/// The latest output of one session.
+#[derive(Debug, Default)]
+pub struct Transcript {
+    output: Mutex<VecDeque<u8>>,
+}
+
+impl Transcript {
+    /// Append output, dropping the oldest bytes beyond [`TRANSCRIPT_BYTES`].
+    pub fn push(&self, data: &[u8]) {
+        let mut output = self.output.lock().unwrap();
+        output.extend(data);
+        let excess = output.len().saturating_sub(TRANSCRIPT_BYTES);
+        output.drain(..excess);
+    }
+
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.output.lock().unwrap().iter().copied().collect()
+    }
+}
+
+/// Write a transcript to `path`, raw or as plain text.
+pub fn export(output: &[u8], raw: bool, path: &Path) -> std::io::Result<()> {
+    if raw {
+        std::fs::write(path, output)
+    } else {
+        std::fs::write(path, strip_ansi(output))
+    }
+}
+
+/// Ask where to save a transcript (blocking). `dir` is the folder the save
+/// dialog opens in. None if the user cancelled.
+pub fn choose_path(session_name: &str, default_name: &str, dir: Option<&Path>) -> Option<PathBuf> {
+    choose_save_path(
+        &format!("Export the transcript of \"{}\" to:", session_name),
+        default_name,
+        dir,
+    )
+}
+
+/// Ask where to save a file (blocking), with `prompt` in the save dialog.
+/// None if the user cancelled.
+pub fn choose_save_path(prompt: &str, default_name: &str, dir: Option<&Path>) -> Option<PathBuf> {
+    let mut script = format!(
+        "POSIX path of (choose file name with prompt {} default name {}",
+        applescript_quote(prompt),
+        applescript_quote(default_name)
+    );
+    if let Some(dir) = dir {
+        script.push_str(&format!(
+            " default location (POSIX file {})",
+            applescript_quote(&dir.display().to_string())
+        ));
+    }
+    script.push(')');
+    let output = std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(&script)
+        .output()
+        .ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Suggested file name, e.g. "zsh 2026-10-16 14.05.09.txt" (".log" when raw).
+pub fn file_name(session_name: &str, raw: bool, at: SystemTime) -> String {
+    let name: String = session_name
+        .chars()
+        .map(|c| if matches!(c, '/' | ':') { '-' } else { c })
+        .collect();
+    format!("{} {}.{}", name.trim(), timestamp(at), if raw { "log" } else { "txt" })
+}
+
+/// Local time for file names, e.g. "2026-10-16 14.05.09".
+pub fn timestamp(at: SystemTime) -> String {
+    let secs = at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as libc::time_t)
+        .unwrap_or(0);
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        secs.to_string()
+    } else {
+        format!(
+            "{}-{:02}-{:02} {:02}.{:02}.{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+}
+
+/// A line of a transcript containing a search term.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    /// Lines between this one and the last line (0: the last line)
+    pub line: usize,
+    /// Character offset of the match in the line
+    pub column: usize,
+    /// The line, shortened around the match
+    pub snippet: String,
+}
+
+/// Lines of the plain text of `output` containing `q`, ignoring ASCII
+/// case, newest first and at most `limit` of them.
+pub fn search(output: &[u8], q: &str, limit: usize) -> Vec<Found> {
+    let q = q.to_ascii_lowercase();
+    if q.is_empty() {
+        return Vec::new();
+    }
+    let text = strip_ansi(output);
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    let mut found = Vec::new();
+    for (line, content) in text.rsplit('\n').enumerate() {
+        if found.len() == limit {
+            break;
+        }
+        // Lowercasing ASCII keeps byte offsets, so `at` is one in `content`
+        let Some(at) = content.to_ascii_lowercase().find(&q) else {
+            continue;
+        };
+        let column = content[..at].chars().count();
+        let snippet = content
+            .chars()
+            .skip(column.saturating_sub(SNIPPET_CONTEXT))
+            .take(SNIPPET_CHARS)
+            .collect();
+        found.push(Found { line, column, snippet });
+    }
+    found
+}
+
+/// A snapshot of the screen for a session over its bandwidth budget: clear
+/// the screen, then the last `rows` lines of the plain text of `output`.
+/// Colors and full-screen programs are lost, but the latest lines show.
+pub fn screen(output: &[u8], rows: u16) -> Vec<u8> {
+    let tail = &output[output.len().saturating_sub(SCREEN_TAIL_BYTES)..];
+    let text = strip_ansi(tail);
+    let mut lines: Vec<&str> = text.rsplit('\n').take(usize::from(rows.max(1))).collect();
+    lines.reverse();
+    let mut screen = b"\x1b[H\x1b[2J".to_vec();
+    screen.extend_from_slice(lines.join("\r\n").as_bytes());
+    screen
+}
+
+/// Plain text of terminal output: escape sequences (CSI, OSC and two-byte
+/// ones) and control characters are removed, CRLF becomes LF, backspace
+/// erases, and a lone CR starts its line over (progress bars keep their
+/// final state).
+pub fn strip_ansi(data: &[u8]) -> String {
+    let mut text: Vec<u8> = Vec::with_capacity(data.len());
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            0x1b => {
+                i += 1;
+                match data.get(i) {
+                    // CSI: parameters up to a final byte in 0x40..=0x7e
+                    Some(b'[') => {
+                        i += 1;
+                        while i < data.len() && !(0x40..=0x7e).contains(&data[i]) {
+                            i += 1;
+                        }
+                    }
+                    // OSC: up to BEL or ESC \
+                    Some(b']') => {
+                        i += 1;
+                        while i < data.len() && data[i] != 0x07 {
+                            if data[i] == 0x1b && data.get(i + 1) == Some(&b'\\') {
+                                i += 1;
+                                break;
+                            }
+                            i += 1;
+                        }
+                    }
+                    // Character set selection takes one more byte
+                    Some(b'(' | b')') => i += 1,
+                    _ => {}
+                }
+            }
+            b'\r' if data.get(i + 1) == Some(&b'\n') => {}
+            b'\r' => text.truncate(line_start),
+            b'\n' => {
+                text.push(b'\n');
+                line_start = text.len();
+            }
+            0x08 => {
+                if text.len() > line_start {
+                    text.pop();
+                }
+            }
+            b'\t' => text.push(b'\t'),
+            b if b < 0x20 || b == 0x7f => {}
+            b => text.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        let output = b"\x1b]0;zsh\x07\x1b[1;32m~\x1b[0m $ ls\r\nfile.txt\x1b(B\r\n\
+            10%\r50%\r100%\r\ntypo\x08\x08 \r\n";
+        assert_eq!(strip_ansi(output), "~ $ ls\nfile.txt\n100%\nty \n");
+    }
+
+    #[test]
+    fn test_transcript_keeps_latest_output() {
+        let transcript = Transcript::default();
+        transcript.push(&vec![b'a'; TRANSCRIPT_BYTES]);
+        transcript.push(b"tail");
+        let snapshot = transcript.snapshot();
+        assert_eq!(snapshot.len(), TRANSCRIPT_BYTES);
+        assert!(snapshot.ends_with(b"aatail"));
+    }
+
+    #[test]
+    fn test_search_newest_first() {
+        let output = b"$ make\r\n\x1b[31mError\x1b[0m: missing file\r\nok\r\nno error here\r\n$ ";
+        let found = search(output, "ERROR", 10);
+        assert_eq!(
+            found,
+            vec![
+                Found { line: 1, column: 3, snippet: "no error here".into() },
+                Found { line: 3, column: 0, snippet: "Error: missing file".into() },
+            ]
+        );
+        assert_eq!(search(output, "error", 1).len(), 1);
+        assert!(search(output, "", 10).is_empty());
+
+        let long = format!("{}needle", "x".repeat(100));
+        let found = search(long.as_bytes(), "needle", 10);
+        assert_eq!(found[0].column, 100);
+        assert!(found[0].snippet.starts_with(&"x".repeat(SNIPPET_CONTEXT)));
+        assert!(found[0].snippet.ends_with("needle"));
+    }
+
+    #[test]
+    fn test_screen_shows_the_last_rows() {
+        let output = b"one\r\n\x1b[1mtwo\x1b[0m\r\nthree\r\n$ ";
+        assert_eq!(screen(output, 3), b"\x1b[H\x1b[2Jtwo\r\nthree\r\n$ ");
+        assert_eq!(screen(output, 24), b"\x1b[H\x1b[2Jone\r\ntwo\r\nthree\r\n$ ");
+    }
+
+    #[test]
+    fn test_file_name_is_safe() {
+        let name = file_name("~/src/app: build", true, UNIX_EPOCH);
+        assert!(name.starts_with("~-src-app- build "), "{}", name);
+        assert!(name.ends_with(".log"));
+    }
+}
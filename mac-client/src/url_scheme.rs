@@ -0,0 +1,333 @@
+//! `ignis://` links.
+//!
+//! The app bundle registers the `ignis` URL scheme (`CFBundleURLTypes` in
+//! `Info.plist`), so links opened from a browser, a launcher such as Raycast
+//! or `open` in a script reach the running mac-client:
+//!
+//! | Link | Action |
+//! |------|--------|
+//! | `ignis://session/<id>/focus` | Bring the session's Terminal tab to the front |
+//! | `ignis://pause` | Turn Do Not Disturb on |
+//! | `ignis://resume` | Turn Do Not Disturb off |
+//! | `ignis://toggle-sharing` | Toggle Do Not Disturb |
+//! | `ignis://copy-code` | Copy the session code |
+//! | `ignis://list-sessions` | List the connected sessions |
+//! | `ignis://session/<id>/close` | Close the session's Terminal window |
+//!
+//! Every action also answers as an [x-callback-url] at
+//! `ignis://x-callback-url/<action>`, which is how Shortcuts ("Open
+//! X-Callback URL") and scripts get results back: `x-success` is opened
+//! with a `result` parameter (the session list as JSON, the code, or the
+//! sharing state), `x-error` with `errorCode` and `errorMessage`. Session
+//! actions take the ID as a parameter there (`focus-session?id=<id>`,
+//! `close-session?id=<id>`).
+//!
+//! macOS delivers the link as a `GetURL` Apple Event; [`register`] installs
+//! the handler that forwards it to the UI thread as [`UiEvent::OpenUrl`].
+//!
+//! [x-callback-url]: https://x-callback-url.com/specification/
+
+use crate::applescript;
+use crate::notify::applescript_quote;
+#[cfg(target_os = "macos")]
+use crate::app::UiEvent;
+use tracing::{error, info};
+
+pub const SCHEME: &str = "ignis";
+
+/// Host of x-callback-url links (`ignis://x-callback-url/<action>`).
+const X_CALLBACK_HOST: &str = "x-callback-url";
+
+/// What an `ignis://` link asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlAction {
+    FocusSession(String),
+    CloseSession(String),
+    ListSessions,
+    Pause,
+    Resume,
+    ToggleSharing,
+    CopyCode,
+}
+
+/// Parse an `ignis://` link. Fragments and a trailing slash are ignored,
+/// and so are query parameters other than a session `id`.
+pub fn parse(url: &str) -> Result<UrlAction, String> {
+    let rest = url
+        .split_once("://")
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(SCHEME))
+        .map(|(_, rest)| rest)
+        .ok_or_else(|| format!("Not an {}:// link: {}", SCHEME, url))?;
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let path = path.strip_prefix(X_CALLBACK_HOST).unwrap_or(path);
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let id = query_param(url, "id").filter(|id| !id.is_empty());
+    match (parts.as_slice(), id) {
+        (["session", id, "focus"], _) if !id.is_empty() => {
+            Ok(UrlAction::FocusSession(id.to_string()))
+        }
+        (["session", id, "close"], _) if !id.is_empty() => {
+            Ok(UrlAction::CloseSession(id.to_string()))
+        }
+        (["focus-session"], Some(id)) => Ok(UrlAction::FocusSession(id)),
+        (["close-session"], Some(id)) => Ok(UrlAction::CloseSession(id)),
+        (["list-sessions"], _) => Ok(UrlAction::ListSessions),
+        (["pause"], _) => Ok(UrlAction::Pause),
+        (["resume"], _) => Ok(UrlAction::Resume),
+        (["toggle-sharing"], _) => Ok(UrlAction::ToggleSharing),
+        (["copy-code"], _) => Ok(UrlAction::CopyCode),
+        _ => Err(format!("Unknown {}:// link: {}", SCHEME, url)),
+    }
+}
+
+/// Percent-decoded value of the first query parameter called `name`.
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let query = url.split('#').next()?.split_once('?')?.1;
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (percent_decode(key) == name).then(|| percent_decode(value))
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                out.push(byte);
+                i += 3;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `base` with `params` added to its query string.
+fn with_params(base: &str, params: &[(&str, &str)]) -> String {
+    let mut url = base.to_string();
+    for (i, (key, value)) in params.iter().enumerate() {
+        let separator = if i == 0 && !base.contains('?') { '?' } else { '&' };
+        url.push(separator);
+        url.push_str(&format!("{}={}", key, percent_encode(value)));
+    }
+    url
+}
+
+/// x-callback-url return addresses of a link; empty for plain links.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Callbacks {
+    success: Option<String>,
+    error: Option<String>,
+}
+
+impl Callbacks {
+    pub fn of(url: &str) -> Self {
+        Self {
+            success: query_param(url, "x-success").filter(|u| !u.is_empty()),
+            error: query_param(url, "x-error").filter(|u| !u.is_empty()),
+        }
+    }
+
+    /// `x-success` with the action's result, if any.
+    fn success_url(&self, result: Option<&str>) -> Option<String> {
+        let base = self.success.as_deref()?;
+        Some(match result {
+            Some(result) => with_params(base, &[("result", result)]),
+            None => base.to_string(),
+        })
+    }
+
+    fn error_url(&self, message: &str) -> Option<String> {
+        let base = self.error.as_deref()?;
+        Some(with_params(base, &[("errorCode", "1"), ("errorMessage", message)]))
+    }
+
+    /// Report success to the caller by opening `x-success`.
+    pub fn succeed(&self, result: Option<&str>) {
+        if let Some(url) = self.success_url(result) {
+            open_callback(&url);
+        }
+    }
+
+    /// Report failure to the caller by opening `x-error`.
+    pub fn fail(&self, message: &str) {
+        if let Some(url) = self.error_url(message) {
+            open_callback(&url);
+        }
+    }
+}
+
+fn open_callback(url: &str) {
+    if let Err(e) = std::process::Command::new("open").arg(url).spawn() {
+        error!("Failed to open x-callback URL: {}", e);
+    }
+}
+
+/// Select the Terminal tab running on `tty` and bring its window to the
+/// front. Blocks until the AppleScript worker has run the script.
+pub fn focus_terminal_tab(tty: &str) {
+    let script = format!(
+        "tell application \"Terminal\"
+repeat with w in windows
+repeat with t in tabs of w
+if tty of t is {} then
+set selected of t to true
+set index of w to 1
+activate
+return true
+end if
+end repeat
+end repeat
+return false
+end tell",
+        applescript_quote(tty)
+    );
+    match applescript::run("focus", script) {
+        Ok(found) if found != "true" => info!("No Terminal tab on {} to focus", tty),
+        Ok(_) => {}
+        Err(e) => error!("Focusing Terminal tab on {} failed: {}", tty, e),
+    }
+}
+
+/// Forward `ignis://` links opened while the app runs (or that launched
+/// it) to the UI thread. Call before the event loop starts.
+#[cfg(target_os = "macos")]
+pub fn register(ui_tx: std::sync::mpsc::Sender<UiEvent>) {
+    apple_event::register(ui_tx);
+}
+
+#[cfg(target_os = "macos")]
+mod apple_event {
+    use crate::app::UiEvent;
+    use objc2::rc::Retained;
+    use objc2::runtime::{AnyObject, NSObject};
+    use objc2::{class, define_class, msg_send, sel, AnyThread, DefinedClass};
+    use std::ffi::{c_char, CStr};
+    use std::sync::mpsc;
+
+    /// `kInternetEventClass` and `kAEGetURL`
+    const GET_URL: u32 = u32::from_be_bytes(*b"GURL");
+    /// `keyDirectObject`, the parameter holding the URL
+    const KEY_DIRECT_OBJECT: u32 = u32::from_be_bytes(*b"----");
+
+    define_class!(
+        // SAFETY: NSObject has no subclassing requirements and UrlHandler
+        // does not implement Drop.
+        #[unsafe(super(NSObject))]
+        #[name = "TerminalRemoteUrlHandler"]
+        #[ivars = mpsc::Sender<UiEvent>]
+        struct UrlHandler;
+
+        impl UrlHandler {
+            #[unsafe(method(handleGetURLEvent:withReplyEvent:))]
+            fn handle_get_url_event(&self, event: &AnyObject, _reply: &AnyObject) {
+                match unsafe { url_of(event) } {
+                    Some(url) => {
+                        let _ = self.ivars().send(UiEvent::OpenUrl(url));
+                    }
+                    None => tracing::warn!("GetURL event without a URL"),
+                }
+            }
+        }
+    );
+
+    /// The direct object of a `GetURL` event as a string.
+    unsafe fn url_of(event: &AnyObject) -> Option<String> {
+        let param: Option<Retained<AnyObject>> =
+            msg_send![event, paramDescriptorForKeyword: KEY_DIRECT_OBJECT];
+        let param = param?;
+        let string: Option<Retained<AnyObject>> = msg_send![&*param, stringValue];
+        let string = string?;
+        let utf8: *const c_char = msg_send![&*string, UTF8String];
+        if utf8.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+    }
+
+    pub fn register(ui_tx: mpsc::Sender<UiEvent>) {
+        let handler = UrlHandler::alloc().set_ivars(ui_tx);
+        let handler: Retained<UrlHandler> = unsafe { msg_send![super(handler), init] };
+        unsafe {
+            let manager: Retained<AnyObject> =
+                msg_send![class!(NSAppleEventManager), sharedAppleEventManager];
+            let _: () = msg_send![
+                &*manager,
+                setEventHandler: &*handler,
+                andSelector: sel!(handleGetURLEvent:withReplyEvent:),
+                forEventClass: GET_URL,
+                andEventID: GET_URL
+            ];
+        }
+        // The event manager does not retain its handler
+        std::mem::forget(handler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_links() {
+        assert_eq!(
+            parse("ignis://session/3f2a-9c/focus"),
+            Ok(UrlAction::FocusSession("3f2a-9c".into()))
+        );
+        assert_eq!(parse("ignis://pause"), Ok(UrlAction::Pause));
+        assert_eq!(parse("IGNIS://resume/"), Ok(UrlAction::Resume));
+        assert_eq!(parse("ignis://toggle-sharing?from=raycast"), Ok(UrlAction::ToggleSharing));
+        assert_eq!(parse("ignis://copy-code"), Ok(UrlAction::CopyCode));
+        assert_eq!(
+            parse("ignis://session/3f2a-9c/close"),
+            Ok(UrlAction::CloseSession("3f2a-9c".into()))
+        );
+
+        assert!(parse("ignis://session//focus").is_err());
+        assert!(parse("ignis://session/abc").is_err());
+        assert!(parse("https://pause").is_err());
+        assert!(parse("ignis:pause").is_err());
+    }
+
+    #[test]
+    fn test_x_callback_links() {
+        let url = "ignis://x-callback-url/close-session?id=3f2a%2D9c\
+            &x-success=shortcuts%3A%2F%2Fx-callback-url%2Fdone\
+            &x-error=shortcuts%3A%2F%2Fx-callback-url%2Ferror%3Fsource%3Dignis";
+        assert_eq!(parse(url), Ok(UrlAction::CloseSession("3f2a-9c".into())));
+        assert_eq!(
+            parse("ignis://x-callback-url/list-sessions"),
+            Ok(UrlAction::ListSessions)
+        );
+        assert!(parse("ignis://x-callback-url/focus-session").is_err());
+
+        let callbacks = Callbacks::of(url);
+        assert_eq!(
+            callbacks.success_url(Some("[{\"id\":\"a b\"}]")).as_deref(),
+            Some("shortcuts://x-callback-url/done?result=%5B%7B%22id%22%3A%22a%20b%22%7D%5D")
+        );
+        assert_eq!(
+            callbacks.error_url("No session 3f2a-9c").as_deref(),
+            Some("shortcuts://x-callback-url/error?source=ignis&errorCode=1&errorMessage=No%20session%203f2a-9c")
+        );
+        assert_eq!(Callbacks::of("ignis://pause"), Callbacks::default());
+    }
+}
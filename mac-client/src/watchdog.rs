@@ -0,0 +1,140 @@
+//! Self-checks of the listeners and the relay client.
+//!
+//! A task can stay alive while doing nothing useful: the PTY socket file
+//! deleted by a cleanup script leaves the listener accepting on a path no
+//! pty-proxy can reach, and a relay connection stuck on a dead network
+//! never errors out. [`supervise`](crate::supervisor::supervise) only
+//! restarts tasks that stop, so these checks make them stop:
+//!
+//! - Listeners keep a [`BoundSocket`] and every [`CHECK_INTERVAL`] connect
+//!   to themselves through the path. A missing or replaced socket file, or
+//!   a connection that fails, ends the listener with an error, and the
+//!   supervisor binds it again.
+//! - The relay client bumps a [`Heartbeat`] on every connection attempt and
+//!   ping; [`Heartbeat::stalled`] resolves once it has been silent for
+//!   [`RELAY_STALL_AFTER`], and the relay task is restarted.
+//!
+//! Either way the supervisor records the restart under Errors.
+
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UnixStream;
+use tokio::time::Interval;
+
+/// Time between two checks of a listener.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a listener may take to accept its own probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Silence after which the relay client counts as stuck. Longer than the
+/// longest reconnect delay plus a connection attempt.
+pub const RELAY_STALL_AFTER: Duration = Duration::from_secs(120);
+
+/// Ticks every [`CHECK_INTERVAL`], starting one interval from now.
+pub fn checks() -> Interval {
+    let start = tokio::time::Instant::now() + CHECK_INTERVAL;
+    tokio::time::interval_at(start, CHECK_INTERVAL)
+}
+
+/// The socket file a listener bound.
+#[derive(Debug)]
+pub struct BoundSocket {
+    path: PathBuf,
+    /// Device and inode of the socket file
+    id: (u64, u64),
+}
+
+impl BoundSocket {
+    /// Remember the socket just bound at `path`.
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let meta = std::fs::symlink_metadata(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            id: (meta.dev(), meta.ino()),
+        })
+    }
+
+    /// Check that the path still leads to this listener.
+    pub async fn check(&self) -> Result<(), String> {
+        let meta = std::fs::symlink_metadata(&self.path)
+            .map_err(|e| format!("{} is gone: {}", self.path.display(), e))?;
+        if (meta.dev(), meta.ino()) != self.id {
+            return Err(format!("{} was replaced", self.path.display()));
+        }
+        match tokio::time::timeout(PROBE_TIMEOUT, UnixStream::connect(&self.path)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(format!("cannot connect to {}: {}", self.path.display(), e)),
+            Err(_) => Err(format!("{} did not accept a connection", self.path.display())),
+        }
+    }
+}
+
+/// Last sign of life of a task.
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Instant>>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    /// Time since the last beat.
+    pub fn silent_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(*self.0.lock().unwrap())
+    }
+
+    /// Resolve once there has been no beat for `limit`, with the silence.
+    pub async fn stalled(&self, limit: Duration) -> Duration {
+        loop {
+            let silent = self.silent_for(Instant::now());
+            if silent >= limit {
+                return silent;
+            }
+            tokio::time::sleep(limit - silent).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bound_socket_check() {
+        let dir = std::env::temp_dir().join(format!("watchdog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pty.sock");
+        let _ = std::fs::remove_file(&path);
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let bound = BoundSocket::new(&path).unwrap();
+        assert_eq!(bound.check().await, Ok(()));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(bound.check().await.unwrap_err().contains("is gone"));
+
+        // Another listener at the same path
+        let _other = tokio::net::UnixListener::bind(&path).unwrap();
+        assert!(bound.check().await.unwrap_err().contains("was replaced"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_stalls_without_beats() {
+        let heartbeat = Heartbeat::default();
+        let now = Instant::now();
+        assert!(heartbeat.silent_for(now + Duration::from_secs(5)) >= Duration::from_secs(4));
+        heartbeat.beat();
+        let limit = Duration::from_millis(20);
+        assert!(heartbeat.stalled(limit).await >= limit);
+    }
+}
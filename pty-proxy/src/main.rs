@@ -22,11 +22,20 @@ use serde::{Deserialize, Serialize};
 use std::ffi::CString;
 use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
-use std::time::Instant;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-const SOCKET_PATH: &str = "/tmp/terminal-remote.sock";
+mod osc52;
+
+use osc52::{Osc, Osc52Scanner};
+
+/// Overrides the mac-client socket path entirely.
+const SOCKET_ENV: &str = "TERMINAL_REMOTE_SOCKET";
+/// Names the mac-client instance to connect to (must match mac-client's).
+const INSTANCE_ENV: &str = "TERMINAL_REMOTE_INSTANCE";
 const BUF_SIZE: usize = 8192;
 const RECONNECT_INTERVAL_SECS: u64 = 5;
+const CWD_POLL_INTERVAL_MS: u128 = 1000;
 
 /// Registration message sent to mac-client on connect.
 #[derive(Serialize)]
@@ -35,6 +44,11 @@ struct Registration {
     shell: String,
     pid: u32,
     tty: String,
+    /// Working directory of the shell at registration time
+    cwd: Option<String>,
+    /// Same for every connection this proxy makes, so mac-client can give a
+    /// reconnecting proxy its session ID and settings back
+    proxy_id: String,
     proxy_version: u8,
 }
 
@@ -61,7 +75,8 @@ extern "C" fn handle_sigchld(_sig: i32) {
 }
 
 /// SIGWINCH handler — terminal resized.
-/// We need to forward this to the child PTY.
+/// We need to forward this to the child PTY. mac-client also sends it to
+/// disconnected proxies to make them reconnect right away.
 extern "C" fn handle_sigwinch(_sig: i32) {
     SIGWINCH_RECEIVED.store(true, Ordering::Relaxed);
 }
@@ -124,16 +139,15 @@ fn main() {
             // Exec the shell
             let shell_cstr = CString::new(shell.as_str()).unwrap();
             let args = [shell_cstr.clone()];
-            execvp(&shell_cstr, &args).unwrap_or_else(|e| {
-                eprintln!("pty-proxy: exec {} failed: {}", shell, e);
-                std::process::exit(1);
-            });
+            let Err(e) = execvp(&shell_cstr, &args);
+            eprintln!("pty-proxy: exec {} failed: {}", shell, e);
+            std::process::exit(1);
         }
         Ok(ForkResult::Parent { child }) => {
             // === PARENT: proxy I/O ===
             drop(slave); // close slave in parent
 
-            CHILD_PID.store(child.as_raw() as i32, Ordering::Relaxed);
+            CHILD_PID.store(child.as_raw(), Ordering::Relaxed);
 
             // Also set size on master (belt and suspenders — slave already has it)
             if let Some(size) = get_terminal_size(STDIN_FILENO) {
@@ -214,6 +228,13 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
     // Reconnect tracking
     let mut last_reconnect_attempt: Option<Instant> = None;
 
+    // Picks OSC 52 clipboard writes and window titles out of shell output
+    let mut clipboard_scanner = Osc52Scanner::new();
+
+    // Shell working directory last reported to mac-client
+    let mut last_cwd: Option<String> = None;
+    let mut last_cwd_check = Instant::now();
+
     loop {
         // Check if child exited
         if CHILD_EXITED.load(Ordering::Relaxed) {
@@ -233,6 +254,8 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
                     send_frame(sock.as_raw_fd(), resize_msg.as_bytes());
                 }
             }
+            // Reconnect now rather than at the next interval
+            last_reconnect_attempt = None;
         }
 
         // FIX #2: Try to reconnect if socket is gone
@@ -247,6 +270,22 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
                     set_nonblocking(fd.as_raw_fd());
                     socket_fd = Some(fd);
                     frame_buf.clear(); // reset frame buffer for new connection
+                    last_cwd = None; // registration carries the current cwd
+                }
+            }
+        }
+
+        // Report working directory changes (used for session names and history)
+        if last_cwd_check.elapsed().as_millis() >= CWD_POLL_INTERVAL_MS {
+            last_cwd_check = Instant::now();
+            if let Some(ref sock) = socket_fd {
+                let cwd = child_cwd(child);
+                if cwd.is_some() && cwd != last_cwd {
+                    if last_cwd.is_some() {
+                        let msg = serde_json::json!({ "type": "cwd", "path": cwd });
+                        send_frame(sock.as_raw_fd(), msg.to_string().as_bytes());
+                    }
+                    last_cwd = cwd;
                 }
             }
         }
@@ -314,12 +353,7 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
                         // Write to terminal
                         write_all(STDOUT_FILENO, &buf[..n]);
                         // Tee output to mac-client
-                        if let Some(ref sock) = socket_fd {
-                            let mut msg = Vec::with_capacity(1 + n);
-                            msg.push(b'O'); // 'O' = output
-                            msg.extend_from_slice(&buf[..n]);
-                            send_frame(sock.as_raw_fd(), &msg);
-                        }
+                        tee_output(socket_fd.as_ref(), &mut clipboard_scanner, &buf[..n]);
                     }
                     Err(nix::errno::Errno::EAGAIN | nix::errno::Errno::EINTR) => {}
                     Err(_) => break,
@@ -333,12 +367,7 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
                         Ok(0) | Err(_) => break,
                         Ok(n) => {
                             write_all(STDOUT_FILENO, &buf[..n]);
-                            if let Some(ref sock) = socket_fd {
-                                let mut msg = Vec::with_capacity(1 + n);
-                                msg.push(b'O');
-                                msg.extend_from_slice(&buf[..n]);
-                                send_frame(sock.as_raw_fd(), &msg);
-                            }
+                            tee_output(socket_fd.as_ref(), &mut clipboard_scanner, &buf[..n]);
                         }
                     }
                 }
@@ -404,6 +433,30 @@ fn proxy_loop(master_fd: RawFd, mut socket_fd: Option<OwnedFd>, child: Pid, shel
     reap_child(child)
 }
 
+/// Send shell output to mac-client, followed by a clipboard message for
+/// every OSC 52 copy it completes and a title message for every window
+/// title it sets.
+///
+/// Output is scanned even while disconnected so a sequence split across a
+/// reconnect does not leave the scanner mid-escape.
+fn tee_output(socket_fd: Option<&OwnedFd>, scanner: &mut Osc52Scanner, data: &[u8]) {
+    let found = scanner.feed(data);
+    let Some(sock) = socket_fd else {
+        return;
+    };
+    let mut msg = Vec::with_capacity(1 + data.len());
+    msg.push(b'O'); // 'O' = output
+    msg.extend_from_slice(data);
+    send_frame(sock.as_raw_fd(), &msg);
+    for osc in found {
+        let msg = match osc {
+            Osc::Clipboard(copy) => serde_json::json!({ "type": "clipboard", "data": copy }),
+            Osc::Title(title) => serde_json::json!({ "type": "title", "title": title }),
+        };
+        send_frame(sock.as_raw_fd(), msg.to_string().as_bytes());
+    }
+}
+
 /// Handle a message from mac-client (browser → shell).
 /// Returns true if pty-proxy should exit cleanly (Close message received).
 fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bool {
@@ -425,7 +478,7 @@ fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bo
             ControlMessage::Close => {
                 // Kill child shell — use SIGHUP, not SIGTERM.
                 // zsh ignores SIGTERM in interactive mode, but respects SIGHUP.
-                unsafe { libc::kill(child.as_raw() as i32, libc::SIGHUP); }
+                unsafe { libc::kill(child.as_raw(), libc::SIGHUP); }
                 return true;
             }
         }
@@ -437,14 +490,51 @@ fn handle_mac_client_message(payload: &[u8], master_fd: RawFd, child: Pid) -> bo
     false
 }
 
+/// App group container holding mac-client's sockets (under `$HOME`).
+const GROUP_CONTAINER: &str = "Library/Group Containers/group.com.terminal-remote";
+
+/// Sockets of this user's mac-client to try in order, mirroring
+/// mac-client's `paths` module: `pty.sock` (or `pty-<instance>.sock` for a
+/// named instance) in the app group container, then in the
+/// `/tmp/terminal-remote-<uid>/` directory older releases used.
+fn socket_paths() -> Vec<String> {
+    if let Ok(path) = std::env::var(SOCKET_ENV) {
+        return vec![path];
+    }
+    let name = match std::env::var(INSTANCE_ENV) {
+        Ok(instance) if !instance.is_empty() => format!("pty-{}.sock", instance),
+        _ => "pty.sock".to_string(),
+    };
+    let uid = unsafe { nix::libc::getuid() };
+    let mut paths = Vec::new();
+    if let Ok(home) = std::env::var("HOME") {
+        paths.push(format!("{}/{}/{}", home, GROUP_CONTAINER, name));
+    }
+    paths.push(format!("/tmp/terminal-remote-{}/{}", uid, name));
+    paths
+}
+
+/// Identity of this proxy process: its pid and start time, which together
+/// never repeat on one machine.
+fn proxy_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        format!("{}-{:x}", std::process::id(), started)
+    })
+}
+
 /// Connect to mac-client via Unix socket. Returns None on failure (non-fatal).
 fn connect_to_mac_client(shell: &str, child_pid: Pid) -> Option<OwnedFd> {
     use std::os::unix::net::UnixStream;
 
-    let stream = match UnixStream::connect(SOCKET_PATH) {
-        Ok(s) => s,
-        Err(_) => return None, // mac-client not running, that's OK
-    };
+    // mac-client not running is OK
+    let stream = socket_paths()
+        .iter()
+        .find_map(|path| UnixStream::connect(path).ok())?;
 
     // FIX #5: Use into_raw_fd() instead of mem::forget to properly transfer ownership.
     let fd = stream.into_raw_fd();
@@ -460,17 +550,18 @@ fn connect_to_mac_client(shell: &str, child_pid: Pid) -> Option<OwnedFd> {
         })
         .unwrap_or_else(|_| "unknown".to_string());
 
+    let cwd = child_cwd(child_pid).or_else(|| {
+        std::env::current_dir()
+            .ok()
+            .map(|p| p.display().to_string())
+    });
     let reg = Registration {
-        name: format!(
-            "{} - {}",
-            shell,
-            std::env::current_dir()
-                .map(|p| p.display().to_string())
-                .unwrap_or_else(|_| "~".to_string())
-        ),
+        name: format!("{} - {}", shell, cwd.as_deref().unwrap_or("~")),
         shell: shell.to_string(),
         pid: child_pid.as_raw() as u32,
         tty: tty_name,
+        cwd,
+        proxy_id: proxy_id().to_string(),
         proxy_version: 1,
     };
 
@@ -497,6 +588,35 @@ fn connect_to_mac_client(shell: &str, child_pid: Pid) -> Option<OwnedFd> {
     Some(unsafe { OwnedFd::from_raw_fd(fd) })
 }
 
+/// Current working directory of the shell process.
+#[cfg(target_os = "macos")]
+fn child_cwd(child: Pid) -> Option<String> {
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    let ret = unsafe {
+        libc::proc_pidinfo(
+            child.as_raw(),
+            libc::PROC_PIDVNODEPATHINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if ret != size {
+        return None;
+    }
+    let path = unsafe { std::ffi::CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr() as *const libc::c_char) };
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Current working directory of the shell process.
+#[cfg(not(target_os = "macos"))]
+fn child_cwd(child: Pid) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/cwd", child.as_raw()))
+        .ok()
+        .map(|p| p.display().to_string())
+}
+
 /// Send a length-prefixed frame atomically: 4 bytes big-endian length + payload.
 /// FIX #1: Use writev() for atomic writes — length prefix and payload in a single syscall.
 fn send_frame(fd: RawFd, data: &[u8]) {
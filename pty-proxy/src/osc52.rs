@@ -0,0 +1,190 @@
+//! OSC 52 clipboard sequence detection in shell output.
+//!
+//! Programs like tmux and neovim copy to the terminal's clipboard with
+//! `ESC ] 52 ; <selection> ; <base64 data> BEL` (or `ESC \` as terminator).
+//! The sequence still reaches the local terminal untouched; this scanner only
+//! picks out the payload so it can be reported to mac-client as well.
+//! Window titles (`ESC ] 0 ;` or `ESC ] 2 ;`) are picked out the same way.
+//! Sequences may be split across reads, so the scanner keeps state between
+//! calls to [`Osc52Scanner::feed`].
+
+/// Longest OSC body we buffer. Longer sequences are ignored so a runaway
+/// escape cannot grow memory or exceed the 1 MB socket frame limit.
+pub const MAX_OSC_LEN: usize = 512 * 1024;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Outside any escape sequence
+    Ground,
+    /// Saw ESC, waiting for `]`
+    Escape,
+    /// Inside an OSC body
+    Osc,
+    /// Saw ESC inside an OSC body, waiting for `\`
+    OscEscape,
+    /// Inside an OSC body that exceeded [`MAX_OSC_LEN`]; skipped until terminated
+    Overflow,
+}
+
+/// Longest window title reported; longer ones are cut
+pub const MAX_TITLE_LEN: usize = 256;
+
+/// An OSC sequence worth reporting to mac-client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Osc {
+    /// Base64 payload of a clipboard write
+    Clipboard(String),
+    /// The window title a program set
+    Title(String),
+}
+
+/// Incremental scanner for OSC 52 clipboard writes and window titles.
+#[derive(Debug)]
+pub struct Osc52Scanner {
+    state: State,
+    body: Vec<u8>,
+}
+
+impl Default for Osc52Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Osc52Scanner {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            body: Vec::new(),
+        }
+    }
+
+    /// Scan a chunk of output and return every complete clipboard write
+    /// and title change it finishes.
+    ///
+    /// Clipboard queries (`?`) and empty payloads are skipped.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Osc> {
+        let mut found = Vec::new();
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Ground, ESC) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, b']') => {
+                    self.body.clear();
+                    State::Osc
+                }
+                (State::Escape, ESC) => State::Escape,
+                (State::Escape, _) => State::Ground,
+                (State::Osc, BEL) => {
+                    found.extend(self.finish());
+                    State::Ground
+                }
+                (State::Osc, ESC) => State::OscEscape,
+                (State::Osc, _) if self.body.len() >= MAX_OSC_LEN => {
+                    self.body.clear();
+                    State::Overflow
+                }
+                (State::Osc, _) => {
+                    self.body.push(byte);
+                    State::Osc
+                }
+                (State::OscEscape, b'\\') => {
+                    found.extend(self.finish());
+                    State::Ground
+                }
+                // ESC ] restarts the OSC; ESC followed by anything else aborts it
+                (State::OscEscape, b']') => {
+                    self.body.clear();
+                    State::Osc
+                }
+                (State::OscEscape, _) => State::Ground,
+                (State::Overflow, BEL) => State::Ground,
+                (State::Overflow, ESC) => State::Escape,
+                (State::Overflow, _) => State::Overflow,
+            };
+        }
+        found
+    }
+
+    /// Extract a completed OSC body if it is a clipboard write or a title.
+    fn finish(&mut self) -> Option<Osc> {
+        let body = std::mem::take(&mut self.body);
+        if let Some(title) = body.strip_prefix(b"0;").or_else(|| body.strip_prefix(b"2;")) {
+            let title: String = String::from_utf8_lossy(title)
+                .chars()
+                .filter(|c| !c.is_control())
+                .take(MAX_TITLE_LEN)
+                .collect();
+            return Some(Osc::Title(title));
+        }
+        let rest = body.strip_prefix(b"52;")?;
+        let sep = rest.iter().position(|&b| b == b';')?;
+        let data = &rest[sep + 1..];
+        if data.is_empty() || data == b"?" {
+            return None;
+        }
+        String::from_utf8(data.to_vec()).ok().map(Osc::Clipboard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bel_and_st_terminators() {
+        let mut scanner = Osc52Scanner::new();
+        assert_eq!(
+            scanner.feed(b"before\x1b]52;c;aGVsbG8=\x07after"),
+            vec![Osc::Clipboard("aGVsbG8=".to_string())]
+        );
+        assert_eq!(
+            scanner.feed(b"\x1b]52;;d29ybGQ=\x1b\\"),
+            vec![Osc::Clipboard("d29ybGQ=".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_sequence_split_across_reads() {
+        let mut scanner = Osc52Scanner::new();
+        assert!(scanner.feed(b"\x1b]5").is_empty());
+        assert!(scanner.feed(b"2;c;aGVs").is_empty());
+        assert!(scanner.feed(b"bG8=\x1b").is_empty());
+        assert_eq!(scanner.feed(b"\\"), vec![Osc::Clipboard("aGVsbG8=".to_string())]);
+    }
+
+    #[test]
+    fn test_ignores_other_osc_and_queries() {
+        let mut scanner = Osc52Scanner::new();
+        assert!(scanner.feed(b"\x1b]7;file://host/tmp\x07").is_empty());
+        assert!(scanner.feed(b"\x1b]52;c;?\x07").is_empty());
+        assert!(scanner.feed(b"\x1b[1;31mred\x1b[0m").is_empty());
+    }
+
+    #[test]
+    fn test_oversized_sequence_dropped() {
+        let mut scanner = Osc52Scanner::new();
+        let mut data = b"\x1b]52;c;".to_vec();
+        data.resize(data.len() + MAX_OSC_LEN + 10, b'A');
+        data.push(BEL);
+        assert!(scanner.feed(&data).is_empty());
+        assert_eq!(
+            scanner.feed(b"\x1b]52;c;aGk=\x07"),
+            vec![Osc::Clipboard("aGk=".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_window_titles() {
+        let mut scanner = Osc52Scanner::new();
+        assert_eq!(
+            scanner.feed(b"\x1b]0;vim notes.md\x07\x1b]2;~/src\x1b\\"),
+            vec![Osc::Title("vim notes.md".to_string()), Osc::Title("~/src".to_string())]
+        );
+        // Titles can be cleared
+        assert_eq!(scanner.feed(b"\x1b]2;\x07"), vec![Osc::Title(String::new())]);
+    }
+}
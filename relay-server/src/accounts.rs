@@ -0,0 +1,308 @@
+//! Accounts, for a relay a team shares.
+//!
+//! Off unless `ACCOUNTS` is set. Every Mac then registers with a token of
+//! an account, sent where an API key would go, and its sessions count
+//! against the account's limits: how many sessions it may have open at
+//! once, how many bytes they may relay in a quota period
+//! (`QUOTA_PERIOD_SECS`, with `QUOTA_ACTION` applying when they go over)
+//! and how much scrollback they keep per terminal. Unset limits are the
+//! relay's.
+//!
+//! Accounts are managed through the admin API (`ADMIN_TOKEN` as bearer
+//! token), which also shows what each one uses. Tokens are shown once when
+//! created; the relay keeps their SHA-256 only, in `ACCOUNTS_FILE` if set
+//! so accounts survive restarts. Revoking a token or deleting an account
+//! turns away the next registration with it; sessions already hosted run
+//! on. Changed limits apply to the next registration for scrollback and
+//! right away for the others.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::apikeys::hash;
+use crate::state::unix_now;
+
+/// What account tokens start with, so they are easy to spot in config files
+const TOKEN_PREFIX: &str = "ra_";
+
+/// What an account's sessions may use; None is the relay's limit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountLimits {
+    /// Sessions open at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<usize>,
+    /// Bytes all its sessions may relay in a quota period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// Scrollback per terminal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrollback_bytes: Option<u64>,
+}
+
+/// A token as the relay keeps it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredToken {
+    id: String,
+    /// Unix time, in seconds
+    created_at: u64,
+    /// Hex SHA-256 of the token
+    hash: String,
+}
+
+/// An account as the relay keeps it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredAccount {
+    id: String,
+    name: String,
+    created_at: u64,
+    #[serde(default)]
+    limits: AccountLimits,
+    #[serde(default)]
+    tokens: Vec<StoredToken>,
+}
+
+/// A token as the admin API lists it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TokenInfo {
+    pub id: String,
+    pub created_at: u64,
+}
+
+impl From<&StoredToken> for TokenInfo {
+    fn from(token: &StoredToken) -> Self {
+        Self {
+            id: token.id.clone(),
+            created_at: token.created_at,
+        }
+    }
+}
+
+/// An account as the admin API lists it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AccountInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub limits: AccountLimits,
+    pub tokens: Vec<TokenInfo>,
+}
+
+impl From<&StoredAccount> for AccountInfo {
+    fn from(account: &StoredAccount) -> Self {
+        Self {
+            id: account.id.clone(),
+            name: account.name.clone(),
+            created_at: account.created_at,
+            limits: account.limits,
+            tokens: account.tokens.iter().map(TokenInfo::from).collect(),
+        }
+    }
+}
+
+/// The accounts Macs register with
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// Where accounts are saved; kept in memory only without one
+    path: Option<PathBuf>,
+    accounts: RwLock<Vec<StoredAccount>>,
+}
+
+impl Accounts {
+    /// Accounts saved in `path`, if it exists yet
+    pub fn load(path: Option<PathBuf>) -> std::io::Result<Self> {
+        let accounts = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(json) => serde_json::from_slice(&json)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            },
+            None => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            accounts: RwLock::new(accounts),
+        })
+    }
+
+    /// Make an account. Returns it with the only copy of its first token.
+    pub fn create(&self, name: String, limits: AccountLimits) -> std::io::Result<(AccountInfo, String)> {
+        let (token, stored_token) = new_token();
+        let stored = StoredAccount {
+            id: nanoid::nanoid!(10),
+            name,
+            created_at: unix_now(),
+            limits,
+            tokens: vec![stored_token],
+        };
+        let info = AccountInfo::from(&stored);
+        let mut accounts = self.accounts.write().unwrap();
+        accounts.push(stored);
+        self.save(&accounts)?;
+        Ok((info, token))
+    }
+
+    pub fn list(&self) -> Vec<AccountInfo> {
+        self.accounts.read().unwrap().iter().map(AccountInfo::from).collect()
+    }
+
+    /// What an account's sessions may use; None if there is no such
+    /// account (any more)
+    pub fn limits(&self, id: &str) -> Option<AccountLimits> {
+        self.accounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|account| account.id == id)
+            .map(|account| account.limits)
+    }
+
+    /// Replace an account's limits; None if there is no such account
+    pub fn set_limits(&self, id: &str, limits: AccountLimits) -> std::io::Result<Option<AccountInfo>> {
+        self.update(id, |account| account.limits = limits)
+    }
+
+    /// Give an account another token. Returns it with the only copy of the
+    /// token; None if there is no such account.
+    pub fn add_token(&self, id: &str) -> std::io::Result<Option<(TokenInfo, String)>> {
+        let (token, stored) = new_token();
+        let info = TokenInfo::from(&stored);
+        Ok(self.update(id, |account| account.tokens.push(stored))?.map(|_| (info, token)))
+    }
+
+    /// Forget a token of an account; false if it has none with this ID
+    pub fn revoke_token(&self, id: &str, token_id: &str) -> std::io::Result<bool> {
+        let mut accounts = self.accounts.write().unwrap();
+        let Some(account) = accounts.iter_mut().find(|account| account.id == id) else {
+            return Ok(false);
+        };
+        let before = account.tokens.len();
+        account.tokens.retain(|token| token.id != token_id);
+        if account.tokens.len() == before {
+            return Ok(false);
+        }
+        self.save(&accounts)?;
+        Ok(true)
+    }
+
+    /// Forget an account and its tokens; false if there is none with this
+    /// ID
+    pub fn delete(&self, id: &str) -> std::io::Result<bool> {
+        let mut accounts = self.accounts.write().unwrap();
+        let before = accounts.len();
+        accounts.retain(|account| account.id != id);
+        if accounts.len() == before {
+            return Ok(false);
+        }
+        self.save(&accounts)?;
+        Ok(true)
+    }
+
+    /// The ID of the account `given` is a token of, if it is one
+    pub fn check(&self, given: &str) -> Option<String> {
+        let given = hash(given);
+        self.accounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|account| {
+                account
+                    .tokens
+                    .iter()
+                    .any(|token| crate::state::constant_time_eq(token.hash.as_bytes(), given.as_bytes()))
+            })
+            .map(|account| account.id.clone())
+    }
+
+    /// Change an account and save; None if there is no such account
+    fn update(&self, id: &str, change: impl FnOnce(&mut StoredAccount)) -> std::io::Result<Option<AccountInfo>> {
+        let mut accounts = self.accounts.write().unwrap();
+        let Some(account) = accounts.iter_mut().find(|account| account.id == id) else {
+            return Ok(None);
+        };
+        change(account);
+        let info = AccountInfo::from(&*account);
+        self.save(&accounts)?;
+        Ok(Some(info))
+    }
+
+    /// Write the accounts to the file, through a temporary one so a crash
+    /// never leaves half of them
+    fn save(&self, accounts: &[StoredAccount]) -> std::io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(accounts)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// A new token, and how it is kept
+fn new_token() -> (String, StoredToken) {
+    let token = format!("{}{}", TOKEN_PREFIX, nanoid::nanoid!(32));
+    let stored = StoredToken {
+        id: nanoid::nanoid!(10),
+        created_at: unix_now(),
+        hash: hash(&token),
+    };
+    (token, stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accounts_and_tokens() {
+        let accounts = Accounts::default();
+        let limits = AccountLimits {
+            max_sessions: Some(3),
+            ..AccountLimits::default()
+        };
+        let (info, token) = accounts.create("design".into(), limits).unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(accounts.check(&token), Some(info.id.clone()));
+        assert_eq!(accounts.check("ra_guess"), None);
+        assert_eq!(accounts.limits(&info.id), Some(limits));
+
+        // A second token works alongside the first until one is revoked
+        let (second, second_token) = accounts.add_token(&info.id).unwrap().unwrap();
+        assert_eq!(accounts.check(&second_token), Some(info.id.clone()));
+        assert!(accounts.revoke_token(&info.id, &info.tokens[0].id).unwrap());
+        assert!(!accounts.revoke_token(&info.id, &info.tokens[0].id).unwrap());
+        assert_eq!(accounts.check(&token), None);
+        assert_eq!(accounts.list()[0].tokens, vec![second]);
+
+        let limits = AccountLimits {
+            quota_bytes: Some(1 << 30),
+            scrollback_bytes: Some(64 * 1024),
+            ..AccountLimits::default()
+        };
+        assert_eq!(accounts.set_limits(&info.id, limits).unwrap().unwrap().limits, limits);
+        assert!(accounts.set_limits("nope", limits).unwrap().is_none());
+        assert!(accounts.add_token("nope").unwrap().is_none());
+
+        assert!(accounts.delete(&info.id).unwrap());
+        assert!(!accounts.delete(&info.id).unwrap());
+        assert_eq!(accounts.check(&second_token), None);
+        assert_eq!(accounts.limits(&info.id), None);
+    }
+
+    #[test]
+    fn test_accounts_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("relay-accounts-{}.json", nanoid::nanoid!(8)));
+        let accounts = Accounts::load(Some(path.clone())).unwrap();
+        let limits = AccountLimits {
+            max_sessions: Some(1),
+            ..AccountLimits::default()
+        };
+        let (info, token) = accounts.create("ops".into(), limits).unwrap();
+        // Only the hash is written
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&token));
+
+        let reloaded = Accounts::load(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.check(&token), Some(info.id.clone()));
+        assert_eq!(reloaded.limits(&info.id), Some(limits));
+        std::fs::remove_file(path).unwrap();
+    }
+}
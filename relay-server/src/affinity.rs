@@ -0,0 +1,184 @@
+//! Session affinity, for relays behind a load balancer without Redis.
+//!
+//! Off unless `AFFINITY_INSTANCES` lists the relays sharing the load
+//! balancer, by `RELAY_INSTANCE_ID`. Every code then belongs to one of them
+//! by rendezvous hashing: the instance whose hash with the code is highest.
+//! Relays hand out only codes that belong to themselves, so the relay a
+//! Mac registered with is the one its code hashes to, and taking a relay
+//! out of the list only moves the codes that were its own.
+//!
+//! The load balancer routes on the code, which clients put in the
+//! WebSocket handshake: the web UI as `/ws?code=...`, the mac-client in an
+//! `X-Relay-Session` header when it comes back. The relay answers the
+//! handshake with a `relay_session` cookie holding the code, for balancers
+//! that can only stick on cookies, and names itself in `X-Relay-Instance`.
+//! `GET /api/affinity/{code}` says which instance a code belongs to, for
+//! balancers that look routes up. A relay that gets a code belonging to
+//! another instance, and holds no session with it, answers the handshake
+//! with 421 and the right instance in `X-Relay-Instance`.
+
+use axum::http::{header, HeaderMap, HeaderName};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::session::normalize_code;
+
+/// Header naming the session code of a handshake
+pub static SESSION_HEADER: HeaderName = HeaderName::from_static("x-relay-session");
+
+/// Header naming the relay instance a handshake reached, or should have
+pub static INSTANCE_HEADER: HeaderName = HeaderName::from_static("x-relay-instance");
+
+/// Cookie holding the session code of a handshake
+pub const SESSION_COOKIE: &str = "relay_session";
+
+/// The relays behind a load balancer, and which of them this one is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Affinity {
+    instance: String,
+    instances: Vec<String>,
+}
+
+impl Affinity {
+    /// Instances from `AFFINITY_INSTANCES` and this one's name from
+    /// `RELAY_INSTANCE_ID`; None without instances
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        Self::from_vars(|name| config.get(name))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let instances: Vec<String> = var("AFFINITY_INSTANCES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|instance| !instance.is_empty())
+            .map(str::to_string)
+            .collect();
+        if instances.is_empty() {
+            return Ok(None);
+        }
+        let instance = var("RELAY_INSTANCE_ID")
+            .filter(|id| !id.is_empty())
+            .ok_or("AFFINITY_INSTANCES needs this relay's RELAY_INSTANCE_ID")?;
+        Self::new(instance, instances).map(Some)
+    }
+
+    pub fn new(instance: String, instances: Vec<String>) -> Result<Self, String> {
+        if !instances.contains(&instance) {
+            return Err(format!("RELAY_INSTANCE_ID {} is not in AFFINITY_INSTANCES", instance));
+        }
+        Ok(Self { instance, instances })
+    }
+
+    /// This relay's name
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// The instance a session code belongs to
+    pub fn owner(&self, code: &str) -> &str {
+        let code = normalize_code(code);
+        self.instances
+            .iter()
+            .max_by_key(|instance| weight(instance, &code))
+            .expect("affinity has at least one instance")
+    }
+
+    /// Whether a session code belongs to this relay
+    pub fn owns(&self, code: &str) -> bool {
+        self.owner(code) == self.instance
+    }
+}
+
+/// Rendezvous weight of an instance for a code
+fn weight(instance: &str, code: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(instance.as_bytes());
+    hasher.update([0]);
+    hasher.update(code.as_bytes());
+    hasher.finalize().into()
+}
+
+/// The session code a WebSocket handshake names: in the query, the
+/// `X-Relay-Session` header or the `relay_session` cookie
+pub fn requested_code(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    let cookie = || {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .find_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+            .map(str::to_string)
+    };
+    query
+        .map(str::to_string)
+        .or_else(|| headers.get(&SESSION_HEADER).and_then(|value| value.to_str().ok()).map(str::to_string))
+        .or_else(cookie)
+        .map(|code| normalize_code(&code))
+        .filter(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+/// `Set-Cookie` value handing a session code back to the browser
+pub fn session_cookie(code: &str) -> String {
+    format!("{}={}; Path=/; HttpOnly; SameSite=Lax", SESSION_COOKIE, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+    }
+
+    fn affinity(instance: &str, instances: &[&str]) -> Affinity {
+        Affinity::new(instance.into(), instances.iter().map(|i| i.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        assert_eq!(Affinity::from_vars(vars(&[])).unwrap(), None);
+        let parsed = Affinity::from_vars(vars(&[("AFFINITY_INSTANCES", "a, b,"), ("RELAY_INSTANCE_ID", "b")])).unwrap();
+        assert_eq!(parsed, Some(affinity("b", &["a", "b"])));
+        assert!(Affinity::from_vars(vars(&[("AFFINITY_INSTANCES", "a,b")])).is_err());
+        assert!(Affinity::from_vars(vars(&[("AFFINITY_INSTANCES", "a,b"), ("RELAY_INSTANCE_ID", "c")])).is_err());
+    }
+
+    #[test]
+    fn test_every_instance_agrees_and_few_codes_move() {
+        let codes: Vec<String> = (0..300).map(|i| format!("CODE{}", i)).collect();
+        let three = ["relay-1", "relay-2", "relay-3"];
+        let a = affinity("relay-1", &three);
+        let b = affinity("relay-3", &three);
+        for code in &codes {
+            assert_eq!(a.owner(code), b.owner(code));
+            assert_eq!(a.owner(code), a.owner(&code.to_lowercase()));
+        }
+        // Each instance gets a share
+        for instance in three {
+            assert!(codes.iter().filter(|code| a.owner(code) == instance).count() > 50);
+        }
+        // Dropping relay-3 moves only its own codes
+        let two = affinity("relay-1", &["relay-1", "relay-2"]);
+        for code in &codes {
+            if a.owner(code) != "relay-3" {
+                assert_eq!(two.owner(code), a.owner(code));
+            }
+        }
+    }
+
+    #[test]
+    fn test_requested_code() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_code(&headers, None), None);
+        assert_eq!(requested_code(&headers, Some("k7qh3m")), Some("K7QH3M".into()));
+        headers.insert(header::COOKIE, "theme=dark; relay_session=ABC234".parse().unwrap());
+        assert_eq!(requested_code(&headers, None), Some("ABC234".into()));
+        headers.insert(&SESSION_HEADER, "maple-otter-42".parse().unwrap());
+        assert_eq!(requested_code(&headers, None), Some("maple-otter-42".into()));
+        assert_eq!(requested_code(&headers, Some("XYZ789")), Some("XYZ789".into()));
+        // Nothing that could not be a code ends up in a cookie
+        assert_eq!(requested_code(&HeaderMap::new(), Some("A;B")), None);
+    }
+}
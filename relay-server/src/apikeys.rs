@@ -0,0 +1,162 @@
+//! API keys Macs need to host sessions on a relay.
+//!
+//! Off unless `REQUIRE_API_KEY` is set; any Mac may register then. With it
+//! on, a `register` must carry a key handed out through the admin API
+//! (`/api/admin/keys`, with `ADMIN_TOKEN` as bearer token), so a public
+//! relay hosts sessions only for the Macs its operator let in. Keys are
+//! shown once when created; the relay keeps their SHA-256 only, in
+//! `API_KEYS_FILE` if set so they survive restarts. Revoking a key turns
+//! away the next registration with it; sessions already hosted run on.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What keys start with, so they are easy to spot in config files
+const KEY_PREFIX: &str = "rk_";
+
+/// A key as the relay keeps it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredKey {
+    id: String,
+    name: String,
+    /// Unix time, in seconds
+    created_at: u64,
+    /// Hex SHA-256 of the key
+    hash: String,
+}
+
+/// A key as the admin API lists it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+}
+
+impl From<&StoredKey> for ApiKeyInfo {
+    fn from(key: &StoredKey) -> Self {
+        Self {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            created_at: key.created_at,
+        }
+    }
+}
+
+/// The keys Macs may register with
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    /// Where keys are saved; kept in memory only without one
+    path: Option<PathBuf>,
+    keys: RwLock<Vec<StoredKey>>,
+}
+
+impl ApiKeys {
+    /// Keys saved in `path`, if it exists yet
+    pub fn load(path: Option<PathBuf>) -> std::io::Result<Self> {
+        let keys = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(json) => serde_json::from_slice(&json)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            },
+            None => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// Make a key named `name`. Returns it with the only copy of the key.
+    pub fn create(&self, name: String) -> std::io::Result<(ApiKeyInfo, String)> {
+        let key = format!("{}{}", KEY_PREFIX, nanoid::nanoid!(32));
+        let stored = StoredKey {
+            id: nanoid::nanoid!(10),
+            name,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            hash: hash(&key),
+        };
+        let info = ApiKeyInfo::from(&stored);
+        let mut keys = self.keys.write().unwrap();
+        keys.push(stored);
+        self.save(&keys)?;
+        Ok((info, key))
+    }
+
+    pub fn list(&self) -> Vec<ApiKeyInfo> {
+        self.keys.read().unwrap().iter().map(ApiKeyInfo::from).collect()
+    }
+
+    /// Forget a key; false if there is none with this ID
+    pub fn revoke(&self, id: &str) -> std::io::Result<bool> {
+        let mut keys = self.keys.write().unwrap();
+        let before = keys.len();
+        keys.retain(|key| key.id != id);
+        if keys.len() == before {
+            return Ok(false);
+        }
+        self.save(&keys)?;
+        Ok(true)
+    }
+
+    /// The ID of the key `given` is, if it is one
+    pub fn check(&self, given: &str) -> Option<String> {
+        let given = hash(given);
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| crate::state::constant_time_eq(key.hash.as_bytes(), given.as_bytes()))
+            .map(|key| key.id.clone())
+    }
+
+    /// Write the keys to the file, through a temporary one so a crash
+    /// never leaves half of them
+    fn save(&self, keys: &[StoredKey]) -> std::io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(keys)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// Hex SHA-256 of a key or token
+pub(crate) fn hash(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_check_revoke() {
+        let keys = ApiKeys::default();
+        let (info, key) = keys.create("office mac".into()).unwrap();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(keys.check(&key), Some(info.id.clone()));
+        assert_eq!(keys.check("rk_guess"), None);
+        assert_eq!(keys.list(), vec![info.clone()]);
+
+        assert!(keys.revoke(&info.id).unwrap());
+        assert!(!keys.revoke(&info.id).unwrap());
+        assert_eq!(keys.check(&key), None);
+    }
+
+    #[test]
+    fn test_keys_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("relay-keys-{}.json", nanoid::nanoid!(8)));
+        let keys = ApiKeys::load(Some(path.clone())).unwrap();
+        let (info, key) = keys.create("ci".into()).unwrap();
+        // Only the hash is written
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&key));
+
+        let reloaded = ApiKeys::load(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.check(&key), Some(info.id));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,460 @@
+//! Audit trail of what browsers typed.
+//!
+//! Off unless `AUDIT_LOG_DIR` is set. Every input frame a browser sends to a
+//! Mac is then appended to a file of that directory, one JSON object per
+//! line and one file per UTC day (`audit-2026-10-16.jsonl`), by a single
+//! writer task, so lines are never rewritten or interleaved. Files older
+//! than the retention are deleted. The relay cannot read the input of
+//! passthrough sessions; it is logged as the ciphertext it is. Direct
+//! browser-to-Mac links are not set up while auditing, since their input
+//! would never pass the relay.
+//!
+//! With `AUDIT_KEY` set, the payload of every entry is sealed with AES-GCM
+//! before it is written, under a key derived with HKDF from that master key
+//! for the entry's session (client ID and code). The metadata stays
+//! readable for filtering and pruning, and is authenticated with the
+//! payload. Entries are opened again on export; without the master key the
+//! files do not give away what was typed.
+//!
+//! `GET /api/audit`, with `AUDIT_TOKEN` as bearer token, exports entries as
+//! JSON lines, optionally only those `since` a Unix time (seconds) and of
+//! one `code` or `client_id`.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// Default time audit files are kept
+pub const DEFAULT_AUDIT_RETENTION: Duration = Duration::from_secs(90 * 24 * 3600);
+
+/// Entries waiting to be written before input waits for the writer
+const QUEUE_LEN: usize = 1024;
+
+const SECS_PER_DAY: u64 = 24 * 3600;
+
+/// HKDF info prefix of session keys
+const KEY_INFO: &[u8] = b"ignis-term audit v1";
+
+const NONCE_LEN: usize = 12;
+
+/// Master key sealing audit entries at rest, from `AUDIT_KEY`
+#[derive(Clone)]
+pub struct AuditKey([u8; 32]);
+
+impl AuditKey {
+    /// A key given as base64 of 32 bytes (`openssl rand -base64 32`)
+    pub fn from_base64(text: &str) -> Option<Self> {
+        let bytes = STANDARD.decode(text.trim()).ok()?;
+        bytes.try_into().ok().map(Self)
+    }
+
+    /// Cipher for the entries of one session
+    fn session_cipher(&self, client_id: &str, code: &str) -> Aes256Gcm {
+        let hkdf = Hkdf::<Sha256>::new(None, &self.0);
+        let mut key = Key::<Aes256Gcm>::default();
+        hkdf.expand_multi_info(&[KEY_INFO, client_id.as_bytes(), b"\0", code.as_bytes()], &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Aes256Gcm::new(&key)
+    }
+}
+
+/// One input frame
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// Unix time in milliseconds
+    pub at: u64,
+    pub code: String,
+    pub client_id: String,
+    pub browser_id: String,
+    /// Terminal session the input went to
+    pub session_id: String,
+    /// The frame's payload, base64; `nonce || ciphertext` when sealed
+    pub data: String,
+    /// Whether `data` is sealed with the session's key
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sealed: bool,
+    /// Set on export for a sealed entry that did not open: it was sealed
+    /// under another `AUDIT_KEY`, or altered on disk
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unopened: bool,
+}
+
+impl AuditEntry {
+    pub fn new(code: &str, client_id: &str, browser_id: &str, frame: &[u8]) -> Self {
+        let session_id = crate::frame::session_id(frame).unwrap_or_default();
+        let payload = frame.get(1 + session_id.len()..).unwrap_or_default();
+        Self {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default(),
+            code: code.to_string(),
+            client_id: client_id.to_string(),
+            browser_id: browser_id.to_string(),
+            session_id: String::from_utf8_lossy(session_id).into_owned(),
+            data: STANDARD.encode(payload),
+            sealed: false,
+            unopened: false,
+        }
+    }
+
+    /// Authenticated along with the payload, so sealed data cannot be
+    /// passed off as another entry's
+    fn aad(&self) -> Vec<u8> {
+        format!("{}\0{}\0{}\0{}\0{}", self.at, self.code, self.client_id, self.browser_id, self.session_id)
+            .into_bytes()
+    }
+
+    /// The entry with its payload sealed under the session's key
+    fn seal(mut self, key: &AuditKey) -> Self {
+        let payload = STANDARD.decode(&self.data).expect("entries hold base64 payloads");
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = key
+            .session_cipher(&self.client_id, &self.code)
+            .encrypt(&nonce, Payload { msg: &payload, aad: &self.aad() })
+            .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&sealed);
+        self.data = STANDARD.encode(out);
+        self.sealed = true;
+        self
+    }
+
+    /// Put the payload in the clear. False, leaving the entry as it was,
+    /// if it was sealed under another key, there is none, or it was altered
+    fn open(&mut self, key: Option<&AuditKey>) -> bool {
+        if !self.sealed {
+            return true;
+        }
+        let Some(payload) = key.and_then(|key| self.open_payload(key)) else {
+            return false;
+        };
+        self.data = STANDARD.encode(payload);
+        self.sealed = false;
+        true
+    }
+
+    fn open_payload(&self, key: &AuditKey) -> Option<Vec<u8>> {
+        let sealed = STANDARD.decode(&self.data).ok()?;
+        let (nonce, ciphertext) = sealed.split_at_checked(NONCE_LEN)?;
+        key.session_cipher(&self.client_id, &self.code)
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &self.aad() })
+            .ok()
+    }
+}
+
+/// Which entries to export
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// Unix time in seconds
+    pub since: Option<u64>,
+    pub code: Option<String>,
+    pub client_id: Option<String>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.since.is_none_or(|since| entry.at >= since * 1000)
+            && self.code.as_ref().is_none_or(|code| *code == entry.code)
+            && self.client_id.as_ref().is_none_or(|id| *id == entry.client_id)
+    }
+}
+
+/// The audit files and their writer
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    retention: Duration,
+    /// Bearer token for exports; exports are off without one
+    token: Option<String>,
+    /// Seals entries before they are written; entries are written in the
+    /// clear without one
+    key: Option<AuditKey>,
+    entries: mpsc::Sender<AuditEntry>,
+}
+
+impl AuditLog {
+    /// Start writing to `dir`, creating it if needed
+    pub fn open(
+        dir: PathBuf,
+        retention: Duration,
+        token: Option<String>,
+        key: Option<AuditKey>,
+    ) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let (entries, rx) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(write_entries(dir.clone(), rx));
+        Ok(Self {
+            dir,
+            retention,
+            token,
+            key,
+            entries,
+        })
+    }
+
+    pub async fn record(&self, entry: AuditEntry) {
+        let entry = match &self.key {
+            Some(key) => entry.seal(key),
+            None => entry,
+        };
+        if self.entries.send(entry).await.is_err() {
+            tracing::error!("Audit writer is gone, input is not being logged");
+        }
+    }
+
+    /// Whether `given` is the export token
+    pub fn token_matches(&self, given: &str) -> bool {
+        self.token
+            .as_deref()
+            .is_some_and(|token| crate::state::constant_time_eq(token.as_bytes(), given.as_bytes()))
+    }
+
+    /// Entries matching `query`, as JSON lines with their payloads in the
+    /// clear, oldest first. Sealed entries that do not open are exported
+    /// sealed and flagged `unopened`
+    pub async fn export(&self, query: &AuditQuery) -> std::io::Result<String> {
+        let first_day = query.since.map(|since| since / SECS_PER_DAY).unwrap_or_default();
+        let mut out = String::new();
+        for (day, path) in self.files().await? {
+            if day < first_day {
+                continue;
+            }
+            let content = tokio::fs::read_to_string(&path).await?;
+            let mut unopened = 0;
+            for line in content.lines() {
+                match serde_json::from_str::<AuditEntry>(line) {
+                    Ok(mut entry) if query.matches(&entry) => {
+                        if !entry.open(self.key.as_ref()) {
+                            entry.unopened = true;
+                            unopened += 1;
+                        }
+                        out.push_str(&serde_json::to_string(&entry).expect("audit entries serialize"));
+                        out.push('\n');
+                    }
+                    Ok(_) => {}
+                    // A line cut short by a crash
+                    Err(_) => tracing::warn!(file = %path.display(), "Skipping unreadable audit line"),
+                }
+            }
+            if unopened > 0 {
+                tracing::warn!(
+                    file = %path.display(),
+                    "{} audit entries do not open: altered, or sealed under another AUDIT_KEY",
+                    unopened
+                );
+            }
+        }
+        Ok(out)
+    }
+
+    /// Delete files of days past the retention
+    pub async fn prune(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let oldest_kept = now.saturating_sub(self.retention).as_secs() / SECS_PER_DAY;
+        let Ok(files) = self.files().await else { return };
+        for (day, path) in files {
+            if day < oldest_kept {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => tracing::info!(file = %path.display(), "Audit file expired"),
+                    Err(e) => tracing::warn!(file = %path.display(), "Cannot delete expired audit file: {}", e),
+                }
+            }
+        }
+    }
+
+    /// Audit files with their day (days since the epoch), oldest first
+    async fn files(&self) -> std::io::Result<Vec<(u64, PathBuf)>> {
+        let mut files = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if let Some(day) = entry.file_name().to_str().and_then(day_of_file) {
+                files.push((day, entry.path()));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Append entries to the file of their day
+async fn write_entries(dir: PathBuf, mut entries: mpsc::Receiver<AuditEntry>) {
+    let mut open: Option<(u64, tokio::fs::File)> = None;
+    while let Some(entry) = entries.recv().await {
+        let day = entry.at / 1000 / SECS_PER_DAY;
+        if open.as_ref().is_none_or(|(open_day, _)| *open_day != day) {
+            open = match open_day_file(&dir, day).await {
+                Ok(file) => Some((day, file)),
+                Err(e) => {
+                    tracing::error!("Cannot open audit file: {}", e);
+                    continue;
+                }
+            };
+        }
+        let (_, file) = open.as_mut().expect("opened above");
+        let mut line = serde_json::to_vec(&entry).expect("audit entries serialize");
+        line.push(b'\n');
+        // Flushed line by line: tokio holds writes back otherwise
+        let written = async {
+            file.write_all(&line).await?;
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            tracing::error!("Cannot write audit entry: {}", e);
+            open = None;
+        }
+    }
+}
+
+async fn open_day_file(dir: &Path, day: u64) -> std::io::Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(file_name(day)))
+        .await
+}
+
+/// `audit-YYYY-MM-DD.jsonl` for a day since the epoch
+fn file_name(day: u64) -> String {
+    let (y, m, d) = civil_from_days(day as i64);
+    format!("audit-{:04}-{:02}-{:02}.jsonl", y, m, d)
+}
+
+/// The day since the epoch of an audit file name
+fn day_of_file(name: &str) -> Option<u64> {
+    let date = name.strip_prefix("audit-")?.strip_suffix(".jsonl")?;
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+    u64::try_from(days_from_civil(y, m, d)).ok()
+}
+
+/// Year, month and day of a day since the epoch (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(m <= 2), m, d)
+}
+
+/// Day since the epoch of a date, the inverse of [`civil_from_days`]
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(file_name(0), "audit-1970-01-01.jsonl");
+        // 2024-02-29
+        assert_eq!(file_name(19782), "audit-2024-02-29.jsonl");
+        for day in [0, 59, 60, 10_956, 19_782, 20_742] {
+            assert_eq!(day_of_file(&file_name(day)), Some(day));
+        }
+        assert_eq!(day_of_file("recording.bin"), None);
+        assert_eq!(day_of_file("audit-2024-xx-01.jsonl"), None);
+    }
+
+    /// Exported entries, once the writer, which runs on its own, wrote
+    /// `count` of them
+    async fn export_when_written(log: &AuditLog, count: usize) -> String {
+        let mut exported = String::new();
+        for _ in 0..100 {
+            exported = log.export(&AuditQuery::default()).await.unwrap();
+            if exported.lines().count() == count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        exported
+    }
+
+    #[tokio::test]
+    async fn test_record_export_prune() {
+        let dir = std::env::temp_dir().join(format!("relay-audit-{}", nanoid::nanoid!(8)));
+        let log = AuditLog::open(dir.clone(), DEFAULT_AUDIT_RETENTION, Some("secret".into()), None).unwrap();
+        assert!(log.token_matches("secret"));
+        assert!(!log.token_matches("guess"));
+
+        let entry = AuditEntry::new("ABC123", "mac-1", "b1", b"\x02s1ls\r");
+        assert_eq!(entry.session_id, "s1");
+        assert_eq!(entry.data, STANDARD.encode(b"ls\r"));
+        log.record(entry.clone()).await;
+        log.record(AuditEntry::new("XYZ789", "mac-2", "b2", b"\x02s1pwd\r")).await;
+        // A file of a day long past the retention
+        std::fs::write(dir.join("audit-2001-01-01.jsonl"), "").unwrap();
+
+        let exported = export_when_written(&log, 2).await;
+        assert_eq!(exported.lines().count(), 2);
+
+        let query = AuditQuery {
+            code: Some("ABC123".into()),
+            ..AuditQuery::default()
+        };
+        let exported = log.export(&query).await.unwrap();
+        let entries: Vec<AuditEntry> = exported.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(entries, vec![entry]);
+
+        log.prune().await;
+        assert!(!dir.join("audit-2001-01-01.jsonl").exists());
+        assert_eq!(log.files().await.unwrap().len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sealed_entries() {
+        let dir = std::env::temp_dir().join(format!("relay-audit-{}", nanoid::nanoid!(8)));
+        let key = AuditKey::from_base64(&STANDARD.encode([7u8; 32])).unwrap();
+        let log = AuditLog::open(dir.clone(), DEFAULT_AUDIT_RETENTION, None, Some(key)).unwrap();
+        let entry = AuditEntry::new("ABC123", "mac-1", "b1", b"\x02s1hunter2\r");
+        log.record(entry.clone()).await;
+
+        // Exported in the clear
+        let exported = export_when_written(&log, 1).await;
+        let entries: Vec<AuditEntry> = exported.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(entries, vec![entry.clone()]);
+
+        // The file holds only the sealed payload
+        let (_, path) = log.files().await.unwrap().pop().unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        assert!(!written.contains(&entry.data));
+        assert!(written.contains("\"sealed\":true"));
+
+        // Another key, or none, cannot read it, and the export says so
+        let other = AuditKey::from_base64(&STANDARD.encode([8u8; 32]));
+        for key in [other, None] {
+            let log = AuditLog::open(dir.clone(), DEFAULT_AUDIT_RETENTION, None, key).unwrap();
+            let exported: AuditEntry = serde_json::from_str(&log.export(&AuditQuery::default()).await.unwrap()).unwrap();
+            assert!(exported.sealed && exported.unopened);
+            assert_ne!(exported.data, entry.data);
+        }
+
+        // Nor can the payload be moved to another entry
+        let mut moved = AuditEntry {
+            browser_id: "b2".into(),
+            ..serde_json::from_str(&written).unwrap()
+        };
+        let key = AuditKey::from_base64(&STANDARD.encode([7u8; 32]));
+        assert!(!moved.open(key.as_ref()));
+        assert!(AuditKey::from_base64("c2hvcnQ=").is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
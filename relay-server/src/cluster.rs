@@ -0,0 +1,358 @@
+//! Several relays sharing one Redis, behind a load balancer.
+//!
+//! Each relay has an instance ID. Session codes are claimed in Redis
+//! (`relay:code:<code>` holds the ID of the relay the mac-client is
+//! connected to), so no two relays hand out the same code, and a relay that
+//! gets a browser for a code it does not hold knows where the session is.
+//!
+//! That browser is linked to the owning relay over pub/sub instead of being
+//! turned away: the relay it connected to publishes a [`LinkOpen`] on the
+//! owner's channel (`relay:instance:<id>`), and from then on forwards the
+//! browser's messages on `relay:link:<link>:up` and delivers the owner's
+//! replies from `relay:link:<link>:down`. The owner runs the browser like a
+//! local one, so passwords, approvals, limits, scrollback and fan-out all
+//! stay where the session is and nothing else has to be shared.
+//!
+//! Code claims expire unless refreshed, so sessions of a relay that went
+//! away without cleaning up free their codes after [`CLAIM_TTL`].
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio_util::sync::PollSender;
+use tracing::Instrument;
+
+use crate::handlers::{handle_browser, BrowserSink, Join, DEAD_PEER_TIMEOUT};
+use crate::state::AppState;
+
+/// How long a code claim lasts without being refreshed
+pub const CLAIM_TTL: Duration = Duration::from_secs(90);
+
+/// Time between two refreshes of the claims of local sessions
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a relay waits for the owner to take up a link
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Release a claim only if this relay still holds it
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Frame tags of link messages
+const TAG_TEXT: u8 = 0;
+const TAG_BINARY: u8 = 1;
+const TAG_CLOSE: u8 = 2;
+/// Sent down by the owner once it listens on the link
+const TAG_READY: u8 = 3;
+/// The owner's keepalive pings and the browser's pongs, so the owner can
+/// tell a linked browser that went away
+const TAG_PING: u8 = 4;
+const TAG_PONG: u8 = 5;
+
+/// A browser on another relay asks to join a session held here
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinkOpen {
+    /// Names the link's `up` and `down` channels
+    pub link: String,
+    #[serde(flatten)]
+    pub join: Join,
+}
+
+/// This relay's connection to the shared Redis
+#[derive(Clone)]
+pub struct Cluster {
+    instance_id: String,
+    client: redis::Client,
+    conn: ConnectionManager,
+}
+
+impl std::fmt::Debug for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cluster")
+            .field("instance_id", &self.instance_id)
+            .finish_non_exhaustive()
+    }
+}
+
+fn code_key(code: &str) -> String {
+    format!("relay:code:{}", code)
+}
+
+fn instance_channel(instance_id: &str) -> String {
+    format!("relay:instance:{}", instance_id)
+}
+
+fn link_channel(link: &str, direction: &str) -> String {
+    format!("relay:link:{}:{}", link, direction)
+}
+
+/// Encode a WebSocket message for a link
+fn encode(msg: Message) -> Vec<u8> {
+    let (tag, payload) = match msg {
+        Message::Text(text) => (TAG_TEXT, text.as_bytes().to_vec()),
+        Message::Binary(data) => (TAG_BINARY, data.to_vec()),
+        Message::Close(_) => (TAG_CLOSE, Vec::new()),
+        Message::Ping(data) => (TAG_PING, data.to_vec()),
+        Message::Pong(data) => (TAG_PONG, data.to_vec()),
+    };
+    let mut out = Vec::with_capacity(1 + payload.len());
+    out.push(tag);
+    out.extend_from_slice(&payload);
+    out
+}
+
+/// Decode a link message; `None` for the ready marker and garbage.
+fn decode(data: &[u8]) -> Option<Message> {
+    let (&tag, payload) = data.split_first()?;
+    match tag {
+        TAG_TEXT => String::from_utf8(payload.to_vec())
+            .ok()
+            .map(|t| Message::Text(t.into())),
+        TAG_BINARY => Some(Message::Binary(payload.to_vec().into())),
+        TAG_CLOSE => Some(Message::Close(None)),
+        TAG_PING => Some(Message::Ping(payload.to_vec().into())),
+        TAG_PONG => Some(Message::Pong(payload.to_vec().into())),
+        _ => None,
+    }
+}
+
+impl Cluster {
+    /// Connect to Redis at `url` as the relay `instance_id`.
+    pub async fn connect(url: &str, instance_id: String) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_connection_manager().await?;
+        Ok(Self {
+            instance_id,
+            client,
+            conn,
+        })
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Claim a session code for this relay. False if another relay holds it.
+    pub async fn claim(&self, code: &str) -> redis::RedisResult<bool> {
+        let mut conn = self.conn.clone();
+        let set: Option<String> = redis::cmd("SET")
+            .arg(code_key(code))
+            .arg(&self.instance_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(CLAIM_TTL.as_secs())
+            .query_async(&mut conn)
+            .await?;
+        Ok(set.is_some())
+    }
+
+    /// Keep the claims on `codes` from expiring
+    pub async fn refresh(&self, codes: &[String]) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for code in codes {
+            pipe.expire(code_key(code), CLAIM_TTL.as_secs() as i64).ignore();
+        }
+        pipe.query_async::<()>(&mut conn).await
+    }
+
+    /// Give a code back
+    pub async fn release(&self, code: &str) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        redis::Script::new(RELEASE_SCRIPT)
+            .key(code_key(code))
+            .arg(&self.instance_id)
+            .invoke_async::<()>(&mut conn)
+            .await
+    }
+
+    /// The relay holding a session code
+    pub async fn owner(&self, code: &str) -> redis::RedisResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        conn.get(code_key(code)).await
+    }
+
+    async fn publish(&self, channel: &str, data: Vec<u8>) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        conn.publish::<_, _, ()>(channel, data).await
+    }
+
+    /// Take up links opened by other relays for sessions held here. Runs
+    /// until the subscription fails.
+    pub async fn serve_links(self, state: AppState) -> redis::RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(instance_channel(&self.instance_id)).await?;
+        tracing::info!(instance = %self.instance_id, "Accepting browsers linked from other relays");
+        let mut messages = pubsub.into_on_message();
+        while let Some(msg) = messages.next().await {
+            match serde_json::from_slice::<LinkOpen>(msg.get_payload_bytes()) {
+                Ok(open) => {
+                    let (cluster, state) = (self.clone(), state.clone());
+                    let span = tracing::info_span!(
+                        "connection",
+                        id = %open.link,
+                        transport = "cluster",
+                        code = tracing::field::Empty,
+                        browser_id = tracing::field::Empty
+                    );
+                    tokio::spawn(
+                        async move {
+                            let link = open.link.clone();
+                            if let Err(e) = cluster.accept_link(open, state).await {
+                                tracing::warn!(link = %link, "Cannot take up link: {}", e);
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                Err(e) => tracing::warn!("Invalid link request: {}", e),
+            }
+        }
+        Err(redis::RedisError::from((
+            redis::ErrorKind::IoError,
+            "link subscription ended",
+        )))
+    }
+
+    /// Run a linked browser as if it were connected here
+    async fn accept_link(&self, open: LinkOpen, state: AppState) -> redis::RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(link_channel(&open.link, "up")).await?;
+        let down = link_channel(&open.link, "down");
+        self.publish(&down, vec![TAG_READY]).await?;
+        tracing::info!(link = %open.link, code = %open.join.session_code, "Browser linked from another relay");
+
+        let receiver = pubsub
+            .into_on_message()
+            .filter_map(|msg| async move { decode(msg.get_payload_bytes()) })
+            .map(Ok::<_, axum::Error>);
+        // Replies go through a channel so handle_browser gets a plain sink
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(64);
+        let sender = PollSender::new(tx).sink_map_err(axum::Error::new);
+        let cluster = self.clone();
+        let forward = tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if cluster.publish(&down, encode(msg)).await.is_err() {
+                    break;
+                }
+            }
+            let _ = cluster.publish(&down, vec![TAG_CLOSE]).await;
+        });
+
+        handle_browser(
+            sender,
+            Box::pin(receiver),
+            state,
+            open.join,
+        )
+        .await;
+        let _ = forward.await;
+        Ok(())
+    }
+
+    /// Connect a browser to a session held by the relay `owner`. Returns
+    /// when either side closes.
+    pub async fn join_remote(&self, socket: WebSocket, owner: &str, mut open: LinkOpen) -> redis::RedisResult<()> {
+        open.link = nanoid::nanoid!(16);
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(link_channel(&open.link, "down")).await?;
+        let mut down = pubsub.into_on_message();
+        let request = serde_json::to_vec(&open).expect("link request serializes");
+        self.publish(&instance_channel(owner), request).await?;
+
+        let (mut sender, mut receiver) = socket.split();
+        let ready = tokio::time::timeout(LINK_TIMEOUT, down.next()).await;
+        if !matches!(ready, Ok(Some(ref msg)) if msg.get_payload_bytes() == [TAG_READY]) {
+            tracing::warn!(owner = %owner, code = %open.join.session_code, "Owning relay did not take up the link");
+            send_link_failed(&mut sender).await;
+            return Ok(());
+        }
+        tracing::info!(owner = %owner, code = %open.join.session_code, link = %open.link, "Browser linked to another relay");
+
+        // The owner pings the browser through the link, so a silent link
+        // means the owner is gone
+        let up = link_channel(&open.link, "up");
+        loop {
+            tokio::select! {
+                msg = tokio::time::timeout(DEAD_PEER_TIMEOUT, down.next()) => {
+                    let Ok(msg) = msg else {
+                        tracing::warn!(owner = %owner, link = %open.link, "Owning relay went silent, closing link");
+                        send_link_failed(&mut sender).await;
+                        break;
+                    };
+                    let Some(msg) = msg.and_then(|m| decode(m.get_payload_bytes())) else { break };
+                    let closing = matches!(msg, Message::Close(_));
+                    if sender.send(msg).await.is_err() || closing {
+                        break;
+                    }
+                }
+                msg = receiver.next() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => Message::Close(None),
+                    };
+                    let closing = matches!(msg, Message::Close(_));
+                    self.publish(&up, encode(msg)).await?;
+                    if closing {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tell a browser its session's relay cannot be reached
+async fn send_link_failed(sender: &mut impl BrowserSink) {
+    let msg = crate::protocol::ControlMessage::Error {
+        message: "The relay holding this session is not responding".into(),
+    };
+    let _ = sender
+        .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_messages_round_trip() {
+        for msg in [
+            Message::Text("{\"type\":\"list_sessions\"}".into()),
+            Message::Binary(vec![2, b's', b'1', b'x'].into()),
+            Message::Close(None),
+            Message::Ping(vec![1, 2].into()),
+            Message::Pong(Vec::new().into()),
+        ] {
+            let decoded = decode(&encode(msg.clone())).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+        }
+        assert!(decode(&[TAG_READY]).is_none());
+        assert!(decode(&[]).is_none());
+    }
+
+    #[test]
+    fn test_link_open_serialization() {
+        let open = LinkOpen {
+            link: "l1".into(),
+            join: Join {
+                session_code: "ABC234".into(),
+                password: Some("hunter2".into()),
+                role: Some(crate::protocol::Role::Viewer),
+                ..Default::default()
+            },
+        };
+        let json = serde_json::to_string(&open).unwrap();
+        assert_eq!(json, r#"{"link":"l1","session_code":"ABC234","password":"hunter2","role":"viewer"}"#);
+        assert_eq!(serde_json::from_str::<LinkOpen>(&json).unwrap(), open);
+    }
+}
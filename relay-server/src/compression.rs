@@ -0,0 +1,83 @@
+//! Compression of terminal output sent to browsers.
+//!
+//! The WebSocket library has no permessage-deflate, so the relay does the
+//! same one level up for browsers that ask for it in their `auth`: binary
+//! frames go through one raw deflate stream per browser, flushed after every
+//! frame. Later frames refer back to earlier ones, which is where the gains
+//! on chatty terminal output come from. Each message is
+//!
+//! ```text
+//! [4 bytes length of the frame, big endian][deflated bytes]
+//! ```
+//!
+//! so the browser knows how much of its inflate stream makes up the frame.
+
+use flate2::{Compress, Compression, FlushCompress};
+
+/// Deflate stream to one browser
+pub struct Deflater {
+    compress: Compress,
+}
+
+impl Deflater {
+    pub fn new() -> Self {
+        Self {
+            compress: Compress::new(Compression::fast(), false),
+        }
+    }
+
+    /// One frame, ready to be sent as a binary message
+    pub fn compress(&mut self, frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + frame.len() / 2 + 16);
+        out.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                out.reserve(64);
+            }
+            self.compress
+                .compress_vec(&frame[consumed..], &mut out, FlushCompress::Sync)
+                .expect("deflating into memory cannot fail");
+            let done = self.compress.total_in() - start == frame.len() as u64;
+            // Flushed once the last call had room to spare
+            if done && out.len() < out.capacity() {
+                break;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Decompress, FlushDecompress};
+
+    #[test]
+    fn test_frames_inflate_one_by_one() {
+        let mut deflater = Deflater::new();
+        let mut inflater = Decompress::new(false);
+        let frames: Vec<Vec<u8>> = vec![
+            b"\x01ahello hello hello hello".to_vec(),
+            Vec::new(),
+            b"\x01a$ ls -la\r\n".repeat(200),
+            (0..=255).collect(),
+        ];
+        let mut sizes = Vec::new();
+        for frame in &frames {
+            let message = deflater.compress(frame);
+            let len = u32::from_be_bytes(message[..4].try_into().unwrap()) as usize;
+            assert_eq!(len, frame.len());
+            sizes.push(message.len());
+
+            // Everything of the frame comes out of its own message
+            let mut out = Vec::with_capacity(len + 1);
+            inflater
+                .decompress_vec(&message[4..], &mut out, FlushDecompress::Sync)
+                .unwrap();
+            assert_eq!(&out, frame);
+        }
+        assert!(sizes[2] < frames[2].len() / 10);
+    }
+}
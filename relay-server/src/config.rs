@@ -0,0 +1,338 @@
+//! Relay settings, layered: a TOML file, then environment variables, then
+//! command-line flags, each overriding the one before.
+//!
+//! Every setting has one name in [`SETTINGS`]. In the environment it is
+//! spelled as listed (`SCROLLBACK_BYTES=65536`), in the file in lower case
+//! (`scrollback_bytes = 65536`) and as a flag in lower case with dashes
+//! (`--scrollback-bytes 65536`). The file is named by `--config` or
+//! `RELAY_CONFIG`; there is none by default. Lists may be TOML arrays or
+//! comma-separated strings. Unknown names in the file or flags stop the
+//! relay at startup, with the closest known name as a hint.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Every setting, with what `--help` says about it
+pub const SETTINGS: &[(&str, &str)] = &[
+    ("BIND_ADDRESS", "Address to listen on (default: 0.0.0.0)"),
+    ("PORT", "Listen port (default: 3000)"),
+    ("LISTEN", "Addresses to listen on instead, with options: plain, admin, cert=, key="),
+    ("UNIX_SOCKET", "Listen on this Unix socket instead, for a reverse proxy on the same host"),
+    ("UNIX_SOCKET_MODE", "Permissions of the Unix socket, octal (default: 660)"),
+    ("LOG_FORMAT", "json for one JSON object per log line (default: text)"),
+    ("TOKIO_CONSOLE", "Serve tokio-console on 127.0.0.1:6669 (needs a build with the console feature)"),
+    ("WEBHOOK_URLS", "Comma-separated URLs told about session events"),
+    ("WEBHOOK_SECRET", "Signs webhook bodies"),
+    ("SCROLLBACK_BYTES", "Scrollback kept per terminal for replay (default: 1048576)"),
+    ("REPLAY_BYTES_PER_SEC", "Pace of scrollback replay to joining browsers (default: 262144, 0: no limit)"),
+    ("SNAPSHOT_ON_JOIN", "Send new browsers each terminal's screen instead of its raw scrollback"),
+    ("SNAPSHOT_HISTORY_LINES", "Lines of history sent with a snapshot (default: 1000)"),
+    ("RECORDING_BYTES", "Cap on one session recording (default: 16777216)"),
+    ("RECORDING_RETENTION_SECS", "Delete recordings this long after they end (default: 86400)"),
+    ("MAX_BROWSERS_PER_CLIENT", "Browsers allowed at once per Mac"),
+    ("MAX_BROWSERS_PER_SESSION", "Browsers allowed at once per session code"),
+    ("JOINS_PER_MINUTE", "Browser joins per IP before it is banned (default: 30, 0: no limit)"),
+    ("REGISTRATIONS_PER_MINUTE", "Mac registrations per IP before it is banned (default: 10, 0: no limit)"),
+    ("RATE_LIMIT_BAN_SECS", "How long an IP over a limit is refused (default: 300)"),
+    ("JOIN_LOCKOUT_FAILURES", "Failed joins that lock out an IP or session (default: 10, 0: never)"),
+    ("JOIN_LOCKOUT_SECS", "How long failures count and lockouts last (default: 900)"),
+    ("CLIENT_IP_HEADER", "Take the client IP from this proxy header"),
+    ("JOIN_ALLOW", "Only let browsers join from these ranges"),
+    ("JOIN_DENY", "Never let browsers join from these ranges"),
+    ("REGISTER_ALLOW", "Only let Macs register from these ranges"),
+    ("REGISTER_DENY", "Never let Macs register from these ranges"),
+    ("PUBLIC_URL", "Where browsers reach the web UI, for join URLs (default: from the request)"),
+    ("ICE_SERVERS", "STUN/TURN URLs browsers use for direct links to Macs"),
+    ("ALLOWED_ORIGINS", "Other web pages browsers may connect from, * for any"),
+    ("WEBTRANSPORT_PORT", "UDP port for browsers on WebTransport"),
+    ("AUDIT_LOG_DIR", "Log every browser input frame in this directory"),
+    ("AUDIT_RETENTION_DAYS", "Delete audit files this many days old (default: 90)"),
+    ("AUDIT_TOKEN", "Bearer token for exporting the audit trail"),
+    ("AUDIT_KEY", "Seal audit entries with keys derived from this 32-byte base64 master key"),
+    ("ADMIN_TOKEN", "Bearer token of the admin API"),
+    ("REQUIRE_API_KEY", "Only host sessions for Macs registering with an API key"),
+    ("API_KEYS_FILE", "Keep API keys (hashed) in this file across restarts"),
+    ("ACCOUNTS", "Only host sessions for Macs registering with an account token, within the account's limits"),
+    ("ACCOUNTS_FILE", "Keep accounts and their tokens (hashed) in this file across restarts"),
+    ("SESSION_QUOTA_BYTES", "Bytes one session may relay per quota period"),
+    ("CLIENT_QUOTA_BYTES", "Bytes all sessions of one Mac may relay per quota period"),
+    ("QUOTA_PERIOD_SECS", "Length of a quota period (default: 86400)"),
+    ("QUOTA_ACTION", "disconnect (default) or throttle sessions over a quota"),
+    ("QUOTA_THROTTLE_BYTES_PER_SEC", "Output rate of throttled sessions (default: 16384)"),
+    ("SESSION_MAX_LIFETIME_SECS", "Close sessions this long after the Mac registered"),
+    ("SESSION_IDLE_TIMEOUT_SECS", "Close sessions with no browsers and no output for this long"),
+    ("RESUME_GRACE_SECS", "Hold sessions this long for a Mac whose connection dropped (default: 60)"),
+    ("STATE_FILE", "Keep sessions in this file across a restart, for Macs to resume"),
+    ("BROWSER_RESUME_SECS", "Let browsers whose connection dropped resume this long (default: 120)"),
+    ("CHAT_HISTORY", "Chat lines kept per session for browsers joining later (default: 100, 0: none)"),
+    ("SLOW_BROWSER", "Browsers that can't keep up: resync (default) or disconnect"),
+    ("CODE_FORMAT", "Session codes: chars (default) or words"),
+    ("CODE_LENGTH", "Characters per code (default: 6)"),
+    ("CODE_ALPHABET", "Characters of codes (default: A-Z, 2-9)"),
+    ("CODE_WORDS", "Words per word code, before the number (default: 2)"),
+    ("CODE_MIN_BITS", "Lengthen codes until they carry this much entropy"),
+    ("REDIS_URL", "Share session codes with other relays behind a load balancer"),
+    ("RELAY_INSTANCE_ID", "This relay's name in Redis and AFFINITY_INSTANCES (default: random)"),
+    ("AFFINITY_INSTANCES", "Relays behind a load balancer routing on session codes, by instance ID"),
+    ("FEDERATION_PEERS", "Relays of other regions browsers can join sessions of"),
+    ("FEDERATION_SECRET", "Shared by peered relays; required to peer"),
+    ("TLS_CERT", "Certificate chain (PEM) for HTTPS"),
+    ("TLS_KEY", "Its private key (PEM)"),
+    ("ACME_DOMAINS", "Get certificates for these domains from Let's Encrypt"),
+    ("ACME_EMAIL", "Contact for certificate expiry notices"),
+    ("ACME_CACHE_DIR", "Account and certificates across restarts"),
+    ("ACME_STAGING", "Use the Let's Encrypt staging directory"),
+];
+
+/// Where a setting's value came from, for error messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    File(PathBuf),
+    Env,
+    Flag,
+}
+
+/// The relay's settings, with every layer applied
+#[derive(Debug, Default)]
+pub struct Config {
+    values: HashMap<&'static str, (String, Source)>,
+}
+
+/// What the command line asked for
+#[derive(Debug)]
+pub enum Command {
+    Run(Config),
+    /// Print `--help` and stop
+    Help,
+}
+
+impl Config {
+    /// Settings from the config file, the environment and the command line
+    pub fn load() -> Result<Command, String> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Self::from_layers(&args, |name| std::env::var(name).ok(), |path| std::fs::read_to_string(path))
+    }
+
+    fn from_layers(
+        args: &[String],
+        env: impl Fn(&str) -> Option<String>,
+        read: impl Fn(&Path) -> std::io::Result<String>,
+    ) -> Result<Command, String> {
+        let mut flags = Vec::new();
+        let mut file = env("RELAY_CONFIG").filter(|path| !path.is_empty());
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Ok(Command::Help);
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("unexpected argument {}, settings are given as --name value", arg));
+            };
+            let (flag, inline) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+            // A flag without a value turns a setting on
+            let mut value = || match inline.clone() {
+                Some(value) => value,
+                None if args.as_slice().first().is_some_and(|next| !next.starts_with("--")) => args.next().unwrap().clone(),
+                None => "1".to_string(),
+            };
+            if flag == "config" {
+                file = Some(value());
+                continue;
+            }
+            let name = setting(&flag.replace('-', "_").to_ascii_uppercase())
+                .ok_or_else(|| unknown(&format!("flag --{}", flag), flag, Spelling::Flag))?;
+            flags.push((name, value()));
+        }
+
+        let mut config = Config::default();
+        if let Some(path) = file {
+            let path = PathBuf::from(path);
+            let text = read(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            for (name, value) in parse_file(&text).map_err(|e| format!("{}: {}", path.display(), e))? {
+                config.values.insert(name, (value, Source::File(path.clone())));
+            }
+        }
+        for (name, _) in SETTINGS {
+            if let Some(value) = env(name) {
+                config.values.insert(name, (value, Source::Env));
+            }
+        }
+        for (name, value) in flags {
+            config.values.insert(name, (value, Source::Flag));
+        }
+        Ok(Command::Run(config))
+    }
+
+    /// The value of a setting, if any layer has one
+    pub fn get(&self, name: &str) -> Option<String> {
+        debug_assert!(setting(name).is_some(), "{} is not in SETTINGS", name);
+        self.values.get(name).map(|(value, _)| value.clone())
+    }
+
+    /// Whether an on/off setting is on; anything but empty, 0 or false is
+    pub fn flag(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|v| !v.is_empty() && v != "0" && v != "false")
+    }
+
+    /// An optional number, or what is wrong with it and where it was set
+    pub fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        let Some((value, source)) = self.values.get(name) else {
+            return Ok(None);
+        };
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value.parse().map(Some).map_err(|_| {
+            let source = match source {
+                Source::File(path) => format!("{} in {}", name.to_ascii_lowercase(), path.display()),
+                Source::Env => name.to_string(),
+                Source::Flag => format!("--{}", name.to_ascii_lowercase().replace('_', "-")),
+            };
+            format!("{} must be a valid number, not {:?}", source, value)
+        })
+    }
+
+    /// What `--help` prints
+    pub fn usage() -> String {
+        let mut out = String::from(
+            "Usage: relay-server [--config FILE] [--setting value]...\n\n\
+             Settings come from FILE (TOML, or RELAY_CONFIG), then the environment,\n\
+             then flags. As environment variables:\n\n",
+        );
+        for (name, help) in SETTINGS {
+            out.push_str(&format!("  {:<30} {}\n", name, help));
+        }
+        out
+    }
+}
+
+/// The known name of a setting
+fn setting(name: &str) -> Option<&'static str> {
+    SETTINGS.iter().map(|(known, _)| *known).find(|known| *known == name)
+}
+
+/// How a setting is written where it was not known
+enum Spelling {
+    File,
+    Flag,
+}
+
+/// An error for an unknown setting, hinting at the closest known one
+fn unknown(what: &str, name: &str, spelling: Spelling) -> String {
+    let upper = name.replace(['-', '_'], "_").to_ascii_uppercase();
+    let closest = SETTINGS
+        .iter()
+        .map(|(known, _)| (edit_distance(&upper, known), *known))
+        .min()
+        .filter(|(distance, _)| *distance <= 3);
+    match closest {
+        Some((_, known)) => {
+            let known = known.to_ascii_lowercase();
+            match spelling {
+                Spelling::File => format!("unknown {}, did you mean {}?", what, known),
+                Spelling::Flag => format!("unknown {}, did you mean --{}?", what, known.replace('_', "-")),
+            }
+        }
+        None => format!("unknown {}, see --help for the settings", what),
+    }
+}
+
+/// The settings of a TOML file
+fn parse_file(text: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let name = setting(&key.to_ascii_uppercase())
+                .filter(|_| key == key.to_ascii_lowercase())
+                .ok_or_else(|| unknown(&format!("setting {}", key), &key, Spelling::File))?;
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Float(n) => n.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        toml::Value::String(s) => Ok(s),
+                        other => Err(format!("{} must list strings, not {}", key, other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+                _ => return Err(format!("{} must be a value, not a table or date", key)),
+            };
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Levenshtein distance, for hints at misspelled settings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != *cb)).min(row[j] + 1).min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(args: &[&str], env: &[(&str, &str)], file: &str) -> Result<Config, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let file = file.to_string();
+        match Config::from_layers(&args, |name| env.get(name).cloned(), |_| Ok(file.clone()))? {
+            Command::Run(config) => Ok(config),
+            Command::Help => panic!("asked for help"),
+        }
+    }
+
+    #[test]
+    fn test_layers_override_each_other() {
+        let file = r#"
+            port = 8080
+            scrollback_bytes = 65536
+            snapshot_on_join = true
+            join_allow = ["10.0.0.0/8", "fd00::/8"]
+        "#;
+        let config = load(&["--config", "relay.toml", "--port=9090"], &[("PORT", "7070"), ("SCROLLBACK_BYTES", "1024")], file).unwrap();
+        assert_eq!(config.get("PORT").as_deref(), Some("9090"));
+        assert_eq!(config.number::<usize>("SCROLLBACK_BYTES"), Ok(Some(1024)));
+        assert!(config.flag("SNAPSHOT_ON_JOIN"));
+        assert_eq!(config.get("JOIN_ALLOW").as_deref(), Some("10.0.0.0/8,fd00::/8"));
+        assert_eq!(config.get("CODE_FORMAT"), None);
+
+        // The file is only read when named
+        let config = load(&["--require-api-key", "--code-format", "words"], &[], "port = \"nope\"").unwrap();
+        assert!(config.flag("REQUIRE_API_KEY"));
+        assert_eq!(config.get("CODE_FORMAT").as_deref(), Some("words"));
+        assert_eq!(config.number::<u16>("PORT"), Ok(None));
+    }
+
+    #[test]
+    fn test_helpful_errors() {
+        let err = load(&["--config", "relay.toml"], &[], "scrollback_byte = 1").unwrap_err();
+        assert_eq!(err, "relay.toml: unknown setting scrollback_byte, did you mean scrollback_bytes?");
+        let err = load(&["--prot", "80"], &[], "").unwrap_err();
+        assert_eq!(err, "unknown flag --prot, did you mean --port?");
+        assert!(load(&["3000"], &[], "").is_err());
+        assert!(load(&["--config", "relay.toml"], &[], "[limits]\nport = 1").is_err());
+
+        let config = load(&["--config", "relay.toml"], &[], "port = \"eighty\"").unwrap();
+        assert_eq!(config.number::<u16>("PORT"), Err("port in relay.toml must be a valid number, not \"eighty\"".into()));
+        let config = load(&["--port", "-1"], &[], "").unwrap();
+        assert_eq!(config.number::<u16>("PORT"), Err("--port must be a valid number, not \"-1\"".into()));
+    }
+}
@@ -0,0 +1,268 @@
+//! Relays in different regions that let each other's browsers in.
+//!
+//! Off unless `FEDERATION_SECRET` is set. Peers are listed in
+//! `FEDERATION_PEERS`, comma-separated, as the URLs their web UI is served
+//! from (`https://relay-eu.example.com`), and share the secret.
+//!
+//! A relay that gets a browser for a code it does not hold, and that its
+//! cluster does not hold either, asks every peer whether it does
+//! (`GET /federation/sessions/{code}`). If one does, the relay opens a
+//! WebSocket to that peer's `/federation/link`, sends the browser's `auth`
+//! as a [`Join`] and from then on passes messages through both ways. The
+//! peer runs the browser like one of its own, so passwords, approvals,
+//! limits and scrollback stay where the session is. Users far from a Mac's
+//! relay connect to their nearest one, which only has to carry their
+//! traffic to the Mac's region over a connection that is already open.
+//!
+//! Both endpoints want the secret as bearer token. Peers only answer for
+//! sessions they (or their cluster) hold and never ask their own peers, so
+//! a browser is linked across one hop at most. Relays hand out codes on
+//! their own; when two peers hold the same code, the first in
+//! `FEDERATION_PEERS` wins.
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{future, SinkExt, StreamExt};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+use crate::config::Config;
+use crate::handlers::{Join, DEAD_PEER_TIMEOUT};
+
+/// How long a peer may take to say whether it holds a session, or to
+/// take up a link
+const PEER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Relays this one peers with
+#[derive(Clone)]
+pub struct Federation {
+    /// Base URLs, without a trailing slash
+    peers: Vec<String>,
+    secret: String,
+    client: reqwest::Client,
+}
+
+impl std::fmt::Debug for Federation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Federation")
+            .field("peers", &self.peers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Federation {
+    /// Peers from `FEDERATION_PEERS` and `FEDERATION_SECRET`; None without
+    /// a secret
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        Self::from_vars(|name| config.get(name))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let peers = var("FEDERATION_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(|peer| match peer.split_once("://") {
+                Some(("http" | "https", host)) if !host.is_empty() => Ok(peer.trim_end_matches('/').to_string()),
+                _ => Err(format!("{} is not a relay URL like https://relay.example.com", peer)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match var("FEDERATION_SECRET").filter(|secret| !secret.is_empty()) {
+            Some(secret) => Ok(Some(Self::new(peers, secret))),
+            None if peers.is_empty() => Ok(None),
+            None => Err("FEDERATION_PEERS needs a FEDERATION_SECRET".into()),
+        }
+    }
+
+    pub fn new(peers: Vec<String>, secret: String) -> Self {
+        Self {
+            peers,
+            secret,
+            client: reqwest::Client::builder()
+                .timeout(PEER_TIMEOUT)
+                .build()
+                .expect("HTTP client without custom TLS settings"),
+        }
+    }
+
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// Whether `given` is the shared secret
+    pub fn secret_matches(&self, given: &str) -> bool {
+        crate::state::constant_time_eq(self.secret.as_bytes(), given.as_bytes())
+    }
+
+    /// The peer holding a session code, asking all of them at once
+    pub async fn locate(&self, code: &str) -> Option<&str> {
+        let answers = future::join_all(self.peers.iter().map(|peer| async move {
+            let url = format!("{}/federation/sessions/{}", peer, code);
+            match self.client.get(&url).bearer_auth(&self.secret).send().await {
+                Ok(response) => response.status().is_success(),
+                Err(e) => {
+                    tracing::debug!(peer = %peer, "Cannot ask peer for a session: {}", e);
+                    false
+                }
+            }
+        }))
+        .await;
+        self.peers
+            .iter()
+            .zip(answers)
+            .find_map(|(peer, holds)| holds.then_some(peer.as_str()))
+    }
+
+    /// Connect a browser to a session held by `peer`. Returns when either
+    /// side closes.
+    pub async fn join_peer(&self, socket: WebSocket, peer: &str, join: Join) -> Result<(), tungstenite::Error> {
+        let mut request = link_url(peer).into_client_request()?;
+        let bearer = format!("Bearer {}", self.secret).parse().expect("secrets are header values");
+        request.headers_mut().insert(axum::http::header::AUTHORIZATION, bearer);
+        let (mut browser_tx, mut browser_rx) = socket.split();
+        let link = match tokio::time::timeout(PEER_TIMEOUT, tokio_tungstenite::connect_async(request)).await {
+            Ok(Ok((link, _))) => link,
+            Ok(Err(e)) => {
+                send_peer_failed(&mut browser_tx).await;
+                return Err(e);
+            }
+            Err(_) => {
+                tracing::warn!(peer = %peer, "Peer relay did not take up the link");
+                send_peer_failed(&mut browser_tx).await;
+                return Ok(());
+            }
+        };
+        let (mut peer_tx, mut peer_rx) = link.split();
+        let open = serde_json::to_string(&join).expect("joins serialize");
+        peer_tx.send(tungstenite::Message::Text(open.into())).await?;
+        tracing::info!(peer = %peer, code = %join.session_code, "Browser linked to a peer relay");
+
+        // The peer pings the browser through the link, so a silent link
+        // means the peer is gone
+        loop {
+            tokio::select! {
+                msg = tokio::time::timeout(DEAD_PEER_TIMEOUT, peer_rx.next()) => {
+                    let Ok(msg) = msg else {
+                        tracing::warn!(peer = %peer, "Peer relay went silent, closing link");
+                        send_peer_failed(&mut browser_tx).await;
+                        break;
+                    };
+                    let Some(msg) = msg.and_then(Result::ok).and_then(from_peer) else {
+                        let _ = browser_tx.send(Message::Close(None)).await;
+                        break;
+                    };
+                    let closing = matches!(msg, Message::Close(_));
+                    if browser_tx.send(msg).await.is_err() || closing {
+                        break;
+                    }
+                }
+                msg = browser_rx.next() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => Message::Close(None),
+                    };
+                    let closing = matches!(msg, Message::Close(_));
+                    peer_tx.send(to_peer(msg)).await?;
+                    if closing {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// WebSocket URL of a peer's link endpoint
+fn link_url(peer: &str) -> String {
+    let url = match peer.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some((_, rest)) => format!("ws://{}", rest),
+        None => peer.to_string(),
+    };
+    format!("{}/federation/link", url)
+}
+
+/// A browser's message as it goes to the peer
+fn to_peer(msg: Message) -> tungstenite::Message {
+    match msg {
+        Message::Text(text) => tungstenite::Message::Text(text.as_str().into()),
+        Message::Binary(data) => tungstenite::Message::Binary(data),
+        Message::Ping(data) => tungstenite::Message::Ping(data),
+        Message::Pong(data) => tungstenite::Message::Pong(data),
+        Message::Close(_) => tungstenite::Message::Close(None),
+    }
+}
+
+/// The peer's message as it goes to the browser; None for raw frames
+fn from_peer(msg: tungstenite::Message) -> Option<Message> {
+    Some(match msg {
+        tungstenite::Message::Text(text) => Message::Text(text.as_str().into()),
+        tungstenite::Message::Binary(data) => Message::Binary(data),
+        tungstenite::Message::Ping(data) => Message::Ping(data),
+        tungstenite::Message::Pong(data) => Message::Pong(data),
+        tungstenite::Message::Close(_) => Message::Close(None),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
+
+/// Tell a browser its session's relay cannot be reached
+async fn send_peer_failed(sender: &mut futures_util::stream::SplitSink<WebSocket, Message>) {
+    let msg = crate::protocol::ControlMessage::Error {
+        message: "The relay holding this session is not responding".into(),
+    };
+    let _ = sender
+        .send(Message::Text(serde_json::to_string(&msg).unwrap().into()))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_vars() {
+        assert!(Federation::from_vars(|_| None).unwrap().is_none());
+
+        let vars = |name: &str| match name {
+            "FEDERATION_PEERS" => Some("https://relay-eu.example.com/, http://10.0.0.2:3000".to_string()),
+            "FEDERATION_SECRET" => Some("s3cret".to_string()),
+            _ => None,
+        };
+        let federation = Federation::from_vars(vars).unwrap().unwrap();
+        assert_eq!(federation.peers(), ["https://relay-eu.example.com", "http://10.0.0.2:3000"]);
+        assert!(federation.secret_matches("s3cret"));
+        assert!(!federation.secret_matches("guess"));
+
+        // Accepting links without peers of its own is fine, peers without
+        // a secret are not
+        assert!(Federation::from_vars(|name| (name == "FEDERATION_SECRET").then(|| "s3cret".to_string()))
+            .unwrap()
+            .is_some_and(|federation| federation.peers().is_empty()));
+        assert!(Federation::from_vars(|name| (name == "FEDERATION_PEERS").then(|| "https://a.example".to_string())).is_err());
+        assert!(Federation::from_vars(|name| match name {
+            "FEDERATION_PEERS" => Some("relay-eu.example.com".to_string()),
+            _ => Some("s3cret".to_string()),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_link_url() {
+        assert_eq!(link_url("https://relay-eu.example.com"), "wss://relay-eu.example.com/federation/link");
+        assert_eq!(link_url("http://10.0.0.2:3000"), "ws://10.0.0.2:3000/federation/link");
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        for msg in [
+            Message::Text("{\"type\":\"list_sessions\"}".into()),
+            Message::Binary(vec![2, b's', b'1', b'x'].into()),
+            Message::Ping(vec![1].into()),
+            Message::Pong(Default::default()),
+            Message::Close(None),
+        ] {
+            assert_eq!(from_peer(to_peer(msg.clone())), Some(msg));
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Binary frame format for terminal traffic.
+//!
+//! Every binary frame between mac-client and browsers carries the terminal
+//! session it belongs to:
+//!
+//! ```text
+//! [1 byte session_id length][session_id bytes][payload]
+//! ```
+//!
+//! The session ID is the routing header: it is never encrypted, and it is
+//! the only part of a frame the relay reads. Payloads of passthrough
+//! sessions are end-to-end encrypted and stored and forwarded untouched.
+
+/// Terminal session ID bytes of a frame, or None if the frame is malformed.
+pub fn session_id(frame: &[u8]) -> Option<&[u8]> {
+    let id_len = *frame.first()? as usize;
+    frame.get(1..1 + id_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_id() {
+        let frame = [2, b'a', b'b', b'x', b'y', b'z'];
+        assert_eq!(session_id(&frame), Some(&b"ab"[..]));
+        assert_eq!(session_id(&[]), None);
+        assert_eq!(session_id(&[5, b'a']), None);
+    }
+}
@@ -0,0 +1,296 @@
+//! Admin API, for whoever holds `ADMIN_TOKEN`. `/debug/sessions` lists
+//! sessions with their codes and client IDs for it too, and only counts
+//! them for anyone else.
+//!
+//! `GET /api/admin/keys` lists the API keys Macs may register with,
+//! `POST /api/admin/keys` with `{"name": "..."}` makes one and answers with
+//! the key, which is shown this once, and `DELETE /api/admin/keys/{id}`
+//! revokes one.
+//!
+//! With accounts on, `GET /api/admin/accounts` lists them with their
+//! limits, tokens and usage, `POST /api/admin/accounts` with `{"name":
+//! "...", "max_sessions": ..., "quota_bytes": ..., "scrollback_bytes":
+//! ...}` makes one and answers with its first token, shown this once,
+//! `PUT /api/admin/accounts/{id}/limits` replaces its limits,
+//! `POST /api/admin/accounts/{id}/tokens` adds a token,
+//! `DELETE /api/admin/accounts/{id}/tokens/{token_id}` revokes one and
+//! `DELETE /api/admin/accounts/{id}` deletes the account.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::ws::WRONG_PASSWORD_DELAY;
+use crate::accounts::{AccountInfo, AccountLimits, Accounts, TokenInfo};
+use crate::apikeys::{ApiKeyInfo, ApiKeys};
+use crate::state::{AccountUsage, AppState};
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAccount {
+    pub name: String,
+    #[serde(flatten)]
+    pub limits: AccountLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedAccount {
+    #[serde(flatten)]
+    pub info: AccountInfo,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub info: TokenInfo,
+    pub token: String,
+}
+
+/// What an account's sessions use right now
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub sessions: usize,
+    pub browsers: usize,
+    pub bytes_total: u64,
+    pub bytes_in_period: u64,
+}
+
+impl From<AccountUsage> for Usage {
+    fn from(usage: AccountUsage) -> Self {
+        Self {
+            sessions: usage.sessions,
+            browsers: usage.browsers,
+            bytes_total: usage.bytes_total,
+            bytes_in_period: usage.bytes_in_period,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountWithUsage {
+    #[serde(flatten)]
+    pub info: AccountInfo,
+    pub usage: Usage,
+}
+
+/// Whether the request carries the admin token; the response to send if
+/// not
+async fn check_token(headers: &HeaderMap, state: &AppState) -> Result<(), Response> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.is_some_and(|token| state.admin_token_matches(token)) {
+        if bearer.is_some() {
+            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+        }
+        return Err((StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Admin token required").into_response());
+    }
+    Ok(())
+}
+
+/// Whether the request carries the admin token
+pub async fn is_admin(headers: &HeaderMap, state: &AppState) -> bool {
+    check_token(headers, state).await.is_ok()
+}
+
+/// The relay's API keys if the request carries the admin token; the
+/// response to send if not
+async fn check_admin<'a>(headers: &HeaderMap, state: &'a AppState) -> Result<&'a ApiKeys, Response> {
+    check_token(headers, state).await?;
+    state
+        .api_keys()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "This relay lets any Mac register").into_response())
+}
+
+/// The relay's accounts if the request carries the admin token; the
+/// response to send if not
+async fn check_accounts<'a>(headers: &HeaderMap, state: &'a AppState) -> Result<&'a Accounts, Response> {
+    check_token(headers, state).await?;
+    state
+        .accounts()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "This relay has no accounts").into_response())
+}
+
+/// The response to failing to write the accounts file
+fn cannot_save(e: std::io::Error) -> Response {
+    tracing::error!("Cannot save accounts: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Cannot save accounts").into_response()
+}
+
+pub async fn list_api_keys_handler(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    match check_admin(&headers, &state).await {
+        Ok(keys) => Json(keys.list()).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn create_api_key_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(new): Json<NewApiKey>,
+) -> Response {
+    let keys = match check_admin(&headers, &state).await {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    match keys.create(new.name) {
+        Ok((info, key)) => {
+            tracing::info!(event = "api_key_created", key_id = %info.id, name = %info.name, "API key created");
+            (StatusCode::CREATED, Json(CreatedApiKey { info, key })).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Cannot save API keys: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot save API keys").into_response()
+        }
+    }
+}
+
+pub async fn revoke_api_key_handler(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let keys = match check_admin(&headers, &state).await {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    match keys.revoke(&id) {
+        Ok(true) => {
+            tracing::info!(event = "api_key_revoked", key_id = %id, "API key revoked");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "No such API key").into_response(),
+        Err(e) => {
+            tracing::error!("Cannot save API keys: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot save API keys").into_response()
+        }
+    }
+}
+
+pub async fn list_accounts_handler(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    let accounts: Vec<AccountWithUsage> = accounts
+        .list()
+        .into_iter()
+        .map(|info| AccountWithUsage {
+            usage: state.account_usage(&info.id).into(),
+            info,
+        })
+        .collect();
+    Json(accounts).into_response()
+}
+
+pub async fn create_account_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(new): Json<NewAccount>,
+) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    match accounts.create(new.name, new.limits) {
+        Ok((info, token)) => {
+            tracing::info!(event = "account_created", account_id = %info.id, name = %info.name, "Account created");
+            (StatusCode::CREATED, Json(CreatedAccount { info, token })).into_response()
+        }
+        Err(e) => cannot_save(e),
+    }
+}
+
+pub async fn account_limits_handler(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(limits): Json<AccountLimits>,
+) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    match accounts.set_limits(&id, limits) {
+        Ok(Some(info)) => {
+            tracing::info!(event = "account_limits_changed", account_id = %id, limits = ?limits, "Account limits changed");
+            Json(info).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "No such account").into_response(),
+        Err(e) => cannot_save(e),
+    }
+}
+
+pub async fn delete_account_handler(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    match accounts.delete(&id) {
+        Ok(true) => {
+            tracing::info!(event = "account_deleted", account_id = %id, "Account deleted");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "No such account").into_response(),
+        Err(e) => cannot_save(e),
+    }
+}
+
+pub async fn create_account_token_handler(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    match accounts.add_token(&id) {
+        Ok(Some((info, token))) => {
+            tracing::info!(event = "account_token_created", account_id = %id, token_id = %info.id, "Account token created");
+            (StatusCode::CREATED, Json(CreatedToken { info, token })).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "No such account").into_response(),
+        Err(e) => cannot_save(e),
+    }
+}
+
+pub async fn revoke_account_token_handler(
+    Path((id, token_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let accounts = match check_accounts(&headers, &state).await {
+        Ok(accounts) => accounts,
+        Err(response) => return response,
+    };
+    match accounts.revoke_token(&id, &token_id) {
+        Ok(true) => {
+            tracing::info!(event = "account_token_revoked", account_id = %id, token_id = %token_id, "Account token revoked");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "No such account token").into_response(),
+        Err(e) => cannot_save(e),
+    }
+}
@@ -0,0 +1,250 @@
+//! HTTP API for browsers that want to look at a session before joining it.
+//!
+//! `GET /api/sessions/{code}/terminals` lists the terminal sessions the
+//! mac-client reported, so a browser can show its session picker before it
+//! opens the WebSocket. The same rules as joining apply: a password goes in
+//! an `Authorization: Bearer` header, attempts count against the IP's join
+//! limit and failures towards lockouts, and Macs that approve browsers one
+//! by one or paused sharing list nothing.
+//!
+//! `GET /api/sessions/{code}/terminals/{id}/transcript`, under the same
+//! rules, downloads the output the relay keeps of one terminal: as plain
+//! text without escape sequences, or with `?format=raw` as it was sent.
+//! End-to-end encrypted sessions have no transcript the relay could read.
+//!
+//! `GET /api/sessions/{code}/qr.png` draws the session's join URL as a QR
+//! code, for clients of the Mac that cannot draw one themselves. It wants
+//! the session's resume token as bearer token, which only the Mac has. The
+//! URL holds no end-to-end pairing string; the relay never sees it.
+//!
+//! `GET /api/affinity/{code}` names the relay instance a session code
+//! belongs to, for load balancers that route on codes (see
+//! [`crate::affinity`]).
+//!
+//! `GET /api/audit` exports the audit trail of browser input, for whoever
+//! holds `AUDIT_TOKEN`.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use super::ws::WRONG_PASSWORD_DELAY;
+use crate::audit::AuditQuery;
+use crate::protocol::{AuthFailure, TerminalInfo};
+use crate::qr;
+use crate::ratelimit::client_ip;
+use crate::session::normalize_code;
+use crate::state::AppState;
+use crate::transcript::strip_ansi;
+
+#[derive(Debug, Serialize)]
+pub struct AffinityAnswer {
+    pub code: String,
+    pub instance: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TerminalList {
+    pub terminals: Vec<TerminalInfo>,
+}
+
+/// How a transcript is sent
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptFormat {
+    /// Without escape sequences
+    #[default]
+    Text,
+    /// The bytes the terminal wrote
+    Raw,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TranscriptQuery {
+    #[serde(default)]
+    pub format: TranscriptFormat,
+}
+
+pub async fn affinity_handler(Path(code): Path<String>, State(state): State<AppState>) -> Response {
+    let Some(affinity) = state.affinity() else {
+        return (StatusCode::NOT_FOUND, "This relay routes no session codes").into_response();
+    };
+    let code = normalize_code(&code);
+    let instance = affinity.owner(&code).to_string();
+    Json(AffinityAnswer { code, instance }).into_response()
+}
+
+pub async fn terminals_handler(
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+) -> Response {
+    match check_access(&code, &headers, peer, &state).await {
+        Ok(code) => Json(TerminalList {
+            terminals: state.terminals(&code).unwrap_or_default(),
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn transcript_handler(
+    Path((code, id)): Path<(String, String)>,
+    Query(query): Query<TranscriptQuery>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+) -> Response {
+    let code = match check_access(&code, &headers, peer, &state).await {
+        Ok(code) => code,
+        Err(response) => return response,
+    };
+    if state.is_passthrough(&code) {
+        return (StatusCode::CONFLICT, "Output is end-to-end encrypted, the relay cannot read it").into_response();
+    }
+    if state.is_zero_retention(&code) {
+        return (StatusCode::CONFLICT, "This session keeps no output").into_response();
+    }
+    let Some(output) = state.terminal_output(&code, &id).await else {
+        return (StatusCode::NOT_FOUND, "No such terminal").into_response();
+    };
+    // Terminal IDs come from the Mac
+    let name: String = id.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect();
+    let (content_type, extension, body) = match query.format {
+        TranscriptFormat::Text => ("text/plain; charset=utf-8", "txt", strip_ansi(&output).into_bytes()),
+        TranscriptFormat::Raw => ("application/octet-stream", "log", output),
+    };
+    let disposition = format!("attachment; filename=\"{}-{}.{}\"", code, name, extension);
+    ([(header::CONTENT_TYPE, content_type.to_string()), (header::CONTENT_DISPOSITION, disposition)], body).into_response()
+}
+
+pub async fn qr_handler(Path(code): Path<String>, headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let code = normalize_code(&code);
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.is_some_and(|token| state.resume_token_matches(&code, token)) {
+        if bearer.is_some() {
+            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+        }
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "The Mac's resume token is required")
+            .into_response();
+    }
+    let Some(base) = public_url(&headers, &state) else {
+        return (StatusCode::BAD_REQUEST, "Cannot tell the relay's URL, set PUBLIC_URL").into_response();
+    };
+    match qr::png(&join_url(&base, &code)) {
+        Ok(image) => (
+            [(header::CONTENT_TYPE, "image/png"), (header::CACHE_CONTROL, "no-store")],
+            image,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Cannot draw QR code: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot draw QR code").into_response()
+        }
+    }
+}
+
+/// The web UI's login page with the session code, as the Mac links to it
+fn join_url(base: &str, code: &str) -> String {
+    format!("{}/login#code={}", base, code)
+}
+
+/// Where browsers reach the web UI: `PUBLIC_URL`, or the host the request
+/// was sent to
+fn public_url(headers: &HeaderMap, state: &AppState) -> Option<String> {
+    if let Some(url) = state.public_url() {
+        return Some(url.to_string());
+    }
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let scheme = match headers.get("x-forwarded-proto").and_then(|value| value.to_str().ok()) {
+        Some("https") => "https",
+        _ => "http",
+    };
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// The normalized session code if the request may look into the session,
+/// under the rules for joining it; the response to send if not
+async fn check_access(code: &str, headers: &HeaderMap, peer: SocketAddr, state: &AppState) -> Result<String, Response> {
+    let ip = client_ip(headers, peer, state.client_ip_header());
+    if !state.join_permitted(ip) {
+        state.metrics().join_failed(AuthFailure::IpNotAllowed);
+        return Err((StatusCode::FORBIDDEN, "This relay does not accept browsers from your network").into_response());
+    }
+    if let Err(ban) = state.check_join_rate(ip) {
+        let retry = ban.as_secs().max(1).to_string();
+        return Err((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry)], "Too many attempts").into_response());
+    }
+    if let Some(left) = state.join_lockout(ip) {
+        let retry = left.as_secs().max(1).to_string();
+        return Err((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry)], "Too many failed attempts").into_response());
+    }
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let code = normalize_code(code);
+    if !state.validate_session_code(&code) {
+        state.metrics().join_failed(AuthFailure::InvalidCode);
+        tokio::time::sleep(state.join_failed(Some(ip), None)).await;
+        return Err((StatusCode::NOT_FOUND, "Invalid session code").into_response());
+    }
+    let password_lockout = state.requires_password(&code).then(|| state.password_lockout(&code)).flatten();
+    if let Some(left) = password_lockout {
+        let retry = left.as_secs().max(1).to_string();
+        return Err((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry)], "Too many wrong passwords").into_response());
+    }
+    if state.requires_password(&code) && !bearer.is_some_and(|given| state.password_matches(&code, given)) {
+        let kind = if bearer.is_some() { AuthFailure::WrongPassword } else { AuthFailure::PasswordRequired };
+        state.metrics().join_failed(kind);
+        if kind == AuthFailure::WrongPassword {
+            tokio::time::sleep(state.join_failed(Some(ip), Some(&code)).max(WRONG_PASSWORD_DELAY)).await;
+        }
+        return Err((StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "This Mac requires a password")
+            .into_response());
+    }
+    if state.is_sharing_paused(&code) {
+        return Err((StatusCode::FORBIDDEN, "Sharing is paused on the Mac").into_response());
+    }
+    if state.is_approval_required(&code) {
+        return Err((StatusCode::FORBIDDEN, "The Mac approves browsers as they join").into_response());
+    }
+    Ok(code)
+}
+
+pub async fn audit_handler(
+    Query(query): Query<AuditQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(audit) = state.audit() else {
+        return (StatusCode::NOT_FOUND, "Input is not audited on this relay").into_response();
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.is_some_and(|token| audit.token_matches(token)) {
+        if bearer.is_some() {
+            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+        }
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Audit token required").into_response();
+    }
+    match audit.export(&query).await {
+        Ok(lines) => ([(header::CONTENT_TYPE, "application/x-ndjson")], lines).into_response(),
+        Err(e) => {
+            tracing::error!("Cannot read the audit log: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Cannot read the audit log").into_response()
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Endpoints peer relays use to reach the sessions held here.
+//!
+//! `GET /federation/sessions/{code}` answers 204 if the session is held by
+//! this relay or its cluster, 404 if not. `GET /federation/link` upgrades
+//! to a WebSocket whose first message is a browser's [`Join`]; after that
+//! it carries that browser's messages. Both want `FEDERATION_SECRET` as
+//! bearer token.
+
+use axum::{
+    extract::{ws::Message, ws::WebSocket, ConnectInfo, Path, State, WebSocketUpgrade},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::Instrument;
+
+use super::ws::{handle_browser, remote_owner, Join, WRONG_PASSWORD_DELAY};
+use crate::cluster::LinkOpen;
+use crate::session::normalize_code;
+use crate::state::AppState;
+
+/// How long a peer may take to send the browser's join
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the request carries the federation secret; the response to
+/// send if not
+async fn check_peer(headers: &HeaderMap, state: &AppState) -> Result<(), Response> {
+    let Some(federation) = state.federation() else {
+        return Err((StatusCode::NOT_FOUND, "This relay has no peers").into_response());
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.is_some_and(|secret| federation.secret_matches(secret)) {
+        if bearer.is_some() {
+            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+        }
+        return Err((StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Federation secret required")
+            .into_response());
+    }
+    Ok(())
+}
+
+pub async fn federation_session_handler(
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    if let Err(response) = check_peer(&headers, &state).await {
+        return response;
+    }
+    let code = normalize_code(&code);
+    if state.validate_session_code(&code) || remote_owner(&state, &code).await.is_some() {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "Invalid session code").into_response()
+    }
+}
+
+pub async fn federation_link_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+) -> Response {
+    if let Err(response) = check_peer(&headers, &state).await {
+        return response;
+    }
+    let span = tracing::info_span!(
+        "connection",
+        id = %nanoid::nanoid!(10),
+        peer = %peer,
+        transport = "federation",
+        code = tracing::field::Empty,
+        browser_id = tracing::field::Empty
+    );
+    ws.on_upgrade(move |socket| handle_link(socket, state).instrument(span))
+        .into_response()
+}
+
+/// Run a browser linked from a peer as if it were connected here
+async fn handle_link(mut socket: WebSocket, state: AppState) {
+    let join = match tokio::time::timeout(JOIN_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<Join>(&text),
+        _ => {
+            tracing::debug!("Peer link closed before the browser's join");
+            return;
+        }
+    };
+    let mut join = match join {
+        Ok(join) => join,
+        Err(e) => {
+            tracing::warn!("Invalid join from peer relay: {}", e);
+            return;
+        }
+    };
+    join.session_code = normalize_code(&join.session_code);
+    tracing::info!(code = %join.session_code, "Browser linked from a peer relay");
+
+    // The session may be on another relay of this one's cluster
+    if let Some(owner) = remote_owner(&state, &join.session_code).await {
+        let open = LinkOpen {
+            link: String::new(),
+            join,
+        };
+        let cluster = state.cluster().expect("owner found through the cluster");
+        if let Err(e) = cluster.join_remote(socket, &owner, open).await {
+            tracing::warn!(owner = %owner, "Browser link to another relay failed: {}", e);
+        }
+        return;
+    }
+    let (sender, receiver) = socket.split();
+    handle_browser(sender, receiver, state, join).await;
+}
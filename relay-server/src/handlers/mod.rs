@@ -1,2 +1,17 @@
+mod admin;
+mod api;
+mod federation;
+mod playback;
+mod sse;
 mod ws;
+pub use admin::{
+    account_limits_handler, create_account_handler, create_account_token_handler, create_api_key_handler,
+    delete_account_handler, list_accounts_handler, list_api_keys_handler, revoke_account_token_handler,
+    is_admin, revoke_api_key_handler,
+};
+pub use api::{affinity_handler, audit_handler, qr_handler, terminals_handler, transcript_handler};
+pub use federation::{federation_link_handler, federation_session_handler};
+pub use playback::playback_handler;
+pub use sse::{events_handler, input_handler};
 pub use ws::ws_handler;
+pub(crate) use ws::{handle_browser, send_auth_failed, BrowserSink, Join, DEAD_PEER_TIMEOUT};
@@ -0,0 +1,110 @@
+//! Playback of session recordings over `/playback/{id}`.
+//!
+//! The recording ID is the only credential: browsers get it from the
+//! `recording_list` of a session they joined. Frames are sent as binary
+//! frames in the live format, spaced as they were recorded divided by the
+//! speed (`?speed=2`, or a `playback_speed` message while playing). Long
+//! pauses are shortened, then `playback_ended` is sent and the socket closed.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::protocol::ControlMessage;
+use crate::state::AppState;
+
+/// Slowest and fastest playback
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 64.0;
+
+/// Longest wait between two frames, whatever the speed: idle time while
+/// nobody typed is skipped
+const MAX_PAUSE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+pub struct PlaybackParams {
+    speed: Option<f64>,
+}
+
+pub async fn playback_handler(
+    ws: WebSocketUpgrade,
+    Path(id): Path<String>,
+    Query(params): Query<PlaybackParams>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    if !state.origin_permitted(&headers) {
+        return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+    }
+    let Some(frames) = state.recording_frames(&id) else {
+        return (StatusCode::NOT_FOUND, "No such recording").into_response();
+    };
+    let speed = clamp_speed(params.speed.unwrap_or(1.0));
+    tracing::info!(recording_id = %id, frames = frames.len(), speed, "Playing back recording");
+    ws.on_upgrade(move |socket| play(socket, frames, speed))
+}
+
+fn clamp_speed(speed: f64) -> f64 {
+    if speed.is_finite() {
+        speed.clamp(MIN_SPEED, MAX_SPEED)
+    } else {
+        1.0
+    }
+}
+
+/// Time to wait before a frame recorded `gap` after the previous one
+fn pause(gap: Duration, speed: f64) -> Duration {
+    gap.div_f64(speed).min(MAX_PAUSE)
+}
+
+async fn play(socket: WebSocket, frames: Vec<(Duration, Vec<u8>)>, mut speed: f64) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut previous = Duration::ZERO;
+    for (at, data) in frames {
+        let wait = tokio::time::sleep(pause(at.saturating_sub(previous), speed));
+        tokio::pin!(wait);
+        // Speed changes apply to the next frame
+        loop {
+            tokio::select! {
+                _ = &mut wait => break,
+                msg = receiver.next() => match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Ok(ControlMessage::PlaybackSpeed { speed: new }) = serde_json::from_str(&text) {
+                            speed = clamp_speed(new);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        previous = at;
+        if sender.send(Message::Binary(data.into())).await.is_err() {
+            return;
+        }
+    }
+    let ended = ControlMessage::PlaybackEnded.to_text();
+    let _ = sender.send(Message::Text(ended.into())).await;
+    let _ = sender.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause() {
+        assert_eq!(pause(Duration::from_millis(500), 2.0), Duration::from_millis(250));
+        assert_eq!(pause(Duration::from_secs(600), 1.0), MAX_PAUSE);
+        assert_eq!(clamp_speed(1000.0), MAX_SPEED);
+        assert_eq!(clamp_speed(f64::NAN), 1.0);
+    }
+}
@@ -0,0 +1,288 @@
+//! Server-Sent Events for browsers behind proxies that block WebSockets.
+//!
+//! `GET /api/sessions/{code}/events` joins a session like an `auth` over
+//! `/ws` does, with the password as a bearer token (or `?password=` where
+//! the client cannot set headers, like `EventSource`) and `role`, `token`,
+//! `browser_key`, `version`, `resume_token` and `replay_bytes` as query
+//! parameters. It streams what a WebSocket browser would get:
+//!
+//! ```text
+//! event: stream     first, the ID input is POSTed to
+//! event: control    a control message, JSON
+//! event: frame      a binary frame, base64
+//! ```
+//!
+//! Input goes to `POST /api/streams/{id}/input`: a JSON body is a control
+//! message, any other body a binary frame. The stream runs through the same
+//! code as a WebSocket browser, so passwords, approvals, roles, limits and
+//! scrollback all apply. Pings become SSE comments, and a browser counts as
+//! answering them for as long as its response can be written.
+
+use axum::{
+    body::Bytes,
+    extract::{ws::Message, ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+use tracing::Instrument;
+
+use super::ws::{handle_browser, Join};
+use crate::protocol::{AuthFailure, Role};
+use crate::ratelimit::client_ip;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct EventsParams {
+    password: Option<String>,
+    role: Option<Role>,
+    token: Option<String>,
+    browser_key: Option<String>,
+    version: Option<u32>,
+    resume_token: Option<String>,
+    nickname: Option<String>,
+    replay_bytes: Option<u64>,
+}
+
+pub async fn events_handler(
+    Path(code): Path<String>,
+    Query(params): Query<EventsParams>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+) -> Response {
+    let ip = client_ip(&headers, peer, state.client_ip_header());
+    if !state.origin_permitted(&headers) {
+        return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+    }
+    if !state.join_permitted(ip) {
+        state.metrics().join_failed(AuthFailure::IpNotAllowed);
+        return (StatusCode::FORBIDDEN, "This relay does not accept browsers from your network").into_response();
+    }
+    if let Err(ban) = state.check_join_rate(ip) {
+        let retry = ban.as_secs().max(1).to_string();
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry)], "Too many attempts").into_response();
+    }
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let join = Join {
+        session_code: code,
+        browser_key: params.browser_key,
+        password: bearer.or(params.password),
+        role: params.role,
+        token: params.token,
+        version: params.version,
+        compression: None,
+        resume_token: params.resume_token,
+        nickname: params.nickname,
+        replay_bytes: params.replay_bytes,
+        ip: Some(ip),
+        user_agent,
+    };
+    let span = tracing::info_span!(
+        "connection",
+        id = %nanoid::nanoid!(10),
+        ip = %ip,
+        transport = "sse",
+        code = tracing::field::Empty,
+        browser_id = tracing::field::Empty
+    );
+    Sse::new(open_stream(state, join, span)).into_response()
+}
+
+/// Start a browser on the event stream it reads
+fn open_stream(state: AppState, join: Join, span: tracing::Span) -> impl Stream<Item = Result<Event, Infallible>> {
+    let id = nanoid::nanoid!();
+    let (out_tx, out_rx) = mpsc::channel::<Message>(64);
+    let (in_tx, in_rx) = mpsc::channel::<Message>(64);
+    state.add_event_stream(id.clone(), in_tx.clone());
+
+    let sender = PollSender::new(out_tx).sink_map_err(axum::Error::new);
+    let receiver = ReceiverStream::new(in_rx).map(Ok);
+    tokio::spawn(handle_browser(sender, receiver, state.clone(), join).instrument(span));
+
+    let first = Event::default().event("stream").data(&id);
+    let outgoing = Outgoing {
+        messages: out_rx,
+        input: in_tx,
+        _registration: Registration { state, id },
+    };
+    stream::once(async { Ok(first) }).chain(stream::unfold(outgoing, |mut out| async move {
+        loop {
+            let event = match out.messages.recv().await? {
+                Message::Text(text) => Event::default().event("control").data(text.as_str()),
+                Message::Binary(data) => Event::default().event("frame").data(STANDARD.encode(&data)),
+                Message::Ping(_) => {
+                    let _ = out.input.try_send(Message::Pong(Bytes::new()));
+                    Event::default().comment("ping")
+                }
+                Message::Pong(_) => continue,
+                Message::Close(_) => return None,
+            };
+            return Some((Ok(event), out));
+        }
+    }))
+}
+
+/// What a browser's event stream reads from
+struct Outgoing {
+    messages: mpsc::Receiver<Message>,
+    /// For answering pings on the browser's behalf
+    input: mpsc::Sender<Message>,
+    _registration: Registration,
+}
+
+/// Takes a stream's input off the state once its response is gone, which
+/// ends its browser
+struct Registration {
+    state: AppState,
+    id: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.state.remove_event_stream(&self.id);
+    }
+}
+
+pub async fn input_handler(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> StatusCode {
+    if !state.origin_permitted(&headers) {
+        return StatusCode::FORBIDDEN;
+    }
+    let Some(input) = state.event_stream(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let msg = if json {
+        match String::from_utf8(body.to_vec()) {
+            Ok(text) => Message::Text(text.into()),
+            Err(_) => return StatusCode::BAD_REQUEST,
+        }
+    } else {
+        Message::Binary(body)
+    };
+    match input.send(msg).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::NOT_FOUND,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::MacMessage;
+
+    /// The events of a stream as sent, one string each
+    fn open_wire(state: &AppState, code: &str) -> impl Stream<Item = String> + Unpin {
+        let join = Join {
+            session_code: code.into(),
+            ..Join::default()
+        };
+        wire(state, join)
+    }
+
+    fn wire(state: &AppState, join: Join) -> impl Stream<Item = String> + Unpin {
+        Sse::new(open_stream(state.clone(), join, tracing::Span::none()))
+            .into_response()
+            .into_body()
+            .into_data_stream()
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+    }
+
+    /// The stream ID in the first event
+    fn stream_id(first: &str) -> String {
+        first.strip_prefix("event: stream\ndata: ").unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_stream_joins_and_forwards_input() {
+        let state = AppState::new();
+        let (mac_tx, mut mac_rx) = mpsc::channel(8);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        let mut events = open_wire(&state, &code);
+
+        let id = stream_id(&events.next().await.unwrap());
+        let success = events.next().await.unwrap();
+        assert!(success.starts_with("event: control\ndata: {\"type\":\"auth_success\""));
+
+        let mut headers = HeaderMap::new();
+        let status = input_handler(Path(id.clone()), headers.clone(), State(state.clone()), Bytes::from_static(b"\x02s1ls")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+        let body = Bytes::from_static(br#"{"type":"list_sessions"}"#);
+        assert_eq!(input_handler(Path(id.clone()), headers, State(state.clone()), body).await, StatusCode::NO_CONTENT);
+
+        // BrowserConnected and presence first, then the input in order
+        let mut seen = Vec::new();
+        while seen.len() < 4 {
+            seen.push(mac_rx.recv().await.unwrap());
+        }
+        assert!(matches!(&seen[0], MacMessage::Text(text) if text.contains("browser_connected")));
+        assert!(matches!(&seen[1], MacMessage::Text(text) if text.contains("presence")));
+        assert!(matches!(&seen[2], MacMessage::Binary(data) if data == b"\x02s1ls"));
+        assert!(matches!(&seen[3], MacMessage::Text(text) if text.contains("list_sessions")));
+
+        // Closing the response ends the browser
+        drop(events);
+        assert!(state.event_stream(&id).is_none());
+        let status = input_handler(Path(id), HeaderMap::new(), State(state), Bytes::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_after_auth_failure() {
+        let state = AppState::new();
+        let events: Vec<String> = open_wire(&state, "ZZZZZZ").collect().await;
+        assert_eq!(events.len(), 2);
+        assert!(events[1].contains("invalid_code"));
+        assert!(state.event_stream(&stream_id(&events[0])).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_replays_the_end_of_the_scrollback() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(8);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        for data in [b"\x01aone", b"\x01atwo", b"\x01asix"] {
+            state.broadcast_to_browsers(&code, Bytes::from_static(data)).await;
+        }
+        let join = Join {
+            session_code: code.clone(),
+            replay_bytes: Some(10),
+            ..Join::default()
+        };
+        let events: Vec<String> = wire(&state, join).take(6).collect().await;
+        assert!(events[1].contains("auth_success"));
+        assert_eq!(events[2], "event: control\ndata: {\"type\":\"replay_start\",\"bytes\":10}\n\n");
+        assert_eq!(events[3], format!("event: frame\ndata: {}\n\n", STANDARD.encode(b"\x01atwo")));
+        assert_eq!(events[4], format!("event: frame\ndata: {}\n\n", STANDARD.encode(b"\x01asix")));
+        assert!(events[5].contains("replay_end"));
+    }
+}
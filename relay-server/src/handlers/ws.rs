@@ -1,24 +1,144 @@
 use axum::{
+    body::Bytes,
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Query, State,
     },
-    response::IntoResponse,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tracing::Instrument;
 
-use crate::protocol::ControlMessage;
-use crate::state::{AppState, BrowserMessage, MacMessage};
+use crate::affinity::{requested_code, session_cookie, INSTANCE_HEADER};
+use crate::cluster::LinkOpen;
+use crate::compression::Deflater;
+use crate::latency::unix_millis;
+use crate::protocol::{
+    negotiate_version, AuthFailure, ControlMessage, FrameCompression, Role, Viewer, MIN_PROTOCOL_VERSION,
+};
+use crate::session::normalize_code;
+use crate::ratelimit::client_ip;
+use crate::quota::Verdict;
+use crate::state::{
+    unix_now, AppState, BrowserMessage, DepartedBrowser, Expiry, JoinRefusal, JoinScope, MacMessage, SlowBrowser,
+};
+use crate::webhooks::WebhookEvent;
+
+/// Messages to a browser: its WebSocket, or a link through another relay
+pub trait BrowserSink: Sink<Message, Error = axum::Error> + Unpin + Send + 'static {}
+impl<T: Sink<Message, Error = axum::Error> + Unpin + Send + 'static> BrowserSink for T {}
+
+/// Messages from a browser, like [`BrowserSink`]
+pub trait BrowserStream: Stream<Item = Result<Message, axum::Error>> + Unpin + Send + 'static {}
+impl<T: Stream<Item = Result<Message, axum::Error>> + Unpin + Send + 'static> BrowserStream for T {}
+
+/// How long a browser may wait for the Mac to approve it
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Delay before answering a wrong password, to slow down guessing
+pub(crate) const WRONG_PASSWORD_DELAY: Duration = Duration::from_secs(1);
+
+/// Scrollback sent between two pauses of a paced replay
+const REPLAY_CHUNK: usize = 16 * 1024;
+
+/// Time between pings to mac-clients and browsers
+pub const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// A peer that sends nothing for this long, not even a pong, is gone
+/// (a sleeping laptop or a dropped mobile connection) and is dropped
+pub const DEAD_PEER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ticks every [`PING_INTERVAL`], starting one interval from now
+fn ping_timer() -> tokio::time::Interval {
+    let start = tokio::time::Instant::now() + PING_INTERVAL;
+    let mut timer = tokio::time::interval_at(start, PING_INTERVAL);
+    timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    timer
+}
+
+/// What the WebSocket URL may carry
+#[derive(Debug, Deserialize, Default)]
+pub struct WsQuery {
+    /// The session code, for load balancers routing on it
+    pub code: Option<String>,
+}
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    Query(query): Query<WsQuery>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+) -> Response {
+    let ip = client_ip(&headers, peer, state.client_ip_header());
+    if !state.origin_permitted(&headers) {
+        tracing::info!(ip = %ip, origin = ?headers.get(header::ORIGIN), "WebSocket refused - origin not allowed");
+        return (StatusCode::FORBIDDEN, "Origin not allowed").into_response();
+    }
+    // A code that hashes to another relay reached this one past the load
+    // balancer; say where it belongs unless a session here has it anyway
+    let code = requested_code(&headers, query.code.as_deref());
+    if let (Some(affinity), Some(code), None) = (state.affinity(), &code, state.cluster()) {
+        if !affinity.owns(code) && !state.validate_session_code(code) {
+            let owner = affinity.owner(code).to_string();
+            tracing::info!(ip = %ip, code = %code, owner = %owner, "WebSocket refused - session code belongs to another relay");
+            return (
+                StatusCode::MISDIRECTED_REQUEST,
+                [(INSTANCE_HEADER.clone(), owner)],
+                "This session code belongs to another relay",
+            )
+                .into_response();
+        }
+    }
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    // Scripts can send the password as a bearer token instead of in `auth`
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    // Every log line of the connection carries its ID, and the session code
+    // and browser ID once they are known
+    let span = tracing::info_span!(
+        "connection",
+        id = %nanoid::nanoid!(10),
+        ip = %ip,
+        transport = "websocket",
+        code = tracing::field::Empty,
+        browser_id = tracing::field::Empty
+    );
+    let affinity = state.affinity().map(|affinity| affinity.instance().to_string());
+    let mut response = ws
+        .on_upgrade(move |socket| handle_socket(socket, state, ip, user_agent, bearer).instrument(span))
+        .into_response();
+    // Let balancers that stick on cookies or headers keep the connection's
+    // next handshakes on this relay
+    if let Some(cookie) = code.and_then(|code| session_cookie(&code).parse().ok()) {
+        response.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    if let Some(instance) = affinity.and_then(|instance| instance.parse().ok()) {
+        response.headers_mut().insert(INSTANCE_HEADER.clone(), instance);
+    }
+    response
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    ip: IpAddr,
+    user_agent: Option<String>,
+    bearer: Option<String>,
+) {
     let (mut sender, mut receiver) = socket.split();
 
     // Wait for first message to determine client type
@@ -29,13 +149,13 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
 
     // Parse first message as JSON to determine client type
     let Message::Text(text) = first_msg else {
-        tracing::warn!("First message must be JSON Text, got binary");
+        tracing::warn!(event = "error", "First message must be JSON Text, got binary");
         let _ = sender
             .send(Message::Text(
-                serde_json::to_string(&ControlMessage::Error {
+                ControlMessage::Error {
                     message: "First message must be JSON".into(),
-                })
-                .unwrap()
+                }
+                .to_text()
                 .into(),
             ))
             .await;
@@ -43,13 +163,13 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     };
 
     let Ok(control_msg) = serde_json::from_str::<ControlMessage>(&text) else {
-        tracing::warn!("Invalid JSON in first message");
+        tracing::warn!(event = "error", "Invalid JSON in first message");
         let _ = sender
             .send(Message::Text(
-                serde_json::to_string(&ControlMessage::Error {
+                ControlMessage::Error {
                     message: "Invalid JSON".into(),
-                })
-                .unwrap()
+                }
+                .to_text()
                 .into(),
             ))
             .await;
@@ -57,20 +177,168 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     };
 
     match control_msg {
-        ControlMessage::Register { client_id } => {
-            handle_mac_client(sender, receiver, state, client_id).await;
+        ControlMessage::Register { client_id, name, password, passthrough, version, resume_token, scrollback_bytes, api_key, zero_retention } => {
+            let Some(version) = negotiate_version(version) else {
+                tracing::info!(event = "register_failed", client_id = %client_id, version = ?version, "Mac-client registration refused - protocol too old");
+                let _ = sender
+                    .send(Message::Text(
+                        ControlMessage::Error {
+                            message: format!(
+                                "This relay needs protocol version {} or newer, please update the app",
+                                MIN_PROTOCOL_VERSION
+                            ),
+                        }
+                        .to_text()
+                        .into(),
+                    ))
+                    .await;
+                return;
+            };
+            if !state.register_permitted(ip) {
+                tracing::info!(event = "register_failed", ip = %ip, "Mac-client registration refused - IP not allowed");
+                let _ = sender
+                    .send(Message::Text(
+                        ControlMessage::Error {
+                            message: "This relay does not accept Macs from your network".into(),
+                        }
+                        .to_text()
+                        .into(),
+                    ))
+                    .await;
+                return;
+            }
+            if let Err(ban) = state.check_register_rate(ip) {
+                tracing::info!(event = "register_failed", ip = %ip, "Mac-client registration refused - rate limited");
+                let _ = sender
+                    .send(Message::Text(
+                        ControlMessage::Error {
+                            message: format!("Too many registrations, try again in {}s", ban.as_secs().max(1)),
+                        }
+                        .to_text()
+                        .into(),
+                    ))
+                    .await;
+                return;
+            }
+            // A relay that wants API keys hosts no sessions without one
+            let api_key_id = match state.api_keys() {
+                Some(keys) => match api_key.as_deref().and_then(|key| keys.check(key)) {
+                    Some(id) => Some(id),
+                    None => {
+                        if api_key.is_some() {
+                            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+                        }
+                        tracing::info!(event = "register_failed", ip = %ip, client_id = %client_id, "Mac-client registration refused - no valid API key");
+                        let message = match api_key {
+                            Some(_) => "This API key is not valid on this relay",
+                            None => "This relay needs an API key to host sessions",
+                        };
+                        let _ = sender
+                            .send(Message::Text(
+                                ControlMessage::Error { message: message.into() }.to_text().into(),
+                            ))
+                            .await;
+                        return;
+                    }
+                },
+                None => None,
+            };
+            // On a relay with accounts, the key is an account's token
+            let account_id = match state.accounts() {
+                Some(accounts) => match api_key.as_deref().and_then(|token| accounts.check(token)) {
+                    Some(id) => Some(id),
+                    None => {
+                        if api_key.is_some() {
+                            tokio::time::sleep(WRONG_PASSWORD_DELAY).await;
+                        }
+                        tracing::info!(event = "register_failed", ip = %ip, client_id = %client_id, "Mac-client registration refused - no valid account token");
+                        let message = match api_key {
+                            Some(_) => "This account token is not valid on this relay",
+                            None => "This relay needs an account token to host sessions",
+                        };
+                        let _ = sender
+                            .send(Message::Text(
+                                ControlMessage::Error { message: message.into() }.to_text().into(),
+                            ))
+                            .await;
+                        return;
+                    }
+                },
+                None => None,
+            };
+            let registration = Registration {
+                client_id,
+                name,
+                password,
+                passthrough,
+                version,
+                resume_token,
+                scrollback_bytes,
+                zero_retention,
+                api_key_id,
+                account_id,
+            };
+            handle_mac_client(sender, receiver, state, registration).await;
         }
-        ControlMessage::Auth { session_code } => {
-            handle_browser(sender, receiver, state, session_code).await;
+        ControlMessage::Auth { session_code, browser_key, password, role, token, version, compression, resume_token, nickname, replay_bytes } => {
+            if !state.join_permitted(ip) {
+                let reason = "This relay does not accept browsers from your network";
+                send_auth_failed(&mut sender, &state, AuthFailure::IpNotAllowed, reason).await;
+                tracing::info!(event = "join_failed", ip = %ip, "Browser auth refused - IP not allowed");
+                return;
+            }
+            if let Err(ban) = state.check_join_rate(ip) {
+                let reason = format!("Too many attempts, try again in {}s", ban.as_secs().max(1));
+                send_auth_failed(&mut sender, &state, AuthFailure::RateLimited, &reason).await;
+                tracing::info!(event = "join_failed", ip = %ip, "Browser auth refused - rate limited");
+                return;
+            }
+            let join = Join {
+                session_code: normalize_code(&session_code),
+                browser_key,
+                password: password.or(bearer),
+                role,
+                token,
+                version,
+                compression,
+                resume_token,
+                nickname,
+                replay_bytes,
+                ip: Some(ip),
+                user_agent,
+            };
+            // A session held by another relay is joined through it
+            if let Some(owner) = remote_owner(&state, &join.session_code).await {
+                let socket = sender.reunite(receiver).expect("halves of one socket");
+                let open = LinkOpen {
+                    link: String::new(),
+                    join,
+                };
+                let cluster = state.cluster().expect("owner found through the cluster");
+                if let Err(e) = cluster.join_remote(socket, &owner, open).await {
+                    tracing::warn!(owner = %owner, "Browser link to another relay failed: {}", e);
+                }
+                return;
+            }
+            // And one held in another region through that region's relay
+            if let Some(peer) = federated_owner(&state, &join.session_code).await {
+                let socket = sender.reunite(receiver).expect("halves of one socket");
+                let federation = state.federation().expect("peer found through the federation");
+                if let Err(e) = federation.join_peer(socket, &peer, join).await {
+                    tracing::warn!(peer = %peer, "Browser link to a peer relay failed: {}", e);
+                }
+                return;
+            }
+            handle_browser(sender, receiver, state, join).await;
         }
         _ => {
-            tracing::warn!("Unexpected first message type");
+            tracing::warn!(event = "error", "Unexpected first message type");
             let _ = sender
                 .send(Message::Text(
-                    serde_json::to_string(&ControlMessage::Error {
+                    ControlMessage::Error {
                         message: "First message must be Register or Auth".into(),
-                    })
-                    .unwrap()
+                    }
+                    .to_text()
                     .into(),
                 ))
                 .await;
@@ -78,24 +346,109 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     }
 }
 
+/// The other relay holding a session code this relay does not have
+pub(super) async fn remote_owner(state: &AppState, session_code: &str) -> Option<String> {
+    let code = normalize_code(session_code);
+    let cluster = state.cluster()?;
+    if state.validate_session_code(&code) {
+        return None;
+    }
+    match cluster.owner(&code).await {
+        Ok(owner) => owner.filter(|owner| owner != cluster.instance_id()),
+        Err(e) => {
+            tracing::warn!(code = %code, "Cannot look up session code: {}", e);
+            None
+        }
+    }
+}
+
+/// The peer relay holding a session code this relay does not have, asked
+/// once the cluster did not have it either
+async fn federated_owner(state: &AppState, session_code: &str) -> Option<String> {
+    let code = normalize_code(session_code);
+    let federation = state.federation()?;
+    if federation.peers().is_empty() || state.validate_session_code(&code) {
+        return None;
+    }
+    federation.locate(&code).await.map(str::to_string)
+}
+
+/// What a mac-client sent in its `register`
+struct Registration {
+    client_id: String,
+    name: Option<String>,
+    password: Option<String>,
+    passthrough: bool,
+    /// The negotiated protocol version
+    version: u32,
+    /// Token of a session to reclaim
+    resume_token: Option<String>,
+    /// Scrollback to keep per terminal, if less than the relay's cap
+    scrollback_bytes: Option<u64>,
+    /// Keep nothing the session relays
+    zero_retention: bool,
+    /// The API key the mac-client registered with, if it needed one
+    api_key_id: Option<String>,
+    /// The account the mac-client registered with, if it needed one
+    account_id: Option<String>,
+}
+
 /// Handle a mac-client connection
 async fn handle_mac_client(
     mut sender: futures_util::stream::SplitSink<WebSocket, Message>,
     mut receiver: futures_util::stream::SplitStream<WebSocket>,
     state: AppState,
-    client_id: String,
+    registration: Registration,
 ) {
+    let Registration {
+        client_id,
+        name: client_name,
+        password,
+        passthrough,
+        version,
+        resume_token,
+        scrollback_bytes,
+        zero_retention,
+        api_key_id,
+        account_id,
+    } = registration;
     // Create channel for receiving messages to send to mac-client
     let (mac_tx, mut mac_rx) = mpsc::channel::<MacMessage>(1000);
 
-    // Register and get session code
-    let code = state.register_mac_client(mac_tx);
+    // Reclaim the session the token belongs to, or register and get a new
+    // session code
+    let name = client_name.clone().unwrap_or_default();
+    let own_tx = mac_tx.clone();
+    let resumed = resume_token
+        .and_then(|token| state.resume(&token, &client_id, passthrough, mac_tx.clone()));
+    // A session reclaimed is one the account has open already
+    if let (None, Some(max)) = (&resumed, account_id.as_deref().and_then(|id| state.account_full(id))) {
+        tracing::info!(event = "register_failed", client_id = %client_id, account_id = ?account_id, "Mac-client registration refused - account at its session limit");
+        let message = format!("This account has {} sessions open, the most it may", max);
+        let _ = sender
+            .send(Message::Text(ControlMessage::Error { message }.to_text().into()))
+            .await;
+        return;
+    }
+    let code = match &resumed {
+        Some(code) => code.clone(),
+        None => state.register(mac_tx, client_id.clone(), client_name).await,
+    };
+    state.set_account(&code, account_id.clone());
+    state.set_password(&code, password);
+    state.set_passthrough(&code, passthrough);
+    state.set_scrollback_limit(&code, scrollback_bytes);
+    state.set_zero_retention(&code, zero_retention);
 
     // Send registration confirmation
-    let response = ControlMessage::Registered { code: code.clone() };
+    let response = ControlMessage::Registered {
+        code: code.clone(),
+        version: Some(version),
+        resume_token: state.new_resume_token(&code),
+    };
     if sender
         .send(Message::Text(
-            serde_json::to_string(&response).unwrap().into(),
+            response.to_text().into(),
         ))
         .await
         .is_err()
@@ -104,59 +457,278 @@ async fn handle_mac_client(
         return;
     }
 
-    tracing::info!(code = %code, client_id = %client_id, "Mac-client connected");
+    tracing::Span::current().record("code", code.as_str());
+    tracing::info!(
+        event = "register",
+        code = %code,
+        client_id = %client_id,
+        name = %name,
+        password = state.requires_password(&code),
+        passthrough = passthrough,
+        zero_retention = zero_retention,
+        resumed = resumed.is_some(),
+        api_key_id = ?api_key_id,
+        account_id = ?account_id,
+        "Mac-client connected"
+    );
+    state.webhooks().notify(WebhookEvent::MacRegistered {
+        code: code.clone(),
+        client_id: client_id.clone(),
+        client_name: (!name.is_empty()).then(|| name.clone()),
+        resumed: resumed.is_some(),
+    });
+
+    // The browsers that stayed are introduced to the mac-client as if they
+    // just joined, so it sends them the session list again
+    if resumed.is_some() {
+        state.broadcast_control_to_browsers(&code, &ControlMessage::MacStatus { connected: true }).await;
+        for (browser_id, role) in state.browsers_of(&code) {
+            let msg = ControlMessage::BrowserConnected {
+                browser_id,
+                role: Some(role),
+            };
+            state.send_control_to_mac_client(&code, &msg).await;
+        }
+        state.broadcast_presence(&code).await;
+    }
 
     // Spawn task to forward messages from browsers to mac-client
     let code_clone = code.clone();
     let send_task = tokio::spawn(async move {
-        while let Some(msg) = mac_rx.recv().await {
-            let result = match msg {
-                MacMessage::Binary(data) => sender.send(Message::Binary(data.into())).await,
-                MacMessage::Text(text) => sender.send(Message::Text(text.into())).await,
+        let mut pings = ping_timer();
+        loop {
+            let result = tokio::select! {
+                msg = mac_rx.recv() => match msg {
+                    Some(MacMessage::Binary(data)) => sender.send(Message::Binary(data.into())).await,
+                    Some(MacMessage::Text(text)) => sender.send(Message::Text(text.into())).await,
+                    Some(MacMessage::Close) => {
+                        let _ = sender.send(Message::Close(None)).await;
+                        break;
+                    }
+                    None => break,
+                },
+                _ = pings.tick() => sender.send(Message::Ping(Default::default())).await,
             };
             if result.is_err() {
                 break;
             }
         }
-    });
+    }.in_current_span());
 
-    // Process incoming messages from mac-client (terminal output)
-    while let Some(msg_result) = receiver.next().await {
+    // Process incoming messages from mac-client (terminal output). A
+    // mac-client that says goodbye is not waited for
+    let mut closed = false;
+    loop {
+        let msg_result = match tokio::time::timeout(DEAD_PEER_TIMEOUT, receiver.next()).await {
+            Ok(Some(msg_result)) => msg_result,
+            Ok(None) => break,
+            Err(_) => {
+                tracing::info!(code = %code_clone, "Mac-client stopped answering pings, dropping it");
+                break;
+            }
+        };
         match msg_result {
             Ok(Message::Binary(data)) => {
                 // Forward terminal output to all connected browsers
-                state.broadcast_to_browsers(&code_clone, data.to_vec()).await;
+                match state.broadcast_to_browsers(&code_clone, data).await {
+                    Verdict::Within => {}
+                    // Not reading from the mac-client meanwhile slows it down
+                    Verdict::Throttle(delay) => tokio::time::sleep(delay).await,
+                    Verdict::Exceeded => {
+                        tracing::warn!(code = %code_clone, "Session went over its transfer quota, closing it");
+                        state.close_session(&code_clone, Expiry::QuotaExceeded).await;
+                        break;
+                    }
+                }
             }
             Ok(Message::Text(text)) => {
                 // Handle control messages from mac-client
                 if let Ok(ctrl) = serde_json::from_str::<ControlMessage>(&text) {
-                    tracing::info!(code = %code_clone, "Mac-client control message: {:?}", ctrl);
+                    if matches!(ctrl, ControlMessage::LatencyEcho { .. }) {
+                        // Every few seconds per browser
+                        tracing::trace!(code = %code_clone, "Mac-client echoed a latency probe");
+                    } else if passthrough {
+                        tracing::info!(code = %code_clone, "Mac-client control message: {}", ctrl.kind());
+                    } else {
+                        tracing::info!(code = %code_clone, "Mac-client control message: {:?}", ctrl);
+                    }
                     // Forward session messages to browsers
                     match &ctrl {
                         ControlMessage::SessionList { sessions } => {
                             tracing::info!(code = %code_clone, "Forwarding SessionList ({} sessions) to browsers", sessions.len());
-                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                            state.set_terminals(&code_clone, sessions);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
                         }
-                        ControlMessage::SessionConnected { .. } => {
+                        ControlMessage::SessionConnected { session_id, name } => {
                             tracing::info!(code = %code_clone, "Forwarding SessionConnected to browsers");
-                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                            state.add_terminal(&code_clone, session_id, name);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
                         }
                         ControlMessage::SessionDisconnected { session_id } => {
                             tracing::info!(code = %code_clone, session_id = %session_id, "Forwarding SessionDisconnected to browsers, purging scrollback");
-                            state.purge_session_scrollback(&code_clone, &session_id).await;
-                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                            state.purge_session_scrollback(&code_clone, session_id).await;
+                            state.remove_terminal(&code_clone, session_id);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::SessionRenamed { session_id, name } => {
+                            tracing::info!(code = %code_clone, session_id = %session_id, name = %name, "Forwarding SessionRenamed to browsers");
+                            state.rename_terminal(&code_clone, session_id, name);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::SessionMetadata { session_id, metadata } => {
+                            tracing::debug!(code = %code_clone, session_id = %session_id, "Forwarding SessionMetadata to browsers");
+                            state.update_terminal(&code_clone, session_id, metadata);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
                         }
                         ControlMessage::SessionResize { session_id, cols, rows } => {
                             tracing::debug!(code = %code_clone, session_id = %session_id, cols = cols, rows = rows, "Forwarding SessionResize to browsers");
-                            state.broadcast_text_to_browsers(&code_clone, &text).await;
+                            state.resize_terminal(&code_clone, session_id, *rows, *cols).await;
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::SessionError { session_id, message } => {
+                            tracing::info!(code = %code_clone, session_id = %session_id, "Forwarding SessionError to browsers: {}", message);
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::Clipboard { session_id, data } => {
+                            tracing::debug!(code = %code_clone, session_id = %session_id, bytes = data.len(), "Forwarding Clipboard to browsers");
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::SharingPaused { paused } => {
+                            tracing::info!(code = %code_clone, paused = paused, "Mac-client sharing paused changed");
+                            state.set_sharing_paused(&code_clone, *paused);
+                        }
+                        ControlMessage::BrowserRole { role } => {
+                            tracing::info!(code = %code_clone, role = ?role, "Mac-client changed the role of new browsers");
+                            state.set_browser_role(&code_clone, *role);
+                        }
+                        ControlMessage::SetRole { browser_id, role } => {
+                            tracing::info!(code = %code_clone, browser_id = %browser_id, role = ?role, "Mac-client changed a browser's role");
+                            if state.set_role_of(&code_clone, browser_id, *role) {
+                                let msg = ControlMessage::RoleChanged { role: *role };
+                                state.send_control_to_browser(&code_clone, browser_id, &msg).await;
+                                state.broadcast_presence(&code_clone).await;
+                            } else {
+                                tracing::debug!(code = %code_clone, browser_id = %browser_id, "Role change for a browser that is gone");
+                            }
+                        }
+                        ControlMessage::CreateJoinToken { ttl_secs, role, uses } => {
+                            let scope = JoinScope::new(ttl_secs.map(Duration::from_secs), *role, *uses);
+                            if let Some(token) = state.create_join_token(&code_clone, scope) {
+                                tracing::info!(code = %code_clone, ttl_secs = scope.ttl.as_secs(), role = ?scope.role, uses = scope.uses, "Mac-client created a join link");
+                                let msg = ControlMessage::JoinToken {
+                                    token,
+                                    expires_in_secs: scope.ttl.as_secs(),
+                                    role: Some(scope.role),
+                                    uses: Some(scope.uses),
+                                };
+                                state.send_control_to_mac_client(&code_clone, &msg).await;
+                            }
+                        }
+                        ControlMessage::SetCompression { enabled } => {
+                            tracing::info!(code = %code_clone, enabled = enabled, "Mac-client changed output compression");
+                            state.set_compression(&code_clone, *enabled);
+                        }
+                        ControlMessage::SetRecording { enabled } => {
+                            let recording_id = state.set_recording(&code_clone, *enabled);
+                            let msg = ControlMessage::RecordingState { recording_id };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                            state.broadcast_control_to_browsers(&code_clone, &msg).await;
+                        }
+                        ControlMessage::SetBrowserLimit { max } => {
+                            tracing::info!(code = %code_clone, max = ?max, "Mac-client changed browser limit");
+                            state.set_browser_limit(&code_clone, *max);
+                        }
+                        ControlMessage::ApprovalRequired { required } => {
+                            tracing::info!(code = %code_clone, required = required, "Mac-client browser approval changed");
+                            state.set_approval_required(&code_clone, *required);
+                        }
+                        ControlMessage::ApproveBrowser { browser_id, approved, role } => {
+                            tracing::info!(code = %code_clone, browser_id = %browser_id, approved = approved, role = ?role, "Mac-client answered approval request");
+                            if !state.answer_approval(&code_clone, browser_id, *approved, *role) {
+                                tracing::debug!(code = %code_clone, browser_id = %browser_id, "Browser is no longer waiting for approval");
+                            }
+                        }
+                        ControlMessage::E2eRequired { .. } | ControlMessage::E2eKey { .. } => {
+                            tracing::debug!(code = %code_clone, "Forwarding end-to-end encryption message to browsers");
+                            state.broadcast_control_to_browsers(&code_clone, &ctrl).await;
+                        }
+                        ControlMessage::RtcAnswer { browser_id, .. }
+                        | ControlMessage::RtcCandidate { browser_id, .. } => {
+                            tracing::debug!(code = %code_clone, browser_id = %browser_id, "Forwarding WebRTC signaling to browser");
+                            state.send_control_to_browser(&code_clone, browser_id, &ctrl).await;
+                        }
+                        ControlMessage::SearchResults { browser_id, matches, .. } => {
+                            tracing::debug!(code = %code_clone, browser_id = %browser_id, matches = matches.len(), "Forwarding search results to browser");
+                            state.send_control_to_browser(&code_clone, browser_id, &ctrl).await;
+                        }
+                        ControlMessage::RtcDirect { browser_id, active } => {
+                            tracing::info!(code = %code_clone, browser_id = %browser_id, active = active, "Browser direct link changed");
+                            state.set_browser_direct(&code_clone, browser_id, *active);
+                        }
+                        ControlMessage::LatencyEcho { id, sent_at, browser_id, relay_at, .. } => {
+                            let mac_rtt_ms = relay_at.map(|at| unix_millis().saturating_sub(at) as f64);
+                            if let Some(ms) = mac_rtt_ms {
+                                state.record_mac_round_trip(&code_clone, ms);
+                            }
+                            let msg = ControlMessage::LatencyEcho {
+                                id: *id,
+                                sent_at: *sent_at,
+                                browser_id: browser_id.clone(),
+                                relay_at: *relay_at,
+                                mac_rtt_ms,
+                            };
+                            state.send_control_to_browser(&code_clone, browser_id, &msg).await;
+                        }
+                        ControlMessage::Unknown => {
+                            tracing::debug!(code = %code_clone, "Ignoring a message type this relay does not know from mac-client");
+                        }
+                        // The relay's own messages, and those browsers send
+                        ControlMessage::Register { .. }
+                        | ControlMessage::Registered { .. }
+                        | ControlMessage::JoinToken { .. }
+                        | ControlMessage::RecordingState { .. }
+                        | ControlMessage::BrowserConnected { .. }
+                        | ControlMessage::BrowserDisconnected { .. }
+                        | ControlMessage::Presence { .. }
+                        | ControlMessage::ApprovalRequest { .. }
+                        | ControlMessage::ApprovalCancelled { .. }
+                        | ControlMessage::Auth { .. }
+                        | ControlMessage::AuthSuccess { .. }
+                        | ControlMessage::AuthFailed { .. }
+                        | ControlMessage::AwaitingApproval
+                        | ControlMessage::RoleChanged { .. }
+                        | ControlMessage::MacStatus { .. }
+                        | ControlMessage::Resync
+                        | ControlMessage::LiveFromNow
+                        | ControlMessage::ReplayStart { .. }
+                        | ControlMessage::ReplayEnd
+                        | ControlMessage::CloseSession { .. }
+                        | ControlMessage::CreateSession
+                        | ControlMessage::ListSessions
+                        | ControlMessage::RenameSession { .. }
+                        | ControlMessage::ResizeSession { .. }
+                        | ControlMessage::E2eHello { .. }
+                        | ControlMessage::Search { .. }
+                        | ControlMessage::ListRecordings
+                        | ControlMessage::SetNickname { .. }
+                        | ControlMessage::RecordingList { .. }
+                        | ControlMessage::PlaybackSpeed { .. }
+                        | ControlMessage::PlaybackEnded
+                        | ControlMessage::RtcOffer { .. }
+                        | ControlMessage::LatencyProbe { .. }
+                        | ControlMessage::Chat { .. }
+                        | ControlMessage::Error { .. } => {
+                            tracing::debug!(code = %code_clone, "Ignoring {} from mac-client", ctrl.kind());
                         }
-                        _ => {}
                     }
                 } else {
                     tracing::warn!(code = %code_clone, "Failed to parse mac-client message: {}", text);
                 }
             }
-            Ok(Message::Close(_)) => break,
+            Ok(Message::Close(_)) => {
+                closed = true;
+                break;
+            }
             Err(e) => {
                 tracing::debug!(code = %code_clone, "Mac-client error: {}", e);
                 break;
@@ -165,52 +737,352 @@ async fn handle_mac_client(
         }
     }
 
-    // Notify all browsers that the session is gone, then clean up
-    let error_msg = serde_json::to_string(&ControlMessage::Error {
-        message: "Session disconnected".into(),
-    }).unwrap();
-    state.broadcast_text_to_browsers(&code_clone, &error_msg).await;
+    // A dropped connection leaves the session held for the mac-client to
+    // come back. Otherwise notify all browsers that the session is gone,
+    // then clean up (unless the relay already closed the session, or
+    // another connection resumed it)
+    if state.is_session_of(&code_clone, &own_tx) {
+        if !closed && state.detach_session(&code_clone) {
+            tracing::info!(code = %code_clone, "Holding session for the mac-client to come back");
+            state.broadcast_control_to_browsers(&code_clone, &ControlMessage::MacStatus { connected: false }).await;
+        } else {
+            let error_msg = ControlMessage::Error {
+                message: "Session disconnected".into(),
+            };
+            state.broadcast_control_to_browsers(&code_clone, &error_msg).await;
+            state.remove_session(&code_clone);
+        }
+    }
 
     send_task.abort();
-    state.remove_session(&code_clone);
-    tracing::info!(code = %code_clone, "Mac-client disconnected");
+    tracing::info!(event = "mac_disconnect", code = %code_clone, clean = closed, "Mac-client disconnected");
+}
+
+/// What a browser sent to join a session, plus its User-Agent
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Join {
+    pub session_code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub browser_key: Option<String>,
+    /// From `auth`, or the bearer token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// From a one-time join link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Newest protocol version the browser speaks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    /// Compression the browser can inflate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<FrameCompression>,
+    /// From the browser's last connection, to resume it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    /// Name to show the others watching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Only the newest this many bytes of scrollback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_bytes: Option<u64>,
+    /// Where the browser connects from, for lockouts after failed joins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+/// Catch up a browser whose output was dropped: skip the frames still
+/// queued for it and send what it missed from `position` on from the
+/// scrollback instead, or all of it after a `resync` if that is no longer
+/// kept. Returns the position the browser is at then.
+async fn resync(
+    sender: &mut impl BrowserSink,
+    browser_rx: &mut mpsc::Receiver<BrowserMessage>,
+    deflater: &mut Option<Deflater>,
+    state: &AppState,
+    code: &str,
+    position: u64,
+) -> Result<u64, axum::Error> {
+    while let Ok(msg) = browser_rx.try_recv() {
+        if let BrowserMessage::Text(text) = msg {
+            sender.send(Message::Text(text.into())).await?;
+        }
+    }
+    let replay = state.replay(code, Some(position)).await;
+    if !replay.resumed {
+        let json = ControlMessage::Resync.to_text();
+        sender.send(Message::Text(json.into())).await?;
+        if state.is_zero_retention(code) {
+            sender.send(Message::Text(ControlMessage::LiveFromNow.to_text().into())).await?;
+        }
+    }
+    send_replay(sender, deflater, replay.frames, state.replay_rate()).await?;
+    Ok(replay.position)
+}
+
+/// Send scrollback between `replay_start` and `replay_end`, pausing after
+/// every [`REPLAY_CHUNK`] so it goes no faster than `rate` bytes a second
+/// and a slow connection or terminal keeps up
+async fn send_replay(
+    sender: &mut impl BrowserSink,
+    deflater: &mut Option<Deflater>,
+    frames: Vec<Bytes>,
+    rate: Option<usize>,
+) -> Result<(), axum::Error> {
+    if frames.is_empty() {
+        return Ok(());
+    }
+    let bytes = frames.iter().map(Bytes::len).sum::<usize>() as u64;
+    sender.send(Message::Text(ControlMessage::ReplayStart { bytes }.to_text().into())).await?;
+    let start = tokio::time::Instant::now();
+    let (mut sent, mut chunk) = (0, 0);
+    for frame in frames {
+        sent += frame.len();
+        chunk += frame.len();
+        let frame = match deflater.as_mut() {
+            Some(deflater) => deflater.compress(&frame).into(),
+            None => frame,
+        };
+        sender.send(Message::Binary(frame)).await?;
+        if let Some(rate) = rate.filter(|_| chunk >= REPLAY_CHUNK) {
+            chunk = 0;
+            tokio::time::sleep_until(start + Duration::from_secs_f64(sent as f64 / rate as f64)).await;
+        }
+    }
+    sender.send(Message::Text(ControlMessage::ReplayEnd.to_text().into())).await
 }
 
 /// Handle a browser connection
-async fn handle_browser(
-    mut sender: futures_util::stream::SplitSink<WebSocket, Message>,
-    mut receiver: futures_util::stream::SplitStream<WebSocket>,
+pub(crate) async fn handle_browser(
+    mut sender: impl BrowserSink,
+    mut receiver: impl BrowserStream,
     state: AppState,
-    session_code: String,
+    join: Join,
 ) {
-    let code = session_code.to_uppercase();
+    let Join {
+        session_code,
+        browser_key,
+        password,
+        role,
+        token,
+        version,
+        compression,
+        resume_token,
+        nickname,
+        replay_bytes,
+        ip,
+        user_agent,
+    } = join;
+    let code = normalize_code(&session_code);
+    tracing::Span::current().record("code", code.as_str());
+
+    let Some(version) = negotiate_version(version) else {
+        let reason = format!(
+            "This relay needs protocol version {} or newer, please reload the page",
+            MIN_PROTOCOL_VERSION
+        );
+        send_auth_failed(&mut sender, &state, AuthFailure::UnsupportedVersion, &reason).await;
+        tracing::info!(event = "join_failed", code = %code, version = ?version, "Browser auth failed - protocol too old");
+        return;
+    };
+
+    // An IP that failed too often is not even told whether codes exist
+    if let Some(left) = ip.and_then(|ip| state.join_lockout(ip)) {
+        let reason = format!("Too many failed attempts, try again in {}s", left.as_secs().max(1));
+        send_auth_failed(&mut sender, &state, AuthFailure::RateLimited, &reason).await;
+        tracing::info!(event = "join_failed", code = %code, "Browser auth refused - locked out");
+        return;
+    }
 
     // Validate session code
     if !state.validate_session_code(&code) {
-        let response = ControlMessage::AuthFailed {
-            reason: "Invalid session code".into(),
-        };
-        let _ = sender
-            .send(Message::Text(
-                serde_json::to_string(&response).unwrap().into(),
-            ))
-            .await;
-        tracing::info!(code = %code, "Browser auth failed - invalid code");
+        tokio::time::sleep(state.join_failed(ip, None)).await;
+        send_auth_failed(&mut sender, &state, AuthFailure::InvalidCode, "Invalid session code").await;
+        tracing::info!(event = "join_failed", code = %code, "Browser auth failed - invalid code");
+        return;
+    }
+
+    // A browser coming back is who it was before it dropped, but passes
+    // the checks the Mac has now like any other; its token is spent once
+    // it is let in
+    let departed = resume_token
+        .as_deref()
+        .and_then(|resume_token| state.departed_browser(&code, resume_token));
+
+    // A join link lets its browser past the password and approval, with
+    // no more than the link grants
+    let invited = match token.as_deref() {
+        Some(token) => match state.redeem_join_token(&code, token, browser_key.as_deref()) {
+            Some(granted) => Some(granted),
+            None => {
+                tokio::time::sleep(state.join_failed(ip, None)).await;
+                send_auth_failed(
+                    &mut sender,
+                    &state,
+                    AuthFailure::InvalidToken,
+                    "This join link has expired or was already used",
+                )
+                .await;
+                tracing::info!(event = "join_failed", code = %code, "Browser auth failed - invalid join token");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // Check the password before anything else reaches the Mac
+    if invited.is_none() && state.requires_password(&code) {
+        if let Some(left) = state.password_lockout(&code) {
+            let reason = format!("Too many wrong passwords for this Mac, try again in {}s", left.as_secs().max(1));
+            send_auth_failed(&mut sender, &state, AuthFailure::RateLimited, &reason).await;
+            tracing::info!(event = "join_failed", code = %code, "Browser auth refused - password locked");
+            return;
+        }
+        match password.as_deref() {
+            None => {
+                send_auth_failed(&mut sender, &state, AuthFailure::PasswordRequired, "This Mac requires a password").await;
+                tracing::info!(event = "join_failed", code = %code, "Browser auth failed - no password");
+                return;
+            }
+            Some(password) if !state.password_matches(&code, password) => {
+                tokio::time::sleep(state.join_failed(ip, Some(&code)).max(WRONG_PASSWORD_DELAY)).await;
+                send_auth_failed(&mut sender, &state, AuthFailure::WrongPassword, "Wrong password").await;
+                tracing::info!(event = "join_failed", code = %code, "Browser auth failed - wrong password");
+                return;
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Refuse new browsers while the Mac is in Do Not Disturb
+    if state.is_sharing_paused(&code) {
+        send_auth_failed(&mut sender, &state, AuthFailure::SharingPaused, "Sharing is paused on the Mac").await;
+        tracing::info!(event = "join_failed", code = %code, "Browser auth refused - sharing paused");
+        return;
+    }
+
+    // Enforce the per-client browser limit
+    if state.browser_limit_reached(&code) {
+        send_join_refused(&mut sender, &state, JoinRefusal::TooManyBrowsers).await;
+        tracing::info!(event = "join_failed", code = %code, "Browser auth refused - browser limit reached");
+        return;
+    }
+    if let Some(max) = state.session_full(&code) {
+        send_join_refused(&mut sender, &state, JoinRefusal::SessionFull(max)).await;
+        tracing::info!(event = "join_failed", code = %code, max = max, "Browser auth refused - session is full");
         return;
     }
 
+    let browser_id = departed.as_ref().map_or_else(|| nanoid::nanoid!(8), |departed| departed.browser_id.clone());
+    tracing::Span::current().record("browser_id", browser_id.as_str());
+    // Never more than the Mac lets new browsers have, or than their join
+    // link grants; a browser coming back asks for what it had
+    let asked = departed.as_ref().map_or(role.unwrap_or_default(), |departed| departed.role);
+    let mut role = match invited {
+        Some(granted) => asked.min(granted),
+        None => asked.min(state.browser_role(&code)),
+    };
+
+    // Hold the browser back until the Mac lets it in, unless the Mac let
+    // it in before it dropped
+    let mut approved = departed.as_ref().is_some_and(|departed| departed.approved);
+    if invited.is_none() && !approved && state.is_approval_required(&code) {
+        let approval = wait_for_approval(
+            &mut sender,
+            &mut receiver,
+            &state,
+            &code,
+            &browser_id,
+            browser_key,
+            user_agent.clone(),
+        )
+        .await;
+        match approval {
+            Approval::Approved(granted) => {
+                role = role.min(granted);
+                approved = true;
+            }
+            Approval::Refused(reason) => {
+                send_auth_failed(&mut sender, &state, AuthFailure::NotApproved, reason).await;
+                tracing::info!(event = "join_failed", code = %code, browser_id = %browser_id, "Browser auth refused - {}", reason);
+                return;
+            }
+            Approval::Gone => {
+                tracing::info!(code = %code, browser_id = %browser_id, "Browser left while waiting for approval");
+                return;
+            }
+        }
+    }
+
+    // Admitted: spend the resume token, unless another connection came
+    // back with it first
+    if let (Some(_), Some(resume_token)) = (&departed, resume_token.as_deref()) {
+        if state.take_departed_browser(&code, resume_token).is_none() {
+            send_auth_failed(
+                &mut sender,
+                &state,
+                AuthFailure::InvalidToken,
+                "This browser already came back on another connection",
+            )
+            .await;
+            tracing::info!(event = "join_failed", code = %code, browser_id = %browser_id, "Browser auth failed - resume token spent");
+            return;
+        }
+    }
+
     // Create channel for receiving messages to send to browser
     let (browser_tx, mut browser_rx) = mpsc::channel::<BrowserMessage>(1000);
-    let browser_id = nanoid::nanoid!(8);
 
     // Register browser with session
-    state.add_browser(&code, browser_id.clone(), browser_tx);
+    let joined_at = departed.as_ref().map_or_else(unix_now, |departed| departed.joined_at);
+    let viewer = Viewer {
+        nickname,
+        ip,
+        ..Viewer::new(browser_id.clone(), role, joined_at)
+    };
+    let lagging = match state.add_browser(&code, viewer, browser_tx) {
+        Ok(lagging) => lagging,
+        Err(refusal) => {
+            // Not let in after all: it may come back later
+            if let (Some(departed), Some(resume_token)) = (departed, resume_token) {
+                state.keep_departed_browser(&code, resume_token, departed);
+            }
+            send_join_refused(&mut sender, &state, refusal).await;
+            tracing::info!(event = "join_failed", code = %code, refusal = ?refusal, "Browser auth refused - no room left");
+            return;
+        }
+    };
+
+    // Binary frames are compressed if the browser can inflate them and the
+    // Mac did not refuse it
+    let mut deflater = (compression == Some(FrameCompression::Deflate)
+        && state.compression_allowed(&code))
+    .then(Deflater::new);
+
+    // Scrollback so the browser gets terminal history immediately, or
+    // only what it missed
+    let mut replay = state.replay(&code, departed.map(|departed| departed.position)).await;
+    if let Some(max_bytes) = replay_bytes.filter(|_| !replay.resumed) {
+        replay.keep_newest(usize::try_from(max_bytes).unwrap_or(usize::MAX));
+    }
+    let resume_token = state.browser_resume_token();
 
     // Send auth success
-    let response = ControlMessage::AuthSuccess;
+    let response = ControlMessage::AuthSuccess {
+        client_name: state.client_name(&code),
+        role: Some(role),
+        version: Some(version),
+        compression: deflater.as_ref().map(|_| FrameCompression::Deflate),
+        resume_token: resume_token.clone(),
+        resumed: replay.resumed,
+        ice_servers: state.ice_servers(),
+    };
     if sender
         .send(Message::Text(
-            serde_json::to_string(&response).unwrap().into(),
+            response.to_text().into(),
         ))
         .await
         .is_err()
@@ -219,14 +1091,51 @@ async fn handle_browser(
         return;
     }
 
-    tracing::info!(code = %code, browser_id = %browser_id, "Browser connected");
+    tracing::info!(event = "join", code = %code, browser_id = %browser_id, role = ?role, resumed = replay.resumed, "Browser connected");
+    state.webhooks().notify(WebhookEvent::BrowserJoined {
+        code: code.clone(),
+        browser_id: browser_id.clone(),
+        role,
+        user_agent,
+    });
+
+    // Tell the browser it is being recorded
+    if let Some(recording_id) = state.recording_of(&code) {
+        let msg = ControlMessage::RecordingState {
+            recording_id: Some(recording_id),
+        };
+        if sender
+            .send(Message::Text(msg.to_text().into()))
+            .await
+            .is_err()
+        {
+            state.remove_browser(&code, &browser_id);
+            return;
+        }
+    }
+
+    // The Mac of a held session is away until it comes back
+    if state.is_detached(&code) {
+        let msg = ControlMessage::MacStatus { connected: false };
+        if sender.send(Message::Text(msg.to_text().into())).await.is_err() {
+            state.remove_browser(&code, &browser_id);
+            return;
+        }
+    }
+
+    // A session keeping nothing has no history to show
+    if !replay.resumed && state.is_zero_retention(&code) {
+        let msg = ControlMessage::LiveFromNow;
+        if sender.send(Message::Text(msg.to_text().into())).await.is_err() {
+            state.remove_browser(&code, &browser_id);
+            return;
+        }
+    }
 
-    // Replay scrollback so browser gets terminal history immediately.
-    let scrollback = state.get_scrollback(&code).await;
-    if !scrollback.is_empty() {
-        tracing::info!(code = %code, frames = scrollback.len(), "Replaying scrollback to browser");
-        for frame in scrollback {
-            if sender.send(Message::Binary(frame.into())).await.is_err() {
+    // The chat so far; a browser resuming has seen it
+    if !replay.resumed {
+        for msg in state.chat_history(&code) {
+            if sender.send(Message::Text(msg.to_text().into())).await.is_err() {
                 state.remove_browser(&code, &browser_id);
                 return;
             }
@@ -236,52 +1145,247 @@ async fn handle_browser(
     // Notify mac-client that a browser connected (so it can send session list)
     let browser_connected_msg = ControlMessage::BrowserConnected {
         browser_id: browser_id.clone(),
+        role: Some(role),
     };
-    let msg_json = serde_json::to_string(&browser_connected_msg).unwrap();
-    tracing::info!(code = %code, "Sending BrowserConnected to mac-client: {}", msg_json);
-    state.send_text_to_mac_client(&code, &msg_json).await;
+    tracing::info!(code = %code, "Sending BrowserConnected to mac-client: {:?}", browser_connected_msg);
+    state.send_control_to_mac_client(&code, &browser_connected_msg).await;
+    state.broadcast_presence(&code).await;
+
+    // Search queries and the like stay out of the logs of passthrough sessions
+    let passthrough = state.is_passthrough(&code);
+
+    // Scrollback position of the output delivered so far, for resuming
+    let position = Arc::new(AtomicU64::new(replay.position));
 
     // Spawn task to forward messages to browser
     let code_clone = code.clone();
     let browser_id_clone = browser_id.clone();
+    let delivered = position.clone();
+    let (task_state, task_code, task_browser_id) = (state.clone(), code.clone(), browser_id.clone());
     let send_task = tokio::spawn(async move {
-        while let Some(msg) = browser_rx.recv().await {
-            let result = match msg {
-                BrowserMessage::Binary(data) => sender.send(Message::Binary(data.into())).await,
-                BrowserMessage::Text(text) => sender.send(Message::Text(text.into())).await,
+        // The scrollback goes first, paced; output that arrives meanwhile
+        // waits in the channel
+        if !replay.frames.is_empty() {
+            tracing::info!(code = %task_code, frames = replay.frames.len(), "Replaying scrollback to browser");
+            if send_replay(&mut sender, &mut deflater, replay.frames, task_state.replay_rate()).await.is_err() {
+                return;
+            }
+        }
+        let mut pings = ping_timer();
+        loop {
+            // Output was dropped because this browser did not keep up
+            if lagging.swap(false, Ordering::Relaxed) {
+                let action = task_state.slow_browser();
+                task_state.metrics().slow_browser(action);
+                if action == SlowBrowser::Disconnect {
+                    tracing::warn!(code = %task_code, browser_id = %task_browser_id, "Browser does not keep up with the output, disconnecting it");
+                    let close = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: "too slow".into(),
+                    };
+                    let _ = sender.send(Message::Close(Some(close))).await;
+                    break;
+                }
+                tracing::info!(code = %task_code, browser_id = %task_browser_id, "Browser fell behind the output, resyncing it");
+                let from = delivered.load(Ordering::Relaxed);
+                match resync(&mut sender, &mut browser_rx, &mut deflater, &task_state, &task_code, from).await {
+                    Ok(position) => delivered.store(position, Ordering::Relaxed),
+                    Err(_) => break,
+                }
+            }
+            let result = tokio::select! {
+                msg = browser_rx.recv() => match msg {
+                    // Output queued while the scrollback was replayed is
+                    // part of it
+                    Some(BrowserMessage::Binary { seq, .. }) if seq < delivered.load(Ordering::Relaxed) => continue,
+                    Some(BrowserMessage::Binary { seq, data }) => {
+                        let data = match deflater.as_mut() {
+                            Some(deflater) => deflater.compress(&data).into(),
+                            None => data,
+                        };
+                        let result = sender.send(Message::Binary(data)).await;
+                        if result.is_ok() {
+                            delivered.store(seq + 1, Ordering::Relaxed);
+                        }
+                        result
+                    }
+                    Some(BrowserMessage::Text(text)) => sender.send(Message::Text(text.into())).await,
+                    None => break,
+                },
+                _ = pings.tick() => sender.send(Message::Ping(Default::default())).await,
             };
             if result.is_err() {
                 break;
             }
         }
-    });
+    }.in_current_span());
 
     // Process incoming messages from browser (keyboard input)
-    while let Some(msg_result) = receiver.next().await {
+    loop {
+        let msg_result = match tokio::time::timeout(DEAD_PEER_TIMEOUT, receiver.next()).await {
+            Ok(Some(msg_result)) => msg_result,
+            Ok(None) => break,
+            Err(_) => {
+                tracing::info!(code = %code_clone, browser_id = %browser_id_clone, "Browser stopped answering pings, dropping it");
+                break;
+            }
+        };
         match msg_result {
             Ok(Message::Binary(data)) => {
-                // Forward keyboard input to mac-client
+                // Forward keyboard input to mac-client, unless only watching.
+                // The role is looked up every time: the Mac can change it.
+                let role = state.role_of(&code_clone, &browser_id_clone).unwrap_or(Role::Viewer);
+                if role == Role::Viewer {
+                    tracing::trace!(code = %code_clone, browser_id = %browser_id_clone, "Dropping input from viewer");
+                    continue;
+                }
+                state.audit_input(&code_clone, &browser_id_clone, &data).await;
                 state.send_to_mac_client(&code_clone, data.to_vec()).await;
             }
             Ok(Message::Text(text)) => {
                 // Handle control messages from browser
                 if let Ok(ctrl) = serde_json::from_str::<ControlMessage>(&text) {
-                    tracing::debug!(code = %code_clone, "Browser control: {:?}", ctrl);
+                    if passthrough {
+                        tracing::debug!(code = %code_clone, "Browser control: {}", ctrl.kind());
+                    } else {
+                        tracing::debug!(code = %code_clone, "Browser control: {:?}", ctrl);
+                    }
+                    let role = state.role_of(&code_clone, &browser_id_clone).unwrap_or(Role::Viewer);
+                    if role == Role::Viewer && !viewer_may_send(&ctrl) {
+                        tracing::debug!(code = %code_clone, browser_id = %browser_id_clone, "Dropping control message from viewer");
+                        continue;
+                    }
                     match ctrl {
-                        ControlMessage::CloseSession { session_id } => {
-                            // Forward to mac-client as binary frame:
-                            // [session_id_len][session_id][payload]
-                            let payload = b"{\"type\":\"close_session\"}";
-                            let mut frame = Vec::with_capacity(1 + session_id.len() + payload.len());
-                            frame.push(session_id.len() as u8);
-                            frame.extend_from_slice(session_id.as_bytes());
-                            frame.extend_from_slice(payload);
-                            state.send_to_mac_client(&code_clone, frame).await;
-                        }
-                        ControlMessage::CreateSession => {
-                            state.send_text_to_mac_client(&code_clone, &text).await;
-                        }
-                        _ => {}
+                        // Session management commands are handled by the mac-client
+                        msg @ (ControlMessage::CloseSession { .. }
+                        | ControlMessage::CreateSession
+                        | ControlMessage::ListSessions
+                        | ControlMessage::RenameSession { .. }
+                        | ControlMessage::E2eHello { .. }) => {
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        ControlMessage::ListRecordings => {
+                            let msg = ControlMessage::RecordingList {
+                                recordings: state.recordings_of(&code_clone),
+                            };
+                            state.send_control_to_browser(&code_clone, &browser_id_clone, &msg).await;
+                        }
+                        // Sizes are tagged with the browser so the mac-client can
+                        // weigh them against the other browsers' sizes
+                        ControlMessage::ResizeSession { session_id, cols, rows, .. } => {
+                            let msg = ControlMessage::ResizeSession {
+                                browser_id: browser_id_clone.clone(),
+                                session_id,
+                                cols,
+                                rows,
+                            };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        // Searches and signaling are tagged with the browser so the
+                        // answer comes back here
+                        ControlMessage::Search { session_id, q, .. } => {
+                            let msg = ControlMessage::Search {
+                                browser_id: browser_id_clone.clone(),
+                                session_id,
+                                q,
+                            };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        // A direct link would carry input past the audit trail
+                        ControlMessage::RtcOffer { .. } if state.audit().is_some() => {
+                            tracing::debug!(code = %code_clone, "Dropping direct link offer, input is audited");
+                        }
+                        ControlMessage::RtcOffer { sdp, .. } => {
+                            let msg = ControlMessage::RtcOffer {
+                                browser_id: browser_id_clone.clone(),
+                                sdp,
+                            };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        ControlMessage::RtcCandidate { candidate, sdp_mid, sdp_mline_index, .. } => {
+                            let msg = ControlMessage::RtcCandidate {
+                                browser_id: browser_id_clone.clone(),
+                                candidate,
+                                sdp_mid,
+                                sdp_mline_index,
+                            };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        // Stamped so the echo comes back here and tells the
+                        // relay's part of the trip from the Mac's
+                        ControlMessage::LatencyProbe { id, sent_at, rtt_ms, .. } => {
+                            if let Some(ms) = rtt_ms {
+                                state.record_round_trip(&code_clone, ms);
+                            }
+                            let msg = ControlMessage::LatencyProbe {
+                                id,
+                                sent_at,
+                                rtt_ms: None,
+                                browser_id: browser_id_clone.clone(),
+                                relay_at: Some(unix_millis()),
+                            };
+                            state.send_control_to_mac_client(&code_clone, &msg).await;
+                        }
+                        ControlMessage::Chat { name, text, .. } => {
+                            state.chat(&code_clone, &browser_id_clone, name, &text).await;
+                        }
+                        ControlMessage::SetNickname { nickname } => {
+                            if state.set_nickname(&code_clone, &browser_id_clone, nickname) {
+                                state.broadcast_presence(&code_clone).await;
+                            }
+                        }
+                        ControlMessage::Unknown => {
+                            tracing::debug!(code = %code_clone, "Ignoring a message type this relay does not know from browser");
+                        }
+                        // The relay's own messages, and those the mac-client sends
+                        msg @ (ControlMessage::Register { .. }
+                        | ControlMessage::SharingPaused { .. }
+                        | ControlMessage::ApprovalRequired { .. }
+                        | ControlMessage::ApproveBrowser { .. }
+                        | ControlMessage::BrowserRole { .. }
+                        | ControlMessage::SetRole { .. }
+                        | ControlMessage::CreateJoinToken { .. }
+                        | ControlMessage::SetRecording { .. }
+                        | ControlMessage::SetCompression { .. }
+                        | ControlMessage::SetBrowserLimit { .. }
+                        | ControlMessage::Registered { .. }
+                        | ControlMessage::JoinToken { .. }
+                        | ControlMessage::RecordingState { .. }
+                        | ControlMessage::BrowserConnected { .. }
+                        | ControlMessage::BrowserDisconnected { .. }
+                        | ControlMessage::Presence { .. }
+                        | ControlMessage::ApprovalRequest { .. }
+                        | ControlMessage::ApprovalCancelled { .. }
+                        | ControlMessage::Auth { .. }
+                        | ControlMessage::AuthSuccess { .. }
+                        | ControlMessage::AuthFailed { .. }
+                        | ControlMessage::AwaitingApproval
+                        | ControlMessage::RoleChanged { .. }
+                        | ControlMessage::MacStatus { .. }
+                        | ControlMessage::Resync
+                        | ControlMessage::LiveFromNow
+                        | ControlMessage::ReplayStart { .. }
+                        | ControlMessage::ReplayEnd
+                        | ControlMessage::RecordingList { .. }
+                        | ControlMessage::PlaybackSpeed { .. }
+                        | ControlMessage::PlaybackEnded
+                        | ControlMessage::SessionList { .. }
+                        | ControlMessage::SessionConnected { .. }
+                        | ControlMessage::SessionDisconnected { .. }
+                        | ControlMessage::SessionRenamed { .. }
+                        | ControlMessage::SessionMetadata { .. }
+                        | ControlMessage::SessionError { .. }
+                        | ControlMessage::SessionResize { .. }
+                        | ControlMessage::Clipboard { .. }
+                        | ControlMessage::E2eRequired { .. }
+                        | ControlMessage::E2eKey { .. }
+                        | ControlMessage::SearchResults { .. }
+                        | ControlMessage::RtcAnswer { .. }
+                        | ControlMessage::RtcDirect { .. }
+                        | ControlMessage::LatencyEcho { .. }
+                        | ControlMessage::Error { .. }) => {
+                            tracing::debug!(code = %code_clone, browser_id = %browser_id_clone, "Ignoring {} from browser", msg.kind());
+                        }
                     }
                 }
             }
@@ -296,6 +1400,136 @@ async fn handle_browser(
 
     // Cleanup
     send_task.abort();
+    if let Some(resume_token) = resume_token {
+        let departed = DepartedBrowser {
+            browser_id: browser_id_clone.clone(),
+            role: state.role_of(&code_clone, &browser_id_clone).unwrap_or(role),
+            joined_at,
+            position: position.load(Ordering::Relaxed),
+            approved,
+        };
+        state.keep_departed_browser(&code_clone, resume_token, departed);
+    }
     state.remove_browser(&code_clone, &browser_id_clone);
-    tracing::info!(code = %code_clone, browser_id = %browser_id_clone, "Browser disconnected");
+    state.broadcast_presence(&code_clone).await;
+    tracing::info!(event = "leave", code = %code_clone, browser_id = %browser_id_clone, "Browser disconnected");
+    state.webhooks().notify(WebhookEvent::BrowserLeft {
+        code: code_clone,
+        browser_id: browser_id_clone,
+    });
+}
+
+/// Control messages a viewer may send: ones that only read. Session
+/// commands and sizes would change what others see, and a direct link
+/// would carry input past the relay.
+fn viewer_may_send(msg: &ControlMessage) -> bool {
+    matches!(
+        msg,
+        ControlMessage::ListSessions
+            | ControlMessage::ListRecordings
+            | ControlMessage::Search { .. }
+            | ControlMessage::E2eHello { .. }
+            | ControlMessage::LatencyProbe { .. }
+            | ControlMessage::Chat { .. }
+            | ControlMessage::SetNickname { .. }
+    )
+}
+
+/// Tell a browser it is not let in
+pub(crate) async fn send_auth_failed(
+    sender: &mut impl BrowserSink,
+    state: &AppState,
+    kind: AuthFailure,
+    reason: &str,
+) {
+    state.metrics().join_failed(kind);
+    let response = ControlMessage::AuthFailed {
+        reason: reason.into(),
+        kind: Some(kind),
+    };
+    let _ = sender
+        .send(Message::Text(
+            response.to_text().into(),
+        ))
+        .await;
+}
+
+/// Tell a browser the session has no room for it
+async fn send_join_refused(sender: &mut impl BrowserSink, state: &AppState, refusal: JoinRefusal) {
+    match refusal {
+        JoinRefusal::TooManyBrowsers => {
+            let reason = "Too many browsers are connected to this Mac";
+            send_auth_failed(sender, state, AuthFailure::TooManyBrowsers, reason).await
+        }
+        JoinRefusal::SessionFull(max) => {
+            let reason = format!("This session allows {} browsers at once", max);
+            send_auth_failed(sender, state, AuthFailure::SessionFull, &reason).await
+        }
+    }
+}
+
+/// Outcome of waiting for the Mac to approve a browser
+enum Approval {
+    /// Let in, with at most this role
+    Approved(Role),
+    /// Not let in, with the reason shown to the browser
+    Refused(&'static str),
+    /// The browser disconnected
+    Gone,
+}
+
+/// Ask the mac-client to approve a browser and wait for the answer. Nothing
+/// from the browser is forwarded and nothing is sent to it meanwhile.
+async fn wait_for_approval(
+    sender: &mut impl BrowserSink,
+    receiver: &mut impl BrowserStream,
+    state: &AppState,
+    code: &str,
+    browser_id: &str,
+    browser_key: Option<String>,
+    user_agent: Option<String>,
+) -> Approval {
+    let Some(mut decision) = state.request_approval(code, browser_id) else {
+        return Approval::Refused("Session disconnected");
+    };
+    let request = ControlMessage::ApprovalRequest {
+        browser_id: browser_id.to_string(),
+        browser_key,
+        user_agent,
+    };
+    state.send_control_to_mac_client(code, &request).await;
+    tracing::info!(code = %code, browser_id = %browser_id, "Browser waiting for approval");
+
+    let waiting = ControlMessage::AwaitingApproval.to_text();
+    let outcome = if sender.send(Message::Text(waiting.into())).await.is_err() {
+        Approval::Gone
+    } else {
+        let timeout = tokio::time::sleep(APPROVAL_TIMEOUT);
+        tokio::pin!(timeout);
+        loop {
+            tokio::select! {
+                answer = &mut decision => {
+                    return match answer {
+                        Ok(Some(role)) => Approval::Approved(role),
+                        Ok(None) => Approval::Refused("Denied on the Mac"),
+                        Err(_) => Approval::Refused("Session disconnected"),
+                    };
+                }
+                _ = &mut timeout => break Approval::Refused("Nobody approved this browser in time"),
+                msg = receiver.next() => match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break Approval::Gone,
+                    _ => {}
+                },
+            }
+        }
+    };
+
+    // Take the request off the Mac's list
+    if state.cancel_approval(code, browser_id) {
+        let cancelled = ControlMessage::ApprovalCancelled {
+            browser_id: browser_id.to_string(),
+        };
+        state.send_control_to_mac_client(code, &cancelled).await;
+    }
+    outcome
 }
@@ -0,0 +1,145 @@
+//! CIDR allow and deny lists for browser joins and mac-client registrations.
+//!
+//! An internal relay can refuse everyone outside the company's ranges, and
+//! any relay can shut out a misbehaving network. Browsers and mac-clients
+//! get separate lists, e.g. Macs only from the office while browsers join
+//! from anywhere. A denied range wins over an allowed one; with no allow
+//! list every address not denied is let in.
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::config::Config;
+
+/// A range of addresses, like `10.0.0.0/8` or `2001:db8::/32`. A bare
+/// address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` of `bits` bits of two addresses agree
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    prefix == 0 || (net ^ ip) >> (bits - prefix) == 0
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("{} is not an IP address or range", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("{} has a prefix length over {}", s, max))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Who may connect for one purpose
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    /// If not empty, only these ranges are let in
+    allow: Vec<Cidr>,
+    /// Never let in
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Filter from `{prefix}_ALLOW` and `{prefix}_DENY`, comma-separated
+    /// ranges
+    pub fn from_config(prefix: &str, config: &Config) -> Result<Self, String> {
+        Self::from_vars(prefix, |name| config.get(name))
+    }
+
+    fn from_vars(prefix: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let list = |name: String| -> Result<Vec<Cidr>, String> {
+            var(&name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|range| !range.is_empty())
+                .map(|range| range.parse().map_err(|e| format!("{}: {}", name, e)))
+                .collect()
+        };
+        Ok(Self {
+            allow: list(format!("{}_ALLOW", prefix))?,
+            deny: list(format!("{}_DENY", prefix))?,
+        })
+    }
+
+    /// Whether `ip` may connect
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|range| range.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let range: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("10.1.200.3")));
+        assert!(range.contains(ip("::ffff:10.1.0.1")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(!range.contains(ip("2001:db8::1")));
+
+        let range: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(ip("2001:db8:ffff::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("203.0.113.9")));
+        assert!("192.0.2.7".parse::<Cidr>().unwrap().contains(ip("192.0.2.7")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("intranet".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let vars = |name: &str| match name {
+            "JOIN_ALLOW" => Some("10.0.0.0/8, 192.168.0.0/16".to_string()),
+            "JOIN_DENY" => Some("10.66.0.0/16".to_string()),
+            _ => None,
+        };
+        let filter = IpFilter::from_vars("JOIN", vars).unwrap();
+        assert!(filter.permits(ip("10.1.2.3")));
+        assert!(filter.permits(ip("192.168.1.1")));
+        assert!(!filter.permits(ip("10.66.1.1")));
+        assert!(!filter.permits(ip("203.0.113.9")));
+
+        // No lists: everyone
+        let filter = IpFilter::from_vars("REGISTER", vars).unwrap();
+        assert!(filter.permits(ip("203.0.113.9")));
+
+        let err = IpFilter::from_vars("JOIN", |_| Some("10.0.0.0/99".into())).unwrap_err();
+        assert!(err.starts_with("JOIN_ALLOW"));
+    }
+}
@@ -0,0 +1,100 @@
+//! Round-trip latency of sessions, from `latency_probe` messages.
+//!
+//! Browsers probe through the relay to the Mac and back, and report the
+//! round trip they measured with their next probe; the relay measures the
+//! part between itself and the Mac when the echo passes. Each session keeps
+//! the last [`SAMPLES`] of both for the percentiles on `/debug/sessions`.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Round trips kept per session and kind
+pub const SAMPLES: usize = 256;
+
+/// Round trips above this are reported wrong, not slow
+const MAX_RTT_MS: f64 = 60_000.0;
+
+/// Percentiles of recent round trips, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub samples: usize,
+}
+
+/// The last round trips of one kind
+#[derive(Debug, Default)]
+pub struct RoundTrips {
+    samples: VecDeque<f64>,
+}
+
+impl RoundTrips {
+    /// Keep `ms`, dropping the oldest sample when full. Values no network
+    /// takes are ignored.
+    pub fn record(&mut self, ms: f64) {
+        if !(0.0..=MAX_RTT_MS).contains(&ms) {
+            return;
+        }
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    /// None before the first sample
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        // Nearest rank
+        let rank = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Some(Percentiles {
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            samples: sorted.len(),
+        })
+    }
+}
+
+/// Round trips of one session
+#[derive(Debug, Default)]
+pub struct SessionLatency {
+    /// Browser -> relay -> Mac -> relay -> browser, as browsers report it
+    pub end_to_end: RoundTrips,
+    /// Relay -> Mac -> relay, as the relay measures it
+    pub mac: RoundTrips,
+}
+
+/// The relay's clock as probes carry it, in Unix milliseconds
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut trips = RoundTrips::default();
+        assert_eq!(trips.percentiles(), None);
+        for ms in 1..=100 {
+            trips.record(ms as f64);
+        }
+        trips.record(f64::NAN);
+        trips.record(-1.0);
+        trips.record(1e9);
+        let p = trips.percentiles().unwrap();
+        assert_eq!((p.p50, p.p90, p.p99, p.samples), (50.0, 90.0, 99.0, 100));
+
+        // Only the last samples count
+        for _ in 0..SAMPLES {
+            trips.record(5.0);
+        }
+        assert_eq!(trips.percentiles().unwrap().p99, 5.0);
+    }
+}
@@ -0,0 +1,201 @@
+//! Listening on several addresses at once.
+//!
+//! By default the relay listens on `BIND_ADDRESS` and `PORT`, with TLS if
+//! it is configured. `LISTEN` replaces that with a comma-separated list of
+//! addresses, each optionally followed by space-separated options:
+//!
+//! ```text
+//! LISTEN=0.0.0.0:443, [::]:443, 127.0.0.1:9100 admin plain
+//! ```
+//!
+//! - `plain`: no TLS here, even when TLS is configured
+//! - `cert=PATH key=PATH`: TLS with this certificate instead of the
+//!   configured one
+//! - `admin`: only the admin API, `/debug/sessions` and `/metrics`
+//!
+//! Once a listener is `admin`, the others no longer serve those routes, so
+//! they can be kept off the public address. IPv6 addresses take only IPv6
+//! connections, so `0.0.0.0` and `[::]` can share a port.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+
+use crate::config::Config;
+use crate::tls::TlsConfig;
+
+/// Connections waiting to be accepted, per listener
+const BACKLOG: i32 = 1024;
+
+/// One address to listen on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub addr: SocketAddr,
+    /// None for plain HTTP
+    pub tls: Option<TlsConfig>,
+    pub admin: bool,
+}
+
+impl Listener {
+    /// The listeners from `LISTEN`, or the one on `fallback`; those without
+    /// TLS options use `tls`
+    pub fn from_config(config: &Config, fallback: SocketAddr, tls: Option<&TlsConfig>) -> Result<Vec<Self>, String> {
+        Self::from_vars(|name| config.get(name).filter(|v| !v.is_empty()), fallback, tls)
+    }
+
+    fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+        fallback: SocketAddr,
+        tls: Option<&TlsConfig>,
+    ) -> Result<Vec<Self>, String> {
+        let Some(list) = var("LISTEN") else {
+            return Ok(vec![Self {
+                addr: fallback,
+                tls: tls.cloned(),
+                admin: false,
+            }]);
+        };
+        let listeners = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| Self::parse(entry, tls))
+            .collect::<Result<Vec<_>, _>>()?;
+        if listeners.is_empty() {
+            return Err("LISTEN has no address".into());
+        }
+        if listeners.iter().all(|listener| listener.admin) {
+            return Err("LISTEN has only admin addresses".into());
+        }
+        for (i, listener) in listeners.iter().enumerate() {
+            if listeners[..i].iter().any(|other| other.addr == listener.addr) {
+                return Err(format!("{} is in LISTEN twice", listener.addr));
+            }
+        }
+        Ok(listeners)
+    }
+
+    /// One entry of `LISTEN`: an address, then options
+    fn parse(entry: &str, tls: Option<&TlsConfig>) -> Result<Self, String> {
+        let mut words = entry.split_whitespace();
+        let addr = words.next().unwrap_or_default();
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| format!("{:?} in LISTEN must be an address with a port, like 0.0.0.0:443 or [::]:443", addr))?;
+        let (mut plain, mut admin) = (false, false);
+        let (mut cert, mut key): (Option<PathBuf>, Option<PathBuf>) = (None, None);
+        for option in words {
+            match option.split_once('=') {
+                None if option == "plain" => plain = true,
+                None if option == "admin" => admin = true,
+                Some(("cert", path)) if !path.is_empty() => cert = Some(path.into()),
+                Some(("key", path)) if !path.is_empty() => key = Some(path.into()),
+                _ => return Err(format!("unknown option {:?} for {} in LISTEN", option, addr)),
+            }
+        }
+        let tls = match (plain, cert, key) {
+            (true, None, None) => None,
+            (true, _, _) => return Err(format!("{} in LISTEN is plain but has a certificate", addr)),
+            (false, Some(cert), Some(key)) => Some(TlsConfig::Files { cert, key }),
+            (false, None, None) => tls.cloned(),
+            (false, _, _) => return Err(format!("{} in LISTEN needs both cert= and key=", addr)),
+        };
+        Ok(Self { addr, tls, admin })
+    }
+
+    /// Start listening
+    pub fn bind(&self) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(self.addr), Type::STREAM, Some(Protocol::TCP))?;
+        if self.addr.is_ipv6() {
+            // Leave IPv4 to a listener of its own on the same port
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&self.addr.into())?;
+        socket.listen(BACKLOG)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    /// How the listener is reached, for the log
+    pub fn url(&self) -> String {
+        format!("{}://{}", if self.tls.is_some() { "https" } else { "http" }, self.addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+    }
+
+    fn files(cert: &str, key: &str) -> TlsConfig {
+        TlsConfig::Files {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let fallback: SocketAddr = "0.0.0.0:3000".parse().unwrap();
+        let tls = files("relay.pem", "relay.key");
+        let listeners = Listener::from_vars(vars(&[]), fallback, Some(&tls)).unwrap();
+        assert_eq!(
+            listeners,
+            vec![Listener {
+                addr: fallback,
+                tls: Some(tls.clone()),
+                admin: false
+            }]
+        );
+
+        let list = "0.0.0.0:443, [::]:443 ,127.0.0.1:9100 admin plain, 10.0.0.5:8443 cert=internal.pem key=internal.key";
+        let listeners = Listener::from_vars(vars(&[("LISTEN", list)]), fallback, Some(&tls)).unwrap();
+        assert_eq!(listeners.len(), 4);
+        assert_eq!(listeners[1].addr, "[::]:443".parse().unwrap());
+        assert_eq!(listeners[1].tls, Some(tls.clone()));
+        assert!(!listeners[1].admin);
+        assert_eq!(listeners[2].tls, None);
+        assert!(listeners[2].admin);
+        assert_eq!(listeners[3].tls, Some(files("internal.pem", "internal.key")));
+        // Without TLS configured, listeners are plain unless given a certificate
+        let listeners = Listener::from_vars(vars(&[("LISTEN", "[::1]:3000")]), fallback, None).unwrap();
+        assert_eq!(listeners[0].url(), "http://[::1]:3000");
+
+        for bad in [
+            "localhost:443",
+            "0.0.0.0",
+            "0.0.0.0:443 tls",
+            "0.0.0.0:443 cert=relay.pem",
+            "0.0.0.0:443 plain cert=a.pem key=a.key",
+            "0.0.0.0:443, 0.0.0.0:443 admin",
+            "127.0.0.1:9100 admin",
+            " , ",
+        ] {
+            assert!(Listener::from_vars(vars(&[("LISTEN", bad)]), fallback, None).is_err(), "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v4_and_v6_share_a_port() {
+        let v4 = Listener {
+            addr: "0.0.0.0:0".parse().unwrap(),
+            tls: None,
+            admin: false,
+        };
+        let bound = v4.bind().unwrap();
+        let v6 = Listener {
+            addr: SocketAddr::new("::".parse().unwrap(), bound.local_addr().unwrap().port()),
+            ..v4
+        };
+        // Hosts without IPv6 have nothing to share the port with
+        if std::net::TcpListener::bind("[::]:0").is_ok() {
+            let bound_v6 = v6.bind().unwrap();
+            assert_eq!(bound_v6.local_addr().unwrap().port(), bound.local_addr().unwrap().port());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Lockouts against guessing session codes and passwords.
+//!
+//! The per-IP join limit caps how fast one address may try; this looks at
+//! what the tries get. Every failed join (an unknown code, a wrong password
+//! or join link) counts against the IP it came from, and a wrong password
+//! against the code too. Each failure makes the answer to the next one wait
+//! twice as long, from [`BASE_DELAY`] up to [`MAX_DELAY`]. After
+//! `JOIN_LOCKOUT_FAILURES` failures within `JOIN_LOCKOUT_SECS`, the IP is
+//! refused, or the code takes no more passwords, for `JOIN_LOCKOUT_SECS`.
+//! Browsers with a join link or resuming still get in to a locked code.
+
+use dashmap::DashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Default failures before a lockout
+pub const DEFAULT_LOCKOUT_FAILURES: u32 = 10;
+
+/// Default time failures are counted in, and lockouts last
+pub const DEFAULT_LOCKOUT: Duration = Duration::from_secs(900);
+
+/// Wait before answering the first failure
+const BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest wait before answering a failure
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// When IPs and codes are locked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lockout {
+    /// Failures within `duration` that lock out; 0 turns lockouts off
+    pub failures: u32,
+    pub duration: Duration,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            failures: DEFAULT_LOCKOUT_FAILURES,
+            duration: DEFAULT_LOCKOUT,
+        }
+    }
+}
+
+/// Failures of one IP or code since the first one still counted
+#[derive(Debug)]
+struct Failures {
+    since: Instant,
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// What a failed join led to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failure {
+    /// How long to wait before answering it
+    pub delay: Duration,
+    /// The IP is locked out from now on
+    pub ip_locked: bool,
+    /// The code is locked from now on
+    pub code_locked: bool,
+}
+
+/// Failed joins per IP and per code
+#[derive(Debug)]
+pub struct Lockouts {
+    policy: Lockout,
+    ips: DashMap<IpAddr, Failures>,
+    codes: DashMap<String, Failures>,
+}
+
+impl Lockouts {
+    pub fn new(policy: Lockout) -> Self {
+        Self {
+            policy,
+            ips: DashMap::new(),
+            codes: DashMap::new(),
+        }
+    }
+
+    /// How much longer `ip` is locked out, if it is
+    pub fn ip_locked(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        locked(&self.ips, &ip, now)
+    }
+
+    /// How much longer `code` takes no passwords, if it does not
+    pub fn code_locked(&self, code: &str, now: Instant) -> Option<Duration> {
+        locked(&self.codes, code, now)
+    }
+
+    /// Count a failed join from `ip`, against `code` too if given
+    pub fn fail(&self, ip: Option<IpAddr>, code: Option<&str>, now: Instant) -> Failure {
+        if self.policy.failures == 0 {
+            return Failure {
+                delay: Duration::ZERO,
+                ip_locked: false,
+                code_locked: false,
+            };
+        }
+        let (ip_failures, ip_locked) = ip.map_or((0, false), |ip| self.count(&self.ips, ip, now));
+        let (code_failures, code_locked) = code.map_or((0, false), |code| self.count(&self.codes, code.to_string(), now));
+        let failures = ip_failures.max(code_failures);
+        Failure {
+            delay: BASE_DELAY.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(MAX_DELAY),
+            ip_locked,
+            code_locked,
+        }
+    }
+
+    /// Add a failure to `key`. Returns its failures and whether that
+    /// locked it.
+    fn count<K: Eq + Hash>(&self, map: &DashMap<K, Failures>, key: K, now: Instant) -> (u32, bool) {
+        let mut entry = map.entry(key).or_insert_with(|| Failures {
+            since: now,
+            count: 0,
+            locked_until: None,
+        });
+        if entry.locked_until.is_some_and(|until| now >= until)
+            || now.saturating_duration_since(entry.since) >= self.policy.duration
+        {
+            *entry = Failures {
+                since: now,
+                count: 0,
+                locked_until: None,
+            };
+        }
+        entry.count += 1;
+        let locks = entry.locked_until.is_none() && entry.count >= self.policy.failures;
+        if locks {
+            entry.locked_until = Some(now + self.policy.duration);
+        }
+        (entry.count, locks)
+    }
+
+    /// Forget IPs and codes whose failures no longer count
+    pub fn prune(&self, now: Instant) {
+        let duration = self.policy.duration;
+        let current = |failures: &mut Failures| {
+            failures.locked_until.is_some_and(|until| now < until)
+                || now.saturating_duration_since(failures.since) < duration
+        };
+        self.ips.retain(|_, failures| current(failures));
+        self.codes.retain(|_, failures| current(failures));
+    }
+
+    /// IPs and codes currently tracked (for debugging)
+    pub fn tracked(&self) -> (usize, usize) {
+        (self.ips.len(), self.codes.len())
+    }
+}
+
+fn locked<K, Q>(map: &DashMap<K, Failures>, key: &Q, now: Instant) -> Option<Duration>
+where
+    K: Eq + Hash + std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+{
+    let until = map.get(key)?.locked_until?;
+    (now < until).then(|| until - now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delays_escalate_until_locked() {
+        let lockouts = Lockouts::new(Lockout {
+            failures: 4,
+            duration: Duration::from_secs(60),
+        });
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+
+        let delays: Vec<Duration> = (0..3).map(|_| lockouts.fail(Some(ip), None, now).delay).collect();
+        assert_eq!(delays, [Duration::from_millis(250), Duration::from_millis(500), Duration::from_secs(1)]);
+        assert_eq!(lockouts.ip_locked(ip, now), None);
+        assert!(lockouts.fail(Some(ip), None, now).ip_locked);
+        assert_eq!(lockouts.ip_locked(ip, now + Duration::from_secs(20)), Some(Duration::from_secs(40)));
+        // Only the failure that locks says so
+        assert!(!lockouts.fail(Some(ip), None, now).ip_locked);
+        assert_eq!(lockouts.ip_locked(ip, now + Duration::from_secs(60)), None);
+
+        lockouts.prune(now + Duration::from_secs(60));
+        assert_eq!(lockouts.tracked(), (0, 0));
+    }
+
+    #[test]
+    fn test_codes_lock_over_many_ips() {
+        let lockouts = Lockouts::new(Lockout {
+            failures: 3,
+            duration: Duration::from_secs(60),
+        });
+        let now = Instant::now();
+        let locked: Vec<bool> = (1..=3)
+            .map(|i| {
+                let ip: IpAddr = format!("192.0.2.{}", i).parse().unwrap();
+                lockouts.fail(Some(ip), Some("ABC234"), now).code_locked
+            })
+            .collect();
+        assert_eq!(locked, [false, false, true]);
+        assert!(lockouts.code_locked("ABC234", now).is_some());
+        assert!(lockouts.code_locked("XYZ789", now).is_none());
+        // The code's failures delay every IP
+        let ip: IpAddr = "192.0.2.9".parse().unwrap();
+        assert_eq!(lockouts.fail(Some(ip), Some("ABC234"), now).delay, Duration::from_secs(2));
+
+        let off = Lockouts::new(Lockout {
+            failures: 0,
+            ..Lockout::default()
+        });
+        for _ in 0..100 {
+            assert_eq!(off.fail(Some(ip), Some("ABC234"), now).delay, Duration::ZERO);
+        }
+        assert_eq!(off.tracked(), (0, 0));
+    }
+}
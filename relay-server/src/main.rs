@@ -1,34 +1,484 @@
+mod accounts;
+mod affinity;
+mod apikeys;
 mod assets;
+mod audit;
+mod cluster;
+mod compression;
+mod config;
+mod federation;
+mod frame;
 mod handlers;
+mod ipfilter;
+mod latency;
+mod listen;
+mod lockout;
+mod metrics;
+mod origin;
 mod protocol;
+mod qr;
+mod quota;
+mod ratelimit;
+mod recording;
+mod scrollback;
 mod session;
 mod state;
+mod statefile;
+mod tls;
+mod transcript;
+mod unix;
+mod webhooks;
+mod webtransport;
 
-use axum::{extract::State, routing::get, Router};
+use axum::{extract::State, http::{header, HeaderMap}, routing::{delete, get, post, put}, Router};
 use axum_embed::ServeEmbed;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::info;
 
+use crate::accounts::Accounts;
+use crate::apikeys::ApiKeys;
 use crate::assets::Assets;
-use crate::state::AppState;
+use crate::audit::{AuditKey, AuditLog};
+use crate::cluster::Cluster;
+use crate::config::{Command, Config};
+use crate::federation::Federation;
+use crate::ipfilter::IpFilter;
+use crate::listen::Listener;
+use crate::origin::AllowedOrigins;
+use crate::quota::Quotas;
+use crate::ratelimit::RateLimit;
+use crate::affinity::Affinity;
+use crate::session::CodeFormat;
+use crate::unix::UnixSocket;
+use crate::state::{AppState, Limits};
+use crate::statefile::StateFile;
+use crate::tls::{Acceptor, TlsConfig};
+use crate::webhooks::Webhooks;
+use crate::webtransport::WebTransport;
 
-async fn debug_sessions(State(state): State<AppState>) -> String {
-    format!("Active sessions: {}", state.session_count())
+/// Time between two sweeps for expired sessions and stale rate limits
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
+
+async fn debug_sessions(headers: HeaderMap, State(state): State<AppState>) -> String {
+    let mut out = format!("Active sessions: {}\n", state.session_count());
+    // Codes and client IDs are enough to join sessions and to aim at a
+    // Mac's quota and recordings, so only the admin sees them
+    if !handlers::is_admin(&headers, &state).await {
+        return out;
+    }
+    for usage in state.client_usage() {
+        out.push_str(&format!(
+            "{} {} ({}): {} browsers, {} bytes relayed\n",
+            usage.code,
+            usage.client_name.as_deref().unwrap_or("unnamed"),
+            usage.client_id,
+            usage.browsers,
+            usage.bytes_relayed
+        ));
+        for (name, trips) in [("round trip", usage.round_trip), ("to the Mac and back", usage.mac_round_trip)] {
+            if let Some(p) = trips {
+                out.push_str(&format!(
+                    "  {}: p50 {:.0}ms, p90 {:.0}ms, p99 {:.0}ms over {} probes\n",
+                    name, p.p50, p.p90, p.p99, p.samples
+                ));
+            }
+        }
+    }
+    for transfer in state.client_transfer() {
+        out.push_str(&format!(
+            "Client {}: {} bytes relayed, {} this quota period\n",
+            transfer.client_id, transfer.bytes_total, transfer.bytes_in_period
+        ));
+    }
+    let (join_ips, register_ips) = state.rate_limited_ips();
+    out.push_str(&format!("Rate-limited IPs: {} joining, {} registering\n", join_ips, register_ips));
+    let (failed_ips, failed_codes) = state.failed_joins_tracked();
+    out.push_str(&format!("Failed joins tracked: {} IPs, {} codes\n", failed_ips, failed_codes));
+    out
+}
+
+async fn metrics(State(state): State<AppState>) -> ([(header::HeaderName, &'static str); 1], String) {
+    let gauges = state.gauges().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics().render(&gauges),
+    )
+}
+
+/// Stop at startup over settings that make no sense
+fn invalid(message: impl std::fmt::Display) -> ! {
+    eprintln!("relay-server: {}", message);
+    std::process::exit(2)
+}
+
+/// Read an optional number setting, stopping on garbage
+fn number<T: std::str::FromStr>(config: &Config, name: &str) -> Option<T> {
+    config.number(name).unwrap_or_else(|e| invalid(e))
+}
+
+/// Resolves when the relay is told to stop, by Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Initialize tracing: text, or one JSON object per line for log pipelines,
+/// with the connection a line belongs to. With `TOKIO_CONSOLE`, tasks can
+/// be watched in tokio-console too.
+fn init_tracing(config: &Config) {
+    use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*};
+
+    let logs = if config.get("LOG_FORMAT").is_some_and(|v| v == "json") {
+        fmt::layer().json().with_current_span(true).with_span_list(false).boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    let registry = tracing_subscriber::registry().with(logs.with_filter(LevelFilter::INFO));
+    if !config.flag("TOKIO_CONSOLE") {
+        registry.init();
+        return;
+    }
+    #[cfg(feature = "console")]
+    {
+        registry.with(console_subscriber::spawn()).init();
+        tracing::info!("Serving tokio-console");
+    }
+    #[cfg(not(feature = "console"))]
+    {
+        registry.init();
+        tracing::warn!("TOKIO_CONSOLE is set, but this relay was built without the console feature");
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    // Settings from the config file, the environment and flags
+    let config = match Config::load() {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Help) => {
+            print!("{}", Config::usage());
+            return;
+        }
+        Err(e) => invalid(e),
+    };
+
+    init_tracing(&config);
+
+    // Address and port to listen on
+    let bind: IpAddr = match config.get("BIND_ADDRESS").filter(|v| !v.is_empty()) {
+        Some(address) => address
+            .parse()
+            .unwrap_or_else(|_| invalid(format!("BIND_ADDRESS must be an IP address, not {:?}", address))),
+        None => [0, 0, 0, 0].into(),
+    };
+    let port: u16 = number(&config, "PORT").unwrap_or(3000);
+
+    // Scrollback kept per terminal session for replay to new browsers
+    let max_scrollback: usize = number(&config, "SCROLLBACK_BYTES").unwrap_or(state::DEFAULT_MAX_SCROLLBACK);
+    // and how fast it is replayed, 0 for all at once
+    let replay_rate = match number(&config, "REPLAY_BYTES_PER_SEC") {
+        Some(0) => None,
+        Some(rate) => Some(rate),
+        None => Some(state::DEFAULT_REPLAY_RATE),
+    };
+
+    // Optional cap on browsers per mac-client (by client ID)
+    let max_browsers_per_client: Option<usize> = number(&config, "MAX_BROWSERS_PER_CLIENT");
+    // and per session, which the mac-client can lower
+    let max_browsers_per_session: Option<usize> = number(&config, "MAX_BROWSERS_PER_SESSION");
+
+    // Optional TLS, with static certificates or from Let's Encrypt
+    let tls = TlsConfig::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid TLS configuration: {}", e)));
+
+    // Optional Unix domain socket instead of the TCP port, for a reverse
+    // proxy on the same host
+    let unix_socket = UnixSocket::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid Unix socket settings: {}", e)));
+    if unix_socket.is_some() && tls.is_some() {
+        invalid("UNIX_SOCKET is for a reverse proxy, which does TLS; leave out the TLS settings");
+    }
+
+    // Addresses to listen on, from LISTEN or BIND_ADDRESS and PORT
+    let listeners = Listener::from_config(&config, SocketAddr::new(bind, port), tls.as_ref())
+        .unwrap_or_else(|e| invalid(format!("Invalid listen addresses: {}", e)));
+    if unix_socket.is_some() && config.get("LISTEN").is_some() {
+        invalid("Set either UNIX_SOCKET or LISTEN, not both");
+    }
+
+    // Optional WebTransport for browsers, over UDP
+    let webtransport = match number(&config, "WEBTRANSPORT_PORT") {
+        Some(port) => Some(
+            WebTransport::bind(port, tls.as_ref())
+                .await
+                .unwrap_or_else(|e| panic!("Cannot start WebTransport on port {}: {}", port, e)),
+        ),
+        None => None,
+    };
+
+    // Per-IP limits on browser joins and mac-client registrations
+    let ban = number(&config, "RATE_LIMIT_BAN_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(ratelimit::DEFAULT_BAN);
+    let join_rate = RateLimit::new(
+        number(&config, "JOINS_PER_MINUTE").unwrap_or(ratelimit::DEFAULT_JOINS_PER_WINDOW),
+        ban,
+    );
+    let register_rate = RateLimit::new(
+        number(&config, "REGISTRATIONS_PER_MINUTE").unwrap_or(ratelimit::DEFAULT_REGISTRATIONS_PER_WINDOW),
+        ban,
+    );
+
+    // Lockouts of IPs and codes with many failed joins
+    let lockout = lockout::Lockout {
+        failures: number(&config, "JOIN_LOCKOUT_FAILURES").unwrap_or(lockout::DEFAULT_LOCKOUT_FAILURES),
+        duration: number(&config, "JOIN_LOCKOUT_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(lockout::DEFAULT_LOCKOUT),
+    };
+    let client_ip_header = config.get("CLIENT_IP_HEADER").filter(|v| !v.is_empty());
+    if unix_socket.is_some() && client_ip_header.is_none() {
+        tracing::warn!("UNIX_SOCKET is set without CLIENT_IP_HEADER: every client counts as 127.0.0.1 for rate limits");
+    }
+
+    // Optional CIDR ranges browsers and mac-clients may (not) connect from
+    let join_filter = IpFilter::from_config("JOIN", &config).unwrap_or_else(|e| invalid(format!("Invalid IP filter: {}", e)));
+    let register_filter = IpFilter::from_config("REGISTER", &config).unwrap_or_else(|e| invalid(format!("Invalid IP filter: {}", e)));
 
-    // Get port from environment variable or use default
-    let port: u16 = std::env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse()
-        .expect("PORT must be a valid number");
+    // Web pages on other domains that may connect, like a separately hosted
+    // web UI
+    let allowed_origins = AllowedOrigins::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid ALLOWED_ORIGINS: {}", e)));
+    let cors = allowed_origins.cors();
+
+    // Optional limits on how long sessions live
+    let max_session_lifetime = number(&config, "SESSION_MAX_LIFETIME_SECS").map(Duration::from_secs);
+    let session_idle_timeout = number(&config, "SESSION_IDLE_TIMEOUT_SECS").map(Duration::from_secs);
+
+    // Optional screen snapshots for new browsers instead of the raw scrollback
+    let snapshot_history = config.flag("SNAPSHOT_ON_JOIN").then(|| {
+        number(&config, "SNAPSHOT_HISTORY_LINES").unwrap_or(scrollback::DEFAULT_SNAPSHOT_HISTORY)
+    });
+
+    // Session recordings the mac-client asks for
+    let max_recording_bytes = number(&config, "RECORDING_BYTES").unwrap_or(recording::DEFAULT_MAX_RECORDING_BYTES);
+    let recording_retention = number(&config, "RECORDING_RETENTION_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(recording::DEFAULT_RECORDING_RETENTION);
+
+    // What happens to browsers that do not keep up with the output
+    let slow_browser = state::SlowBrowser::parse(&config.get("SLOW_BROWSER").unwrap_or_default())
+        .unwrap_or_else(|e| invalid(e));
+
+    // Optional caps on the bytes one session or client relays
+    let quotas = Quotas::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid transfer quota: {}", e)));
+
+    // How long sessions wait for a mac-client whose connection dropped
+    let resume_grace = number(&config, "RESUME_GRACE_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(state::DEFAULT_RESUME_GRACE);
+
+    // How long browsers whose connection dropped can come back as themselves
+    let browser_resume_window = number(&config, "BROWSER_RESUME_SECS")
+        .map(Duration::from_secs)
+        .unwrap_or(state::DEFAULT_BROWSER_RESUME_WINDOW);
+
+    // Optional file keeping sessions across a restart
+    let state_file = StateFile::from_config(&config);
+    if state_file.is_some() && resume_grace.is_zero() {
+        invalid("STATE_FILE needs a RESUME_GRACE_SECS for Macs to come back in");
+    }
+
+    // Chat lines browsers joining a session are shown
+    let chat_history = number(&config, "CHAT_HISTORY").unwrap_or(state::DEFAULT_CHAT_HISTORY);
+
+    // Optional webhooks told about registrations, joins and expiries
+    let webhook_urls: Vec<String> = config.get("WEBHOOK_URLS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+    let webhook_secret = config.get("WEBHOOK_SECRET").filter(|v| !v.is_empty());
+    let webhooks = Webhooks::new(webhook_urls, webhook_secret);
+
+    // Optional audit trail of browser input
+    let audit = config.get("AUDIT_LOG_DIR").filter(|v| !v.is_empty()).map(|dir| {
+        let retention = number::<u64>(&config, "AUDIT_RETENTION_DAYS")
+            .map(|days| Duration::from_secs(days * 24 * 3600))
+            .unwrap_or(audit::DEFAULT_AUDIT_RETENTION);
+        let token = config.get("AUDIT_TOKEN").filter(|v| !v.is_empty());
+        let key = config.get("AUDIT_KEY").filter(|v| !v.is_empty()).map(|key| {
+            AuditKey::from_base64(&key).unwrap_or_else(|| invalid("AUDIT_KEY is not 32 bytes in base64"))
+        });
+        if key.is_none() {
+            tracing::warn!("AUDIT_KEY is not set, browser input is written to {} in the clear", dir);
+        }
+        AuditLog::open(dir.clone().into(), retention, token, key)
+            .unwrap_or_else(|e| panic!("Cannot open audit log in {}: {}", dir, e))
+    });
+
+    // Optional API keys Macs need to host sessions, managed through the
+    // admin API
+    let admin_token = config.get("ADMIN_TOKEN").filter(|v| !v.is_empty());
+    let api_keys = config.flag("REQUIRE_API_KEY").then(|| {
+        let path = config.get("API_KEYS_FILE").filter(|v| !v.is_empty());
+        ApiKeys::load(path.clone().map(Into::into))
+            .unwrap_or_else(|e| invalid(format!("Cannot read API keys from {}: {}", path.unwrap_or_default(), e)))
+    });
+    if api_keys.is_some() && admin_token.is_none() {
+        tracing::warn!("REQUIRE_API_KEY is set without ADMIN_TOKEN: no API keys can be created");
+    }
+    // Optional accounts Macs register with, with limits of their own
+    let accounts = config.flag("ACCOUNTS").then(|| {
+        if api_keys.is_some() {
+            invalid("ACCOUNTS and REQUIRE_API_KEY cannot both be set: account tokens replace API keys");
+        }
+        if admin_token.is_none() {
+            invalid("ACCOUNTS needs an ADMIN_TOKEN to create accounts with");
+        }
+        let path = config.get("ACCOUNTS_FILE").filter(|v| !v.is_empty());
+        Accounts::load(path.clone().map(Into::into))
+            .unwrap_or_else(|e| invalid(format!("Cannot read accounts from {}: {}", path.unwrap_or_default(), e)))
+    });
+
+    // Length and look of session codes
+    let code_format = CodeFormat::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid session code format: {}", e)));
+
+    // Optional Redis shared with other relays behind the same load balancer
+    let cluster = match config.get("REDIS_URL").filter(|v| !v.is_empty()) {
+        Some(url) => {
+            let instance_id = config
+                .get("RELAY_INSTANCE_ID")
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| nanoid::nanoid!(12));
+            let cluster = Cluster::connect(&url, instance_id)
+                .await
+                .unwrap_or_else(|e| panic!("Cannot connect to Redis at {}: {}", url, e));
+            info!("Sharing sessions through Redis as relay {}", cluster.instance_id());
+            Some(cluster)
+        }
+        None => None,
+    };
+
+    // Where browsers reach the web UI, for the join URLs of QR codes
+    let public_url = config
+        .get("PUBLIC_URL")
+        .filter(|v| !v.is_empty())
+        .map(|url| match url.split_once("://") {
+            Some(("http" | "https", host)) if !host.is_empty() => url.trim_end_matches('/').to_string(),
+            _ => invalid(format!("PUBLIC_URL {} is not a URL like https://relay.example.com", url)),
+        });
+
+    // STUN/TURN servers browsers use to reach Macs directly; empty for none
+    let ice_servers = config.get("ICE_SERVERS").map(|servers| {
+        servers
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| match url.split_once(':') {
+                Some(("stun" | "stuns" | "turn" | "turns", rest)) if !rest.is_empty() => url.to_string(),
+                _ => invalid(format!("ICE_SERVERS entry {} is not a stun: or turn: URL", url)),
+            })
+            .collect()
+    });
+
+    // Optional list of relays behind the same load balancer, which route
+    // browsers by session code
+    let affinity = Affinity::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid affinity settings: {}", e)));
+    if let Some(affinity) = &affinity {
+        info!("Handing out session codes of relay {} for load balancer affinity", affinity.instance());
+    }
+
+    // Optional relays of other regions whose browsers can join sessions here
+    // and the other way round
+    let federation = Federation::from_config(&config).unwrap_or_else(|e| invalid(format!("Invalid federation settings: {}", e)));
+    if let Some(federation) = &federation {
+        info!("Federating with {} peer relays", federation.peers().len());
+    }
 
     // Create application state
-    let state = AppState::new();
+    let limits = Limits {
+        max_scrollback,
+        replay_rate,
+        max_browsers_per_client,
+        max_browsers_per_session,
+        join_rate,
+        register_rate,
+        lockout,
+        client_ip_header,
+        max_session_lifetime,
+        session_idle_timeout,
+        code_format,
+        snapshot_history,
+        max_recording_bytes,
+        recording_retention,
+        resume_grace,
+        browser_resume_window,
+        slow_browser,
+        join_filter,
+        register_filter,
+        allowed_origins,
+        quotas,
+        admin_token,
+        chat_history,
+        affinity,
+        public_url,
+        ice_servers,
+    };
+    let state = AppState::with_cluster(limits, cluster.clone(), federation, webhooks, audit, api_keys, accounts);
+
+    // Sessions from before a restart, held for their Macs to come back
+    if let Some(file) = &state_file {
+        match file.take() {
+            Ok(Some(saved)) => {
+                let total = saved.sessions.len();
+                let restored = state.restore_sessions(saved);
+                info!("Restored {} of {} sessions from {}", restored, total, file.path().display());
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("Cannot restore sessions from {}: {}", file.path().display(), e),
+        }
+    }
+
+    if let Some(cluster) = cluster {
+        // Take up browsers linked from other relays
+        let links = cluster.clone();
+        let linked = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = links.serve_links(linked).await {
+                panic!("Lost the Redis subscription for linked browsers: {}", e);
+            }
+        });
+        // Keep the codes of local sessions claimed
+        let claimed = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(cluster::REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = cluster.refresh(&claimed.session_codes()).await {
+                    tracing::warn!("Cannot refresh session code claims: {}", e);
+                }
+            }
+        });
+    }
+
+    // Close expired sessions and forget IPs whose limits have run out
+    let cleanup = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            cleanup.expire_sessions().await;
+            cleanup.prune_rate_limits();
+            cleanup.prune_client_transfer();
+            cleanup.prune_recordings();
+            cleanup.prune_audit().await;
+        }
+    });
 
     // Create embedded asset server with SPA fallback
     // First param: index file for "/" route, Second: fallback behavior for unknown paths
@@ -38,17 +488,129 @@ async fn main() {
         None,
     );
 
-    // Build router
-    let app = Router::new()
+    // HTTP API, with CORS headers for the allowed origins
+    let mut api = Router::new()
+        .route("/api/sessions/{code}/terminals", get(handlers::terminals_handler))
+        .route("/api/sessions/{code}/terminals/{id}/transcript", get(handlers::transcript_handler))
+        .route("/api/sessions/{code}/qr.png", get(handlers::qr_handler))
+        .route("/api/sessions/{code}/events", get(handlers::events_handler))
+        .route("/api/streams/{id}/input", post(handlers::input_handler))
+        .route("/api/affinity/{code}", get(handlers::affinity_handler))
+        .route("/api/audit", get(handlers::audit_handler));
+    if let Some(webtransport) = webtransport {
+        let info = webtransport.info();
+        api = api.route(
+            "/api/webtransport",
+            get(move || async move { axum::Json(info.read().unwrap().clone()) }),
+        );
+        tokio::spawn(webtransport.serve(state.clone()));
+    }
+    if let Some(cors) = cors {
+        api = api.layer(cors);
+    }
+
+    // Build routers: what browsers and Macs use, and what operators do,
+    // which stays off the public listeners once there is an admin one
+    let public = Router::new()
         .route("/ws", get(handlers::ws_handler))
-        .route("/debug/sessions", get(debug_sessions))
+        .route("/playback/{id}", get(handlers::playback_handler))
+        .route("/federation/sessions/{code}", get(handlers::federation_session_handler))
+        .route("/federation/link", get(handlers::federation_link_handler))
+        .merge(api)
         .fallback_service(serve_assets)
-        .with_state(state);
-
-    // Bind and serve
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    info!("Relay server starting on http://{}", addr);
+        .with_state(state.clone());
+    let admin = Router::new()
+        .route(
+            "/api/admin/keys",
+            get(handlers::list_api_keys_handler).post(handlers::create_api_key_handler),
+        )
+        .route("/api/admin/keys/{id}", delete(handlers::revoke_api_key_handler))
+        .route(
+            "/api/admin/accounts",
+            get(handlers::list_accounts_handler).post(handlers::create_account_handler),
+        )
+        .route("/api/admin/accounts/{id}", delete(handlers::delete_account_handler))
+        .route("/api/admin/accounts/{id}/limits", put(handlers::account_limits_handler))
+        .route("/api/admin/accounts/{id}/tokens", post(handlers::create_account_token_handler))
+        .route(
+            "/api/admin/accounts/{id}/tokens/{token_id}",
+            delete(handlers::revoke_account_token_handler),
+        )
+        .route("/debug/sessions", get(debug_sessions))
+        .route("/metrics", get(metrics))
+        .with_state(state.clone());
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Bind and serve until a listener fails or the relay is told to stop
+    let mut servers = tokio::task::JoinSet::new();
+    if let Some(socket) = unix_socket {
+        let listener = socket
+            .bind()
+            .unwrap_or_else(|e| panic!("Cannot listen on {}: {}", socket.path.display(), e));
+        info!("Relay server starting on unix:{}", socket.path.display());
+        let app = public.merge(admin);
+        servers.spawn(async move {
+            if let Err(e) = unix::serve(listener, app).await {
+                panic!("Unix socket server failed: {}", e);
+            }
+        });
+    } else {
+        let separate_admin = listeners.iter().any(|listener| listener.admin);
+        // Listeners with the same certificate share its acceptor, and with
+        // Let's Encrypt a single order and renewal
+        let mut acceptors: Vec<(TlsConfig, Acceptor)> = Vec::new();
+        for listener in listeners {
+            let app = match (separate_admin, listener.admin) {
+                (false, _) => public.clone().merge(admin.clone()),
+                (true, false) => public.clone(),
+                (true, true) => admin.clone(),
+            };
+            let tcp = listener
+                .bind()
+                .unwrap_or_else(|e| panic!("Cannot listen on {}: {}", listener.addr, e));
+            info!(
+                "Relay server starting on {}{}",
+                listener.url(),
+                if listener.admin { " (admin)" } else { "" }
+            );
+            match listener.tls {
+                Some(tls) => {
+                    let acceptor = match acceptors.iter().find(|(config, _)| *config == tls) {
+                        Some((_, acceptor)) => acceptor.clone(),
+                        None => {
+                            let acceptor = Acceptor::new(tls.clone())
+                                .unwrap_or_else(|e| panic!("Cannot set up TLS for {}: {}", listener.addr, e));
+                            acceptors.push((tls, acceptor.clone()));
+                            acceptor
+                        }
+                    };
+                    servers.spawn(async move {
+                        if let Err(e) = tls::serve(tcp, app, acceptor).await {
+                            panic!("HTTPS server on {} failed: {}", listener.addr, e);
+                        }
+                    });
+                }
+                None => {
+                    servers.spawn(async move {
+                        if let Err(e) = axum::serve(tcp, app.into_make_service_with_connect_info::<SocketAddr>()).await {
+                            panic!("HTTP server on {} failed: {}", listener.addr, e);
+                        }
+                    });
+                }
+            }
+        }
+    }
+    tokio::select! {
+        // Listeners only stop by failing, which stops the relay
+        Some(Err(e)) = servers.join_next() => std::panic::resume_unwind(e.into_panic()),
+        _ = shutdown_signal() => info!("Shutting down"),
+    }
+    // Keep the sessions for the next start
+    if let Some(file) = state_file {
+        let sessions = state.saved_sessions();
+        let count = sessions.len();
+        match file.save(sessions) {
+            Ok(()) => info!("Saved {} sessions to {}", count, file.path().display()),
+            Err(e) => tracing::error!("Cannot save sessions to {}: {}", file.path().display(), e),
+        }
+    }
 }
@@ -0,0 +1,327 @@
+//! Counters for the `/metrics` endpoint, in the Prometheus text format.
+//!
+//! Counters and the broadcast latency histogram are kept here and bumped as
+//! traffic flows; gauges (sessions, browsers, scrollback), the bytes of
+//! each client and the tokio runtime's figures are read when scraped.
+
+use dashmap::DashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::protocol::AuthFailure;
+use crate::state::{ClientTransfer, SlowBrowser};
+
+/// Upper bounds of the broadcast latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5];
+
+/// Which way terminal bytes went through the relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Output, from the mac-client to browsers (counted once per browser)
+    ToBrowsers,
+    /// Input, from browsers to the mac-client
+    ToMac,
+}
+
+/// Cumulative histogram with fixed buckets
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sum_nanos.fetch_add(value.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Values read from the state at scrape time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gauges {
+    pub sessions: usize,
+    pub browsers: usize,
+    pub scrollback_bytes: usize,
+    pub recording_bytes: usize,
+    pub clients: Vec<ClientTransfer>,
+    pub runtime: Runtime,
+}
+
+/// What the tokio runtime is up to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Runtime {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks waiting for a worker in the shared queue
+    pub queue_depth: usize,
+    /// Time each worker spent running tasks since the relay started
+    pub busy: Vec<Duration>,
+}
+
+impl Runtime {
+    /// The figures of the runtime the caller runs on
+    pub fn current() -> Self {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        Self {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            queue_depth: metrics.global_queue_depth(),
+            busy: (0..metrics.num_workers()).map(|worker| metrics.worker_total_busy_duration(worker)).collect(),
+        }
+    }
+}
+
+/// Counters of one relay since it started
+#[derive(Debug, Default)]
+pub struct Metrics {
+    registrations: AtomicU64,
+    joins: AtomicU64,
+    join_failures: DashMap<AuthFailure, AtomicU64>,
+    bytes_to_browsers: AtomicU64,
+    bytes_to_mac: AtomicU64,
+    broadcast_latency: Histogram,
+    slow_browsers_resynced: AtomicU64,
+    slow_browsers_disconnected: AtomicU64,
+}
+
+impl Metrics {
+    pub fn registered(&self) {
+        self.registrations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn joined(&self) {
+        self.joins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn join_failed(&self, kind: AuthFailure) {
+        self.join_failures
+            .entry(kind)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn relayed(&self, direction: Direction, bytes: usize) {
+        let counter = match direction {
+            Direction::ToBrowsers => &self.bytes_to_browsers,
+            Direction::ToMac => &self.bytes_to_mac,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Time from receiving an output frame to handing it to every browser
+    pub fn broadcast_took(&self, elapsed: Duration) {
+        self.broadcast_latency.observe(elapsed);
+    }
+
+    /// A browser fell behind the output and was dealt with
+    pub fn slow_browser(&self, action: SlowBrowser) {
+        let counter = match action {
+            SlowBrowser::Resync => &self.slow_browsers_resynced,
+            SlowBrowser::Disconnect => &self.slow_browsers_disconnected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Everything in the Prometheus text exposition format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric("relay_sessions", "gauge", "Registered mac-clients.", gauges.sessions as u64);
+        metric("relay_browsers", "gauge", "Connected browsers.", gauges.browsers as u64);
+        metric(
+            "relay_scrollback_bytes",
+            "gauge",
+            "Scrollback kept for replay, over all sessions.",
+            gauges.scrollback_bytes as u64,
+        );
+        metric(
+            "relay_recording_bytes",
+            "gauge",
+            "Session recordings kept for playback.",
+            gauges.recording_bytes as u64,
+        );
+        metric(
+            "relay_registrations_total",
+            "counter",
+            "Mac-client registrations.",
+            self.registrations.load(Ordering::Relaxed),
+        );
+        metric(
+            "relay_joins_total",
+            "counter",
+            "Browsers let into a session.",
+            self.joins.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(out, "# HELP relay_join_failures_total Browsers turned away, by reason.");
+        let _ = writeln!(out, "# TYPE relay_join_failures_total counter");
+        let mut failures: Vec<(String, u64)> = self
+            .join_failures
+            .iter()
+            .map(|e| (failure_label(*e.key()), e.value().load(Ordering::Relaxed)))
+            .collect();
+        failures.sort();
+        for (reason, count) in failures {
+            let _ = writeln!(out, "relay_join_failures_total{{reason=\"{}\"}} {}", reason, count);
+        }
+
+        let _ = writeln!(out, "# HELP relay_bytes_total Terminal bytes relayed, by direction.");
+        let _ = writeln!(out, "# TYPE relay_bytes_total counter");
+        let _ = writeln!(
+            out,
+            "relay_bytes_total{{direction=\"to_browsers\"}} {}",
+            self.bytes_to_browsers.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "relay_bytes_total{{direction=\"to_mac\"}} {}",
+            self.bytes_to_mac.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP relay_broadcast_seconds Time to hand an output frame to every browser."
+        );
+        let _ = writeln!(out, "# TYPE relay_broadcast_seconds histogram");
+        self.broadcast_latency.render(&mut out, "relay_broadcast_seconds");
+
+        let _ = writeln!(
+            out,
+            "# HELP relay_slow_browsers_total Browsers whose output queue filled up, by what was done about it."
+        );
+        let _ = writeln!(out, "# TYPE relay_slow_browsers_total counter");
+        let _ = writeln!(
+            out,
+            "relay_slow_browsers_total{{action=\"resync\"}} {}",
+            self.slow_browsers_resynced.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "relay_slow_browsers_total{{action=\"disconnect\"}} {}",
+            self.slow_browsers_disconnected.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(out, "# HELP relay_client_bytes_total Terminal bytes relayed, by mac-client.");
+        let _ = writeln!(out, "# TYPE relay_client_bytes_total counter");
+        for client in &gauges.clients {
+            let _ = writeln!(out, "relay_client_bytes_total{{client_id=\"{}\"}} {}", label_value(&client.client_id), client.bytes_total);
+        }
+        let _ = writeln!(
+            out,
+            "# HELP relay_client_period_bytes Terminal bytes relayed in the current quota period, by mac-client."
+        );
+        let _ = writeln!(out, "# TYPE relay_client_period_bytes gauge");
+        for client in &gauges.clients {
+            let _ = writeln!(out, "relay_client_period_bytes{{client_id=\"{}\"}} {}", label_value(&client.client_id), client.bytes_in_period);
+        }
+
+        let runtime = &gauges.runtime;
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric("relay_runtime_workers", "gauge", "Worker threads of the tokio runtime.", runtime.workers as u64);
+        metric("relay_runtime_alive_tasks", "gauge", "Tasks alive in the tokio runtime.", runtime.alive_tasks as u64);
+        metric(
+            "relay_runtime_queue_depth",
+            "gauge",
+            "Tasks waiting in the runtime's shared queue.",
+            runtime.queue_depth as u64,
+        );
+        let _ = writeln!(out, "# HELP relay_runtime_busy_seconds_total Time spent running tasks, by worker.");
+        let _ = writeln!(out, "# TYPE relay_runtime_busy_seconds_total counter");
+        for (worker, busy) in runtime.busy.iter().enumerate() {
+            let _ = writeln!(out, "relay_runtime_busy_seconds_total{{worker=\"{}\"}} {}", worker, busy.as_secs_f64());
+        }
+        out
+    }
+}
+
+/// A label value with quotes, backslashes and line breaks escaped, as client
+/// IDs are whatever the mac-client sent
+fn label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The reason as it appears in `auth_failed` messages
+fn failure_label(kind: AuthFailure) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.registered();
+        metrics.join_failed(AuthFailure::WrongPassword);
+        metrics.join_failed(AuthFailure::WrongPassword);
+        metrics.relayed(Direction::ToMac, 5);
+        metrics.broadcast_took(Duration::from_micros(700));
+        metrics.slow_browser(SlowBrowser::Disconnect);
+        let out = metrics.render(&Gauges {
+            sessions: 1,
+            browsers: 2,
+            scrollback_bytes: 3,
+            recording_bytes: 4,
+            clients: vec![ClientTransfer {
+                client_id: "mac \"1\"".into(),
+                bytes_total: 10,
+                bytes_in_period: 6,
+            }],
+            runtime: Runtime {
+                workers: 2,
+                alive_tasks: 7,
+                queue_depth: 0,
+                busy: vec![Duration::from_millis(1500), Duration::ZERO],
+            },
+        });
+
+        assert!(out.contains("relay_sessions 1\n"));
+        assert!(out.contains("relay_browsers 2\n"));
+        assert!(out.contains("relay_recording_bytes 4\n"));
+        assert!(out.contains("relay_registrations_total 1\n"));
+        assert!(out.contains("relay_join_failures_total{reason=\"wrong_password\"} 2\n"));
+        assert!(out.contains("relay_bytes_total{direction=\"to_mac\"} 5\n"));
+        assert!(out.contains("relay_broadcast_seconds_bucket{le=\"0.0005\"} 0\n"));
+        assert!(out.contains("relay_broadcast_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("relay_broadcast_seconds_count 1\n"));
+        assert!(out.contains("relay_slow_browsers_total{action=\"resync\"} 0\n"));
+        assert!(out.contains("relay_slow_browsers_total{action=\"disconnect\"} 1\n"));
+        assert!(out.contains("relay_client_bytes_total{client_id=\"mac \\\"1\\\"\"} 10\n"));
+        assert!(out.contains("relay_client_period_bytes{client_id=\"mac \\\"1\\\"\"} 6\n"));
+        assert!(out.contains("relay_runtime_alive_tasks 7\n"));
+        assert!(out.contains("relay_runtime_busy_seconds_total{worker=\"0\"} 1.5\n"));
+        assert!(out.contains("relay_runtime_busy_seconds_total{worker=\"1\"} 0\n"));
+    }
+}
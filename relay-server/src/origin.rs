@@ -0,0 +1,167 @@
+//! Which web pages may talk to the relay.
+//!
+//! Browsers let any page open a WebSocket to any host, sending the page's
+//! `Origin` along; without a check, a site a victim happens to visit could
+//! join sessions with the victim's network position. The relay therefore
+//! only upgrades browser connections from its own web UI (the origin whose
+//! host is the request's `Host`) and from origins listed in
+//! `ALLOWED_ORIGINS`, such as a web UI hosted on another domain. Clients
+//! that send no `Origin` at all, like the mac-client and scripts, are not
+//! browsers and pass.
+//!
+//! The same list decides which origins get CORS headers on the HTTP API.
+
+use axum::http::{header, HeaderMap, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::Config;
+
+/// Origins let in besides the relay's own
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// Only pages served by the relay itself
+    #[default]
+    SameOrigin,
+    /// These too, like `https://term.example.com`
+    List(Vec<String>),
+    /// Every page, as relays did before origins were checked
+    Any,
+}
+
+impl AllowedOrigins {
+    /// Origins from `ALLOWED_ORIGINS`: comma-separated, or `*` for any
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Self::parse(&config.get("ALLOWED_ORIGINS").unwrap_or_default())
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let origins: Vec<&str> = value.split(',').map(str::trim).filter(|origin| !origin.is_empty()).collect();
+        if origins.contains(&"*") {
+            return Ok(Self::Any);
+        }
+        if origins.is_empty() {
+            return Ok(Self::SameOrigin);
+        }
+        origins
+            .into_iter()
+            .map(|origin| {
+                let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+                match origin.split_once("://") {
+                    Some((_, host)) if !host.is_empty() && !host.contains('/') => Ok(origin),
+                    _ => Err(format!("{} is not an origin like https://example.com", origin)),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self::List)
+    }
+
+    /// Whether a browser request with these headers may go ahead
+    pub fn permits(&self, headers: &HeaderMap) -> bool {
+        let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+        self.permits_origin(headers.get(header::ORIGIN), |origin_host| {
+            host.is_some_and(|host| origin_host.eq_ignore_ascii_case(host))
+        })
+    }
+
+    /// Whether a WebTransport session to `authority` may go ahead. The
+    /// relay's own pages are on another port of the same host there.
+    pub fn permits_webtransport(&self, origin: Option<&HeaderValue>, authority: &str) -> bool {
+        self.permits_origin(origin, |origin_host| {
+            hostname(origin_host).eq_ignore_ascii_case(hostname(authority))
+        })
+    }
+
+    fn permits_origin(&self, origin: Option<&HeaderValue>, own_host: impl Fn(&str) -> bool) -> bool {
+        let Some(origin) = origin else {
+            return true;
+        };
+        if *self == Self::Any {
+            return true;
+        }
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.to_ascii_lowercase();
+        let same_origin = origin.split_once("://").is_some_and(|(_, origin_host)| own_host(origin_host));
+        same_origin || matches!(self, Self::List(origins) if origins.contains(&origin))
+    }
+
+    /// CORS headers for the HTTP API; None when only the relay's own pages
+    /// use it, which need none
+    pub fn cors(&self) -> Option<CorsLayer> {
+        let allow = match self {
+            Self::SameOrigin => return None,
+            Self::List(origins) => AllowOrigin::list(
+                origins.iter().map(|origin| HeaderValue::from_str(origin).expect("origins are checked on parsing")),
+            ),
+            Self::Any => AllowOrigin::any(),
+        };
+        Some(
+            CorsLayer::new()
+                .allow_origin(allow)
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]),
+        )
+    }
+}
+
+/// `host` of `host:port`
+fn hostname(authority: &str) -> &str {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(origin: Option<&str>, host: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, host.parse().unwrap());
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, origin.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(AllowedOrigins::parse("").unwrap(), AllowedOrigins::SameOrigin);
+        assert_eq!(AllowedOrigins::parse("https://a.example, *").unwrap(), AllowedOrigins::Any);
+        assert_eq!(
+            AllowedOrigins::parse("https://Term.Example.com/, http://localhost:5173").unwrap(),
+            AllowedOrigins::List(vec!["https://term.example.com".into(), "http://localhost:5173".into()])
+        );
+        assert!(AllowedOrigins::parse("term.example.com").is_err());
+        assert!(AllowedOrigins::parse("https://term.example.com/app").is_err());
+    }
+
+    #[test]
+    fn test_permits() {
+        let same = AllowedOrigins::SameOrigin;
+        // The mac-client sends no Origin
+        assert!(same.permits(&headers(None, "relay.example.com")));
+        assert!(same.permits(&headers(Some("https://relay.example.com"), "relay.example.com")));
+        assert!(!same.permits(&headers(Some("https://evil.example"), "relay.example.com")));
+        assert!(!same.permits(&headers(Some("null"), "relay.example.com")));
+
+        let list = AllowedOrigins::parse("https://term.example.com").unwrap();
+        assert!(list.permits(&headers(Some("https://term.example.com"), "relay.example.com")));
+        assert!(list.permits(&headers(Some("https://relay.example.com"), "relay.example.com")));
+        assert!(!list.permits(&headers(Some("http://term.example.com"), "relay.example.com")));
+
+        assert!(AllowedOrigins::Any.permits(&headers(Some("https://evil.example"), "relay.example.com")));
+    }
+
+    #[test]
+    fn test_permits_webtransport() {
+        let same = AllowedOrigins::SameOrigin;
+        let origin = |origin: &'static str| Some(HeaderValue::from_static(origin));
+        assert!(same.permits_webtransport(None, "relay.example.com:4433"));
+        assert!(same.permits_webtransport(origin("https://relay.example.com").as_ref(), "relay.example.com:4433"));
+        assert!(same.permits_webtransport(origin("http://[::1]:3000").as_ref(), "[::1]:4433"));
+        assert!(!same.permits_webtransport(origin("https://evil.example").as_ref(), "relay.example.com:4433"));
+    }
+}
@@ -1,4 +1,20 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Version of the protocol this relay speaks: the control messages and the
+/// binary frame format. Clients send theirs in `register` and `auth` and
+/// are answered with the version both sides use.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest client version the relay still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The version to speak with a client that sent `version`, or None if the
+/// client is too old. Clients from before versioning send none and speak 1.
+pub fn negotiate_version(version: Option<u32>) -> Option<u32> {
+    let version = version.unwrap_or(1).min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
 
 /// Control messages sent as JSON over WebSocket Text frames.
 /// Terminal I/O is sent as Binary frames (not wrapped in ControlMessage).
@@ -6,38 +22,544 @@ use serde::{Deserialize, Serialize};
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ControlMessage {
     // Mac-client -> Relay
-    Register { client_id: String },
+    /// `client_id` is stable across restarts; `name` is shown to browsers.
+    /// With a `password`, browsers have to send it in their `auth`. With
+    /// `passthrough`, frame payloads are ciphertext the relay never reads.
+    /// `version` is the newest protocol version the client speaks;
+    /// `scrollback_bytes` caps the output kept per terminal for replay
+    /// below the relay's own cap, 0 keeping none. With `zero_retention`
+    /// the relay keeps nothing the session relays, in memory or on disk.
+    Register {
+        client_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        passthrough: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        /// From the last `registered`: reclaims that session code, its
+        /// scrollback and browsers if the relay still holds them
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scrollback_bytes: Option<u64>,
+        /// Needed on relays that only host sessions for Macs they gave a key
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        api_key: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        zero_retention: bool,
+    },
+    /// Do Not Disturb: while paused the relay refuses new browsers
+    SharingPaused { paused: bool },
+    /// While required, new browsers wait for an `approve_browser` before
+    /// they receive anything
+    ApprovalRequired { required: bool },
+    /// Answer to an `approval_request`; `role` lets the browser in with
+    /// less than it asked for
+    ApproveBrowser {
+        browser_id: String,
+        approved: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+    },
+    /// The most browsers joining from now on get (default: controller)
+    BrowserRole { role: Role },
+    /// Grant or revoke input for one connected browser
+    SetRole { browser_id: String, role: Role },
+    /// Mint a join link token that works for `ttl_secs` (default: ten
+    /// minutes) and lets in `uses` browsers (default: one), with at most
+    /// `role` (default: controller)
+    CreateJoinToken {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_secs: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uses: Option<u32>,
+    },
+    /// Start or stop recording the session's output on the relay
+    SetRecording { enabled: bool },
+    /// Allow or refuse compressed output for browsers joining from now on
+    /// (default: allowed, except for passthrough sessions)
+    SetCompression { enabled: bool },
+    /// Browsers allowed in this session at once; None falls back to the
+    /// relay's limit. Never above the relay's limit
+    SetBrowserLimit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<usize>,
+    },
 
     // Relay -> Mac-client
-    Registered { code: String },
-    BrowserConnected { browser_id: String },
+    /// `version` is the protocol version to speak from now on
+    /// `resume_token` reclaims the code after the connection drops
+    Registered {
+        code: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    /// Answer to `create_join_token`, with the scope the relay granted
+    JoinToken {
+        token: String,
+        expires_in_secs: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        uses: Option<u32>,
+    },
+    /// The session is being recorded as `recording_id`, or not (also sent
+    /// to browsers, so they know)
+    RecordingState {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recording_id: Option<String>,
+    },
+    /// `role` is what the browser may do
+    BrowserConnected {
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+    },
     BrowserDisconnected { browser_id: String },
+    /// Everyone connected to the session, oldest first; sent to the
+    /// mac-client and every browser whenever that changes. Browsers are
+    /// not told the IPs.
+    Presence { viewers: Vec<Viewer> },
+    /// A browser is waiting for approval; `browser_key` is the random key the
+    /// browser keeps across visits, `user_agent` its User-Agent header
+    ApprovalRequest {
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user_agent: Option<String>,
+    },
+    /// A browser waiting for approval left or timed out
+    ApprovalCancelled { browser_id: String },
 
     // Browser -> Relay
-    Auth { session_code: String },
+    Auth {
+        session_code: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser_key: Option<String>,
+        /// Password (or bearer token) the Mac registered with, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        /// Role asked for, e.g. from a view-only join link (default: controller)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        /// One-time token from a join link; lets the browser in without
+        /// the password or approval
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        /// Newest protocol version the browser speaks
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        /// Compression the browser can inflate terminal output with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<FrameCompression>,
+        /// From the `auth_success` of an earlier connection: comes back as
+        /// that browser and gets only the output it missed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        /// Name to show the others watching
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nickname: Option<String>,
+        /// Only the newest this many bytes of scrollback, for a slow
+        /// connection
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replay_bytes: Option<u64>,
+    },
 
     // Relay -> Browser
-    AuthSuccess,
-    AuthFailed { reason: String },
+    /// `client_name` is the display name of the Mac the code belongs to;
+    /// `role` what the browser may do (the relay drops a viewer's input);
+    /// `version` the protocol version to speak from now on; `compression`
+    /// is set when every binary frame to the browser is compressed;
+    /// `resume_token` is for the browser's next `auth` after its connection
+    /// drops, and `resumed` is set when it was used: the scrollback that
+    /// follows is only the output the browser missed
+    AuthSuccess {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<FrameCompression>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        resumed: bool,
+        /// STUN/TURN URLs for a direct link to the Mac, if the relay sets
+        /// them
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ice_servers: Option<Vec<String>>,
+    },
+    /// `reason` is shown to the user; `kind` tells why for browsers that
+    /// react, e.g. by asking for a password
+    AuthFailed {
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<AuthFailure>,
+    },
+    /// The Mac has to approve this browser before it is let in
+    AwaitingApproval,
+    /// The Mac changed what this browser may do
+    RoleChanged { role: Role },
+    /// The Mac's connection dropped and the relay holds the session for it
+    /// to come back (false), or it came back (true)
+    MacStatus { connected: bool },
+    /// This browser fell behind and missed output the relay no longer
+    /// keeps: the scrollback follows again, to show instead of what its
+    /// terminals have
+    Resync,
+    /// The session keeps no output: what this browser sees starts now
+    LiveFromNow,
+    /// `bytes` of scrollback follow as binary frames, paced so a slow
+    /// connection keeps up, then `replay_end`
+    ReplayStart { bytes: u64 },
+    ReplayEnd,
 
     // Browser -> Relay -> Mac-client
     CloseSession { session_id: String },
     CreateSession,
+    ListSessions,
+    RenameSession { session_id: String, name: String },
+    /// The size a browser would like; the relay fills in `browser_id` so
+    /// the Mac can weigh every browser's size
+    ResizeSession {
+        #[serde(default)]
+        browser_id: String,
+        session_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    /// Paired browser asks for the output key, with an ephemeral P-256 public key (base64)
+    E2eHello { public_key: String },
+    /// Search the scrollback of one session, or of every shared session when
+    /// `session_id` is left out. Browsers leave `browser_id` out; the relay
+    /// fills it in so the `search_results` come back to that browser only
+    Search {
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        q: String,
+    },
+
+    // Browser -> Relay
+    /// List the recordings of this Mac
+    ListRecordings,
+    /// Change the name shown to the others watching (none: unnamed)
+    SetNickname {
+        #[serde(default)]
+        nickname: Option<String>,
+    },
+    // Relay -> Browser
+    /// Answer to `list_recordings`, oldest first
+    RecordingList { recordings: Vec<RecordingInfo> },
+
+    // Browser <-> Relay on a `/playback/{id}` socket
+    /// Change the playback speed (1.0: as recorded)
+    PlaybackSpeed { speed: f64 },
+    /// Every recorded frame was played
+    PlaybackEnded,
 
     // Mac-client -> Relay -> Browser (session list on connect)
     SessionList { sessions: Vec<SessionInfo> },
     SessionConnected { session_id: String, name: String },
     SessionDisconnected { session_id: String },
+    SessionRenamed { session_id: String, name: String },
+    /// What changed about a terminal; fields left out stay as they were
+    SessionMetadata {
+        session_id: String,
+        #[serde(flatten)]
+        metadata: TerminalMetadata,
+    },
+    SessionError { session_id: String, message: String },
     SessionResize { session_id: String, cols: u16, rows: u16 },
+    /// OSC 52 copy from a shell; `data` is the base64 payload as sent by the program
+    Clipboard { session_id: String, data: String },
+    /// Terminal output is end-to-end encrypted; `fingerprint` identifies the Mac's key
+    E2eRequired { fingerprint: String },
+    /// Output key wrapped for the browser whose `e2e_hello` carried `public_key`
+    E2eKey { public_key: String, key: String },
+    /// Matches of a `search`, newest first; `truncated` when there were more
+    SearchResults {
+        browser_id: String,
+        q: String,
+        matches: Vec<SearchMatch>,
+        truncated: bool,
+    },
+
+    // WebRTC signaling for direct mode: Browser <-> Relay <-> Mac-client.
+    // Browsers leave `browser_id` out; the relay fills it in on the way to
+    // the mac-client and uses it to deliver the reply to that browser only.
+    /// SDP offer for a data channel from the browser
+    RtcOffer {
+        #[serde(default)]
+        browser_id: String,
+        sdp: String,
+    },
+    /// SDP answer from the mac-client
+    RtcAnswer { browser_id: String, sdp: String },
+    /// Trickled ICE candidate, in either direction
+    RtcCandidate {
+        #[serde(default)]
+        browser_id: String,
+        candidate: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sdp_mid: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sdp_mline_index: Option<u16>,
+    },
+    /// Mac-client -> Relay: the browser's data channel opened (or closed);
+    /// while active the relay stops forwarding terminal output to it
+    RtcDirect { browser_id: String, active: bool },
+
+    // Round-trip latency: Browser -> Relay -> Mac-client -> Relay -> Browser.
+    // The relay fills in `browser_id` and `relay_at` (its Unix time in
+    // milliseconds) on the way to the mac-client, which echoes both back at
+    // once; the relay adds `mac_rtt_ms`, the part of the trip between it
+    // and the Mac, before handing the echo to that browser only.
+    /// A probe; `sent_at` is the browser's clock, `rtt_ms` the round trip
+    /// it measured last, which the relay keeps for percentiles
+    LatencyProbe {
+        id: u64,
+        sent_at: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rtt_ms: Option<f64>,
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        relay_at: Option<u64>,
+    },
+    /// The probe coming back
+    LatencyEcho {
+        id: u64,
+        sent_at: f64,
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        relay_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mac_rtt_ms: Option<f64>,
+    },
 
     // Bidirectional
     Error { message: String },
+    /// A chat line of the session. Browsers send `text` and the `name` to
+    /// show, if any; the relay fills in `browser_id` and `at` (Unix
+    /// milliseconds) and passes it to every browser and the mac-client
+    Chat {
+        #[serde(default)]
+        browser_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        text: String,
+        #[serde(default)]
+        at: u64,
+    },
+
+    /// A message of a type this relay does not know, from a newer client;
+    /// never sent
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl ControlMessage {
+    /// The message as sent in a Text frame
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("control messages serialize")
+    }
+
+    /// The `type` of the message, for logs that must not show its contents
+    pub fn kind(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|v| v.get("type")?.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+/// What a browser may do in a session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Types into shells and manages sessions
+    #[default]
+    Controller,
+    /// Watches only: the relay drops its input and session commands
+    Viewer,
+}
+
+impl Role {
+    /// The lesser of two roles
+    pub fn min(self, other: Role) -> Role {
+        if self == Role::Viewer || other == Role::Viewer {
+            Role::Viewer
+        } else {
+            Role::Controller
+        }
+    }
+}
+
+/// How binary frames to a browser are compressed (see `compression.rs`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameCompression {
+    /// One raw deflate stream, flushed after every frame
+    Deflate,
+}
+
+/// Why a browser was not let in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailure {
+    /// No Mac is registered with the session code
+    InvalidCode,
+    /// The Mac requires a password and none was sent
+    PasswordRequired,
+    WrongPassword,
+    /// Do Not Disturb is on at the Mac
+    SharingPaused,
+    /// The Mac's browser limit is reached
+    TooManyBrowsers,
+    /// The session's browser limit is reached
+    SessionFull,
+    /// The Mac denied the browser, or nobody approved it in time
+    NotApproved,
+    /// Too many attempts from the browser's IP; try again later
+    RateLimited,
+    /// The browser's IP is outside the ranges the relay lets join
+    IpNotAllowed,
+    /// The join link expired or was already used, or another connection
+    /// came back with the browser's resume token
+    InvalidToken,
+    /// The browser speaks a protocol version the relay no longer understands
+    UnsupportedVersion,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct SessionInfo {
     pub id: String,
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Git repository or top-level directory the shell is in, for grouping
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Window title the program in the terminal set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Changes to a terminal's [`SessionInfo`], as `session_metadata` carries them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TerminalMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl TerminalMetadata {
+    /// Apply the changes to `info`
+    pub fn apply(&self, info: &mut SessionInfo) {
+        if let Some(name) = &self.name {
+            info.name = name.clone();
+        }
+        let fields = [
+            (&self.shell, &mut info.shell),
+            (&self.cwd, &mut info.cwd),
+            (&self.project, &mut info.project),
+            (&self.title, &mut info.title),
+        ];
+        for (changed, field) in fields {
+            if changed.is_some() {
+                field.clone_from(changed);
+            }
+        }
+    }
+}
+
+/// A recording kept on the relay.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RecordingInfo {
+    /// Plays back at `/playback/{id}`
+    pub id: String,
+    /// Unix time the recording started, in seconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub bytes: u64,
+    /// Still recording
+    pub active: bool,
+    /// Output was left out because the recording reached the relay's cap
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// A terminal session as `GET /api/sessions/{code}/terminals` lists it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TerminalInfo {
+    #[serde(flatten)]
+    pub session: SessionInfo,
+    /// Unix time the relay first heard of the terminal, in seconds
+    pub attached_at: u64,
+}
+
+/// A browser connected to a session, as `presence` lists it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Viewer {
+    pub browser_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    pub role: Role,
+    /// Unix time the browser joined, in seconds
+    pub joined_at: u64,
+    /// Where the browser connects from; only the mac-client is told
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+}
+
+impl Viewer {
+    /// A browser joining now, with no name and no known address
+    pub fn new(browser_id: impl Into<String>, role: Role, joined_at: u64) -> Self {
+        Self {
+            browser_id: browser_id.into(),
+            nickname: None,
+            role,
+            joined_at,
+            ip: None,
+        }
+    }
+}
+
+/// A scrollback line containing a search term.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SearchMatch {
+    pub session_id: String,
+    /// Lines between this one and the end of the scrollback (0: the last line)
+    pub line: usize,
+    /// Character offset of the match in the line
+    pub column: usize,
+    /// The line as plain text, shortened around the match
+    pub snippet: String,
 }
 
 #[cfg(test)]
@@ -46,15 +568,26 @@ mod tests {
 
     #[test]
     fn test_serialize_register() {
-        let msg = ControlMessage::Register { client_id: "test".into() };
+        let msg = ControlMessage::Register {
+            client_id: "test".into(),
+            name: None,
+            password: None,
+            passthrough: false,
+            version: Some(PROTOCOL_VERSION),
+            resume_token: None,
+            scrollback_bytes: None,
+            api_key: None,
+            zero_retention: false,
+        };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
         assert!(json.contains("\"client_id\":\"test\""));
+        assert!(json.contains("\"version\":1"));
     }
 
     #[test]
     fn test_serialize_registered() {
-        let msg = ControlMessage::Registered { code: "ABC123".into() };
+        let msg = ControlMessage::Registered { code: "ABC123".into(), version: None, resume_token: None };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"registered\""));
         assert!(json.contains("\"code\":\"ABC123\""));
@@ -62,9 +595,67 @@ mod tests {
 
     #[test]
     fn test_serialize_auth_success() {
-        let msg = ControlMessage::AuthSuccess;
+        let msg = ControlMessage::AuthSuccess {
+            client_name: None,
+            role: None,
+            version: None,
+            compression: None,
+            resume_token: None,
+            resumed: false,
+            ice_servers: None,
+        };
         let json = serde_json::to_string(&msg).unwrap();
         assert_eq!(json, "{\"type\":\"auth_success\"}");
+
+        let msg = ControlMessage::AuthSuccess {
+            client_name: None,
+            role: None,
+            version: None,
+            compression: Some(FrameCompression::Deflate),
+            resume_token: None,
+            resumed: false,
+            ice_servers: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"auth_success","compression":"deflate"}"#);
+    }
+
+    #[test]
+    fn test_register_name_is_optional() {
+        let json = r#"{"type":"register","client_id":"c1"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::Register {
+                ref client_id,
+                name: None,
+                password: None,
+                passthrough: false,
+                version: None,
+                resume_token: None,
+                scrollback_bytes: None,
+                api_key: None,
+                zero_retention: false,
+            }
+                if client_id == "c1"
+        ));
+
+        let json = r#"{"type":"register","client_id":"c1","passthrough":true,"zero_retention":true}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Register { passthrough: true, zero_retention: true, .. }));
+        assert_eq!(msg.kind(), "register");
+
+        let msg = ControlMessage::AuthSuccess {
+            client_name: Some("Studio Mac".into()),
+            role: None,
+            version: None,
+            compression: None,
+            resume_token: None,
+            resumed: false,
+            ice_servers: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"auth_success","client_name":"Studio Mac"}"#);
     }
 
     #[test]
@@ -72,21 +663,270 @@ mod tests {
         let json = r#"{"type":"auth","session_code":"XYZ789"}"#;
         let msg: ControlMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ControlMessage::Auth { session_code } => {
+            ControlMessage::Auth { session_code, browser_key, password, role, token, version, compression, resume_token, nickname, replay_bytes } => {
                 assert_eq!(session_code, "XYZ789");
+                assert_eq!(browser_key, None);
+                assert_eq!(password, None);
+                assert_eq!(role, None);
+                assert_eq!(token, None);
+                assert_eq!(version, None);
+                assert_eq!(compression, None);
+                assert_eq!(resume_token, None);
+                assert_eq!(nickname, None);
+                assert_eq!(replay_bytes, None);
             }
             _ => panic!("Expected Auth message"),
         }
     }
 
+    #[test]
+    fn test_auth_failed_kind() {
+        let msg = ControlMessage::AuthFailed {
+            reason: "Wrong password".into(),
+            kind: Some(AuthFailure::WrongPassword),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"auth_failed","reason":"Wrong password","kind":"wrong_password"}"#);
+
+        let json = r#"{"type":"auth","session_code":"XYZ789","password":"hunter2"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Auth { password: Some(ref p), .. } if p == "hunter2"));
+    }
+
     #[test]
     fn test_session_info() {
         let info = SessionInfo {
             id: "sess_1".into(),
             name: "My Session".into(),
+            ..Default::default()
         };
         let json = serde_json::to_string(&info).unwrap();
         assert!(json.contains("\"id\":\"sess_1\""));
         assert!(json.contains("\"name\":\"My Session\""));
+        assert!(!json.contains("shell"));
+    }
+
+    #[test]
+    fn test_session_metadata_is_a_delta() {
+        let json = r#"{"type":"session_metadata","session_id":"s1","title":"vim notes.md"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        let ControlMessage::SessionMetadata { session_id, metadata } = &msg else {
+            panic!("expected session_metadata, got {:?}", msg);
+        };
+        assert_eq!(session_id, "s1");
+        assert_eq!(serde_json::to_string(&msg).unwrap(), json);
+
+        let mut info = SessionInfo {
+            id: "s1".into(),
+            name: "zsh".into(),
+            cwd: Some("/tmp".into()),
+            ..Default::default()
+        };
+        metadata.apply(&mut info);
+        assert_eq!(info.title.as_deref(), Some("vim notes.md"));
+        assert_eq!(info.cwd.as_deref(), Some("/tmp"));
+        assert_eq!(info.name, "zsh");
+    }
+
+    #[test]
+    fn test_deserialize_sharing_paused() {
+        let json = r#"{"type":"sharing_paused","paused":true}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::SharingPaused { paused: true }));
+    }
+
+    #[test]
+    fn test_approval_messages() {
+        let json = r#"{"type":"auth","session_code":"XYZ789","browser_key":"k1"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Auth { browser_key: Some(ref k), .. } if k == "k1"));
+
+        let msg = ControlMessage::ApprovalRequest {
+            browser_id: "b1".into(),
+            browser_key: None,
+            user_agent: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"approval_request","browser_id":"b1"}"#);
+
+        let json = r#"{"type":"approve_browser","browser_id":"b1","approved":false}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::ApproveBrowser { approved: false, .. }));
+    }
+
+    #[test]
+    fn test_rtc_offer_from_browser_has_no_browser_id() {
+        let json = r#"{"type":"rtc_offer","sdp":"v=0"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::RtcOffer { ref browser_id, ref sdp } if browser_id.is_empty() && sdp == "v=0"
+        ));
+    }
+
+    #[test]
+    fn test_search_from_browser_has_no_browser_id() {
+        let json = r#"{"type":"search","q":"error"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::Search { ref browser_id, session_id: None, ref q } if browser_id.is_empty() && q == "error"
+        ));
+    }
+
+    #[test]
+    fn test_latency_probe_from_browser() {
+        let json = r#"{"type":"latency_probe","id":3,"sent_at":1234.5,"rtt_ms":42.0}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::LatencyProbe { id: 3, rtt_ms: Some(_), ref browser_id, relay_at: None, .. } if browser_id.is_empty()
+        ));
+
+        let msg = ControlMessage::LatencyEcho {
+            id: 3,
+            sent_at: 1234.5,
+            browser_id: "b1".into(),
+            relay_at: None,
+            mac_rtt_ms: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"latency_echo","id":3,"sent_at":1234.5,"browser_id":"b1"}"#);
+    }
+
+    #[test]
+    fn test_resize_from_browser_has_no_browser_id() {
+        let json = r#"{"type":"resize_session","session_id":"s1","cols":100,"rows":30}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::ResizeSession { ref browser_id, cols: 100, rows: 30, .. } if browser_id.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_roles() {
+        let json = r#"{"type":"auth","session_code":"XYZ789","role":"viewer"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Auth { role: Some(Role::Viewer), .. }));
+
+        let msg = ControlMessage::AuthSuccess {
+            client_name: None,
+            role: Some(Role::Controller),
+            version: None,
+            compression: None,
+            resume_token: None,
+            resumed: false,
+            ice_servers: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"type":"auth_success","role":"controller"}"#);
+
+        let json = r#"{"type":"approve_browser","browser_id":"b1","approved":true,"role":"viewer"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::ApproveBrowser { role: Some(Role::Viewer), .. }));
+
+        let json = r#"{"type":"set_role","browser_id":"b1","role":"controller"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::SetRole { role: Role::Controller, .. }));
+
+        let msg = ControlMessage::RoleChanged { role: Role::Viewer };
+        assert_eq!(serde_json::to_string(&msg).unwrap(), r#"{"type":"role_changed","role":"viewer"}"#);
+
+        let json = r#"{"type":"create_join_token","ttl_secs":3600,"role":"viewer","uses":20}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::CreateJoinToken { ttl_secs: Some(3600), role: Some(Role::Viewer), uses: Some(20) }));
+        let msg: ControlMessage = serde_json::from_str(r#"{"type":"create_join_token"}"#).unwrap();
+        assert!(matches!(msg, ControlMessage::CreateJoinToken { ttl_secs: None, role: None, uses: None }));
+
+        assert_eq!(Role::Controller.min(Role::Viewer), Role::Viewer);
+        assert_eq!(Role::Controller.min(Role::Controller), Role::Controller);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(None), Some(1));
+        assert_eq!(negotiate_version(Some(PROTOCOL_VERSION)), Some(PROTOCOL_VERSION));
+        // A newer client is answered with the relay's version
+        assert_eq!(negotiate_version(Some(PROTOCOL_VERSION + 5)), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(Some(0)), None);
+
+        let json = r#"{"type":"registered","code":"ABC123","version":1}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Registered { version: Some(1), .. }));
+    }
+
+    #[test]
+    fn test_recording_messages() {
+        let json = r#"{"type":"set_recording","enabled":true}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::SetRecording { enabled: true }));
+
+        let msg = ControlMessage::RecordingState { recording_id: None };
+        assert_eq!(serde_json::to_string(&msg).unwrap(), r#"{"type":"recording_state"}"#);
+
+        let json = r#"{"type":"playback_speed","speed":2.5}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::PlaybackSpeed { speed } if speed == 2.5));
+    }
+
+    #[test]
+    fn test_replay_messages() {
+        let json = r#"{"type":"auth","session_code":"ABC234","replay_bytes":65536}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Auth { replay_bytes: Some(65536), .. }));
+
+        assert_eq!(ControlMessage::ReplayStart { bytes: 4096 }.to_text(), r#"{"type":"replay_start","bytes":4096}"#);
+        assert_eq!(ControlMessage::ReplayEnd.to_text(), r#"{"type":"replay_end"}"#);
+    }
+
+    #[test]
+    fn test_chat() {
+        let json = r#"{"type":"chat","text":"see line 40"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Chat { ref browser_id, name: None, ref text, at: 0 } if browser_id.is_empty() && text == "see line 40"));
+
+        let msg = ControlMessage::Chat { browser_id: "b1".into(), name: Some("Ana".into()), text: "hi".into(), at: 5 };
+        assert_eq!(msg.to_text(), r#"{"type":"chat","browser_id":"b1","name":"Ana","text":"hi","at":5}"#);
+    }
+
+    #[test]
+    fn test_presence() {
+        let json = r#"{"type":"set_nickname","nickname":"Ana"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::SetNickname { nickname: Some(ref name) } if name == "Ana"));
+
+        let viewer = Viewer {
+            nickname: Some("Ana".into()),
+            ip: Some("203.0.113.5".parse().unwrap()),
+            ..Viewer::new("b1", Role::Viewer, 1760000000)
+        };
+        let msg = ControlMessage::Presence { viewers: vec![viewer] };
+        assert_eq!(
+            msg.to_text(),
+            r#"{"type":"presence","viewers":[{"browser_id":"b1","nickname":"Ana","role":"viewer","joined_at":1760000000,"ip":"203.0.113.5"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_unknown_types_parse() {
+        let json = r#"{"type":"from_the_future","answer":42}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(msg, ControlMessage::Unknown));
+        // Known types still have to be well formed
+        assert!(serde_json::from_str::<ControlMessage>(r#"{"type":"set_role","role":"root"}"#).is_err());
+
+        let msg = ControlMessage::SessionResize { session_id: "s1".into(), cols: 80, rows: 24 };
+        assert_eq!(msg.to_text(), r#"{"type":"session_resize","session_id":"s1","cols":80,"rows":24}"#);
+    }
+
+    #[test]
+    fn test_deserialize_rename_session() {
+        let json = r#"{"type":"rename_session","session_id":"s1","name":"build"}"#;
+        let msg: ControlMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ControlMessage::RenameSession { ref session_id, ref name } if session_id == "s1" && name == "build"
+        ));
     }
 }
@@ -0,0 +1,98 @@
+//! QR codes of join URLs, as PNG images.
+//!
+//! For clients that cannot draw QR codes themselves: a command line tool or
+//! a popover asks the relay for `/api/sessions/{code}/qr.png`. The image is
+//! black and white, one bit per pixel, so it is written out directly
+//! instead of through an image library.
+
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use std::io::Write;
+
+/// Pixels per module
+const SCALE: usize = 8;
+
+/// Light modules of the quiet zone around the code, per side
+const QUIET_ZONE: usize = 4;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// `text` as a QR code in a PNG image
+pub fn png(text: &str) -> Result<Vec<u8>, String> {
+    let code = qrcode::QrCode::new(text.as_bytes()).map_err(|e| e.to_string())?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let side = (width + 2 * QUIET_ZONE) * SCALE;
+
+    // Rows of packed bits, 1 for light, each after a "no filter" byte
+    let row_bytes = side.div_ceil(8);
+    let mut pixels = Vec::with_capacity(side * (row_bytes + 1));
+    for y in 0..side {
+        pixels.push(0);
+        let mut row = vec![0xff; row_bytes];
+        let my = (y / SCALE).checked_sub(QUIET_ZONE).filter(|&my| my < width);
+        for x in 0..side {
+            let mx = (x / SCALE).checked_sub(QUIET_ZONE).filter(|&mx| mx < width);
+            if let (Some(mx), Some(my)) = (mx, my) {
+                if colors[my * width + mx] == qrcode::Color::Dark {
+                    row[x / 8] &= !(0x80 >> (x % 8));
+                }
+            }
+        }
+        pixels.extend_from_slice(&row);
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&pixels).map_err(|e| e.to_string())?;
+    let idat = zlib.finish().map_err(|e| e.to_string())?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(side as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(side as u32).to_be_bytes());
+    // One bit per pixel, grayscale, deflate, no filter choice, no interlace
+    ihdr.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    let mut image = PNG_SIGNATURE.to_vec();
+    chunk(&mut image, b"IHDR", &ihdr);
+    chunk(&mut image, b"IDAT", &idat);
+    chunk(&mut image, b"IEND", &[]);
+    Ok(image)
+}
+
+/// Append a chunk: length, type, data and the CRC of type and data
+fn chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    image.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_png_holds_the_code() {
+        let image = png("https://relay.example.com/login#code=ABC234").unwrap();
+        assert!(image.starts_with(PNG_SIGNATURE));
+        assert!(image.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        let side = u32::from_be_bytes(image[16..20].try_into().unwrap()) as usize;
+        let width = qrcode::QrCode::new(b"https://relay.example.com/login#code=ABC234").unwrap().width();
+        assert_eq!(side, (width + 2 * QUIET_ZONE) * SCALE);
+
+        let idat_len = u32::from_be_bytes(image[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&image[37..41], b"IDAT");
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&image[41..41 + idat_len]).read_to_end(&mut pixels).unwrap();
+        let row_bytes = side.div_ceil(8);
+        assert_eq!(pixels.len(), side * (row_bytes + 1));
+        // The quiet zone is light, the finder pattern's corner dark
+        assert_eq!(pixels[1], 0xff);
+        let corner = QUIET_ZONE * SCALE;
+        assert_eq!(pixels[corner * (row_bytes + 1) + 1 + corner / 8] & (0x80 >> (corner % 8)), 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! Transfer quotas, so one runaway session cannot use up the server's
+//! traffic allowance.
+//!
+//! The relay counts the terminal bytes it moves for every session and every
+//! mac-client (by client ID, over all its sessions), in both directions and
+//! once for every browser output goes to. With `SESSION_QUOTA_BYTES` or
+//! `CLIENT_QUOTA_BYTES` set, a session that goes over either within a
+//! period (`QUOTA_PERIOD_SECS`, a day by default) is closed, or with
+//! `QUOTA_ACTION=throttle` has its output slowed down to
+//! `QUOTA_THROTTLE_BYTES_PER_SEC` until the period is over. Throttling
+//! stops reading from the Mac for a while, so the slowdown reaches the
+//! program producing the output instead of piling up in the relay.
+//! Quotas are enforced on output; input counts towards them.
+
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Default length of a quota period
+pub const DEFAULT_QUOTA_PERIOD: Duration = Duration::from_secs(24 * 3600);
+
+/// Default output rate of a throttled session
+pub const DEFAULT_THROTTLE_RATE: u64 = 16 * 1024;
+
+/// What happens to a session over its quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// Close it, as if it had expired
+    Disconnect,
+    /// Relay no more than this many bytes a second
+    Throttle { bytes_per_sec: u64 },
+}
+
+/// What a session that just relayed some bytes should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Within,
+    /// Wait this long before relaying more
+    Throttle(Duration),
+    /// Close the session
+    Exceeded,
+}
+
+/// Transfer quotas of a relay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quotas {
+    /// Bytes one session may relay in a period
+    pub session_bytes: Option<u64>,
+    /// Bytes all sessions of one client ID may relay in a period
+    pub client_bytes: Option<u64>,
+    pub period: Duration,
+    pub action: QuotaAction,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self {
+            session_bytes: None,
+            client_bytes: None,
+            period: DEFAULT_QUOTA_PERIOD,
+            action: QuotaAction::Disconnect,
+        }
+    }
+}
+
+impl Quotas {
+    /// Quotas from `SESSION_QUOTA_BYTES`, `CLIENT_QUOTA_BYTES`,
+    /// `QUOTA_PERIOD_SECS`, `QUOTA_ACTION` (`disconnect` or `throttle`) and
+    /// `QUOTA_THROTTLE_BYTES_PER_SEC`
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Self::from_vars(|name| config.get(name))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let number = |name: &str| -> Result<Option<u64>, String> {
+            match var(name).filter(|value| !value.is_empty()) {
+                Some(value) => value.parse().map(Some).map_err(|_| format!("{} must be a valid number", name)),
+                None => Ok(None),
+            }
+        };
+        let action = match var("QUOTA_ACTION").as_deref().unwrap_or("disconnect") {
+            "" | "disconnect" => QuotaAction::Disconnect,
+            "throttle" => QuotaAction::Throttle {
+                bytes_per_sec: number("QUOTA_THROTTLE_BYTES_PER_SEC")?
+                    .unwrap_or(DEFAULT_THROTTLE_RATE)
+                    .max(1),
+            },
+            other => return Err(format!("QUOTA_ACTION must be disconnect or throttle, not {}", other)),
+        };
+        Ok(Self {
+            session_bytes: number("SESSION_QUOTA_BYTES")?,
+            client_bytes: number("CLIENT_QUOTA_BYTES")?,
+            period: number("QUOTA_PERIOD_SECS")?
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_QUOTA_PERIOD),
+            action,
+        })
+    }
+
+    /// What to do after relaying `bytes`, which brought a session to
+    /// `session` and its client to `client` bytes in their periods
+    pub fn verdict(&self, bytes: u64, session: u64, client: u64) -> Verdict {
+        let over = self.session_bytes.is_some_and(|max| session > max) || self.client_bytes.is_some_and(|max| client > max);
+        if over {
+            self.over(bytes)
+        } else {
+            Verdict::Within
+        }
+    }
+
+    /// What to do after relaying `bytes` over a quota
+    pub fn over(&self, bytes: u64) -> Verdict {
+        match self.action {
+            QuotaAction::Disconnect => Verdict::Exceeded,
+            QuotaAction::Throttle { bytes_per_sec } => {
+                Verdict::Throttle(Duration::from_secs_f64(bytes as f64 / bytes_per_sec as f64))
+            }
+        }
+    }
+}
+
+/// Bytes relayed, in total and in the current quota period
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    total: u64,
+    in_period: u64,
+    period_started: Instant,
+}
+
+impl Transfer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            total: 0,
+            in_period: 0,
+            period_started: now,
+        }
+    }
+
+    /// Count `bytes`, starting a new period if the last one is over.
+    /// Returns the bytes of the current period.
+    pub fn add(&mut self, bytes: u64, period: Duration, now: Instant) -> u64 {
+        if self.is_period_over(period, now) {
+            self.in_period = 0;
+            self.period_started = now;
+        }
+        self.total += bytes;
+        self.in_period += bytes;
+        self.in_period
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Bytes of the current period
+    pub fn in_period(&self, period: Duration, now: Instant) -> u64 {
+        if self.is_period_over(period, now) {
+            0
+        } else {
+            self.in_period
+        }
+    }
+
+    pub fn is_period_over(&self, period: Duration, now: Instant) -> bool {
+        now.saturating_duration_since(self.period_started) >= period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_vars() {
+        let quotas = Quotas::from_vars(|_| None).unwrap();
+        assert_eq!(quotas, Quotas::default());
+
+        let vars = |name: &str| match name {
+            "SESSION_QUOTA_BYTES" => Some("1000".to_string()),
+            "QUOTA_ACTION" => Some("throttle".to_string()),
+            "QUOTA_PERIOD_SECS" => Some("3600".to_string()),
+            _ => None,
+        };
+        let quotas = Quotas::from_vars(vars).unwrap();
+        assert_eq!(quotas.session_bytes, Some(1000));
+        assert_eq!(quotas.client_bytes, None);
+        assert_eq!(quotas.period, Duration::from_secs(3600));
+        assert_eq!(quotas.action, QuotaAction::Throttle { bytes_per_sec: DEFAULT_THROTTLE_RATE });
+
+        assert!(Quotas::from_vars(|name| (name == "QUOTA_ACTION").then(|| "block".to_string())).is_err());
+        assert!(Quotas::from_vars(|name| (name == "CLIENT_QUOTA_BYTES").then(|| "1GB".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_verdict() {
+        let mut quotas = Quotas {
+            session_bytes: Some(100),
+            client_bytes: Some(1000),
+            ..Quotas::default()
+        };
+        assert_eq!(quotas.verdict(10, 100, 500), Verdict::Within);
+        assert_eq!(quotas.verdict(10, 101, 500), Verdict::Exceeded);
+        assert_eq!(quotas.verdict(10, 50, 1001), Verdict::Exceeded);
+
+        quotas.action = QuotaAction::Throttle { bytes_per_sec: 1000 };
+        assert_eq!(quotas.verdict(500, 150, 500), Verdict::Throttle(Duration::from_millis(500)));
+        assert_eq!(Quotas::default().verdict(u64::MAX, u64::MAX, u64::MAX), Verdict::Within);
+    }
+
+    #[test]
+    fn test_transfer_periods() {
+        let start = Instant::now();
+        let period = Duration::from_secs(60);
+        let mut transfer = Transfer::new(start);
+        assert_eq!(transfer.add(10, period, start), 10);
+        assert_eq!(transfer.add(5, period, start + Duration::from_secs(59)), 15);
+        assert_eq!(transfer.in_period(period, start + Duration::from_secs(60)), 0);
+        // A new period starts with the next bytes
+        assert_eq!(transfer.add(7, period, start + Duration::from_secs(61)), 7);
+        assert_eq!(transfer.total(), 22);
+        assert!(!transfer.is_period_over(period, start + Duration::from_secs(120)));
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-IP limits on browser joins and mac-client registrations.
+//!
+//! Session codes are short, so an unlimited client could try them all. Each
+//! source IP gets a number of attempts per window; going over bans it for a
+//! while, during which every attempt is refused without looking at the code.
+
+use axum::http::HeaderMap;
+use dashmap::DashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Length of the window attempts are counted in
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// Default browser joins per IP and window
+pub const DEFAULT_JOINS_PER_WINDOW: u32 = 30;
+
+/// Default mac-client registrations per IP and window
+pub const DEFAULT_REGISTRATIONS_PER_WINDOW: u32 = 10;
+
+/// Default time an IP is refused after going over a limit
+pub const DEFAULT_BAN: Duration = Duration::from_secs(300);
+
+/// How many attempts an IP gets and what happens when it uses them up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Attempts per [`WINDOW`]; 0 turns the limit off
+    pub attempts: u32,
+    /// How long an IP is refused once it goes over
+    pub ban: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(attempts: u32, ban: Duration) -> Self {
+        Self { attempts, ban }
+    }
+}
+
+/// Attempts of one IP in the current window
+#[derive(Debug)]
+struct Entry {
+    window_start: Instant,
+    attempts: u32,
+    banned_until: Option<Instant>,
+}
+
+/// Counts attempts per source IP against one [`RateLimit`]
+#[derive(Debug)]
+pub struct IpLimiter {
+    limit: RateLimit,
+    entries: DashMap<IpAddr, Entry>,
+}
+
+impl IpLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Count an attempt from `ip`. Returns how long the IP remains banned
+    /// if it is refused.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.limit.attempts == 0 {
+            return Ok(());
+        }
+        let mut entry = self.entries.entry(ip).or_insert_with(|| Entry {
+            window_start: now,
+            attempts: 0,
+            banned_until: None,
+        });
+        if let Some(until) = entry.banned_until {
+            if now < until {
+                return Err(until - now);
+            }
+            entry.banned_until = None;
+            entry.window_start = now;
+            entry.attempts = 0;
+        }
+        if now.saturating_duration_since(entry.window_start) >= WINDOW {
+            entry.window_start = now;
+            entry.attempts = 0;
+        }
+        entry.attempts += 1;
+        if entry.attempts > self.limit.attempts {
+            entry.banned_until = Some(now + self.limit.ban);
+            tracing::warn!(ip = %ip, ban_secs = self.limit.ban.as_secs(), "Rate limit exceeded, banning IP");
+            return Err(self.limit.ban);
+        }
+        Ok(())
+    }
+
+    /// Forget IPs whose window and ban are over
+    pub fn prune(&self, now: Instant) {
+        self.entries.retain(|_, entry| {
+            entry.banned_until.is_some_and(|until| now < until)
+                || now.saturating_duration_since(entry.window_start) < WINDOW
+        });
+    }
+
+    /// IPs currently tracked (for debugging)
+    pub fn tracked(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// The IP a request comes from: the last address in `header` when the
+/// relay is told to trust one (the proxy in front appends the address it
+/// saw), otherwise the peer of the connection.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, header: Option<&str>) -> IpAddr {
+    header
+        .and_then(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or_else(|| peer.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban_after_limit() {
+        let limiter = IpLimiter::new(RateLimit::new(2, Duration::from_secs(30)));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.check(ip, now).is_ok());
+        assert!(limiter.check(ip, now).is_ok());
+        assert_eq!(limiter.check(ip, now), Err(Duration::from_secs(30)));
+        assert!(limiter.check(other, now).is_ok());
+
+        // Attempts during the ban do not extend it
+        assert_eq!(limiter.check(ip, now + Duration::from_secs(10)), Err(Duration::from_secs(20)));
+        assert!(limiter.check(ip, now + Duration::from_secs(30)).is_ok());
+    }
+
+    #[test]
+    fn test_window_resets_and_prune() {
+        let limiter = IpLimiter::new(RateLimit::new(1, Duration::from_secs(300)));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.check(ip, now).is_ok());
+        assert!(limiter.check(ip, now + WINDOW).is_ok());
+        limiter.prune(now + WINDOW * 3);
+        assert_eq!(limiter.tracked(), 0);
+
+        let off = IpLimiter::new(RateLimit::new(0, Duration::from_secs(300)));
+        for _ in 0..100 {
+            assert!(off.check(ip, now).is_ok());
+        }
+        assert_eq!(off.tracked(), 0);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.9, 198.51.100.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, None), peer.ip());
+        assert_eq!(client_ip(&headers, peer, Some("X-Forwarded-For")), "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&HeaderMap::new(), peer, Some("X-Forwarded-For")), peer.ip());
+    }
+}
@@ -0,0 +1,179 @@
+//! Session recordings kept on the relay for playback.
+//!
+//! A mac-client can ask the relay to record the terminal output of its
+//! session. Frames are stored with the time since the recording started, so
+//! browsers can watch what happened while they were away over the
+//! `/playback/{id}` WebSocket, at the speed they like. Recordings are kept
+//! by client ID: they outlive the session and stay listable when the Mac
+//! reconnects with a new code. They are held in memory only, never
+//! written to disk, and go when the relay stops.
+
+use dashmap::DashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::RecordingInfo;
+
+/// Default cap on one recording, in bytes
+pub const DEFAULT_MAX_RECORDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default time recordings are kept after they end
+pub const DEFAULT_RECORDING_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Terminal output of one session over time
+struct Recording {
+    client_id: String,
+    /// Wall clock start, shown to browsers
+    started_at: SystemTime,
+    started: Instant,
+    /// Frames with the time since the start
+    frames: Vec<(Duration, Vec<u8>)>,
+    bytes: usize,
+    /// When recording stopped; None while it goes on
+    ended: Option<Instant>,
+    /// Frames were left out because the recording was full
+    truncated: bool,
+}
+
+impl Recording {
+    fn info(&self, id: &str) -> RecordingInfo {
+        let duration = self.ended.unwrap_or_else(Instant::now).saturating_duration_since(self.started);
+        RecordingInfo {
+            id: id.to_string(),
+            started_at: self.started_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            duration_ms: duration.as_millis() as u64,
+            bytes: self.bytes as u64,
+            active: self.ended.is_none(),
+            truncated: self.truncated,
+        }
+    }
+}
+
+/// Every recording on this relay
+pub struct Recordings {
+    /// Bytes kept per recording
+    max_bytes: usize,
+    /// How long recordings are kept after they end
+    retention: Duration,
+    /// Recording ID -> recording. IDs are long and random: they are what
+    /// lets a browser play a recording back
+    recordings: DashMap<String, Recording>,
+}
+
+impl Recordings {
+    pub fn new(max_bytes: usize, retention: Duration) -> Self {
+        Self {
+            max_bytes,
+            retention,
+            recordings: DashMap::new(),
+        }
+    }
+
+    /// Start recording a session of `client_id`; returns the recording ID
+    pub fn start(&self, client_id: &str) -> String {
+        let id = nanoid::nanoid!(21);
+        self.recordings.insert(
+            id.clone(),
+            Recording {
+                client_id: client_id.to_string(),
+                started_at: SystemTime::now(),
+                started: Instant::now(),
+                frames: Vec::new(),
+                bytes: 0,
+                ended: None,
+                truncated: false,
+            },
+        );
+        id
+    }
+
+    /// Add a frame to a recording that goes on; frames past the cap are
+    /// left out
+    pub fn append(&self, id: &str, data: &[u8]) {
+        let Some(mut recording) = self.recordings.get_mut(id) else { return };
+        if recording.ended.is_some() {
+            return;
+        }
+        if recording.bytes + data.len() > self.max_bytes {
+            if !recording.truncated {
+                tracing::info!(recording_id = %id, "Recording is full, leaving out further output");
+                recording.truncated = true;
+            }
+            return;
+        }
+        let at = recording.started.elapsed();
+        recording.bytes += data.len();
+        recording.frames.push((at, data.to_vec()));
+    }
+
+    /// Stop a recording; it stays available for playback
+    pub fn stop(&self, id: &str) {
+        if let Some(mut recording) = self.recordings.get_mut(id) {
+            recording.ended.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Recordings of one client, oldest first
+    pub fn list(&self, client_id: &str) -> Vec<RecordingInfo> {
+        let mut list: Vec<RecordingInfo> = self
+            .recordings
+            .iter()
+            .filter(|r| r.client_id == client_id)
+            .map(|r| r.info(r.key()))
+            .collect();
+        list.sort_by_key(|info| info.started_at);
+        list
+    }
+
+    /// The frames recorded so far, for playback
+    pub fn frames(&self, id: &str) -> Option<Vec<(Duration, Vec<u8>)>> {
+        self.recordings.get(id).map(|r| r.frames.clone())
+    }
+
+    /// Forget recordings that ended longer ago than the retention
+    pub fn prune(&self, now: Instant) {
+        self.recordings.retain(|id, recording| {
+            let keep = recording
+                .ended
+                .is_none_or(|ended| now.saturating_duration_since(ended) < self.retention);
+            if !keep {
+                tracing::info!(recording_id = %id, "Recording expired");
+            }
+            keep
+        });
+    }
+
+    /// Bytes over all recordings
+    pub fn bytes(&self) -> usize {
+        self.recordings.iter().map(|r| r.bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_prune() {
+        let recordings = Recordings::new(8, Duration::from_secs(60));
+        let id = recordings.start("mac-1");
+        recordings.append(&id, b"hello");
+        recordings.append(&id, b"world");
+        recordings.stop(&id);
+        recordings.append(&id, b"!");
+
+        let frames = recordings.frames(&id).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].1, b"hello");
+        let list = recordings.list("mac-1");
+        assert_eq!(list.len(), 1);
+        assert!(!list[0].active && list[0].truncated);
+        assert_eq!(list[0].bytes, 5);
+        assert!(recordings.list("mac-2").is_empty());
+
+        let other = recordings.start("mac-1");
+        recordings.prune(Instant::now() + Duration::from_secs(60));
+        assert!(recordings.frames(&id).is_none());
+        // Recordings that go on are kept
+        assert!(recordings.frames(&other).is_some());
+    }
+}
@@ -0,0 +1,414 @@
+//! Terminal output kept for replay to browsers that join later.
+//!
+//! Every terminal session of a mac-client has its own ring of frames, keyed
+//! by the frame's routing header, and its own byte cap: a chatty terminal
+//! only evicts its own history. Frames are numbered as they arrive so the
+//! replay interleaves terminals in the order the output was produced, and
+//! a browser that comes back can be sent only the frames after the last
+//! one it got.
+//!
+//! A mac-client can ask for less than the relay's cap when it registers,
+//! down to nothing: with a cap of 0 no output is kept at all. Frames are
+//! kept as the [`Bytes`] that went to browsers, so keeping them copies
+//! nothing.
+//!
+//! With snapshots on, the relay runs each terminal's output through a vt100
+//! interpreter instead of keeping the frames. A browser that joins gets one
+//! frame per terminal: the last lines of history as plain text, then the
+//! screen as it looks now. That is far less than the raw output, and a
+//! full-screen program that already exited is not drawn again.
+
+use axum::body::Bytes;
+use std::collections::{HashMap, VecDeque};
+
+use crate::frame;
+
+/// Terminal size assumed until the mac-client reports one
+const DEFAULT_ROWS: u16 = 24;
+const DEFAULT_COLS: u16 = 80;
+
+/// Largest terminal snapshots are drawn at, whatever the mac-client says:
+/// the screen is allocated cell by cell
+const MAX_ROWS: u16 = 1000;
+const MAX_COLS: u16 = 1000;
+
+/// Default lines of history sent along with a snapshot
+pub const DEFAULT_SNAPSHOT_HISTORY: usize = 1000;
+
+/// Frames of one terminal session, oldest first
+#[derive(Default)]
+struct Ring {
+    frames: VecDeque<(u64, Bytes)>,
+    bytes: usize,
+    /// The terminal as a vt100 interpreter sees it, with snapshots on
+    screen: Option<vt100::Parser>,
+    /// Number of the last frame interpreted
+    last_seq: u64,
+    /// One past the number of the last frame evicted
+    evicted_until: u64,
+}
+
+impl Ring {
+    /// Drop the oldest frames until no more than `max_bytes` are left.
+    /// Returns the bytes dropped.
+    fn evict(&mut self, max_bytes: usize) -> usize {
+        let mut dropped = 0;
+        while self.bytes > max_bytes {
+            let Some((seq, removed)) = self.frames.pop_front() else { break };
+            self.bytes -= removed.len();
+            dropped += removed.len();
+            self.evicted_until = seq + 1;
+        }
+        dropped
+    }
+}
+
+/// Scrollback of one mac-client
+pub struct Scrollback {
+    /// Bytes kept per terminal session
+    max_bytes: usize,
+    /// Lines of history kept per terminal when snapshots are on
+    snapshot_history: Option<usize>,
+    /// Terminal session ID -> its frames. Frames without a readable routing
+    /// header are kept under `None`
+    terminals: HashMap<Option<Vec<u8>>, Ring>,
+    /// Number of the next frame
+    next_seq: u64,
+    /// Bytes over all terminals
+    bytes: usize,
+}
+
+impl Scrollback {
+    /// Scrollback keeping `max_bytes` of frames per terminal, or snapshots
+    /// with `snapshot_history` lines of history. Nothing at all with
+    /// `max_bytes` 0.
+    pub fn new(max_bytes: usize, snapshot_history: Option<usize>) -> Self {
+        Self {
+            max_bytes,
+            snapshot_history: snapshot_history.filter(|_| max_bytes > 0),
+            terminals: HashMap::new(),
+            next_seq: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Keep frames instead of snapshots, e.g. for end-to-end encrypted
+    /// output the relay cannot interpret
+    pub fn disable_snapshots(&mut self) {
+        self.snapshot_history = None;
+        for ring in self.terminals.values_mut() {
+            // Browsers resuming from before the snapshot cannot catch up
+            if ring.screen.take().is_some() {
+                ring.evicted_until = ring.evicted_until.max(ring.last_seq + 1);
+            }
+        }
+    }
+
+    /// Keep `max_bytes` of frames per terminal from now on, dropping the
+    /// oldest of what is over it. 0 keeps nothing, not even snapshots.
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        if max_bytes == 0 {
+            self.disable_snapshots();
+        }
+        for ring in self.terminals.values_mut() {
+            self.bytes -= ring.evict(max_bytes);
+        }
+    }
+
+    /// Append a frame to its terminal's ring, dropping that terminal's
+    /// oldest frames until it is back under the cap. Returns the frame's
+    /// number.
+    pub fn push(&mut self, data: Bytes) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let key = frame::session_id(&data).map(<[u8]>::to_vec);
+        if let (Some(id), Some(history)) = (&key, self.snapshot_history) {
+            let payload = &data[1 + id.len()..];
+            let ring = self.terminals.entry(key).or_default();
+            ring.screen
+                .get_or_insert_with(|| vt100::Parser::new(DEFAULT_ROWS, DEFAULT_COLS, history))
+                .process(payload);
+            ring.last_seq = seq;
+            return seq;
+        }
+        let ring = self.terminals.entry(key).or_default();
+        ring.bytes += data.len();
+        self.bytes += data.len();
+        ring.frames.push_back((seq, data));
+        self.bytes -= ring.evict(self.max_bytes);
+        seq
+    }
+
+    /// Number the next frame will get: a browser that got every frame so
+    /// far resumes from here
+    pub fn position(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Frames from number `position` on, in order, for a browser that got
+    /// the ones before. None if some of them are no longer kept as they
+    /// were: evicted, or folded into a snapshot.
+    pub fn frames_from(&self, position: u64) -> Option<Vec<Bytes>> {
+        let mut frames: Vec<&(u64, Bytes)> = Vec::new();
+        for ring in self.terminals.values() {
+            if ring.evicted_until > position || (ring.screen.is_some() && ring.last_seq >= position) {
+                return None;
+            }
+            frames.extend(ring.frames.iter().filter(|(seq, _)| *seq >= position));
+        }
+        frames.sort_unstable_by_key(|(seq, _)| *seq);
+        Some(frames.into_iter().map(|(_, data)| data.clone()).collect())
+    }
+
+    /// The mac-client resized a terminal; snapshots are drawn at that size
+    pub fn resize(&mut self, terminal_session_id: &[u8], rows: u16, cols: u16) {
+        let Some(history) = self.snapshot_history else { return };
+        if rows == 0 || cols == 0 {
+            return;
+        }
+        let (rows, cols) = (rows.min(MAX_ROWS), cols.min(MAX_COLS));
+        let ring = self.terminals.entry(Some(terminal_session_id.to_vec())).or_default();
+        ring.screen
+            .get_or_insert_with(|| vt100::Parser::new(rows, cols, history))
+            .screen_mut()
+            .set_size(rows, cols);
+    }
+
+    /// Forget one terminal session; also frames without a routing header
+    /// when `keep_unrouted` is false. Returns the number of frames dropped.
+    pub fn purge(&mut self, terminal_session_id: &[u8], keep_unrouted: bool) -> usize {
+        let mut purged = self.remove(Some(terminal_session_id.to_vec()));
+        if !keep_unrouted {
+            purged += self.remove(None);
+        }
+        purged
+    }
+
+    fn remove(&mut self, key: Option<Vec<u8>>) -> usize {
+        let Some(ring) = self.terminals.remove(&key) else { return 0 };
+        self.bytes -= ring.bytes;
+        ring.frames.len() + usize::from(ring.screen.is_some())
+    }
+
+    /// Frames to replay to a new browser, in the order their output arrived:
+    /// the kept frames, and a snapshot frame per interpreted terminal
+    pub fn frames(&mut self) -> Vec<Bytes> {
+        let mut frames: Vec<(u64, Bytes)> = Vec::new();
+        for (key, ring) in self.terminals.iter_mut() {
+            frames.extend(ring.frames.iter().cloned());
+            if let (Some(id), Some(screen)) = (key, ring.screen.as_mut()) {
+                let mut data = Vec::with_capacity(1 + id.len());
+                data.push(id.len() as u8);
+                data.extend_from_slice(id);
+                data.extend(snapshot(screen.screen_mut()));
+                frames.push((ring.last_seq, data.into()));
+            }
+        }
+        frames.sort_unstable_by_key(|(seq, _)| *seq);
+        frames.into_iter().map(|(_, data)| data).collect()
+    }
+
+    /// What is kept of one terminal's output: the payloads of its frames in
+    /// order, or the snapshot that redraws it. None if nothing is kept.
+    pub fn output(&mut self, terminal_session_id: &[u8]) -> Option<Vec<u8>> {
+        let ring = self.terminals.get_mut(&Some(terminal_session_id.to_vec()))?;
+        let header = 1 + terminal_session_id.len();
+        let mut output: Vec<u8> = Vec::with_capacity(ring.bytes);
+        for (_, data) in &ring.frames {
+            output.extend_from_slice(&data[header..]);
+        }
+        if let Some(screen) = ring.screen.as_mut() {
+            output.extend(snapshot(screen.screen_mut()));
+        }
+        Some(output)
+    }
+
+    /// Frames kept, over all terminals
+    pub fn len(&self) -> usize {
+        self.terminals.values().map(|ring| ring.frames.len()).sum()
+    }
+
+    /// Bytes of frames kept, over all terminals
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+/// Bytes that redraw `screen` in an empty terminal: the history lines
+/// scrolled up out of view, then the screen with its colors and modes
+fn snapshot(screen: &mut vt100::Screen) -> Vec<u8> {
+    let (rows, cols) = screen.size();
+    let mut out = Vec::new();
+    if !screen.alternate_screen() {
+        // Scrolled all the way up, the view shows the oldest history line
+        // first; walk down a screen at a time
+        screen.set_scrollback(usize::MAX);
+        let mut offset = screen.scrollback();
+        let history = offset;
+        while offset > 0 {
+            screen.set_scrollback(offset);
+            let lines = offset.min(rows as usize);
+            for line in screen.rows(0, cols).take(lines) {
+                out.extend_from_slice(line.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            offset -= lines;
+        }
+        screen.set_scrollback(0);
+        // Scroll the history lines still in view up into the scrollback, so
+        // drawing the screen keeps them
+        let in_view = history.min(rows as usize - 1);
+        if in_view > 0 {
+            out.extend(format!("\x1b[{};1H", rows).into_bytes());
+            out.extend(b"\r\n".repeat(in_view));
+        }
+    }
+    out.extend(screen.state_formatted());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: u8, payload: &[u8]) -> Bytes {
+        let mut data = vec![1, id];
+        data.extend_from_slice(payload);
+        data.into()
+    }
+
+    #[test]
+    fn test_eviction_is_per_terminal() {
+        let mut scrollback = Scrollback::new(10, None);
+        scrollback.push(frame(b'a', b"quiet"));
+        for _ in 0..100 {
+            scrollback.push(frame(b'b', b"chatty"));
+        }
+        let frames = scrollback.frames();
+        assert_eq!(frames, vec![frame(b'a', b"quiet"), frame(b'b', b"chatty")]);
+        assert_eq!(scrollback.bytes(), 15);
+    }
+
+    #[test]
+    fn test_set_max_bytes() {
+        let mut scrollback = Scrollback::new(1024, None);
+        scrollback.push(frame(b'a', b"12345"));
+        scrollback.push(frame(b'a', b"67890"));
+        scrollback.set_max_bytes(10);
+        assert_eq!(scrollback.frames(), vec![frame(b'a', b"67890")]);
+        assert_eq!(scrollback.bytes(), 7);
+
+        // Nothing kept, and resuming browsers that missed output cannot
+        // catch up
+        scrollback.set_max_bytes(0);
+        scrollback.push(frame(b'b', b"x"));
+        assert!(scrollback.frames().is_empty());
+        assert_eq!(scrollback.bytes(), 0);
+        assert_eq!(scrollback.frames_from(2), None);
+        assert_eq!(scrollback.frames_from(3), Some(Vec::new()));
+
+        let mut scrollback = Scrollback::new(0, Some(100));
+        scrollback.push(frame(b'a', b"hello"));
+        assert!(scrollback.frames().is_empty());
+    }
+
+    #[test]
+    fn test_replay_order_and_purge() {
+        let mut scrollback = Scrollback::new(1024, None);
+        scrollback.push(frame(b'a', b"1"));
+        scrollback.push(frame(b'b', b"2"));
+        scrollback.push(Bytes::from_static(&[9]));
+        scrollback.push(frame(b'a', b"3"));
+        assert_eq!(
+            scrollback.frames(),
+            vec![frame(b'a', b"1"), frame(b'b', b"2"), Bytes::from_static(&[9]), frame(b'a', b"3")]
+        );
+
+        assert_eq!(scrollback.purge(b"a", true), 2);
+        assert_eq!(scrollback.len(), 2);
+        assert_eq!(scrollback.purge(b"zz", false), 1);
+        assert_eq!(scrollback.frames(), vec![frame(b'b', b"2")]);
+        assert_eq!(scrollback.bytes(), 3);
+
+        scrollback.push(frame(b'b', b"4"));
+        assert_eq!(scrollback.output(b"b").as_deref(), Some(&b"24"[..]));
+        assert_eq!(scrollback.output(b"a"), None);
+    }
+
+    #[test]
+    fn test_frames_from() {
+        let mut scrollback = Scrollback::new(10, None);
+        assert_eq!(scrollback.push(frame(b'a', b"1")), 0);
+        scrollback.push(frame(b'b', b"2"));
+        scrollback.push(frame(b'a', b"3"));
+        assert_eq!(scrollback.position(), 3);
+        assert_eq!(scrollback.frames_from(1), Some(vec![frame(b'b', b"2"), frame(b'a', b"3")]));
+        assert_eq!(scrollback.frames_from(3), Some(vec![]));
+
+        // Frame 0 and 2 are evicted: a browser that missed them gets the
+        // full replay instead
+        scrollback.push(frame(b'a', b"4567890"));
+        assert_eq!(scrollback.frames_from(2), None);
+        assert_eq!(scrollback.frames_from(3), Some(vec![frame(b'a', b"4567890")]));
+
+        let mut snapshots = Scrollback::new(1024, Some(DEFAULT_SNAPSHOT_HISTORY));
+        snapshots.push(frame(b'a', b"$ "));
+        assert_eq!(snapshots.frames_from(0), None);
+        assert_eq!(snapshots.frames_from(1), Some(vec![]));
+    }
+
+    /// What a terminal shows after being fed `data`: the screen, the
+    /// number of history lines and the oldest one
+    fn replayed(data: &[u8], rows: u16, cols: u16) -> (String, usize, String) {
+        let mut parser = vt100::Parser::new(rows, cols, 100);
+        parser.process(data);
+        let screen = parser.screen_mut();
+        let contents = screen.contents();
+        screen.set_scrollback(usize::MAX);
+        let oldest = screen.rows(0, cols).next().unwrap_or_default();
+        (contents, screen.scrollback(), oldest)
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut scrollback = Scrollback::new(1024, Some(DEFAULT_SNAPSHOT_HISTORY));
+        scrollback.resize(b"a", 4, 20);
+        for i in 0..10 {
+            scrollback.push(frame(b'a', format!("line {}\r\n", i).as_bytes()));
+        }
+        scrollback.push(frame(b'a', b"$ "));
+        // A full-screen program that has exited leaves nothing behind
+        scrollback.push(frame(b'b', b"\x1b[?1049hvim\x1b[?1049l"));
+        assert_eq!(scrollback.bytes(), 0);
+
+        let frames = scrollback.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frame::session_id(&frames[0]), Some(&b"a"[..]));
+        let (screen, history, oldest) = replayed(&frames[0][2..], 4, 20);
+        assert_eq!(screen, "line 7\nline 8\nline 9\n$ ");
+        assert_eq!(history, 7);
+        assert_eq!(oldest, "line 0");
+        let (screen, _, _) = replayed(&frames[1][2..], 24, 80);
+        assert_eq!(screen, "");
+
+        // Passthrough sessions keep their frames
+        scrollback.disable_snapshots();
+        assert!(scrollback.frames().is_empty());
+        scrollback.push(frame(b'a', b"sealed"));
+        assert_eq!(scrollback.frames(), vec![frame(b'a', b"sealed")]);
+    }
+
+    #[test]
+    fn test_snapshot_size_is_capped() {
+        let mut scrollback = Scrollback::new(1024, Some(DEFAULT_SNAPSHOT_HISTORY));
+        scrollback.resize(b"a", u16::MAX, u16::MAX);
+        let size = |scrollback: &Scrollback| {
+            let ring = &scrollback.terminals[&Some(b"a".to_vec())];
+            ring.screen.as_ref().unwrap().screen().size()
+        };
+        assert_eq!(size(&scrollback), (MAX_ROWS, MAX_COLS));
+        scrollback.resize(b"a", 50, u16::MAX);
+        assert_eq!(size(&scrollback), (50, MAX_COLS));
+    }
+}
@@ -1,4 +1,9 @@
-use nanoid::nanoid;
+//! Session codes: short enough to type, hard enough to guess.
+//!
+//! By default a code is six characters from an alphabet without lookalikes.
+//! Word codes (`maple-otter-42`) are longer but easier to read aloud.
+
+use crate::config::Config;
 
 /// Characters for session codes - excludes 0/O/1/I/L to avoid confusion
 const CODE_ALPHABET: [char; 31] = [
@@ -7,15 +12,189 @@ const CODE_ALPHABET: [char; 31] = [
     'X', 'Y', 'Z', '2', '3', '4', '5', '6', '7', '8', '9',
 ];
 
-/// Generate a 6-character session code
-pub fn generate_session_code() -> String {
-    nanoid!(6, &CODE_ALPHABET)
+/// Characters left out of configured alphabets because they read like others
+const LOOKALIKES: [char; 5] = ['0', 'O', '1', 'I', 'L'];
+
+/// Default length of character codes
+pub const DEFAULT_CODE_LENGTH: usize = 6;
+
+/// Default number of words in word codes
+pub const DEFAULT_CODE_WORDS: usize = 2;
+
+/// Words for word codes: short, distinct when spoken, one byte each
+const WORDS: [&str; 256] = [
+    "acorn", "amber", "anchor", "apple", "arrow", "aspen", "badge", "bagel",
+    "baker", "bamboo", "banjo", "barley", "basil", "beach", "beacon", "bear",
+    "beaver", "berry", "birch", "bison", "blaze", "bloom", "bluff", "bongo",
+    "brave", "breeze", "brick", "brook", "bubble", "bucket", "buffalo", "button",
+    "cabin", "cactus", "camel", "candle", "canoe", "canyon", "carrot", "castle",
+    "cedar", "cello", "chalk", "cherry", "chess", "chili", "cider", "circus",
+    "citrus", "clay", "cliff", "clover", "cobalt", "cocoa", "comet", "copper",
+    "coral", "cotton", "cougar", "coyote", "crane", "crater", "cricket", "crystal",
+    "dahlia", "daisy", "dancer", "delta", "desert", "dingo", "dolphin", "donkey",
+    "dragon", "drum", "eagle", "echo", "ember", "falcon", "fern", "fiddle",
+    "finch", "fjord", "flame", "flint", "flute", "forest", "fossil", "fox",
+    "galaxy", "garden", "garlic", "gecko", "geyser", "ginger", "glacier", "globe",
+    "goose", "granite", "grape", "gravel", "guitar", "hammock", "harbor", "hazel",
+    "hedge", "heron", "hickory", "honey", "hornet", "husky", "igloo", "indigo",
+    "iris", "island", "ivory", "jackal", "jade", "jasmine", "jelly", "jigsaw",
+    "jungle", "juniper", "kayak", "kelp", "kettle", "kiwi", "koala", "ladder",
+    "lagoon", "lantern", "lemon", "lilac", "lime", "linen", "lizard", "llama",
+    "lobster", "lotus", "lunar", "lynx", "magnet", "mango", "maple", "marble",
+    "meadow", "melon", "mesa", "meteor", "mint", "mocha", "monkey", "moose",
+    "mosaic", "moss", "muffin", "nectar", "needle", "nickel", "nutmeg", "oasis",
+    "ocean", "olive", "onion", "orbit", "orchid", "otter", "owl", "oyster",
+    "paddle", "panda", "papaya", "parrot", "peach", "peanut", "pebble", "pepper",
+    "piano", "pickle", "pigeon", "pine", "pirate", "pixel", "plum", "polar",
+    "pony", "poppy", "prairie", "pretzel", "prism", "pumpkin", "puzzle", "quail",
+    "quartz", "quill", "rabbit", "radar", "radish", "raven", "reef", "ribbon",
+    "river", "robin", "rocket", "rose", "ruby", "saddle", "saffron", "salmon",
+    "sapphire", "scarf", "sequoia", "shadow", "shell", "sierra", "silver", "sketch",
+    "sloth", "snow", "solar", "sparrow", "spruce", "squid", "stone", "storm",
+    "sugar", "summit", "sunset", "swan", "tango", "teapot", "thistle", "thunder",
+    "tiger", "timber", "toast", "tomato", "topaz", "tulip", "tundra", "turtle",
+    "umbrella", "valley", "velvet", "violet", "walnut", "walrus", "willow", "window",
+    "winter", "wizard", "wombat", "yak", "yarrow", "zebra", "zephyr", "zinc",
+];
+
+/// How session codes look
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodeFormat {
+    /// `length` characters from `alphabet`, e.g. `K7QH3M`
+    Chars { length: usize, alphabet: Vec<char> },
+    /// `words` words and a two-digit number, e.g. `maple-otter-42`
+    Words { words: usize },
+}
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        Self::Chars {
+            length: DEFAULT_CODE_LENGTH,
+            alphabet: CODE_ALPHABET.to_vec(),
+        }
+    }
+}
+
+impl CodeFormat {
+    /// Read the format from `CODE_FORMAT` (`chars` or `words`), `CODE_LENGTH`,
+    /// `CODE_ALPHABET`, `CODE_WORDS` and `CODE_MIN_BITS`.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Self::from_vars(|name| config.get(name).filter(|v| !v.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let number = |name: &str| -> Result<Option<usize>, String> {
+            var(name)
+                .map(|v| v.parse().map_err(|_| format!("{} must be a valid number", name)))
+                .transpose()
+        };
+        let format = match var("CODE_FORMAT").as_deref() {
+            None | Some("chars") => {
+                let length = number("CODE_LENGTH")?.unwrap_or(DEFAULT_CODE_LENGTH);
+                let alphabet = match var("CODE_ALPHABET") {
+                    Some(alphabet) => alphabet_without_lookalikes(&alphabet)
+                        .ok_or("CODE_ALPHABET needs at least two characters other than 0, O, 1, I and L")?,
+                    None => CODE_ALPHABET.to_vec(),
+                };
+                if length == 0 {
+                    return Err("CODE_LENGTH must be at least 1".to_string());
+                }
+                Self::Chars { length, alphabet }
+            }
+            Some("words") => {
+                let words = number("CODE_WORDS")?.unwrap_or(DEFAULT_CODE_WORDS);
+                if words == 0 {
+                    return Err("CODE_WORDS must be at least 1".to_string());
+                }
+                Self::Words { words }
+            }
+            Some(other) => return Err(format!("CODE_FORMAT must be chars or words, not {}", other)),
+        };
+        let min_bits = var("CODE_MIN_BITS")
+            .map(|v| v.parse::<f64>().map_err(|_| "CODE_MIN_BITS must be a valid number".to_string()))
+            .transpose()?;
+        Ok(match min_bits {
+            Some(bits) => format.with_min_entropy(bits),
+            None => format,
+        })
+    }
+
+    /// A new random code
+    pub fn generate(&self) -> String {
+        match self {
+            Self::Chars { length, alphabet } => nanoid::format(nanoid::rngs::default, alphabet, *length),
+            Self::Words { words } => {
+                let mut parts: Vec<String> = nanoid::rngs::default(*words)
+                    .into_iter()
+                    .map(|b| WORDS[b as usize].to_string())
+                    .collect();
+                parts.push(format!("{:02}", random_below_100()));
+                parts.join("-")
+            }
+        }
+    }
+
+    /// How many bits of randomness a code carries
+    pub fn entropy_bits(&self) -> f64 {
+        match self {
+            Self::Chars { length, alphabet } => *length as f64 * (alphabet.len() as f64).log2(),
+            Self::Words { words } => *words as f64 * (WORDS.len() as f64).log2() + 100f64.log2(),
+        }
+    }
+
+    /// The same kind of code, made longer until it carries at least `bits`
+    pub fn with_min_entropy(mut self, bits: f64) -> Self {
+        while self.entropy_bits() < bits {
+            match &mut self {
+                Self::Chars { length, .. } => *length += 1,
+                Self::Words { words } => *words += 1,
+            }
+        }
+        self
+    }
+}
+
+/// Upper-cased, deduplicated characters of `alphabet` without lookalikes;
+/// None if fewer than two are left.
+fn alphabet_without_lookalikes(alphabet: &str) -> Option<Vec<char>> {
+    let mut chars: Vec<char> = Vec::new();
+    for c in alphabet.chars().flat_map(char::to_uppercase) {
+        if c.is_alphanumeric() && !LOOKALIKES.contains(&c) && !chars.contains(&c) {
+            chars.push(c);
+        }
+    }
+    (chars.len() >= 2).then_some(chars)
+}
+
+/// A uniformly random number below 100
+fn random_below_100() -> u8 {
+    loop {
+        let byte = nanoid::rngs::default(1)[0];
+        if byte < 200 {
+            return byte % 100;
+        }
+    }
+}
+
+/// A code as typed by a user, in the form codes are handed out in: word
+/// codes in lower case, character codes in upper case.
+pub fn normalize_code(code: &str) -> String {
+    let code = code.trim();
+    if code.contains('-') {
+        code.to_lowercase()
+    } else {
+        code.to_uppercase()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn generate_session_code() -> String {
+        CodeFormat::default().generate()
+    }
+
     #[test]
     fn test_code_length() {
         let code = generate_session_code();
@@ -42,4 +221,40 @@ mod tests {
             assert!(!code.contains('L'));
         }
     }
+
+    #[test]
+    fn test_word_codes() {
+        let code = CodeFormat::Words { words: 2 }.generate();
+        let parts: Vec<&str> = code.split('-').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(WORDS.contains(&parts[0]) && WORDS.contains(&parts[1]));
+        assert_eq!(parts[2].len(), 2);
+        assert!(parts[2].chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(normalize_code(" Maple-Otter-42 "), "maple-otter-42");
+        assert_eq!(normalize_code("k7qh3m"), "K7QH3M");
+    }
+
+    #[test]
+    fn test_format_from_vars() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| pairs.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(CodeFormat::from_vars(vars(&[])).unwrap(), CodeFormat::default());
+
+        let format = CodeFormat::from_vars(vars(&[("CODE_LENGTH", "4"), ("CODE_ALPHABET", "abc01")])).unwrap();
+        assert_eq!(
+            format,
+            CodeFormat::Chars {
+                length: 4,
+                alphabet: vec!['A', 'B', 'C']
+            }
+        );
+        assert!(CodeFormat::from_vars(vars(&[("CODE_ALPHABET", "0O1IL")])).is_err());
+        assert!(CodeFormat::from_vars(vars(&[("CODE_FORMAT", "emoji")])).is_err());
+
+        // Three words and the number carry over 30 bits
+        let format = CodeFormat::from_vars(vars(&[("CODE_FORMAT", "words"), ("CODE_MIN_BITS", "30")])).unwrap();
+        assert_eq!(format, CodeFormat::Words { words: 3 });
+        assert!(format.entropy_bits() >= 30.0);
+    }
 }
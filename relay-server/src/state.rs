@@ -1,16 +1,112 @@
-use dashmap::DashMap;
+use axum::body::Bytes;
+use axum::extract::ws::Message;
+use axum::http::{HeaderMap, HeaderValue};
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, Mutex};
 
-use crate::session::generate_session_code;
+use crate::accounts::Accounts;
+use crate::affinity::Affinity;
+use crate::apikeys::{hash, ApiKeys};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::cluster::Cluster;
+use crate::federation::Federation;
+use crate::ipfilter::IpFilter;
+use crate::latency::{unix_millis, Percentiles, SessionLatency};
+use crate::lockout::{Lockout, Lockouts};
+use crate::origin::AllowedOrigins;
+use crate::metrics::{Direction, Gauges, Metrics, Runtime};
+use crate::protocol::{ControlMessage, RecordingInfo, Role, SessionInfo, TerminalInfo, TerminalMetadata, Viewer};
+use crate::quota::{Quotas, Transfer, Verdict};
+use crate::ratelimit::{self, IpLimiter, RateLimit};
+use crate::recording::{self, Recordings};
+use crate::scrollback::Scrollback;
+use crate::session::CodeFormat;
+use crate::statefile::{Saved, SavedInvite, SavedSession};
+use crate::webhooks::{WebhookEvent, Webhooks};
 
-/// Maximum scrollback buffer size (1 MB)
-const MAX_SCROLLBACK: usize = 1024 * 1024;
+/// Default scrollback buffer size per terminal session (1 MB)
+pub const DEFAULT_MAX_SCROLLBACK: usize = 1024 * 1024;
+
+/// Pace of scrollback replay to a joining browser by default (256 KB/s)
+pub const DEFAULT_REPLAY_RATE: usize = 256 * 1024;
+
+/// How long a session is held for its mac-client to come back by default
+pub const DEFAULT_RESUME_GRACE: Duration = Duration::from_secs(60);
+
+/// How long a browser that left can resume by default
+pub const DEFAULT_BROWSER_RESUME_WINDOW: Duration = Duration::from_secs(120);
+
+/// Chat lines kept per session for browsers joining later by default
+pub const DEFAULT_CHAT_HISTORY: usize = 100;
+
+/// Longest chat line, in characters; longer ones are cut
+const MAX_CHAT_CHARS: usize = 2000;
+
+/// Longest name a browser goes by in chat and presence, in characters
+const MAX_NICKNAME_CHARS: usize = 40;
+
+/// How long a join link works when the mac-client does not say
+pub const DEFAULT_JOIN_TOKEN_TTL: Duration = Duration::from_secs(600);
+
+/// Longest a join link may work
+pub const MAX_JOIN_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most browsers one join link may let in
+pub const MAX_JOIN_TOKEN_USES: u32 = 100;
+
+/// What a join link lets its holders do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinScope {
+    /// The most the browsers it lets in get
+    pub role: Role,
+    /// How many browsers it lets in
+    pub uses: u32,
+    /// How long it works
+    pub ttl: Duration,
+}
+
+impl JoinScope {
+    /// The scope a mac-client asked for, within the relay's caps: one
+    /// controller for [`DEFAULT_JOIN_TOKEN_TTL`] unless it says otherwise
+    pub fn new(ttl: Option<Duration>, role: Option<Role>, uses: Option<u32>) -> Self {
+        Self {
+            role: role.unwrap_or_default(),
+            uses: uses.unwrap_or(1).clamp(1, MAX_JOIN_TOKEN_USES),
+            ttl: ttl.unwrap_or(DEFAULT_JOIN_TOKEN_TTL).min(MAX_JOIN_TOKEN_TTL),
+        }
+    }
+}
+
+/// A join link waiting to be used
+#[derive(Debug, Clone, Copy)]
+struct JoinLink {
+    role: Role,
+    uses_left: u32,
+    expires: Instant,
+}
+
+/// A browser a join link let in: what it gets back in with, showing the
+/// same link again, until the link expires
+#[derive(Debug, Clone)]
+struct Invite {
+    role: Role,
+    expires: Instant,
+    /// Hex SHA-256 of the link's token
+    token_hash: String,
+}
 
 /// Message types that can be sent to browsers
 #[derive(Debug, Clone)]
 pub enum BrowserMessage {
-    Binary(Vec<u8>),
+    /// Terminal output, with its number in the session's scrollback. The
+    /// bytes are shared by every browser and the scrollback.
+    Binary { seq: u64, data: Bytes },
     Text(String),
 }
 
@@ -19,19 +115,214 @@ pub enum BrowserMessage {
 pub enum MacMessage {
     Binary(Vec<u8>),
     Text(String),
+    /// Close the connection (the session was ended by the relay)
+    Close,
+}
+
+/// A browser connected to a session
+#[derive(Debug)]
+pub struct Browser {
+    /// Channel to send messages to the browser
+    pub tx: mpsc::Sender<BrowserMessage>,
+    /// Who it is, as presence updates list it; the mac-client can change
+    /// its role at any time
+    pub viewer: Viewer,
+    /// Set when output was dropped because the channel was full
+    pub lagging: Arc<AtomicBool>,
+}
+
+/// Why a browser cannot be added to a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinRefusal {
+    /// The Mac has as many browsers as the relay allows one client ID
+    TooManyBrowsers,
+    /// The session has as many browsers as it allows, this many
+    SessionFull(usize),
+}
+
+/// What happens to a browser that does not take output as fast as its
+/// session produces it, once its queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowBrowser {
+    /// Drop what does not fit, and send the browser what it missed from
+    /// the scrollback once it caught up
+    #[default]
+    Resync,
+    /// Close its connection
+    Disconnect,
+}
+
+impl SlowBrowser {
+    /// From `SLOW_BROWSER`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "" | "resync" => Ok(Self::Resync),
+            "disconnect" => Ok(Self::Disconnect),
+            other => Err(format!("SLOW_BROWSER must be resync or disconnect, not {}", other)),
+        }
+    }
+}
+
+/// A browser that left, as it is let back in with its resume token
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepartedBrowser {
+    pub browser_id: String,
+    pub role: Role,
+    /// Unix time it first joined, in seconds
+    pub joined_at: u64,
+    /// Scrollback position its output stopped at
+    pub position: u64,
+    /// The Mac approved it, so it is not asked to again
+    pub approved: bool,
+}
+
+/// Output to send a joining browser
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub frames: Vec<Bytes>,
+    /// Only the frames a resuming browser missed
+    pub resumed: bool,
+    /// Scrollback position the frames end at
+    pub position: u64,
+}
+
+impl Replay {
+    /// Keep only the newest frames, no more than `max_bytes` of them, for
+    /// a browser that asked for the end of the scrollback only
+    pub fn keep_newest(&mut self, max_bytes: usize) {
+        let mut bytes = 0;
+        let kept = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| {
+                bytes += frame.len();
+                bytes <= max_bytes
+            })
+            .count();
+        self.frames.drain(..self.frames.len() - kept);
+    }
 }
 
 /// A connected mac-client session
 pub struct Session {
+    /// Stable ID the mac-client registered with (the same across restarts)
+    client_id: String,
+    /// Display name of the Mac, shown to browsers
+    client_name: Option<String>,
+    /// ID of the account the mac-client registered with, if it needed one
+    account: Option<String>,
+    /// Password browsers must send to join, if the mac-client set one
+    password: Option<PasswordHash>,
+    /// Frame payloads are end-to-end encrypted: kept opaque, never logged
+    passthrough: bool,
+    /// No output is kept: no scrollback, recording or chat history. Input
+    /// is still audited, the operator's trail is not the Mac's to turn off
+    zero_retention: bool,
+    /// Browsers joining may get compressed output
+    compression: AtomicBool,
+    /// Terminal bytes relayed in either direction
+    transfer: std::sync::Mutex<Transfer>,
+    /// Recent round trips through the session
+    latency: std::sync::Mutex<SessionLatency>,
     /// Channel to send messages to the mac-client
     pub mac_tx: mpsc::Sender<MacMessage>,
-    /// Connected browsers: browser_id -> sender channel
-    pub browsers: DashMap<String, mpsc::Sender<BrowserMessage>>,
-    /// Accumulated terminal output frames for replay on browser reconnect.
-    /// Each entry is a complete binary frame (with session ID prefix).
-    scrollback_frames: Mutex<Vec<Vec<u8>>>,
-    /// Total byte count of all frames in scrollback (for cap enforcement).
-    scrollback_bytes: Mutex<usize>,
+    /// Connected browsers: browser_id -> channel and permissions
+    pub browsers: DashMap<String, Browser>,
+    /// Browsers receiving terminal output over a direct WebRTC data channel
+    direct_browsers: DashSet<String>,
+    /// Terminal output frames for replay on browser reconnect, per
+    /// terminal session
+    scrollback: Mutex<Scrollback>,
+    /// Browsers allowed at once, as set by the mac-client
+    max_browsers: std::sync::Mutex<Option<usize>>,
+    /// Do Not Disturb on the mac-client: new browsers are refused
+    sharing_paused: AtomicBool,
+    /// New browsers wait for the mac-client's approval
+    approval_required: AtomicBool,
+    /// Browsers waiting for approval: browser_id -> where the answer goes
+    /// (the role it is let in with, or None if denied)
+    pending_approvals: DashMap<String, oneshot::Sender<Option<Role>>>,
+    /// New browsers join as viewers whatever they ask for
+    view_only: AtomicBool,
+    /// Join link tokens that still let browsers in
+    join_tokens: DashMap<String, JoinLink>,
+    /// Keys of browsers let in by a join link, let in again on reconnect
+    /// with the role the link granted while it lasts
+    invited_keys: DashMap<String, Invite>,
+    /// Browsers that left, by the resume token they were given: when they
+    /// left and how to let them back in
+    departed_browsers: DashMap<String, (Instant, DepartedBrowser)>,
+    /// ID of the recording of this session's output, while recording
+    recording: std::sync::Mutex<Option<String>>,
+    /// Terminal sessions the mac-client reported, in its order
+    terminals: std::sync::Mutex<Vec<TerminalInfo>>,
+    /// Last chat lines, oldest first, as they were sent
+    chat: std::sync::Mutex<VecDeque<ControlMessage>>,
+    /// Hex SHA-256 of the token that lets the mac-client reclaim this
+    /// session after its connection drops; empty while none is issued
+    resume_token_hash: String,
+    /// When the mac-client's connection dropped; the session is held for
+    /// it to come back until the resume grace runs out
+    detached: std::sync::Mutex<Option<Instant>>,
+    /// When the mac-client registered
+    created_at: Instant,
+    /// Last terminal frame in either direction, or browser leaving
+    last_activity: std::sync::Mutex<Instant>,
+}
+
+impl Session {
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Why the session should be closed at `now`, if it should
+    fn expiry(&self, limits: &Limits, now: Instant) -> Option<Expiry> {
+        if limits
+            .max_session_lifetime
+            .is_some_and(|max| now.saturating_duration_since(self.created_at) >= max)
+        {
+            return Some(Expiry::Lifetime);
+        }
+        if self
+            .detached
+            .lock()
+            .unwrap()
+            .is_some_and(|at| now.saturating_duration_since(at) >= limits.resume_grace)
+        {
+            return Some(Expiry::MacGone);
+        }
+        let idle = now.saturating_duration_since(*self.last_activity.lock().unwrap());
+        if self.browsers.is_empty() && limits.session_idle_timeout.is_some_and(|max| idle >= max) {
+            return Some(Expiry::Idle);
+        }
+        None
+    }
+}
+
+/// Why the relay closed a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Registered for longer than the maximum lifetime
+    Lifetime,
+    /// No frames and no browsers for the idle timeout
+    Idle,
+    /// The mac-client did not come back within the resume grace
+    MacGone,
+    /// It relayed more than the session or its client may in a period
+    QuotaExceeded,
+}
+
+impl Expiry {
+    /// Shown to the mac-client and browsers
+    pub fn message(self) -> &'static str {
+        match self {
+            Expiry::Lifetime => "Session expired: it reached the relay's maximum lifetime",
+            Expiry::Idle => "Session expired: it was idle for too long",
+            Expiry::MacGone => "Session disconnected",
+            Expiry::QuotaExceeded => "Session closed: it used up the relay's transfer quota",
+        }
+    }
 }
 
 /// Shared application state
@@ -43,171 +334,2338 @@ pub struct AppState {
 struct AppStateInner {
     /// Session code -> Session data
     sessions: DashMap<String, Session>,
+    /// Scrollback cap per terminal session, in bytes
+    max_scrollback: usize,
+    /// Browsers allowed at once across all sessions of one client ID
+    max_browsers_per_client: Option<usize>,
+    /// Held while a browser is checked against the browser limits and
+    /// added, so joins at the same time cannot both take the last place
+    admission: std::sync::Mutex<()>,
+    /// Browser joins per source IP
+    join_limiter: IpLimiter,
+    /// Mac-client registrations per source IP
+    register_limiter: IpLimiter,
+    /// Failed joins per source IP and session code
+    lockouts: Lockouts,
+    /// Header holding the client's IP behind a reverse proxy
+    client_ip_header: Option<String>,
+    /// Session lifetime and idle limits
+    limits: Limits,
+    /// Redis shared with other relays, if any
+    cluster: Option<Cluster>,
+    /// Relays of other regions, if any
+    federation: Option<Federation>,
+    /// Where session lifecycle events are sent
+    webhooks: Webhooks,
+    /// Where browser input is logged, if anywhere
+    audit: Option<AuditLog>,
+    /// Keys Macs must register with, if they must
+    api_keys: Option<ApiKeys>,
+    /// Accounts Macs must register with, if they must
+    accounts: Option<Accounts>,
+    /// Terminal bytes relayed per client ID, over all its sessions
+    client_transfer: DashMap<String, Transfer>,
+    /// Terminal bytes relayed per account ID, over all its sessions
+    account_transfer: DashMap<String, Transfer>,
+    /// Counters for `/metrics`
+    metrics: Metrics,
+    /// Session recordings for playback
+    recordings: Recordings,
+    /// Input of browsers on Server-Sent Events, by stream ID
+    event_streams: DashMap<String, mpsc::Sender<Message>>,
+}
+
+/// Limits of a relay, read from the environment at startup
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Scrollback cap per terminal session, in bytes
+    pub max_scrollback: usize,
+    /// Bytes per second scrollback is replayed at to a browser; None sends
+    /// it all at once
+    pub replay_rate: Option<usize>,
+    /// Browsers allowed at once across all sessions of one client ID
+    pub max_browsers_per_client: Option<usize>,
+    /// Browsers allowed at once in one session, unless the mac-client sets
+    /// a lower limit
+    pub max_browsers_per_session: Option<usize>,
+    /// Browser joins per source IP
+    pub join_rate: RateLimit,
+    /// Mac-client registrations per source IP
+    pub register_rate: RateLimit,
+    /// When IPs and codes with failed joins are locked out
+    pub lockout: Lockout,
+    /// Header a reverse proxy puts the client's IP in (e.g.
+    /// `X-Forwarded-For`), used instead of the peer address for rate limits
+    pub client_ip_header: Option<String>,
+    /// Sessions are closed this long after the mac-client registered
+    pub max_session_lifetime: Option<Duration>,
+    /// Sessions without browsers are closed after this long without frames
+    pub session_idle_timeout: Option<Duration>,
+    /// How session codes look
+    pub code_format: CodeFormat,
+    /// Lines of history sent with the screen snapshot new browsers get
+    /// instead of the raw scrollback; None replays the raw scrollback
+    pub snapshot_history: Option<usize>,
+    /// Cap on one session recording, in bytes
+    pub max_recording_bytes: usize,
+    /// Recordings are deleted this long after they end
+    pub recording_retention: Duration,
+    /// How long a session is held after its mac-client's connection drops,
+    /// for it to come back with the resume token; zero closes it at once
+    pub resume_grace: Duration,
+    /// How long a browser that left can come back with its resume token
+    /// and get only the output it missed; zero gives out no tokens
+    pub browser_resume_window: Duration,
+    /// What happens to browsers that fall behind the output
+    pub slow_browser: SlowBrowser,
+    /// Source IPs browsers may join from
+    pub join_filter: IpFilter,
+    /// Source IPs mac-clients may register from
+    pub register_filter: IpFilter,
+    /// Web pages besides the relay's own that browsers may connect from
+    pub allowed_origins: AllowedOrigins,
+    /// Bytes sessions and clients may relay
+    pub quotas: Quotas,
+    /// Bearer token of the admin API, which is off without one
+    pub admin_token: Option<String>,
+    /// Chat lines kept per session for browsers joining later
+    pub chat_history: usize,
+    /// Relays behind the same load balancer, which hand out only codes
+    /// that hash to themselves
+    pub affinity: Option<Affinity>,
+    /// Where browsers reach the web UI, for join URLs; taken from the
+    /// request without one
+    pub public_url: Option<String>,
+    /// STUN/TURN URLs browsers use for direct links to the Mac; None
+    /// leaves the web UI's own
+    pub ice_servers: Option<Vec<String>>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_scrollback: DEFAULT_MAX_SCROLLBACK,
+            replay_rate: Some(DEFAULT_REPLAY_RATE),
+            max_browsers_per_client: None,
+            max_browsers_per_session: None,
+            join_rate: RateLimit::new(ratelimit::DEFAULT_JOINS_PER_WINDOW, ratelimit::DEFAULT_BAN),
+            register_rate: RateLimit::new(ratelimit::DEFAULT_REGISTRATIONS_PER_WINDOW, ratelimit::DEFAULT_BAN),
+            lockout: Lockout::default(),
+            client_ip_header: None,
+            max_session_lifetime: None,
+            session_idle_timeout: None,
+            code_format: CodeFormat::default(),
+            snapshot_history: None,
+            max_recording_bytes: recording::DEFAULT_MAX_RECORDING_BYTES,
+            recording_retention: recording::DEFAULT_RECORDING_RETENTION,
+            resume_grace: DEFAULT_RESUME_GRACE,
+            browser_resume_window: DEFAULT_BROWSER_RESUME_WINDOW,
+            slow_browser: SlowBrowser::default(),
+            join_filter: IpFilter::default(),
+            register_filter: IpFilter::default(),
+            allowed_origins: AllowedOrigins::default(),
+            quotas: Quotas::default(),
+            admin_token: None,
+            chat_history: DEFAULT_CHAT_HISTORY,
+            affinity: None,
+            public_url: None,
+            ice_servers: None,
+        }
+    }
+}
+
+/// Usage of one registered mac-client, for the debug endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientUsage {
+    pub code: String,
+    pub client_id: String,
+    pub client_name: Option<String>,
+    pub browsers: usize,
+    pub bytes_relayed: u64,
+    /// Browser to Mac and back, as browsers measured it
+    pub round_trip: Option<Percentiles>,
+    /// Relay to Mac and back
+    pub mac_round_trip: Option<Percentiles>,
+}
+
+/// What the sessions of an account use, for the admin API
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountUsage {
+    pub sessions: usize,
+    pub browsers: usize,
+    pub bytes_total: u64,
+    /// Bytes of the current quota period
+    pub bytes_in_period: u64,
+}
+
+/// Bytes one client ID relayed, for the debug endpoint and metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientTransfer {
+    pub client_id: String,
+    pub bytes_total: u64,
+    /// Bytes of the current quota period
+    pub bytes_in_period: u64,
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::with_scrollback_limit(DEFAULT_MAX_SCROLLBACK)
+    }
+
+    /// Create state with a custom scrollback cap (bytes per terminal session).
+    pub fn with_scrollback_limit(max_scrollback: usize) -> Self {
+        Self::with_limits(max_scrollback, None)
+    }
+
+    /// Create state with a scrollback cap and an optional limit on browsers
+    /// per client ID.
+    pub fn with_limits(max_scrollback: usize, max_browsers_per_client: Option<usize>) -> Self {
+        Self::from_limits(Limits {
+            max_scrollback,
+            max_browsers_per_client,
+            ..Limits::default()
+        })
+    }
+
+    /// Create state with every limit spelled out.
+    pub fn from_limits(limits: Limits) -> Self {
+        Self::with_cluster(limits, None, None, Webhooks::default(), None, None, None)
+    }
+
+    /// Create state for a relay sharing session codes with other relays,
+    /// peering with the relays of other regions in `federation`, telling
+    /// `webhooks` what happens to sessions, logging browser input to
+    /// `audit` and letting only Macs with one of `api_keys` or a token of
+    /// one of `accounts` register.
+    pub fn with_cluster(
+        limits: Limits,
+        cluster: Option<Cluster>,
+        federation: Option<Federation>,
+        webhooks: Webhooks,
+        audit: Option<AuditLog>,
+        api_keys: Option<ApiKeys>,
+        accounts: Option<Accounts>,
+    ) -> Self {
         Self {
             inner: Arc::new(AppStateInner {
                 sessions: DashMap::new(),
+                max_scrollback: limits.max_scrollback,
+                max_browsers_per_client: limits.max_browsers_per_client,
+                admission: std::sync::Mutex::new(()),
+                join_limiter: IpLimiter::new(limits.join_rate),
+                register_limiter: IpLimiter::new(limits.register_rate),
+                lockouts: Lockouts::new(limits.lockout),
+                client_ip_header: limits.client_ip_header.clone(),
+                recordings: Recordings::new(limits.max_recording_bytes, limits.recording_retention),
+                event_streams: DashMap::new(),
+                client_transfer: DashMap::new(),
+                account_transfer: DashMap::new(),
+                limits,
+                cluster,
+                federation,
+                webhooks,
+                audit,
+                api_keys,
+                accounts,
+                metrics: Metrics::default(),
             }),
         }
     }
 
+    /// Header holding the client's IP behind a reverse proxy, if trusted
+    pub fn client_ip_header(&self) -> Option<&str> {
+        self.inner.client_ip_header.as_deref()
+    }
+
+    /// Count a browser join from `ip`. Returns how long the IP is still
+    /// banned if it went over the limit.
+    pub fn check_join_rate(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.inner.join_limiter.check(ip, Instant::now())
+    }
+
+    /// Count a mac-client registration from `ip`, like [`Self::check_join_rate`].
+    pub fn check_register_rate(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.inner.register_limiter.check(ip, Instant::now())
+    }
+
+    /// How much longer `ip` is locked out after failed joins, if it is
+    pub fn join_lockout(&self, ip: IpAddr) -> Option<Duration> {
+        self.inner.lockouts.ip_locked(ip, Instant::now())
+    }
+
+    /// How much longer a session takes no passwords after wrong ones, if
+    /// it does not
+    pub fn password_lockout(&self, code: &str) -> Option<Duration> {
+        self.inner.lockouts.code_locked(code, Instant::now())
+    }
+
+    /// Count a failed join from `ip`, against the session of `code` too if
+    /// the password was wrong. Returns how long to wait before answering.
+    pub fn join_failed(&self, ip: Option<IpAddr>, code: Option<&str>) -> Duration {
+        let failure = self.inner.lockouts.fail(ip, code, Instant::now());
+        let secs = self.inner.limits.lockout.duration.as_secs();
+        if failure.ip_locked {
+            tracing::warn!(event = "ip_locked_out", ip = ?ip, lockout_secs = secs, "Too many failed joins, locking out IP");
+        }
+        if let (true, Some(code)) = (failure.code_locked, code) {
+            tracing::warn!(event = "code_under_attack", code = %code, lockout_secs = secs, "Too many wrong passwords, session takes none for a while");
+            self.inner.webhooks.notify(WebhookEvent::CodeUnderAttack {
+                code: code.to_string(),
+                failures: self.inner.limits.lockout.failures,
+                lockout_secs: secs,
+            });
+        }
+        failure.delay
+    }
+
+    /// Whether browsers may join from `ip`
+    pub fn join_permitted(&self, ip: IpAddr) -> bool {
+        self.inner.limits.join_filter.permits(ip)
+    }
+
+    /// Whether mac-clients may register from `ip`
+    pub fn register_permitted(&self, ip: IpAddr) -> bool {
+        self.inner.limits.register_filter.permits(ip)
+    }
+
+    /// Whether a browser request with these headers comes from a page
+    /// allowed to connect
+    pub fn origin_permitted(&self, headers: &HeaderMap) -> bool {
+        self.inner.limits.allowed_origins.permits(headers)
+    }
+
+    /// Take input POSTed for an event stream
+    pub fn add_event_stream(&self, id: String, input: mpsc::Sender<Message>) {
+        self.inner.event_streams.insert(id, input);
+    }
+
+    pub fn remove_event_stream(&self, id: &str) {
+        self.inner.event_streams.remove(id);
+    }
+
+    /// Where input for an event stream goes
+    pub fn event_stream(&self, id: &str) -> Option<mpsc::Sender<Message>> {
+        self.inner.event_streams.get(id).map(|input| input.clone())
+    }
+
+    /// Whether a page from `origin` may open a WebTransport session to
+    /// `authority`
+    pub fn webtransport_origin_permitted(&self, origin: Option<&HeaderValue>, authority: &str) -> bool {
+        self.inner.limits.allowed_origins.permits_webtransport(origin, authority)
+    }
+
+    /// Forget source IPs that are no longer limited
+    pub fn prune_rate_limits(&self) {
+        let now = Instant::now();
+        self.inner.join_limiter.prune(now);
+        self.inner.register_limiter.prune(now);
+        self.inner.lockouts.prune(now);
+    }
+
+    /// Forget the transfer of clients without sessions whose quota period
+    /// is over
+    pub fn prune_client_transfer(&self) {
+        let now = Instant::now();
+        let period = self.inner.limits.quotas.period;
+        let active: std::collections::HashSet<String> =
+            self.inner.sessions.iter().map(|s| s.client_id.clone()).collect();
+        self.inner
+            .client_transfer
+            .retain(|client_id, transfer| active.contains(client_id) || !transfer.is_period_over(period, now));
+    }
+
+    /// Delete recordings past their retention
+    pub fn prune_recordings(&self) {
+        self.inner.recordings.prune(Instant::now());
+    }
+
+    /// Start or stop recording a session. Returns the ID of the recording
+    /// going on afterwards, if any.
+    pub fn set_recording(&self, code: &str, enabled: bool) -> Option<String> {
+        let session = self.inner.sessions.get(code)?;
+        let mut recording = session.recording.lock().unwrap();
+        match (enabled, recording.as_ref()) {
+            (true, None) if session.zero_retention => {
+                tracing::info!(code = %code, "Not recording a session that keeps nothing");
+            }
+            (true, None) => {
+                let id = self.inner.recordings.start(&session.client_id);
+                tracing::info!(code = %code, recording_id = %id, "Recording started");
+                *recording = Some(id);
+            }
+            (false, Some(id)) => {
+                tracing::info!(code = %code, recording_id = %id, "Recording stopped");
+                self.inner.recordings.stop(id);
+                *recording = None;
+            }
+            _ => {}
+        }
+        recording.clone()
+    }
+
+    /// ID of the recording of a session going on, if any
+    pub fn recording_of(&self, code: &str) -> Option<String> {
+        self.inner.sessions.get(code)?.recording.lock().unwrap().clone()
+    }
+
+    /// Recordings of the Mac holding a session, also from its earlier
+    /// sessions
+    pub fn recordings_of(&self, code: &str) -> Vec<RecordingInfo> {
+        match self.inner.sessions.get(code) {
+            Some(session) => self.inner.recordings.list(&session.client_id),
+            None => Vec::new(),
+        }
+    }
+
+    /// Frames of a recording with the time since it started, to play back
+    pub fn recording_frames(&self, recording_id: &str) -> Option<Vec<(Duration, Vec<u8>)>> {
+        self.inner.recordings.frames(recording_id)
+    }
+
+    /// Stop the recording of a session that ends
+    fn stop_recording(&self, session: &Session) {
+        if let Some(id) = session.recording.lock().unwrap().take() {
+            self.inner.recordings.stop(&id);
+        }
+    }
+
+    /// Source IPs tracked by the join and registration limits
+    pub fn rate_limited_ips(&self) -> (usize, usize) {
+        (self.inner.join_limiter.tracked(), self.inner.register_limiter.tracked())
+    }
+
+    /// Source IPs and session codes with failed joins
+    pub fn failed_joins_tracked(&self) -> (usize, usize) {
+        self.inner.lockouts.tracked()
+    }
+
     /// Register a new mac-client, returns unique session code
-    pub fn register_mac_client(&self, mac_tx: mpsc::Sender<MacMessage>) -> String {
+    pub fn register_mac_client(
+        &self,
+        mac_tx: mpsc::Sender<MacMessage>,
+        client_id: String,
+        client_name: Option<String>,
+    ) -> String {
         // Generate code with collision check
         let code = loop {
-            let candidate = generate_session_code();
+            let candidate = self.inner.limits.code_format.generate();
+            let affinity = &self.inner.limits.affinity;
+            if affinity.as_ref().is_some_and(|affinity| !affinity.owns(&candidate)) {
+                continue;
+            }
             if !self.inner.sessions.contains_key(&candidate) {
                 break candidate;
             }
             tracing::debug!("Session code collision, regenerating");
         };
 
-        self.inner.sessions.insert(
-            code.clone(),
-            Session {
-                mac_tx,
-                browsers: DashMap::new(),
-                scrollback_frames: Mutex::new(Vec::new()),
-                scrollback_bytes: Mutex::new(0),
-            },
-        );
+        self.inner.sessions.insert(code.clone(), self.new_session(mac_tx, client_id, client_name));
 
         tracing::info!(code = %code, "Mac-client registered");
         code
     }
 
-    /// Validate a session code, returns true if valid
-    pub fn validate_session_code(&self, code: &str) -> bool {
-        self.inner.sessions.contains_key(code)
+    /// A session as a mac-client registering now gets it
+    fn new_session(&self, mac_tx: mpsc::Sender<MacMessage>, client_id: String, client_name: Option<String>) -> Session {
+        Session {
+            client_id,
+            client_name,
+            account: None,
+            password: None,
+            passthrough: false,
+            zero_retention: false,
+            compression: AtomicBool::new(true),
+            transfer: std::sync::Mutex::new(Transfer::new(Instant::now())),
+            latency: std::sync::Mutex::new(SessionLatency::default()),
+            mac_tx,
+            browsers: DashMap::new(),
+            direct_browsers: DashSet::new(),
+            scrollback: Mutex::new(Scrollback::new(self.inner.max_scrollback, self.inner.limits.snapshot_history)),
+            max_browsers: std::sync::Mutex::new(None),
+            sharing_paused: AtomicBool::new(false),
+            approval_required: AtomicBool::new(false),
+            pending_approvals: DashMap::new(),
+            join_tokens: DashMap::new(),
+            invited_keys: DashMap::new(),
+            departed_browsers: DashMap::new(),
+            recording: std::sync::Mutex::new(None),
+            terminals: std::sync::Mutex::new(Vec::new()),
+            chat: std::sync::Mutex::new(VecDeque::new()),
+            resume_token_hash: String::new(),
+            detached: std::sync::Mutex::new(None),
+            view_only: AtomicBool::new(false),
+            created_at: Instant::now(),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+        }
     }
 
-    /// Remove a session (when mac-client disconnects)
-    pub fn remove_session(&self, code: &str) {
-        if self.inner.sessions.remove(code).is_some() {
-            tracing::info!(code = %code, "Session removed");
+    /// The sessions as the state file keeps them across a restart: no
+    /// output, and no terminals of sessions keeping nothing
+    pub fn saved_sessions(&self) -> Vec<SavedSession> {
+        let now = Instant::now();
+        self.inner
+            .sessions
+            .iter()
+            .map(|session| SavedSession {
+                code: session.key().clone(),
+                client_id: session.client_id.clone(),
+                client_name: session.client_name.clone(),
+                account: session.account.clone(),
+                password_hash: session.password.clone(),
+                resume_token_hash: session.resume_token_hash.clone(),
+                password: None,
+                resume_token: None,
+                passthrough: session.passthrough,
+                zero_retention: session.zero_retention,
+                compression: session.compression.load(Ordering::Relaxed),
+                max_browsers: *session.max_browsers.lock().unwrap(),
+                sharing_paused: session.sharing_paused.load(Ordering::Relaxed),
+                approval_required: session.approval_required.load(Ordering::Relaxed),
+                view_only: session.view_only.load(Ordering::Relaxed),
+                invites: session
+                    .invited_keys
+                    .iter()
+                    .filter(|invite| invite.expires > now)
+                    .map(|invite| SavedInvite {
+                        key: invite.key().clone(),
+                        role: invite.role,
+                        token_hash: invite.token_hash.clone(),
+                        expires_in_secs: invite.expires.saturating_duration_since(now).as_secs(),
+                    })
+                    .collect(),
+                terminals: match session.zero_retention {
+                    true => Vec::new(),
+                    false => session.terminals.lock().unwrap().clone(),
+                },
+                age_secs: now.saturating_duration_since(session.created_at).as_secs(),
+            })
+            .collect()
+    }
+
+    /// Hold the sessions of a state file for their mac-clients to come
+    /// back to, as if their connections had dropped when the relay
+    /// stopped. Returns how many were restored.
+    pub fn restore_sessions(&self, saved: Saved) -> usize {
+        let stopped = Duration::from_secs(saved.age_secs());
+        // Past the resume grace every one would be closed right away
+        if stopped >= self.inner.limits.resume_grace {
+            return 0;
+        }
+        let now = Instant::now();
+        let mut restored = 0;
+        for saved in saved.sessions {
+            if self.inner.sessions.contains_key(&saved.code) {
+                continue;
+            }
+            // Nothing reads the channel until the mac-client is back
+            let (mac_tx, _) = mpsc::channel(1);
+            let mut session = self.new_session(mac_tx, saved.client_id, saved.client_name);
+            session.account = saved.account;
+            session.password = saved.password_hash.or(saved.password.as_deref().map(PasswordHash::new));
+            session.resume_token_hash = match saved.resume_token {
+                Some(token) => hash(&token),
+                None => saved.resume_token_hash,
+            };
+            session.compression = AtomicBool::new(saved.compression);
+            session.max_browsers = std::sync::Mutex::new(saved.max_browsers);
+            session.sharing_paused = AtomicBool::new(saved.sharing_paused);
+            session.approval_required = AtomicBool::new(saved.approval_required);
+            session.view_only = AtomicBool::new(saved.view_only);
+            // Their links went on expiring while the relay was down
+            session.invited_keys = saved
+                .invites
+                .into_iter()
+                .filter_map(|invite| {
+                    let left = Duration::from_secs(invite.expires_in_secs).checked_sub(stopped)?;
+                    let expires = now + left;
+                    Some((invite.key, Invite { role: invite.role, expires, token_hash: invite.token_hash }))
+                })
+                .collect();
+            session.terminals = std::sync::Mutex::new(saved.terminals);
+            let age = Duration::from_secs(saved.age_secs) + stopped;
+            session.created_at = now.checked_sub(age).unwrap_or(now);
+            session.detached = std::sync::Mutex::new(Some(now.checked_sub(stopped).unwrap_or(now)));
+            self.inner.sessions.insert(saved.code.clone(), session);
+            self.set_passthrough(&saved.code, saved.passthrough);
+            self.set_zero_retention(&saved.code, saved.zero_retention);
+            restored += 1;
         }
+        restored
     }
 
-    /// Get count of active sessions (for debugging)
-    pub fn session_count(&self) -> usize {
-        self.inner.sessions.len()
+    /// Register a new mac-client, claiming its code from the other relays
+    /// when clustered
+    pub async fn register(
+        &self,
+        mac_tx: mpsc::Sender<MacMessage>,
+        client_id: String,
+        client_name: Option<String>,
+    ) -> String {
+        loop {
+            let code = self.register_mac_client(mac_tx.clone(), client_id.clone(), client_name.clone());
+            let Some(cluster) = &self.inner.cluster else {
+                self.inner.metrics.registered();
+                return code;
+            };
+            match cluster.claim(&code).await {
+                Ok(true) => {
+                    self.inner.metrics.registered();
+                    return code;
+                }
+                Ok(false) => {
+                    tracing::debug!(code = %code, "Session code held by another relay, regenerating");
+                    self.inner.sessions.remove(&code);
+                }
+                // Serve the session anyway; only this relay will know the code
+                Err(e) => {
+                    tracing::warn!(code = %code, "Cannot claim session code: {}", e);
+                    self.inner.metrics.registered();
+                    return code;
+                }
+            }
+        }
     }
 
-    /// Add a browser to a session
-    pub fn add_browser(&self, code: &str, browser_id: String, tx: mpsc::Sender<BrowserMessage>) {
-        if let Some(session) = self.inner.sessions.get(code) {
-            session.browsers.insert(browser_id, tx);
+    /// Where session lifecycle events are sent
+    pub fn webhooks(&self) -> &Webhooks {
+        &self.inner.webhooks
+    }
+
+    /// Where browser input is logged, if anywhere
+    pub fn audit(&self) -> Option<&AuditLog> {
+        self.inner.audit.as_ref()
+    }
+
+    /// Keys Macs must register with; None if any Mac may
+    pub fn api_keys(&self) -> Option<&ApiKeys> {
+        self.inner.api_keys.as_ref()
+    }
+
+    /// Accounts Macs must register with; None if there are none
+    pub fn accounts(&self) -> Option<&Accounts> {
+        self.inner.accounts.as_ref()
+    }
+
+    /// What the sessions of an account use right now
+    pub fn account_usage(&self, account_id: &str) -> AccountUsage {
+        let mut usage = AccountUsage::default();
+        for session in self.inner.sessions.iter() {
+            if session.account.as_deref() == Some(account_id) {
+                usage.sessions += 1;
+                usage.browsers += session.browsers.len();
+            }
+        }
+        if let Some(transfer) = self.inner.account_transfer.get(account_id) {
+            usage.bytes_total = transfer.total();
+            usage.bytes_in_period = transfer.in_period(self.inner.limits.quotas.period, Instant::now());
         }
+        usage
     }
 
-    /// Remove a browser from a session
-    pub fn remove_browser(&self, code: &str, browser_id: &str) {
-        if let Some(session) = self.inner.sessions.get(code) {
-            session.browsers.remove(browser_id);
+    /// The most sessions an account may have open, if it has them all open
+    /// already
+    pub fn account_full(&self, account_id: &str) -> Option<usize> {
+        let max = self.inner.accounts.as_ref()?.limits(account_id)?.max_sessions?;
+        let open = self
+            .inner
+            .sessions
+            .iter()
+            .filter(|session| session.account.as_deref() == Some(account_id))
+            .count();
+        (open >= max).then_some(max)
+    }
+
+    /// Count a session against an account's limits (set before the code is
+    /// handed out)
+    pub fn set_account(&self, code: &str, account_id: Option<String>) {
+        if let Some(mut session) = self.inner.sessions.get_mut(code) {
+            session.account = account_id;
         }
     }
 
-    /// Broadcast terminal output (binary) to all browsers in a session
-    pub async fn broadcast_to_browsers(&self, code: &str, data: Vec<u8>) {
-        if let Some(session) = self.inner.sessions.get(code) {
-            // Append frame to scrollback, dropping oldest frames if over cap
-            {
-                let frame_len = data.len();
-                let mut frames = session.scrollback_frames.lock().await;
-                let mut total = session.scrollback_bytes.lock().await;
+    /// Whether `given` is the admin API's token
+    pub fn admin_token_matches(&self, given: &str) -> bool {
+        self.inner
+            .limits
+            .admin_token
+            .as_deref()
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), given.as_bytes()))
+    }
 
-                frames.push(data.clone());
-                *total += frame_len;
+    /// Log an input frame of a browser, if input is audited. Zero-retention
+    /// sessions are audited too
+    pub async fn audit_input(&self, code: &str, browser_id: &str, frame: &[u8]) {
+        let Some(audit) = &self.inner.audit else { return };
+        let Some(client_id) = self.inner.sessions.get(code).map(|s| s.client_id.clone()) else {
+            return;
+        };
+        audit.record(AuditEntry::new(code, &client_id, browser_id, frame)).await;
+    }
 
-                // Drop oldest frames until we're under the cap
-                while *total > MAX_SCROLLBACK && !frames.is_empty() {
-                    let removed = frames.remove(0);
-                    *total -= removed.len();
-                }
+    /// Delete audit files past their retention
+    pub async fn prune_audit(&self) {
+        if let Some(audit) = &self.inner.audit {
+            audit.prune().await;
+        }
+    }
+
+    /// Counters for `/metrics`
+    pub fn metrics(&self) -> &Metrics {
+        &self.inner.metrics
+    }
+
+    /// Current sessions, browsers and scrollback, for `/metrics`
+    pub async fn gauges(&self) -> Gauges {
+        let sessions: Vec<_> = self.session_codes();
+        let mut gauges = Gauges {
+            sessions: sessions.len(),
+            ..Gauges::default()
+        };
+        for code in sessions {
+            if let Some(session) = self.inner.sessions.get(&code) {
+                gauges.browsers += session.browsers.len();
+                gauges.scrollback_bytes += session.scrollback.lock().await.bytes();
             }
+        }
+        gauges.recording_bytes = self.inner.recordings.bytes();
+        gauges.clients = self.client_transfer();
+        gauges.runtime = Runtime::current();
+        gauges
+    }
 
-            for entry in session.browsers.iter() {
-                let _ = entry.value().send(BrowserMessage::Binary(data.clone())).await;
+    /// Redis shared with other relays, if any
+    pub fn cluster(&self) -> Option<&Cluster> {
+        self.inner.cluster.as_ref()
+    }
+
+    /// Relays behind the same load balancer, if routed by session code
+    pub fn affinity(&self) -> Option<&Affinity> {
+        self.inner.limits.affinity.as_ref()
+    }
+
+    /// Relays of other regions, if this one peers with any
+    pub fn federation(&self) -> Option<&Federation> {
+        self.inner.federation.as_ref()
+    }
+
+    /// Codes of the sessions held by this relay
+    pub fn session_codes(&self) -> Vec<String> {
+        self.inner.sessions.iter().map(|s| s.key().clone()).collect()
+    }
+
+    /// Validate a session code, returns true if valid
+    pub fn validate_session_code(&self, code: &str) -> bool {
+        self.inner.sessions.contains_key(code)
+    }
+
+    /// Require browsers to send `password` to join (set before the code is
+    /// handed out)
+    pub fn set_password(&self, code: &str, password: Option<String>) {
+        if let Some(mut session) = self.inner.sessions.get_mut(code) {
+            session.password = password.filter(|p| !p.is_empty()).as_deref().map(PasswordHash::new);
+        }
+    }
+
+    /// Treat a session's frames as ciphertext (set before the code is
+    /// handed out)
+    pub fn set_passthrough(&self, code: &str, passthrough: bool) {
+        if let Some(mut session) = self.inner.sessions.get_mut(code) {
+            session.passthrough = passthrough;
+            // The relay cannot interpret ciphertext, and it does not compress
+            if passthrough {
+                session.scrollback.get_mut().disable_snapshots();
+                session.compression.store(false, Ordering::Relaxed);
             }
         }
     }
 
-    /// Purge scrollback frames belonging to a specific terminal session.
-    /// Binary frame format: [1 byte session_id_len][session_id][payload]
-    pub async fn purge_session_scrollback(&self, code: &str, terminal_session_id: &str) {
+    /// Keep at most `max_bytes` of scrollback per terminal of a session, as
+    /// its mac-client asked, and the relay's cap without a request. Never
+    /// more than the cap, or than its account's.
+    pub fn set_scrollback_limit(&self, code: &str, max_bytes: Option<u64>) {
+        if let Some(mut session) = self.inner.sessions.get_mut(code) {
+            let account_cap = session
+                .account
+                .as_deref()
+                .and_then(|id| self.inner.accounts.as_ref()?.limits(id)?.scrollback_bytes)
+                .map(|max| usize::try_from(max).unwrap_or(usize::MAX));
+            let cap = account_cap.map_or(self.inner.max_scrollback, |max| max.min(self.inner.max_scrollback));
+            let max_bytes = max_bytes.map_or(cap, |max| usize::try_from(max).unwrap_or(usize::MAX).min(cap));
+            let max_bytes = if session.zero_retention { 0 } else { max_bytes };
+            session.scrollback.get_mut().set_max_bytes(max_bytes);
+        }
+    }
+
+    /// Keep nothing a session relays from now on, as its mac-client asked:
+    /// what is kept already goes, and so does a recording going on
+    pub fn set_zero_retention(&self, code: &str, enabled: bool) {
+        let Some(mut session) = self.inner.sessions.get_mut(code) else { return };
+        session.zero_retention = enabled;
+        if enabled {
+            session.scrollback.get_mut().set_max_bytes(0);
+            session.chat.lock().unwrap().clear();
+            self.stop_recording(&session);
+        }
+    }
+
+    /// Whether a session keeps nothing it relays
+    pub fn is_zero_retention(&self, code: &str) -> bool {
+        self.inner.sessions.get(code).is_some_and(|session| session.zero_retention)
+    }
+
+    /// Allow or refuse compressed output for browsers joining a session
+    pub fn set_compression(&self, code: &str, enabled: bool) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let mut frames = session.scrollback_frames.lock().await;
-            let mut total = session.scrollback_bytes.lock().await;
+            session.compression.store(enabled, Ordering::Relaxed);
+        }
+    }
 
-            let tid = terminal_session_id.as_bytes();
-            let before = frames.len();
-            frames.retain(|frame| {
-                if frame.is_empty() {
-                    return false;
-                }
-                let id_len = frame[0] as usize;
-                if frame.len() < 1 + id_len {
-                    return false;
-                }
-                let frame_sid = &frame[1..1 + id_len];
-                frame_sid != tid
-            });
-            let after = frames.len();
+    /// Whether browsers joining a session may get compressed output
+    pub fn compression_allowed(&self, code: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.compression.load(Ordering::Relaxed))
+    }
+
+    /// Whether a session's frames are end-to-end encrypted
+    pub fn is_passthrough(&self, code: &str) -> bool {
+        self.inner.sessions.get(code).is_some_and(|session| session.passthrough)
+    }
 
-            // Recalculate total bytes
-            *total = frames.iter().map(|f| f.len()).sum();
+    /// Whether browsers need a password to join a session
+    pub fn requires_password(&self, code: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.password.is_some())
+    }
 
-            if before != after {
-                tracing::info!(
-                    code = %code,
-                    terminal_session_id = %terminal_session_id,
-                    purged = before - after,
-                    remaining = after,
-                    "Purged scrollback frames for dead session"
-                );
-            }
+    /// Whether `given` lets a browser into a session
+    pub fn password_matches(&self, code: &str, given: &str) -> bool {
+        let Some(session) = self.inner.sessions.get(code) else {
+            return false;
+        };
+        session.password.as_ref().is_none_or(|password| password.matches(given))
+    }
+
+    /// Display name of the Mac behind a session code
+    pub fn client_name(&self, code: &str) -> Option<String> {
+        self.inner.sessions.get(code)?.client_name.clone()
+    }
+
+    /// Whether the per-client browser limit keeps another browser from
+    /// joining a session (counted over every session of the same client ID)
+    pub fn browser_limit_reached(&self, code: &str) -> bool {
+        let Some(max) = self.inner.max_browsers_per_client else {
+            return false;
+        };
+        let Some(client_id) = self.inner.sessions.get(code).map(|s| s.client_id.clone()) else {
+            return false;
+        };
+        let browsers: usize = self
+            .inner
+            .sessions
+            .iter()
+            .filter(|s| s.client_id == client_id)
+            .map(|s| s.browsers.len())
+            .sum();
+        browsers >= max
+    }
+
+    /// Set the browser limit of a session; None falls back to the relay's
+    pub fn set_browser_limit(&self, code: &str, max: Option<usize>) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            *session.max_browsers.lock().unwrap() = max;
         }
     }
 
-    /// Get scrollback frames for replay to a newly connected browser.
-    pub async fn get_scrollback(&self, code: &str) -> Vec<Vec<u8>> {
+    /// The browser limit of a session if it keeps another browser from
+    /// joining: the mac-client's limit, capped by the relay's
+    pub fn session_full(&self, code: &str) -> Option<usize> {
+        let session = self.inner.sessions.get(code)?;
+        let relay = self.inner.limits.max_browsers_per_session;
+        let max = match (*session.max_browsers.lock().unwrap(), relay) {
+            (Some(mac), Some(relay)) => mac.min(relay),
+            (mac, relay) => mac.or(relay)?,
+        };
+        (session.browsers.len() >= max).then_some(max)
+    }
+
+    /// Usage of every registered mac-client, sorted by client ID
+    pub fn client_usage(&self) -> Vec<ClientUsage> {
+        let mut usage: Vec<ClientUsage> = self
+            .inner
+            .sessions
+            .iter()
+            .map(|entry| {
+                let latency = entry.latency.lock().unwrap();
+                ClientUsage {
+                    code: entry.key().clone(),
+                    client_id: entry.client_id.clone(),
+                    client_name: entry.client_name.clone(),
+                    browsers: entry.browsers.len(),
+                    bytes_relayed: entry.transfer.lock().unwrap().total(),
+                    round_trip: latency.end_to_end.percentiles(),
+                    mac_round_trip: latency.mac.percentiles(),
+                }
+            })
+            .collect();
+        usage.sort_by(|a, b| (&a.client_id, &a.code).cmp(&(&b.client_id, &b.code)));
+        usage
+    }
+
+    /// Keep a round trip a browser of the session measured, in milliseconds
+    pub fn record_round_trip(&self, code: &str, ms: f64) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let frames = session.scrollback_frames.lock().await;
-            frames.clone()
-        } else {
-            Vec::new()
+            session.latency.lock().unwrap().end_to_end.record(ms);
         }
     }
 
-    /// Broadcast text message (JSON) to all browsers in a session
-    pub async fn broadcast_text_to_browsers(&self, code: &str, text: &str) {
+    /// Keep a round trip between the relay and the session's Mac
+    pub fn record_mac_round_trip(&self, code: &str, ms: f64) {
         if let Some(session) = self.inner.sessions.get(code) {
-            for entry in session.browsers.iter() {
-                let _ = entry.value().send(BrowserMessage::Text(text.to_string())).await;
+            session.latency.lock().unwrap().mac.record(ms);
+        }
+    }
+
+    /// Bytes relayed per client ID, sorted by client ID
+    pub fn client_transfer(&self) -> Vec<ClientTransfer> {
+        let now = Instant::now();
+        let period = self.inner.limits.quotas.period;
+        let mut transfer: Vec<ClientTransfer> = self
+            .inner
+            .client_transfer
+            .iter()
+            .map(|entry| ClientTransfer {
+                client_id: entry.key().clone(),
+                bytes_total: entry.total(),
+                bytes_in_period: entry.in_period(period, now),
+            })
+            .collect();
+        transfer.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        transfer
+    }
+
+    /// Count bytes a session relayed towards its quotas, its client's and
+    /// its account's
+    fn charge(&self, session: &Session, bytes: usize) -> Verdict {
+        let now = Instant::now();
+        let quotas = &self.inner.limits.quotas;
+        let bytes = bytes as u64;
+        let in_session = session.transfer.lock().unwrap().add(bytes, quotas.period, now);
+        let in_client = self
+            .inner
+            .client_transfer
+            .entry(session.client_id.clone())
+            .or_insert_with(|| Transfer::new(now))
+            .add(bytes, quotas.period, now);
+        if let Some(account_id) = &session.account {
+            let in_account = self
+                .inner
+                .account_transfer
+                .entry(account_id.clone())
+                .or_insert_with(|| Transfer::new(now))
+                .add(bytes, quotas.period, now);
+            let quota = self.inner.accounts.as_ref().and_then(|accounts| accounts.limits(account_id)?.quota_bytes);
+            if quota.is_some_and(|max| in_account > max) {
+                return quotas.over(bytes);
             }
         }
+        quotas.verdict(bytes, in_session, in_client)
     }
 
-    /// Send keyboard input (binary) to mac-client
-    pub async fn send_to_mac_client(&self, code: &str, data: Vec<u8>) {
+    /// Refuse or accept new browsers for a session (mac-client Do Not Disturb)
+    pub fn set_sharing_paused(&self, code: &str, paused: bool) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let _ = session.mac_tx.send(MacMessage::Binary(data)).await;
+            session.sharing_paused.store(paused, Ordering::Relaxed);
         }
     }
 
-    /// Send text message (JSON) to mac-client
-    pub async fn send_text_to_mac_client(&self, code: &str, text: &str) {
+    /// Whether the mac-client behind a session has paused sharing
+    pub fn is_sharing_paused(&self, code: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.sharing_paused.load(Ordering::Relaxed))
+    }
+
+    /// Require (or stop requiring) the mac-client to approve new browsers.
+    /// Browsers still waiting are let in when the requirement is dropped.
+    pub fn set_approval_required(&self, code: &str, required: bool) {
         if let Some(session) = self.inner.sessions.get(code) {
-            let _ = session.mac_tx.send(MacMessage::Text(text.to_string())).await;
+            session.approval_required.store(required, Ordering::Relaxed);
+            if !required {
+                let waiting: Vec<String> =
+                    session.pending_approvals.iter().map(|e| e.key().clone()).collect();
+                for browser_id in waiting {
+                    if let Some((_, tx)) = session.pending_approvals.remove(&browser_id) {
+                        let _ = tx.send(Some(Role::Controller));
+                    }
+                }
+            }
         }
     }
-}
 
-impl Default for AppState {
+    /// Whether new browsers of a session need the mac-client's approval
+    pub fn is_approval_required(&self, code: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.approval_required.load(Ordering::Relaxed))
+    }
+
+    /// Park a browser until the mac-client answers. The receiver yields the
+    /// role the browser is let in with (None if denied), or an error if the
+    /// session goes away first.
+    pub fn request_approval(&self, code: &str, browser_id: &str) -> Option<oneshot::Receiver<Option<Role>>> {
+        let session = self.inner.sessions.get(code)?;
+        let (tx, rx) = oneshot::channel();
+        session.pending_approvals.insert(browser_id.to_string(), tx);
+        Some(rx)
+    }
+
+    /// Deliver the mac-client's decision. Returns false if the browser is no
+    /// longer waiting.
+    pub fn answer_approval(&self, code: &str, browser_id: &str, approved: bool, role: Option<Role>) -> bool {
+        let decision = approved.then(|| role.unwrap_or_default());
+        self.inner
+            .sessions
+            .get(code)
+            .and_then(|session| session.pending_approvals.remove(browser_id))
+            .is_some_and(|(_, tx)| tx.send(decision).is_ok())
+    }
+
+    /// Let new browsers of a session join as viewers only, or as they ask
+    pub fn set_browser_role(&self, code: &str, role: Role) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.view_only.store(role == Role::Viewer, Ordering::Relaxed);
+        }
+    }
+
+    /// The most a new browser of a session gets
+    pub fn browser_role(&self, code: &str) -> Role {
+        let view_only = self
+            .inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.view_only.load(Ordering::Relaxed));
+        if view_only {
+            Role::Viewer
+        } else {
+            Role::Controller
+        }
+    }
+
+    /// Mint a join link token for a session with `scope`. Returns the
+    /// token.
+    pub fn create_join_token(&self, code: &str, scope: JoinScope) -> Option<String> {
+        let session = self.inner.sessions.get(code)?;
+        let now = Instant::now();
+        session.join_tokens.retain(|_, link| link.expires > now);
+        let token = nanoid::nanoid!(22);
+        let link = JoinLink {
+            role: scope.role,
+            uses_left: scope.uses,
+            expires: now + scope.ttl,
+        };
+        session.join_tokens.insert(token.clone(), link);
+        Some(token)
+    }
+
+    /// Use up one use of a join link token. Returns the most the browser
+    /// gets if the token was valid, or if the browser with `browser_key`
+    /// already came in with this link and it has not expired yet (a
+    /// reconnect); None if neither.
+    pub fn redeem_join_token(&self, code: &str, token: &str, browser_key: Option<&str>) -> Option<Role> {
+        let session = self.inner.sessions.get(code)?;
+        let now = Instant::now();
+        let mut granted = None;
+        session.join_tokens.remove_if_mut(token, |_, link| {
+            if now < link.expires {
+                granted = Some(Invite {
+                    role: link.role,
+                    expires: link.expires,
+                    token_hash: hash(token),
+                });
+                link.uses_left -= 1;
+            }
+            link.uses_left == 0 || now >= link.expires
+        });
+        session.invited_keys.retain(|_, invite| invite.expires > now);
+        match (browser_key, granted) {
+            (Some(key), Some(invite)) => {
+                let role = invite.role;
+                session.invited_keys.insert(key.to_string(), invite);
+                Some(role)
+            }
+            // A key alone is not enough: the browser shows the link too
+            (Some(key), None) => session
+                .invited_keys
+                .get(key)
+                .filter(|invite| constant_time_eq(invite.token_hash.as_bytes(), hash(token).as_bytes()))
+                .map(|invite| invite.role),
+            (None, granted) => granted.map(|invite| invite.role),
+        }
+    }
+
+    /// A token for a joining browser to resume with later, unless the relay
+    /// does not let browsers resume
+    pub fn browser_resume_token(&self) -> Option<String> {
+        (!self.inner.limits.browser_resume_window.is_zero()).then(|| nanoid::nanoid!(32))
+    }
+
+    /// Remember a browser that left, for it to come back with `token`
+    pub fn keep_departed_browser(&self, code: &str, token: String, browser: DepartedBrowser) {
+        let Some(session) = self.inner.sessions.get(code) else { return };
+        let now = Instant::now();
+        let window = self.inner.limits.browser_resume_window;
+        session
+            .departed_browsers
+            .retain(|_, (left, _)| now.saturating_duration_since(*left) < window);
+        session.departed_browsers.insert(token, (now, browser));
+    }
+
+    /// The browser that left with `token`, if it came back within the
+    /// resume window
+    pub fn departed_browser(&self, code: &str, token: &str) -> Option<DepartedBrowser> {
+        let session = self.inner.sessions.get(code)?;
+        let departed = session.departed_browsers.get(token)?;
+        let (left, browser) = departed.value();
+        (left.elapsed() < self.inner.limits.browser_resume_window).then(|| browser.clone())
+    }
+
+    /// Spend `token` once its browser is let back in. A token works once:
+    /// None if another connection spent it first.
+    pub fn take_departed_browser(&self, code: &str, token: &str) -> Option<DepartedBrowser> {
+        let session = self.inner.sessions.get(code)?;
+        session.departed_browsers.remove(token).map(|(_, (_, browser))| browser)
+    }
+
+    /// Forget a browser that stopped waiting. Returns true if it was waiting.
+    pub fn cancel_approval(&self, code: &str, browser_id: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.pending_approvals.remove(browser_id).is_some())
+    }
+
+    /// Whether `code` is still the session of the mac-client sending on
+    /// `mac_tx` (the relay may have closed it and handed the code out again)
+    pub fn is_session_of(&self, code: &str, mac_tx: &mpsc::Sender<MacMessage>) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.mac_tx.same_channel(mac_tx))
+    }
+
+    /// Issue the token the mac-client of a session reclaims it with,
+    /// replacing any earlier one. Only its hash is kept.
+    pub fn new_resume_token(&self, code: &str) -> Option<String> {
+        let mut session = self.inner.sessions.get_mut(code)?;
+        let token = nanoid::nanoid!(32);
+        session.resume_token_hash = hash(&token);
+        Some(token)
+    }
+
+    /// Whether `given` is the current resume token of a session, which
+    /// only its mac-client knows
+    pub fn resume_token_matches(&self, code: &str, given: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| constant_time_eq(session.resume_token_hash.as_bytes(), hash(given).as_bytes()))
+    }
+
+    /// Bytes per second of scrollback replay, if paced
+    pub fn replay_rate(&self) -> Option<usize> {
+        self.inner.limits.replay_rate
+    }
+
+    /// Where browsers reach the web UI, if set
+    pub fn public_url(&self) -> Option<&str> {
+        self.inner.limits.public_url.as_deref()
+    }
+
+    /// STUN/TURN URLs for browsers' direct links, if set
+    pub fn ice_servers(&self) -> Option<Vec<String>> {
+        self.inner.limits.ice_servers.clone()
+    }
+
+    /// Whether a session is held for its mac-client to come back
+    pub fn is_detached(&self, code: &str) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .is_some_and(|session| session.detached.lock().unwrap().is_some())
+    }
+
+    /// Hold a session whose mac-client's connection dropped, for it to
+    /// come back. Returns false if the relay does not hold sessions.
+    pub fn detach_session(&self, code: &str) -> bool {
+        if self.inner.limits.resume_grace.is_zero() {
+            return false;
+        }
+        let Some(session) = self.inner.sessions.get(code) else {
+            return false;
+        };
+        *session.detached.lock().unwrap() = Some(Instant::now());
+        true
+    }
+
+    /// Hand a session back to its mac-client, on a new connection: the code,
+    /// scrollback and browsers stay. A connection still holding the session
+    /// is closed. The token is spent until a new one is issued; returns the
+    /// session code.
+    pub fn resume(
+        &self,
+        token: &str,
+        client_id: &str,
+        passthrough: bool,
+        mac_tx: mpsc::Sender<MacMessage>,
+    ) -> Option<String> {
+        let token_hash = hash(token);
+        let code = self
+            .inner
+            .sessions
+            .iter()
+            .find(|s| constant_time_eq(s.resume_token_hash.as_bytes(), token_hash.as_bytes()))
+            .map(|s| s.key().clone())?;
+        let mut session = self.inner.sessions.get_mut(&code)?;
+        // Encrypted and plain output do not mix in one scrollback
+        if session.client_id != client_id || session.passthrough != passthrough {
+            return None;
+        }
+        let old_tx = std::mem::replace(&mut session.mac_tx, mac_tx);
+        let _ = old_tx.try_send(MacMessage::Close);
+        session.resume_token_hash = String::new();
+        *session.detached.lock().unwrap() = None;
+        session.touch();
+        tracing::info!(code = %code, "Mac-client resumed session");
+        Some(code)
+    }
+
+    /// The output kept of one terminal session, for its transcript. None
+    /// for a terminal the mac-client never reported and nothing is kept of.
+    pub async fn terminal_output(&self, code: &str, terminal_session_id: &str) -> Option<Vec<u8>> {
+        let session = self.inner.sessions.get(code)?;
+        let output = session.scrollback.lock().await.output(terminal_session_id.as_bytes());
+        let reported = || session.terminals.lock().unwrap().iter().any(|t| t.session.id == terminal_session_id);
+        output.or_else(|| reported().then(Vec::new))
+    }
+
+    /// Pass a chat line from a browser to the session's browsers and its
+    /// mac-client, and keep it for browsers joining later. Returns false if
+    /// there was nothing to pass.
+    pub async fn chat(&self, code: &str, browser_id: &str, name: Option<String>, text: &str) -> bool {
+        let text = text.trim();
+        if text.is_empty() {
+            return false;
+        }
+        let Some(session) = self.inner.sessions.get(code) else {
+            return false;
+        };
+        let name = clean_nickname(name).or_else(|| {
+            session
+                .browsers
+                .get(browser_id)
+                .and_then(|browser| browser.viewer.nickname.clone())
+        });
+        let msg = ControlMessage::Chat {
+            browser_id: browser_id.to_string(),
+            name,
+            text: text.chars().take(MAX_CHAT_CHARS).collect(),
+            at: unix_millis(),
+        };
+        if !session.zero_retention {
+            let mut chat = session.chat.lock().unwrap();
+            chat.push_back(msg.clone());
+            while chat.len() > self.inner.limits.chat_history {
+                chat.pop_front();
+            }
+        }
+        drop(session);
+        self.broadcast_control_to_browsers(code, &msg).await;
+        self.send_control_to_mac_client(code, &msg).await;
+        true
+    }
+
+    /// Chat lines kept for a browser that joins, oldest first
+    pub fn chat_history(&self, code: &str) -> Vec<ControlMessage> {
+        self.inner
+            .sessions
+            .get(code)
+            .map(|session| session.chat.lock().unwrap().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Terminal sessions the mac-client reported, or None for an unknown code
+    pub fn terminals(&self, code: &str) -> Option<Vec<TerminalInfo>> {
+        Some(self.inner.sessions.get(code)?.terminals.lock().unwrap().clone())
+    }
+
+    /// The mac-client sent its session list; terminals it had reported
+    /// before keep their attach time
+    pub fn set_terminals(&self, code: &str, sessions: &[SessionInfo]) {
+        let Some(session) = self.inner.sessions.get(code) else { return };
+        let mut terminals = session.terminals.lock().unwrap();
+        let now = unix_now();
+        *terminals = sessions
+            .iter()
+            .map(|info| TerminalInfo {
+                session: info.clone(),
+                attached_at: terminals
+                    .iter()
+                    .find(|t| t.session.id == info.id)
+                    .map_or(now, |t| t.attached_at),
+            })
+            .collect();
+    }
+
+    /// A terminal session was opened
+    pub fn add_terminal(&self, code: &str, id: &str, name: &str) {
+        let Some(session) = self.inner.sessions.get(code) else { return };
+        let mut terminals = session.terminals.lock().unwrap();
+        if terminals.iter().all(|t| t.session.id != id) {
+            terminals.push(TerminalInfo {
+                session: SessionInfo {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    ..SessionInfo::default()
+                },
+                attached_at: unix_now(),
+            });
+        }
+    }
+
+    /// A terminal session was renamed
+    pub fn rename_terminal(&self, code: &str, id: &str, name: &str) {
+        let Some(session) = self.inner.sessions.get(code) else { return };
+        let mut terminals = session.terminals.lock().unwrap();
+        if let Some(terminal) = terminals.iter_mut().find(|t| t.session.id == id) {
+            terminal.session.name = name.to_string();
+        }
+    }
+
+    /// Something about a terminal session changed
+    pub fn update_terminal(&self, code: &str, id: &str, metadata: &TerminalMetadata) {
+        let Some(session) = self.inner.sessions.get(code) else { return };
+        let mut terminals = session.terminals.lock().unwrap();
+        if let Some(terminal) = terminals.iter_mut().find(|t| t.session.id == id) {
+            metadata.apply(&mut terminal.session);
+        }
+    }
+
+    /// A terminal session ended
+    pub fn remove_terminal(&self, code: &str, id: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.terminals.lock().unwrap().retain(|t| t.session.id != id);
+        }
+    }
+
+    /// Connected browsers of a session and what they may do
+    pub fn browsers_of(&self, code: &str) -> Vec<(String, Role)> {
+        self.inner
+            .sessions
+            .get(code)
+            .map(|session| session.browsers.iter().map(|b| (b.key().clone(), b.viewer.role)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Browsers connected to a session, oldest first
+    pub fn presence(&self, code: &str) -> Vec<Viewer> {
+        let mut viewers: Vec<Viewer> = self
+            .inner
+            .sessions
+            .get(code)
+            .map(|session| session.browsers.iter().map(|b| b.viewer.clone()).collect())
+            .unwrap_or_default();
+        viewers.sort_by(|a, b| a.joined_at.cmp(&b.joined_at).then_with(|| a.browser_id.cmp(&b.browser_id)));
+        viewers
+    }
+
+    /// Tell the mac-client and every browser who is connected now;
+    /// browsers get the list without IPs
+    pub async fn broadcast_presence(&self, code: &str) {
+        let mut viewers = self.presence(code);
+        self.send_control_to_mac_client(code, &ControlMessage::Presence { viewers: viewers.clone() })
+            .await;
+        for viewer in &mut viewers {
+            viewer.ip = None;
+        }
+        self.broadcast_control_to_browsers(code, &ControlMessage::Presence { viewers })
+            .await;
+    }
+
+    /// Change the name a connected browser goes by. Returns false if the
+    /// browser is not connected.
+    pub fn set_nickname(&self, code: &str, browser_id: &str, nickname: Option<String>) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .and_then(|session| {
+                session
+                    .browsers
+                    .get_mut(browser_id)
+                    .map(|mut b| b.viewer.nickname = clean_nickname(nickname))
+            })
+            .is_some()
+    }
+
+    /// Remove a session (when mac-client disconnects)
+    pub fn remove_session(&self, code: &str) {
+        if let Some((_, session)) = self.inner.sessions.remove(code) {
+            self.stop_recording(&session);
+            tracing::info!(code = %code, "Session removed");
+            self.release_code(code);
+        }
+    }
+
+    /// Close sessions past their lifetime or idle limit: browsers and the
+    /// mac-client are told why, the mac-client is disconnected and the code
+    /// is freed. Returns the closed codes.
+    pub async fn expire_sessions(&self) -> Vec<String> {
+        let now = Instant::now();
+        let expired: Vec<(String, Expiry)> = self
+            .inner
+            .sessions
+            .iter()
+            .filter_map(|s| Some((s.key().clone(), s.expiry(&self.inner.limits, now)?)))
+            .collect();
+        let mut closed = Vec::with_capacity(expired.len());
+        for (code, expiry) in expired {
+            if self.close_session(&code, expiry).await {
+                closed.push(code);
+            }
+        }
+        closed
+    }
+
+    /// Close a session for `expiry`, telling browsers and the mac-client
+    /// why. Returns false if it was gone already.
+    pub async fn close_session(&self, code: &str, expiry: Expiry) -> bool {
+        let Some((_, session)) = self.inner.sessions.remove(code) else {
+            return false;
+        };
+        self.stop_recording(&session);
+        self.release_code(code);
+        let text = ControlMessage::Error {
+            message: expiry.message().to_string(),
+        }
+        .to_text();
+        for entry in session.browsers.iter() {
+            let _ = entry.tx.send(BrowserMessage::Text(text.clone())).await;
+        }
+        let _ = session.mac_tx.send(MacMessage::Text(text)).await;
+        let _ = session.mac_tx.send(MacMessage::Close).await;
+        tracing::info!(code = %code, reason = ?expiry, "Session expired");
+        self.inner.webhooks.notify(WebhookEvent::SessionExpired {
+            code: code.to_string(),
+            reason: expiry.message().to_string(),
+        });
+        true
+    }
+
+    /// Give a removed session's code back to the other relays
+    fn release_code(&self, code: &str) {
+        if let Some(cluster) = self.inner.cluster.clone() {
+            let code = code.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = cluster.release(&code).await {
+                    tracing::warn!(code = %code, "Cannot release session code: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Get count of active sessions (for debugging)
+    pub fn session_count(&self) -> usize {
+        self.inner.sessions.len()
+    }
+
+    /// Add a browser to a session
+    /// Returns the flag set when output to the browser had to be dropped.
+    pub fn add_browser(
+        &self,
+        code: &str,
+        mut viewer: Viewer,
+        tx: mpsc::Sender<BrowserMessage>,
+    ) -> Result<Arc<AtomicBool>, JoinRefusal> {
+        let _admission = self.inner.admission.lock().unwrap();
+        if self.browser_limit_reached(code) {
+            return Err(JoinRefusal::TooManyBrowsers);
+        }
+        if let Some(max) = self.session_full(code) {
+            return Err(JoinRefusal::SessionFull(max));
+        }
+        let lagging = Arc::new(AtomicBool::new(false));
+        if let Some(session) = self.inner.sessions.get(code) {
+            viewer.nickname = clean_nickname(viewer.nickname);
+            let browser_id = viewer.browser_id.clone();
+            let browser = Browser {
+                tx,
+                viewer,
+                lagging: lagging.clone(),
+            };
+            session.browsers.insert(browser_id, browser);
+            self.inner.metrics.joined();
+        }
+        Ok(lagging)
+    }
+
+    /// What happens to browsers that fall behind the output
+    pub fn slow_browser(&self) -> SlowBrowser {
+        self.inner.limits.slow_browser
+    }
+
+    /// What a connected browser may do, if it is still connected
+    pub fn role_of(&self, code: &str, browser_id: &str) -> Option<Role> {
+        self.inner
+            .sessions
+            .get(code)
+            .and_then(|session| session.browsers.get(browser_id).map(|b| b.viewer.role))
+    }
+
+    /// Grant or revoke a connected browser's input. Returns false if the
+    /// browser is not connected.
+    pub fn set_role_of(&self, code: &str, browser_id: &str, role: Role) -> bool {
+        self.inner
+            .sessions
+            .get(code)
+            .and_then(|session| session.browsers.get_mut(browser_id).map(|mut b| b.viewer.role = role))
+            .is_some()
+    }
+
+    /// Remove a browser from a session
+    pub fn remove_browser(&self, code: &str, browser_id: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.browsers.remove(browser_id);
+            session.direct_browsers.remove(browser_id);
+            // The idle timeout counts from the last browser leaving
+            session.touch();
+        }
+    }
+
+    /// Mark a browser as receiving output directly from the mac-client (or no
+    /// longer); terminal output is not relayed to direct browsers
+    pub fn set_browser_direct(&self, code: &str, browser_id: &str, active: bool) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            if !active {
+                session.direct_browsers.remove(browser_id);
+            } else if session.browsers.contains_key(browser_id) {
+                session.direct_browsers.insert(browser_id.to_string());
+            }
+        }
+    }
+
+    /// Broadcast terminal output (binary) to all browsers in a session.
+    /// Returns what the session's quotas say about relaying more.
+    pub async fn broadcast_to_browsers(&self, code: &str, data: Bytes) -> Verdict {
+        let started = Instant::now();
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.touch();
+
+            let seq = session.scrollback.lock().await.push(data.clone());
+            if let Some(id) = session.recording.lock().unwrap().as_deref() {
+                self.inner.recordings.append(id, &data);
+            }
+
+            // A browser that does not keep up must not hold up the others:
+            // what does not fit in its queue is dropped, and its send task
+            // deals with the gap
+            let mut relayed = 0;
+            for entry in session.browsers.iter() {
+                if session.direct_browsers.contains(entry.key()) {
+                    continue;
+                }
+                match entry.tx.try_send(BrowserMessage::Binary { seq, data: data.clone() }) {
+                    Ok(()) => {
+                        relayed += data.len();
+                        self.inner.metrics.relayed(Direction::ToBrowsers, data.len());
+                    }
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        if !entry.lagging.swap(true, Ordering::Relaxed) {
+                            tracing::debug!(code = %code, browser_id = %entry.key(), "Browser queue full, dropping its output");
+                        }
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => {}
+                }
+            }
+            self.inner.metrics.broadcast_took(started.elapsed());
+            return self.charge(&session, relayed);
+        }
+        Verdict::Within
+    }
+
+    /// A terminal session was resized on the Mac
+    pub async fn resize_terminal(&self, code: &str, terminal_session_id: &str, rows: u16, cols: u16) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.scrollback.lock().await.resize(terminal_session_id.as_bytes(), rows, cols);
+        }
+    }
+
+    /// Purge scrollback frames belonging to a specific terminal session, by
+    /// their routing header. Malformed frames are dropped as well, except
+    /// in passthrough sessions, where the relay does not judge frames.
+    pub async fn purge_session_scrollback(&self, code: &str, terminal_session_id: &str) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            let mut scrollback = session.scrollback.lock().await;
+            let purged = scrollback.purge(terminal_session_id.as_bytes(), session.passthrough);
+            if purged > 0 {
+                tracing::info!(
+                    code = %code,
+                    terminal_session_id = %terminal_session_id,
+                    purged,
+                    remaining = scrollback.len(),
+                    "Purged scrollback frames for dead session"
+                );
+            }
+        }
+    }
+
+    /// Scrollback frames for replay to a newly connected browser: only the
+    /// ones from `resume_from` on if the browser resumes from there and
+    /// they are all still kept, all of them otherwise
+    pub async fn replay(&self, code: &str, resume_from: Option<u64>) -> Replay {
+        let Some(session) = self.inner.sessions.get(code) else {
+            return Replay {
+                frames: Vec::new(),
+                resumed: false,
+                position: 0,
+            };
+        };
+        let mut scrollback = session.scrollback.lock().await;
+        let position = scrollback.position();
+        match resume_from.and_then(|from| scrollback.frames_from(from)) {
+            Some(frames) => Replay {
+                frames,
+                resumed: true,
+                position,
+            },
+            None => Replay {
+                frames: scrollback.frames(),
+                resumed: false,
+                position,
+            },
+        }
+    }
+
+    /// Send a control message to all browsers in a session
+    pub async fn broadcast_control_to_browsers(&self, code: &str, msg: &ControlMessage) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            let text = msg.to_text();
+            for entry in session.browsers.iter() {
+                let _ = entry.tx.send(BrowserMessage::Text(text.clone())).await;
+            }
+        }
+    }
+
+    /// Send a control message to one browser in a session
+    pub async fn send_control_to_browser(&self, code: &str, browser_id: &str, msg: &ControlMessage) {
+        let tx = self
+            .inner
+            .sessions
+            .get(code)
+            .and_then(|session| session.browsers.get(browser_id).map(|b| b.tx.clone()));
+        if let Some(tx) = tx {
+            let _ = tx.send(BrowserMessage::Text(msg.to_text())).await;
+        }
+    }
+
+    /// Send keyboard input (binary) to mac-client
+    pub async fn send_to_mac_client(&self, code: &str, data: Vec<u8>) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            session.touch();
+            // Quotas are enforced on output only
+            let _ = self.charge(&session, data.len());
+            self.inner.metrics.relayed(Direction::ToMac, data.len());
+            let _ = session.mac_tx.send(MacMessage::Binary(data)).await;
+        }
+    }
+
+    /// Send a control message to the mac-client
+    pub async fn send_control_to_mac_client(&self, code: &str, msg: &ControlMessage) {
+        if let Some(session) = self.inner.sessions.get(code) {
+            let _ = session.mac_tx.send(MacMessage::Text(msg.to_text())).await;
+        }
+    }
+}
+
+/// Compare without stopping at the first difference, so response times do
+/// not reveal how much of a guess was right
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// A session password as the relay keeps it, in memory and in the state
+/// file: the hex SHA-256 of a random salt and the password
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PasswordHash {
+    salt: String,
+    hash: String,
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Self {
+        let salt = nanoid::nanoid!(16);
+        Self {
+            hash: hash(&format!("{}{}", salt, password)),
+            salt,
+        }
+    }
+
+    pub fn matches(&self, given: &str) -> bool {
+        constant_time_eq(self.hash.as_bytes(), hash(&format!("{}{}", self.salt, given)).as_bytes())
+    }
+}
+
+/// A name to show for a browser: trimmed and capped, none if blank
+fn clean_nickname(nickname: Option<String>) -> Option<String> {
+    nickname
+        .map(|name| name.trim().chars().take(MAX_NICKNAME_CHARS).collect::<String>())
+        .filter(|name| !name.is_empty())
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+impl Default for AppState {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountLimits;
+
+    #[test]
+    fn test_browser_limit_counts_every_session_of_a_client() {
+        let state = AppState::with_limits(DEFAULT_MAX_SCROLLBACK, Some(2));
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        let first = state.register_mac_client(mac_tx.clone(), "mac-1".into(), Some("Studio".into()));
+        let second = state.register_mac_client(mac_tx.clone(), "mac-1".into(), Some("Studio".into()));
+        let other = state.register_mac_client(mac_tx, "mac-2".into(), None);
+
+        state.add_browser(&first, Viewer::new("b1", Role::Controller, 0), browser_tx.clone()).unwrap();
+        assert!(!state.browser_limit_reached(&second));
+        state.add_browser(&second, Viewer::new("b2", Role::Controller, 0), browser_tx.clone()).unwrap();
+        assert!(state.browser_limit_reached(&first));
+        assert!(!state.browser_limit_reached(&other));
+        // Checked again as the browser is added, for joins that passed the
+        // first check together
+        let refused = state.add_browser(&first, Viewer::new("b3", Role::Controller, 0), browser_tx.clone());
+        assert_eq!(refused.err(), Some(JoinRefusal::TooManyBrowsers));
+
+        let usage = state.client_usage();
+        assert_eq!(usage.len(), 3);
+        assert_eq!(usage.iter().filter(|u| u.client_id == "mac-1").map(|u| u.browsers).sum::<usize>(), 2);
+        assert_eq!(state.client_name(&first).as_deref(), Some("Studio"));
+    }
+
+    #[test]
+    fn test_session_browser_limit() {
+        let state = AppState::from_limits(Limits {
+            max_browsers_per_session: Some(2),
+            ..Limits::default()
+        });
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.add_browser(&code, Viewer::new("b1", Role::Controller, 0), browser_tx.clone()).unwrap();
+        assert_eq!(state.session_full(&code), None);
+
+        // The Mac can lower the relay's limit but not raise it
+        state.set_browser_limit(&code, Some(1));
+        assert_eq!(state.session_full(&code), Some(1));
+        state.set_browser_limit(&code, Some(100));
+        state.add_browser(&code, Viewer::new("b2", Role::Controller, 0), browser_tx.clone()).unwrap();
+        assert_eq!(state.session_full(&code), Some(2));
+        state.set_browser_limit(&code, None);
+        assert_eq!(state.session_full(&code), Some(2));
+        // Joins that passed the first check together are held to it
+        let refused = state.add_browser(&code, Viewer::new("b3", Role::Controller, 0), browser_tx);
+        assert_eq!(refused.err(), Some(JoinRefusal::SessionFull(2)));
+    }
+
+    #[test]
+    fn test_password() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        assert!(!state.requires_password(&code));
+        assert!(state.password_matches(&code, "anything"));
+
+        state.set_password(&code, Some("hunter2".into()));
+        assert!(state.requires_password(&code));
+        assert!(state.password_matches(&code, "hunter2"));
+        assert!(!state.password_matches(&code, "hunter"));
+        assert!(!state.password_matches("NOCODE", "hunter2"));
+
+        // An empty password is no password
+        state.set_password(&code, Some(String::new()));
+        assert!(!state.requires_password(&code));
+    }
+
+    #[test]
+    fn test_browser_role() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        assert_eq!(state.browser_role(&code), Role::Controller);
+        state.set_browser_role(&code, Role::Viewer);
+        assert_eq!(state.browser_role(&code), Role::Viewer);
+        state.set_browser_role(&code, Role::Controller);
+        assert_eq!(state.browser_role(&code), Role::Controller);
+
+        // Connected browsers keep their own role until it is changed
+        let (browser_tx, _browser_rx) = mpsc::channel(1);
+        state.add_browser(&code, Viewer::new("b1", Role::Viewer, 0), browser_tx).unwrap();
+        assert_eq!(state.role_of(&code, "b1"), Some(Role::Viewer));
+        assert!(state.set_role_of(&code, "b1", Role::Controller));
+        assert_eq!(state.role_of(&code, "b1"), Some(Role::Controller));
+        assert!(!state.set_role_of(&code, "b2", Role::Controller));
+        assert_eq!(state.role_of(&code, "b2"), None);
+    }
+
+    #[test]
+    fn test_codes_belong_to_this_relay() {
+        let instances = vec!["relay-1".to_string(), "relay-2".to_string(), "relay-3".to_string()];
+        let affinity = Affinity::new("relay-2".into(), instances).unwrap();
+        let state = AppState::from_limits(Limits {
+            affinity: Some(affinity.clone()),
+            ..Limits::default()
+        });
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        for _ in 0..20 {
+            let code = state.register_mac_client(mac_tx.clone(), "mac-1".into(), None);
+            assert_eq!(affinity.owner(&code), "relay-2");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire() {
+        let state = AppState::from_limits(Limits {
+            session_idle_timeout: Some(Duration::ZERO),
+            ..Limits::default()
+        });
+        let (mac_tx, mut mac_rx) = mpsc::channel(4);
+        let (browser_tx, _browser_rx) = mpsc::channel(4);
+        let watched = state.register_mac_client(mac_tx.clone(), "mac-1".into(), None);
+        let idle = state.register_mac_client(mac_tx, "mac-2".into(), None);
+        state.add_browser(&watched, Viewer::new("b1", Role::Controller, 0), browser_tx).unwrap();
+
+        assert_eq!(state.expire_sessions().await, vec![idle.clone()]);
+        assert!(!state.validate_session_code(&idle));
+        assert!(state.validate_session_code(&watched));
+        assert!(matches!(mac_rx.recv().await, Some(MacMessage::Text(text)) if text.contains("idle")));
+        assert!(matches!(mac_rx.recv().await, Some(MacMessage::Close)));
+
+        let state = AppState::from_limits(Limits {
+            max_session_lifetime: Some(Duration::ZERO),
+            ..Limits::default()
+        });
+        let (mac_tx, _mac_rx) = mpsc::channel(4);
+        let (browser_tx, _browser_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.add_browser(&code, Viewer::new("b1", Role::Controller, 0), browser_tx).unwrap();
+        assert_eq!(state.expire_sessions().await, vec![code]);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_quotas() {
+        let state = AppState::from_limits(Limits {
+            quotas: Quotas {
+                client_bytes: Some(10),
+                ..Quotas::default()
+            },
+            ..Limits::default()
+        });
+        let (mac_tx, _mac_rx) = mpsc::channel(4);
+        let (browser_tx, _browser_rx) = mpsc::channel(4);
+        let first = state.register_mac_client(mac_tx.clone(), "mac-1".into(), None);
+        let second = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.add_browser(&first, Viewer::new("b1", Role::Controller, 0), browser_tx.clone()).unwrap();
+        state.add_browser(&second, Viewer::new("b2", Role::Controller, 0), browser_tx).unwrap();
+
+        // Output counts once per browser, input too
+        assert_eq!(state.broadcast_to_browsers(&first, Bytes::from(vec![1, b'a', 0, 0])).await, Verdict::Within);
+        state.send_to_mac_client(&first, vec![2, b'a', 0]).await;
+        let usage = state.client_usage();
+        assert_eq!(usage.iter().find(|usage| usage.code == first).unwrap().bytes_relayed, 7);
+        // The client's sessions share its quota
+        assert_eq!(state.broadcast_to_browsers(&second, Bytes::from(vec![1, b'a', 0, 0])).await, Verdict::Exceeded);
+        let transfer = state.client_transfer();
+        assert_eq!(transfer.len(), 1);
+        assert_eq!(transfer[0].bytes_total, 11);
+
+        assert!(state.close_session(&second, Expiry::QuotaExceeded).await);
+        assert!(!state.close_session(&second, Expiry::QuotaExceeded).await);
+        // Kept while the client has sessions or its period runs
+        state.prune_client_transfer();
+        assert_eq!(state.client_transfer().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_account_limits() {
+        let accounts = Accounts::default();
+        let limits = AccountLimits {
+            max_sessions: Some(2),
+            quota_bytes: Some(16),
+            scrollback_bytes: Some(8),
+        };
+        let (account, _) = accounts.create("team".into(), limits).unwrap();
+        let state = AppState::with_cluster(Limits::default(), None, None, Webhooks::default(), None, None, Some(accounts));
+        let (mac_tx, _mac_rx) = mpsc::channel(4);
+        let (browser_tx, _browser_rx) = mpsc::channel(4);
+        let first = state.register_mac_client(mac_tx.clone(), "mac-1".into(), None);
+        state.set_account(&first, Some(account.id.clone()));
+        assert_eq!(state.account_full(&account.id), None);
+        let second = state.register_mac_client(mac_tx.clone(), "mac-2".into(), None);
+        state.set_account(&second, Some(account.id.clone()));
+        assert_eq!(state.account_full(&account.id), Some(2));
+        // Sessions of no account count against none
+        state.register_mac_client(mac_tx, "mac-3".into(), None);
+        assert_eq!(state.account_usage(&account.id).sessions, 2);
+
+        // Scrollback is capped at the account's, even when asked for more
+        state.set_scrollback_limit(&first, Some(1024));
+        state.add_browser(&first, Viewer::new("b1", Role::Controller, 0), browser_tx.clone()).unwrap();
+        for _ in 0..2 {
+            let verdict = state.broadcast_to_browsers(&first, Bytes::from_static(b"\x01a12345")).await;
+            assert_eq!(verdict, Verdict::Within);
+        }
+        assert_eq!(state.replay(&first, None).await.frames.len(), 1);
+
+        // Different Macs of the account share its quota
+        state.add_browser(&second, Viewer::new("b2", Role::Controller, 0), browser_tx).unwrap();
+        assert_eq!(state.broadcast_to_browsers(&second, Bytes::from(vec![1, b'a', 0, 0])).await, Verdict::Exceeded);
+        let usage = state.account_usage(&account.id);
+        assert_eq!((usage.browsers, usage.bytes_total, usage.bytes_in_period), (2, 18, 18));
+
+        state.close_session(&second, Expiry::QuotaExceeded).await;
+        assert_eq!(state.account_full(&account.id), None);
+    }
+
+    #[tokio::test]
+    async fn test_slow_browsers_do_not_hold_up_others() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let (slow_tx, mut slow_rx) = mpsc::channel(1);
+        let (fast_tx, mut fast_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        let lagging = state.add_browser(&code, Viewer::new("slow", Role::Controller, 0), slow_tx).unwrap();
+        state.add_browser(&code, Viewer::new("fast", Role::Controller, 0), fast_tx).unwrap();
+
+        state.broadcast_to_browsers(&code, Bytes::from(vec![1, b'a', 0])).await;
+        assert!(!lagging.load(Ordering::Relaxed));
+        state.broadcast_to_browsers(&code, Bytes::from(vec![1, b'a', 1])).await;
+        assert!(lagging.load(Ordering::Relaxed));
+
+        // The slow browser only got what fit, the other one everything
+        assert!(matches!(slow_rx.recv().await, Some(BrowserMessage::Binary { seq: 0, .. })));
+        assert!(slow_rx.try_recv().is_err());
+        assert!(matches!(fast_rx.recv().await, Some(BrowserMessage::Binary { seq: 0, .. })));
+        assert!(matches!(fast_rx.recv().await, Some(BrowserMessage::Binary { seq: 1, .. })));
+
+        assert_eq!(SlowBrowser::parse(""), Ok(SlowBrowser::Resync));
+        assert_eq!(SlowBrowser::parse("disconnect"), Ok(SlowBrowser::Disconnect));
+        assert!(SlowBrowser::parse("drop").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_keeps_code_and_browsers() {
+        let state = AppState::new();
+        let (old_tx, mut old_rx) = mpsc::channel(4);
+        let (browser_tx, _browser_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(old_tx, "mac-1".into(), None);
+        state.add_browser(&code, Viewer::new("b1", Role::Viewer, 0), browser_tx).unwrap();
+        let token = state.new_resume_token(&code).unwrap();
+        assert!(state.detach_session(&code));
+
+        // Only the same Mac, with the same kind of output, gets it back
+        let (new_tx, _new_rx) = mpsc::channel(4);
+        assert_eq!(state.resume(&token, "mac-2", false, new_tx.clone()), None);
+        assert_eq!(state.resume(&token, "mac-1", true, new_tx.clone()), None);
+        assert_eq!(state.resume(&token, "mac-1", false, new_tx.clone()).as_deref(), Some(code.as_str()));
+        assert!(state.is_session_of(&code, &new_tx));
+        assert!(matches!(old_rx.recv().await, Some(MacMessage::Close)));
+        assert_eq!(state.browsers_of(&code), vec![("b1".to_string(), Role::Viewer)]);
+        // Tokens work once
+        assert!(!state.resume_token_matches(&code, &token));
+        let new_token = state.new_resume_token(&code).unwrap();
+        assert_ne!(new_token, token);
+        assert!(state.resume_token_matches(&code, &new_token));
+        assert_eq!(state.resume(&token, "mac-1", false, new_tx), None);
+        assert!(state.expire_sessions().await.is_empty());
+
+        // Sessions whose Mac does not come back are closed
+        let state = AppState::from_limits(Limits {
+            resume_grace: Duration::from_millis(1),
+            ..Limits::default()
+        });
+        let (mac_tx, _mac_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        assert!(state.detach_session(&code));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(state.expire_sessions().await, vec![code]);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_survive_a_restart() {
+        let before = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(4);
+        let code = before.register_mac_client(mac_tx.clone(), "mac-1".into(), Some("Studio".into()));
+        before.set_password(&code, Some("hunter2".into()));
+        before.set_approval_required(&code, true);
+        before.add_terminal(&code, "a", "zsh");
+        before.broadcast_to_browsers(&code, Bytes::from(vec![1, b'a', b'x'])).await;
+        let token = before.new_resume_token(&code).unwrap();
+        let private = before.register_mac_client(mac_tx, "mac-2".into(), None);
+        before.add_terminal(&private, "b", "secret-project");
+        before.set_zero_retention(&private, true);
+
+        let saved = before.saved_sessions();
+        assert_eq!(saved.len(), 2);
+        assert!(saved.iter().find(|s| s.code == private).unwrap().terminals.is_empty());
+        // Neither the password nor the token is written
+        let written = serde_json::to_string(&saved).unwrap();
+        assert!(!written.contains("hunter2") && !written.contains(&token));
+
+        let after = AppState::new();
+        let restored = after.restore_sessions(Saved { saved_at: unix_now(), sessions: saved.clone() });
+        assert_eq!(restored, 2);
+        // Held for the Mac, without the output but with its settings
+        assert!(after.is_detached(&code));
+        assert!(after.password_matches(&code, "hunter2"));
+        assert!(after.is_approval_required(&code));
+        assert_eq!(after.client_name(&code).as_deref(), Some("Studio"));
+        assert_eq!(after.terminals(&code).unwrap()[0].session.name, "zsh");
+        assert!(after.replay(&code, None).await.frames.is_empty());
+        assert!(after.is_zero_retention(&private));
+        assert_eq!(after.terminals(&private), Some(Vec::new()));
+
+        let (new_tx, _new_rx) = mpsc::channel(4);
+        assert_eq!(after.resume(&token, "mac-1", false, new_tx).as_deref(), Some(code.as_str()));
+        assert!(!after.is_detached(&code));
+
+        // Not after the resume grace
+        let after = AppState::new();
+        let stale = Saved {
+            saved_at: unix_now() - DEFAULT_RESUME_GRACE.as_secs() - 1,
+            sessions: saved,
+        };
+        assert_eq!(after.restore_sessions(stale), 0);
+        assert_eq!(after.session_count(), 0);
+    }
+
+    #[test]
+    fn test_terminals_keep_their_attach_time() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        assert_eq!(state.terminals(&code), Some(Vec::new()));
+        assert_eq!(state.terminals("NOPE"), None);
+
+        state.add_terminal(&code, "a", "zsh");
+        state.inner.sessions.get(&code).unwrap().terminals.lock().unwrap()[0].attached_at = 1;
+        let info = |id: &str, name: &str| SessionInfo {
+            id: id.into(),
+            name: name.into(),
+            ..SessionInfo::default()
+        };
+        state.set_terminals(&code, &[info("a", "zsh"), info("b", "vim")]);
+        state.rename_terminal(&code, "b", "notes");
+        let title = TerminalMetadata {
+            title: Some("vim notes.md".into()),
+            ..TerminalMetadata::default()
+        };
+        state.update_terminal(&code, "b", &title);
+        state.remove_terminal(&code, "zz");
+
+        let terminals = state.terminals(&code).unwrap();
+        assert_eq!(terminals.len(), 2);
+        assert_eq!(terminals[0].attached_at, 1);
+        assert!(terminals[1].attached_at > 1);
+        assert_eq!(terminals[1].session.name, "notes");
+        assert_eq!(terminals[1].session.title.as_deref(), Some("vim notes.md"));
+        state.remove_terminal(&code, "a");
+        assert_eq!(state.terminals(&code).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_terminal_output() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.broadcast_to_browsers(&code, Bytes::from_static(b"\x01a$ ls")).await;
+        state.broadcast_to_browsers(&code, Bytes::from_static(b"\x01bvim")).await;
+        state.broadcast_to_browsers(&code, Bytes::from_static(b"\x01a\r\nfile")).await;
+        assert_eq!(state.terminal_output(&code, "a").await.as_deref(), Some(&b"$ ls\r\nfile"[..]));
+
+        // Reported terminals without output have an empty transcript
+        assert_eq!(state.terminal_output(&code, "c").await, None);
+        state.add_terminal(&code, "c", "zsh");
+        assert_eq!(state.terminal_output(&code, "c").await, Some(Vec::new()));
+        assert_eq!(state.terminal_output("NOPE", "a").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_chat_reaches_everyone_and_keeps_the_latest() {
+        let state = AppState::from_limits(Limits {
+            chat_history: 2,
+            ..Limits::default()
+        });
+        let (mac_tx, mut mac_rx) = mpsc::channel(4);
+        let (browser_tx, mut browser_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.add_browser(&code, Viewer::new("b1", Role::Viewer, 0), browser_tx).unwrap();
+
+        assert!(!state.chat(&code, "b1", None, "  ").await);
+        assert!(state.chat(&code, "b1", Some(" Ana ".into()), "hi").await);
+        let Some(BrowserMessage::Text(text)) = browser_rx.recv().await else { panic!() };
+        assert!(matches!(
+            serde_json::from_str(&text),
+            Ok(ControlMessage::Chat { name: Some(name), text, .. }) if name == "Ana" && text == "hi"
+        ));
+        assert!(mac_rx.recv().await.is_some());
+
+        state.chat(&code, "b1", None, "two").await;
+        state.chat(&code, "b1", None, "three").await;
+        let kept: Vec<_> = state
+            .chat_history(&code)
+            .into_iter()
+            .map(|msg| match msg {
+                ControlMessage::Chat { text, .. } => text,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(kept, ["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn test_presence_tells_only_the_mac_ips() {
+        let state = AppState::new();
+        let (mac_tx, mut mac_rx) = mpsc::channel(4);
+        let (browser_tx, mut browser_rx) = mpsc::channel(4);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        let ip: IpAddr = "203.0.113.5".parse().unwrap();
+        state.add_browser(&code, Viewer { ip: Some(ip), ..Viewer::new("late", Role::Viewer, 20) }, browser_tx.clone()).unwrap();
+        state.add_browser(&code, Viewer::new("early", Role::Controller, 10), browser_tx).unwrap();
+
+        assert!(state.set_nickname(&code, "late", Some(format!("  {}  ", "x".repeat(50)))));
+        assert!(!state.set_nickname(&code, "gone", None));
+        let viewers = state.presence(&code);
+        assert_eq!(viewers.iter().map(|v| v.browser_id.as_str()).collect::<Vec<_>>(), ["early", "late"]);
+        assert_eq!(viewers[1].nickname.as_deref(), Some(&"x".repeat(MAX_NICKNAME_CHARS)[..]));
+
+        state.broadcast_presence(&code).await;
+        let Some(MacMessage::Text(to_mac)) = mac_rx.recv().await else { panic!() };
+        assert!(to_mac.contains("203.0.113.5"));
+        let Some(BrowserMessage::Text(to_browser)) = browser_rx.recv().await else { panic!() };
+        assert!(to_browser.starts_with(r#"{"type":"presence""#) && !to_browser.contains("203.0.113.5"));
+    }
+
+    #[tokio::test]
+    async fn test_zero_retention_keeps_nothing() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(8);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.broadcast_to_browsers(&code, Bytes::from_static(b"\x01a$ ls")).await;
+        assert!(state.set_recording(&code, true).is_some());
+
+        // What was kept goes
+        state.set_zero_retention(&code, true);
+        assert!(state.is_zero_retention(&code));
+        assert_eq!(state.recording_of(&code), None);
+        assert!(state.replay(&code, None).await.frames.is_empty());
+
+        state.set_scrollback_limit(&code, Some(1024));
+        state.broadcast_to_browsers(&code, Bytes::from_static(b"\x01asecret")).await;
+        assert!(state.replay(&code, None).await.frames.is_empty());
+        assert_eq!(state.set_recording(&code, true), None);
+        assert!(state.chat(&code, "b1", None, "hi").await);
+        assert!(state.chat_history(&code).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_zero_retention_input_is_audited() {
+        let dir = std::env::temp_dir().join(format!("relay-audit-{}", nanoid::nanoid!(8)));
+        let audit = AuditLog::open(dir.clone(), crate::audit::DEFAULT_AUDIT_RETENTION, None, None).unwrap();
+        let state = AppState::with_cluster(Limits::default(), None, None, Webhooks::default(), Some(audit), None, None);
+        let (mac_tx, _mac_rx) = mpsc::channel(8);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.set_zero_retention(&code, true);
+
+        state.audit_input(&code, "b1", b"\x02s1rm -rf build\r").await;
+        // The writer runs on its own
+        let audit = state.audit().unwrap();
+        let mut exported = String::new();
+        for _ in 0..100 {
+            exported = audit.export(&crate::audit::AuditQuery::default()).await.unwrap();
+            if !exported.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let entry: AuditEntry = serde_json::from_str(&exported).unwrap();
+        assert_eq!((entry.code.as_str(), entry.browser_id.as_str()), (code.as_str(), "b1"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_join_tokens_work_once() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+
+        let scope = JoinScope::new(None, None, None);
+        assert_eq!(scope.ttl, DEFAULT_JOIN_TOKEN_TTL);
+        let token = state.create_join_token(&code, scope).unwrap();
+        assert_eq!(state.redeem_join_token("NOCODE", &token, None), None);
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), Some(Role::Controller));
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k2")), None);
+        // The browser it let in gets back in after a reconnect
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), Some(Role::Controller));
+        // but only with the link it came in with
+        assert_eq!(state.redeem_join_token(&code, "made-up", Some("k1")), None);
+
+        let expired = state.create_join_token(&code, JoinScope::new(Some(Duration::ZERO), None, None)).unwrap();
+        assert_eq!(state.redeem_join_token(&code, &expired, None), None);
+        let scope = JoinScope::new(Some(Duration::from_secs(u64::MAX)), None, Some(0));
+        assert_eq!((scope.ttl, scope.uses), (MAX_JOIN_TOKEN_TTL, 1));
+    }
+
+    #[test]
+    fn test_scoped_join_links() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+
+        // A view-only link for a class lets in that many viewers
+        let token = state.create_join_token(&code, JoinScope::new(None, Some(Role::Viewer), Some(2))).unwrap();
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), Some(Role::Viewer));
+        assert_eq!(state.redeem_join_token(&code, &token, None), Some(Role::Viewer));
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k3")), None);
+        // Reconnecting gets no more than the link granted
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), Some(Role::Viewer));
+
+        // Its keys survive a restart with their role
+        let saved = state.saved_sessions();
+        assert_eq!(saved[0].invites.len(), 1);
+        assert_eq!((saved[0].invites[0].key.as_str(), saved[0].invites[0].role), ("k1", Role::Viewer));
+    }
+
+    #[test]
+    fn test_invited_keys_expire_with_their_link() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+
+        let token = state
+            .create_join_token(&code, JoinScope::new(Some(Duration::from_millis(20)), None, Some(1)))
+            .unwrap();
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), Some(Role::Controller));
+        std::thread::sleep(Duration::from_millis(30));
+        // Past the link's lifetime its browser needs the password again
+        assert_eq!(state.redeem_join_token(&code, &token, Some("k1")), None);
+        assert_eq!(state.redeem_join_token(&code, "made-up", Some("k1")), None);
+        assert!(state.saved_sessions()[0].invites.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_purge_by_routing_header() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let plain = state.register_mac_client(mac_tx.clone(), "mac-1".into(), None);
+        let sealed = state.register_mac_client(mac_tx, "mac-2".into(), None);
+        state.set_passthrough(&sealed, true);
+        assert!(state.is_passthrough(&sealed));
+        for code in [&plain, &sealed] {
+            state.broadcast_to_browsers(code, Bytes::from(vec![1, b'a', 0xde, 0xad])).await;
+            state.broadcast_to_browsers(code, Bytes::from(vec![1, b'b', 0xbe, 0xef])).await;
+            state.broadcast_to_browsers(code, Bytes::from(vec![9])).await;
+            state.purge_session_scrollback(code, "a").await;
+        }
+        assert_eq!(state.replay(&plain, None).await.frames, vec![vec![1, b'b', 0xbe, 0xef]]);
+        assert_eq!(state.replay(&sealed, None).await.frames, vec![vec![1, b'b', 0xbe, 0xef], vec![9]]);
+    }
+
+    #[tokio::test]
+    async fn test_departed_browsers_resume_where_they_left() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        state.broadcast_to_browsers(&code, Bytes::from(vec![1, b'a', b'1'])).await;
+        let joined = state.replay(&code, None).await;
+        assert_eq!((joined.frames.len(), joined.resumed, joined.position), (1, false, 1));
+
+        let token = state.browser_resume_token().unwrap();
+        let browser = DepartedBrowser {
+            browser_id: "b1".into(),
+            role: Role::Viewer,
+            joined_at: 5,
+            position: joined.position,
+            approved: false,
+        };
+        state.keep_departed_browser(&code, token.clone(), browser.clone());
+        state.broadcast_to_browsers(&code, Bytes::from(vec![1, b'a', b'2'])).await;
+
+        assert_eq!(state.departed_browser(&code, "guess"), None);
+        let back = state.departed_browser(&code, &token).unwrap();
+        assert_eq!(back, browser);
+        assert_eq!(state.take_departed_browser(&code, &token), Some(browser));
+        let replay = state.replay(&code, Some(back.position)).await;
+        assert_eq!(replay.frames, vec![vec![1, b'a', b'2']]);
+        assert!(replay.resumed);
+        assert_eq!(replay.position, 2);
+        // Once only
+        assert_eq!(state.departed_browser(&code, &token), None);
+        assert_eq!(state.take_departed_browser(&code, &token), None);
+
+        // Or only the end of the scrollback, for a slow connection
+        let mut replay = state.replay(&code, None).await;
+        replay.keep_newest(3);
+        assert_eq!(replay.frames, vec![vec![1, b'a', b'2']]);
+        replay.keep_newest(2);
+        assert!(replay.frames.is_empty());
+        assert_eq!(replay.position, 2);
+
+        let state = AppState::from_limits(Limits {
+            browser_resume_window: Duration::ZERO,
+            ..Limits::default()
+        });
+        assert_eq!(state.browser_resume_token(), None);
+    }
+
+    #[tokio::test]
+    async fn test_approvals_reach_the_waiting_browser() {
+        let state = AppState::new();
+        let (mac_tx, _mac_rx) = mpsc::channel(1);
+        let code = state.register_mac_client(mac_tx, "mac-1".into(), None);
+        assert!(!state.is_approval_required(&code));
+        state.set_approval_required(&code, true);
+        assert!(state.is_approval_required(&code));
+
+        let denied = state.request_approval(&code, "b1").unwrap();
+        assert!(state.answer_approval(&code, "b1", false, None));
+        assert_eq!(denied.await.unwrap(), None);
+        assert!(!state.answer_approval(&code, "b1", true, None));
+
+        let viewer = state.request_approval(&code, "b4").unwrap();
+        assert!(state.answer_approval(&code, "b4", true, Some(Role::Viewer)));
+        assert_eq!(viewer.await.unwrap(), Some(Role::Viewer));
+
+        let left = state.request_approval(&code, "b2").unwrap();
+        assert!(state.cancel_approval(&code, "b2"));
+        assert!(left.await.is_err());
+
+        let waiting = state.request_approval(&code, "b3").unwrap();
+        state.set_approval_required(&code, false);
+        assert_eq!(waiting.await.unwrap(), Some(Role::Controller));
+    }
+}
@@ -0,0 +1,220 @@
+//! Sessions kept across a relay restart.
+//!
+//! Off unless `STATE_FILE` names a file. When the relay is stopped (Ctrl-C
+//! or SIGTERM) it writes the sessions it holds there: codes, resume tokens,
+//! the settings the Macs made and the terminals they reported, but no
+//! output and no connections. On startup it reads the file back, deletes
+//! it, and holds each session as if its Mac's connection had just dropped:
+//! a Mac coming back with its resume token within `RESUME_GRACE_SECS` of
+//! the shutdown gets its code back, and browsers can rejoin it.
+//!
+//! Sessions in zero retention mode are kept without their terminals. Resume
+//! tokens and session passwords are kept only as the hashes the relay
+//! checks them against; the file is still only readable by the relay's
+//! user. A file that does not parse is left in place.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::protocol::{Role, TerminalInfo};
+use crate::state::{unix_now, PasswordHash};
+
+/// One session as written to the state file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedSession {
+    pub code: String,
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    /// ID of the account the session counts against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<PasswordHash>,
+    /// Hex SHA-256 of the Mac's resume token
+    #[serde(default)]
+    pub resume_token_hash: String,
+    /// Written in the clear by earlier relays; read, never written
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub resume_token: Option<String>,
+    #[serde(default)]
+    pub passthrough: bool,
+    #[serde(default)]
+    pub zero_retention: bool,
+    #[serde(default)]
+    pub compression: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_browsers: Option<usize>,
+    #[serde(default)]
+    pub sharing_paused: bool,
+    #[serde(default)]
+    pub approval_required: bool,
+    #[serde(default)]
+    pub view_only: bool,
+    /// Browsers let in by a join link
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invites: Vec<SavedInvite>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terminals: Vec<TerminalInfo>,
+    /// Seconds since the Mac registered, for the session lifetime
+    pub age_secs: u64,
+}
+
+/// A browser a join link let in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedInvite {
+    pub key: String,
+    pub role: Role,
+    /// Hex SHA-256 of its link's token
+    pub token_hash: String,
+    /// Seconds its link had left when the relay stopped
+    pub expires_in_secs: u64,
+}
+
+/// What the file holds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Saved {
+    /// Unix time the relay stopped, in seconds
+    pub saved_at: u64,
+    pub sessions: Vec<SavedSession>,
+}
+
+impl Saved {
+    /// Seconds since the relay stopped
+    pub fn age_secs(&self) -> u64 {
+        unix_now().saturating_sub(self.saved_at)
+    }
+}
+
+/// Where sessions are kept across restarts
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    /// The file named by `STATE_FILE`, if any
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config
+            .get("STATE_FILE")
+            .filter(|path| !path.is_empty())
+            .map(|path| Self::new(path.into()))
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the sessions, replacing the file in one step
+    pub fn save(&self, sessions: Vec<SavedSession>) -> std::io::Result<()> {
+        let saved = Saved {
+            saved_at: unix_now(),
+            sessions,
+        };
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        // A file left by an earlier run keeps its mode; only a new one gets 0600
+        match std::fs::remove_file(&tmp) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(&serde_json::to_vec(&saved)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)
+    }
+
+    /// Read the sessions and delete the file, so they are restored once
+    /// only; None without a file. A file that does not parse is kept.
+    pub fn take(&self) -> std::io::Result<Option<Saved>> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let saved = serde_json::from_slice(&data).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("not a state file ({}), left in place", e))
+        })?;
+        std::fs::remove_file(&self.path)?;
+        Ok(Some(saved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_save_and_take() {
+        let path = std::env::temp_dir().join(format!("relay-state-{}.json", nanoid::nanoid!(8)));
+        let file = StateFile::new(path.clone());
+        assert!(file.take().unwrap().is_none());
+
+        let session = SavedSession {
+            code: "ABC234".into(),
+            client_id: "mac-1".into(),
+            client_name: None,
+            account: Some("acct-1".into()),
+            password_hash: Some(PasswordHash::new("hunter2")),
+            resume_token_hash: "0".repeat(64),
+            password: None,
+            resume_token: None,
+            passthrough: false,
+            zero_retention: false,
+            compression: true,
+            max_browsers: None,
+            sharing_paused: false,
+            approval_required: true,
+            view_only: false,
+            invites: vec![SavedInvite {
+                key: "k1".into(),
+                role: Role::Viewer,
+                token_hash: "0".repeat(64),
+                expires_in_secs: 600,
+            }],
+            terminals: Vec::new(),
+            age_secs: 30,
+        };
+        // Even over a temporary file left readable by an earlier run
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, "").unwrap();
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o644)).unwrap();
+        file.save(vec![session.clone()]).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(!written.contains("hunter2"));
+
+        let saved = file.take().unwrap().unwrap();
+        assert_eq!(saved.sessions, vec![session]);
+        assert!(saved.age_secs() < 5);
+        // Once only
+        assert!(!path.exists());
+        assert!(file.take().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_corrupt_file_is_kept() {
+        let path = std::env::temp_dir().join(format!("relay-state-{}.json", nanoid::nanoid!(8)));
+        std::fs::write(&path, "{\"saved_at\":").unwrap();
+        let file = StateFile::new(path.clone());
+        let err = file.take().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
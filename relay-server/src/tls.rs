@@ -0,0 +1,274 @@
+//! HTTPS/WSS without a reverse proxy.
+//!
+//! TLS is off unless configured, in one of two ways:
+//!
+//! - `TLS_CERT` and `TLS_KEY`: PEM files of a certificate chain and its
+//!   private key, e.g. from certbot. They are read at startup, so restart
+//!   the relay after renewing them.
+//! - `ACME_DOMAINS`: certificates for these domains (comma-separated) are
+//!   obtained from Let's Encrypt and renewed automatically. The challenge
+//!   (tls-alpn-01) is answered on the relay's own port, which must be
+//!   reachable as port 443 of every domain. `ACME_EMAIL` is the contact for
+//!   expiry notices, `ACME_CACHE_DIR` keeps the account and certificates
+//!   across restarts (default `./acme-cache`), and `ACME_STAGING=1` uses
+//!   the staging directory while testing.
+//!
+//! Listeners in `LISTEN` use these settings too unless they have their own
+//! certificate or are plain; see [`crate::listen`].
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use futures_util::StreamExt;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rustls_acme::caches::DirCache;
+use rustls_acme::futures_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_acme::futures_rustls::rustls::{self, ServerConfig};
+use rustls_acme::futures_rustls::server::TlsStream;
+use rustls_acme::futures_rustls::{LazyConfigAcceptor, TlsAcceptor};
+use rustls_acme::{is_tls_alpn_challenge, AcmeConfig};
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+
+/// Default directory for the ACME account and certificates
+pub const DEFAULT_ACME_CACHE_DIR: &str = "acme-cache";
+
+/// Protocols offered during the handshake. HTTP/2 is left out: browsers
+/// open WebSockets over HTTP/1.1 unless the server supports extended
+/// CONNECT.
+const ALPN_PROTOCOLS: &[&[u8]] = &[b"http/1.1"];
+
+/// Where the certificate comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsConfig {
+    /// PEM files given by `TLS_CERT` and `TLS_KEY`
+    Files { cert: PathBuf, key: PathBuf },
+    /// Let's Encrypt, for `ACME_DOMAINS`
+    Acme {
+        domains: Vec<String>,
+        email: Option<String>,
+        cache_dir: PathBuf,
+        staging: bool,
+    },
+}
+
+impl TlsConfig {
+    /// Read the TLS settings; `None` serves plain HTTP.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        Self::from_vars(|name| config.get(name).filter(|v| !v.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let files = (var("TLS_CERT"), var("TLS_KEY"));
+        let domains = var("ACME_DOMAINS").map(|v| {
+            v.split(',')
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty())
+                .collect::<Vec<_>>()
+        });
+        match (files, domains) {
+            ((None, None), None) => Ok(None),
+            ((Some(_), _) | (_, Some(_)), Some(_)) => {
+                Err("set either TLS_CERT/TLS_KEY or ACME_DOMAINS, not both".to_string())
+            }
+            ((Some(cert), Some(key)), None) => Ok(Some(Self::Files {
+                cert: cert.into(),
+                key: key.into(),
+            })),
+            ((Some(_), None), None) => Err("TLS_CERT is set but TLS_KEY is not".to_string()),
+            ((None, Some(_)), None) => Err("TLS_KEY is set but TLS_CERT is not".to_string()),
+            (_, Some(domains)) if domains.is_empty() => Err("ACME_DOMAINS has no domain".to_string()),
+            (_, Some(domains)) => Ok(Some(Self::Acme {
+                domains,
+                email: var("ACME_EMAIL"),
+                cache_dir: var("ACME_CACHE_DIR")
+                    .unwrap_or_else(|| DEFAULT_ACME_CACHE_DIR.to_string())
+                    .into(),
+                staging: var("ACME_STAGING").is_some_and(|v| v != "0" && v != "false"),
+            })),
+        }
+    }
+}
+
+fn alpn_protocols() -> Vec<Vec<u8>> {
+    ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect()
+}
+
+/// Load a certificate chain and private key from PEM files.
+fn load_server_config(cert: &PathBuf, key: &PathBuf) -> Result<ServerConfig, String> {
+    let open = |path: &PathBuf| {
+        std::fs::File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("cannot open {}: {}", path.display(), e))
+    };
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .map_err(|e| format!("cannot read {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", cert.display()));
+    }
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|e| format!("cannot read {}: {}", key.display(), e))?
+        .ok_or_else(|| format!("no private key in {}", key.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    config.alpn_protocols = alpn_protocols();
+    Ok(config)
+}
+
+/// Serve one TLS connection from `peer`, WebSocket upgrades included.
+fn serve_connection<S>(stream: S, peer: SocketAddr, app: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        // What `into_make_service_with_connect_info` provides over plain HTTP
+        let service = TowerToHyperService::new(app.layer(Extension(ConnectInfo(peer))));
+        if let Err(e) = auto::Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .await
+        {
+            debug!("HTTPS connection ended: {}", e);
+        }
+    });
+}
+
+/// What TLS connections are accepted with; cheap to clone, so listeners
+/// with the same certificate share one
+#[derive(Clone)]
+pub enum Acceptor {
+    /// A certificate from files
+    Files(TlsAcceptor),
+    /// Certificates from Let's Encrypt; tls-alpn-01 challenges are answered
+    /// with their own config
+    Acme {
+        default: Arc<ServerConfig>,
+        challenge: Arc<ServerConfig>,
+    },
+}
+
+impl Acceptor {
+    /// Load the certificate, or start getting and renewing it from
+    /// Let's Encrypt
+    pub fn new(config: TlsConfig) -> Result<Self, String> {
+        match config {
+            TlsConfig::Files { cert, key } => {
+                let acceptor = TlsAcceptor::from(Arc::new(load_server_config(&cert, &key)?));
+                info!("Serving TLS with the certificate in {}", cert.display());
+                Ok(Self::Files(acceptor))
+            }
+            TlsConfig::Acme {
+                domains,
+                email,
+                cache_dir,
+                staging,
+            } => {
+                info!(
+                    "Serving TLS with Let's Encrypt{} certificates for {}",
+                    if staging { " (staging)" } else { "" },
+                    domains.join(", ")
+                );
+                let mut state = AcmeConfig::new(domains)
+                    .contact(email.map(|e| format!("mailto:{}", e)))
+                    .cache(DirCache::new(cache_dir))
+                    .directory_lets_encrypt(!staging)
+                    .state();
+                let mut default = ServerConfig::builder()
+                    .with_no_client_auth()
+                    .with_cert_resolver(state.resolver());
+                default.alpn_protocols = alpn_protocols();
+                let challenge = state.challenge_rustls_config();
+                // Polling the state drives certificate orders and renewals
+                tokio::spawn(async move {
+                    while let Some(event) = state.next().await {
+                        match event {
+                            Ok(done) => debug!("ACME: {:?}", done),
+                            Err(e) => warn!("ACME: {}", e),
+                        }
+                    }
+                });
+                Ok(Self::Acme {
+                    default: Arc::new(default),
+                    challenge,
+                })
+            }
+        }
+    }
+
+    /// Do the handshake with a client. None for ACME validation
+    /// connections, which are done once it is.
+    async fn accept(&self, tcp: TcpStream) -> std::io::Result<Option<TlsStream<Compat<TcpStream>>>> {
+        match self {
+            Self::Files(acceptor) => acceptor.accept(tcp.compat()).await.map(Some),
+            Self::Acme { default, challenge } => {
+                let start = LazyConfigAcceptor::new(rustls::server::Acceptor::default(), tcp.compat()).await?;
+                if is_tls_alpn_challenge(&start.client_hello()) {
+                    start.into_stream(challenge.clone()).await?;
+                    return Ok(None);
+                }
+                start.into_stream(default.clone()).await.map(Some)
+            }
+        }
+    }
+}
+
+/// Accept HTTPS connections on `listener` until it fails.
+pub async fn serve(listener: TcpListener, app: Router, acceptor: Acceptor) -> Result<(), String> {
+    loop {
+        let (tcp, addr) = listener.accept().await.map_err(|e| e.to_string())?;
+        let (acceptor, app) = (acceptor.clone(), app.clone());
+        tokio::spawn(async move {
+            match acceptor.accept(tcp).await {
+                Ok(Some(tls)) => serve_connection(tls.compat(), addr, app),
+                Ok(None) => debug!("Answered an ACME challenge from {}", addr),
+                Err(e) => debug!("TLS handshake with {} failed: {}", addr, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(vars: &[(&str, &str)]) -> Result<Option<TlsConfig>, String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        TlsConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_tls_config_from_vars() {
+        assert_eq!(parse(&[]), Ok(None));
+        assert_eq!(
+            parse(&[("TLS_CERT", "cert.pem"), ("TLS_KEY", "key.pem")]),
+            Ok(Some(TlsConfig::Files {
+                cert: "cert.pem".into(),
+                key: "key.pem".into()
+            }))
+        );
+        assert!(parse(&[("TLS_CERT", "cert.pem")]).is_err());
+        assert!(parse(&[("TLS_CERT", "cert.pem"), ("TLS_KEY", "key.pem"), ("ACME_DOMAINS", "a.com")]).is_err());
+        assert!(parse(&[("ACME_DOMAINS", " , ")]).is_err());
+        assert_eq!(
+            parse(&[("ACME_DOMAINS", "relay.example.com, www.example.com"), ("ACME_STAGING", "1")]),
+            Ok(Some(TlsConfig::Acme {
+                domains: vec!["relay.example.com".into(), "www.example.com".into()],
+                email: None,
+                cache_dir: DEFAULT_ACME_CACHE_DIR.into(),
+                staging: true,
+            }))
+        );
+    }
+}
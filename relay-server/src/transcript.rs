@@ -0,0 +1,69 @@
+//! Terminal output as plain text, for transcripts.
+//!
+//! Escape sequences (colors, cursor movement, window titles, ...) are
+//! dropped, line endings become `\n` and other control characters go too.
+//! Output that redraws a line in place, like a progress bar, keeps every
+//! version of it; it is a log of what was printed, not of the screen.
+
+/// `output` without escape sequences and control characters
+pub fn strip_ansi(output: &[u8]) -> String {
+    let mut text = Vec::with_capacity(output.len());
+    let mut bytes = output.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            0x1b => match bytes.next() {
+                // CSI: parameters and intermediates, then a final byte
+                Some(b'[') => {
+                    for byte in bytes.by_ref() {
+                        if (0x40..=0x7e).contains(&byte) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, DCS and the like: up to BEL or ESC \
+                Some(b']' | b'P' | b'X' | b'^' | b'_') => {
+                    while let Some(byte) = bytes.next() {
+                        if byte == 0x07 || (byte == 0x1b && bytes.next_if_eq(&b'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Anything else: intermediates, then a final byte
+                Some(0x20..=0x2f) => {
+                    for byte in bytes.by_ref() {
+                        if byte >= 0x30 {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            b'\r' => {
+                if bytes.peek() != Some(&b'\n') {
+                    text.push(b'\n');
+                }
+            }
+            b'\n' | b'\t' => text.push(byte),
+            0x00..=0x1f | 0x7f => {}
+            _ => text.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi(b"\x1b[1;32m$\x1b[0m ls\r\nfile\r\n"), "$ ls\nfile\n");
+        // Window title, charset switch, bell and backspace
+        assert_eq!(strip_ansi(b"\x1b]0;zsh\x07a\x1b(Bb\x07\x08c"), "abc");
+        assert_eq!(strip_ansi(b"\x1b]8;;https://x\x1b\\link\x1b]8;;\x1b\\"), "link");
+        assert_eq!(strip_ansi(b"10%\r50%\r100%\r\n"), "10%\n50%\n100%\n");
+        assert_eq!(strip_ansi("caf\u{e9}\t\u{2713}".as_bytes()), "caf\u{e9}\t\u{2713}");
+        // Cut off mid-sequence
+        assert_eq!(strip_ansi(b"done\x1b[3"), "done");
+    }
+}
@@ -0,0 +1,137 @@
+//! Listening on a Unix domain socket, for a reverse proxy on the same host.
+//!
+//! Off unless `UNIX_SOCKET` names a path; the relay then listens there
+//! instead of on `BIND_ADDRESS` and `PORT`, so nginx or Caddy can reach it
+//! without a TCP port anyone else on the host could connect to. The socket
+//! gets `UNIX_SOCKET_MODE` as permissions (octal, default 660) so only the
+//! proxy's group can use it. A socket left over from an earlier run is
+//! replaced.
+//!
+//! Connections over the socket have no client address, so requests look
+//! like they came from `127.0.0.1`; set `CLIENT_IP_HEADER` to the header the
+//! proxy puts the real one in. TLS is left to the proxy.
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+
+use crate::config::Config;
+
+/// Permissions of the socket unless set
+const DEFAULT_MODE: u32 = 0o660;
+
+/// The address handlers see for connections over the socket
+const LOCAL_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// Where to listen, and who may connect
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+
+impl UnixSocket {
+    /// The socket from `UNIX_SOCKET` and `UNIX_SOCKET_MODE`; None without
+    /// a path
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        Self::from_vars(|name| config.get(name).filter(|v| !v.is_empty()))
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let mode = var("UNIX_SOCKET_MODE")
+            .map(|mode| {
+                u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| format!("UNIX_SOCKET_MODE must be octal permissions like 660, not {}", mode))
+            })
+            .transpose()?;
+        match var("UNIX_SOCKET") {
+            Some(path) => Ok(Some(Self {
+                path: path.into(),
+                mode: mode.unwrap_or(DEFAULT_MODE),
+            })),
+            None if mode.is_some() => Err("UNIX_SOCKET_MODE needs a UNIX_SOCKET".into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Create the socket, replacing a stale one
+    pub fn bind(&self) -> std::io::Result<UnixListener> {
+        remove_stale(&self.path)?;
+        let listener = UnixListener::bind(&self.path)?;
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(self.mode))?;
+        Ok(listener)
+    }
+}
+
+/// Remove a socket an earlier run left behind; anything else at the path
+/// is an error
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Serve `app` on `listener` until it fails
+pub async fn serve(listener: UnixListener, app: Router) -> std::io::Result<()> {
+    // What `into_make_service_with_connect_info` provides over TCP
+    axum::serve(listener, app.layer(Extension(ConnectInfo(LOCAL_PEER)))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn test_from_vars() {
+        assert_eq!(UnixSocket::from_vars(vars(&[])), Ok(None));
+        let socket = UnixSocket::from_vars(vars(&[("UNIX_SOCKET", "/run/relay.sock")])).unwrap().unwrap();
+        assert_eq!(socket.mode, 0o660);
+        let socket = UnixSocket::from_vars(vars(&[("UNIX_SOCKET", "/run/relay.sock"), ("UNIX_SOCKET_MODE", "0o600")]));
+        assert_eq!(socket.unwrap().unwrap().mode, 0o600);
+        assert!(UnixSocket::from_vars(vars(&[("UNIX_SOCKET", "/run/relay.sock"), ("UNIX_SOCKET_MODE", "rw")])).is_err());
+        assert!(UnixSocket::from_vars(vars(&[("UNIX_SOCKET_MODE", "600")])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("relay-{}.sock", nanoid::nanoid!(8)));
+        let socket = UnixSocket { path: path.clone(), mode: 0o600 };
+        // A stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = socket.bind().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let app = Router::new().route("/", get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.to_string() }));
+        tokio::spawn(serve(listener, app));
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: relay\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("127.0.0.1:0"));
+        std::fs::remove_file(path).unwrap();
+
+        // Anything else at the path is left alone
+        let file = std::env::temp_dir().join(format!("relay-{}.txt", nanoid::nanoid!(8)));
+        std::fs::write(&file, "notes").unwrap();
+        assert!(UnixSocket { path: file.clone(), mode: 0o600 }.bind().is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+}
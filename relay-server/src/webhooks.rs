@@ -0,0 +1,140 @@
+//! Webhook notifications of session lifecycle events.
+//!
+//! Every event is POSTed as JSON to each configured URL, in the background:
+//! a slow or broken endpoint never holds up a join. With a secret set, the
+//! body is signed like GitHub webhooks do it, so receivers can check it came
+//! from this relay:
+//!
+//! ```text
+//! X-Relay-Signature-256: sha256=<hex of HMAC-SHA256(secret, body)>
+//! ```
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::Role;
+
+/// Header carrying the signature of the body
+const SIGNATURE_HEADER: &str = "X-Relay-Signature-256";
+
+/// How long one delivery may take
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened to a session
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A mac-client registered, or reclaimed its session
+    MacRegistered {
+        code: String,
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        client_name: Option<String>,
+        resumed: bool,
+    },
+    BrowserJoined {
+        code: String,
+        browser_id: String,
+        role: Role,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_agent: Option<String>,
+    },
+    BrowserLeft { code: String, browser_id: String },
+    /// The relay closed the session; `reason` is what browsers were told
+    SessionExpired { code: String, reason: String },
+    /// So many wrong passwords were tried on a session that it takes none
+    /// for `lockout_secs`
+    CodeUnderAttack { code: String, failures: u32, lockout_secs: u64 },
+}
+
+/// An event as it is sent
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// Unix time of the event, in seconds
+    at: u64,
+}
+
+/// Where events go
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<String>, secret: Option<String>) -> Self {
+        Self {
+            urls,
+            secret,
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("HTTP client without custom TLS settings"),
+        }
+    }
+
+    /// Send an event to every URL, without waiting for the answers
+    pub fn notify(&self, event: WebhookEvent) {
+        if self.urls.is_empty() {
+            return;
+        }
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let body = serde_json::to_vec(&Payload { event: &event, at }).unwrap();
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        for url in &self.urls {
+            let mut request = self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            let url = url.clone();
+            tokio::spawn(async move {
+                match request.send().await.and_then(|response| response.error_for_status()) {
+                    Ok(_) => tracing::debug!(url = %url, "Webhook delivered"),
+                    Err(e) => tracing::warn!(url = %url, "Webhook delivery failed: {}", e),
+                }
+            });
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body`
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let event = WebhookEvent::BrowserLeft {
+            code: "ABC123".into(),
+            browser_id: "b1".into(),
+        };
+        let json = serde_json::to_string(&Payload { event: &event, at: 7 }).unwrap();
+        assert_eq!(json, r#"{"event":"browser_left","code":"ABC123","browser_id":"b1","at":7}"#);
+    }
+}
@@ -0,0 +1,406 @@
+//! WebTransport (HTTP/3 over QUIC) for browsers, next to the WebSocket.
+//!
+//! Off unless `WEBTRANSPORT_PORT` (UDP) is set. Browsers connect to
+//! `https://<host>:<port>/` and open one bidirectional stream, on which both
+//! sides send
+//!
+//! ```text
+//! [1 byte kind: 0 control message, 1 binary frame][4 bytes length, big endian][payload]
+//! ```
+//!
+//! The browser's first message is its `auth`, as on the WebSocket, sent
+//! within ten seconds of connecting or the connection is closed; after
+//! that its binary messages are input. Terminal output does not share that
+//! stream: each terminal session gets a unidirectional stream of its own,
+//! opened at its first frame, carrying `[4 bytes length][frame]`. A lost
+//! packet then only holds up the terminal it belonged to instead of every
+//! terminal of the browser, which is what hurts on lossy mobile networks.
+//! Pings are datagrams the browser echoes back.
+//!
+//! The browser is then run by the same code as a WebSocket browser, so
+//! everything from passwords to scrollback works alike. Only sessions held
+//! by this relay can be joined, not ones linked through the cluster.
+//!
+//! With `TLS_CERT` and `TLS_KEY`, that certificate is used. Otherwise the
+//! relay makes its own, short-lived as browsers require for certificates
+//! they are given by hash (`serverCertificateHashes`), and replaces it
+//! before it runs out. `GET /api/webtransport` tells pages the port and the
+//! current hash.
+
+use axum::body::Bytes;
+use axum::extract::ws::Message;
+use axum::http::HeaderValue;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+use tracing::Instrument;
+use wtransport::endpoint::endpoint_side::Server;
+use wtransport::endpoint::IncomingSession;
+use wtransport::{Connection, Endpoint, Identity, SendStream, ServerConfig};
+
+use crate::handlers::{handle_browser, send_auth_failed, BrowserSink, Join};
+use crate::protocol::{AuthFailure, ControlMessage};
+use crate::session::normalize_code;
+use crate::state::AppState;
+use crate::tls::TlsConfig;
+
+/// Kinds of message on the control stream
+const KIND_CONTROL: u8 = 0;
+const KIND_FRAME: u8 = 1;
+
+/// Largest message a browser may send
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// How long the last messages to a leaving browser may take to arrive
+const FINISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a browser may take to open its stream and send its auth, as
+/// long as a federated peer gets for its join
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the relay's own certificate is replaced; browsers accept ones
+/// valid for at most 14 days
+const CERTIFICATE_ROTATION: Duration = Duration::from_secs(12 * 24 * 3600);
+
+/// What pages need to connect, for `GET /api/webtransport`
+#[derive(Debug, Clone, Serialize)]
+pub struct WebTransportInfo {
+    pub port: u16,
+    /// SHA-256 of the relay's own certificate, hex; None with a certificate
+    /// browsers trust anyway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_hash: Option<String>,
+}
+
+/// The WebTransport listener
+pub struct WebTransport {
+    endpoint: Endpoint<Server>,
+    port: u16,
+    /// Whether the certificate is the relay's own, to be rotated
+    self_signed: bool,
+    info: Arc<RwLock<WebTransportInfo>>,
+}
+
+impl WebTransport {
+    /// Listen on UDP `port`, with the certificate of `tls` if it has files
+    pub async fn bind(port: u16, tls: Option<&TlsConfig>) -> Result<Self, String> {
+        let (identity, self_signed) = match tls {
+            Some(TlsConfig::Files { cert, key }) => {
+                let identity = Identity::load_pemfiles(cert, key)
+                    .await
+                    .map_err(|e| format!("cannot load {}: {}", cert.display(), e))?;
+                (identity, false)
+            }
+            _ => (self_signed_identity()?, true),
+        };
+        let info = WebTransportInfo {
+            port,
+            certificate_hash: self_signed.then(|| certificate_hash(&identity)),
+        };
+        let endpoint = Endpoint::server(server_config(port, identity)).map_err(|e| e.to_string())?;
+        Ok(Self {
+            endpoint,
+            port,
+            self_signed,
+            info: Arc::new(RwLock::new(info)),
+        })
+    }
+
+    /// Port and certificate hash as they are now
+    pub fn info(&self) -> Arc<RwLock<WebTransportInfo>> {
+        self.info.clone()
+    }
+
+    /// Accept browsers until the relay stops
+    pub async fn serve(self, state: AppState) {
+        tracing::info!(port = self.port, "WebTransport listening");
+        let mut rotation = tokio::time::interval_at(
+            tokio::time::Instant::now() + CERTIFICATE_ROTATION,
+            CERTIFICATE_ROTATION,
+        );
+        loop {
+            tokio::select! {
+                incoming = self.endpoint.accept() => {
+                    tokio::spawn(accept(incoming, state.clone()));
+                }
+                _ = rotation.tick(), if self.self_signed => self.rotate_certificate(),
+            }
+        }
+    }
+
+    fn rotate_certificate(&self) {
+        let identity = match self_signed_identity() {
+            Ok(identity) => identity,
+            Err(e) => {
+                tracing::warn!("Cannot make a new WebTransport certificate: {}", e);
+                return;
+            }
+        };
+        let hash = certificate_hash(&identity);
+        match self.endpoint.reload_config(server_config(self.port, identity), false) {
+            Ok(()) => {
+                tracing::info!("Replaced the WebTransport certificate");
+                self.info.write().unwrap().certificate_hash = Some(hash);
+            }
+            Err(e) => tracing::warn!("Cannot replace the WebTransport certificate: {}", e),
+        }
+    }
+}
+
+fn server_config(port: u16, identity: Identity) -> ServerConfig {
+    ServerConfig::builder()
+        .with_bind_default(port)
+        .with_identity(identity)
+        .keep_alive_interval(Some(Duration::from_secs(5)))
+        .build()
+}
+
+fn self_signed_identity() -> Result<Identity, String> {
+    Identity::self_signed(["localhost", "127.0.0.1", "::1"]).map_err(|e| e.to_string())
+}
+
+fn certificate_hash(identity: &Identity) -> String {
+    let hash = identity.certificate_chain().as_slice()[0].hash();
+    hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Take a browser from its handshake to the end of its session
+async fn accept(incoming: IncomingSession, state: AppState) {
+    let request = match incoming.await {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::debug!("WebTransport handshake failed: {}", e);
+            return;
+        }
+    };
+    let ip = request.remote_address().ip().to_canonical();
+    let origin = match request.origin().map(HeaderValue::from_str) {
+        Some(Ok(origin)) => Some(origin),
+        Some(Err(_)) => Some(HeaderValue::from_static("null")),
+        None => None,
+    };
+    if !state.webtransport_origin_permitted(origin.as_ref(), request.authority()) {
+        tracing::info!(ip = %ip, origin = ?request.origin(), "WebTransport refused - origin not allowed");
+        request.forbidden().await;
+        return;
+    }
+    let user_agent = request.user_agent().map(str::to_string);
+    let connection = match request.accept().await {
+        Ok(connection) => connection,
+        Err(e) => {
+            tracing::debug!("WebTransport session failed: {}", e);
+            return;
+        }
+    };
+    let span = tracing::info_span!(
+        "connection",
+        id = %nanoid::nanoid!(10),
+        ip = %ip,
+        transport = "webtransport",
+        code = tracing::field::Empty,
+        browser_id = tracing::field::Empty
+    );
+    run(connection, state, ip, user_agent).instrument(span).await;
+}
+
+async fn run(connection: Connection, state: AppState, ip: IpAddr, user_agent: Option<String>) {
+    // A connection that never gets to its auth holds nothing for long
+    let opened = tokio::time::timeout(AUTH_TIMEOUT, async {
+        let (control_tx, mut control_rx) = connection.accept_bi().await.ok()?;
+        let first = match read_message(&mut control_rx).await {
+            Ok(Some(Message::Text(text))) => serde_json::from_str::<ControlMessage>(&text).ok(),
+            _ => None,
+        };
+        Some((control_tx, control_rx, first))
+    })
+    .await;
+    let (control_tx, control_rx, first) = match opened {
+        Ok(Some(opened)) => opened,
+        Ok(None) => return,
+        Err(_) => {
+            tracing::debug!("WebTransport browser sent no auth in time");
+            connection.close(0u32.into(), b"");
+            return;
+        }
+    };
+
+    // Messages go through channels so handle_browser gets a plain sink and
+    // stream, like for linked browsers
+    let connection = Arc::new(connection);
+    let (out_tx, out_rx) = mpsc::channel::<Message>(64);
+    let (in_tx, in_rx) = mpsc::channel::<Message>(64);
+    let writer = tokio::spawn(write_messages(connection.clone(), control_tx, out_rx).in_current_span());
+    let mut sender = PollSender::new(out_tx).sink_map_err(axum::Error::new);
+
+    match first {
+        Some(ControlMessage::Auth { session_code, browser_key, password, role, token, version, resume_token, nickname, replay_bytes, .. }) => {
+            if admit(&mut sender, &state, ip).await {
+                let join = Join {
+                    session_code: normalize_code(&session_code),
+                    browser_key,
+                    password,
+                    role,
+                    token,
+                    version,
+                    compression: None,
+                    resume_token,
+                    nickname,
+                    replay_bytes,
+                    ip: Some(ip),
+                    user_agent,
+                };
+                let reader = tokio::spawn(read_messages(connection.clone(), control_rx, in_tx));
+                handle_browser(sender, ReceiverStream::new(in_rx).map(Ok), state, join).await;
+                reader.abort();
+            }
+        }
+        _ => {
+            tracing::warn!(event = "error", "First WebTransport message must be auth");
+            let msg = ControlMessage::Error {
+                message: "First message must be Auth".into(),
+            };
+            let _ = sender.send(Message::Text(msg.to_text().into())).await;
+        }
+    }
+    // The writer ends once the handler dropped its sender
+    let _ = writer.await;
+    connection.close(0u32.into(), b"");
+}
+
+/// Whether a browser from `ip` may go on to join, telling it why not
+async fn admit(sender: &mut impl BrowserSink, state: &AppState, ip: IpAddr) -> bool {
+    if !state.join_permitted(ip) {
+        let reason = "This relay does not accept browsers from your network";
+        send_auth_failed(sender, state, AuthFailure::IpNotAllowed, reason).await;
+        tracing::info!(event = "join_failed", ip = %ip, "Browser auth refused - IP not allowed");
+        return false;
+    }
+    if let Err(ban) = state.check_join_rate(ip) {
+        let reason = format!("Too many attempts, try again in {}s", ban.as_secs().max(1));
+        send_auth_failed(sender, state, AuthFailure::RateLimited, &reason).await;
+        tracing::info!(event = "join_failed", ip = %ip, "Browser auth refused - rate limited");
+        return false;
+    }
+    true
+}
+
+/// Hand what the browser sends to its handler: messages on the control
+/// stream, and echoed pings as pongs
+async fn read_messages(connection: Arc<Connection>, mut control: impl AsyncRead + Unpin, input: mpsc::Sender<Message>) {
+    loop {
+        let msg = tokio::select! {
+            msg = read_message(&mut control) => match msg {
+                Ok(Some(msg)) => msg,
+                _ => break,
+            },
+            datagram = connection.receive_datagram() => match datagram {
+                Ok(_) => Message::Pong(Bytes::new()),
+                Err(_) => break,
+            },
+        };
+        if input.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Send what the handler has for the browser: control messages on the
+/// control stream, frames on their terminal's stream, pings as datagrams
+async fn write_messages(connection: Arc<Connection>, mut control: SendStream, mut output: mpsc::Receiver<Message>) {
+    let mut terminals: HashMap<Vec<u8>, SendStream> = HashMap::new();
+    while let Some(msg) = output.recv().await {
+        let written = match msg {
+            Message::Text(text) => control.write_all(&encode_message(KIND_CONTROL, text.as_bytes())).await.is_ok(),
+            Message::Binary(frame) => {
+                let id = crate::frame::session_id(&frame).unwrap_or_default().to_vec();
+                let stream = match terminals.get_mut(&id) {
+                    Some(stream) => stream,
+                    None => {
+                        let Ok(opening) = connection.open_uni().await else { break };
+                        let Ok(stream) = opening.await else { break };
+                        terminals.entry(id).or_insert(stream)
+                    }
+                };
+                let mut data = Vec::with_capacity(4 + frame.len());
+                data.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+                data.extend_from_slice(&frame);
+                stream.write_all(&data).await.is_ok()
+            }
+            Message::Ping(_) => connection.send_datagram(b"ping").is_ok(),
+            Message::Pong(_) => true,
+            Message::Close(_) => break,
+        };
+        if !written {
+            break;
+        }
+    }
+    // Let the last messages arrive before the connection is closed
+    let _ = tokio::time::timeout(FINISH_TIMEOUT, control.finish()).await;
+}
+
+fn encode_message(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(5 + payload.len());
+    data.push(kind);
+    data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// The next message on the control stream; None when the browser closed it
+async fn read_message(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Option<Message>> {
+    let kind = match stream.read_u8().await {
+        Ok(kind) => kind,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message too long"));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await?;
+    match kind {
+        KIND_CONTROL => String::from_utf8(payload)
+            .map(|text| Some(Message::Text(text.into())))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        KIND_FRAME => Ok(Some(Message::Binary(payload.into()))),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unknown message kind")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_messages_round_trip() {
+        let mut data = encode_message(KIND_CONTROL, br#"{"type":"list_sessions"}"#);
+        data.extend(encode_message(KIND_FRAME, b"\x02s1ls\r"));
+        let mut stream = &data[..];
+        let msg = read_message(&mut stream).await.unwrap().unwrap();
+        assert!(matches!(msg, Message::Text(text) if text.as_str() == r#"{"type":"list_sessions"}"#));
+        let msg = read_message(&mut stream).await.unwrap().unwrap();
+        assert!(matches!(msg, Message::Binary(data) if &data[..] == b"\x02s1ls\r"));
+        assert!(read_message(&mut stream).await.unwrap().is_none());
+
+        let mut bad = &encode_message(7, b"x")[..];
+        assert!(read_message(&mut bad).await.is_err());
+        let mut long = &[KIND_FRAME, 0xff, 0xff, 0xff, 0xff][..];
+        assert!(read_message(&mut long).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_self_signed_certificate() {
+        let identity = self_signed_identity().unwrap();
+        let hash = certificate_hash(&identity);
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
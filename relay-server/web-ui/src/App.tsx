@@ -14,6 +14,7 @@ export default function App() {
             <Routes>
               <Route path="/" element={<TerminalPage />} />
               <Route path="/login" element={<LoginPage />} />
+              <Route path="/s/:code" element={<LoginPage />} />
               <Route path="*" element={<Navigate to="/login" replace />} />
             </Routes>
           </TabsProvider>
@@ -0,0 +1,111 @@
+import { useEffect, useRef, useState, type FormEvent } from 'react';
+import { useConnection } from '../context/ConnectionContext';
+import { AuthSuccessMessage, ChatMessage } from '../../shared/protocol';
+import './ChatPanel.css';
+
+/** Lines kept on screen; the relay keeps its own, shorter history */
+const MAX_LINES = 500;
+
+function formatTime(at: number | undefined): string {
+  return at ? new Date(at).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' }) : '';
+}
+
+/**
+ * Chat with whoever else watches this session. Lines go through the relay
+ * and are not end-to-end encrypted.
+ */
+export default function ChatPanel() {
+  const { registerMessageHandler, sendMessage, nickname, setNickname } = useConnection();
+  const [open, setOpen] = useState(false);
+  const [lines, setLines] = useState<ChatMessage[]>([]);
+  const [unread, setUnread] = useState(0);
+  const [name, setName] = useState(nickname ?? '');
+  const [draft, setDraft] = useState('');
+  const openRef = useRef(open);
+  const listRef = useRef<HTMLOListElement>(null);
+
+  useEffect(() => {
+    openRef.current = open;
+    if (open) setUnread(0);
+  }, [open]);
+
+  useEffect(() => {
+    return registerMessageHandler((data) => {
+      if (data.type === 'auth_success') {
+        // A fresh join gets the relay's history again; a resumed one does not
+        const auth = AuthSuccessMessage.safeParse(data);
+        if (auth.success && !auth.data.resumed) setLines([]);
+        return;
+      }
+      if (data.type !== 'chat') return;
+      const msg = ChatMessage.safeParse(data);
+      if (!msg.success) return;
+      setLines((lines) => [...lines, msg.data].slice(-MAX_LINES));
+      if (!openRef.current) setUnread((n) => n + 1);
+    });
+  }, [registerMessageHandler]);
+
+  useEffect(() => {
+    listRef.current?.lastElementChild?.scrollIntoView({ block: 'end' });
+  }, [lines, open]);
+
+  // The name is the one presence shows; the relay puts it on chat lines
+  function commitName() {
+    if (name.trim() !== (nickname ?? '')) setNickname(name);
+  }
+
+  function handleSubmit(event: FormEvent) {
+    event.preventDefault();
+    const text = draft.trim();
+    if (!text) return;
+    commitName();
+    sendMessage({ type: 'chat', text });
+    setDraft('');
+  }
+
+  if (!open) {
+    return (
+      <button className="chat-toggle" onClick={() => setOpen(true)} title="Chat with others watching">
+        Chat{unread > 0 && <span className="chat-unread">{unread}</span>}
+      </button>
+    );
+  }
+
+  return (
+    <aside className="chat-panel">
+      <header className="chat-header">
+        <span>Chat</span>
+        <button className="chat-close" onClick={() => setOpen(false)} aria-label="Close chat">
+          ×
+        </button>
+      </header>
+      <ol className="chat-lines" ref={listRef}>
+        {lines.map((line, i) => (
+          <li key={`${line.at ?? 0}-${i}`} className="chat-line">
+            <span className="chat-time">{formatTime(line.at)}</span>
+            <span className="chat-name">{line.name || 'Someone'}</span>
+            <span className="chat-text">{line.text}</span>
+          </li>
+        ))}
+      </ol>
+      <form className="chat-form" onSubmit={handleSubmit}>
+        <input
+          className="chat-name-input"
+          value={name}
+          onChange={(e) => setName(e.target.value)}
+          onBlur={commitName}
+          placeholder="Name"
+          maxLength={40}
+        />
+        <input
+          className="chat-input"
+          value={draft}
+          onChange={(e) => setDraft(e.target.value)}
+          placeholder="Message (not end-to-end encrypted)"
+          maxLength={2000}
+          autoComplete="off"
+        />
+      </form>
+    </aside>
+  );
+}
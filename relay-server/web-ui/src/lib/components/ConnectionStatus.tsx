@@ -1,22 +1,95 @@
-import { useConnection, type ConnectionState } from '../context/ConnectionContext';
+import { useConnection, type ConnectionState, type EncryptionState } from '../context/ConnectionContext';
 import './ConnectionStatus.css';
 
 const stateDisplay: Record<ConnectionState, { label: string; color: string; icon: string }> = {
   disconnected: { label: 'Disconnected', color: 'text-gray-500', icon: '○' },
   connecting: { label: 'Connecting...', color: 'text-yellow-500', icon: '◐' },
   authenticating: { label: 'Authenticating...', color: 'text-yellow-500', icon: '◑' },
+  awaiting_approval: { label: 'Waiting for approval...', color: 'text-yellow-500', icon: '◑' },
   connected: { label: 'Connected', color: 'text-green-500', icon: '●' },
   reconnecting: { label: 'Reconnecting...', color: 'text-orange-500', icon: '◐' },
 };
 
+const encryptionDisplay: Record<EncryptionState, { label: string; color: string; title: string } | null> = {
+  none: null,
+  waiting: { label: 'Pairing...', color: 'text-yellow-500', title: 'Waiting for the output key from the Mac' },
+  encrypted: { label: 'Encrypted', color: 'text-green-500', title: 'Output is end-to-end encrypted' },
+  unpaired: {
+    label: 'Not paired',
+    color: 'text-orange-500',
+    title: 'Output is end-to-end encrypted. Open the pairing link from the Mac menu on this device.',
+  },
+  mismatch: {
+    label: 'Wrong pairing',
+    color: 'text-orange-500',
+    title: 'The pairing link belongs to another Mac. Open the pairing link from this Mac.',
+  },
+};
+
 export default function ConnectionStatus() {
-  const { state } = useConnection();
+  const { state, encryption, direct, clientName, macAway, macReconnected, latency, liveOnly, replaying, viewers } = useConnection();
   const display = stateDisplay[state];
+  const e2e = encryptionDisplay[encryption];
 
   return (
     <div className="connection-status">
       <span className={`icon ${display.color}`}>{display.icon}</span>
       <span className={`label ${display.color}`}>{display.label}</span>
+      {state === 'connected' && clientName && (
+        <span className="label client-name" title="The Mac this session code belongs to">
+          to {clientName}
+        </span>
+      )}
+      {state === 'connected' && macAway && (
+        <span className="label mac-away text-orange-500" title="The Mac lost its connection; the session stays open while it reconnects">
+          · Mac reconnecting...
+        </span>
+      )}
+      {state === 'connected' && macReconnected && (
+        <span className="label mac-back text-green-500" title="The Mac is back; the session and its terminals carried on">
+          · Mac reconnected
+        </span>
+      )}
+      {state === 'connected' && liveOnly && (
+        <span className="label live-only" title="This Mac has the relay keep nothing; you see output from when you joined">
+          · Live only
+        </span>
+      )}
+      {state === 'connected' && replaying !== null && (
+        <span className="label replaying" title={`The relay is sending ${Math.ceil(replaying / 1024)} KB of earlier output`}>
+          · Loading history...
+        </span>
+      )}
+      {state === 'connected' && viewers.length > 1 && (
+        <span
+          className="label watching"
+          title={viewers.map((v) => `${v.nickname || 'Browser ' + v.browser_id}${v.role === 'viewer' ? ' (view only)' : ''}`).join('\n')}
+        >
+          · {viewers.length} watching
+        </span>
+      )}
+      {state === 'connected' && direct && (
+        <span className="label direct text-green-500" title="Terminal data goes straight to the Mac, not through the relay">
+          · Direct
+        </span>
+      )}
+      {state === 'connected' && latency && (
+        <span
+          className="label latency"
+          title={
+            latency.macMs === null
+              ? 'Round trip to the Mac and back'
+              : `Round trip to the Mac and back, ${Math.round(latency.macMs)} ms of it between the relay and the Mac`
+          }
+        >
+          · {latency.roundTripMs} ms
+        </span>
+      )}
+      {e2e && (
+        <span className={`label e2e ${e2e.color}`} title={e2e.title}>
+          · {e2e.label}
+        </span>
+      )}
     </div>
   );
 }
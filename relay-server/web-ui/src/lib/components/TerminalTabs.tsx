@@ -1,6 +1,17 @@
-import { useTabs } from '../context/TabsContext';
+import { useTabs, type SessionInfo } from '../context/TabsContext';
 import './TerminalTabs.css';
 
+/** Sessions grouped by project, groups in order of their first session. */
+function groupByProject(sessions: SessionInfo[]): [string | undefined, SessionInfo[]][] {
+  const groups = new Map<string | undefined, SessionInfo[]>();
+  for (const session of sessions) {
+    const members = groups.get(session.project);
+    if (members) members.push(session);
+    else groups.set(session.project, [session]);
+  }
+  return [...groups.entries()];
+}
+
 export default function TerminalTabs() {
   const { sessions, activeSessionId, switchSession, createTab, closeTab } = useTabs();
 
@@ -24,31 +35,38 @@ export default function TerminalTabs() {
       </div>
 
       <div className="tab-list" role="tablist" aria-label="Terminal sessions">
-        {sessions.map((session) => (
-          <div
-            key={session.id}
-            className={`tab-item${session.id === activeSessionId ? ' active' : ''}${!session.connected ? ' disconnected' : ''}`}
-            role="tab"
-            tabIndex={0}
-            aria-selected={session.id === activeSessionId}
-            onClick={() => switchSession(session.id)}
-            onKeyDown={(e) => {
-              if (e.key === 'Enter' || e.key === ' ') switchSession(session.id);
-            }}
-            title={session.name}
-          >
-            <span className="tab-title">{session.name || 'Terminal'}</span>
-            {!session.connected && <span className="disconnected-badge">offline</span>}
-            <button
-              className="btn-close-tab"
-              onClick={(e) => handleCloseSession(e, session.id)}
-              title="Close session"
-              aria-label={`Close session ${session.name}`}
+        {groupByProject(sessions).map(([project, members]) => [
+          project !== undefined && (
+            <div key={`project:${project}`} className="tab-group-label" title={project}>
+              {project}
+            </div>
+          ),
+          ...members.map((session) => (
+            <div
+              key={session.id}
+              className={`tab-item${session.id === activeSessionId ? ' active' : ''}${!session.connected ? ' disconnected' : ''}`}
+              role="tab"
+              tabIndex={0}
+              aria-selected={session.id === activeSessionId}
+              onClick={() => switchSession(session.id)}
+              onKeyDown={(e) => {
+                if (e.key === 'Enter' || e.key === ' ') switchSession(session.id);
+              }}
+              title={[session.title, session.cwd].filter(Boolean).join('\n') || session.name}
             >
-              &times;
-            </button>
-          </div>
-        ))}
+              <span className="tab-title">{session.name || 'Terminal'}</span>
+              {!session.connected && <span className="disconnected-badge">offline</span>}
+              <button
+                className="btn-close-tab"
+                onClick={(e) => handleCloseSession(e, session.id)}
+                title="Close session"
+                aria-label={`Close session ${session.name}`}
+              >
+                &times;
+              </button>
+            </div>
+          )),
+        ])}
       </div>
 
       {sessions.length === 0 && <div className="tab-empty">No sessions</div>}
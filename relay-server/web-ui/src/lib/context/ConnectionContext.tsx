@@ -4,8 +4,11 @@
  *
  * Protocol: v2 Rust relay
  * - Endpoint: /ws
- * - Auth: auth/auth_success/auth_failed
- * - Terminal I/O: Binary frames with session ID prefix
+ * - Auth: auth/auth_success/auth_failed, with awaiting_approval in between
+ *   when the Mac approves each new browser, and a password when it set one
+ * - Terminal I/O: Binary frames with session ID prefix, over a direct WebRTC
+ *   data channel when one opens (see protocol/direct.ts)
+ * - End-to-end encryption: output decrypted locally once paired (see protocol/e2e.ts)
  */
 
 import { createContext, useContext, useState, useRef, useCallback, useEffect, type ReactNode } from 'react';
@@ -13,13 +16,32 @@ import ReconnectingWebSocket from 'reconnecting-websocket';
 import type {
   AuthMessage,
   AuthSuccessMessage,
+  Role,
+  RoleChangedMessage,
+  MacStatusMessage,
   AuthFailedMessage,
+  AuthFailure,
   ErrorMessage,
   SessionConnectedMessage,
   SessionDisconnectedMessage,
   ConfigMessage,
+  E2eKeyMessage,
+  E2eRequiredMessage,
+  RtcAnswerMessage,
+  LatencyProbeMessage,
+  LatencyEchoMessage,
+  RtcCandidateMessage,
+  PresenceMessage,
+  ReplayStartMessage,
+  SetNicknameMessage,
+  ViewerSchema,
 } from '../../shared/protocol';
+import { PROTOCOL_VERSION } from '../../shared/protocol';
+import { DIRECT_ICE_SERVERS } from '../../shared/constants';
 import { decodeBinaryFrame, encodeInputMessage } from '../protocol/binary';
+import { DirectLink } from '../protocol/direct';
+import { FrameInflater } from '../protocol/inflate';
+import { E2eSession, fingerprint, loadPairing } from '../protocol/e2e';
 
 // =============================================================================
 // Connection State Types
@@ -29,9 +51,20 @@ export type ConnectionState =
   | 'disconnected'
   | 'connecting'
   | 'authenticating'
+  | 'awaiting_approval'
   | 'connected'
   | 'reconnecting';
 
+/**
+ * End-to-end encryption of terminal output:
+ * - none: the Mac sends plaintext (or has not said otherwise)
+ * - waiting: paired, waiting for the Mac's output key
+ * - encrypted: output is decrypted locally
+ * - unpaired: output is encrypted but this browser has no pairing link
+ * - mismatch: the pairing link belongs to another Mac
+ */
+export type EncryptionState = 'none' | 'waiting' | 'encrypted' | 'unpaired' | 'mismatch';
+
 // =============================================================================
 // Session Storage Helpers
 // =============================================================================
@@ -62,6 +95,46 @@ function clearStoredSessionCode(): void {
   }
 }
 
+const NICKNAME_STORAGE_KEY = 'terminal-nickname';
+
+/** Name this browser goes by in chat and presence, if it chose one */
+function getStoredNickname(): string | undefined {
+  try {
+    return localStorage.getItem(NICKNAME_STORAGE_KEY) || undefined;
+  } catch {
+    return undefined;
+  }
+}
+
+function storeNickname(nickname: string | undefined): void {
+  try {
+    if (nickname) {
+      localStorage.setItem(NICKNAME_STORAGE_KEY, nickname);
+    } else {
+      localStorage.removeItem(NICKNAME_STORAGE_KEY);
+    }
+  } catch {
+    // Ignore storage errors
+  }
+}
+
+const BROWSER_KEY_STORAGE_KEY = 'terminal-browser-key';
+
+/** Random key identifying this browser to Macs that approve browsers */
+function getBrowserKey(): string | undefined {
+  try {
+    let key = localStorage.getItem(BROWSER_KEY_STORAGE_KEY);
+    if (!key) {
+      const bytes = crypto.getRandomValues(new Uint8Array(16));
+      key = Array.from(bytes, (b) => b.toString(16).padStart(2, '0')).join('');
+      localStorage.setItem(BROWSER_KEY_STORAGE_KEY, key);
+    }
+    return key;
+  } catch {
+    return undefined;
+  }
+}
+
 // =============================================================================
 // Handler Types
 // =============================================================================
@@ -76,12 +149,63 @@ export type BinaryHandler = (sessionId: string, data: Uint8Array) => void;
 // Context Interface
 // =============================================================================
 
+/** How a browser joins besides the session code */
+export interface JoinOptions {
+  /** Required when the Mac set one */
+  password?: string;
+  /** 'viewer' joins read-only */
+  role?: Role;
+  /** From a one-time join link */
+  token?: string;
+  /** Only the newest this many bytes of scrollback, for a slow connection */
+  replayBytes?: number;
+}
+
+/** Last round trip through the relay to the Mac and back */
+export interface Latency {
+  roundTripMs: number;
+  /** The part between the relay and the Mac, if the relay measured it */
+  macMs: number | null;
+}
+
+/** Time between latency probes while connected */
+const LATENCY_PROBE_INTERVAL_MS = 5000;
+
+/** How long "Mac reconnected" shows after the Mac came back */
+const MAC_RECONNECTED_NOTICE_MS = 4000;
+
 interface ConnectionContextValue {
   state: ConnectionState;
+  encryption: EncryptionState;
+  /** Terminal frames go over a direct data channel instead of the relay */
+  direct: boolean;
   error: string | null;
+  /** Why the last auth failed, e.g. 'password_required' */
+  authFailure: AuthFailure | null;
   sessionCode: string | null;
+  /** Display name of the Mac this browser is connected to */
+  clientName: string | null;
+  /** Only watching: the relay drops input from this browser */
+  viewOnly: boolean;
+  /** The Mac lost its connection to the relay and may come back */
+  macAway: boolean;
+  /** The Mac just came back after losing its connection; the session and
+   * its terminals carried on */
+  macReconnected: boolean;
+  /** Null until the first probe came back, or with an older Mac */
+  latency: Latency | null;
+  /** The session keeps no output on the relay: no history before joining */
+  liveOnly: boolean;
+  /** Bytes of scrollback the relay is still sending, if it is */
+  replaying: number | null;
+  /** Browsers connected to the session, this one included, oldest first */
+  viewers: ViewerSchema[];
+  /** Name this browser shows the others, if any */
+  nickname: string | undefined;
+  /** Change it, for this and later sessions */
+  setNickname: (nickname: string) => void;
   isConnected: boolean;
-  connect: (sessionCode: string, onConnected?: () => void) => void;
+  connect: (sessionCode: string, onConnected?: () => void, options?: JoinOptions) => void;
   disconnect: () => void;
   /** Send a JSON control message */
   sendMessage: (message: object) => void;
@@ -110,10 +234,31 @@ export function useConnection(): ConnectionContextValue {
 export function ConnectionProvider({ children }: { children: ReactNode }) {
   const [state, setState] = useState<ConnectionState>('disconnected');
   const [error, setError] = useState<string | null>(null);
+  const [authFailure, setAuthFailure] = useState<AuthFailure | null>(null);
+  const [encryption, setEncryption] = useState<EncryptionState>('none');
+  const [direct, setDirect] = useState(false);
   const [sessionCode, setSessionCode] = useState<string | null>(null);
+  const [clientName, setClientName] = useState<string | null>(null);
+  const [viewOnly, setViewOnly] = useState(false);
+  const [macAway, setMacAway] = useState(false);
+  const [macReconnected, setMacReconnected] = useState(false);
+  const [latency, setLatency] = useState<Latency | null>(null);
+  const [liveOnly, setLiveOnly] = useState(false);
+  const [replaying, setReplaying] = useState<number | null>(null);
+  const [viewers, setViewers] = useState<ViewerSchema[]>([]);
+  const [nickname, setNicknameState] = useState<string | undefined>(getStoredNickname);
 
   const wsRef = useRef<ReconnectingWebSocket | null>(null);
   const currentCodeRef = useRef<string | null>(null);
+  // Kept in memory only, for reconnects of this connection
+  const passwordRef = useRef<string | undefined>(undefined);
+  const roleRef = useRef<Role | undefined>(undefined);
+  const tokenRef = useRef<string | undefined>(undefined);
+  const replayBytesRef = useRef<number | undefined>(undefined);
+  // Terminals keep their output across reconnects, so only what they
+  // missed is replayed
+  const resumeTokenRef = useRef<string | undefined>(undefined);
+  const viewOnlyRef = useRef(false);
   const onConnectedCallbackRef = useRef<(() => void) | null>(null);
   const messageHandlersRef = useRef<Set<MessageHandler>>(new Set());
   const binaryHandlersRef = useRef<Set<BinaryHandler>>(new Set());
@@ -121,6 +266,28 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   // Refs for state values that event handlers need to read (avoids stale closures)
   const stateRef = useRef<ConnectionState>('disconnected');
 
+  // Latency probes of the current connection
+  const probeIdRef = useRef(0);
+  const lastRttRef = useRef<number | undefined>(undefined);
+
+  // End-to-end encryption: key exchange for the current connection, and a
+  // promise chain that keeps decrypted frames in arrival order
+  const pairingRef = useRef(loadPairing());
+  const e2eRef = useRef<E2eSession | null>(null);
+  const decryptChainRef = useRef<Promise<void>>(Promise.resolve());
+  const encryptedWithoutKeyRef = useRef(false);
+
+  // Direct data channel to the Mac for the current connection, if any
+  const directRef = useRef<DirectLink | null>(null);
+  const iceServersRef = useRef(DIRECT_ICE_SERVERS);
+  const inflaterRef = useRef<FrameInflater | null>(null);
+
+  const closeDirect = useCallback(() => {
+    directRef.current?.close();
+    directRef.current = null;
+    setDirect(false);
+  }, []);
+
   // ---------------------------------------------------------------------------
   // Handler Registration
   // ---------------------------------------------------------------------------
@@ -147,12 +314,24 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   }, []);
 
   const sendBinary = useCallback((frame: Uint8Array) => {
+    if (viewOnlyRef.current) return;
+    if (stateRef.current === 'connected' && directRef.current?.send(frame)) {
+      return;
+    }
     const ws = wsRef.current;
     if (stateRef.current === 'connected' && ws && ws.readyState === WebSocket.OPEN) {
       ws.send(frame);
     }
   }, []);
 
+  const setNickname = useCallback((name: string) => {
+    const trimmed = name.trim() || undefined;
+    storeNickname(trimmed);
+    setNicknameState(trimmed);
+    const msg: SetNicknameMessage = { type: 'set_nickname', nickname: trimmed };
+    sendMessageFn(msg);
+  }, [sendMessageFn]);
+
   const sendTerminalInput = useCallback((termSessionId: string, payload: string) => {
     const frame = encodeInputMessage(termSessionId, payload);
     sendBinary(frame);
@@ -163,6 +342,7 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
   // ---------------------------------------------------------------------------
 
   const disconnect = useCallback(() => {
+    closeDirect();
     if (wsRef.current) {
       wsRef.current.close();
       wsRef.current = null;
@@ -170,21 +350,36 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     setState('disconnected');
     stateRef.current = 'disconnected';
     setError(null);
+    setAuthFailure(null);
     setSessionCode(null);
+    setClientName(null);
+    setViewOnly(false);
+    setViewers([]);
+    setLiveOnly(false);
+    setReplaying(null);
+    viewOnlyRef.current = false;
     currentCodeRef.current = null;
+    passwordRef.current = undefined;
+    roleRef.current = undefined;
+    tokenRef.current = undefined;
+    replayBytesRef.current = undefined;
     clearStoredSessionCode();
+    e2eRef.current = null;
+    encryptedWithoutKeyRef.current = false;
+    setEncryption('none');
     // Notify handlers of disconnect
     for (const handler of messageHandlersRef.current) {
       handler({ type: '__disconnect' });
     }
-  }, []);
+  }, [closeDirect]);
 
   // ---------------------------------------------------------------------------
   // Connect
   // ---------------------------------------------------------------------------
 
-  const connect = useCallback((code: string, onConnected?: () => void) => {
+  const connect = useCallback((code: string, onConnected?: () => void, options: JoinOptions = {}) => {
     // Close existing connection if any
+    closeDirect();
     if (wsRef.current) {
       wsRef.current.close();
       wsRef.current = null;
@@ -193,15 +388,23 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     setState('connecting');
     stateRef.current = 'connecting';
     setError(null);
+    setAuthFailure(null);
     setSessionCode(null);
     currentCodeRef.current = code;
+    passwordRef.current = options.password || undefined;
+    roleRef.current = options.role;
+    tokenRef.current = options.token;
+    replayBytesRef.current = options.replayBytes;
+    resumeTokenRef.current = undefined;
     onConnectedCallbackRef.current = onConnected ?? null;
 
     // Derive relay URL: use env var in dev, or derive from current location in production
-    const relayUrl = import.meta.env.VITE_RELAY_URL
-      || `${location.protocol === 'https:' ? 'wss:' : 'ws:'}//${location.host}/ws`;
+    const relayUrl = new URL(import.meta.env.VITE_RELAY_URL
+      || `${location.protocol === 'https:' ? 'wss:' : 'ws:'}//${location.host}/ws`);
+    // Load balancers in front of several relays route on the code
+    relayUrl.searchParams.set('code', code);
 
-    const ws = new ReconnectingWebSocket(relayUrl, [], {
+    const ws = new ReconnectingWebSocket(relayUrl.toString(), [], {
       maxReconnectionDelay: 30000,
       minReconnectionDelay: 1000,
       reconnectionDelayGrowFactor: 2,
@@ -214,28 +417,81 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     ws.addEventListener('open', () => {
       setState('authenticating');
       stateRef.current = 'authenticating';
+      // A new connection starts a new deflate stream
+      inflaterRef.current = null;
 
       if (currentCodeRef.current) {
         // Send auth message with session code
         const authMessage: AuthMessage = {
           type: 'auth',
           session_code: currentCodeRef.current,
+          browser_key: getBrowserKey(),
+          password: passwordRef.current,
+          role: roleRef.current,
+          token: tokenRef.current,
+          version: PROTOCOL_VERSION,
+          compression: FrameInflater.supported() ? 'deflate' : undefined,
+          resume_token: resumeTokenRef.current,
+          nickname: getStoredNickname(),
+          replay_bytes: replayBytesRef.current,
         };
         ws.send(JSON.stringify(authMessage));
       }
     });
 
+    const dispatchBinary = (sessionId: string, payload: Uint8Array) => {
+      for (const handler of binaryHandlersRef.current) {
+        handler(sessionId, payload);
+      }
+    };
+
+    // Binary frame from the relay or the direct channel: decode (and
+    // decrypt) and dispatch to binary handlers
+    const handleFrame = (frame: Uint8Array) => {
+      try {
+        const { sessionId, payload } = decodeBinaryFrame(frame);
+        const e2e = e2eRef.current;
+        if (e2e) {
+          decryptChainRef.current = decryptChainRef.current
+            .then(() => e2e.open(sessionId, payload))
+            .then((plain) => dispatchBinary(sessionId, plain))
+            .catch((e) => console.error('[Connection] Failed to decrypt frame:', e));
+        } else if (!encryptedWithoutKeyRef.current) {
+          dispatchBinary(sessionId, payload);
+        }
+      } catch (e) {
+        console.error('[Connection] Failed to decode binary frame:', e);
+      }
+    };
+
+    // Offer the Mac a direct data channel; frames stay on the relay until it opens
+    const startDirect = () => {
+      closeDirect();
+      if (typeof RTCPeerConnection === 'undefined') return;
+      const link = new DirectLink(
+        (message) => {
+          if (ws.readyState === WebSocket.OPEN) ws.send(JSON.stringify(message));
+        },
+        handleFrame,
+        (open) => {
+          if (directRef.current === link) setDirect(open);
+        },
+        iceServersRef.current,
+      );
+      directRef.current = link;
+      link.start().catch((e) => console.warn('[Connection] Direct offer failed:', e));
+    };
+
     ws.addEventListener('message', (event: MessageEvent) => {
-      // Binary frame: decode and dispatch to binary handlers
       if (event.data instanceof ArrayBuffer) {
-        try {
-          const frame = new Uint8Array(event.data);
-          const { sessionId, payload } = decodeBinaryFrame(frame);
-          for (const handler of binaryHandlersRef.current) {
-            handler(sessionId, payload);
-          }
-        } catch (e) {
-          console.error('[Connection] Failed to decode binary frame:', e);
+        const inflater = inflaterRef.current;
+        if (inflater) {
+          inflater
+            .inflate(new Uint8Array(event.data))
+            .then(handleFrame)
+            .catch((e) => console.error('[Connection] Failed to inflate frame:', e));
+        } else {
+          handleFrame(new Uint8Array(event.data));
         }
         return;
       }
@@ -246,13 +502,33 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
 
         switch (data.type) {
           case 'auth_success': {
+            const msg = data as AuthSuccessMessage;
             setState('connected');
             stateRef.current = 'connected';
             setSessionCode(currentCodeRef.current);
+            setClientName(msg.client_name ?? null);
+            resumeTokenRef.current = msg.resume_token;
+            viewOnlyRef.current = msg.role === 'viewer';
+            setMacAway(false);
+            setMacReconnected(false);
+            // A resumed browser keeps what it was told before
+            if (!msg.resumed) setLiveOnly(false);
+            // Frames from the relay (not the direct channel) are compressed
+            inflaterRef.current = msg.compression === 'deflate' ? new FrameInflater() : null;
+            iceServersRef.current = msg.ice_servers ?? DIRECT_ICE_SERVERS;
+            setViewOnly(viewOnlyRef.current);
             setError(null);
             if (currentCodeRef.current) {
               storeSessionCode(currentCodeRef.current);
             }
+            // Ask the Mac for the output key if this browser is paired
+            encryptedWithoutKeyRef.current = false;
+            decryptChainRef.current = Promise.resolve();
+            e2eRef.current = pairingRef.current ? new E2eSession(pairingRef.current) : null;
+            setEncryption(e2eRef.current ? 'waiting' : 'none');
+            e2eRef.current?.hello().then((hello) => ws.send(JSON.stringify(hello)));
+            // A direct channel would carry input past the relay
+            if (!viewOnlyRef.current) startDirect();
             // Fire one-time connected callback
             if (onConnectedCallbackRef.current) {
               const cb = onConnectedCallbackRef.current;
@@ -266,10 +542,65 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
             break;
           }
 
+          case 'awaiting_approval': {
+            setState('awaiting_approval');
+            stateRef.current = 'awaiting_approval';
+            break;
+          }
+
+          case 'role_changed': {
+            const msg = data as RoleChangedMessage;
+            viewOnlyRef.current = msg.role === 'viewer';
+            setViewOnly(viewOnlyRef.current);
+            if (viewOnlyRef.current) {
+              closeDirect();
+            } else if (!directRef.current) {
+              startDirect();
+            }
+            for (const handler of messageHandlersRef.current) {
+              handler(data);
+            }
+            break;
+          }
+
+          case 'mac_status': {
+            const msg = data as MacStatusMessage;
+            setMacAway(!msg.connected);
+            setMacReconnected(msg.connected);
+            // The Mac's peer connections went with its relay connection
+            if (!msg.connected) {
+              closeDirect();
+            } else if (!viewOnlyRef.current) {
+              startDirect();
+            }
+            break;
+          }
+
+          case 'live_from_now': {
+            setLiveOnly(true);
+            break;
+          }
+
+          case 'replay_start': {
+            setReplaying((data as ReplayStartMessage).bytes);
+            break;
+          }
+
+          case 'replay_end': {
+            setReplaying(null);
+            break;
+          }
+
+          case 'presence': {
+            setViewers((data as PresenceMessage).viewers);
+            break;
+          }
+
           case 'auth_failed': {
             const msg = data as AuthFailedMessage;
             console.error('[Connection] Auth failed:', msg.reason);
             setError(msg.reason);
+            setAuthFailure(msg.kind ?? null);
             setState('disconnected');
             stateRef.current = 'disconnected';
             setSessionCode(null);
@@ -296,12 +627,77 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
             break;
           }
 
+          case 'e2e_required': {
+            const msg = data as E2eRequiredMessage;
+            const pairing = pairingRef.current;
+            if (!pairing) {
+              // Ciphertext is useless here; drop it instead of printing it
+              encryptedWithoutKeyRef.current = true;
+              setEncryption('unpaired');
+              break;
+            }
+            fingerprint(pairing).then((expected) => {
+              if (expected !== msg.fingerprint) {
+                console.error('[Connection] Paired with key', expected, 'but this Mac has', msg.fingerprint);
+                setEncryption('mismatch');
+              }
+            });
+            break;
+          }
+
+          case 'latency_echo': {
+            const msg = data as LatencyEchoMessage;
+            const roundTripMs = Math.round(performance.now() - msg.sent_at);
+            lastRttRef.current = roundTripMs;
+            setLatency({ roundTripMs, macMs: msg.mac_rtt_ms ?? null });
+            break;
+          }
+
+          case 'rtc_answer': {
+            const msg = data as RtcAnswerMessage;
+            directRef.current
+              ?.acceptAnswer(msg)
+              .catch((e) => console.warn('[Connection] Direct answer rejected:', e));
+            break;
+          }
+
+          case 'rtc_candidate': {
+            const msg = data as RtcCandidateMessage;
+            directRef.current
+              ?.addCandidate(msg)
+              .catch((e) => console.warn('[Connection] Direct candidate rejected:', e));
+            break;
+          }
+
+          case 'e2e_key': {
+            const msg = data as E2eKeyMessage;
+            e2eRef.current
+              ?.acceptKey(msg.public_key, msg.key)
+              .then((mine) => {
+                if (mine) setEncryption('encrypted');
+              })
+              .catch((e) => {
+                console.error('[Connection] Failed to unwrap output key:', e);
+                setEncryption('mismatch');
+              });
+            break;
+          }
+
           // Session events forwarded from mac-client
           case 'session_list':
           case 'session_connected':
           case 'session_disconnected':
+          case 'session_metadata':
           // Session resize (mac -> browser)
           case 'session_resize':
+          // OSC 52 copy from a shell (mac -> browser)
+          case 'clipboard':
+          // Scrollback search answer (mac -> this browser)
+          case 'search_results':
+          // Missed output, the scrollback follows (relay -> this browser)
+          case 'resync':
+          // Session chat (relay -> every browser)
+          case 'chat':
           // Config message
           case 'config':
           // Legacy tab messages (if any)
@@ -324,6 +720,9 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     });
 
     ws.addEventListener('close', () => {
+      // The relay forgets this browser; a new offer follows the next auth
+      closeDirect();
+      setReplaying(null);
       if (stateRef.current === 'connected') {
         setState('reconnecting');
         stateRef.current = 'reconnecting';
@@ -340,7 +739,35 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
     });
 
     wsRef.current = ws;
-  }, []);
+  }, [closeDirect]);
+
+  // Probe the round trip to the Mac while connected
+  useEffect(() => {
+    if (state !== 'connected') {
+      setLatency(null);
+      lastRttRef.current = undefined;
+      return;
+    }
+    const probe = () => {
+      const msg: LatencyProbeMessage = {
+        type: 'latency_probe',
+        id: ++probeIdRef.current,
+        sent_at: performance.now(),
+        rtt_ms: lastRttRef.current,
+      };
+      sendMessageFn(msg);
+    };
+    probe();
+    const timer = setInterval(probe, LATENCY_PROBE_INTERVAL_MS);
+    return () => clearInterval(timer);
+  }, [state, sendMessageFn]);
+
+  // "Mac reconnected" shows for a moment
+  useEffect(() => {
+    if (!macReconnected) return;
+    const timer = setTimeout(() => setMacReconnected(false), MAC_RECONNECTED_NOTICE_MS);
+    return () => clearTimeout(timer);
+  }, [macReconnected]);
 
   // Auto-reconnect on mount if we have a stored session code
   useEffect(() => {
@@ -352,8 +779,21 @@ export function ConnectionProvider({ children }: { children: ReactNode }) {
 
   const value: ConnectionContextValue = {
     state,
+    encryption,
+    direct,
     error,
+    authFailure,
     sessionCode,
+    clientName,
+    viewOnly,
+    macAway,
+    macReconnected,
+    latency,
+    liveOnly,
+    replaying,
+    viewers,
+    nickname,
+    setNickname,
     isConnected: state === 'connected',
     connect,
     disconnect,
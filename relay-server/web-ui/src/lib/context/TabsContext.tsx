@@ -3,7 +3,8 @@
  *
  * Manages shell sessions displayed as tabs in the UI. Sessions are discovered
  * from binary frame headers (when terminal data arrives) or from explicit
- * session_connected/session_disconnected JSON messages.
+ * session_connected/session_disconnected JSON messages. session_metadata
+ * keeps their working directory and window title current.
  *
  * Key behaviors:
  * - Sessions are added when first binary frame arrives for a new sessionId
@@ -21,7 +22,12 @@ import {
   useRef,
   type ReactNode,
 } from 'react';
-import type { SessionConnectedMessage, SessionDisconnectedMessage, SessionListMessage } from '../../shared/protocol';
+import type {
+  SessionConnectedMessage,
+  SessionDisconnectedMessage,
+  SessionListMessage,
+  SessionMetadataMessage,
+} from '../../shared/protocol';
 import { useConnection } from './ConnectionContext';
 import { useTerminal } from './TerminalContext';
 
@@ -32,6 +38,12 @@ import { useTerminal } from './TerminalContext';
 export interface SessionInfo {
   id: string;
   name: string;
+  /** Project the session is grouped under (from the session list) */
+  project?: string;
+  /** Working directory of the shell */
+  cwd?: string;
+  /** Window title the program in the shell set */
+  title?: string;
   connected: boolean;
   lastActivity: number; // timestamp
 }
@@ -100,7 +112,7 @@ export function TabsProvider({ children }: { children: ReactNode }) {
    * Add or update a session from binary data or session_connected message.
    * If this is the first session, auto-switch to it.
    */
-  const addOrUpdateSession = useCallback((sessionId: string, name?: string) => {
+  const addOrUpdateSession = useCallback((sessionId: string, name?: string, project?: string) => {
     setSessions((prev) => {
       const existing = prev.find((s) => s.id === sessionId);
 
@@ -108,7 +120,13 @@ export function TabsProvider({ children }: { children: ReactNode }) {
         // Update lastActivity and ensure connected = true
         return prev.map((s) =>
           s.id === sessionId
-            ? { ...s, connected: true, lastActivity: Date.now(), name: name ?? s.name }
+            ? {
+                ...s,
+                connected: true,
+                lastActivity: Date.now(),
+                name: name ?? s.name,
+                project: project ?? s.project,
+              }
             : s
         );
       }
@@ -117,6 +135,7 @@ export function TabsProvider({ children }: { children: ReactNode }) {
       const newSession: SessionInfo = {
         id: sessionId,
         name: name ?? sessionId, // Use sessionId as fallback name
+        project,
         connected: true,
         lastActivity: Date.now(),
       };
@@ -142,6 +161,25 @@ export function TabsProvider({ children }: { children: ReactNode }) {
     }
   }, [setActiveSession]);
 
+  /**
+   * Apply a session_metadata delta to a known session.
+   */
+  const updateSessionMetadata = useCallback((msg: SessionMetadataMessage) => {
+    setSessions((prev) =>
+      prev.map((s) =>
+        s.id === msg.session_id
+          ? {
+              ...s,
+              name: msg.name ?? s.name,
+              project: msg.project ?? s.project,
+              cwd: msg.cwd ?? s.cwd,
+              title: msg.title ?? s.title,
+            }
+          : s
+      )
+    );
+  }, []);
+
   /**
    * Mark a session as disconnected. It will be removed after a delay.
    */
@@ -217,10 +255,15 @@ export function TabsProvider({ children }: { children: ReactNode }) {
           const msg = data as unknown as SessionListMessage;
           console.log('[TabsContext] Received session_list:', msg.sessions.length, 'sessions');
           for (const session of msg.sessions) {
-            addOrUpdateSession(session.id, session.name);
+            addOrUpdateSession(session.id, session.name, session.project);
+            updateSessionMetadata({ type: 'session_metadata', session_id: session.id, cwd: session.cwd, title: session.title });
           }
           break;
         }
+        case 'session_metadata': {
+          updateSessionMetadata(data as unknown as SessionMetadataMessage);
+          break;
+        }
         case 'session_connected': {
           const msg = data as unknown as SessionConnectedMessage;
           addOrUpdateSession(msg.session_id, msg.name);
@@ -238,7 +281,7 @@ export function TabsProvider({ children }: { children: ReactNode }) {
       }
     });
     return unregister;
-  }, [registerMessageHandler, addOrUpdateSession, markSessionDisconnected, reset]);
+  }, [registerMessageHandler, addOrUpdateSession, updateSessionMetadata, markSessionDisconnected, reset]);
 
   // ---------------------------------------------------------------------------
   // Cleanup timers on unmount
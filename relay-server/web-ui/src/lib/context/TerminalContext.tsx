@@ -10,6 +10,7 @@
  * - Data buffering before terminal mounts
  * - Per-session terminal instances (one xterm per session)
  * - Session resize forwarding (mac -> UI, one-way)
+ * - OSC 52 copies from shells written to the browser clipboard
  */
 
 import {
@@ -22,7 +23,7 @@ import {
   type ReactNode,
 } from 'react';
 import type { Terminal, ITerminalOptions } from '@xterm/xterm';
-import type { ConfigMessage } from '../../shared/protocol';
+import type { ClipboardMessage, ConfigMessage } from '../../shared/protocol';
 import { defaultTerminalOptions, configToXtermOptions } from '../iterm-theme';
 import { useConnection } from './ConnectionContext';
 
@@ -30,6 +31,16 @@ import { useConnection } from './ConnectionContext';
 const DEBUG = false;
 const log = (...args: unknown[]) => DEBUG && console.log('[TerminalContext]', ...args);
 
+/** Decode a base64 OSC 52 payload as UTF-8 text. */
+function decodeClipboard(data: string): string | null {
+  try {
+    const bytes = Uint8Array.from(atob(data), (c) => c.charCodeAt(0));
+    return new TextDecoder().decode(bytes);
+  } catch {
+    return null;
+  }
+}
+
 // =============================================================================
 // Context Types
 // =============================================================================
@@ -183,7 +194,7 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
   }, [registerBinaryHandler, writeBinaryData]);
 
   // ---------------------------------------------------------------------------
-  // Message Handler - config + session_resize messages
+  // Message Handler - config + session_resize + clipboard + resync messages
   // ---------------------------------------------------------------------------
 
   useEffect(() => {
@@ -207,6 +218,25 @@ export function TerminalProvider({ children }: { children: ReactNode }) {
           }
           break;
         }
+        case 'clipboard': {
+          const msg = data as unknown as ClipboardMessage;
+          const text = decodeClipboard(msg.data);
+          log('clipboard:', msg.session_id, text?.length);
+          // Browsers may refuse clipboard writes without a user gesture; ignore that
+          if (text !== null && navigator.clipboard) {
+            navigator.clipboard.writeText(text).catch(() => {});
+          }
+          break;
+        }
+        case 'resync': {
+          // The scrollback that follows redraws every terminal
+          log('resync');
+          for (const terminal of terminalsRef.current.values()) {
+            terminal.reset();
+          }
+          pendingDataRef.current.clear();
+          break;
+        }
         case '__disconnect': {
           setActiveSession(null);
           pendingDataRef.current.clear();
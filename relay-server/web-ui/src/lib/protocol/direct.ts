@@ -0,0 +1,91 @@
+/**
+ * Direct mode: terminal frames over a peer-to-peer WebRTC data channel.
+ *
+ * The relay WebSocket carries the signaling (rtc_offer / rtc_answer /
+ * rtc_candidate). Once the data channel to the Mac opens, binary frames in
+ * both directions use it instead of the relay; they have the same format as
+ * the relay's binary frames. If the channel never opens (no path between the
+ * peers, or direct mode is off on the Mac) or later closes, everything stays
+ * on (or returns to) the WebSocket.
+ */
+
+import type { RtcAnswerMessage, RtcCandidateMessage } from '../../shared/protocol';
+
+export class DirectLink {
+  private pc: RTCPeerConnection;
+  private channel: RTCDataChannel;
+  private closed = false;
+
+  /**
+   * @param signal - Send a signaling message to the relay
+   * @param onFrame - Binary frame received from the Mac
+   * @param onOpenChange - The data channel opened (true) or closed (false)
+   * @param iceServers - STUN/TURN URLs for finding a path to the Mac
+   */
+  constructor(
+    private signal: (message: object) => void,
+    onFrame: (frame: Uint8Array) => void,
+    onOpenChange: (open: boolean) => void,
+    iceServers: string[],
+  ) {
+    this.pc = new RTCPeerConnection({ iceServers: iceServers.length ? [{ urls: iceServers }] : [] });
+    this.channel = this.pc.createDataChannel('terminal', { ordered: true });
+    this.channel.binaryType = 'arraybuffer';
+
+    this.channel.addEventListener('open', () => onOpenChange(true));
+    this.channel.addEventListener('close', () => onOpenChange(false));
+    this.channel.addEventListener('message', (event: MessageEvent) => {
+      if (event.data instanceof ArrayBuffer) {
+        onFrame(new Uint8Array(event.data));
+      }
+    });
+
+    this.pc.addEventListener('icecandidate', (event) => {
+      const c = event.candidate;
+      if (!c || !c.candidate) return;
+      const message: RtcCandidateMessage = {
+        type: 'rtc_candidate',
+        candidate: c.candidate,
+        sdp_mid: c.sdpMid ?? undefined,
+        sdp_mline_index: c.sdpMLineIndex ?? undefined,
+      };
+      this.signal(message);
+    });
+  }
+
+  /** Send the offer; the Mac answers if direct mode is on. */
+  async start(): Promise<void> {
+    const offer = await this.pc.createOffer();
+    await this.pc.setLocalDescription(offer);
+    if (!this.closed) {
+      this.signal({ type: 'rtc_offer', sdp: offer.sdp });
+    }
+  }
+
+  async acceptAnswer(message: RtcAnswerMessage): Promise<void> {
+    if (this.closed) return;
+    await this.pc.setRemoteDescription({ type: 'answer', sdp: message.sdp });
+  }
+
+  async addCandidate(message: RtcCandidateMessage): Promise<void> {
+    if (this.closed) return;
+    await this.pc.addIceCandidate({
+      candidate: message.candidate,
+      sdpMid: message.sdp_mid ?? null,
+      sdpMLineIndex: message.sdp_mline_index ?? null,
+    });
+  }
+
+  /** Send a frame over the data channel. Returns false if it is not open. */
+  send(frame: Uint8Array): boolean {
+    if (this.channel.readyState !== 'open') return false;
+    this.channel.send(frame);
+    return true;
+  }
+
+  close(): void {
+    this.closed = true;
+    this.channel.close();
+    this.pc.close();
+  }
+}
@@ -0,0 +1,158 @@
+/**
+ * End-to-end decryption of terminal output (see mac-client/src/e2e.rs).
+ *
+ * The pairing link opened from the Mac carries `#pair=<pairing string>`:
+ * base64url of a version byte, the Mac's P-256 public key (65 bytes) and a
+ * 16-byte pairing secret. The fragment never reaches the server.
+ *
+ * After auth the browser sends `e2e_hello` with an ephemeral P-256 public
+ * key. The Mac answers with `e2e_key`: the output key wrapped with
+ * AES-256-GCM under HKDF-SHA256(ECDH secret, salt = pairing secret).
+ * Output frame payloads are then `nonce (12) || ciphertext`, with the
+ * session ID as associated data.
+ */
+
+const PAIRING_STORAGE_KEY = 'terminal-e2e-pairing';
+const PAIRING_VERSION = 1;
+const PUBLIC_KEY_LEN = 65;
+const PAIRING_SECRET_LEN = 16;
+const NONCE_LEN = 12;
+const ECDH = { name: 'ECDH', namedCurve: 'P-256' } as const;
+
+const textEncoder = new TextEncoder();
+const WRAP_INFO = textEncoder.encode('terminal-remote e2e v1');
+
+function fromBase64(text: string) {
+  const binary = atob(text.replace(/-/g, '+').replace(/_/g, '/'));
+  return Uint8Array.from(binary, (c) => c.charCodeAt(0));
+}
+
+function toBase64(bytes: Uint8Array): string {
+  return btoa(String.fromCharCode(...bytes));
+}
+
+/** Decoded pairing string (byte arrays as WebCrypto accepts them) */
+export interface Pairing {
+  macPublicKey: ReturnType<typeof fromBase64>;
+  secret: ReturnType<typeof fromBase64>;
+}
+
+/** Parse a pairing string, or null if it is malformed. */
+export function parsePairing(text: string): Pairing | null {
+  try {
+    const bytes = fromBase64(text);
+    if (bytes.length !== 1 + PUBLIC_KEY_LEN + PAIRING_SECRET_LEN || bytes[0] !== PAIRING_VERSION) {
+      return null;
+    }
+    return {
+      macPublicKey: bytes.slice(1, 1 + PUBLIC_KEY_LEN),
+      secret: bytes.slice(1 + PUBLIC_KEY_LEN),
+    };
+  } catch {
+    return null;
+  }
+}
+
+/**
+ * Remove a `name=value` parameter from the URL fragment without reloading,
+ * keeping any others.
+ */
+export function stripHashParam(name: string): void {
+  const rest = location.hash
+    .slice(1)
+    .split('&')
+    .filter((param) => param && !param.startsWith(`${name}=`))
+    .join('&');
+  history.replaceState(null, '', location.pathname + location.search + (rest ? `#${rest}` : ''));
+}
+
+/**
+ * Pairing for this browser: taken from a `#pair=` fragment (which is then
+ * removed from the address bar) or from an earlier visit.
+ */
+export function loadPairing(): Pairing | null {
+  const match = /(?:^#|&)pair=([A-Za-z0-9_-]+)/.exec(location.hash);
+  try {
+    if (match && parsePairing(match[1])) {
+      localStorage.setItem(PAIRING_STORAGE_KEY, match[1]);
+      stripHashParam('pair');
+    }
+    const stored = localStorage.getItem(PAIRING_STORAGE_KEY);
+    return stored ? parsePairing(stored) : null;
+  } catch {
+    return match ? parsePairing(match[1]) : null;
+  }
+}
+
+/** Fingerprint of the Mac's public key, as shown in its menu. */
+export async function fingerprint(pairing: Pairing): Promise<string> {
+  const digest = new Uint8Array(await crypto.subtle.digest('SHA-256', pairing.macPublicKey));
+  const hex = Array.from(digest.slice(0, 6), (b) => b.toString(16).padStart(2, '0')).join('');
+  return `${hex.slice(0, 4)}-${hex.slice(4, 8)}-${hex.slice(8, 12)}`;
+}
+
+/**
+ * Key exchange and output decryption for one authenticated connection.
+ * Frames passed to `open` before the key arrives wait for it.
+ */
+export class E2eSession {
+  private readonly keyPair: Promise<CryptoKeyPair>;
+  private readonly publicKey: Promise<string>;
+  private readonly contentKey: Promise<CryptoKey>;
+  private resolveKey!: (key: CryptoKey) => void;
+
+  constructor(private readonly pairing: Pairing) {
+    this.keyPair = crypto.subtle.generateKey(ECDH, false, ['deriveBits']);
+    this.publicKey = this.keyPair
+      .then((pair) => crypto.subtle.exportKey('raw', pair.publicKey))
+      .then((raw) => toBase64(new Uint8Array(raw)));
+    this.contentKey = new Promise((resolve) => {
+      this.resolveKey = resolve;
+    });
+  }
+
+  /** The `e2e_hello` message to send to the Mac. */
+  async hello(): Promise<{ type: 'e2e_hello'; public_key: string }> {
+    return { type: 'e2e_hello', public_key: await this.publicKey };
+  }
+
+  /**
+   * Unwrap the output key from an `e2e_key` message. Returns false if the
+   * message is meant for another browser.
+   */
+  async acceptKey(publicKey: string, wrapped: string): Promise<boolean> {
+    if (publicKey !== (await this.publicKey)) {
+      return false;
+    }
+    const pair = await this.keyPair;
+    const macKey = await crypto.subtle.importKey('raw', this.pairing.macPublicKey, ECDH, false, []);
+    const shared = await crypto.subtle.deriveBits({ name: 'ECDH', public: macKey }, pair.privateKey, 256);
+    const hkdfKey = await crypto.subtle.importKey('raw', shared, 'HKDF', false, ['deriveKey']);
+    const wrapKey = await crypto.subtle.deriveKey(
+      { name: 'HKDF', hash: 'SHA-256', salt: this.pairing.secret, info: WRAP_INFO },
+      hkdfKey,
+      { name: 'AES-GCM', length: 256 },
+      false,
+      ['decrypt'],
+    );
+    const sealed = fromBase64(wrapped);
+    const raw = await crypto.subtle.decrypt(
+      { name: 'AES-GCM', iv: sealed.slice(0, NONCE_LEN), additionalData: fromBase64(publicKey) },
+      wrapKey,
+      sealed.slice(NONCE_LEN),
+    );
+    this.resolveKey(await crypto.subtle.importKey('raw', raw, 'AES-GCM', false, ['decrypt']));
+    return true;
+  }
+
+  /** Decrypt an output frame payload for `sessionId`. */
+  async open(sessionId: string, payload: Uint8Array): Promise<Uint8Array> {
+    const key = await this.contentKey;
+    const plain = await crypto.subtle.decrypt(
+      { name: 'AES-GCM', iv: payload.slice(0, NONCE_LEN), additionalData: textEncoder.encode(sessionId) },
+      key,
+      payload.slice(NONCE_LEN),
+    );
+    return new Uint8Array(plain);
+  }
+}
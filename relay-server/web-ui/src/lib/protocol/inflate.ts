@@ -0,0 +1,65 @@
+/**
+ * Compressed terminal output from the relay (see relay-server/src/compression.rs).
+ *
+ * Browsers that ask for `deflate` in their auth get every binary frame as
+ * a 4-byte big-endian length followed by the frame's bytes from one raw
+ * deflate stream, flushed after each frame. The stream spans the whole
+ * connection, so messages must be inflated one after the other, in order.
+ */
+
+const LENGTH_LEN = 4;
+
+export class FrameInflater {
+  private writer: WritableStreamDefaultWriter<Uint8Array>;
+  private reader: ReadableStreamDefaultReader<Uint8Array>;
+  /** Inflated bytes read past the end of the previous frame */
+  private pending = new Uint8Array(0);
+  private chain: Promise<unknown> = Promise.resolve();
+
+  /** Whether this browser can inflate raw deflate streams */
+  static supported(): boolean {
+    try {
+      new DecompressionStream('deflate-raw');
+      return true;
+    } catch {
+      return false;
+    }
+  }
+
+  constructor() {
+    const stream = new DecompressionStream('deflate-raw');
+    this.writer = stream.writable.getWriter();
+    this.reader = stream.readable.getReader();
+  }
+
+  /** The frame in one message from the relay */
+  inflate(message: Uint8Array): Promise<Uint8Array> {
+    const result = this.chain.then(() => this.next(message));
+    this.chain = result.catch(() => undefined);
+    return result;
+  }
+
+  private async next(message: Uint8Array): Promise<Uint8Array> {
+    if (message.length < LENGTH_LEN) {
+      throw new Error(`Compressed message too short: ${message.length} bytes`);
+    }
+    const length = new DataView(message.buffer, message.byteOffset, LENGTH_LEN).getUint32(0);
+    // Not awaited: the stream only takes more input once its output is read
+    this.writer.write(message.slice(LENGTH_LEN)).catch(() => undefined);
+
+    const frame = new Uint8Array(length);
+    let filled = 0;
+    while (filled < length) {
+      if (this.pending.length === 0) {
+        const { value, done } = await this.reader.read();
+        if (done) throw new Error('Inflate stream ended');
+        this.pending = value;
+      }
+      const take = Math.min(this.pending.length, length - filled);
+      frame.set(this.pending.subarray(0, take), filled);
+      this.pending = this.pending.subarray(take);
+      filled += take;
+    }
+    return frame;
+  }
+}
@@ -1,13 +1,79 @@
 import { useState, useEffect } from 'react';
-import { useNavigate } from 'react-router-dom';
+import { useNavigate, useParams } from 'react-router-dom';
 import { useConnection } from '../lib/context/ConnectionContext';
+import { stripHashParam } from '../lib/protocol/e2e';
+import type { Role } from '../../shared/protocol';
 import './LoginPage.css';
 
+/** Shortest code a relay can be configured to hand out */
+const MIN_CODE_LENGTH = 4;
+
+/**
+ * A code as typed, in the form the relay hands codes out in: word codes
+ * (`maple-otter-42`) in lower case, character codes in upper case.
+ */
+function normalizeCode(code: string): string {
+  const trimmed = code.replace(/\s/g, '');
+  return trimmed.includes('-') ? trimmed.toLowerCase() : trimmed.toUpperCase();
+}
+
+/**
+ * Session code from a join URL (`/login#code=ABC123`, as in the Mac's QR
+ * code), removed from the address bar so it does not linger in history.
+ */
+function takeJoinCode(): string | null {
+  const match = /(?:^#|&)code=([A-Za-z0-9-]{4,64})(?:&|$)/.exec(location.hash);
+  if (!match) return null;
+  stripHashParam('code');
+  return normalizeCode(match[1]);
+}
+
+/**
+ * Session code from a short link (`/s/ABC123`); the address bar is set back
+ * to the login page so the code does not linger there either.
+ */
+function takePathCode(code: string | undefined): string | null {
+  if (!code || !/^[A-Za-z0-9-]{4,64}$/.test(code)) return null;
+  history.replaceState(null, '', `/login${location.hash}`);
+  return normalizeCode(code);
+}
+
+/** One-time token from a join link the Mac minted */
+function takeJoinToken(): string | undefined {
+  const match = /(?:^#|&)token=([A-Za-z0-9_-]+)(?:&|$)/.exec(location.hash);
+  if (!match) return undefined;
+  stripHashParam('token');
+  return match[1];
+}
+
+/** `role=viewer` in a join URL joins read-only */
+function takeJoinRole(): Role | undefined {
+  if (!/(?:^#|&)role=viewer(?:&|$)/.test(location.hash)) return undefined;
+  stripHashParam('role');
+  return 'viewer';
+}
+
+/** `history=64` in a join URL replays only the last 64 KB of scrollback */
+function takeJoinHistory(): number | undefined {
+  const match = /(?:^#|&)history=(\d{1,6})(?:&|$)/.exec(location.hash);
+  if (!match) return undefined;
+  stripHashParam('history');
+  return Number(match[1]) * 1024;
+}
+
 export default function LoginPage() {
-  const [sessionCode, setSessionCode] = useState('');
+  const { code: pathCode } = useParams();
+  const [joinCode] = useState(() => takeJoinCode() ?? takePathCode(pathCode));
+  const [joinRole] = useState(takeJoinRole);
+  const [joinToken] = useState(takeJoinToken);
+  const [joinHistory] = useState(takeJoinHistory);
+  const [sessionCode, setSessionCode] = useState(joinCode ?? '');
+  const [password, setPassword] = useState('');
   const [isSubmitting, setIsSubmitting] = useState(false);
   const navigate = useNavigate();
-  const { state, error, isConnected, connect } = useConnection();
+  const { state, error, authFailure, isConnected, connect } = useConnection();
+  // Shown once the relay says the Mac requires a password
+  const needsPassword = authFailure === 'password_required' || authFailure === 'wrong_password';
 
   // Redirect to terminal if already connected
   useEffect(() => {
@@ -23,16 +89,40 @@ export default function LoginPage() {
     }
   }, [error]);
 
+  // Connect straight away when opened from a join URL
+  useEffect(() => {
+    if (joinCode && !isConnected) {
+      setIsSubmitting(true);
+      connect(joinCode, () => {
+        navigate('/');
+      }, { role: joinRole, token: joinToken, replayBytes: joinHistory });
+    }
+    // Only once, on arrival
+    // eslint-disable-next-line react-hooks/exhaustive-deps
+  }, []);
+
   function handleSubmit(e: React.FormEvent) {
     e.preventDefault();
 
-    const code = sessionCode.toUpperCase().replace(/\s/g, '');
-    if (code.length !== 6) return;
+    const code = normalizeCode(sessionCode);
+    if (code.length < MIN_CODE_LENGTH) return;
 
     setIsSubmitting(true);
     connect(code, () => {
       navigate('/');
-    });
+    }, { password: needsPassword ? password : undefined, role: joinRole, replayBytes: joinHistory });
+  }
+
+  if (state === 'awaiting_approval') {
+    return (
+      <div className="login-container">
+        <div className="login-box">
+          <h1>Waiting for approval</h1>
+          <p className="subtitle">Allow this browser under Browser Requests in the menu bar on your Mac</p>
+          <div className="reconnecting-spinner" />
+        </div>
+      </div>
+    );
   }
 
   // Show reconnecting spinner while auto-reconnect is in progress
@@ -63,7 +153,7 @@ export default function LoginPage() {
               value={sessionCode}
               onChange={(e) => setSessionCode(e.target.value)}
               placeholder="ABC123"
-              maxLength={6}
+              maxLength={64}
               autoComplete="off"
               autoCapitalize="characters"
               spellCheck={false}
@@ -72,6 +162,23 @@ export default function LoginPage() {
             />
           </div>
 
+          {needsPassword && (
+            <div className="input-wrapper">
+              <label htmlFor="password" className="sr-only">Password</label>
+              <input
+                id="password"
+                type="password"
+                value={password}
+                onChange={(e) => setPassword(e.target.value)}
+                placeholder="Password"
+                autoComplete="current-password"
+                autoFocus
+                className="password-input"
+                disabled={isSubmitting}
+              />
+            </div>
+          )}
+
           {error && (
             <div className="error-box">
               {error}
@@ -81,7 +188,7 @@ export default function LoginPage() {
           <button
             type="submit"
             className="btn-primary"
-            disabled={sessionCode.length !== 6 || isSubmitting}
+            disabled={normalizeCode(sessionCode).length < MIN_CODE_LENGTH || (needsPassword && !password) || isSubmitting}
           >
             {isSubmitting ? 'Connecting...' : 'Connect'}
           </button>
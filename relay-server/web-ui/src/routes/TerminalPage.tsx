@@ -7,6 +7,7 @@ import Terminal from '../lib/components/Terminal';
 import TerminalTabs from '../lib/components/TerminalTabs';
 import MobileControlBar from '../lib/components/MobileControlBar';
 import ConnectionStatus from '../lib/components/ConnectionStatus';
+import ChatPanel from '../lib/components/ChatPanel';
 import './TerminalPage.css';
 
 export default function TerminalPage() {
@@ -63,6 +64,7 @@ export default function TerminalPage() {
             ))}
             <MobileControlBar onKey={handleMobileKey} />
           </div>
+          <ChatPanel />
         </div>
       ) : (
         <main className="waiting-state">
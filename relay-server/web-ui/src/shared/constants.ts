@@ -6,3 +6,7 @@
 export const TERMINAL_MIN_COLS = 20;
 export const TERMINAL_MIN_ROWS = 5;
 export const TERMINAL_DEFAULT_SCROLLBACK = 50000;
+
+// Direct mode: STUN servers for finding a peer-to-peer path to the Mac,
+// unless the relay names its own
+export const DIRECT_ICE_SERVERS = ['stun:stun.l.google.com:19302'];
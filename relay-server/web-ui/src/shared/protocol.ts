@@ -9,34 +9,132 @@ import { z } from 'zod';
 // Auth Protocol Messages (Rust Relay v2)
 // =============================================================================
 
+/**
+ * What a browser may do: a viewer only watches, the relay drops its input
+ */
+export const Role = z.enum(['controller', 'viewer']);
+export type Role = z.infer<typeof Role>;
+
+/**
+ * Newest protocol version (control messages and binary frame format) this
+ * UI speaks. Sent in `auth`; the relay answers with the version to use.
+ */
+export const PROTOCOL_VERSION = 1;
+
 /**
  * Browser authenticates with the relay using a session code.
  * This is the first message sent after WebSocket connection.
  * Uses snake_case to match Rust relay's serde(rename_all = "snake_case").
+ * `browser_key` is a random key kept in local storage, so a Mac that
+ * approves browsers can "always allow" this one. `password` is required
+ * when the Mac set one. `role: 'viewer'` joins read-only. `token` comes from
+ * a one-time join link and stands in for the password and approval.
+ * `version` is the newest protocol version the browser speaks.
+ * `compression: 'deflate'` asks for compressed binary frames.
+ * `replay_bytes` asks for only the newest this many bytes of scrollback.
  */
 export const AuthMessage = z.object({
   type: z.literal('auth'),
-  session_code: z.string().length(6),
+  session_code: z.string().min(4).max(64),
+  browser_key: z.string().optional(),
+  password: z.string().optional(),
+  role: Role.optional(),
+  token: z.string().optional(),
+  version: z.number().int().optional(),
+  compression: z.enum(['deflate']).optional(),
+  resume_token: z.string().optional(),
+  nickname: z.string().optional(),
+  replay_bytes: z.number().int().nonnegative().optional(),
 });
 export type AuthMessage = z.infer<typeof AuthMessage>;
 
 /**
- * Relay confirms successful authentication
+ * Relay confirms successful authentication.
+ * `client_name` is the display name of the Mac the code belongs to,
+ * `role` what this browser was let in as, `version` the protocol version
+ * to speak (missing from relays that predate versioning: 1). With
+ * `compression` set, every binary frame from the relay is compressed.
+ * `resume_token` goes in the `auth` after a dropped connection; `resumed`
+ * says the scrollback that follows is only the output this browser missed.
  */
 export const AuthSuccessMessage = z.object({
   type: z.literal('auth_success'),
+  client_name: z.string().optional(),
+  role: Role.optional(),
+  version: z.number().int().optional(),
+  compression: z.enum(['deflate']).optional(),
+  resume_token: z.string().optional(),
+  resumed: z.boolean().optional(),
+  ice_servers: z.array(z.string()).optional(),
 });
 export type AuthSuccessMessage = z.infer<typeof AuthSuccessMessage>;
 
 /**
- * Relay rejects authentication with a reason
+ * Why the relay turned a browser away
+ */
+export const AuthFailure = z.enum([
+  'invalid_code',
+  'password_required',
+  'wrong_password',
+  'sharing_paused',
+  'too_many_browsers',
+  'session_full',
+  'not_approved',
+  'rate_limited',
+  'ip_not_allowed',
+  'invalid_token',
+  'unsupported_version',
+]);
+export type AuthFailure = z.infer<typeof AuthFailure>;
+
+/**
+ * Relay rejects authentication with a reason (shown to the user) and its
+ * kind (missing from older relays)
  */
 export const AuthFailedMessage = z.object({
   type: z.literal('auth_failed'),
   reason: z.string(),
+  kind: AuthFailure.optional(),
 });
 export type AuthFailedMessage = z.infer<typeof AuthFailedMessage>;
 
+/**
+ * The Mac approves each new browser; nothing arrives until it does
+ * (auth_success) or refuses (auth_failed).
+ */
+export const AwaitingApprovalMessage = z.object({
+  type: z.literal('awaiting_approval'),
+});
+export type AwaitingApprovalMessage = z.infer<typeof AwaitingApprovalMessage>;
+
+/**
+ * The Mac granted or revoked this browser's input
+ */
+export const RoleChangedMessage = z.object({
+  type: z.literal('role_changed'),
+  role: Role,
+});
+export type RoleChangedMessage = z.infer<typeof RoleChangedMessage>;
+
+/**
+ * The Mac's connection to the relay dropped and the relay holds the session
+ * for it to come back (`connected: false`), or it came back
+ */
+export const MacStatusMessage = z.object({
+  type: z.literal('mac_status'),
+  connected: z.boolean(),
+});
+export type MacStatusMessage = z.infer<typeof MacStatusMessage>;
+
+/**
+ * This browser fell behind and missed output the relay no longer keeps; the
+ * scrollback follows again, to show instead of what the terminals have
+ */
+export const ResyncMessage = z.object({
+  type: z.literal('resync'),
+});
+export type ResyncMessage = z.infer<typeof ResyncMessage>;
+
 // =============================================================================
 // Session Event Messages (Mac Client -> Browser via Relay)
 // =============================================================================
@@ -47,6 +145,11 @@ export type AuthFailedMessage = z.infer<typeof AuthFailedMessage>;
 export const SessionInfoSchema = z.object({
   id: z.string(),
   name: z.string(),
+  /** Git repository or top-level directory the shell is in */
+  project: z.string().optional(),
+  cwd: z.string().optional(),
+  /** Window title the program in the shell set */
+  title: z.string().optional(),
 });
 export type SessionInfoSchema = z.infer<typeof SessionInfoSchema>;
 
@@ -81,6 +184,305 @@ export const SessionDisconnectedMessage = z.object({
 });
 export type SessionDisconnectedMessage = z.infer<typeof SessionDisconnectedMessage>;
 
+/**
+ * Something about a shell session changed. Fields left out stay as they were.
+ */
+export const SessionMetadataMessage = z.object({
+  type: z.literal('session_metadata'),
+  session_id: z.string(),
+  name: z.string().optional(),
+  shell: z.string().optional(),
+  cwd: z.string().optional(),
+  project: z.string().optional(),
+  title: z.string().optional(),
+});
+export type SessionMetadataMessage = z.infer<typeof SessionMetadataMessage>;
+
+/**
+ * A program in a shell copied text with OSC 52 (tmux, neovim, ...).
+ * `data` is the base64 payload exactly as the program sent it.
+ */
+export const ClipboardMessage = z.object({
+  type: z.literal('clipboard'),
+  session_id: z.string(),
+  data: z.string(),
+});
+export type ClipboardMessage = z.infer<typeof ClipboardMessage>;
+
+// =============================================================================
+// Scrollback Search (Browser <-> Mac via Relay)
+// =============================================================================
+
+/**
+ * Browser -> Mac: search the scrollback of one session, or of every shared
+ * session when `session_id` is left out. Matching is case-insensitive.
+ */
+export const SearchMessage = z.object({
+  type: z.literal('search'),
+  session_id: z.string().optional(),
+  q: z.string(),
+});
+export type SearchMessage = z.infer<typeof SearchMessage>;
+
+/**
+ * A scrollback line containing the search term. `line` counts lines back
+ * from the end of the scrollback (0 is the last line); `column` is the
+ * character offset of the match in the line.
+ */
+export const SearchMatchSchema = z.object({
+  session_id: z.string(),
+  line: z.number(),
+  column: z.number(),
+  snippet: z.string(),
+});
+export type SearchMatchSchema = z.infer<typeof SearchMatchSchema>;
+
+/**
+ * Mac -> browser: matches of a search, newest first. Only sent to the
+ * browser that searched; `truncated` when there were more than were sent.
+ */
+export const SearchResultsMessage = z.object({
+  type: z.literal('search_results'),
+  q: z.string(),
+  matches: z.array(SearchMatchSchema),
+  truncated: z.boolean(),
+});
+export type SearchResultsMessage = z.infer<typeof SearchResultsMessage>;
+
+// =============================================================================
+// Recording Messages (Browser <-> Relay)
+// =============================================================================
+
+/**
+ * Relay -> browser: the Mac has the relay record this session as
+ * `recording_id`, or stopped (no id).
+ */
+export const RecordingStateMessage = z.object({
+  type: z.literal('recording_state'),
+  recording_id: z.string().optional(),
+});
+export type RecordingStateMessage = z.infer<typeof RecordingStateMessage>;
+
+/**
+ * A recording kept on the relay; plays back at `/playback/{id}`.
+ * `started_at` is Unix time in seconds.
+ */
+export const RecordingInfoSchema = z.object({
+  id: z.string(),
+  started_at: z.number(),
+  duration_ms: z.number(),
+  bytes: z.number(),
+  active: z.boolean(),
+  truncated: z.boolean().optional(),
+});
+export type RecordingInfoSchema = z.infer<typeof RecordingInfoSchema>;
+
+/**
+ * Browser -> relay: list this Mac's recordings
+ */
+export const ListRecordingsMessage = z.object({
+  type: z.literal('list_recordings'),
+});
+export type ListRecordingsMessage = z.infer<typeof ListRecordingsMessage>;
+
+/**
+ * Relay -> browser: answer to `list_recordings`, oldest first
+ */
+export const RecordingListMessage = z.object({
+  type: z.literal('recording_list'),
+  recordings: z.array(RecordingInfoSchema),
+});
+export type RecordingListMessage = z.infer<typeof RecordingListMessage>;
+
+/**
+ * Browser -> relay on a playback socket: change the speed (1: as recorded)
+ */
+export const PlaybackSpeedMessage = z.object({
+  type: z.literal('playback_speed'),
+  speed: z.number().positive(),
+});
+export type PlaybackSpeedMessage = z.infer<typeof PlaybackSpeedMessage>;
+
+/**
+ * Relay -> browser on a playback socket: every frame was played
+ */
+export const PlaybackEndedMessage = z.object({
+  type: z.literal('playback_ended'),
+});
+export type PlaybackEndedMessage = z.infer<typeof PlaybackEndedMessage>;
+
+// =============================================================================
+// End-to-End Encryption Messages
+// =============================================================================
+
+/**
+ * Terminal output from this Mac is end-to-end encrypted.
+ * `fingerprint` identifies the Mac's device key (as shown in its menu).
+ */
+export const E2eRequiredMessage = z.object({
+  type: z.literal('e2e_required'),
+  fingerprint: z.string(),
+});
+export type E2eRequiredMessage = z.infer<typeof E2eRequiredMessage>;
+
+/**
+ * Paired browser -> Mac: ephemeral P-256 public key (base64, uncompressed).
+ */
+export const E2eHelloMessage = z.object({
+  type: z.literal('e2e_hello'),
+  public_key: z.string(),
+});
+export type E2eHelloMessage = z.infer<typeof E2eHelloMessage>;
+
+/**
+ * Mac -> paired browser: output key wrapped for the browser whose hello
+ * carried `public_key`. Broadcast, so other browsers ignore it.
+ */
+export const E2eKeyMessage = z.object({
+  type: z.literal('e2e_key'),
+  public_key: z.string(),
+  key: z.string(),
+});
+export type E2eKeyMessage = z.infer<typeof E2eKeyMessage>;
+
+// =============================================================================
+// Direct Mode Signaling (Browser <-> Mac via Relay)
+// =============================================================================
+
+/**
+ * Browser -> Mac: SDP offer for the terminal data channel.
+ */
+export const RtcOfferMessage = z.object({
+  type: z.literal('rtc_offer'),
+  sdp: z.string(),
+});
+export type RtcOfferMessage = z.infer<typeof RtcOfferMessage>;
+
+/**
+ * Mac -> browser: SDP answer. Only sent to the browser that made the offer.
+ */
+export const RtcAnswerMessage = z.object({
+  type: z.literal('rtc_answer'),
+  sdp: z.string(),
+});
+export type RtcAnswerMessage = z.infer<typeof RtcAnswerMessage>;
+
+/**
+ * Trickled ICE candidate, in either direction.
+ */
+export const RtcCandidateMessage = z.object({
+  type: z.literal('rtc_candidate'),
+  candidate: z.string(),
+  sdp_mid: z.string().optional(),
+  sdp_mline_index: z.number().optional(),
+});
+export type RtcCandidateMessage = z.infer<typeof RtcCandidateMessage>;
+
+// =============================================================================
+// Latency Probes (Browser -> Relay -> Mac -> Relay -> Browser)
+// =============================================================================
+
+/**
+ * Browser -> Mac: a probe the Mac echoes back at once. `sent_at` is the
+ * browser's clock (only the browser compares it); `rtt_ms` is the round
+ * trip this browser measured last, which the relay keeps for statistics.
+ */
+export const LatencyProbeMessage = z.object({
+  type: z.literal('latency_probe'),
+  id: z.number(),
+  sent_at: z.number(),
+  rtt_ms: z.number().optional(),
+});
+export type LatencyProbeMessage = z.infer<typeof LatencyProbeMessage>;
+
+/**
+ * Mac -> browser: the probe coming back, only to the browser that sent it.
+ * `mac_rtt_ms` is the part of the trip between the relay and the Mac.
+ */
+export const LatencyEchoMessage = z.object({
+  type: z.literal('latency_echo'),
+  id: z.number(),
+  sent_at: z.number(),
+  mac_rtt_ms: z.number().optional(),
+});
+export type LatencyEchoMessage = z.infer<typeof LatencyEchoMessage>;
+
+/**
+ * Relay -> browser: the session keeps no output, so no scrollback follows;
+ * what this browser sees starts now
+ */
+export const LiveFromNowMessage = z.object({
+  type: z.literal('live_from_now'),
+});
+export type LiveFromNowMessage = z.infer<typeof LiveFromNowMessage>;
+
+/**
+ * Relay -> browser: `bytes` of scrollback follow as binary frames, paced so
+ * a slow connection keeps up, until `replay_end`
+ */
+export const ReplayStartMessage = z.object({
+  type: z.literal('replay_start'),
+  bytes: z.number(),
+});
+export type ReplayStartMessage = z.infer<typeof ReplayStartMessage>;
+
+export const ReplayEndMessage = z.object({
+  type: z.literal('replay_end'),
+});
+export type ReplayEndMessage = z.infer<typeof ReplayEndMessage>;
+
+// =============================================================================
+// Presence Messages (Browser <-> Relay)
+// =============================================================================
+
+/**
+ * A browser connected to the session. `joined_at` is Unix time in seconds.
+ */
+export const ViewerSchema = z.object({
+  browser_id: z.string(),
+  nickname: z.string().optional(),
+  role: Role,
+  joined_at: z.number(),
+});
+export type ViewerSchema = z.infer<typeof ViewerSchema>;
+
+/**
+ * Relay -> browser: everyone connected to the session, oldest first, sent
+ * whenever that changes
+ */
+export const PresenceMessage = z.object({
+  type: z.literal('presence'),
+  viewers: z.array(ViewerSchema),
+});
+export type PresenceMessage = z.infer<typeof PresenceMessage>;
+
+/**
+ * Browser -> relay: change the name shown to the others watching
+ */
+export const SetNicknameMessage = z.object({
+  type: z.literal('set_nickname'),
+  nickname: z.string().optional(),
+});
+export type SetNicknameMessage = z.infer<typeof SetNicknameMessage>;
+
+// =============================================================================
+// Chat Messages (Browser <-> Relay)
+// =============================================================================
+
+/**
+ * A chat line of the session. Browsers send `text` and the `name` to show;
+ * the relay fills in `browser_id` and `at` (Unix milliseconds) and passes it
+ * to every browser, the sender included. Not end-to-end encrypted.
+ */
+export const ChatMessage = z.object({
+  type: z.literal('chat'),
+  browser_id: z.string().optional(),
+  name: z.string().optional(),
+  text: z.string(),
+  at: z.number().optional(),
+});
+export type ChatMessage = z.infer<typeof ChatMessage>;
+
 // =============================================================================
 // Error Messages (Relay -> Any Client)
 // =============================================================================
@@ -114,14 +114,22 @@ else
     echo "  No install directory found (skipped)"
 fi
 
-# ── Remove Unix socket ────────────────────────────────────────
-echo -e "${BLUE}> Removing Unix socket...${NC}"
-if [ -e /tmp/terminal-remote.sock ]; then
-    rm -f /tmp/terminal-remote.sock
-    echo -e "${GREEN}  Removed /tmp/terminal-remote.sock${NC}"
+# ── Remove Unix sockets ───────────────────────────────────────
+echo -e "${BLUE}> Removing Unix sockets...${NC}"
+RUNTIME_DIR="/tmp/terminal-remote-$(id -u)"
+if [ -d "$RUNTIME_DIR" ]; then
+    rm -rf "$RUNTIME_DIR"
+    echo -e "${GREEN}  Removed $RUNTIME_DIR/${NC}"
 else
-    echo "  No Unix socket found (skipped)"
+    echo "  No socket directory found (skipped)"
 fi
+# Sockets from versions before per-user socket directories
+for sock in /tmp/terminal-remote.sock /tmp/terminal-remote-control.sock; do
+    if [ -O "$sock" ]; then
+        rm -f "$sock"
+        echo -e "${GREEN}  Removed $sock${NC}"
+    fi
+done
 
 # ── Optional dependency removal ───────────────────────────────
 echo ""
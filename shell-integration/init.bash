@@ -39,8 +39,16 @@ _terminal_remote_init() {
   local proxy
   proxy=$(_terminal_remote_find_proxy) || return 0  # silently skip if not found
 
-  # Check if mac-client is running (socket exists)
-  [[ -S /tmp/terminal-remote.sock ]] || return 0  # silently skip
+  # Check if mac-client is running (socket exists). Must match the paths
+  # pty-proxy tries: the app group container, then the pre-container
+  # per-user directory, optionally per named instance.
+  local name="pty${TERMINAL_REMOTE_INSTANCE:+-$TERMINAL_REMOTE_INSTANCE}.sock"
+  if [[ -n "$TERMINAL_REMOTE_SOCKET" ]]; then
+    [[ -S "$TERMINAL_REMOTE_SOCKET" ]] || return 0  # silently skip
+  else
+    [[ -S "$HOME/Library/Group Containers/group.com.terminal-remote/$name" ||
+       -S "/tmp/terminal-remote-$UID/$name" ]] || return 0  # silently skip
+  fi
 
   exec "$proxy"
 }
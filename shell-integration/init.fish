@@ -42,8 +42,24 @@ function _terminal_remote_init
     set -l proxy (_terminal_remote_find_proxy)
     or return 0  # silently skip if not found
 
-    # Check if mac-client is running (socket exists)
-    if not test -S /tmp/terminal-remote.sock
+    # Check if mac-client is running (socket exists). Must match the paths
+    # pty-proxy tries: the app group container, then the pre-container
+    # per-user directory, optionally per named instance.
+    set -l name pty.sock
+    if set -q TERMINAL_REMOTE_INSTANCE; and test -n "$TERMINAL_REMOTE_INSTANCE"
+        set name pty-$TERMINAL_REMOTE_INSTANCE.sock
+    end
+    set -l socks "$HOME/Library/Group Containers/group.com.terminal-remote/$name" /tmp/terminal-remote-(id -u)/$name
+    if set -q TERMINAL_REMOTE_SOCKET
+        set socks $TERMINAL_REMOTE_SOCKET
+    end
+    set -l found 0
+    for sock in $socks
+        if test -S $sock
+            set found 1
+        end
+    end
+    if test $found -eq 0
         return 0  # silently skip
     end
 